@@ -0,0 +1,566 @@
+//! A mpmc queue which delivers every value at least once, handing values
+//! back out when the receiver they were delivered to gives up on them
+
+use crate::clock::{Clock, MonotonicClock};
+use crate::dead_letter::{DeadLetterReason, DeadLetterSender};
+use crate::error::FrozenTrySendError;
+use crate::multiqueue::{InnerRecv, InnerSend, MultiQueue, MPMC};
+use crate::wait::{Backoff, YieldingWait};
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{RecvError, TryRecvError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+extern crate parking_lot;
+
+/// The values which were delivered and not acknowledged, shared by every receiver
+struct Redelivery<T> {
+    timeout: Duration,
+    clock: Arc<dyn Clock>,
+    // Values given up on along with when they can be delivered again and how many
+    // times they've been delivered. Ordered by when they can be delivered, since
+    // the timeout is always the same and nacked values go at the front
+    waiting: parking_lot::Mutex<VecDeque<(Instant, usize, T)>>,
+    // Deliveries which haven't been acknowledged or given up on yet, so the values
+    // may still come back. Deliveries are only settled under the lock on waiting
+    outstanding: AtomicUsize,
+    // How many times a value can be delivered before it goes to the dead letters
+    dead_letters: Option<(usize, DeadLetterSender<T>)>,
+}
+
+impl<T> Redelivery<T> {
+    /// Hands back a value which was given up on, to be delivered again once visible_at passes.
+    /// Values which have been delivered too many times go to the dead letters instead
+    fn give_up(&self, visible_at: Option<Instant>, deliveries: usize, val: T) {
+        if let Some((max_deliveries, ref dead_letters)) = self.dead_letters {
+            if deliveries >= max_deliveries {
+                self.settle();
+                dead_letters.send(DeadLetterReason::TooManyDeliveries, val);
+                return;
+            }
+        }
+        let mut waiting = self.waiting.lock();
+        match visible_at {
+            Some(at) => waiting.push_back((at, deliveries, val)),
+            None => waiting.push_front((self.clock.now(), deliveries, val)),
+        }
+        self.outstanding.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Takes the oldest value given up on if it can be delivered again
+    fn take_visible(&self) -> Option<(usize, T)> {
+        let mut waiting = self.waiting.lock();
+        match waiting.front() {
+            Some(&(at, _, _)) if at <= self.clock.now() => (),
+            _ => return None,
+        }
+        let (_, deliveries, val) = waiting.pop_front().unwrap();
+        self.outstanding.fetch_add(1, Ordering::SeqCst);
+        Some((deliveries, val))
+    }
+
+    /// Returns how long until the next value given up on can be delivered again
+    fn next_visible(&self) -> Option<Duration> {
+        let waiting = self.waiting.lock();
+        waiting
+            .front()
+            .map(|&(at, _, _)| at.saturating_duration_since(self.clock.now()))
+    }
+
+    /// Marks a delivery as done with, so its value won't come back
+    fn settle(&self) {
+        // Pairs with is_settled, so that nobody sees the queue settled before this is done
+        let _waiting = self.waiting.lock();
+        self.outstanding.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Returns whether nothing is waiting to be delivered again or may still be given up on
+    fn is_settled(&self) -> bool {
+        let waiting = self.waiting.lock();
+        waiting.is_empty() && self.outstanding.load(Ordering::SeqCst) == 0
+    }
+}
+
+/// This is the sending half of an acknowledged mpmc queue
+#[derive(Clone)]
+pub struct MPMCAckedSender<T> {
+    sender: InnerSend<MPMC<T>, T>,
+}
+
+/// This is the receiving half of an acknowledged mpmc queue. Each value is handed
+/// out in a ```Delivery```, which has to be acknowledged with ```ack``` once the value
+/// has been dealt with. A delivery which is dropped without being acknowledged,
+/// because the receiver panicked or gave up on it, is delivered again to whichever
+/// receiver asks next once the visibility timeout has passed. ```nack``` hands it
+/// back to be delivered again straight away.
+///
+/// Values handed back are delivered before new ones, but otherwise there's no
+/// ordering between them. The receivers only disconnect once the writers are
+/// gone, the queue is empty and every delivery has been acknowledged, since until
+/// then a value may still come back.
+///
+/// # Examples
+///
+/// ```
+/// use multiqueue2::mpmc_queue_acked;
+/// use std::thread;
+/// use std::time::Duration;
+///
+/// let (send, recv) = mpmc_queue_acked(16, Duration::from_millis(10));
+/// for job in 0..4 {
+///     send.try_send(job).unwrap();
+/// }
+/// drop(send);
+///
+/// // This worker dies partway through its first job
+/// let flaky = recv.clone();
+/// let _ = thread::spawn(move || {
+///     let job = flaky.recv().unwrap();
+///     panic!("worker lost while running job {}", *job);
+/// })
+/// .join();
+///
+/// let mut done = Vec::new();
+/// for job in recv {
+///     done.push(job.ack());
+/// }
+/// done.sort();
+/// assert_eq!(vec![0, 1, 2, 3], done);
+/// ```
+pub struct MPMCAckedReceiver<T> {
+    receiver: InnerRecv<MPMC<T>, T>,
+    redelivery: Arc<Redelivery<T>>,
+}
+
+/// A value handed out by a ```MPMCAckedReceiver```, which has to be acknowledged
+/// with ```ack``` once it's been dealt with. It's delivered again if this is
+/// dropped without being acknowledged.
+pub struct Delivery<T> {
+    val: Option<T>,
+    deliveries: usize,
+    redelivery: Arc<Redelivery<T>>,
+}
+
+impl<T> MPMCAckedSender<T> {
+    /// Tries to send a value into the queue
+    #[inline(always)]
+    pub fn try_send(&self, val: T) -> Result<(), FrozenTrySendError<T>> {
+        self.sender.try_send(val)
+    }
+
+    /// Removes this writer from the queue
+    pub fn unsubscribe(self) {
+        self.sender.unsubscribe();
+    }
+}
+
+impl<T> MPMCAckedReceiver<T> {
+    /// Tries to receive a value without blocking, taking a value which was handed back
+    /// and is visible again before any new one. Returns Disconnected once the writers
+    /// are gone, the queue is empty and no delivery is left unacknowledged.
+    pub fn try_recv(&self) -> Result<Delivery<T>, TryRecvError> {
+        if let Some((deliveries, val)) = self.redelivery.take_visible() {
+            return Ok(self.deliver(deliveries + 1, val));
+        }
+        // Counted first so that nobody sees the queue settled while this holds a value
+        self.redelivery.outstanding.fetch_add(1, Ordering::SeqCst);
+        match self.receiver.try_recv() {
+            Ok(val) => Ok(self.deliver(1, val)),
+            Err(e) => {
+                self.redelivery.outstanding.fetch_sub(1, Ordering::SeqCst);
+                match e {
+                    TryRecvError::Disconnected if !self.redelivery.is_settled() => {
+                        Err(TryRecvError::Empty)
+                    }
+                    e => Err(e),
+                }
+            }
+        }
+    }
+
+    /// Receives a value, waiting for one to be sent or handed back if there isn't any.
+    /// The queue is polled, sleeping for at most DEFAULT_SELECT_MAX_SLEEP_US
+    /// microseconds at a time, so that values handed back are picked up
+    pub fn recv(&self) -> Result<Delivery<T>, RecvError> {
+        let mut backoff = Backoff::new();
+        loop {
+            match self.try_recv() {
+                Ok(delivery) => return Ok(delivery),
+                Err(TryRecvError::Disconnected) => return Err(RecvError),
+                Err(TryRecvError::Empty) => backoff.snooze(self.redelivery.next_visible()),
+            }
+        }
+    }
+
+    /// Returns the number of values handed back which are waiting to be delivered again
+    pub fn redelivering(&self) -> usize {
+        self.redelivery.waiting.lock().len()
+    }
+
+    /// Returns the number of deliveries which haven't been acknowledged or handed back
+    pub fn unacked(&self) -> usize {
+        self.redelivery.outstanding.load(Ordering::SeqCst)
+    }
+
+    /// Returns how long a value which was given up on waits before it's delivered again
+    pub fn visibility_timeout(&self) -> Duration {
+        self.redelivery.timeout
+    }
+
+    /// Removes this receiver from the queue. Values handed back
+    /// are still delivered to the other receivers
+    pub fn unsubscribe(self) {}
+
+    fn deliver(&self, deliveries: usize, val: T) -> Delivery<T> {
+        Delivery {
+            val: Some(val),
+            deliveries,
+            redelivery: self.redelivery.clone(),
+        }
+    }
+}
+
+impl<T> Delivery<T> {
+    /// Acknowledges that the value has been dealt with, so it won't be delivered again,
+    /// and hands it over
+    pub fn ack(mut self) -> T {
+        let val = self.val.take().unwrap();
+        self.redelivery.settle();
+        val
+    }
+
+    /// Hands the value back to be delivered again straight away,
+    /// ahead of everything else waiting to be delivered. If it's been delivered
+    /// as many times as the queue allows, it goes to the dead letters instead
+    pub fn nack(mut self) {
+        let val = self.val.take().unwrap();
+        self.redelivery.give_up(None, self.deliveries, val);
+    }
+
+    /// Returns how many times the value has been delivered, including this time
+    pub fn deliveries(&self) -> usize {
+        self.deliveries
+    }
+}
+
+impl<T> Deref for Delivery<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.val.as_ref().unwrap()
+    }
+}
+
+impl<T> Drop for Delivery<T> {
+    fn drop(&mut self) {
+        if let Some(val) = self.val.take() {
+            let visible_at = self.redelivery.clock.now() + self.redelivery.timeout;
+            self.redelivery
+                .give_up(Some(visible_at), self.deliveries, val);
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Delivery<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Delivery")
+            .field("val", &**self)
+            .field("deliveries", &self.deliveries)
+            .finish()
+    }
+}
+
+impl<T> Drop for Redelivery<T> {
+    fn drop(&mut self) {
+        if let Some((_, ref dead_letters)) = self.dead_letters {
+            for (_, _, val) in self.waiting.get_mut().drain(..) {
+                dead_letters.send(DeadLetterReason::Undeliverable, val);
+            }
+        }
+    }
+}
+
+impl<T> Clone for MPMCAckedReceiver<T> {
+    fn clone(&self) -> Self {
+        MPMCAckedReceiver {
+            receiver: self.receiver.clone(),
+            redelivery: self.redelivery.clone(),
+        }
+    }
+}
+
+impl<T> Iterator for MPMCAckedReceiver<T> {
+    type Item = Delivery<T>;
+
+    #[inline(always)]
+    fn next(&mut self) -> Option<Delivery<T>> {
+        self.recv().ok()
+    }
+}
+
+/// Creates a (```MPMCAckedSender```, ```MPMCAckedReceiver```) pair with the passed
+/// capacity, where values given up on are delivered again once visibility_timeout
+/// has passed. Values handed back are kept aside rather than in the queue,
+/// so they never take up its capacity.
+///
+/// # Examples
+///
+/// ```
+/// use multiqueue2::mpmc_queue_acked;
+/// use std::time::Duration;
+///
+/// let (w, r) = mpmc_queue_acked(4, Duration::from_secs(30));
+/// w.try_send("job").unwrap();
+/// let delivery = r.try_recv().unwrap();
+/// assert_eq!("job", *delivery);
+/// delivery.nack();
+/// let delivery = r.try_recv().unwrap();
+/// assert_eq!(2, delivery.deliveries());
+/// assert_eq!("job", delivery.ack());
+/// ```
+pub fn mpmc_queue_acked<T>(
+    capacity: usize,
+    visibility_timeout: Duration,
+) -> (MPMCAckedSender<T>, MPMCAckedReceiver<T>) {
+    mpmc_queue_acked_with_clock(capacity, visibility_timeout, MonotonicClock)
+}
+
+/// Identical to ```mpmc_queue_acked```, except the visibility timeout
+/// is measured with the passed clock
+///
+/// # Examples
+///
+/// ```
+/// use multiqueue2::{mpmc_queue_acked_with_clock, MockClock};
+/// use std::sync::Arc;
+/// use std::time::Duration;
+///
+/// let clock = Arc::new(MockClock::new());
+/// let (w, r) = mpmc_queue_acked_with_clock(4, Duration::from_secs(30), clock.clone());
+/// w.try_send(1).unwrap();
+/// drop(r.try_recv().unwrap());
+/// assert!(r.try_recv().is_err());
+/// clock.advance(Duration::from_secs(30));
+/// assert_eq!(1, r.try_recv().unwrap().ack());
+/// ```
+pub fn mpmc_queue_acked_with_clock<T, C: Clock + 'static>(
+    capacity: usize,
+    visibility_timeout: Duration,
+    clock: C,
+) -> (MPMCAckedSender<T>, MPMCAckedReceiver<T>) {
+    // Handed back values become visible again without a send to wake anybody,
+    // so receivers snooze with a backoff instead of blocking on the queue
+    let (sender, receiver) =
+        MultiQueue::<MPMC<T>, T>::create_tx_rx_with(capacity, YieldingWait::new());
+    let redelivery = Redelivery {
+        timeout: visibility_timeout,
+        clock: Arc::new(clock),
+        waiting: parking_lot::Mutex::new(VecDeque::new()),
+        outstanding: AtomicUsize::new(0),
+        dead_letters: None,
+    };
+    (
+        MPMCAckedSender { sender },
+        MPMCAckedReceiver {
+            receiver,
+            redelivery: Arc::new(redelivery),
+        },
+    )
+}
+
+/// Identical to ```mpmc_queue_acked```, except a value which has been delivered
+/// max_deliveries times without being acknowledged goes to the dead letters instead
+/// of being delivered again. So do the values still in the queue or waiting to be
+/// delivered again when the queue is destroyed.
+///
+/// # Examples
+///
+/// ```
+/// use multiqueue2::{dead_letter_queue, mpmc_queue_acked_with_dead_letters, DeadLetterReason};
+/// use std::time::Duration;
+///
+/// let (dead_letters, dead) = dead_letter_queue(4);
+/// let (w, r) = mpmc_queue_acked_with_dead_letters(4, Duration::from_secs(30), 2, dead_letters);
+/// w.try_send("poison").unwrap();
+/// r.try_recv().unwrap().nack();
+/// r.try_recv().unwrap().nack();
+/// assert!(r.try_recv().is_err());
+///
+/// let letter = dead.try_recv().unwrap();
+/// assert_eq!((DeadLetterReason::TooManyDeliveries, "poison"), (letter.reason, letter.val));
+/// ```
+pub fn mpmc_queue_acked_with_dead_letters<T>(
+    capacity: usize,
+    visibility_timeout: Duration,
+    max_deliveries: usize,
+    dead_letters: DeadLetterSender<T>,
+) -> (MPMCAckedSender<T>, MPMCAckedReceiver<T>) {
+    assert!(
+        max_deliveries > 0,
+        "Multiqueue error - zero max deliveries received"
+    );
+    let (sender, receiver) = MultiQueue::<MPMC<T>, T>::create_tx_rx_with_dead_letters(
+        capacity,
+        YieldingWait::new(),
+        dead_letters.clone(),
+    );
+    let redelivery = Redelivery {
+        timeout: visibility_timeout,
+        clock: Arc::new(MonotonicClock),
+        waiting: parking_lot::Mutex::new(VecDeque::new()),
+        outstanding: AtomicUsize::new(0),
+        dead_letters: Some((max_deliveries, dead_letters)),
+    };
+    (
+        MPMCAckedSender { sender },
+        MPMCAckedReceiver {
+            receiver,
+            redelivery: Arc::new(redelivery),
+        },
+    )
+}
+
+unsafe impl<T: Send> Send for MPMCAckedSender<T> {}
+unsafe impl<T: Send> Send for MPMCAckedReceiver<T> {}
+
+#[cfg(test)]
+mod test {
+
+    use super::{
+        mpmc_queue_acked, mpmc_queue_acked_with_clock, mpmc_queue_acked_with_dead_letters,
+    };
+    use crate::clock::MockClock;
+    use crate::dead_letter::{dead_letter_queue, DeadLetterReason};
+
+    extern crate crossbeam;
+    use self::crossbeam::scope;
+
+    use std::sync::mpsc::TryRecvError;
+    use std::sync::Arc;
+    use std::thread::yield_now;
+    use std::time::Duration;
+
+    #[test]
+    fn test_redelivery() {
+        let clock = Arc::new(MockClock::new());
+        let timeout = Duration::from_secs(10);
+        let (writer, reader) = mpmc_queue_acked_with_clock(4, timeout, clock.clone());
+        assert_eq!(timeout, reader.visibility_timeout());
+        for i in 0..3 {
+            writer.try_send(i).unwrap();
+        }
+        let first = reader.try_recv().unwrap();
+        let second = reader.try_recv().unwrap();
+        assert_eq!((0, 1), (*first, *second));
+        assert_eq!(2, reader.unacked());
+        drop(first);
+        clock.advance(Duration::from_secs(5));
+        second.nack();
+        assert_eq!(2, reader.redelivering());
+        assert_eq!(0, reader.unacked());
+
+        // The nacked value comes back straight away, ahead of the dropped one
+        let second = reader.try_recv().unwrap();
+        assert_eq!((1, 2), (*second, second.deliveries()));
+        assert_eq!(2, reader.try_recv().unwrap().ack());
+        assert_eq!(Err(TryRecvError::Empty), reader.try_recv().map(|d| d.ack()));
+        clock.advance(Duration::from_secs(5));
+        let first = reader.try_recv().unwrap();
+        assert_eq!((0, 2), (*first, first.deliveries()));
+        assert_eq!(1, second.ack());
+
+        // Nothing can disconnect while a delivery may still come back
+        drop(writer);
+        assert_eq!(Err(TryRecvError::Empty), reader.try_recv().map(|d| d.ack()));
+        assert_eq!(0, first.ack());
+        assert_eq!(
+            Err(TryRecvError::Disconnected),
+            reader.try_recv().map(|d| d.ack())
+        );
+    }
+
+    #[test]
+    fn test_dead_letters() {
+        let (dead_letters, dead) = dead_letter_queue(8);
+        let timeout = Duration::from_secs(0);
+        let (writer, reader) = mpmc_queue_acked_with_dead_letters(4, timeout, 2, dead_letters);
+        for i in 0..4 {
+            writer.try_send(i).unwrap();
+        }
+        // Dropping and nacking both count towards the limit
+        drop(reader.try_recv().unwrap());
+        let first = reader.try_recv().unwrap();
+        assert_eq!((0, 2), (*first, first.deliveries()));
+        first.nack();
+        assert_eq!(0, reader.unacked());
+        let letter = dead.try_recv().unwrap();
+        assert_eq!(
+            (DeadLetterReason::TooManyDeliveries, 0),
+            (letter.reason, letter.val)
+        );
+
+        assert_eq!(1, reader.try_recv().unwrap().ack());
+        reader.try_recv().unwrap().nack();
+        assert_eq!(1, reader.redelivering());
+
+        // Values waiting to be delivered again and values never delivered
+        // both go to the dead letters along with the queue
+        drop((writer, reader));
+        let mut left: Vec<_> = dead.into_iter().map(|l| (l.reason, l.val)).collect();
+        left.sort_unstable_by_key(|&(_, val)| val);
+        let reason = DeadLetterReason::Undeliverable;
+        assert_eq!(vec![(reason, 2), (reason, 3)], left);
+    }
+
+    #[test]
+    fn test_acked_threaded() {
+        let num_loop = 10000;
+        let (writer, reader) = mpmc_queue_acked(8, Duration::from_millis(1));
+        scope(|scope| {
+            for _ in 0..2 {
+                let cur_writer = writer.clone();
+                scope.spawn(move |_| {
+                    for i in 0..num_loop {
+                        while cur_writer.try_send(i).is_err() {
+                            yield_now();
+                        }
+                    }
+                });
+            }
+            writer.unsubscribe();
+            let mut workers = Vec::new();
+            for worker in 0..3 {
+                let cur_reader = reader.clone();
+                workers.push(scope.spawn(move |_| {
+                    let mut done = Vec::new();
+                    for (i, delivery) in cur_reader.enumerate() {
+                        // Workers give up on some values, and the first one gives up on
+                        // every value it's handed for the first time
+                        if i % 7 == 0 || (worker == 0 && delivery.deliveries() == 1) {
+                            if i % 2 == 0 {
+                                delivery.nack();
+                            }
+                            continue;
+                        }
+                        done.push(delivery.ack());
+                    }
+                    done
+                }));
+            }
+            reader.unsubscribe();
+            let mut done: Vec<_> = workers
+                .into_iter()
+                .flat_map(|worker| worker.join().unwrap())
+                .collect();
+            done.sort_unstable();
+            let mut expected: Vec<_> = (0..num_loop).chain(0..num_loop).collect();
+            expected.sort_unstable();
+            assert_eq!(expected, done);
+        })
+        .unwrap();
+    }
+}
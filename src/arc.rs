@@ -0,0 +1,246 @@
+//! A broadcast queue which keeps values in shared allocations, so that every
+//! stream gets a handle to the same value instead of a clone of it
+
+use crate::error::FrozenTrySendError;
+use crate::multiqueue::{BCast, InnerRecv, InnerSend, MultiQueue};
+
+use std::sync::mpsc::{RecvError, TryRecvError};
+use std::sync::Arc;
+
+/// This is the sending half of a shared broadcast queue. Values are moved into
+/// an ```Arc``` before being sent, so senders still hand over plain values.
+pub struct BroadcastArcSender<T> {
+    sender: InnerSend<BCast<Arc<T>>, Arc<T>>,
+}
+
+/// This is the receiving half of a shared broadcast queue. Receiving a value
+/// only bumps its refcount, and every stream gets a handle to the same allocation.
+///
+/// This is meant for large values going to several streams, where cloning one
+/// for each stream is most of the cost of broadcasting it. Values which are
+/// cheap to clone are better off in an ordinary ```broadcast_queue```.
+///
+/// # Examples
+///
+/// ```
+/// use multiqueue2::broadcast_queue_arc;
+/// use std::sync::Arc;
+///
+/// let (send, recv) = broadcast_queue_arc(4);
+/// let other = recv.add_stream();
+///
+/// send.try_send([7_u8; 2048]).unwrap();
+/// let first = recv.try_recv().unwrap();
+/// let second = other.try_recv().unwrap();
+/// // Both streams got the same value, and it was never cloned
+/// assert!(Arc::ptr_eq(&first, &second));
+/// assert_eq!(7, first[0]);
+/// ```
+pub struct BroadcastArcReceiver<T> {
+    receiver: InnerRecv<BCast<Arc<T>>, Arc<T>>,
+}
+
+/// Takes back a value which failed to send. Nothing else has seen it,
+/// so this is the only handle to it
+fn unshare<T>(val: Arc<T>) -> T {
+    Arc::try_unwrap(val).unwrap_or_else(|_| panic!("Multiqueue error - an unsent value was shared"))
+}
+
+impl<T> BroadcastArcSender<T> {
+    /// Tries to send a value into the queue, moving it into an ```Arc``` first.
+    /// If there is no space or all readers have been disconnected,
+    /// returns the value in the error.
+    pub fn try_send(&self, val: T) -> Result<(), FrozenTrySendError<T>> {
+        self.sender
+            .try_send(Arc::new(val))
+            .map_err(|e| e.map(unshare))
+    }
+
+    /// Tries to send a value which is already shared, without allocating.
+    /// If there is no space or all readers have been disconnected,
+    /// returns the handle in the error.
+    pub fn try_send_arc(&self, val: Arc<T>) -> Result<(), FrozenTrySendError<Arc<T>>> {
+        self.sender.try_send(val)
+    }
+
+    /// Returns how far behind the writers the slowest stream is
+    pub fn max_lag(&self) -> usize {
+        self.sender.max_lag()
+    }
+
+    /// Removes this writer from the queue
+    pub fn unsubscribe(self) {
+        self.sender.unsubscribe()
+    }
+}
+
+impl<T> BroadcastArcReceiver<T> {
+    /// Tries to receive a handle to the next value without blocking
+    pub fn try_recv(&self) -> Result<Arc<T>, TryRecvError> {
+        self.receiver.try_recv()
+    }
+
+    /// Receives a handle to the next value, blocking until there is data
+    pub fn recv(&self) -> Result<Arc<T>, RecvError> {
+        self.receiver.recv()
+    }
+
+    /// Adds a new stream to the queue, which starts at the same point as this one
+    pub fn add_stream(&self) -> BroadcastArcReceiver<T> {
+        BroadcastArcReceiver {
+            receiver: self.receiver.add_stream(),
+        }
+    }
+
+    /// Returns the number of items waiting to be received on this stream
+    pub fn lag(&self) -> usize {
+        self.receiver.lag()
+    }
+
+    /// Removes this receiver from the stream.
+    /// Returns true if this was the last receiver on it
+    pub fn unsubscribe(self) -> bool {
+        self.receiver.unsubscribe()
+    }
+}
+
+impl<T> Clone for BroadcastArcSender<T> {
+    fn clone(&self) -> BroadcastArcSender<T> {
+        BroadcastArcSender {
+            sender: self.sender.clone(),
+        }
+    }
+}
+
+impl<T> Clone for BroadcastArcReceiver<T> {
+    /// The new receiver shares this one's stream
+    fn clone(&self) -> BroadcastArcReceiver<T> {
+        BroadcastArcReceiver {
+            receiver: self.receiver.clone(),
+        }
+    }
+}
+
+impl<T> Iterator for BroadcastArcReceiver<T> {
+    type Item = Arc<T>;
+
+    #[inline(always)]
+    fn next(&mut self) -> Option<Arc<T>> {
+        self.recv().ok()
+    }
+}
+
+/// Creates a (```BroadcastArcSender```, ```BroadcastArcReceiver```) pair with a capacity
+/// that's the next power of two >= the given capacity. The slots only hold a pointer,
+/// and a value is freed once every stream has received it and dropped its handle.
+///
+/// # Examples
+///
+/// ```
+/// use multiqueue2::broadcast_queue_arc;
+/// let (w, r) = broadcast_queue_arc(10);
+/// w.try_send(vec![1; 1000]).unwrap();
+/// assert_eq!(vec![1; 1000], *r.try_recv().unwrap());
+/// ```
+pub fn broadcast_queue_arc<T>(capacity: usize) -> (BroadcastArcSender<T>, BroadcastArcReceiver<T>) {
+    let (send, recv) = MultiQueue::<BCast<Arc<T>>, Arc<T>>::create_tx_rx(capacity);
+    (
+        BroadcastArcSender { sender: send },
+        BroadcastArcReceiver { receiver: recv },
+    )
+}
+
+unsafe impl<T: Send + Sync> Send for BroadcastArcSender<T> {}
+unsafe impl<T: Send + Sync> Send for BroadcastArcReceiver<T> {}
+
+#[cfg(test)]
+mod test {
+
+    use super::broadcast_queue_arc;
+    use crate::error::FrozenTrySendError;
+
+    extern crate crossbeam;
+    use self::crossbeam::scope;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use std::sync::Arc;
+    use std::thread::yield_now;
+
+    struct NoClone<'a> {
+        drops: &'a AtomicUsize,
+    }
+
+    impl<'a> Drop for NoClone<'a> {
+        fn drop(&mut self) {
+            self.drops.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_shared_between_streams() {
+        let drops = AtomicUsize::new(0);
+        {
+            let (writer, reader) = broadcast_queue_arc(2);
+            let other = reader.add_stream();
+            writer.try_send(NoClone { drops: &drops }).unwrap();
+            let first = reader.try_recv().unwrap();
+            let second = other.try_recv().unwrap();
+            assert!(Arc::ptr_eq(&first, &second));
+            drop((first, second));
+            // The queue still holds its handle until the slot is written over
+            assert_eq!(0, drops.load(Ordering::Relaxed));
+            writer.try_send(NoClone { drops: &drops }).unwrap();
+            writer.try_send(NoClone { drops: &drops }).unwrap();
+            assert_eq!(1, drops.load(Ordering::Relaxed));
+            assert_eq!(2, reader.lag());
+        }
+        assert_eq!(3, drops.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_failed_send_returns_value() {
+        let (writer, reader) = broadcast_queue_arc(1);
+        writer.try_send(String::from("first")).unwrap();
+        match writer.try_send(String::from("second")) {
+            Err(FrozenTrySendError::Full(val)) => assert_eq!("second", val),
+            _ => panic!("Queue should be full"),
+        }
+        assert_eq!("first", *reader.try_recv().unwrap());
+        let shared = Arc::new(String::from("third"));
+        writer.try_send_arc(shared.clone()).unwrap();
+        assert!(Arc::ptr_eq(&shared, &reader.try_recv().unwrap()));
+        drop(reader);
+        match writer.try_send(String::from("fourth")) {
+            Err(FrozenTrySendError::Full(val)) | Err(FrozenTrySendError::Disconnected(val)) => {
+                assert_eq!("fourth", val)
+            }
+            _ => panic!("Queue has no readers"),
+        }
+    }
+
+    #[test]
+    fn test_arc_threaded() {
+        let (writer, reader) = broadcast_queue_arc::<Vec<usize>>(4);
+        let num_loop = 10000;
+        scope(|scope| {
+            for _ in 0..2 {
+                let cur_reader = reader.add_stream();
+                scope.spawn(move |_| {
+                    let got: Vec<_> = cur_reader.map(|v| v[0]).collect();
+                    assert_eq!((0..num_loop).collect::<Vec<_>>(), got);
+                });
+            }
+            reader.unsubscribe();
+            for i in 0..num_loop {
+                let mut val = vec![i; 64];
+                while let Err(FrozenTrySendError::Full(v)) = writer.try_send(val) {
+                    val = v;
+                    yield_now();
+                }
+            }
+            writer.unsubscribe();
+        })
+        .unwrap();
+    }
+}
@@ -1,7 +1,10 @@
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::Ordering;
+
+use crate::sync::AtomicUsize;
 
 const UPDATE_EPOCH: usize = 1;
 const NO_READER: usize = 1 << 1;
+const FROZEN: usize = 1 << 2;
 
 pub struct AtomicSignal {
     flags: AtomicUsize,
@@ -43,12 +46,23 @@ impl AtomicSignal {
         (prev & NO_READER) != 0
     }
 
-    #[allow(dead_code)]
     #[inline(always)]
     pub fn clear_reader(&self, ord: Ordering) -> bool {
         let prev = self.flags.fetch_and(!NO_READER, ord);
         (prev & NO_READER) != 0
     }
+
+    #[inline(always)]
+    pub fn set_frozen(&self, ord: Ordering) -> bool {
+        let prev = self.flags.fetch_or(FROZEN, ord);
+        (prev & FROZEN) != 0
+    }
+
+    #[inline(always)]
+    pub fn clear_frozen(&self, ord: Ordering) -> bool {
+        let prev = self.flags.fetch_and(!FROZEN, ord);
+        (prev & FROZEN) != 0
+    }
 }
 
 impl LoadedSignal {
@@ -66,4 +80,9 @@ impl LoadedSignal {
     pub fn get_reader(&self) -> bool {
         (self.flags & NO_READER) != 0
     }
+
+    #[inline(always)]
+    pub fn get_frozen(&self) -> bool {
+        (self.flags & FROZEN) != 0
+    }
 }
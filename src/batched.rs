@@ -0,0 +1,252 @@
+//! Support for sending values into a queue in batches
+
+use crate::error::FrozenTrySendError;
+use crate::multiqueue::{InnerSend, QueueRW};
+
+use std::collections::VecDeque;
+
+use std::time::{Duration, Instant};
+
+trait Target<T> {
+    fn try_send_from(&self, vals: &mut VecDeque<T>) -> Result<(), FrozenTrySendError<()>>;
+}
+
+impl<RW: QueueRW<T>, T> Target<T> for InnerSend<RW, T> {
+    fn try_send_from(&self, vals: &mut VecDeque<T>) -> Result<(), FrozenTrySendError<()>> {
+        InnerSend::try_send_from(self, vals)
+    }
+}
+
+/// This holds on to values sent through it and only puts them into the queue
+/// once it has a batch of them, waking the receivers once for the whole batch.
+/// A batch goes out when it reaches the maximum size, when the oldest value in it
+/// has waited for the maximum delay, or when ```flush``` is called. Larger batches
+/// trade latency for throughput.
+///
+/// The delay is only checked when a value is sent, so a sender which goes quiet
+/// should call ```flush``` to push out what it's holding. Dropping the sender
+/// flushes it as well, and any values which still don't fit are dropped.
+/// These are made with ```batched``` and ```batched_with_delay```
+/// on ```BroadcastSender``` and ```MPMCSender```.
+///
+/// # Examples
+///
+/// ```
+/// use multiqueue2::mpmc_queue;
+/// use std::sync::mpsc::TryRecvError;
+///
+/// let (w, r) = mpmc_queue(8);
+/// let mut w = w.batched(3);
+/// w.try_send(1).unwrap();
+/// w.try_send(2).unwrap();
+/// assert_eq!(Err(TryRecvError::Empty), r.try_recv());
+/// // This fills the batch, so they all go in
+/// w.try_send(3).unwrap();
+/// assert_eq!(vec![1, 2, 3], r.try_iter().collect::<Vec<_>>());
+/// w.try_send(4).unwrap();
+/// w.flush().unwrap();
+/// assert_eq!(4, r.try_recv().unwrap());
+/// ```
+pub struct BatchedSender<T> {
+    sender: Box<dyn Target<T>>,
+    pending: VecDeque<T>,
+    max_batch: usize,
+    max_delay: Option<Duration>,
+    due_at: Option<Instant>,
+}
+
+impl<T> BatchedSender<T> {
+    pub(crate) fn new<RW: QueueRW<T> + 'static>(
+        sender: InnerSend<RW, T>,
+        max_batch: usize,
+        max_delay: Option<Duration>,
+    ) -> BatchedSender<T>
+    where
+        T: 'static,
+    {
+        assert!(
+            max_batch > 0,
+            "Multiqueue error - a batched sender needs room for at least one value"
+        );
+        BatchedSender {
+            sender: Box::new(sender),
+            pending: VecDeque::with_capacity(max_batch),
+            max_batch,
+            max_delay,
+            due_at: None,
+        }
+    }
+
+    /// Adds the value to the batch, sending the batch if it's now full or has waited
+    /// long enough. A batch which doesn't all fit in the queue is kept around and
+    /// tried again later. Fails once the batch is full and can't be sent, which
+    /// is also what happens when the queue has no receivers or is frozen
+    pub fn try_send(&mut self, val: T) -> Result<(), FrozenTrySendError<T>> {
+        if self.pending.len() >= self.max_batch {
+            match self.flush() {
+                Err(FrozenTrySendError::Disconnected(())) => {
+                    return Err(FrozenTrySendError::Disconnected(val))
+                }
+                Err(e) if self.pending.len() >= self.max_batch => return Err(e.map(|()| val)),
+                _ => (),
+            }
+        }
+        if self.pending.is_empty() {
+            self.due_at = self.max_delay.map(|delay| Instant::now() + delay);
+        }
+        self.pending.push_back(val);
+        let due = match self.due_at {
+            Some(at) => Instant::now() >= at,
+            None => false,
+        };
+        if self.pending.len() >= self.max_batch || due {
+            // Nothing is sent to a disconnected queue, so the value is still at the back
+            if let Err(FrozenTrySendError::Disconnected(())) = self.flush() {
+                let val = self.pending.pop_back().unwrap();
+                return Err(FrozenTrySendError::Disconnected(val));
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends everything in the batch which fits into the queue. Fails if some of it
+    /// didn't go in, in which case the rest stays in the batch
+    pub fn flush(&mut self) -> Result<(), FrozenTrySendError<()>> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let rval = self.sender.try_send_from(&mut self.pending);
+        if self.pending.is_empty() {
+            self.due_at = None;
+        }
+        rval
+    }
+
+    /// Returns the number of values waiting in the batch
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Returns the number of values a batch holds before it's sent
+    pub fn max_batch(&self) -> usize {
+        self.max_batch
+    }
+
+    /// Flushes the batch and removes the writer from the queue
+    pub fn unsubscribe(self) {}
+}
+
+impl<T> Drop for BatchedSender<T> {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+unsafe impl<T: Send + Sync> Send for BatchedSender<T> {}
+
+#[cfg(test)]
+mod test {
+
+    use crate::broadcast::broadcast_queue;
+    use crate::error::FrozenTrySendError;
+    use crate::mpmc::mpmc_queue;
+
+    extern crate crossbeam;
+    use self::crossbeam::scope;
+
+    use std::sync::mpsc::TryRecvError;
+    use std::thread::{sleep, yield_now};
+    use std::time::Duration;
+
+    #[test]
+    fn test_batch_size() {
+        let (writer, reader) = mpmc_queue(4);
+        let mut writer = writer.batched(3);
+        assert_eq!(3, writer.max_batch());
+        writer.try_send(0).unwrap();
+        writer.try_send(1).unwrap();
+        assert_eq!(2, writer.pending());
+        assert_eq!(Err(TryRecvError::Empty), reader.try_recv());
+        writer.try_send(2).unwrap();
+        assert_eq!(0, writer.pending());
+        assert_eq!(0, reader.try_recv().unwrap());
+        // Only two of these fit, the other waits in the batch
+        for i in 3..6 {
+            writer.try_send(i).unwrap();
+        }
+        assert_eq!(1, writer.pending());
+        writer.try_send(6).unwrap();
+        writer.try_send(7).unwrap();
+        assert_eq!(3, writer.pending());
+        assert_eq!(Err(FrozenTrySendError::Full(8)), writer.try_send(8));
+        assert_eq!(1, reader.try_recv().unwrap());
+        assert_eq!(Err(FrozenTrySendError::Full(())), writer.flush());
+        assert_eq!(vec![2, 3, 4, 5], reader.try_iter().collect::<Vec<_>>());
+        writer.try_send(8).unwrap();
+        assert_eq!(vec![6, 7, 8], reader.try_iter().collect::<Vec<_>>());
+        writer.try_send(9).unwrap();
+        writer.flush().unwrap();
+        assert_eq!(vec![9], reader.try_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_batch_delay() {
+        let (writer, reader) = broadcast_queue(8);
+        let mut writer = writer.batched_with_delay(100, Duration::from_millis(10));
+        writer.try_send(0).unwrap();
+        assert_eq!(Err(TryRecvError::Empty), reader.try_recv());
+        sleep(Duration::from_millis(20));
+        // The oldest value has waited long enough, so this goes out with it
+        writer.try_send(1).unwrap();
+        assert_eq!(vec![0, 1], reader.try_iter().collect::<Vec<_>>());
+        writer.try_send(2).unwrap();
+        assert_eq!(1, writer.pending());
+    }
+
+    #[test]
+    fn test_batch_no_readers() {
+        let (writer, reader) = mpmc_queue(4);
+        let mut writer = writer.batched(2);
+        writer.try_send(0).unwrap();
+        drop(reader);
+        // The batch can't go anywhere, so it stays full
+        writer.try_send(1).unwrap();
+        assert!(writer.try_send(2).is_err());
+        assert_eq!(2, writer.pending());
+        assert!(writer.flush().is_err());
+    }
+
+    #[test]
+    fn test_batch_drop_flushes() {
+        let (writer, reader) = mpmc_queue(4);
+        let mut writer = writer.batched(8);
+        writer.try_send(0).unwrap();
+        writer.try_send(1).unwrap();
+        writer.unsubscribe();
+        assert_eq!(vec![0, 1], reader.into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_batch_threaded() {
+        let num_loop = 100000;
+        let (writer, reader) = mpmc_queue(16);
+        scope(|scope| {
+            scope.spawn(move |_| {
+                let mut writer = writer.batched(5);
+                for i in 0..num_loop {
+                    let mut val = i;
+                    while let Err(FrozenTrySendError::Full(v)) = writer.try_send(val) {
+                        val = v;
+                        yield_now();
+                    }
+                }
+                while writer.flush().is_err() {
+                    yield_now();
+                }
+            });
+            let got: Vec<_> = reader.into_iter().collect();
+            assert_eq!((0..num_loop).collect::<Vec<_>>(), got);
+        })
+        .unwrap();
+    }
+}
@@ -0,0 +1,67 @@
+extern crate crossbeam;
+extern crate multiqueue2 as multiqueue;
+
+use crate::multiqueue::broadcast_queue;
+
+use crossbeam::scope;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+/// Adds and removes streams as fast as possible while a writer and reader
+/// keep the queue busy, to see what subscription churn costs everybody else
+fn runit(name: &str, n_churners: usize) {
+    let num_do = 10_000_000;
+    let num_churn = 100_000;
+    let (writer, reader) = broadcast_queue(20000);
+    let done = AtomicBool::new(false);
+    let dref = &done;
+    let start = Instant::now();
+    let (sent_ns, churn_ns) = scope(|scope| {
+        let sender = scope.spawn(move |_| {
+            let start = Instant::now();
+            for i in 0..num_do as u64 {
+                while writer.try_send(i).is_err() {}
+            }
+            start.elapsed().as_nanos() as f64 / num_do as f64
+        });
+        let mut churners = Vec::new();
+        for _ in 0..n_churners {
+            let r = reader.clone();
+            churners.push(scope.spawn(move |_| {
+                let start = Instant::now();
+                let mut churned = 0;
+                while churned < num_churn && !dref.load(Ordering::Relaxed) {
+                    drop(r.add_stream());
+                    churned += 1;
+                }
+                start.elapsed().as_nanos() as f64 / churned.max(1) as f64
+            }));
+        }
+        // The churners share this stream, so they don't hold the writer up themselves
+        for _ in 0..num_do {
+            reader.recv().unwrap();
+        }
+        done.store(true, Ordering::Relaxed);
+        let churn_ns = churners
+            .into_iter()
+            .map(|c| c.join().unwrap())
+            .fold(0.0, |total, ns| total + ns)
+            / n_churners.max(1) as f64;
+        (sender.join().unwrap(), churn_ns)
+    })
+    .unwrap();
+    println!(
+        "{}: {} ns per item sent, {} ns per stream added and removed, {:?} total",
+        name,
+        sent_ns,
+        churn_ns,
+        start.elapsed()
+    );
+}
+
+fn main() {
+    runit("no churn", 0);
+    runit("1 churner", 1);
+    runit("2 churners", 2);
+}
@@ -0,0 +1,280 @@
+//! A mpmc queue which keeps values in boxes, so that the ring stays small however large they are
+
+use crate::error::FrozenTrySendError;
+use crate::multiqueue::{InnerRecv, InnerSend, MultiQueue, MPMC};
+
+extern crate crossbeam;
+use self::crossbeam::queue::ArrayQueue;
+
+use std::sync::mpsc::{RecvError, TryRecvError};
+use std::sync::Arc;
+
+// The value is taken out of the box on receipt so the box can be handed back
+type Slot<T> = Box<Option<T>>;
+
+/// Boxes which have been received from, waiting to be sent with again
+struct BoxPool<T> {
+    free: ArrayQueue<Slot<T>>,
+}
+
+/// This is the sending half of a boxed mpmc queue. Values are moved into a box
+/// before being sent, reusing one which has already been received from if possible.
+pub struct MPMCBoxedSender<T> {
+    sender: InnerSend<MPMC<Slot<T>>, Slot<T>>,
+    pool: Arc<BoxPool<T>>,
+}
+
+/// This is the receiving half of a boxed mpmc queue. Values are moved out of their
+/// box when received, and the box is handed back for the writers to send with.
+///
+/// This is meant for values of many kilobytes, where inline slots make the ring
+/// huge and every value gets copied in and out of it. Small values are better
+/// off in an ordinary ```mpmc_queue```.
+///
+/// # Examples
+///
+/// ```
+/// use multiqueue2::{mpmc_queue_boxed, FrozenTrySendError};
+/// use std::thread;
+///
+/// let (send, recv) = mpmc_queue_boxed(4);
+///
+/// let handle = thread::spawn(move || {
+///     let mut total = 0;
+///     for frame in recv {
+///         let frame: [u8; 16384] = frame;
+///         total += frame[0] as usize;
+///     }
+///     total
+/// });
+///
+/// for i in 0..10 {
+///     let mut frame = [0; 16384];
+///     frame[0] = i;
+///     while let Err(FrozenTrySendError::Full(v)) = send.try_send(frame) {
+///         frame = v;
+///         thread::yield_now();
+///     }
+/// }
+/// drop(send);
+/// assert_eq!(45, handle.join().unwrap());
+/// ```
+pub struct MPMCBoxedReceiver<T> {
+    receiver: InnerRecv<MPMC<Slot<T>>, Slot<T>>,
+    pool: Arc<BoxPool<T>>,
+}
+
+impl<T> BoxPool<T> {
+    fn take(&self, val: T) -> Slot<T> {
+        match self.free.pop() {
+            Some(mut slot) => {
+                *slot = Some(val);
+                slot
+            }
+            None => Box::new(Some(val)),
+        }
+    }
+
+    fn give_back(&self, mut slot: Slot<T>) -> T {
+        let val = slot
+            .take()
+            .expect("Multiqueue error - received an empty box");
+        // If the pool is already full the box is just freed
+        let _ = self.free.push(slot);
+        val
+    }
+}
+
+impl<T> MPMCBoxedSender<T> {
+    /// Tries to send a value into the queue, boxing it first.
+    /// If there is no space or all readers have been disconnected,
+    /// returns the value in the error.
+    pub fn try_send(&self, val: T) -> Result<(), FrozenTrySendError<T>> {
+        self.sender
+            .try_send(self.pool.take(val))
+            .map_err(|e| e.map(|slot| self.pool.give_back(slot)))
+    }
+
+    /// Returns how far behind the writers the slowest consumer is
+    pub fn max_lag(&self) -> usize {
+        self.sender.max_lag()
+    }
+
+    /// Removes this writer from the queue
+    pub fn unsubscribe(self) {
+        self.sender.unsubscribe()
+    }
+}
+
+impl<T> MPMCBoxedReceiver<T> {
+    /// Tries to receive a value from the queue without blocking,
+    /// handing its box back to the writers.
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.receiver
+            .try_recv()
+            .map(|slot| self.pool.give_back(slot))
+    }
+
+    /// Receives a value from the queue, blocking until there is data,
+    /// and hands its box back to the writers.
+    pub fn recv(&self) -> Result<T, RecvError> {
+        self.receiver.recv().map(|slot| self.pool.give_back(slot))
+    }
+
+    /// Returns the number of boxes waiting to be sent with again
+    pub fn pooled(&self) -> usize {
+        self.pool.free.len()
+    }
+
+    /// Returns the number of items waiting to be received
+    pub fn lag(&self) -> usize {
+        self.receiver.lag()
+    }
+
+    /// Removes this receiver from the queue.
+    /// Returns true if this was the last receiver
+    pub fn unsubscribe(self) -> bool {
+        self.receiver.unsubscribe()
+    }
+}
+
+impl<T> Clone for MPMCBoxedSender<T> {
+    fn clone(&self) -> MPMCBoxedSender<T> {
+        MPMCBoxedSender {
+            sender: self.sender.clone(),
+            pool: self.pool.clone(),
+        }
+    }
+}
+
+impl<T> Clone for MPMCBoxedReceiver<T> {
+    fn clone(&self) -> MPMCBoxedReceiver<T> {
+        MPMCBoxedReceiver {
+            receiver: self.receiver.clone(),
+            pool: self.pool.clone(),
+        }
+    }
+}
+
+impl<T> Iterator for MPMCBoxedReceiver<T> {
+    type Item = T;
+
+    #[inline(always)]
+    fn next(&mut self) -> Option<T> {
+        self.recv().ok()
+    }
+}
+
+/// Creates a (```MPMCBoxedSender```, ```MPMCBoxedReceiver```) pair with a capacity that's
+/// the next power of two >= the given capacity. The slots only hold a pointer,
+/// and up to capacity received boxes are kept around to be sent with again.
+///
+/// # Examples
+///
+/// ```
+/// use multiqueue2::mpmc_queue_boxed;
+/// let (w, r) = mpmc_queue_boxed(10);
+/// w.try_send(vec![1; 1000]).unwrap();
+/// assert_eq!(vec![1; 1000], r.try_recv().unwrap());
+/// assert_eq!(1, r.pooled());
+/// ```
+pub fn mpmc_queue_boxed<T>(capacity: usize) -> (MPMCBoxedSender<T>, MPMCBoxedReceiver<T>) {
+    let (send, recv) = MultiQueue::<MPMC<Slot<T>>, Slot<T>>::create_tx_rx(capacity);
+    let pool = Arc::new(BoxPool {
+        free: ArrayQueue::new(capacity.max(1)),
+    });
+    (
+        MPMCBoxedSender {
+            sender: send,
+            pool: pool.clone(),
+        },
+        MPMCBoxedReceiver {
+            receiver: recv,
+            pool,
+        },
+    )
+}
+
+unsafe impl<T: Send> Send for MPMCBoxedSender<T> {}
+unsafe impl<T: Send> Send for MPMCBoxedReceiver<T> {}
+
+#[cfg(test)]
+mod test {
+
+    use super::mpmc_queue_boxed;
+    use crate::error::FrozenTrySendError;
+
+    extern crate crossbeam;
+    use self::crossbeam::scope;
+
+    use std::sync::mpsc::TryRecvError;
+    use std::thread::yield_now;
+
+    #[test]
+    fn test_boxes_reused() {
+        let (writer, reader) = mpmc_queue_boxed(2);
+        for i in 0..10 {
+            writer.try_send([i; 4096]).unwrap();
+            writer.try_send([i + 1; 4096]).unwrap();
+            assert!(writer.try_send([0; 4096]).is_err());
+            assert_eq!([i; 4096], reader.try_recv().unwrap());
+            assert_eq!([i + 1; 4096], reader.try_recv().unwrap());
+            // No more than the two boxes in flight are ever needed
+            assert_eq!(2, reader.pooled());
+        }
+        assert_eq!(Err(TryRecvError::Empty), reader.try_recv());
+        drop(writer);
+        assert_eq!(Err(TryRecvError::Disconnected), reader.try_recv());
+    }
+
+    #[test]
+    fn test_failed_send_returns_value() {
+        let (writer, reader) = mpmc_queue_boxed(1);
+        writer.try_send(String::from("first")).unwrap();
+        match writer.try_send(String::from("second")) {
+            Err(FrozenTrySendError::Full(val)) => assert_eq!("second", val),
+            _ => panic!("Queue should be full"),
+        }
+        // The box the failed send took went back to the pool
+        assert_eq!(1, reader.pooled());
+        assert_eq!("first", reader.try_recv().unwrap());
+        drop(reader);
+        match writer.try_send(String::from("third")) {
+            Err(FrozenTrySendError::Full(val)) | Err(FrozenTrySendError::Disconnected(val)) => {
+                assert_eq!("third", val)
+            }
+            _ => panic!("Queue has no readers"),
+        }
+    }
+
+    #[test]
+    fn test_boxed_threaded() {
+        let (writer, reader) = mpmc_queue_boxed(4);
+        let num_loop = 10000;
+        scope(|scope| {
+            for _ in 0..2 {
+                let cur_writer = writer.clone();
+                scope.spawn(move |_| {
+                    for i in 0..num_loop {
+                        let mut val = vec![i; 64];
+                        while let Err(FrozenTrySendError::Full(v)) = cur_writer.try_send(val) {
+                            val = v;
+                            yield_now();
+                        }
+                    }
+                });
+            }
+            writer.unsubscribe();
+            for _ in 0..2 {
+                let cur_reader = reader.clone();
+                scope.spawn(move |_| {
+                    for val in cur_reader {
+                        assert!(val.iter().all(|v| *v == val[0]));
+                    }
+                });
+            }
+            reader.unsubscribe();
+        })
+        .unwrap();
+    }
+}
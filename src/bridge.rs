@@ -0,0 +1,268 @@
+//! Adapters which give the plain senders and receivers a ```Sink``` and ```Stream```,
+//! for apps which are mostly synchronous but have an async edge somewhere
+
+use crate::error::FrozenTrySendError;
+use crate::multiqueue::{
+    futures_multiqueue, FutInnerRecv, FutInnerSend, InnerRecv, InnerSend, QueueRW, MPMC,
+};
+use crate::sync::yield_now;
+
+extern crate futures;
+use self::futures::future::poll_fn;
+use self::futures::{Async, AsyncSink, Future, Poll, Sink, StartSend, Stream};
+
+use std::fmt;
+use std::sync::mpsc::SendError;
+use std::thread;
+
+/// This is a ```Stream``` over the values a plain receiver gets. The receiver is
+/// moved onto a thread of its own which blocks on it, and hands each value over
+/// through a small futures queue.
+///
+/// The stream ends once every writer on the original queue has been dropped and
+/// everything sent has been received. Dropping the stream stops the thread the next
+/// time it gets a value or the writers go, which unsubscribes the receiver.
+///
+/// These are made with ```into_stream_adapter``` on ```BroadcastReceiver```,
+/// ```BroadcastUniReceiver```, ```MPMCReceiver``` and ```MPMCUniReceiver```
+///
+/// # Examples
+///
+/// ```
+/// extern crate futures;
+/// use futures::{Future, Stream};
+/// use multiqueue2::mpmc_queue;
+///
+/// let (w, r) = mpmc_queue(10);
+/// let stream = r.into_stream_adapter(4);
+/// for i in 0..3 {
+///     w.try_send(i).unwrap();
+/// }
+/// drop(w);
+/// assert_eq!(vec![0, 1, 2], stream.collect().wait().unwrap());
+/// ```
+pub struct BlockingStreamAdapter<T> {
+    receiver: FutInnerRecv<MPMC<T>, T>,
+}
+
+/// This is a ```Sink``` into a plain sender. The sender is moved onto a thread of
+/// its own which takes values from a small futures queue and sends them on,
+/// yielding while the original queue is full.
+///
+/// Once every stream on the original queue has unsubscribed, the thread stops and
+/// sends into the sink fail. Dropping the sink stops the thread once it has sent
+/// what was handed to it, which drops the sender.
+///
+/// These are made with ```into_sink_adapter``` on ```BroadcastSender``` and ```MPMCSender```
+pub struct BlockingSinkAdapter<T> {
+    sender: FutInnerSend<MPMC<T>, T>,
+}
+
+/// Lets a handle move onto the adapter's thread. The constructors on the
+/// public types ask for the same bounds their own ```Send``` impls do
+struct AssertSend<H>(H);
+
+unsafe impl<H> Send for AssertSend<H> {}
+
+impl<T: Send + 'static> BlockingStreamAdapter<T> {
+    pub(crate) fn new<RW: QueueRW<T> + 'static>(
+        receiver: InnerRecv<RW, T>,
+        hand_off: usize,
+    ) -> BlockingStreamAdapter<T> {
+        let (send, recv) = futures_multiqueue::<MPMC<T>, T>(hand_off);
+        let receiver = AssertSend(receiver);
+        thread::spawn(move || {
+            let AssertSend(receiver) = receiver;
+            while let Ok(val) = receiver.recv() {
+                if !hand_over(&send, val) {
+                    break;
+                }
+            }
+        });
+        BlockingStreamAdapter { receiver: recv }
+    }
+}
+
+/// Sends val into the futures queue, parking this thread while it's full.
+/// Returns false once the stream adapter has been dropped
+fn hand_over<T>(send: &FutInnerSend<MPMC<T>, T>, val: T) -> bool {
+    let mut val = Some(val);
+    let sent = poll_fn(|| -> Poll<bool, ()> {
+        let v = val
+            .take()
+            .expect("Multiqueue error - value already handed over");
+        let mut send = send;
+        match send.start_send(v) {
+            Ok(AsyncSink::Ready) => {
+                let _ = send.poll_complete();
+                Ok(Async::Ready(true))
+            }
+            // The adapter wakes this once it's gone, and it's checked
+            // after parking so that can't have been missed
+            Ok(AsyncSink::NotReady(v)) if send.stream_count() != 0 => {
+                val = Some(v);
+                Ok(Async::NotReady)
+            }
+            _ => Ok(Async::Ready(false)),
+        }
+    })
+    .wait();
+    sent == Ok(true)
+}
+
+impl<T: Send + 'static> BlockingSinkAdapter<T> {
+    pub(crate) fn new<RW: QueueRW<T> + 'static>(
+        sender: InnerSend<RW, T>,
+        hand_off: usize,
+    ) -> BlockingSinkAdapter<T> {
+        let (send, recv) = futures_multiqueue::<MPMC<T>, T>(hand_off);
+        let sender = AssertSend(sender);
+        thread::spawn(move || {
+            let AssertSend(sender) = sender;
+            for val in (&recv).wait() {
+                let mut val = match val {
+                    Ok(val) => val,
+                    Err(()) => break,
+                };
+                loop {
+                    match sender.try_send(val) {
+                        Ok(()) => break,
+                        Err(FrozenTrySendError::Full(v))
+                        | Err(FrozenTrySendError::Disconnected(v))
+                        | Err(FrozenTrySendError::Frozen(v)) => {
+                            if sender.stream_count() == 0 {
+                                return;
+                            }
+                            val = v;
+                            yield_now();
+                        }
+                    }
+                }
+            }
+        });
+        BlockingSinkAdapter { sender: send }
+    }
+}
+
+impl<T> Stream for BlockingStreamAdapter<T> {
+    type Item = T;
+    type Error = ();
+
+    #[inline(always)]
+    fn poll(&mut self) -> Poll<Option<T>, ()> {
+        (&self.receiver).poll()
+    }
+}
+
+impl<T> Sink for BlockingSinkAdapter<T> {
+    type SinkItem = T;
+    type SinkError = SendError<T>;
+
+    /// Hands the value to the sender's thread, and fails
+    /// with it once that thread has stopped
+    fn start_send(&mut self, msg: T) -> StartSend<T, SendError<T>> {
+        if self.sender.stream_count() == 0 {
+            return Err(SendError(msg));
+        }
+        match (&self.sender).start_send(msg)? {
+            // The thread wakes this once it's gone, and it's
+            // checked after parking so that can't have been missed
+            AsyncSink::NotReady(msg) if self.sender.stream_count() == 0 => Err(SendError(msg)),
+            res => Ok(res),
+        }
+    }
+
+    #[inline(always)]
+    fn poll_complete(&mut self) -> Poll<(), SendError<T>> {
+        (&self.sender).poll_complete()
+    }
+}
+
+impl<T> fmt::Debug for BlockingStreamAdapter<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BlockingStreamAdapter").finish()
+    }
+}
+
+impl<T> fmt::Debug for BlockingSinkAdapter<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BlockingSinkAdapter").finish()
+    }
+}
+
+unsafe impl<T: Send> Send for BlockingStreamAdapter<T> {}
+unsafe impl<T: Send> Send for BlockingSinkAdapter<T> {}
+
+#[cfg(test)]
+mod test {
+
+    use crate::broadcast::broadcast_queue;
+    use crate::mpmc::mpmc_queue;
+
+    use super::futures::{Future, Sink, Stream};
+
+    use std::thread;
+
+    #[test]
+    fn test_stream_adapter() {
+        let (w, r) = broadcast_queue(4);
+        let r2 = r.add_stream();
+        let streams = (r.into_stream_adapter(2), r2.into_stream_adapter(2));
+        let t = thread::spawn(move || {
+            for i in 0..1000 {
+                while w.try_send(i).is_err() {
+                    thread::yield_now();
+                }
+            }
+        });
+        // Both have to be taken from, or the writer gets stuck behind one
+        let got = streams.0.zip(streams.1).collect().wait().unwrap();
+        assert_eq!((0..1000).map(|i| (i, i)).collect::<Vec<_>>(), got);
+        t.join().unwrap();
+    }
+
+    #[test]
+    fn test_stream_adapter_dropped() {
+        let (w, r) = broadcast_queue(4);
+        drop(r.into_stream_adapter(1));
+        // The thread stops on the next value, since nothing takes them
+        w.try_send(1).unwrap();
+        while w.stream_count() != 0 {
+            thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn test_sink_adapter() {
+        let (w, r) = mpmc_queue(2);
+        let sink = w.into_sink_adapter(2);
+        let t = thread::spawn(move || {
+            let mut sink = sink;
+            for i in 0..1000 {
+                sink = sink.send(i).wait().unwrap();
+            }
+        });
+        assert_eq!(
+            (0..1000).collect::<Vec<_>>(),
+            r.into_iter().collect::<Vec<_>>()
+        );
+        t.join().unwrap();
+    }
+
+    #[test]
+    fn test_sink_adapter_disconnects() {
+        let (w, r) = broadcast_queue(2);
+        let mut sink = w.into_sink_adapter(2);
+        drop(r);
+        let mut i = 0;
+        // Values get taken until the thread notices the streams are gone
+        let err = loop {
+            match sink.send(i).wait() {
+                Ok(s) => sink = s,
+                Err(e) => break e,
+            }
+            i += 1;
+        };
+        assert_eq!(i, err.0);
+    }
+}
@@ -1,20 +1,54 @@
-use crate::countedindex::Index;
+use crate::batched::BatchedSender;
+use crate::bridge::{BlockingSinkAdapter, BlockingStreamAdapter};
+use crate::clock::Clock;
+use crate::conflate::KeyConflator;
+use crate::countedindex::capacity_from_u64;
+use crate::dedup::IdWindow;
+use crate::error::{
+    AttachError, FrozenTrySendError, LaggedRecvError, LaggedTryRecvError, StreamsFullError,
+};
+#[cfg(feature = "fault_injection")]
+use crate::faults::FaultConfig;
+#[cfg(feature = "tokio")]
+use crate::fut_streams::StreamTasks;
+use crate::inspector::Inspector;
+#[cfg(feature = "test-util")]
+use crate::invariants::InvariantReport;
+use crate::io::{QueueReader, QueueWriter};
+use crate::memory::Reclaim;
+use crate::merged::MergeSource;
+#[cfg(feature = "metrics")]
+use crate::metrics::QueueMetrics;
+use crate::mpmc::{MPMCReceiver, MPMCSender};
 use crate::multiqueue::{
-    futures_multiqueue, futures_multiqueue_with, BCast, FutInnerRecv, FutInnerSend,
-    FutInnerUniRecv, InnerRecv, InnerSend, MultiQueue,
+    futures_multiqueue, futures_multiqueue_with, BCast, Barrier, DropPolicy, FutInnerRecv,
+    FutInnerSend, FutInnerUniRecv, InnerRecv, InnerSend, MultiQueue, ReaderToken, RecvGuard,
 };
-use crate::wait::Wait;
+use crate::rate_limited::{PacedReceiver, RateLimitedFutSender, RateLimitedSender};
+use crate::stats::{MemoryFootprint, QueueStats};
+use crate::tee::TeeTarget;
+use crate::wait::{DefaultWait, Wait};
 
-use std::sync::mpsc::{RecvError, SendError, TryRecvError, TrySendError};
+use std::borrow::Cow;
+use std::fmt;
+use std::hash::Hash;
+use std::sync::mpsc::{RecvError, SendError, TryRecvError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 extern crate futures;
-use futures::{Async, Poll, Sink, StartSend, Stream};
+#[cfg(feature = "tokio")]
+use futures::IntoFuture;
+use futures::{Poll, Sink, StartSend, Stream};
 
 /// This class is the sending half of the broadcasting ```MultiQueue```. It supports both
 /// single and multi consumer modes with competitive performance in each case.
 /// It only supports nonblocking writes (the futures sender being an exception)
 /// as well as being the conduit for adding new writers.
 ///
+/// Counts and lags such as ```max_lag```, ```stream_count``` and ```BroadcastReceiver::lag```
+/// are read while the queue keeps running, so they may be stale by the time they are used.
+///
 /// # Examples
 ///
 /// ```
@@ -64,7 +98,7 @@ use futures::{Async, Poll, Sink, StartSend, Stream};
 /// // Stream 1 consumer 0 got 2
 /// // etc
 /// ```
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct BroadcastSender<T: Clone> {
     sender: InnerSend<BCast<T>, T>,
 }
@@ -152,20 +186,21 @@ pub struct BroadcastReceiver<T: Clone> {
 /// };
 /// assert_eq!(2, val);
 /// ```
+#[derive(Debug)]
 pub struct BroadcastUniReceiver<T: Clone + Sync> {
     receiver: InnerRecv<BCast<T>, T>,
 }
 
 /// This is the futures-compatible version of ```BroadcastSender```
 /// It implements Sink
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct BroadcastFutSender<T: Clone> {
     sender: FutInnerSend<BCast<T>, T>,
 }
 
 /// This is the futures-compatible version of ```BroadcastReceiver```
 /// It implements ```Stream```
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct BroadcastFutReceiver<T: Clone> {
     receiver: FutInnerRecv<BCast<T>, T>,
 }
@@ -174,1029 +209,4845 @@ pub struct BroadcastFutReceiver<T: Clone> {
 /// It implements ```Stream``` and behaves like the iterator would.
 /// To use a different function must transform itself into a different
 /// ```BroadcastFutUniRecveiver``` use ```transform_operation```
+///
+/// When the function can be cloned, so can the receiver. Each clone is on a
+/// stream of its own which starts where the original is, so it sees every value
+/// the original does. ```add_stream_with``` does the same with another function.
+///
+/// # Example:
+///
+/// ```
+/// use multiqueue2::broadcast_fut_queue;
+///
+/// let (w, r) = broadcast_fut_queue(10);
+/// let mut doubled = r.into_single(|x: &u32| 2 * *x).ok().unwrap();
+/// let mut doubled2 = doubled.clone();
+/// w.try_send(3).unwrap();
+/// assert_eq!(6, doubled.try_recv().unwrap());
+/// assert_eq!(6, doubled2.try_recv().unwrap());
+/// ```
 pub struct BroadcastFutUniReceiver<R, F: FnMut(&T) -> R, T: Clone + Sync> {
     receiver: FutInnerUniRecv<BCast<T>, R, F, T>,
 }
 
+/// This is a receiver on a stream with a maximum lag. Instead of making
+/// the writers wait on it, the stream gets skipped forwards once it falls
+/// too far behind and the receiver is told how many items it missed
+/// with a ```Lagged``` error, like a lossy broadcast channel.
+/// It's created with ```BroadcastReceiver::add_bounded_stream```
+#[derive(Clone, Debug)]
+pub struct BroadcastBoundedReceiver<T: Clone> {
+    receiver: InnerRecv<BCast<T>, T>,
+}
+
+/// This is a stream which has been taken offline with ```BroadcastReceiver::pause```.
+/// Writers don't wait on it, so anything it hasn't read may get overwritten.
+/// It can't receive until it's turned back into a receiver with ```resume```
+#[derive(Debug)]
+pub struct BroadcastPausedReceiver<T: Clone> {
+    receiver: InnerRecv<BCast<T>, T>,
+}
+
+/// This is a stream which has been taken off the queue with
+/// ```BroadcastReceiver::detach```. It only remembers where the stream was,
+/// so it can be sent anywhere without holding up the writers, and it's
+/// turned back into a receiver with ```BroadcastReceiver::attach```
+#[derive(Debug)]
+pub struct BroadcastReaderToken<T: Clone> {
+    token: ReaderToken<BCast<T>, T>,
+}
+
 impl<T: Clone> BroadcastSender<T> {
     #[inline(always)]
-    pub fn try_send(&self, val: T) -> Result<(), TrySendError<T>> {
+    pub fn try_send(&self, val: T) -> Result<(), FrozenTrySendError<T>> {
         self.sender.try_send(val)
     }
 
-    /// Removes the writer from the queue
-    pub fn unsubscribe(self) {
-        self.sender.unsubscribe();
+    /// Identical to ```try_send```, except it bounds how long a send can take when
+    /// other senders keep committing their values first. Each time this loses to
+    /// one of them it backs off for a little longer, and once it's lost max_attempts
+    /// times it gives up and hands the value back in Full. A sender which has lost several
+    /// times in a row has the others hold back until it's through, so no sender
+    /// starves. With a single sender there's nobody to lose to, so this is try_send
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::broadcast_queue;
+    /// let (w, r) = broadcast_queue(4);
+    /// let w2 = w.clone();
+    /// w.try_send_spin(1, 16).unwrap();
+    /// w2.try_send_spin(2, 16).unwrap();
+    /// assert_eq!(vec![1, 2], r.try_iter().collect::<Vec<_>>());
+    /// ```
+    pub fn try_send_spin(&self, val: T, max_attempts: usize) -> Result<(), FrozenTrySendError<T>> {
+        self.sender.try_send_spin(val, max_attempts)
     }
-}
 
-impl<T: Clone> BroadcastReceiver<T> {
-    /// Tries to receive a value from the queue without blocking.
+    /// Sends as many values from the front of vals as there's room for without blocking,
+    /// returning how many it sent. Receivers are only woken once for all of them.
+    /// When this is the only sender, the values are copied straight into the queue
+    /// and the write head moves past them all at once, so it pairs with
+    /// ```BroadcastReceiver::read_exact``` as a ring of samples.
+    /// Returns 0 when the queue is full or there are no receivers
     ///
-    /// # Examples:
+    /// # Examples
     ///
     /// ```
     /// use multiqueue2::broadcast_queue;
-    /// let (w, r) = broadcast_queue(10);
-    /// w.try_send(1).unwrap();
-    /// assert_eq!(1, r.try_recv().unwrap());
+    /// let (w, r) = broadcast_queue(4);
+    /// assert_eq!(4, w.write_from(&[1, 2, 3, 4, 5, 6]));
+    /// let mut buf = [0; 4];
+    /// assert_eq!(Ok(4), r.read_exact(&mut buf));
+    /// assert_eq!([1, 2, 3, 4], buf);
+    /// assert_eq!(2, w.write_from(&[5, 6]));
     /// ```
+    pub fn write_from(&self, vals: &[T]) -> usize
+    where
+        T: Copy,
+    {
+        self.sender.try_send_slice(vals)
+    }
+
+    /// Sends clones of all of vals back to back without blocking, or none of them if
+    /// they don't all fit, so a record made of several values is never cut off by
+    /// a full queue. Values from other senders never land in between them.
+    /// They also show up all at once: the first is only made visible after the rest
+    /// have been written, and streams read in order, so no receiver gets the start
+    /// of a record before the rest of it is in the queue.
+    /// More values than the queue holds never fit.
+    /// Panics if the queue was made with ```broadcast_queue_conflated```
+    /// or ```broadcast_queue_deduplicated```
+    ///
+    /// # Examples
     ///
     /// ```
     /// use multiqueue2::broadcast_queue;
-    /// use std::thread;
+    /// let (w, r) = broadcast_queue(4);
+    /// w.try_send_all(&[1, 2, 3]).unwrap();
+    /// // Only one of these would fit, so neither goes in
+    /// assert!(w.try_send_all(&[4, 5]).is_err());
+    /// w.try_send_all(&[4]).unwrap();
+    /// assert_eq!(vec![1, 2, 3, 4], r.try_iter().collect::<Vec<_>>());
+    /// ```
+    pub fn try_send_all<const N: usize>(
+        &self,
+        vals: &[T; N],
+    ) -> Result<(), FrozenTrySendError<()>> {
+        match self.sender.try_send_all(vals.to_vec()) {
+            Ok(()) => Ok(()),
+            Err(FrozenTrySendError::Full(_)) => Err(FrozenTrySendError::Full(())),
+            Err(FrozenTrySendError::Disconnected(_)) => Err(FrozenTrySendError::Disconnected(())),
+            Err(FrozenTrySendError::Frozen(_)) => Err(FrozenTrySendError::Frozen(())),
+        }
+    }
+
+    /// Identical to ```BroadcastSender::try_send_all```, except it sends the values
+    /// from an iterator and hands them back if they didn't go in
     ///
-    /// let (send, recv) = broadcast_queue(10);
+    /// # Examples
     ///
-    /// let handle = thread::spawn(move || {
-    ///     for _ in 0..10 {
-    ///         loop {
-    ///             match recv.try_recv() {
-    ///                 Ok(val) => {
-    ///                     println!("Got {}", val);
-    ///                     break;
-    ///                 },
-    ///                 Err(_) => (),
-    ///             }
-    ///         }
-    ///     }
-    ///     assert!(recv.try_recv().is_err()); // recv would block here
-    /// });
+    /// ```
+    /// use multiqueue2::{broadcast_queue, FrozenTrySendError};
     ///
-    /// for i in 0..10 {
-    ///     send.try_send(i).unwrap();
-    /// }
+    /// let (w, r) = broadcast_queue(4);
+    /// w.send_transaction(vec![1, 2]).unwrap();
+    /// assert_eq!(Err(FrozenTrySendError::Full(vec![3, 4, 5])), w.send_transaction(3..6));
+    /// assert_eq!(vec![1, 2], r.try_iter().collect::<Vec<_>>());
+    /// ```
+    pub fn send_transaction<I: IntoIterator<Item = T>>(
+        &self,
+        vals: I,
+    ) -> Result<(), FrozenTrySendError<Vec<T>>> {
+        self.sender.try_send_all(vals.into_iter().collect())
+    }
+
+    /// Tries to send a value which receivers can't see until the deadline has passed.
+    /// Values are still received in the order they were sent, so everything sent
+    /// after this waits for the deadline as well. Panics unless the queue was
+    /// created with ```broadcast_queue_delayed```.
     ///
-    /// // Drop the sender to close the queue
-    /// drop(send);
+    /// # Examples
     ///
-    /// handle.join();
     /// ```
-    #[inline(always)]
-    pub fn try_recv(&self) -> Result<T, TryRecvError> {
-        self.receiver.try_recv()
+    /// use multiqueue2::broadcast_queue_delayed;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let (w, r) = broadcast_queue_delayed(4);
+    /// let deadline = Instant::now() + Duration::from_millis(20);
+    /// w.try_send_after(1, deadline).unwrap();
+    /// assert!(r.try_recv().is_err());
+    /// // recv blocks until the deadline has passed
+    /// assert_eq!(1, r.recv().unwrap());
+    /// assert!(Instant::now() >= deadline);
+    /// ```
+    pub fn try_send_after(&self, val: T, deadline: Instant) -> Result<(), FrozenTrySendError<T>> {
+        self.sender.try_send_after(val, deadline)
     }
 
-    /// Receives a value from the queue, blocks until there is data.
+    /// Tries to send a value, and if the queue is full, takes the oldest value its only
+    /// stream hasn't received yet off to make room, handing it back instead of dropping it.
+    /// This keeps the most recent values around without losing track of the rest.
+    /// Queues with several streams or a paused one fail like ```try_send```.
+    /// Should other senders take the room before the value goes in, only the first
+    /// value displaced is handed back, and it's dropped if the send fails after all.
+    /// Panics unless the queue was created with ```broadcast_queue_replacing```.
     ///
-    /// # Examples:
+    /// # Examples
     ///
     /// ```
-    /// use multiqueue2::broadcast_queue;
-    /// let (w, r) = broadcast_queue(10);
-    /// w.try_send(1).unwrap();
-    /// assert_eq!(1, r.recv().unwrap());
+    /// use multiqueue2::broadcast_queue_replacing;
+    ///
+    /// let (w, r) = broadcast_queue_replacing(2);
+    /// assert_eq!(None, w.try_send_or_replace(1).unwrap());
+    /// assert_eq!(None, w.try_send_or_replace(2).unwrap());
+    /// assert_eq!(Some(1), w.try_send_or_replace(3).unwrap());
+    /// assert_eq!(2, r.try_recv().unwrap());
+    /// assert_eq!(3, r.try_recv().unwrap());
     /// ```
+    pub fn try_send_or_replace(&self, val: T) -> Result<Option<T>, FrozenTrySendError<T>> {
+        self.sender.try_send_or_replace(val)
+    }
+
+    /// Tries to send a value which receivers drop instead of receiving once ttl has
+    /// passed, so that stale values are never handed out. Panics unless the queue
+    /// was created with ```broadcast_queue_expiring```.
+    ///
+    /// # Examples
     ///
     /// ```
-    /// use multiqueue2::broadcast_queue;
+    /// use multiqueue2::broadcast_queue_expiring;
     /// use std::thread;
+    /// use std::time::Duration;
     ///
-    /// let (send, recv) = broadcast_queue(10);
+    /// let (w, r) = broadcast_queue_expiring(4);
+    /// w.try_send_with_ttl(1, Duration::from_millis(10)).unwrap();
+    /// w.try_send_with_ttl(2, Duration::from_secs(60)).unwrap();
+    /// thread::sleep(Duration::from_millis(20));
+    /// // 1 has expired, so it's skipped
+    /// assert_eq!(2, r.try_recv().unwrap());
+    /// ```
+    pub fn try_send_with_ttl(&self, val: T, ttl: Duration) -> Result<(), FrozenTrySendError<T>> {
+        self.sender.try_send_with_ttl(val, ttl)
+    }
+
+    /// Identical to ```BroadcastSender::try_send```, except it sends a clone of
+    /// the value, made only once there's room for it. A full queue hands the
+    /// reference back without having cloned anything.
     ///
-    /// let handle = thread::spawn(move || {
-    ///     // note the lack of dealing with failed reads.
-    ///     // unwrap 'ignores' the error where sender disconnects
-    ///     for _ in 0..10 {
-    ///         println!("Got {}", recv.recv().unwrap());
-    ///     }
-    ///     assert!(recv.try_recv().is_err());
-    /// });
+    /// Conflated queues clone the value before trying, since they have to own it
+    /// to find the value it replaces.
     ///
-    /// for i in 0..10 {
-    ///     send.try_send(i).unwrap();
-    /// }
+    /// # Examples:
     ///
-    /// // Drop the sender to close the queue
-    /// drop(send);
+    /// ```
+    /// use multiqueue2::{broadcast_queue, FrozenTrySendError};
     ///
-    /// handle.join();
+    /// let (w, r) = broadcast_queue(1);
+    /// let msg = "hello".to_string();
+    /// w.try_send_ref(&msg).unwrap();
+    /// assert_eq!(Err(FrozenTrySendError::Full(&msg)), w.try_send_ref(&msg));
+    /// assert_eq!(msg, r.try_recv().unwrap());
     /// ```
-    #[inline(always)]
-    pub fn recv(&self) -> Result<T, RecvError> {
-        self.receiver.recv()
+    pub fn try_send_ref<'a>(&self, val: &'a T) -> Result<(), FrozenTrySendError<&'a T>> {
+        self.sender.try_send_ref(val)
     }
 
-    /// Adds a new data stream to the queue, starting at the same position
-    /// as the ```BroadcastReceiver``` this is being called on.
+    /// Returns a barrier after everything sent so far, which
+    /// ```BroadcastSender::wait_for_barrier``` waits for every stream to get past.
+    /// Nothing is written into the queue, so the receivers don't see it.
     ///
     /// # Examples
     ///
     /// ```
     /// use multiqueue2::broadcast_queue;
-    /// let (w, r) = broadcast_queue(10);
-    /// w.try_send(1).unwrap();
-    /// assert_eq!(r.recv().unwrap(), 1);
+    ///
+    /// let (w, r) = broadcast_queue(4);
     /// w.try_send(1).unwrap();
-    /// let r2 = r.add_stream();
-    /// assert_eq!(r.recv().unwrap(), 1);
-    /// assert_eq!(r2.recv().unwrap(), 1);
-    /// assert!(r.try_recv().is_err());
-    /// assert!(r2.try_recv().is_err());
+    /// let barrier = w.barrier();
+    /// w.try_send(2).unwrap();
+    /// assert!(!w.barrier_passed(barrier));
+    /// assert_eq!(1, r.try_recv().unwrap());
+    /// assert!(w.barrier_passed(barrier));
     /// ```
+    pub fn barrier(&self) -> Barrier {
+        self.sender.barrier()
+    }
+
+    /// Sends the marker and returns a barrier right after it, for when the receivers
+    /// need to see where the barrier is. Sending the marker works like try_send.
+    ///
+    /// # Examples
     ///
     /// ```
     /// use multiqueue2::broadcast_queue;
-    ///
     /// use std::thread;
     ///
-    /// let (send, recv) = broadcast_queue(4);
-    /// let mut handles = vec![];
-    /// for i in 0..2 { // or n
-    ///     let cur_recv = recv.add_stream();
-    ///     handles.push(thread::spawn(move || {
-    ///         for val in cur_recv {
-    ///             println!("Stream {} got {}", i, val);
-    ///         }
-    ///     }));
-    /// }
-    ///
-    /// // Take notice that I drop the reader - this removes it from
-    /// // the queue, meaning that the readers in the new threads
-    /// // won't get starved by the lack of progress from recv
-    /// recv.unsubscribe();
-    ///
-    /// for i in 0..10 {
-    ///     // Don't do this busy loop in real stuff unless you're really sure
-    ///     loop {
-    ///         if send.try_send(i).is_ok() {
-    ///             break;
+    /// let (w, r) = broadcast_queue(4);
+    /// let reader = thread::spawn(move || {
+    ///     let mut segment = Vec::new();
+    ///     for val in r {
+    ///         // None marks where one file ends and the next begins
+    ///         match val {
+    ///             Some(line) => segment.push(line),
+    ///             None => segment.clear(),
     ///         }
     ///     }
-    /// }
-    ///
-    /// // Drop the sender to close the queue
-    /// drop(send);
-    ///
-    /// for t in handles {
-    ///     t.join();
-    /// }
-    ///
+    /// });
+    /// w.try_send(Some("first")).unwrap();
+    /// let barrier = w.try_send_barrier(None).unwrap();
+    /// // Nothing sent before the marker is still in flight after this
+    /// w.wait_for_barrier(barrier);
+    /// w.try_send(Some("second")).unwrap();
+    /// drop(w);
+    /// reader.join().unwrap();
     /// ```
-    pub fn add_stream(&self) -> BroadcastReceiver<T> {
-        BroadcastReceiver {
-            receiver: self.receiver.add_stream(),
-        }
+    pub fn try_send_barrier(&self, marker: T) -> Result<Barrier, FrozenTrySendError<T>> {
+        self.sender.try_send_barrier(marker)
     }
 
-    /// Removes the given reader from the queue subscription lib
-    /// Returns true if this is the last reader in a given broadcast unit
+    /// Returns whether every stream has received everything sent before the barrier.
+    /// Paused streams aren't waited for, and streams which leave stop being waited for.
+    pub fn barrier_passed(&self, barrier: Barrier) -> bool {
+        self.sender.barrier_passed(barrier)
+    }
+
+    /// Blocks until ```BroadcastSender::barrier_passed``` is true. Nothing wakes the
+    /// sender when a stream moves, so this polls the streams, sleeping for
+    /// at most DEFAULT_SELECT_MAX_SLEEP_US microseconds at a time.
+    pub fn wait_for_barrier(&self, barrier: Barrier) {
+        self.sender.wait_for_barrier(barrier)
+    }
+
+    /// Identical to ```BroadcastSender::wait_for_barrier```, except it gives up
+    /// once timeout has passed. Returns whether the streams passed the barrier
+    pub fn wait_for_barrier_timeout(&self, barrier: Barrier, timeout: Duration) -> bool {
+        self.sender.wait_for_barrier_timeout(barrier, timeout)
+    }
+
+    /// Returns how many values the queue holds, which is the capacity it was
+    /// made with rounded up to a power of two
     ///
     /// # Examples
     ///
     /// ```
     /// use multiqueue2::broadcast_queue;
-    /// let (writer, reader) = broadcast_queue(1);
-    /// let reader_2_1 = reader.add_stream();
-    /// let reader_2_2 = reader_2_1.clone();
-    /// writer.try_send(1).expect("This will succeed since queue is empty");
-    /// reader.try_recv().expect("This reader can read");
-    /// assert!(writer.try_send(1).is_err(), "This fails since the reader2 group hasn't advanced");
-    /// assert!(!reader_2_2.unsubscribe(), "This returns false since reader_2_1 is still alive");
-    /// assert!(reader_2_1.unsubscribe(),
-    ///         "This returns true since there are no readers alive in the reader_2_x group");
-    /// writer.try_send(1).expect("This succeeds since the reader_2 group is not blocking");
+    /// let (w, r) = broadcast_queue::<usize>(5);
+    /// assert_eq!(8, w.capacity());
+    /// assert_eq!(8, r.capacity());
     /// ```
-    pub fn unsubscribe(self) -> bool {
-        self.receiver.unsubscribe()
+    pub fn capacity(&self) -> usize {
+        self.sender.capacity()
     }
 
-    /// Returns a non-owning iterator that iterates over the queue
-    /// until it fails to receive an item, either through being empty
-    /// or begin disconnected. This iterator will never block.
+    /// Returns how many items the slowest stream is behind the writers.
     ///
-    /// # Examples:
+    /// # Examples
     ///
     /// ```
     /// use multiqueue2::broadcast_queue;
-    /// let (w, r) = broadcast_queue(2);
-    /// for _ in 0 .. 3 {
-    ///     w.try_send(1).unwrap();
-    ///     w.try_send(2).unwrap();
-    ///     for val in r.try_iter().zip(1..2) {
-    ///         assert_eq!(val.0, val.1);
-    ///     }
-    /// }
+    /// let (w, r) = broadcast_queue(4);
+    /// let r2 = r.add_stream();
+    /// w.try_send(1).unwrap();
+    /// w.try_send(2).unwrap();
+    /// r.try_recv().unwrap();
+    /// assert_eq!(2, w.max_lag());
+    /// r2.try_recv().unwrap();
+    /// assert_eq!(1, w.max_lag());
     /// ```
-    pub fn try_iter(&'_ self) -> BroadcastRefIter<'_, T> {
-        BroadcastRefIter { recv: self }
+    pub fn max_lag(&self) -> usize {
+        self.sender.max_lag()
     }
-}
 
-impl<T: Clone + Sync> BroadcastReceiver<T> {
-    /// If there is only one ```BroadcastReceiver``` on the stream, converts the
-    /// Receiver into a ```BroadcastUniReceiver``` otherwise returns the Receiver.
+    /// Returns the number of streams subscribed to the queue.
     ///
-    /// # Example:
+    /// # Examples
     ///
     /// ```
     /// use multiqueue2::broadcast_queue;
+    /// let (w, r) = broadcast_queue::<usize>(4);
+    /// let r2 = r.add_stream();
+    /// // Clones are on the same stream
+    /// let _r3 = r2.clone();
+    /// assert_eq!(2, w.stream_count());
+    /// r.unsubscribe();
+    /// assert_eq!(1, w.stream_count());
+    /// ```
+    pub fn stream_count(&self) -> usize {
+        self.sender.stream_count()
+    }
+
+    /// Freezes the queue, so that sends fail with ```Frozen``` until it's unfrozen,
+    /// which tells them apart from a queue with no room. Receivers go on receiving what's already in the queue and
+    /// then wait for more instead of disconnecting, so a queue can be stopped to
+    /// look at its state and started again. Any handle on the queue can freeze and
+    /// unfreeze it. Sends which already claimed their slot are waited for, so what
+    /// they sent can be received once this returns. A send which checked for the
+    /// freeze just before it but hasn't claimed a slot yet can still go in.
     ///
-    /// let (w, r) = broadcast_queue(10);
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::{broadcast_queue, FrozenTrySendError};
+    /// let (w, r) = broadcast_queue(4);
     /// w.try_send(1).unwrap();
-    /// let r2 = r.clone();
-    /// // Fails since there's two receivers on the stream
-    /// assert!(r2.into_single().is_err());
-    /// let single_r = r.into_single().unwrap();
-    /// let val = match single_r.try_recv_view(|x| 2 * *x) {
-    ///     Ok(val) => val,
-    ///     Err(_) => panic!("Queue should have an element"),
-    /// };
-    /// assert_eq!(2, val);
+    /// w.freeze();
+    /// assert_eq!(Err(FrozenTrySendError::Frozen(2)), w.try_send(2));
+    /// assert_eq!(1, r.try_recv().unwrap());
+    /// assert!(r.try_recv().is_err());
+    /// r.unfreeze();
+    /// w.try_send(2).unwrap();
+    /// assert_eq!(2, r.try_recv().unwrap());
     /// ```
-    pub fn into_single(self) -> Result<BroadcastUniReceiver<T>, BroadcastReceiver<T>> {
-        if self.receiver.is_single() {
-            Ok(BroadcastUniReceiver {
-                receiver: self.receiver,
-            })
-        } else {
-            Err(self)
-        }
+    pub fn freeze(&self) {
+        self.sender.freeze()
     }
-}
 
-impl<T: Clone + Sync> BroadcastUniReceiver<T> {
-    /// Identical to ```BroadcastReceiver::try_recv```
-    #[inline(always)]
-    pub fn try_recv(&self) -> Result<T, TryRecvError> {
-        self.receiver.try_recv()
+    /// Unfreezes the queue, so that sends go in again
+    pub fn unfreeze(&self) {
+        self.sender.unfreeze()
     }
 
-    /// Identical to ```BroadcastReceiver::recv```
-    #[inline(always)]
-    pub fn recv(&self) -> Result<T, RecvError> {
-        self.receiver.recv()
+    /// Returns whether the queue is frozen
+    pub fn is_frozen(&self) -> bool {
+        self.sender.is_frozen()
     }
 
-    /// Applies the passed function to the value in the queue without copying it out
-    /// If there is no data in the queue or the writers have disconnected,
-    /// returns an ```Err((F, TryRecvError))```
+    /// Returns the label of each stream along with how many items it is behind
+    /// the writers, so that a backed up queue can be traced to the stream holding it up.
+    ///
+    /// # Examples
     ///
-    /// # Example
     /// ```
     /// use multiqueue2::broadcast_queue;
+    /// let (w, r) = broadcast_queue(4);
+    /// let audit = r.add_stream_named("audit");
+    /// w.try_send(1).unwrap();
+    /// w.try_send(2).unwrap();
+    /// audit.try_recv().unwrap();
+    /// assert_eq!(vec![(None, 2), (Some("audit".to_string()), 1)], w.stream_lags());
+    /// ```
+    pub fn stream_lags(&self) -> Vec<(Option<String>, usize)> {
+        self.sender.stream_lags()
+    }
+
+    /// Returns the label of each stream which has had items waiting to be read, but
+    /// hasn't moved for at least threshold, along with how long it's been stuck.
+    /// Streams are only seen moving by calls to this, so it should be called more often
+    /// than threshold, and a stream's time starts at the call which last saw it move.
+    /// Paused streams and streams with nothing to read are never stalled.
     ///
-    /// let (w, r) = broadcast_queue(10);
-    /// let single_r = r.into_single().unwrap();
-    /// for i in 0..5 {
-    ///     w.try_send(i).unwrap();
-    /// }
+    /// # Examples
     ///
-    /// for i in 0..5 {
-    ///     let val = match single_r.try_recv_view(|x| 1 + *x) {
-    ///         Ok(val) => val,
-    ///         Err(_) => panic!("Queue shouldn't be disconncted or empty"),
-    ///     };
-    ///     assert_eq!(i + 1, val);
-    /// }
-    /// assert!(single_r.try_recv_view(|x| *x).is_err());
-    /// drop(w);
-    /// assert!(single_r.try_recv_view(|x| *x).is_err());
     /// ```
-    #[inline(always)]
-    pub fn try_recv_view<R, F: FnOnce(&T) -> R>(&self, op: F) -> Result<R, (F, TryRecvError)> {
-        self.receiver.try_recv_view(op)
+    /// use multiqueue2::broadcast_queue;
+    /// use std::thread::sleep;
+    /// use std::time::Duration;
+    ///
+    /// let (w, r) = broadcast_queue(4);
+    /// let hung = r.add_stream_named("hung");
+    /// w.try_send(1).unwrap();
+    /// r.try_recv().unwrap();
+    /// assert!(w.stalled_streams(Duration::from_millis(10)).is_empty());
+    /// sleep(Duration::from_millis(20));
+    /// let stalled = w.stalled_streams(Duration::from_millis(10));
+    /// assert_eq!(1, stalled.len());
+    /// assert_eq!(Some("hung".to_string()), stalled[0].0);
+    /// assert!(stalled[0].1 >= Duration::from_millis(20));
+    /// # drop(hung);
+    /// ```
+    pub fn stalled_streams(&self, threshold: Duration) -> Vec<(Option<String>, Duration)> {
+        self.sender.stalled_streams(threshold)
     }
 
-    /// Applies the passed function to the value in the queue without copying it out
-    /// If there is no data in the queue, blocks until an item is pushed into the queue
-    /// or all writers disconnect
+    /// Returns the number of writers subscribed to the queue.
+    ///
+    /// # Examples
     ///
-    /// # Example
     /// ```
     /// use multiqueue2::broadcast_queue;
+    /// let (w, r) = broadcast_queue::<usize>(4);
+    /// let w2 = w.clone();
+    /// assert_eq!(2, w.writer_count());
+    /// w2.unsubscribe();
+    /// assert_eq!(1, r.writer_count());
+    /// ```
+    pub fn writer_count(&self) -> usize {
+        self.sender.writer_count()
+    }
+
+    /// Returns a snapshot of the queue's state: the head, each stream's position
+    /// and consumers, how full the queue is and whether it's been closed.
+    /// With the "stats" feature it also has the number of sends, failed sends
+    /// and notifications to waiting readers, along with how many values
+    /// each stream has received.
     ///
-    /// let (w, r) = broadcast_queue(10);
-    /// let single_r = r.into_single().unwrap();
-    /// for i in 0..5 {
-    ///     w.try_send(i).unwrap();
-    /// }
+    /// # Examples
     ///
-    /// for i in 0..5 {
-    ///     let val = match single_r.recv_view(|x| 1 + *x) {
-    ///         Ok(val) => val,
-    ///         Err(_) => panic!("Queue shouldn't be disconncted or empty"),
-    ///     };
-    ///     assert_eq!(i + 1, val);
+    /// ```
+    /// use multiqueue2::broadcast_queue;
+    /// let (w, r) = broadcast_queue(2);
+    /// let r2 = r.add_stream();
+    /// for i in 0..3 {
+    ///     let _ = w.try_send(i);
     /// }
-    /// drop(w);
-    /// assert!(single_r.recv_view(|x| *x).is_err());
+    /// r.try_recv().unwrap();
+    /// let stats = w.stats();
+    /// assert_eq!(2, stats.head);
+    /// assert_eq!(2, stats.occupancy);
+    /// assert_eq!(vec![1, 0], stats.streams.iter().map(|s| s.tail).collect::<Vec<_>>());
+    /// assert!(!stats.closed);
+    /// # drop(r2);
     /// ```
-    #[inline(always)]
-    pub fn recv_view<R, F: FnOnce(&T) -> R>(&self, op: F) -> Result<R, (F, RecvError)> {
-        self.receiver.recv_view(op)
+    pub fn stats(&self) -> QueueStats {
+        self.sender.stats()
     }
 
-    /// Almost identical to ```BroadcastReceiver::unsubscribe```, except it doesn't
-    /// return a boolean of whether this was the last receiver on the stream
-    /// because a receiver of this type must be the last one on the stream
-    pub fn unsubscribe(self) {
-        self.receiver.unsubscribe();
+    /// Returns how many bytes the queue takes up, split into the slots holding
+    /// the values, the refcounts kept alongside them, cache line padding, the rest
+    /// of the queue itself, the streams and the memory manager's bookkeeping.
+    /// Only the slots are counted, not anything the values point to.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::broadcast_queue;
+    /// use std::mem::size_of;
+    ///
+    /// let (w, _r) = broadcast_queue::<u64>(1024);
+    /// let footprint = w.memory_footprint();
+    /// // Each slot holds a value along with its tag
+    /// assert_eq!(1024 * 2 * size_of::<u64>(), footprint.ring);
+    /// assert!(footprint.total() > footprint.ring + footprint.refcounts + footprint.padding);
+    /// ```
+    pub fn memory_footprint(&self) -> MemoryFootprint {
+        self.sender.memory_footprint()
     }
 
-    /// Transforms the ```BroadcastUniReceiver``` into a ```BroadcastReceiver```
+    /// Returns a read only handle on the queue for monitoring it. The inspector
+    /// isn't a writer or a stream, so holding it doesn't keep receivers from
+    /// disconnecting or writers from sending.
     ///
-    /// # Example
+    /// # Examples
     ///
     /// ```
     /// use multiqueue2::broadcast_queue;
     ///
-    /// let (w, r) = broadcast_queue(10);
+    /// let (w, r) = broadcast_queue(4);
+    /// let inspector = r.inspector();
     /// w.try_send(1).unwrap();
-    /// let single_r = r.into_single().unwrap();
-    /// let normal_r = single_r.into_multi();
-    /// normal_r.clone();
+    /// assert_eq!(Some(vec![1]), inspector.pending());
+    /// drop(w);
+    /// assert_eq!(0, inspector.writer_count());
+    /// assert_eq!(1, r.try_recv().unwrap());
+    /// assert!(r.try_recv().is_err());
     /// ```
-    pub fn into_multi(self) -> BroadcastReceiver<T> {
-        BroadcastReceiver {
-            receiver: self.receiver,
-        }
+    pub fn inspector(&self) -> Inspector<T>
+    where
+        T: 'static,
+    {
+        Inspector::new(self.sender.shared_queue())
     }
 
-    /// Returns a non-owning iterator that iterates over the queue
-    /// until it fails to receive an item, either through being empty
-    /// or begin disconnected. This iterator will never block.
+    /// Returns clones of every value the queue still holds for its receivers,
+    /// oldest first and along with the sequence number each was written at.
+    /// These are the values the furthest behind stream has yet to receive,
+    /// leaving out paused streams since writers don't wait on them.
+    /// Nothing is received, so every stream stays where it was. Together with
+    /// ```broadcast_queue_restored``` this can carry a queue's contents
+    /// over to a fresh one, as when saving state between test runs.
     ///
-    /// # Examples:
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::{broadcast_queue, broadcast_queue_restored};
+    ///
+    /// let (w, r) = broadcast_queue(4);
+    /// w.try_send("a").unwrap();
+    /// w.try_send("b").unwrap();
+    /// w.try_send("c").unwrap();
+    /// r.try_recv().unwrap();
+    /// let dump = w.dump();
+    /// assert_eq!(vec![(1, "b"), (2, "c")], dump);
+    ///
+    /// let (_w2, r2) = broadcast_queue_restored(4, dump);
+    /// assert_eq!(vec!["b", "c"], r2.try_iter().collect::<Vec<_>>());
+    /// // The dump left the original queue alone
+    /// assert_eq!("b", r.try_recv().unwrap());
+    /// ```
+    pub fn dump(&self) -> Vec<(u64, T)> {
+        self.sender.dump()
+    }
+
+    /// Looks over the queue's internals for inconsistencies, such as slots which don't
+    /// hold what streams have yet to read or a stream past the head, and reports
+    /// what it finds. This is for stress tests to run between phases, while nothing
+    /// is sending or receiving. Only available with the "test-util" feature.
+    #[cfg(feature = "test-util")]
+    pub fn check_invariants(&self) -> InvariantReport {
+        self.sender.check_invariants()
+    }
+
+    /// Starts injecting the faults in the passed config into the queue, for testing
+    /// how code copes with sends failing or readers waking up late. Every handle on
+    /// the queue sees the same faults, and a new config replaces the old one.
+    /// Only available with the "fault_injection" feature.
+    #[cfg(feature = "fault_injection")]
+    pub fn inject_faults(&self, config: FaultConfig) {
+        self.sender.inject_faults(config)
+    }
+
+    /// Sets the label the queue is listed under in ```registry::live_queues```,
+    /// replacing any label from before. Every handle on the queue labels the same
+    /// queue. Only available with the "registry" feature.
+    #[cfg(feature = "registry")]
+    pub fn set_label<N: Into<Cow<'static, str>>>(&self, label: N) {
+        self.sender.set_label(label.into())
+    }
+
+    /// Sets the high watermark in ```stats``` back to zero, returning what it was.
+    /// Only available with the "stats" feature.
+    ///
+    /// # Examples
     ///
     /// ```
+    /// # #[cfg(feature = "stats")]
+    /// # {
     /// use multiqueue2::broadcast_queue;
-    /// let (w, r) = broadcast_queue(2);
-    /// let sr = r.into_single().unwrap();
-    /// w.try_send(1).unwrap();
-    /// w.try_send(2).unwrap();
-    /// w.unsubscribe();
-    /// for val in sr.iter_with(|x| 2 * *x).zip(1..2) {
-    ///     assert_eq!(val.0, val.1 * 2);
+    /// let (w, r) = broadcast_queue(4);
+    /// for i in 0..3 {
+    ///     w.try_send(i).unwrap();
+    /// }
+    /// for _ in 0..3 {
+    ///     r.try_recv().unwrap();
     /// }
+    /// w.try_send(3).unwrap();
+    /// assert_eq!(3, w.stats().high_water);
+    /// assert_eq!(3, w.reset_high_water());
+    /// w.try_send(4).unwrap();
+    /// assert_eq!(2, w.stats().high_water);
+    /// # }
     /// ```
-    pub fn iter_with<R, F: FnMut(&T) -> R>(self, op: F) -> BroadcastUniIter<R, F, T> {
-        BroadcastUniIter { recv: self, op }
+    #[cfg(feature = "stats")]
+    pub fn reset_high_water(&self) -> usize {
+        self.sender.reset_high_water()
     }
 
-    /// Returns a non-owning iterator that iterates over the queue
-    /// until it fails to receive an item, either through being empty
-    /// or begin disconnected. This iterator will never block.
+    /// Turns this into a sender which puts values into the queue max_batch at a time,
+    /// waking the receivers once per batch. Panics if max_batch is 0
     ///
-    /// # Examples:
+    /// # Examples
     ///
     /// ```
     /// use multiqueue2::broadcast_queue;
-    /// let (w, r) = broadcast_queue(2);
-    /// let sr = r.into_single().unwrap();
-    /// for _ in 0 .. 3 {
-    ///     w.try_send(1).unwrap();
-    ///     w.try_send(2).unwrap();
-    ///     for val in sr.try_iter_with(|x| 2 * *x).zip(1..2) {
-    ///         assert_eq!(val.0, val.1*2);
-    ///     }
-    /// }
+    /// let (w, r) = broadcast_queue(4);
+    /// let mut w = w.batched(2);
+    /// w.try_send(1).unwrap();
+    /// assert!(r.try_recv().is_err());
+    /// w.try_send(2).unwrap();
+    /// assert_eq!(vec![1, 2], r.try_iter().collect::<Vec<_>>());
     /// ```
-    pub fn try_iter_with<R, F: FnMut(&T) -> R>(&self, op: F) -> BroadcastUniRefIter<R, F, T> {
-        BroadcastUniRefIter { recv: self, op }
+    pub fn batched(self, max_batch: usize) -> BatchedSender<T>
+    where
+        T: 'static,
+    {
+        BatchedSender::new(self.sender, max_batch, None)
     }
-}
 
-impl<T: Clone> BroadcastFutSender<T> {
-    /// Equivalent to ```BroadcastSender::try_send```
-    #[inline(always)]
-    pub fn try_send(&self, val: T) -> Result<(), TrySendError<T>> {
-        self.sender.try_send(val)
+    /// Identical to ```batched```, except a batch is also sent once its oldest value
+    /// has waited for max_delay. The delay is only checked when a value is sent
+    pub fn batched_with_delay(self, max_batch: usize, max_delay: Duration) -> BatchedSender<T>
+    where
+        T: 'static,
+    {
+        BatchedSender::new(self.sender, max_batch, Some(max_delay))
     }
 
-    /// Equivalent to ```BroadcastSender::unsubscribe```
+    /// Turns this into a sender which sends at most per_second values a second,
+    /// and up to burst of them at once after it's been quiet. Panics if either is 0
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::broadcast_queue;
+    /// let (w, r) = broadcast_queue(4);
+    /// let w = w.rate_limited(100, 1);
+    /// w.try_send(1).unwrap();
+    /// assert!(w.try_send(2).is_err());
+    /// w.send(2).unwrap();
+    /// assert_eq!(vec![1, 2], r.try_iter().collect::<Vec<_>>());
+    /// ```
+    pub fn rate_limited(self, per_second: u32, burst: u32) -> RateLimitedSender<T>
+    where
+        T: 'static,
+    {
+        RateLimitedSender::new(self.sender, per_second, burst)
+    }
+
+    /// Removes the writer from the queue
     pub fn unsubscribe(self) {
-        self.sender.unsubscribe()
+        self.sender.unsubscribe();
     }
 }
 
-impl<T: Clone> BroadcastFutReceiver<T> {
-    /// Equivalent to ```BroadcastReceiver::try_recv```
+type IntoMPMCResult<T> =
+    Result<(MPMCSender<T>, MPMCReceiver<T>), (BroadcastSender<T>, BroadcastReceiver<T>)>;
+
+impl<T: Clone> BroadcastReceiver<T> {
+    /// Tries to receive a value from the queue without blocking.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// use multiqueue2::broadcast_queue;
+    /// let (w, r) = broadcast_queue(10);
+    /// w.try_send(1).unwrap();
+    /// assert_eq!(1, r.try_recv().unwrap());
+    /// ```
+    ///
+    /// ```
+    /// use multiqueue2::broadcast_queue;
+    /// use std::thread;
+    ///
+    /// let (send, recv) = broadcast_queue(10);
+    ///
+    /// let handle = thread::spawn(move || {
+    ///     for _ in 0..10 {
+    ///         loop {
+    ///             match recv.try_recv() {
+    ///                 Ok(val) => {
+    ///                     println!("Got {}", val);
+    ///                     break;
+    ///                 },
+    ///                 Err(_) => (),
+    ///             }
+    ///         }
+    ///     }
+    ///     assert!(recv.try_recv().is_err()); // recv would block here
+    /// });
+    ///
+    /// for i in 0..10 {
+    ///     send.try_send(i).unwrap();
+    /// }
+    ///
+    /// // Drop the sender to close the queue
+    /// drop(send);
+    ///
+    /// handle.join();
+    /// ```
     #[inline(always)]
     pub fn try_recv(&self) -> Result<T, TryRecvError> {
         self.receiver.try_recv()
     }
 
-    /// Equivalent to ```BroadcastReceiver::recv```
+    /// Receives a value from the queue, blocks until there is data.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// use multiqueue2::broadcast_queue;
+    /// let (w, r) = broadcast_queue(10);
+    /// w.try_send(1).unwrap();
+    /// assert_eq!(1, r.recv().unwrap());
+    /// ```
+    ///
+    /// ```
+    /// use multiqueue2::broadcast_queue;
+    /// use std::thread;
+    ///
+    /// let (send, recv) = broadcast_queue(10);
+    ///
+    /// let handle = thread::spawn(move || {
+    ///     // note the lack of dealing with failed reads.
+    ///     // unwrap 'ignores' the error where sender disconnects
+    ///     for _ in 0..10 {
+    ///         println!("Got {}", recv.recv().unwrap());
+    ///     }
+    ///     assert!(recv.try_recv().is_err());
+    /// });
+    ///
+    /// for i in 0..10 {
+    ///     send.try_send(i).unwrap();
+    /// }
+    ///
+    /// // Drop the sender to close the queue
+    /// drop(send);
+    ///
+    /// handle.join();
+    /// ```
     #[inline(always)]
     pub fn recv(&self) -> Result<T, RecvError> {
         self.receiver.recv()
     }
 
-    pub fn add_stream(&self) -> BroadcastFutReceiver<T> {
-        BroadcastFutReceiver {
-            receiver: self.receiver.add_stream(),
-        }
+    /// Identical to ```BroadcastReceiver::try_recv```, but also returns the sequence
+    /// number the value was written at. Sequence numbers start at zero and
+    /// increase by one for every value written to the queue, so they can be
+    /// used to deduplicate or persist values downstream.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// use multiqueue2::broadcast_queue;
+    /// let (w, r) = broadcast_queue(10);
+    /// w.try_send("a").unwrap();
+    /// w.try_send("b").unwrap();
+    /// assert_eq!((0, "a"), r.try_recv_indexed().unwrap());
+    /// assert_eq!((1, "b"), r.try_recv_indexed().unwrap());
+    /// ```
+    #[inline(always)]
+    pub fn try_recv_indexed(&self) -> Result<(u64, T), TryRecvError> {
+        self.receiver.try_recv_indexed()
     }
 
-    /// Identical to ```BroadcastReceiver::unsubscribe```
-    pub fn unsubscribe(self) -> bool {
-        self.receiver.unsubscribe()
+    /// Identical to ```BroadcastReceiver::recv```, but also returns the sequence
+    /// number the value was written at
+    #[inline(always)]
+    pub fn recv_indexed(&self) -> Result<(u64, T), RecvError> {
+        self.receiver.recv_indexed()
     }
-}
 
-impl<T: Clone + Sync> BroadcastFutReceiver<T> {
-    /// Analog of ```BroadcastReceiver::into_single```
-    /// Since the ```BroadcastFutUniReceiver``` acts more like an iterator,
-    /// this takes the operation to be applied to each value
-    pub fn into_single<R, F: FnMut(&T) -> R>(
-        self,
-        op: F,
-    ) -> Result<BroadcastFutUniReceiver<R, F, T>, (F, BroadcastFutReceiver<T>)> {
-        match self.receiver.into_single(op) {
-            Ok(sreceiver) => Ok(BroadcastFutUniReceiver {
-                receiver: sreceiver,
-            }),
-            Err((o, receiver)) => Err((o, BroadcastFutReceiver { receiver })),
-        }
+    /// Identical to ```BroadcastReceiver::try_recv```, but also returns how long
+    /// the value waited in the queue. Only works on queues created with
+    /// ```broadcast_queue_timestamped```, and panics on any other queue.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::broadcast_queue_timestamped;
+    /// use std::thread::sleep;
+    /// use std::time::Duration;
+    ///
+    /// let (w, r) = broadcast_queue_timestamped(10);
+    /// w.try_send("a").unwrap();
+    /// sleep(Duration::from_millis(5));
+    /// let (val, latency) = r.try_recv_with_latency().unwrap();
+    /// assert_eq!("a", val);
+    /// assert!(latency >= Duration::from_millis(5));
+    /// ```
+    #[inline(always)]
+    pub fn try_recv_with_latency(&self) -> Result<(T, Duration), TryRecvError> {
+        self.receiver.try_recv_with_latency()
     }
-}
 
-impl<R, F: FnMut(&T) -> R, T: Clone + Sync> BroadcastFutUniReceiver<R, F, T> {
-    /// Equivalent to ```BroadcastReceiver::try_recv``` using the held operation
-    #[inline(always)]
-    pub fn try_recv(&mut self) -> Result<R, TryRecvError> {
-        self.receiver.try_recv()
+    /// Identical to ```BroadcastReceiver::recv```, but also returns how long
+    /// the value waited in the queue. Panics if the queue isn't timestamped
+    pub fn recv_with_latency(&self) -> Result<(T, Duration), RecvError> {
+        self.receiver.recv_with_latency()
     }
 
-    /// Equivalent to B```roadcastReceiver::recv``` using the held operation
-    #[inline(always)]
-    pub fn recv(&mut self) -> Result<R, RecvError> {
-        self.receiver.recv()
+    /// Copies as many waiting values as fit into buf without blocking, returning how
+    /// many it copied. When this is the only receiver on its stream, the values are
+    /// copied straight out of the queue and the stream moves past them all at once.
+    /// If there is no data in the queue or the writers have disconnected,
+    /// returns an ```Err(TryRecvError)```
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::broadcast_queue;
+    /// let (w, r) = broadcast_queue(8);
+    /// for i in 0..5 {
+    ///     w.try_send(i as f32).unwrap();
+    /// }
+    /// let mut samples = [0.0; 4];
+    /// assert_eq!(Ok(4), r.read_exact(&mut samples));
+    /// assert_eq!([0.0, 1.0, 2.0, 3.0], samples);
+    /// assert_eq!(Ok(1), r.read_exact(&mut samples));
+    /// assert!(r.read_exact(&mut samples).is_err());
+    /// ```
+    pub fn read_exact(&self, buf: &mut [T]) -> Result<usize, TryRecvError>
+    where
+        T: Copy,
+    {
+        self.receiver.try_recv_slice(buf)
     }
 
-    /// Adds a stream with the specified method
-    pub fn add_stream_with<RQ, FQ: FnMut(&T) -> RQ>(
-        &self,
-        op: FQ,
-    ) -> BroadcastFutUniReceiver<RQ, FQ, T> {
-        BroadcastFutUniReceiver {
-            receiver: self.receiver.add_stream_with(op),
-        }
+    /// Returns how many items sit between this stream and the writers.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::broadcast_queue;
+    /// let (w, r) = broadcast_queue(4);
+    /// w.try_send(1).unwrap();
+    /// w.try_send(2).unwrap();
+    /// assert_eq!(2, r.lag());
+    /// r.try_recv().unwrap();
+    /// assert_eq!(1, r.lag());
+    /// ```
+    pub fn lag(&self) -> usize {
+        self.receiver.lag()
     }
 
-    /// Returns a new receiver on the same stream using a different method
-    pub fn transform_operation<RQ, FQ: FnMut(&T) -> RQ>(
-        self,
-        op: FQ,
-    ) -> BroadcastFutUniReceiver<RQ, FQ, T> {
-        BroadcastFutUniReceiver {
-            receiver: self.receiver.add_stream_with(op),
-        }
+    /// Drops everything waiting on this stream, returning how many values it dropped,
+    /// so a stream can skip stale values without receiving each one. Values sent while
+    /// this runs may be left for the next receive. Other streams still get every value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::broadcast_queue;
+    /// let (w, r) = broadcast_queue(4);
+    /// let r2 = r.add_stream();
+    /// w.try_send(1).unwrap();
+    /// w.try_send(2).unwrap();
+    /// assert_eq!(2, r.clear());
+    /// assert!(r.try_recv().is_err());
+    /// assert_eq!(1, r2.try_recv().unwrap());
+    /// ```
+    pub fn clear(&self) -> usize {
+        self.receiver.clear()
     }
 
-    /// Identical to ```BroadcastReceiver::unsubscribe```
-    pub fn unsubscribe(self) -> bool {
-        self.receiver.unsubscribe()
+    /// Returns the number of receivers on this stream, including this one.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::broadcast_queue;
+    /// let (_w, r) = broadcast_queue::<usize>(4);
+    /// let r2 = r.clone();
+    /// let other = r.add_stream();
+    /// assert_eq!(2, r.consumers_on_stream());
+    /// assert_eq!(1, other.consumers_on_stream());
+    /// drop(r2);
+    /// assert_eq!(1, r.consumers_on_stream());
+    /// ```
+    pub fn consumers_on_stream(&self) -> usize {
+        self.receiver.consumers_on_stream()
     }
 
-    /// Transforms this back into ```BroadcastFutReceiver```, returning the new receiver
-    pub fn into_multi(self) -> BroadcastFutReceiver<T> {
-        BroadcastFutReceiver {
-            receiver: self.receiver.into_multi(),
-        }
+    /// Identical to ```BroadcastSender::stream_count```
+    pub fn stream_count(&self) -> usize {
+        self.receiver.stream_count()
     }
-}
 
-impl<T: Clone> Sink for &BroadcastFutSender<T> {
-    type SinkItem = T;
-    type SinkError = SendError<T>;
+    /// Identical to ```BroadcastSender::freeze```
+    pub fn freeze(&self) {
+        self.receiver.freeze()
+    }
 
-    #[inline(always)]
-    fn start_send(&mut self, msg: T) -> StartSend<T, SendError<T>> {
-        (&self.sender).start_send(msg)
+    /// Identical to ```BroadcastSender::unfreeze```
+    pub fn unfreeze(&self) {
+        self.receiver.unfreeze()
     }
 
-    #[inline(always)]
-    fn poll_complete(&mut self) -> Poll<(), SendError<T>> {
-        Ok(Async::Ready(()))
+    /// Identical to ```BroadcastSender::is_frozen```
+    pub fn is_frozen(&self) -> bool {
+        self.receiver.is_frozen()
     }
-}
 
-impl<T: Clone> Sink for BroadcastFutSender<T> {
-    type SinkItem = T;
-    type SinkError = SendError<T>;
+    /// Identical to ```BroadcastSender::writer_count```
+    pub fn writer_count(&self) -> usize {
+        self.receiver.writer_count()
+    }
 
-    #[inline(always)]
-    fn start_send(&mut self, msg: T) -> StartSend<T, SendError<T>> {
-        (&*self).start_send(msg)
+    /// Identical to ```BroadcastSender::capacity```
+    pub fn capacity(&self) -> usize {
+        self.receiver.capacity()
     }
 
-    #[inline(always)]
-    fn poll_complete(&mut self) -> Poll<(), SendError<T>> {
-        (&*self).poll_complete()
+    /// Identical to ```BroadcastSender::stats```
+    pub fn stats(&self) -> QueueStats {
+        self.receiver.stats()
     }
-}
 
-impl<T: Clone> Stream for &BroadcastFutReceiver<T> {
-    type Item = T;
-    type Error = ();
+    /// Identical to ```BroadcastSender::memory_footprint```
+    pub fn memory_footprint(&self) -> MemoryFootprint {
+        self.receiver.memory_footprint()
+    }
 
-    #[inline(always)]
-    fn poll(&mut self) -> Poll<Option<T>, ()> {
-        (&self.receiver).poll()
+    /// Identical to ```BroadcastSender::inspector```
+    pub fn inspector(&self) -> Inspector<T>
+    where
+        T: 'static,
+    {
+        Inspector::new(self.receiver.shared_queue())
     }
-}
 
-impl<T: Clone> Stream for BroadcastFutReceiver<T> {
-    type Item = T;
-    type Error = ();
+    /// Identical to ```BroadcastSender::check_invariants```
+    #[cfg(feature = "test-util")]
+    pub fn check_invariants(&self) -> InvariantReport {
+        self.receiver.check_invariants()
+    }
 
-    #[inline(always)]
-    fn poll(&mut self) -> Poll<Option<T>, ()> {
-        (&*self).poll()
+    /// Identical to ```BroadcastSender::inject_faults```
+    #[cfg(feature = "fault_injection")]
+    pub fn inject_faults(&self, config: FaultConfig) {
+        self.receiver.inject_faults(config)
     }
-}
 
-impl<R, F: FnMut(&T) -> R, T: Clone + Sync> Stream for BroadcastFutUniReceiver<R, F, T> {
-    type Item = R;
-    type Error = ();
+    /// Identical to ```BroadcastSender::set_label```
+    #[cfg(feature = "registry")]
+    pub fn set_label<N: Into<Cow<'static, str>>>(&self, label: N) {
+        self.receiver.set_label(label.into())
+    }
 
-    #[inline(always)]
-    fn poll(&mut self) -> Poll<Option<R>, ()> {
-        self.receiver.poll()
+    /// Identical to ```BroadcastSender::reset_high_water```
+    #[cfg(feature = "stats")]
+    pub fn reset_high_water(&self) -> usize {
+        self.receiver.reset_high_water()
     }
-}
 
-pub struct BroadcastIter<T: Clone> {
-    recv: BroadcastReceiver<T>,
-}
+    /// Returns clones of everything this stream has yet to receive,
+    /// without receiving any of it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::broadcast_queue;
+    /// let (w, r) = broadcast_queue(4);
+    /// w.try_send(1).unwrap();
+    /// w.try_send(2).unwrap();
+    /// assert_eq!(vec![1, 2], r.snapshot());
+    /// // Nothing was received
+    /// assert_eq!(1, r.try_recv().unwrap());
+    /// assert_eq!(vec![2], r.snapshot());
+    /// ```
+    pub fn snapshot(&self) -> Vec<T> {
+        self.receiver.snapshot()
+    }
 
-impl<T: Clone> Iterator for BroadcastIter<T> {
-    type Item = T;
+    /// Identical to ```BroadcastSender::dump```. This looks at the whole queue,
+    /// unlike ```snapshot``` which only looks at this stream
+    pub fn dump(&self) -> Vec<(u64, T)> {
+        self.receiver.dump()
+    }
 
-    #[inline(always)]
-    fn next(&mut self) -> Option<T> {
-        match self.recv.recv() {
-            Ok(val) => Some(val),
-            Err(_) => None,
+    /// Adds a new data stream to the queue, starting at the same position
+    /// as the ```BroadcastReceiver``` this is being called on.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::broadcast_queue;
+    /// let (w, r) = broadcast_queue(10);
+    /// w.try_send(1).unwrap();
+    /// assert_eq!(r.recv().unwrap(), 1);
+    /// w.try_send(1).unwrap();
+    /// let r2 = r.add_stream();
+    /// assert_eq!(r.recv().unwrap(), 1);
+    /// assert_eq!(r2.recv().unwrap(), 1);
+    /// assert!(r.try_recv().is_err());
+    /// assert!(r2.try_recv().is_err());
+    /// ```
+    ///
+    /// ```
+    /// use multiqueue2::broadcast_queue;
+    ///
+    /// use std::thread;
+    ///
+    /// let (send, recv) = broadcast_queue(4);
+    /// let mut handles = vec![];
+    /// for i in 0..2 { // or n
+    ///     let cur_recv = recv.add_stream();
+    ///     handles.push(thread::spawn(move || {
+    ///         for val in cur_recv {
+    ///             println!("Stream {} got {}", i, val);
+    ///         }
+    ///     }));
+    /// }
+    ///
+    /// // Take notice that I drop the reader - this removes it from
+    /// // the queue, meaning that the readers in the new threads
+    /// // won't get starved by the lack of progress from recv
+    /// recv.unsubscribe();
+    ///
+    /// for i in 0..10 {
+    ///     // Don't do this busy loop in real stuff unless you're really sure
+    ///     loop {
+    ///         if send.try_send(i).is_ok() {
+    ///             break;
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// // Drop the sender to close the queue
+    /// drop(send);
+    ///
+    /// for t in handles {
+    ///     t.join();
+    /// }
+    ///
+    /// ```
+    pub fn add_stream(&self) -> BroadcastReceiver<T> {
+        BroadcastReceiver {
+            receiver: self.receiver.add_stream(),
         }
     }
-}
-
-impl<T: Clone> IntoIterator for BroadcastReceiver<T> {
-    type Item = T;
-
-    type IntoIter = BroadcastIter<T>;
 
-    fn into_iter(self) -> BroadcastIter<T> {
-        BroadcastIter { recv: self }
+    /// Identical to ```add_stream```, but returns an error instead of panicking
+    /// when the queue was made with ```broadcast_queue_fixed``` and every one
+    /// of its streams is taken
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::{broadcast_queue_fixed, StreamsFullError};
+    /// let (_w, r) = broadcast_queue_fixed::<usize, 2>(4);
+    /// let _r2 = r.try_add_stream().unwrap();
+    /// assert_eq!(StreamsFullError, r.try_add_stream().unwrap_err());
+    /// ```
+    pub fn try_add_stream(&self) -> Result<BroadcastReceiver<T>, StreamsFullError> {
+        Ok(BroadcastReceiver {
+            receiver: self.receiver.try_add_stream()?,
+        })
     }
-}
-
-pub struct BroadcastSCIter<T: Clone + Sync> {
-    recv: BroadcastUniReceiver<T>,
-}
 
-impl<T: Clone + Sync> Iterator for BroadcastSCIter<T> {
-    type Item = T;
+    /// Identical to ```add_stream```, except the new stream carries the passed label.
+    /// Stream labels show up in the Debug output of its receivers
+    /// and in ```BroadcastSender::stream_lags```.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::broadcast_queue;
+    /// let (w, r) = broadcast_queue::<usize>(4);
+    /// let audit = r.add_stream_named("audit");
+    /// assert_eq!(Some("audit"), audit.stream_name());
+    /// // Clones are on the same stream
+    /// assert_eq!(Some("audit"), audit.clone().stream_name());
+    /// assert_eq!(None, r.stream_name());
+    /// ```
+    pub fn add_stream_named<N: Into<Cow<'static, str>>>(&self, name: N) -> BroadcastReceiver<T> {
+        BroadcastReceiver {
+            receiver: self.receiver.add_stream_named(name.into()),
+        }
+    }
 
-    #[inline(always)]
-    fn next(&mut self) -> Option<T> {
-        match self.recv.recv() {
-            Ok(val) => Some(val),
-            Err(_) => None,
+    /// Identical to ```clone```, except the new receiver carries the passed label.
+    /// Receiver labels show up in Debug output, and are kept by plain clones.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::broadcast_queue;
+    /// let (w, r) = broadcast_queue::<usize>(4);
+    /// let worker = r.clone_named(format!("worker-{}", 1));
+    /// assert_eq!(Some("worker-1"), worker.name());
+    /// assert_eq!(None, r.name());
+    /// assert!(format!("{:?}", worker).contains("worker-1"));
+    /// ```
+    pub fn clone_named<N: Into<Cow<'static, str>>>(&self, name: N) -> BroadcastReceiver<T> {
+        BroadcastReceiver {
+            receiver: self.receiver.clone_named(name.into()),
         }
     }
-}
 
-impl<T: Clone + Sync> IntoIterator for BroadcastUniReceiver<T> {
-    type Item = T;
+    /// Returns the label given to this receiver by ```clone_named```, if any
+    pub fn name(&self) -> Option<&str> {
+        self.receiver.name()
+    }
 
-    type IntoIter = BroadcastSCIter<T>;
+    /// Returns the label given to this stream by ```add_stream_named```, if any
+    pub fn stream_name(&self) -> Option<&str> {
+        self.receiver.stream_name()
+    }
 
-    fn into_iter(self) -> BroadcastSCIter<T> {
-        BroadcastSCIter { recv: self }
+    /// Adds a new data stream at the same spot as this one which only receives values
+    /// that pass the predicate. The rest are skipped over inside the queue
+    /// without ever being cloned. Streams added from a filtered stream keep its filter.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::broadcast_queue;
+    /// let (w, r) = broadcast_queue(10);
+    /// let evens = r.add_stream_filtered(|x: &u32| x % 2 == 0);
+    /// for i in 0..6 {
+    ///     w.try_send(i).unwrap();
+    /// }
+    /// assert_eq!(vec![0, 2, 4], evens.try_iter().collect::<Vec<_>>());
+    /// assert_eq!(6, r.try_iter().count());
+    /// ```
+    pub fn add_stream_filtered<P>(&self, keep: P) -> BroadcastReceiver<T>
+    where
+        P: Fn(&T) -> bool + Send + Sync + 'static,
+    {
+        BroadcastReceiver {
+            receiver: self.receiver.add_stream_filtered(keep),
+        }
+    }
+
+    /// Adds a new data stream at the same spot as this one which only receives
+    /// every nth value, starting with the next one. Like with ```add_stream_filtered```,
+    /// the values in between are skipped over without being cloned, so a consumer
+    /// which only needs a sample of the traffic doesn't pay for all of it.
+    /// The sample is exact when the stream has a single consumer. Consumers racing
+    /// for the same value can each count it, so with several it's only 1 in n on average.
+    /// Panics if n is 0.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::broadcast_queue;
+    /// let (w, r) = broadcast_queue(10);
+    /// let sampled = r.add_stream_sampled(3);
+    /// for i in 0..8 {
+    ///     w.try_send(i).unwrap();
+    /// }
+    /// assert_eq!(vec![0, 3, 6], sampled.try_iter().collect::<Vec<_>>());
+    /// ```
+    pub fn add_stream_sampled(&self, n: usize) -> BroadcastReceiver<T> {
+        BroadcastReceiver {
+            receiver: self.receiver.add_stream_sampled(n),
+        }
+    }
+
+    /// Adds a new data stream to the queue, starting at the oldest item
+    /// still held in the queue. This lets late subscribers replay up to
+    /// capacity - 1 of the most recently sent items, even ones which
+    /// every other stream has already received.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::broadcast_queue;
+    /// let (w, r) = broadcast_queue(4);
+    /// for i in 0..6 {
+    ///     w.try_send(i).unwrap();
+    ///     assert_eq!(i, r.recv().unwrap());
+    /// }
+    /// let replay = r.add_stream_from_earliest();
+    /// assert_eq!(3, replay.try_recv().unwrap());
+    /// assert_eq!(4, replay.try_recv().unwrap());
+    /// assert_eq!(5, replay.try_recv().unwrap());
+    /// assert!(replay.try_recv().is_err());
+    /// ```
+    pub fn add_stream_from_earliest(&self) -> BroadcastReceiver<T> {
+        BroadcastReceiver {
+            receiver: self.receiver.add_stream_from_earliest(),
+        }
+    }
+
+    /// Adds a new data stream to the queue, starting after the most recently
+    /// sent item regardless of where the ```BroadcastReceiver``` this is
+    /// being called on is.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::broadcast_queue;
+    /// let (w, r) = broadcast_queue(4);
+    /// w.try_send(1).unwrap();
+    /// let r2 = r.add_stream_from_latest();
+    /// assert!(r2.try_recv().is_err());
+    /// w.try_send(2).unwrap();
+    /// assert_eq!(2, r2.try_recv().unwrap());
+    /// assert_eq!(1, r.try_recv().unwrap());
+    /// ```
+    pub fn add_stream_from_latest(&self) -> BroadcastReceiver<T> {
+        BroadcastReceiver {
+            receiver: self.receiver.add_stream_from_latest(),
+        }
+    }
+
+    /// Adds a new stream at the same spot as this one which never makes the
+    /// writers wait. Once it falls more than max_lag items behind it gets
+    /// skipped forwards, and the next receive reports how many items were missed.
+    /// max_lag is capped at the capacity of the queue.
+    ///
+    /// # Panics
+    ///
+    /// Panics if max_lag is zero
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::{broadcast_queue, LaggedTryRecvError};
+    /// let (w, r) = broadcast_queue(4);
+    /// let slow = r.add_bounded_stream(2);
+    /// for i in 0..5 {
+    ///     // The writer never gets stuck behind the slow stream
+    ///     w.try_send(i).unwrap();
+    ///     assert_eq!(i, r.try_recv().unwrap());
+    /// }
+    /// assert_eq!(Err(LaggedTryRecvError::Lagged(3)), slow.try_recv());
+    /// assert_eq!(Ok(3), slow.try_recv());
+    /// assert_eq!(Ok(4), slow.try_recv());
+    /// ```
+    pub fn add_bounded_stream(&self, max_lag: usize) -> BroadcastBoundedReceiver<T> {
+        BroadcastBoundedReceiver {
+            receiver: self.receiver.add_bounded_stream(max_lag),
+        }
+    }
+
+    /// Removes the given reader from the queue subscription lib
+    /// Returns true if this is the last reader in a given broadcast unit
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::broadcast_queue;
+    /// let (writer, reader) = broadcast_queue(1);
+    /// let reader_2_1 = reader.add_stream();
+    /// let reader_2_2 = reader_2_1.clone();
+    /// writer.try_send(1).expect("This will succeed since queue is empty");
+    /// reader.try_recv().expect("This reader can read");
+    /// assert!(writer.try_send(1).is_err(), "This fails since the reader2 group hasn't advanced");
+    /// assert!(!reader_2_2.unsubscribe(), "This returns false since reader_2_1 is still alive");
+    /// assert!(reader_2_1.unsubscribe(),
+    ///         "This returns true since there are no readers alive in the reader_2_x group");
+    /// writer.try_send(1).expect("This succeeds since the reader_2 group is not blocking");
+    /// ```
+    pub fn unsubscribe(self) -> bool {
+        self.receiver.unsubscribe()
+    }
+
+    /// Turns this into a receiver which receives at most per_second values a second,
+    /// and up to burst of them at once after it's been quiet. Panics if either is 0
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::broadcast_queue;
+    /// let (w, r) = broadcast_queue(4);
+    /// let r = r.paced(100, 1);
+    /// w.try_send(1).unwrap();
+    /// w.try_send(2).unwrap();
+    /// assert_eq!(1, r.try_recv().unwrap());
+    /// assert!(r.try_recv().is_err());
+    /// assert_eq!(2, r.recv().unwrap());
+    /// ```
+    pub fn paced(self, per_second: u32, burst: u32) -> PacedReceiver<T>
+    where
+        T: 'static,
+    {
+        PacedReceiver::new(self.receiver, per_second, burst)
+    }
+
+    /// If this is the only ```BroadcastReceiver``` on the stream, pauses the stream
+    /// so that writers stop waiting on it, otherwise returns the Receiver.
+    /// Items on the stream may get overwritten while it's paused.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::broadcast_queue;
+    /// let (w, r) = broadcast_queue(4);
+    /// let r2 = r.add_stream();
+    /// let paused = r2.pause().unwrap();
+    /// // The paused stream doesn't hold up the writer
+    /// for i in 0..10 {
+    ///     w.try_send(i).unwrap();
+    ///     assert_eq!(i, r.try_recv().unwrap());
+    /// }
+    /// let r2 = paused.resume();
+    /// // The stream picks up from the oldest item still in the queue
+    /// for i in 7..10 {
+    ///     assert_eq!(i, r2.try_recv().unwrap());
+    /// }
+    /// ```
+    pub fn pause(self) -> Result<BroadcastPausedReceiver<T>, BroadcastReceiver<T>> {
+        if self.receiver.is_single() {
+            self.receiver.pause();
+            Ok(BroadcastPausedReceiver {
+                receiver: self.receiver,
+            })
+        } else {
+            Err(self)
+        }
+    }
+
+    /// If this is the only ```BroadcastReceiver``` on the stream and there's another
+    /// stream on the queue, takes the stream off the queue and returns where it was,
+    /// otherwise returns the Receiver. Writers don't wait on a detached stream,
+    /// so it can only be attached again while nothing it hadn't read was overwritten.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::{broadcast_queue, AttachError};
+    /// let (w, r) = broadcast_queue(4);
+    /// let r2 = r.add_stream();
+    /// w.try_send(1).unwrap();
+    /// let token = r2.detach().unwrap();
+    /// w.try_send(2).unwrap();
+    /// let r2 = r.attach(token).unwrap();
+    /// assert_eq!(1, r2.try_recv().unwrap());
+    /// assert_eq!(2, r2.try_recv().unwrap());
+    ///
+    /// let token = r2.detach().unwrap();
+    /// for i in 0..10 {
+    ///     w.try_send(i).unwrap();
+    ///     r.try_recv().unwrap();
+    /// }
+    /// assert_eq!(AttachError::Overwritten, r.attach(token).unwrap_err());
+    /// ```
+    pub fn detach(self) -> Result<BroadcastReaderToken<T>, BroadcastReceiver<T>> {
+        if self.receiver.is_single() && self.receiver.stream_count() > 1 {
+            Ok(BroadcastReaderToken {
+                token: self.receiver.detach(),
+            })
+        } else {
+            Err(self)
+        }
+    }
+
+    /// Adds a stream detached with ```BroadcastReceiver::detach``` back to the queue,
+    /// continuing from where it was detached. Fails if the stream was detached from a
+    /// different queue or if writers have overwritten anything it hadn't read
+    pub fn attach(
+        &self,
+        token: BroadcastReaderToken<T>,
+    ) -> Result<BroadcastReceiver<T>, AttachError> {
+        Ok(BroadcastReceiver {
+            receiver: self.receiver.attach(token.token)?,
+        })
+    }
+
+    /// Returns a non-owning iterator that iterates over the queue
+    /// until it fails to receive an item, either through being empty
+    /// or begin disconnected. This iterator will never block.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// use multiqueue2::broadcast_queue;
+    /// let (w, r) = broadcast_queue(2);
+    /// for _ in 0 .. 3 {
+    ///     w.try_send(1).unwrap();
+    ///     w.try_send(2).unwrap();
+    ///     for val in r.try_iter().zip(1..2) {
+    ///         assert_eq!(val.0, val.1);
+    ///     }
+    /// }
+    /// ```
+    pub fn try_iter(&'_ self) -> BroadcastRefIter<'_, T> {
+        BroadcastRefIter { recv: self }
+    }
+
+    /// Returns a non-owning iterator that blocks until each item arrives, like
+    /// ```recv```, and ends once the queue is disconnected. Unlike ```into_iter```
+    /// this leaves the receiver usable, so it can be looped over in a scoped thread.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// use multiqueue2::broadcast_queue;
+    /// let (w, r) = broadcast_queue(16);
+    /// for i in 0..10 {
+    ///     w.try_send(i).unwrap();
+    /// }
+    /// drop(w);
+    /// assert_eq!(45, r.iter().sum::<i32>());
+    /// assert!(r.try_recv().is_err());
+    /// ```
+    pub fn iter(&self) -> BroadcastBlockingRefIter<'_, T> {
+        BroadcastBlockingRefIter { recv: self }
+    }
+
+    /// Returns a non-owning iterator that blocks on each item like ```iter```, but
+    /// ends once nothing has arrived for timeout, or once the queue is disconnected.
+    /// The timeout starts over each time it waits for an item, so this drains a
+    /// burst of values and stops when the queue goes quiet. Waiting polls the queue
+    /// rather than blocking, since nothing wakes a receiver when its time runs out.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// use multiqueue2::broadcast_queue;
+    /// use std::time::Duration;
+    ///
+    /// let (w, r) = broadcast_queue(16);
+    /// for i in 0..5 {
+    ///     w.try_send(i).unwrap();
+    /// }
+    /// let batch: Vec<_> = r.iter_timeout(Duration::from_millis(10)).collect();
+    /// assert_eq!(vec![0, 1, 2, 3, 4], batch);
+    /// ```
+    pub fn iter_timeout(&self, timeout: Duration) -> BroadcastTimeoutIter<'_, T> {
+        BroadcastTimeoutIter {
+            recv: self,
+            deadline: None,
+            idle: Some(timeout),
+        }
+    }
+
+    /// Returns a non-owning iterator that blocks on each item like ```iter```, but
+    /// ends once the queue's clock reaches deadline, or once the queue is disconnected.
+    /// Values which are still waiting at the deadline are left in the queue
+    pub fn iter_until(&self, deadline: Instant) -> BroadcastTimeoutIter<'_, T> {
+        BroadcastTimeoutIter {
+            recv: self,
+            deadline: Some(deadline),
+            idle: None,
+        }
+    }
+
+    /// Turns this receiver and the passed sender into an ```MPMCSender``` and
+    /// ```MPMCReceiver``` for the same queue, so that receives move values out of it
+    /// instead of cloning them. Values which haven't been received stay in the queue.
+    ///
+    /// This only works once they are the only handles left on the queue, with no
+    /// other streams, receivers, senders or detached streams, and the stream isn't
+    /// bounded or paused. Otherwise both are handed back as they were.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// use multiqueue2::broadcast_queue;
+    ///
+    /// let (w, r) = broadcast_queue(10);
+    /// let r2 = r.add_stream();
+    /// w.try_send(1).unwrap();
+    /// // Fails since there's another stream
+    /// let (w, r) = r.into_mpmc(w).unwrap_err();
+    /// drop(r2);
+    /// let (w, r) = r.into_mpmc(w).ok().unwrap();
+    /// w.try_send(2).unwrap();
+    /// assert_eq!(1, r.try_recv().unwrap());
+    /// assert_eq!(2, r.try_recv().unwrap());
+    /// ```
+    #[allow(clippy::result_large_err)]
+    pub fn into_mpmc(self, sender: BroadcastSender<T>) -> IntoMPMCResult<T> {
+        match self.receiver.into_mpmc(sender.sender) {
+            Ok((sender, receiver)) => Ok((MPMCSender { sender }, MPMCReceiver { receiver })),
+            Err((sender, receiver)) => {
+                Err((BroadcastSender { sender }, BroadcastReceiver { receiver }))
+            }
+        }
     }
 }
 
-pub struct BroadcastRefIter<'a, T: Clone + 'a> {
-    recv: &'a BroadcastReceiver<T>,
+impl<T: Clone + Sync> BroadcastReceiver<T> {
+    /// Tries to view the next value in place without cloning it, even when
+    /// there are several consumers on the stream. The value is claimed for this
+    /// consumer before op runs, and writers can't reuse its slot until op is done,
+    /// so op should be quick. If there's nothing to view, op is handed back.
+    /// If op panics, the value still counts as received and its slot is given back.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::broadcast_queue;
+    /// let (w, r) = broadcast_queue(10);
+    /// let r2 = r.clone();
+    /// w.try_send(vec![1, 2, 3]).unwrap();
+    /// w.try_send(vec![4, 5, 6]).unwrap();
+    /// assert_eq!(3, r.try_recv_view(|v| v.len()).ok().unwrap());
+    /// assert_eq!(4, r2.try_recv_view(|v| v[0]).ok().unwrap());
+    /// assert!(r.try_recv_view(|v| v.len()).is_err());
+    /// ```
+    #[inline(always)]
+    pub fn try_recv_view<R, F: FnOnce(&T) -> R>(&self, op: F) -> Result<R, (F, TryRecvError)> {
+        self.receiver.try_recv_view_shared(op)
+    }
+
+    /// Identical to ```BroadcastReceiver::try_recv_view```, except it blocks until
+    /// there is data to view
+    #[inline(always)]
+    pub fn recv_view<R, F: FnOnce(&T) -> R>(&self, op: F) -> Result<R, (F, RecvError)> {
+        self.receiver.recv_view_shared(op)
+    }
+
+    /// If there is only one ```BroadcastReceiver``` on the stream, converts the
+    /// Receiver into a ```BroadcastUniReceiver``` otherwise returns the Receiver.
+    ///
+    /// # Example:
+    ///
+    /// ```
+    /// use multiqueue2::broadcast_queue;
+    ///
+    /// let (w, r) = broadcast_queue(10);
+    /// w.try_send(1).unwrap();
+    /// let r2 = r.clone();
+    /// // Fails since there's two receivers on the stream
+    /// assert!(r2.into_single().is_err());
+    /// let single_r = r.into_single().unwrap();
+    /// let val = match single_r.try_recv_view(|x| 2 * *x) {
+    ///     Ok(val) => val,
+    ///     Err(_) => panic!("Queue should have an element"),
+    /// };
+    /// assert_eq!(2, val);
+    /// ```
+    pub fn into_single(self) -> Result<BroadcastUniReceiver<T>, BroadcastReceiver<T>> {
+        if self.receiver.is_single() {
+            Ok(BroadcastUniReceiver {
+                receiver: self.receiver,
+            })
+        } else {
+            Err(self)
+        }
+    }
 }
 
-impl<'a, T: Clone + 'a> Iterator for BroadcastRefIter<'a, T> {
-    type Item = T;
+impl<T: Clone + Sync> BroadcastUniReceiver<T> {
+    /// Identical to ```BroadcastReceiver::try_recv```
+    #[inline(always)]
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.receiver.try_recv()
+    }
+
+    /// Identical to ```BroadcastReceiver::recv```
+    #[inline(always)]
+    pub fn recv(&self) -> Result<T, RecvError> {
+        self.receiver.recv()
+    }
+
+    /// Identical to ```BroadcastReceiver::try_recv_indexed```
+    #[inline(always)]
+    pub fn try_recv_indexed(&self) -> Result<(u64, T), TryRecvError> {
+        self.receiver.try_recv_indexed()
+    }
+
+    /// Identical to ```BroadcastReceiver::recv_indexed```
+    #[inline(always)]
+    pub fn recv_indexed(&self) -> Result<(u64, T), RecvError> {
+        self.receiver.recv_indexed()
+    }
+
+    /// Identical to ```BroadcastReceiver::try_recv_with_latency```
+    #[inline(always)]
+    pub fn try_recv_with_latency(&self) -> Result<(T, Duration), TryRecvError> {
+        self.receiver.try_recv_with_latency()
+    }
+
+    /// Identical to ```BroadcastReceiver::recv_with_latency```
+    pub fn recv_with_latency(&self) -> Result<(T, Duration), RecvError> {
+        self.receiver.recv_with_latency()
+    }
+
+    /// Identical to ```BroadcastReceiver::read_exact```
+    pub fn read_exact(&self, buf: &mut [T]) -> Result<usize, TryRecvError>
+    where
+        T: Copy,
+    {
+        self.receiver.try_recv_slice(buf)
+    }
+
+    /// Identical to ```BroadcastReceiver::lag```
+    pub fn lag(&self) -> usize {
+        self.receiver.lag()
+    }
+
+    /// Identical to ```BroadcastReceiver::snapshot```
+    pub fn snapshot(&self) -> Vec<T> {
+        self.receiver.snapshot()
+    }
+
+    /// Applies the passed function to the value in the queue without copying it out
+    /// If there is no data in the queue or the writers have disconnected,
+    /// returns an ```Err((F, TryRecvError))```
+    /// If the function panics, the value still counts as received.
+    ///
+    /// # Example
+    /// ```
+    /// use multiqueue2::broadcast_queue;
+    ///
+    /// let (w, r) = broadcast_queue(10);
+    /// let single_r = r.into_single().unwrap();
+    /// for i in 0..5 {
+    ///     w.try_send(i).unwrap();
+    /// }
+    ///
+    /// for i in 0..5 {
+    ///     let val = match single_r.try_recv_view(|x| 1 + *x) {
+    ///         Ok(val) => val,
+    ///         Err(_) => panic!("Queue shouldn't be disconncted or empty"),
+    ///     };
+    ///     assert_eq!(i + 1, val);
+    /// }
+    /// assert!(single_r.try_recv_view(|x| *x).is_err());
+    /// drop(w);
+    /// assert!(single_r.try_recv_view(|x| *x).is_err());
+    /// ```
+    #[inline(always)]
+    pub fn try_recv_view<R, F: FnOnce(&T) -> R>(&self, op: F) -> Result<R, (F, TryRecvError)> {
+        self.receiver.try_recv_view(op)
+    }
+
+    /// Applies the passed function to the value in the queue without copying it out
+    /// If there is no data in the queue, blocks until an item is pushed into the queue
+    /// or all writers disconnect
+    ///
+    /// # Example
+    /// ```
+    /// use multiqueue2::broadcast_queue;
+    ///
+    /// let (w, r) = broadcast_queue(10);
+    /// let single_r = r.into_single().unwrap();
+    /// for i in 0..5 {
+    ///     w.try_send(i).unwrap();
+    /// }
+    ///
+    /// for i in 0..5 {
+    ///     let val = match single_r.recv_view(|x| 1 + *x) {
+    ///         Ok(val) => val,
+    ///         Err(_) => panic!("Queue shouldn't be disconncted or empty"),
+    ///     };
+    ///     assert_eq!(i + 1, val);
+    /// }
+    /// drop(w);
+    /// assert!(single_r.recv_view(|x| *x).is_err());
+    /// ```
+    #[inline(always)]
+    pub fn recv_view<R, F: FnOnce(&T) -> R>(&self, op: F) -> Result<R, (F, RecvError)> {
+        self.receiver.recv_view(op)
+    }
+
+    /// Borrows the next value in the queue without copying it out. The value
+    /// counts as received once the returned guard is dropped, so unlike
+    /// ```try_recv_view``` it can be held across early returns and ```?```.
+    /// If there is no data in the queue or the writers have disconnected,
+    /// returns an ```Err(TryRecvError)```
+    ///
+    /// # Example
+    /// ```
+    /// use multiqueue2::broadcast_queue;
+    ///
+    /// let (w, r) = broadcast_queue(10);
+    /// let mut single_r = r.into_single().unwrap();
+    /// w.try_send(vec![1, 2]).unwrap();
+    ///
+    /// {
+    ///     let val = single_r.try_recv_guard().unwrap();
+    ///     assert_eq!(vec![1, 2], *val);
+    /// }
+    /// assert!(single_r.try_recv_guard().is_err());
+    /// ```
+    #[inline(always)]
+    pub fn try_recv_guard(&mut self) -> Result<RecvGuard<'_, T>, TryRecvError> {
+        self.receiver.try_recv_guard()
+    }
+
+    /// Identical to ```try_recv_guard```, except it blocks until an item is pushed
+    /// into the queue or all writers disconnect
+    #[inline(always)]
+    pub fn recv_guard(&mut self) -> Result<RecvGuard<'_, T>, RecvError> {
+        self.receiver.recv_guard()
+    }
+
+    /// Almost identical to ```BroadcastReceiver::unsubscribe```, except it doesn't
+    /// return a boolean of whether this was the last receiver on the stream
+    /// because a receiver of this type must be the last one on the stream
+    pub fn unsubscribe(self) {
+        self.receiver.unsubscribe();
+    }
+
+    /// Transforms the ```BroadcastUniReceiver``` into a ```BroadcastReceiver```
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use multiqueue2::broadcast_queue;
+    ///
+    /// let (w, r) = broadcast_queue(10);
+    /// w.try_send(1).unwrap();
+    /// let single_r = r.into_single().unwrap();
+    /// let normal_r = single_r.into_multi();
+    /// normal_r.clone();
+    /// ```
+    pub fn into_multi(self) -> BroadcastReceiver<T> {
+        BroadcastReceiver {
+            receiver: self.receiver,
+        }
+    }
+
+    /// Returns a non-owning iterator that iterates over the queue
+    /// until it fails to receive an item, either through being empty
+    /// or begin disconnected. This iterator will never block.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// use multiqueue2::broadcast_queue;
+    /// let (w, r) = broadcast_queue(2);
+    /// let sr = r.into_single().unwrap();
+    /// w.try_send(1).unwrap();
+    /// w.try_send(2).unwrap();
+    /// w.unsubscribe();
+    /// for val in sr.iter_with(|x| 2 * *x).zip(1..2) {
+    ///     assert_eq!(val.0, val.1 * 2);
+    /// }
+    /// ```
+    pub fn iter_with<R, F: FnMut(&T) -> R>(self, op: F) -> BroadcastUniIter<R, F, T> {
+        BroadcastUniIter { recv: self, op }
+    }
+
+    /// Returns a non-owning iterator that iterates over the queue
+    /// until it fails to receive an item, either through being empty
+    /// or begin disconnected. This iterator will never block.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// use multiqueue2::broadcast_queue;
+    /// let (w, r) = broadcast_queue(2);
+    /// let sr = r.into_single().unwrap();
+    /// for _ in 0 .. 3 {
+    ///     w.try_send(1).unwrap();
+    ///     w.try_send(2).unwrap();
+    ///     for val in sr.try_iter_with(|x| 2 * *x).zip(1..2) {
+    ///         assert_eq!(val.0, val.1*2);
+    ///     }
+    /// }
+    /// ```
+    pub fn try_iter_with<R, F: FnMut(&T) -> R>(&self, op: F) -> BroadcastUniRefIter<'_, R, F, T> {
+        BroadcastUniRefIter { recv: self, op }
+    }
+}
+
+impl<T: Clone> BroadcastBoundedReceiver<T> {
+    /// Tries to receive a value from the stream without blocking.
+    /// If the stream was skipped forwards since the last receive, this returns
+    /// ```Lagged``` with the number of items missed instead, and the following
+    /// receive returns the oldest item still held for the stream.
+    #[inline(always)]
+    pub fn try_recv(&self) -> Result<T, LaggedTryRecvError> {
+        self.receiver.try_recv_bounded()
+    }
+
+    /// Receives a value from the stream, blocks until there is data or
+    /// the stream was skipped forwards.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::{broadcast_queue, LaggedRecvError};
+    /// let (w, r) = broadcast_queue(4);
+    /// let slow = r.add_bounded_stream(1);
+    /// w.try_send(1).unwrap();
+    /// w.try_send(2).unwrap();
+    /// assert_eq!(Err(LaggedRecvError::Lagged(1)), slow.recv());
+    /// assert_eq!(Ok(2), slow.recv());
+    /// ```
+    pub fn recv(&self) -> Result<T, LaggedRecvError> {
+        self.receiver.recv_bounded()
+    }
+
+    /// Identical to ```BroadcastReceiver::lag```
+    pub fn lag(&self) -> usize {
+        self.receiver.lag()
+    }
+
+    /// Identical to ```BroadcastReceiver::snapshot```
+    pub fn snapshot(&self) -> Vec<T> {
+        self.receiver.snapshot()
+    }
+
+    /// Returns the most items this stream may fall behind the writers
+    pub fn max_lag(&self) -> usize {
+        self.receiver.max_lag()
+    }
+
+    /// Adds a new bounded stream at the same spot as this one, with the same max lag
+    pub fn add_stream(&self) -> BroadcastBoundedReceiver<T> {
+        self.add_bounded_stream(self.max_lag())
+    }
+
+    /// Identical to ```BroadcastReceiver::add_bounded_stream```
+    pub fn add_bounded_stream(&self, max_lag: usize) -> BroadcastBoundedReceiver<T> {
+        BroadcastBoundedReceiver {
+            receiver: self.receiver.add_bounded_stream(max_lag),
+        }
+    }
+
+    /// Identical to ```BroadcastReceiver::unsubscribe```
+    pub fn unsubscribe(self) -> bool {
+        self.receiver.unsubscribe()
+    }
+}
+
+impl<T: Clone> BroadcastPausedReceiver<T> {
+    /// Makes the writers wait on the stream again. If nothing the stream hadn't
+    /// read was overwritten it continues from where it was paused, otherwise it
+    /// continues from the oldest item still held in the queue
+    pub fn resume(self) -> BroadcastReceiver<T> {
+        self.receiver.resume();
+        BroadcastReceiver {
+            receiver: self.receiver,
+        }
+    }
+
+    /// Identical to ```BroadcastReceiver::unsubscribe```
+    pub fn unsubscribe(self) -> bool {
+        self.receiver.unsubscribe()
+    }
+}
+
+impl<T: Clone> BroadcastFutSender<T> {
+    /// Equivalent to ```BroadcastSender::try_send```
+    #[inline(always)]
+    pub fn try_send(&self, val: T) -> Result<(), FrozenTrySendError<T>> {
+        self.sender.try_send(val)
+    }
+
+    /// Equivalent to ```BroadcastSender::max_lag```
+    pub fn max_lag(&self) -> usize {
+        self.sender.max_lag()
+    }
+
+    /// Equivalent to ```BroadcastSender::stream_count```
+    pub fn stream_count(&self) -> usize {
+        self.sender.stream_count()
+    }
+
+    /// Equivalent to ```BroadcastSender::freeze```
+    pub fn freeze(&self) {
+        self.sender.freeze()
+    }
+
+    /// Equivalent to ```BroadcastSender::unfreeze```
+    pub fn unfreeze(&self) {
+        self.sender.unfreeze()
+    }
+
+    /// Equivalent to ```BroadcastSender::is_frozen```
+    pub fn is_frozen(&self) -> bool {
+        self.sender.is_frozen()
+    }
+
+    /// Equivalent to ```BroadcastSender::stream_lags```
+    pub fn stream_lags(&self) -> Vec<(Option<String>, usize)> {
+        self.sender.stream_lags()
+    }
+
+    /// Equivalent to ```BroadcastSender::stalled_streams```
+    pub fn stalled_streams(&self, threshold: Duration) -> Vec<(Option<String>, Duration)> {
+        self.sender.stalled_streams(threshold)
+    }
+
+    /// Equivalent to ```BroadcastSender::writer_count```
+    pub fn writer_count(&self) -> usize {
+        self.sender.writer_count()
+    }
+
+    /// Equivalent to ```BroadcastSender::stats```
+    pub fn stats(&self) -> QueueStats {
+        self.sender.stats()
+    }
+
+    /// Equivalent to ```BroadcastSender::reset_high_water```
+    #[cfg(feature = "stats")]
+    pub fn reset_high_water(&self) -> usize {
+        self.sender.reset_high_water()
+    }
+
+    /// Changes how many times senders and receivers on this queue spin and
+    /// then yield before parking, as set up front by ```broadcast_fut_queue_with```.
+    /// This takes effect for every handle on the queue, including ones already waiting
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::broadcast_fut_queue;
+    ///
+    /// let (w, r) = broadcast_fut_queue(10);
+    /// // Latency matters more than cpu from here on, so spin for longer before parking
+    /// w.set_spins(100_000, 100);
+    /// w.try_send(1).unwrap();
+    /// assert_eq!(1, r.try_recv().unwrap());
+    /// ```
+    pub fn set_spins(&self, try_spins: usize, yield_spins: usize) {
+        self.sender.set_spins(try_spins, yield_spins)
+    }
+
+    /// Equivalent to ```BroadcastSender::rate_limited```, making a sender which
+    /// implements ```Sink```, see ```RateLimitedFutSender```
+    pub fn rate_limited(self, per_second: u32, burst: u32) -> RateLimitedFutSender<T>
+    where
+        T: 'static,
+    {
+        RateLimitedFutSender::new(self.sender, per_second, burst)
+    }
+
+    /// Equivalent to ```BroadcastSender::unsubscribe```
+    pub fn unsubscribe(self) {
+        self.sender.unsubscribe()
+    }
+}
+
+impl<T: Clone> BroadcastFutReceiver<T> {
+    /// Equivalent to ```BroadcastReceiver::try_recv```
+    #[inline(always)]
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.receiver.try_recv()
+    }
+
+    /// Equivalent to ```BroadcastReceiver::recv```
+    #[inline(always)]
+    pub fn recv(&self) -> Result<T, RecvError> {
+        self.receiver.recv()
+    }
+
+    /// Equivalent to ```BroadcastReceiver::try_recv_indexed```
+    #[inline(always)]
+    pub fn try_recv_indexed(&self) -> Result<(u64, T), TryRecvError> {
+        self.receiver.try_recv_indexed()
+    }
+
+    /// Equivalent to ```BroadcastReceiver::recv_indexed```
+    #[inline(always)]
+    pub fn recv_indexed(&self) -> Result<(u64, T), RecvError> {
+        self.receiver.recv_indexed()
+    }
+
+    /// Equivalent to ```BroadcastReceiver::try_recv_with_latency```
+    #[inline(always)]
+    pub fn try_recv_with_latency(&self) -> Result<(T, Duration), TryRecvError> {
+        self.receiver.try_recv_with_latency()
+    }
+
+    /// Equivalent to ```BroadcastReceiver::recv_with_latency```
+    pub fn recv_with_latency(&self) -> Result<(T, Duration), RecvError> {
+        self.receiver.recv_with_latency()
+    }
+
+    /// Equivalent to ```BroadcastReceiver::lag```
+    pub fn lag(&self) -> usize {
+        self.receiver.lag()
+    }
+
+    /// Equivalent to ```BroadcastReceiver::clear```
+    pub fn clear(&self) -> usize {
+        self.receiver.clear()
+    }
+
+    /// Equivalent to ```BroadcastReceiver::consumers_on_stream```
+    pub fn consumers_on_stream(&self) -> usize {
+        self.receiver.consumers_on_stream()
+    }
+
+    /// Equivalent to ```BroadcastReceiver::add_stream_named```
+    pub fn add_stream_named<N: Into<Cow<'static, str>>>(&self, name: N) -> BroadcastFutReceiver<T> {
+        BroadcastFutReceiver {
+            receiver: self.receiver.add_stream_named(name.into()),
+        }
+    }
+
+    /// Equivalent to ```BroadcastReceiver::clone_named```
+    pub fn clone_named<N: Into<Cow<'static, str>>>(&self, name: N) -> BroadcastFutReceiver<T> {
+        BroadcastFutReceiver {
+            receiver: self.receiver.clone_named(name.into()),
+        }
+    }
+
+    /// Equivalent to ```BroadcastReceiver::name```
+    pub fn name(&self) -> Option<&str> {
+        self.receiver.name()
+    }
+
+    /// Equivalent to ```BroadcastReceiver::stream_name```
+    pub fn stream_name(&self) -> Option<&str> {
+        self.receiver.stream_name()
+    }
+
+    /// Equivalent to ```BroadcastSender::stream_count```
+    pub fn stream_count(&self) -> usize {
+        self.receiver.stream_count()
+    }
+
+    /// Equivalent to ```BroadcastSender::writer_count```
+    pub fn writer_count(&self) -> usize {
+        self.receiver.writer_count()
+    }
+
+    /// Equivalent to ```BroadcastSender::stats```
+    pub fn stats(&self) -> QueueStats {
+        self.receiver.stats()
+    }
+
+    /// Equivalent to ```BroadcastSender::reset_high_water```
+    #[cfg(feature = "stats")]
+    pub fn reset_high_water(&self) -> usize {
+        self.receiver.reset_high_water()
+    }
+
+    /// Equivalent to ```BroadcastReceiver::snapshot```
+    pub fn snapshot(&self) -> Vec<T> {
+        self.receiver.snapshot()
+    }
+
+    pub fn add_stream(&self) -> BroadcastFutReceiver<T> {
+        BroadcastFutReceiver {
+            receiver: self.receiver.add_stream(),
+        }
+    }
+
+    /// Equivalent to ```BroadcastReceiver::add_stream_filtered```
+    pub fn add_stream_filtered<P>(&self, keep: P) -> BroadcastFutReceiver<T>
+    where
+        P: Fn(&T) -> bool + Send + Sync + 'static,
+    {
+        BroadcastFutReceiver {
+            receiver: self.receiver.add_stream_filtered(keep),
+        }
+    }
+
+    /// Equivalent to ```BroadcastReceiver::add_stream_sampled```
+    pub fn add_stream_sampled(&self, n: usize) -> BroadcastFutReceiver<T> {
+        BroadcastFutReceiver {
+            receiver: self.receiver.add_stream_sampled(n),
+        }
+    }
+
+    /// Equivalent to ```BroadcastReceiver::add_stream_from_earliest```
+    pub fn add_stream_from_earliest(&self) -> BroadcastFutReceiver<T> {
+        BroadcastFutReceiver {
+            receiver: self.receiver.add_stream_from_earliest(),
+        }
+    }
+
+    /// Equivalent to ```BroadcastReceiver::add_stream_from_latest```
+    pub fn add_stream_from_latest(&self) -> BroadcastFutReceiver<T> {
+        BroadcastFutReceiver {
+            receiver: self.receiver.add_stream_from_latest(),
+        }
+    }
+
+    /// Equivalent to ```BroadcastFutSender::set_spins```
+    pub fn set_spins(&self, try_spins: usize, yield_spins: usize) {
+        self.receiver.set_spins(try_spins, yield_spins)
+    }
+
+    /// Identical to ```BroadcastReceiver::unsubscribe```
+    pub fn unsubscribe(self) -> bool {
+        self.receiver.unsubscribe()
+    }
+}
+
+impl<T: Clone + Sync> BroadcastFutReceiver<T> {
+    /// Analog of ```BroadcastReceiver::into_single```
+    /// Since the ```BroadcastFutUniReceiver``` acts more like an iterator,
+    /// this takes the operation to be applied to each value
+    // Too large for clippy only with order_checks, as with FutInnerRecv::into_single
+    #[cfg_attr(feature = "order_checks", allow(clippy::result_large_err))]
+    pub fn into_single<R, F: FnMut(&T) -> R>(
+        self,
+        op: F,
+    ) -> Result<BroadcastFutUniReceiver<R, F, T>, (F, BroadcastFutReceiver<T>)> {
+        match self.receiver.into_single(op) {
+            Ok(sreceiver) => Ok(BroadcastFutUniReceiver {
+                receiver: sreceiver,
+            }),
+            Err((o, receiver)) => Err((o, BroadcastFutReceiver { receiver })),
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<T: Clone + Send + Sync + 'static> BroadcastFutReceiver<T> {
+    /// Adds ```streams``` streams, each turned into a single receiver, and spawns
+    /// a task on the tokio runtime for each of them which passes every value to ```f```
+    /// and waits on the future it returns before moving on. This receiver unsubscribes
+    /// once the streams are added, so every value sent from then on goes to each task,
+    /// see ```StreamTasks```
+    ///
+    /// # Panics
+    ///
+    /// Panics if ```streams``` is zero, or if this isn't called from a task
+    /// running on the tokio runtime
+    pub fn spawn_streams<F, U>(self, streams: usize, f: F) -> StreamTasks
+    where
+        F: Fn(&T) -> U + Send + Sync + 'static,
+        U: IntoFuture<Item = (), Error = ()>,
+        U::Future: Send + 'static,
+    {
+        StreamTasks::new(self, streams, f)
+    }
+}
+
+impl<R, F: FnMut(&T) -> R, T: Clone + Sync> BroadcastFutUniReceiver<R, F, T> {
+    /// Equivalent to ```BroadcastReceiver::try_recv``` using the held operation
+    #[inline(always)]
+    pub fn try_recv(&mut self) -> Result<R, TryRecvError> {
+        self.receiver.try_recv()
+    }
+
+    /// Equivalent to B```roadcastReceiver::recv``` using the held operation
+    #[inline(always)]
+    pub fn recv(&mut self) -> Result<R, RecvError> {
+        self.receiver.recv()
+    }
+
+    /// Equivalent to ```BroadcastReceiver::lag```
+    pub fn lag(&self) -> usize {
+        self.receiver.lag()
+    }
+
+    /// Adds a stream with the specified method
+    pub fn add_stream_with<RQ, FQ: FnMut(&T) -> RQ>(
+        &self,
+        op: FQ,
+    ) -> BroadcastFutUniReceiver<RQ, FQ, T> {
+        BroadcastFutUniReceiver {
+            receiver: self.receiver.add_stream_with(op),
+        }
+    }
+
+    /// Returns a new receiver on the same stream using a different method
+    pub fn transform_operation<RQ, FQ: FnMut(&T) -> RQ>(
+        self,
+        op: FQ,
+    ) -> BroadcastFutUniReceiver<RQ, FQ, T> {
+        BroadcastFutUniReceiver {
+            receiver: self.receiver.add_stream_with(op),
+        }
+    }
+
+    /// Identical to ```BroadcastReceiver::unsubscribe```
+    pub fn unsubscribe(self) -> bool {
+        self.receiver.unsubscribe()
+    }
+
+    /// Transforms this back into ```BroadcastFutReceiver```, returning the new receiver
+    pub fn into_multi(self) -> BroadcastFutReceiver<T> {
+        BroadcastFutReceiver {
+            receiver: self.receiver.into_multi(),
+        }
+    }
+}
+
+impl<T: Clone> Sink for &BroadcastFutSender<T> {
+    type SinkItem = T;
+    type SinkError = SendError<T>;
+
+    #[inline(always)]
+    fn start_send(&mut self, msg: T) -> StartSend<T, SendError<T>> {
+        (&self.sender).start_send(msg)
+    }
+
+    #[inline(always)]
+    fn poll_complete(&mut self) -> Poll<(), SendError<T>> {
+        (&self.sender).poll_complete()
+    }
+}
+
+impl<T: Clone> Sink for BroadcastFutSender<T> {
+    type SinkItem = T;
+    type SinkError = SendError<T>;
+
+    #[inline(always)]
+    fn start_send(&mut self, msg: T) -> StartSend<T, SendError<T>> {
+        (&*self).start_send(msg)
+    }
+
+    #[inline(always)]
+    fn poll_complete(&mut self) -> Poll<(), SendError<T>> {
+        (&*self).poll_complete()
+    }
+}
+
+impl<T: Clone> Stream for &BroadcastFutReceiver<T> {
+    type Item = T;
+    type Error = ();
+
+    #[inline(always)]
+    fn poll(&mut self) -> Poll<Option<T>, ()> {
+        (&self.receiver).poll()
+    }
+}
+
+impl<T: Clone> Stream for BroadcastFutReceiver<T> {
+    type Item = T;
+    type Error = ();
+
+    #[inline(always)]
+    fn poll(&mut self) -> Poll<Option<T>, ()> {
+        (&*self).poll()
+    }
+}
+
+impl<R, F: FnMut(&T) -> R, T: Clone + Sync + fmt::Debug> fmt::Debug
+    for BroadcastFutUniReceiver<R, F, T>
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BroadcastFutUniReceiver")
+            .field("receiver", &self.receiver)
+            .finish()
+    }
+}
+
+impl<R, F: FnMut(&T) -> R + Clone, T: Clone + Sync> Clone for BroadcastFutUniReceiver<R, F, T> {
+    fn clone(&self) -> Self {
+        self.add_stream_with(self.receiver.op.clone())
+    }
+}
+
+impl<R, F: FnMut(&T) -> R, T: Clone + Sync> Stream for BroadcastFutUniReceiver<R, F, T> {
+    type Item = R;
+    type Error = ();
+
+    #[inline(always)]
+    fn poll(&mut self) -> Poll<Option<R>, ()> {
+        self.receiver.poll()
+    }
+}
+
+pub struct BroadcastIter<T: Clone> {
+    recv: BroadcastReceiver<T>,
+}
+
+impl<T: Clone> Iterator for BroadcastIter<T> {
+    type Item = T;
+
+    #[inline(always)]
+    fn next(&mut self) -> Option<T> {
+        self.recv.recv().ok()
+    }
+}
+
+impl<T: Clone + 'static> From<BroadcastReceiver<T>> for MergeSource<T> {
+    fn from(recv: BroadcastReceiver<T>) -> MergeSource<T> {
+        MergeSource::new(recv.receiver)
+    }
+}
+
+impl<T: Clone> BroadcastReceiver<T> {
+    /// Gives up the receiver's stream to a ```ConsumerGroup```
+    pub(crate) fn into_inner(self) -> InnerRecv<BCast<T>, T> {
+        self.receiver
+    }
+}
+
+impl<T: Clone + 'static> From<BroadcastSender<T>> for TeeTarget<T> {
+    fn from(send: BroadcastSender<T>) -> TeeTarget<T> {
+        TeeTarget::new(send.sender)
+    }
+}
+
+impl<T: Clone> IntoIterator for BroadcastReceiver<T> {
+    type Item = T;
+
+    type IntoIter = BroadcastIter<T>;
+
+    fn into_iter(self) -> BroadcastIter<T> {
+        BroadcastIter { recv: self }
+    }
+}
+
+pub struct BroadcastSCIter<T: Clone + Sync> {
+    recv: BroadcastUniReceiver<T>,
+}
+
+impl<T: Clone + Sync> Iterator for BroadcastSCIter<T> {
+    type Item = T;
+
+    #[inline(always)]
+    fn next(&mut self) -> Option<T> {
+        self.recv.recv().ok()
+    }
+}
+
+impl BroadcastSender<Vec<u8>> {
+    /// Turns this sender into an ```io::Write``` which cuts what's written
+    /// into frames of frame_size bytes, see ```QueueWriter```
+    pub fn into_writer(self, frame_size: usize) -> QueueWriter {
+        QueueWriter::new(self.sender, frame_size)
+    }
+}
+
+impl BroadcastUniReceiver<Vec<u8>> {
+    /// Turns this receiver into an ```io::Read``` and ```io::BufRead``` over the
+    /// bytes of every frame on the stream, see ```QueueReader```
+    pub fn into_reader(self) -> QueueReader {
+        QueueReader::new(self.receiver)
+    }
+}
+
+impl<T: Clone + Send + Sync + 'static> BroadcastSender<T> {
+    /// Turns this sender into a ```Sink``` which sends from a thread
+    /// of its own, see ```BlockingSinkAdapter```. hand_off is the capacity
+    /// of the futures queue values are handed to the thread through
+    pub fn into_sink_adapter(self, hand_off: usize) -> BlockingSinkAdapter<T> {
+        BlockingSinkAdapter::new(self.sender, hand_off)
+    }
+}
+
+impl<T: Clone + Send + Sync + 'static> BroadcastReceiver<T> {
+    /// Turns this receiver into a ```Stream``` which receives on a thread
+    /// of its own, see ```BlockingStreamAdapter```. hand_off is the capacity
+    /// of the futures queue the thread hands values over through
+    pub fn into_stream_adapter(self, hand_off: usize) -> BlockingStreamAdapter<T> {
+        BlockingStreamAdapter::new(self.receiver, hand_off)
+    }
+}
+
+impl<T: Clone + Send + Sync + 'static> BroadcastUniReceiver<T> {
+    /// Identical to ```BroadcastReceiver::into_stream_adapter```
+    pub fn into_stream_adapter(self, hand_off: usize) -> BlockingStreamAdapter<T> {
+        BlockingStreamAdapter::new(self.receiver, hand_off)
+    }
+}
+
+impl<T: Clone + Sync> IntoIterator for BroadcastUniReceiver<T> {
+    type Item = T;
+
+    type IntoIter = BroadcastSCIter<T>;
+
+    fn into_iter(self) -> BroadcastSCIter<T> {
+        BroadcastSCIter { recv: self }
+    }
+}
+
+pub struct BroadcastRefIter<'a, T: Clone + 'a> {
+    recv: &'a BroadcastReceiver<T>,
+}
+
+impl<'a, T: Clone + 'a> Iterator for BroadcastRefIter<'a, T> {
+    type Item = T;
+
+    #[inline(always)]
+    fn next(&mut self) -> Option<T> {
+        self.recv.try_recv().ok()
+    }
+}
+
+impl<'a, T: Clone + 'a> IntoIterator for &'a BroadcastReceiver<T> {
+    type Item = T;
+
+    type IntoIter = BroadcastRefIter<'a, T>;
+
+    fn into_iter(self) -> BroadcastRefIter<'a, T> {
+        BroadcastRefIter { recv: self }
+    }
+}
+
+pub struct BroadcastBlockingRefIter<'a, T: Clone + 'a> {
+    recv: &'a BroadcastReceiver<T>,
+}
+
+impl<'a, T: Clone + 'a> Iterator for BroadcastBlockingRefIter<'a, T> {
+    type Item = T;
+
+    #[inline(always)]
+    fn next(&mut self) -> Option<T> {
+        self.recv.recv().ok()
+    }
+}
+
+pub struct BroadcastTimeoutIter<'a, T: Clone + 'a> {
+    recv: &'a BroadcastReceiver<T>,
+    // Set for iter_until, where the deadline is fixed
+    deadline: Option<Instant>,
+    // Set for iter_timeout, where each wait gets a deadline of its own
+    idle: Option<Duration>,
+}
+
+impl<'a, T: Clone + 'a> Iterator for BroadcastTimeoutIter<'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        let deadline = match self.idle {
+            Some(idle) => self.recv.receiver.now().checked_add(idle),
+            None => match self.deadline {
+                // Values left at the deadline stay queued, even if they keep coming
+                Some(deadline) if self.recv.receiver.now() >= deadline => return None,
+                deadline => deadline,
+            },
+        };
+        match deadline {
+            Some(deadline) => self.recv.receiver.recv_until(deadline).ok(),
+            // Too far off to represent, so it never comes
+            None => self.recv.recv().ok(),
+        }
+    }
+}
+
+pub struct BroadcastSCRefIter<'a, T: Clone + Sync + 'a> {
+    recv: &'a BroadcastUniReceiver<T>,
+}
+
+impl<'a, T: Clone + Sync + 'a> Iterator for BroadcastSCRefIter<'a, T> {
+    type Item = T;
+
+    #[inline(always)]
+    fn next(&mut self) -> Option<T> {
+        self.recv.try_recv().ok()
+    }
+}
+
+impl<'a, T: Clone + Sync + 'a> IntoIterator for &'a BroadcastUniReceiver<T> {
+    type Item = T;
+
+    type IntoIter = BroadcastSCRefIter<'a, T>;
+
+    fn into_iter(self) -> BroadcastSCRefIter<'a, T> {
+        BroadcastSCRefIter { recv: self }
+    }
+}
+
+pub struct BroadcastUniIter<R, F: FnMut(&T) -> R, T: Clone + Sync> {
+    recv: BroadcastUniReceiver<T>,
+    op: F,
+}
+
+impl<R, F: FnMut(&T) -> R, T: Clone + Sync> Iterator for BroadcastUniIter<R, F, T> {
+    type Item = R;
+
+    #[inline(always)]
+    fn next(&mut self) -> Option<R> {
+        let opref = &mut self.op;
+        self.recv.recv_view(|v| opref(v)).ok()
+    }
+}
+
+pub struct BroadcastUniRefIter<'a, R, F: FnMut(&T) -> R, T: Clone + Sync + 'a> {
+    recv: &'a BroadcastUniReceiver<T>,
+    op: F,
+}
+
+impl<'a, R, F: FnMut(&T) -> R, T: Clone + Sync + 'a> Iterator for BroadcastUniRefIter<'a, R, F, T> {
+    type Item = R;
+
+    #[inline(always)]
+    fn next(&mut self) -> Option<R> {
+        let opref = &mut self.op;
+        self.recv.try_recv_view(|v| opref(v)).ok()
+    }
+}
+
+/// Creates a (```BroadcastSender```, ```BroadcastReceiver```) pair with a capacity that's
+/// the next power of two >= the given capacity
+///
+/// # Panics
+///
+/// Panics if the capacity is too large for the queue to tell full from empty
+///
+/// # Example
+/// ```
+/// use multiqueue2::broadcast_queue;
+/// let (w, r) = broadcast_queue(10);
+/// w.try_send(10).unwrap();
+/// assert_eq!(10, r.try_recv().unwrap());
+/// ```
+pub fn broadcast_queue<T: Clone>(capacity: usize) -> (BroadcastSender<T>, BroadcastReceiver<T>) {
+    let (send, recv) = MultiQueue::<BCast<T>, T>::create_tx_rx(capacity);
+    (
+        BroadcastSender { sender: send },
+        BroadcastReceiver { receiver: recv },
+    )
+}
+
+/// Creates a (```BroadcastSender```, ```BroadcastReceiver```) pair with a capacity that's
+/// the next power of two >= the given capacity and the specified wait strategy
+///
+/// # Example
+/// ```
+/// use multiqueue2::broadcast_queue_with;
+/// use multiqueue2::wait::BusyWait;
+/// let (w, r) = broadcast_queue_with(10, BusyWait::new());
+/// w.try_send(10).unwrap();
+/// assert_eq!(10, r.try_recv().unwrap());
+/// ```
+pub fn broadcast_queue_with<T: Clone, W: Wait + 'static>(
+    capacity: usize,
+    wait: W,
+) -> (BroadcastSender<T>, BroadcastReceiver<T>) {
+    let (send, recv) = MultiQueue::<BCast<T>, T>::create_tx_rx_with(capacity, wait);
+    (
+        BroadcastSender { sender: send },
+        BroadcastReceiver { receiver: recv },
+    )
+}
+
+/// Creates a (```BroadcastSender```, ```BroadcastReceiver```) pair with a capacity that's
+/// the next power of two >= the given capacity, holding the values from a
+/// ```dump``` of another queue in the order they were dumped in. Sequence numbers
+/// start again from zero. This panics if the dump doesn't fit in the queue.
+///
+/// # Example
+/// ```
+/// use multiqueue2::{broadcast_queue, broadcast_queue_restored};
+/// let (w, _r) = broadcast_queue(4);
+/// w.try_send(10).unwrap();
+/// let (_w, r) = broadcast_queue_restored(4, w.dump());
+/// assert_eq!((0, 10), r.try_recv_indexed().unwrap());
+/// ```
+pub fn broadcast_queue_restored<T: Clone>(
+    capacity: usize,
+    dump: Vec<(u64, T)>,
+) -> (BroadcastSender<T>, BroadcastReceiver<T>) {
+    let (send, recv) = MultiQueue::<BCast<T>, T>::create_tx_rx(capacity);
+    let vals = dump.into_iter().map(|(_, val)| val).collect();
+    if send.try_send_all(vals).is_err() {
+        panic!("Multiqueue error - dump larger than capacity");
+    }
+    (
+        BroadcastSender { sender: send },
+        BroadcastReceiver { receiver: recv },
+    )
+}
+
+/// Creates a (```BroadcastSender```, ```BroadcastReceiver```) pair which reports
+/// sends, receives and the like to the passed ```QueueMetrics```
+///
+/// # Example
+/// ```
+/// use multiqueue2::{broadcast_queue_with_metrics, QueueMetrics};
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// use std::sync::Arc;
+///
+/// #[derive(Default)]
+/// struct Received(AtomicUsize);
+///
+/// impl QueueMetrics for Received {
+///     fn on_recv(&self) {
+///         self.0.fetch_add(1, Ordering::Relaxed);
+///     }
+/// }
+///
+/// let received = Arc::new(Received::default());
+/// let (w, r) = broadcast_queue_with_metrics(10, received.clone());
+/// let r2 = r.add_stream();
+/// w.try_send(10).unwrap();
+/// assert_eq!(10, r.try_recv().unwrap());
+/// assert_eq!(10, r2.try_recv().unwrap());
+/// assert_eq!(2, received.0.load(Ordering::Relaxed));
+/// ```
+#[cfg(feature = "metrics")]
+pub fn broadcast_queue_with_metrics<T: Clone, M: QueueMetrics + 'static>(
+    capacity: usize,
+    metrics: M,
+) -> (BroadcastSender<T>, BroadcastReceiver<T>) {
+    let (send, recv) = MultiQueue::<BCast<T>, T>::create_tx_rx_with_metrics(
+        capacity,
+        DefaultWait::new(),
+        Arc::new(metrics),
+    );
+    (
+        BroadcastSender { sender: send },
+        BroadcastReceiver { receiver: recv },
+    )
+}
+
+/// Creates a (```BroadcastSender```, ```BroadcastReceiver```) pair which frees the
+/// memory replaced as streams come and go through the passed ```Reclaim``` backend
+///
+/// # Example
+/// ```
+/// use multiqueue2::broadcast_queue_with_reclaim;
+/// use multiqueue2::memory::LeakReclaim;
+/// let (w, r) = broadcast_queue_with_reclaim(10, LeakReclaim::new());
+/// let r2 = r.add_stream();
+/// w.try_send(10).unwrap();
+/// assert_eq!(10, r.try_recv().unwrap());
+/// assert_eq!(10, r2.try_recv().unwrap());
+/// ```
+pub fn broadcast_queue_with_reclaim<T: Clone, R: Reclaim + 'static>(
+    capacity: usize,
+    reclaim: R,
+) -> (BroadcastSender<T>, BroadcastReceiver<T>) {
+    let (send, recv) = MultiQueue::<BCast<T>, T>::create_tx_rx_with_reclaim(
+        capacity,
+        DefaultWait::new(),
+        Arc::new(reclaim),
+    );
+    (
+        BroadcastSender { sender: send },
+        BroadcastReceiver { receiver: recv },
+    )
+}
+
+/// Creates a (```BroadcastSender```, ```BroadcastReceiver```) pair which holds up to
+/// STREAMS streams inline in the queue itself, so writers don't have to chase
+/// a pointer for each one when checking how far behind they are. A removed
+/// stream's room is used again once every sender and receiver has moved on from it,
+/// which happens as they go on sending and receiving. Adding a stream while all
+/// STREAMS are taken panics, and ```BroadcastReceiver::try_add_stream``` returns an
+/// error instead.
+///
+/// # Example
+/// ```
+/// use multiqueue2::broadcast_queue_fixed;
+/// let (w, r) = broadcast_queue_fixed::<usize, 2>(10);
+/// let r2 = r.add_stream();
+/// w.try_send(10).unwrap();
+/// assert_eq!(10, r.try_recv().unwrap());
+/// assert_eq!(10, r2.try_recv().unwrap());
+/// ```
+pub fn broadcast_queue_fixed<T: Clone, const STREAMS: usize>(
+    capacity: usize,
+) -> (BroadcastSender<T>, BroadcastReceiver<T>) {
+    let (send, recv) = MultiQueue::<BCast<T>, T>::create_tx_rx_fixed::<STREAMS>(capacity);
+    (
+        BroadcastSender { sender: send },
+        BroadcastReceiver { receiver: recv },
+    )
+}
+
+/// Creates a (```BroadcastSender```, ```BroadcastReceiver```) pair where the policy
+/// decides what happens to the values still in the queue when the last sender drops.
+/// Panics if the policy is ```DropPolicy::Discard```, since every stream
+/// receives the values separately and one may have received what another hasn't.
+///
+/// # Example
+/// ```
+/// use multiqueue2::{broadcast_queue_with_drop_policy, DropPolicy};
+/// use std::thread;
+/// use std::time::Duration;
+///
+/// let (w, r) = broadcast_queue_with_drop_policy(4, DropPolicy::Wait(Duration::from_secs(10)));
+/// let reader = thread::spawn(move || r.into_iter().collect::<Vec<_>>());
+/// w.try_send(1).unwrap();
+/// w.try_send(2).unwrap();
+/// // This blocks until the reader has received both
+/// drop(w);
+/// assert_eq!(vec![1, 2], reader.join().unwrap());
+/// ```
+pub fn broadcast_queue_with_drop_policy<T: Clone>(
+    capacity: usize,
+    drop_policy: DropPolicy<T>,
+) -> (BroadcastSender<T>, BroadcastReceiver<T>) {
+    assert!(
+        !matches!(drop_policy, DropPolicy::Discard(_)),
+        "Multiqueue error - broadcast queues can't discard the values left by the last sender"
+    );
+    let (send, recv) =
+        MultiQueue::<BCast<T>, T>::create_tx_rx_with_drop_policy(capacity, drop_policy);
+    (
+        BroadcastSender { sender: send },
+        BroadcastReceiver { receiver: recv },
+    )
+}
+
+/// Creates a (```BroadcastSender```, ```BroadcastReceiver```) pair where senders can
+/// make room with ```try_send_or_replace``` by taking the oldest value off the queue.
+///
+/// # Example
+/// ```
+/// use multiqueue2::broadcast_queue_replacing;
+/// let (w, r) = broadcast_queue_replacing(1);
+/// w.try_send_or_replace(10).unwrap();
+/// assert_eq!(Some(10), w.try_send_or_replace(11).unwrap());
+/// assert_eq!(11, r.try_recv().unwrap());
+/// ```
+pub fn broadcast_queue_replacing<T: Clone>(
+    capacity: usize,
+) -> (BroadcastSender<T>, BroadcastReceiver<T>) {
+    let (send, recv) = MultiQueue::<BCast<T>, T>::create_tx_rx_replacing(capacity);
+    (
+        BroadcastSender { sender: send },
+        BroadcastReceiver { receiver: recv },
+    )
+}
+
+/// Creates a (```BroadcastSender```, ```BroadcastReceiver```) pair where values can be
+/// sent with ```try_send_after``` so that receivers can't see them until a deadline.
+///
+/// # Example
+/// ```
+/// use multiqueue2::broadcast_queue_delayed;
+/// use std::time::Instant;
+/// let (w, r) = broadcast_queue_delayed(10);
+/// w.try_send(10).unwrap();
+/// w.try_send_after(11, Instant::now()).unwrap();
+/// assert_eq!(10, r.try_recv().unwrap());
+/// assert_eq!(11, r.try_recv().unwrap());
+/// ```
+pub fn broadcast_queue_delayed<T: Clone>(
+    capacity: usize,
+) -> (BroadcastSender<T>, BroadcastReceiver<T>) {
+    let (send, recv) = MultiQueue::<BCast<T>, T>::create_tx_rx_delayed(capacity);
+    (
+        BroadcastSender { sender: send },
+        BroadcastReceiver { receiver: recv },
+    )
+}
+
+/// Creates a (```BroadcastSender```, ```BroadcastReceiver```) pair where values can be
+/// sent with ```try_send_with_ttl``` so that receivers drop them once they're too old.
+///
+/// # Example
+/// ```
+/// use multiqueue2::broadcast_queue_expiring;
+/// use std::time::Duration;
+/// let (w, r) = broadcast_queue_expiring(10);
+/// w.try_send(10).unwrap();
+/// w.try_send_with_ttl(11, Duration::from_secs(60)).unwrap();
+/// assert_eq!(10, r.try_recv().unwrap());
+/// assert_eq!(11, r.try_recv().unwrap());
+/// ```
+pub fn broadcast_queue_expiring<T: Clone>(
+    capacity: usize,
+) -> (BroadcastSender<T>, BroadcastReceiver<T>) {
+    let (send, recv) = MultiQueue::<BCast<T>, T>::create_tx_rx_expiring(capacity);
+    (
+        BroadcastSender { sender: send },
+        BroadcastReceiver { receiver: recv },
+    )
+}
+
+/// Creates a (```BroadcastSender```, ```BroadcastReceiver```) pair which records when
+/// each value was sent, so receivers can find out how long it waited with
+/// ```try_recv_with_latency``` and ```recv_with_latency```.
+///
+/// # Example
+/// ```
+/// use multiqueue2::broadcast_queue_timestamped;
+/// let (w, r) = broadcast_queue_timestamped(10);
+/// w.try_send(10).unwrap();
+/// let (val, _latency) = r.recv_with_latency().unwrap();
+/// assert_eq!(10, val);
+/// ```
+pub fn broadcast_queue_timestamped<T: Clone>(
+    capacity: usize,
+) -> (BroadcastSender<T>, BroadcastReceiver<T>) {
+    let (send, recv) = MultiQueue::<BCast<T>, T>::create_tx_rx_timestamped(capacity);
+    (
+        BroadcastSender { sender: send },
+        BroadcastReceiver { receiver: recv },
+    )
+}
+
+/// Creates a (```BroadcastSender```, ```BroadcastReceiver```) pair which reads the time
+/// from the passed ```Clock``` instead of ```Instant::now```, so that timed code can be
+/// tested without sleeping. Values can be sent with both ```try_send_after``` and
+/// ```try_send_with_ttl```, and receivers can ask for latency.
+///
+/// # Example
+/// ```
+/// use multiqueue2::{broadcast_queue_with_clock, MockClock};
+/// use std::sync::Arc;
+/// use std::time::Duration;
+/// let clock = Arc::new(MockClock::new());
+/// let (w, r) = broadcast_queue_with_clock(10, clock.clone());
+/// w.try_send(10).unwrap();
+/// clock.advance(Duration::from_millis(5));
+/// assert_eq!((10, Duration::from_millis(5)), r.try_recv_with_latency().unwrap());
+/// ```
+pub fn broadcast_queue_with_clock<T: Clone, C: Clock + 'static>(
+    capacity: usize,
+    clock: C,
+) -> (BroadcastSender<T>, BroadcastReceiver<T>) {
+    let (send, recv) =
+        MultiQueue::<BCast<T>, T>::create_tx_rx_with_clock(capacity, Arc::new(clock));
+    (
+        BroadcastSender { sender: send },
+        BroadcastReceiver { receiver: recv },
+    )
+}
+
+/// Creates a (```BroadcastSender```, ```BroadcastReceiver```) pair where values are
+/// conflated by the key the passed function returns. When a value is sent while an older
+/// one with the same key hasn't been read yet, every stream skips the older one and only
+/// sees the newer one, in the spot it was sent at. The older value still takes up space
+/// in the queue until the streams move past it.
+///
+/// # Example
+/// ```
+/// use multiqueue2::broadcast_queue_conflated;
+/// let (w, r) = broadcast_queue_conflated(10, |quote: &(&str, u32)| quote.0);
+/// w.try_send(("AAPL", 100)).unwrap();
+/// w.try_send(("MSFT", 200)).unwrap();
+/// w.try_send(("AAPL", 101)).unwrap();
+/// assert_eq!(("MSFT", 200), r.try_recv().unwrap());
+/// assert_eq!(("AAPL", 101), r.try_recv().unwrap());
+/// assert!(r.try_recv().is_err());
+/// ```
+pub fn broadcast_queue_conflated<T, K, F>(
+    capacity: usize,
+    key: F,
+) -> (BroadcastSender<T>, BroadcastReceiver<T>)
+where
+    T: Clone,
+    K: Hash + Eq + Send + 'static,
+    F: Fn(&T) -> K + Send + Sync + 'static,
+{
+    let (send, recv) = MultiQueue::<BCast<T>, T>::create_tx_rx_conflated(
+        capacity,
+        KeyConflator::new(capacity, key),
+    );
+    (
+        BroadcastSender { sender: send },
+        BroadcastReceiver { receiver: recv },
+    )
+}
+
+/// Creates a (```BroadcastSender```, ```BroadcastReceiver```) pair which drops a sent
+/// value if one with the same id was among the last window values sent, so writers
+/// can retry sends without the streams seeing a value twice. A send which is dropped
+/// as a duplicate still succeeds. Only values which made it into the queue count
+/// towards the window, and sending all at once isn't supported.
+///
+/// # Panics
+///
+/// Panics if the window is zero.
+///
+/// # Example
+/// ```
+/// use multiqueue2::broadcast_queue_deduplicated;
+/// let (w, r) = broadcast_queue_deduplicated(10, 2, |msg: &(u32, &str)| msg.0);
+/// w.try_send((1, "first")).unwrap();
+/// w.try_send((1, "retried")).unwrap();
+/// w.try_send((2, "second")).unwrap();
+/// w.try_send((3, "third")).unwrap();
+/// // The first id has fallen out of the window
+/// w.try_send((1, "again")).unwrap();
+/// assert_eq!((1, "first"), r.try_recv().unwrap());
+/// assert_eq!((2, "second"), r.try_recv().unwrap());
+/// assert_eq!((3, "third"), r.try_recv().unwrap());
+/// assert_eq!((1, "again"), r.try_recv().unwrap());
+/// assert!(r.try_recv().is_err());
+/// ```
+pub fn broadcast_queue_deduplicated<T, K, F>(
+    capacity: usize,
+    window: usize,
+    id: F,
+) -> (BroadcastSender<T>, BroadcastReceiver<T>)
+where
+    T: Clone,
+    K: Hash + Eq + Clone + Send + 'static,
+    F: Fn(&T) -> K + Send + Sync + 'static,
+{
+    let (send, recv) =
+        MultiQueue::<BCast<T>, T>::create_tx_rx_deduplicated(capacity, IdWindow::new(window, id));
+    (
+        BroadcastSender { sender: send },
+        BroadcastReceiver { receiver: recv },
+    )
+}
+
+/// Futures variant of broadcast_queue - datastructures implement
+/// Sink + Stream at a minor (~30 ns) performance cost to BlockingWait
+pub fn broadcast_fut_queue<T: Clone>(
+    capacity: usize,
+) -> (BroadcastFutSender<T>, BroadcastFutReceiver<T>) {
+    let (isend, irecv) = futures_multiqueue::<BCast<T>, T>(capacity);
+    (
+        BroadcastFutSender { sender: isend },
+        BroadcastFutReceiver { receiver: irecv },
+    )
+}
+
+pub fn broadcast_fut_queue_with<T: Clone>(
+    capacity: usize,
+    try_spins: usize,
+    yield_spins: usize,
+) -> (BroadcastFutSender<T>, BroadcastFutReceiver<T>) {
+    let (send, recv) = futures_multiqueue_with::<BCast<T>, T>(capacity, try_spins, yield_spins);
+    (
+        BroadcastFutSender { sender: send },
+        BroadcastFutReceiver { receiver: recv },
+    )
+}
+
+/// Identical to ```broadcast_queue```, taking the capacity as a u64 like older versions did
+#[deprecated(
+    since = "0.1.8",
+    note = "capacities are usize now, use broadcast_queue"
+)]
+pub fn broadcast_queue_u64<T: Clone>(capacity: u64) -> (BroadcastSender<T>, BroadcastReceiver<T>) {
+    broadcast_queue(capacity_from_u64(capacity))
+}
+
+/// Identical to ```broadcast_queue_with```, taking the capacity as a u64 like older versions did
+#[deprecated(
+    since = "0.1.8",
+    note = "capacities are usize now, use broadcast_queue_with"
+)]
+pub fn broadcast_queue_with_u64<T: Clone, W: Wait + 'static>(
+    capacity: u64,
+    wait: W,
+) -> (BroadcastSender<T>, BroadcastReceiver<T>) {
+    broadcast_queue_with(capacity_from_u64(capacity), wait)
+}
+
+/// Identical to ```broadcast_fut_queue```, taking the capacity as a u64 like older versions did
+#[deprecated(
+    since = "0.1.8",
+    note = "capacities are usize now, use broadcast_fut_queue"
+)]
+pub fn broadcast_fut_queue_u64<T: Clone>(
+    capacity: u64,
+) -> (BroadcastFutSender<T>, BroadcastFutReceiver<T>) {
+    broadcast_fut_queue(capacity_from_u64(capacity))
+}
+
+/// Identical to ```broadcast_fut_queue_with```, taking the capacity as a u64 like older versions did
+#[deprecated(
+    since = "0.1.8",
+    note = "capacities are usize now, use broadcast_fut_queue_with"
+)]
+pub fn broadcast_fut_queue_with_u64<T: Clone>(
+    capacity: u64,
+    try_spins: usize,
+    yield_spins: usize,
+) -> (BroadcastFutSender<T>, BroadcastFutReceiver<T>) {
+    broadcast_fut_queue_with(capacity_from_u64(capacity), try_spins, yield_spins)
+}
+
+unsafe impl<T: Send + Sync + Clone> Send for BroadcastSender<T> {}
+unsafe impl<T: Send + Sync + Clone> Send for BroadcastReceiver<T> {}
+unsafe impl<T: Send + Sync + Clone> Send for BroadcastUniReceiver<T> {}
+unsafe impl<T: Send + Sync + Clone> Send for BroadcastBoundedReceiver<T> {}
+unsafe impl<T: Send + Sync + Clone> Send for BroadcastPausedReceiver<T> {}
+
+#[cfg(test)]
+// Some of the original tests predate these lints
+#[allow(
+    clippy::assertions_on_constants,
+    clippy::same_item_push,
+    clippy::unnecessary_cast
+)]
+mod test {
+
+    use super::{
+        broadcast_queue, broadcast_queue_conflated, broadcast_queue_deduplicated,
+        broadcast_queue_delayed, broadcast_queue_expiring, broadcast_queue_fixed,
+        broadcast_queue_replacing, broadcast_queue_restored, broadcast_queue_timestamped,
+        broadcast_queue_with_clock, broadcast_queue_with_drop_policy, broadcast_queue_with_reclaim,
+        BroadcastReceiver,
+    };
+    use crate::clock::{Clock, MockClock};
+    use crate::error::{
+        AttachError, FrozenTrySendError, LaggedRecvError, LaggedTryRecvError, StreamsFullError,
+    };
+    use crate::memory::{CrossbeamReclaim, EpochReclaim, LeakReclaim, Reclaim};
+    use crate::multiqueue::DropPolicy;
+
+    extern crate crossbeam;
+    use self::crossbeam::scope;
+
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::mpsc::{RecvError, TryRecvError};
+    use std::sync::{Arc, Barrier};
+    use std::thread::{self, sleep, yield_now};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn build_queue() {
+        let _ = broadcast_queue::<usize>(10);
+    }
+
+    #[test]
+    fn push_pop_test() {
+        let (writer, reader) = broadcast_queue(1);
+        for _ in 0..100 {
+            assert!(reader.try_recv().is_err());
+            writer.try_send(1 as usize).expect("Push should succeed");
+            assert!(writer.try_send(1).is_err());
+            assert_eq!(1, reader.try_recv().unwrap());
+        }
+    }
+
+    fn mpsc_broadcast(senders: usize, receivers: usize) {
+        let (writer, reader) = broadcast_queue(4);
+        let myb = Barrier::new(receivers + senders);
+        let bref = &myb;
+        let num_loop = 100000;
+        scope(|scope| {
+            for q in 0..senders {
+                let cur_writer = writer.clone();
+                scope.spawn(move |_| {
+                    bref.wait();
+                    'outer: for i in 0..num_loop {
+                        for _ in 0..100000000 {
+                            if cur_writer.try_send((q, i)).is_ok() {
+                                continue 'outer;
+                            }
+                            yield_now();
+                        }
+                        assert!(false, "Writer could not write");
+                    }
+                });
+            }
+            writer.unsubscribe();
+            for _ in 0..receivers {
+                let this_reader = reader.add_stream().into_single().unwrap();
+                scope.spawn(move |_| {
+                    let mut myv = Vec::new();
+                    for _ in 0..senders {
+                        myv.push(0);
+                    }
+                    bref.wait();
+                    for _ in 0..num_loop * senders {
+                        loop {
+                            if let Ok(val) = this_reader.try_recv_view(|x| *x) {
+                                assert_eq!(myv[val.0], val.1);
+                                myv[val.0] += 1;
+                                break;
+                            }
+                            yield_now();
+                        }
+                    }
+                    for val in myv {
+                        if val != num_loop {
+                            panic!("Wrong number of values obtained for this");
+                        }
+                    }
+                    assert!(this_reader.try_recv().is_err());
+                });
+            }
+            reader.unsubscribe();
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_spsc_this() {
+        mpsc_broadcast(1, 1);
+    }
+
+    #[test]
+    fn test_spsc_broadcast() {
+        mpsc_broadcast(1, 3);
+    }
+
+    #[test]
+    fn test_mpsc_single() {
+        mpsc_broadcast(2, 1);
+    }
+
+    #[test]
+    fn test_mpsc_broadcast() {
+        mpsc_broadcast(2, 3);
+    }
+
+    #[test]
+    fn test_remove_reader() {
+        let (writer, reader) = broadcast_queue(1);
+        assert!(writer.try_send(1).is_ok());
+        let reader_2 = reader.add_stream();
+        assert!(writer.try_send(1).is_err());
+        assert_eq!(1, reader.try_recv().unwrap());
+        assert!(reader.try_recv().is_err());
+        assert_eq!(1, reader_2.try_recv().unwrap());
+        assert!(reader_2.try_recv().is_err());
+        assert!(writer.try_send(1).is_ok());
+        assert!(writer.try_send(1).is_err());
+        assert_eq!(1, reader.try_recv().unwrap());
+        assert!(reader.try_recv().is_err());
+        reader_2.unsubscribe();
+        assert!(writer.try_send(2).is_ok());
+        assert_eq!(2, reader.try_recv().unwrap());
+    }
+
+    fn mpmc_broadcast(senders: usize, receivers: usize, nclone: usize) {
+        let (writer, reader) = broadcast_queue(10);
+        let myb = Barrier::new((receivers * nclone) + senders);
+        let bref = &myb;
+        let num_loop = 1000000;
+        let counter = AtomicUsize::new(0);
+        let _do_panic = AtomicUsize::new(0);
+        let do_panic = &_do_panic;
+        let cref = &counter;
+        scope(|scope| {
+            for _ in 0..senders {
+                let cur_writer = writer.clone();
+                scope.spawn(move |_| {
+                    bref.wait();
+                    'outer: for i in 0..num_loop {
+                        for _ in 0..100000000 {
+                            if do_panic.load(Ordering::Relaxed) > 0 {
+                                panic!("Somebody left");
+                            }
+                            if cur_writer.try_send(i).is_ok() {
+                                continue 'outer;
+                            }
+                            yield_now();
+                        }
+                        assert!(false, "Writer could not write");
+                    }
+                });
+            }
+            writer.unsubscribe();
+            for _ in 0..receivers {
+                let _this_reader = reader.add_stream();
+                for _ in 0..nclone {
+                    let this_reader = _this_reader.clone();
+                    scope.spawn(move |_| {
+                        let mut cur = 0;
+                        bref.wait();
+                        loop {
+                            match this_reader.try_recv() {
+                                Ok(val) => {
+                                    if (senders == 1) && (val != 0) && (val <= cur) {
+                                        do_panic.fetch_add(1, Ordering::Relaxed);
+                                        panic!(
+                                            "Non-increasing values read {} last, val was {}",
+                                            cur, val
+                                        );
+                                    }
+                                    cur = val;
+                                    cref.fetch_add(1, Ordering::Relaxed);
+                                }
+                                Err(TryRecvError::Disconnected) => break,
+                                _ => yield_now(),
+                            }
+                        }
+                    });
+                }
+            }
+            reader.unsubscribe();
+        })
+        .unwrap();
+        assert_eq!(
+            senders * receivers * num_loop,
+            counter.load(Ordering::SeqCst)
+        );
+    }
+
+    #[test]
+    fn test_spmc() {
+        mpmc_broadcast(1, 1, 2);
+    }
+
+    #[test]
+    fn test_spmc_broadcast() {
+        mpmc_broadcast(1, 2, 2);
+    }
+
+    #[test]
+    fn test_mpmc() {
+        mpmc_broadcast(2, 1, 2);
+    }
+
+    #[test]
+    fn test_mpmc_broadcast() {
+        mpmc_broadcast(2, 2, 2);
+    }
+
+    #[test]
+    fn test_baddrop() {
+        // This ensures that a bogus arc isn't dropped from the queue
+        let (writer, reader) = broadcast_queue(1);
+        for _ in 0..10 {
+            writer.try_send(Arc::new(10)).unwrap();
+            reader.recv().unwrap();
+        }
+    }
+
+    struct Dropper<'a> {
+        aref: &'a AtomicUsize,
+    }
+
+    impl<'a> Dropper<'a> {
+        pub fn new(a: &AtomicUsize) -> Dropper<'_> {
+            a.fetch_add(1, Ordering::Relaxed);
+            Dropper { aref: a }
+        }
+    }
+
+    impl<'a> Drop for Dropper<'a> {
+        fn drop(&mut self) {
+            self.aref.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    impl<'a> Clone for Dropper<'a> {
+        fn clone(&self) -> Dropper<'a> {
+            self.aref.fetch_add(1, Ordering::Relaxed);
+            Dropper { aref: self.aref }
+        }
+    }
+
+    #[test]
+    fn test_gooddrop() {
+        // This counts the # of drops and creations
+        let count = AtomicUsize::new(0);
+        {
+            let (writer, reader) = broadcast_queue(1);
+            for _ in 0..10 {
+                writer.try_send(Dropper::new(&count)).unwrap();
+                reader.recv().unwrap();
+            }
+        }
+        assert_eq!(count.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_clear() {
+        let count = AtomicUsize::new(0);
+        {
+            let (writer, reader) = broadcast_queue(4);
+            let other = reader.add_stream();
+            for _ in 0..3 {
+                writer.try_send(Dropper::new(&count)).unwrap();
+            }
+            assert_eq!(3, reader.clear());
+            assert!(reader.try_recv().is_err());
+            // Nothing was cloned, and the other stream still has everything
+            assert_eq!(3, count.load(Ordering::Relaxed));
+            other.recv().unwrap();
+            assert_eq!(2, other.clear());
+            assert_eq!(0, other.clear());
+            // Neither stream holds the writer up anymore
+            for _ in 0..4 {
+                writer.try_send(Dropper::new(&count)).unwrap();
+            }
+            assert_eq!(4, count.load(Ordering::Relaxed));
+        }
+        assert_eq!(count.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_read_exact() {
+        let (writer, reader) = broadcast_queue(4);
+        let other = reader.add_stream();
+        let mut buf = [0; 8];
+        for i in 0..3_u32 {
+            writer.try_send(i).unwrap();
+        }
+        assert_eq!(Ok(3), reader.read_exact(&mut buf));
+        assert_eq!(Ok(3), other.read_exact(&mut buf));
+        // These wrap around the end of the ring
+        for i in 3..7 {
+            writer.try_send(i).unwrap();
+        }
+        assert_eq!(Ok(4), reader.read_exact(&mut buf));
+        assert_eq!([3, 4, 5, 6], buf[..4]);
+        // A shared stream takes them one at a time, but ends up the same
+        let shared = other.clone();
+        assert_eq!(Ok(2), shared.read_exact(&mut buf[..2]));
+        assert_eq!([3, 4], buf[..2]);
+        assert_eq!(Ok(2), other.read_exact(&mut buf));
+        assert_eq!([5, 6], buf[..2]);
+        assert_eq!(Err(TryRecvError::Empty), reader.read_exact(&mut buf));
+        drop(writer);
+        assert_eq!(Err(TryRecvError::Disconnected), other.read_exact(&mut buf));
+    }
+
+    #[test]
+    fn test_write_from() {
+        let (writer, reader) = broadcast_queue(4);
+        let other = reader.add_stream();
+        assert_eq!(4, writer.write_from(&[1, 2, 3, 4, 5]));
+        let mut buf = [0; 4];
+        assert_eq!(Ok(4), reader.read_exact(&mut buf));
+        // The slowest stream holds the writer up
+        assert_eq!(0, writer.write_from(&[5]));
+        assert_eq!(1, other.try_recv().unwrap());
+        assert_eq!(1, writer.write_from(&[5, 6]));
+        assert_eq!(Ok(4), other.read_exact(&mut buf));
+        assert_eq!([2, 3, 4, 5], buf);
+        assert_eq!(Ok(1), reader.read_exact(&mut buf));
+        assert_eq!(5, buf[0]);
+    }
+
+    #[test]
+    fn test_write_from_timestamped() {
+        let (writer, reader) = broadcast_queue_timestamped(4);
+        assert_eq!(2, writer.write_from(&[1, 2]));
+        assert_eq!(1, reader.try_recv_with_latency().unwrap().0);
+        assert_eq!(2, reader.try_recv_with_latency().unwrap().0);
+    }
+
+    #[test]
+    fn test_recv_guard() {
+        let (writer, reader) = broadcast_queue(1);
+        let other = reader.add_stream();
+        let mut single = reader.into_single().unwrap();
+        writer.try_send(vec![1]).unwrap();
+        {
+            let guard = single.try_recv_guard().unwrap();
+            assert_eq!(vec![1], *guard);
+            assert_eq!(vec![1], other.try_recv().unwrap());
+            // The guarded stream still holds the writer up
+            assert!(writer.try_send(vec![2]).is_err());
+        }
+        writer.try_send(vec![2]).unwrap();
+        assert_eq!(vec![2], *single.recv_guard().unwrap());
+        assert_eq!(
+            Err(TryRecvError::Empty),
+            single.try_recv_guard().map(|v| v.clone())
+        );
+        drop(writer);
+        assert_eq!(Err(RecvError), single.recv_guard().map(|v| v.clone()));
+    }
+
+    #[test]
+    fn test_into_mpmc_refused() {
+        let (writer, reader) = broadcast_queue::<usize>(4);
+        let (other_writer, _other_reader) = broadcast_queue::<usize>(4);
+        let (other_writer, reader) = reader.into_mpmc(other_writer).unwrap_err();
+        drop(other_writer);
+
+        let reader2 = reader.clone();
+        let (writer, reader) = reader.into_mpmc(writer).unwrap_err();
+        drop(reader2);
+        let writer2 = writer.clone();
+        let (writer, reader) = reader.into_mpmc(writer).unwrap_err();
+        drop(writer2);
+
+        let token = reader.add_stream().detach().ok().unwrap();
+        let (writer, reader) = reader.into_mpmc(writer).unwrap_err();
+        drop(token);
+
+        let (writer, reader) = reader.into_mpmc(writer).ok().unwrap();
+        writer.try_send(1).unwrap();
+        assert_eq!(1, reader.try_recv().unwrap());
+    }
+
+    #[test]
+    fn test_into_mpmc_gooddrop() {
+        let count = AtomicUsize::new(0);
+        {
+            let (writer, reader) = broadcast_queue(4);
+            for _ in 0..4 {
+                writer.try_send(Dropper::new(&count)).unwrap();
+            }
+            reader.recv().unwrap();
+            reader.recv().unwrap();
+            // The two read values are still in the queue until it's converted
+            assert_eq!(4, count.load(Ordering::Relaxed));
+            let (writer, reader) = reader.into_mpmc(writer).ok().unwrap();
+            assert_eq!(2, count.load(Ordering::Relaxed));
+            for _ in 0..10 {
+                writer.try_send(Dropper::new(&count)).unwrap();
+                reader.recv().unwrap();
+            }
+            assert_eq!(2, count.load(Ordering::Relaxed));
+            writer.try_send(Dropper::new(&count)).unwrap();
+        }
+        assert_eq!(0, count.load(Ordering::Relaxed));
+    }
+
+    // Zero sized values can't point at a counter, so they count clones and drops in a static
+    static ZST_LIVE: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Debug)]
+    struct ZstDropper;
+
+    impl ZstDropper {
+        fn new() -> ZstDropper {
+            ZST_LIVE.fetch_add(1, Ordering::Relaxed);
+            ZstDropper
+        }
+    }
+
+    impl Drop for ZstDropper {
+        fn drop(&mut self) {
+            ZST_LIVE.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    impl Clone for ZstDropper {
+        fn clone(&self) -> ZstDropper {
+            ZstDropper::new()
+        }
+    }
+
+    #[test]
+    fn test_zero_sized() {
+        let (writer, reader) = broadcast_queue(4);
+        let stream = reader.add_stream();
+        for _ in 0..4 {
+            writer.try_send(()).unwrap();
+        }
+        assert!(writer.try_send(()).is_err());
+        for _ in 0..4 {
+            assert_eq!(Ok(()), reader.try_recv());
+        }
+        // The other stream still holds the writer up
+        assert!(writer.try_send(()).is_err());
+        assert_eq!(4, stream.lag());
+        stream.unsubscribe();
+
+        let num_loop = 100000;
+        scope(|scope| {
+            for _ in 0..2 {
+                let cur_writer = writer.clone();
+                scope.spawn(move |_| {
+                    for _ in 0..num_loop {
+                        while cur_writer.try_send(()).is_err() {
+                            yield_now();
+                        }
+                    }
+                });
+            }
+            writer.unsubscribe();
+            for _ in 0..2 {
+                let cur_reader = reader.add_stream();
+                scope.spawn(move |_| {
+                    assert_eq!(2 * num_loop, cur_reader.into_iter().count());
+                });
+            }
+            reader.unsubscribe();
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_zero_sized_gooddrop() {
+        {
+            let (writer, reader) = broadcast_queue(4);
+            let stream = reader.add_stream();
+            for _ in 0..10 {
+                writer.try_send(ZstDropper::new()).unwrap();
+                drop(reader.recv().unwrap());
+                drop(stream.recv().unwrap());
+            }
+            writer.try_send(ZstDropper::new()).unwrap();
+            drop(reader.recv().unwrap());
+            stream.unsubscribe();
+            let single = reader.into_single().unwrap();
+            writer.try_send(ZstDropper::new()).unwrap();
+            assert!(single.try_recv_view(|_| ()).is_ok());
+            writer.try_send(ZstDropper::new()).unwrap();
+            // Broadcast values stay in their slot until it gets written over
+            assert_eq!(4, ZST_LIVE.load(Ordering::Relaxed));
+        }
+        assert_eq!(0, ZST_LIVE.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_iterator_comp() {
+        let (writer, reader) = broadcast_queue::<usize>(10);
+        drop(writer);
+        for _ in reader {}
+    }
+
+    #[test]
+    fn test_borrowed_iter() {
+        let (writer, reader) = broadcast_queue::<usize>(4);
+        scope(|scope| {
+            scope.spawn(|_| {
+                for i in 0..1000 {
+                    let mut val = writer.try_send(i);
+                    while let Err(FrozenTrySendError::Full(v)) = val {
+                        yield_now();
+                        val = writer.try_send(v);
+                    }
+                }
+                writer.unsubscribe();
+            });
+            let got: Vec<usize> = reader.iter().collect();
+            assert_eq!((0..1000).collect::<Vec<_>>(), got);
+        })
+        .unwrap();
+        assert_eq!(Err(TryRecvError::Disconnected), reader.try_recv());
+    }
+
+    #[test]
+    fn test_iter_until_clock() {
+        let clock = Arc::new(MockClock::new());
+        let (writer, reader) = broadcast_queue_with_clock(8, clock.clone());
+        writer.try_send(1).unwrap();
+        writer.try_send(2).unwrap();
+        let deadline = clock.now() + Duration::from_secs(1);
+        scope(|scope| {
+            let clock = clock.clone();
+            scope.spawn(move |_| {
+                sleep(Duration::from_millis(10));
+                writer.try_send(3).unwrap();
+                sleep(Duration::from_millis(10));
+                clock.advance(Duration::from_secs(1));
+            });
+            let got: Vec<usize> = reader.iter_until(deadline).collect();
+            assert_eq!(vec![1, 2, 3], got);
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_single_leave_multi() {
+        let (writer, reader) = broadcast_queue::<usize>(10);
+        let reader2 = reader.clone();
+        writer.try_send(1).unwrap();
+        writer.try_send(1).unwrap();
+        assert_eq!(reader2.recv().unwrap(), 1);
+        drop(reader2);
+        let reader_s = reader.into_single().unwrap();
+        assert!(reader_s.recv_view(|x| *x).is_ok());
+    }
+
+    #[test]
+    fn test_lag() {
+        let (writer, reader) = broadcast_queue(2);
+        let reader_2 = reader.add_stream();
+        for _ in 0..10 {
+            assert_eq!(0, reader.lag());
+            writer.try_send(1).unwrap();
+            writer.try_send(2).unwrap();
+            assert_eq!(2, reader.lag());
+            assert_eq!(2, writer.max_lag());
+            reader.try_recv().unwrap();
+            reader.try_recv().unwrap();
+            assert_eq!(0, reader.lag());
+            assert_eq!(2, reader_2.lag());
+            assert_eq!(2, writer.max_lag());
+            reader_2.try_recv().unwrap();
+            reader_2.try_recv().unwrap();
+            assert_eq!(0, writer.max_lag());
+        }
+    }
+
+    #[test]
+    fn test_recv_indexed() {
+        let (writer, reader) = broadcast_queue(2);
+        let reader_2 = reader.add_stream();
+        for i in 0..10 {
+            writer.try_send(i).unwrap();
+            assert_eq!((i, i), reader.try_recv_indexed().unwrap());
+            assert_eq!((i, i), reader_2.recv_indexed().unwrap());
+        }
+        let late = reader.add_stream();
+        writer.try_send(10).unwrap();
+        assert_eq!((10, 10), late.try_recv_indexed().unwrap());
+        drop(writer);
+        assert_eq!((10, 10), reader.try_recv_indexed().unwrap());
+        assert_eq!(Err(TryRecvError::Disconnected), reader.try_recv_indexed());
+    }
+
+    #[test]
+    fn test_bounded_stream() {
+        let (writer, reader) = broadcast_queue(4);
+        let slow = reader.add_bounded_stream(2);
+        assert_eq!(2, slow.max_lag());
+        for i in 0..3 {
+            writer.try_send(i).unwrap();
+            assert_eq!(i, reader.try_recv().unwrap());
+        }
+        // The reader notices on its own that it's too far behind
+        assert_eq!(Err(LaggedTryRecvError::Lagged(1)), slow.try_recv());
+        assert_eq!(Ok(1), slow.try_recv());
+        assert_eq!(Ok(2), slow.try_recv());
+        assert_eq!(Err(LaggedTryRecvError::Empty), slow.try_recv());
+        for i in 3..20 {
+            writer.try_send(i).unwrap();
+            assert_eq!(i, reader.try_recv().unwrap());
+            // Writers only skip the stream when they would otherwise wait on it
+            assert!(slow.lag() <= 4);
+        }
+        assert_eq!(Err(LaggedTryRecvError::Lagged(15)), slow.try_recv());
+        assert_eq!(Ok(18), slow.try_recv());
+        assert_eq!(Ok(19), slow.try_recv());
+        drop(writer);
+        assert_eq!(Err(LaggedTryRecvError::Disconnected), slow.try_recv());
+    }
+
+    #[test]
+    fn test_bounded_stream_capped() {
+        let (writer, reader) = broadcast_queue(4);
+        let slow = reader.add_bounded_stream(100);
+        assert_eq!(4, slow.max_lag());
+        reader.unsubscribe();
+        for i in 0..10 {
+            writer.try_send(i).unwrap();
+        }
+        assert_eq!(Err(LaggedRecvError::Lagged(6)), slow.recv());
+        for i in 6..10 {
+            assert_eq!(Ok(i), slow.recv());
+        }
+    }
+
+    #[test]
+    fn test_bounded_stream_threaded() {
+        bounded_stream_threaded(1);
+        bounded_stream_threaded(2);
+    }
+
+    fn bounded_stream_threaded(consumers: usize) {
+        let (writer, reader) = broadcast_queue(8);
+        let slow = reader.add_bounded_stream(4);
+        let mut slow_consumers = Vec::new();
+        for _ in 1..consumers {
+            slow_consumers.push(slow.clone());
+        }
+        slow_consumers.push(slow);
+        let num_loop = 20000;
+        scope(|scope| {
+            for consumer in slow_consumers {
+                scope.spawn(move |_| {
+                    let mut last = None;
+                    loop {
+                        match consumer.recv() {
+                            Ok(val) => {
+                                let val: Vec<usize> = val;
+                                assert_eq!(val, vec![val[0]; 8]);
+                                if let Some(last) = last {
+                                    assert!(val[0] > last);
+                                }
+                                last = Some(val[0]);
+                            }
+                            Err(LaggedRecvError::Lagged(n)) => assert!(n > 0),
+                            Err(LaggedRecvError::Disconnected) => break,
+                        }
+                    }
+                });
+            }
+            for i in 0..num_loop {
+                loop {
+                    match writer.try_send(vec![i; 8]) {
+                        Ok(_) => break,
+                        // Only happens while the slow streams are cloning a slot
+                        Err(_) => yield_now(),
+                    }
+                }
+                assert_eq!(vec![i; 8], reader.recv().unwrap());
+            }
+            drop(writer);
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_conflated() {
+        let (writer, reader) = broadcast_queue_conflated(4, |v: &(usize, usize)| v.0);
+        let reader_2 = reader.add_stream();
+        writer.try_send((0, 0)).unwrap();
+        writer.try_send((1, 1)).unwrap();
+        assert_eq!((0, 0), reader.try_recv().unwrap());
+        // The first stream already saw the old value, the second one never will
+        writer.try_send((0, 2)).unwrap();
+        assert_eq!((1, 1), reader.try_recv().unwrap());
+        assert_eq!((0, 2), reader.try_recv().unwrap());
+        assert_eq!((1, 1), reader_2.try_recv().unwrap());
+        assert_eq!((0, 2), reader_2.try_recv().unwrap());
+        // The replaced values still take up space until reader_2 moves past them
+        for i in 3..7 {
+            writer.try_send((0, i)).unwrap();
+            assert_eq!((0, i), reader.try_recv().unwrap());
+        }
+        assert!(writer.try_send((0, 7)).is_err());
+        let single = reader_2.into_single().unwrap();
+        assert_eq!(6, single.try_recv_view(|v| v.1).ok().unwrap());
+        assert_eq!(Err(TryRecvError::Empty), single.try_recv());
+    }
+
+    #[test]
+    fn test_conflated_threaded() {
+        let (writer, reader) = broadcast_queue_conflated(8, |v: &(usize, usize)| v.0);
+        let num_loop = 10000;
+        scope(|scope| {
+            for _ in 0..2 {
+                let cur_reader = reader.add_stream();
+                scope.spawn(move |_| {
+                    let mut last = [0; 4];
+                    while let Ok((key, val)) = cur_reader.recv() {
+                        assert!(val >= last[key]);
+                        last[key] = val;
+                    }
+                    for (key, val) in last.iter().enumerate() {
+                        assert_eq!(num_loop - 4 + key, *val);
+                    }
+                });
+            }
+            reader.unsubscribe();
+            for i in 0..num_loop {
+                while writer.try_send((i % 4, i)).is_err() {
+                    yield_now();
+                }
+            }
+            drop(writer);
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_deduplicated() {
+        let (writer, reader) = broadcast_queue_deduplicated(8, 4, |v: &(u64, usize)| v.0);
+        let reader_2 = reader.add_stream();
+        let writer_2 = writer.clone();
+        writer.try_send((7, 0)).unwrap();
+        // Ids are shared between writers
+        writer_2.try_send((7, 1)).unwrap();
+        writer_2.try_send((8, 2)).unwrap();
+        for stream in [&reader, &reader_2].iter() {
+            assert_eq!((7, 0), stream.try_recv().unwrap());
+            assert_eq!((8, 2), stream.try_recv().unwrap());
+            assert_eq!(Err(TryRecvError::Empty), stream.try_recv());
+        }
+    }
+
+    #[derive(Debug)]
+    struct CountedClone<'a> {
+        val: usize,
+        clones: &'a AtomicUsize,
+    }
 
-    #[inline(always)]
-    fn next(&mut self) -> Option<T> {
-        match self.recv.try_recv() {
-            Ok(val) => Some(val),
-            Err(_) => None,
+    impl<'a> Clone for CountedClone<'a> {
+        fn clone(&self) -> CountedClone<'a> {
+            self.clones.fetch_add(1, Ordering::Relaxed);
+            CountedClone {
+                val: self.val,
+                clones: self.clones,
+            }
         }
     }
-}
 
-impl<'a, T: Clone + 'a> IntoIterator for &'a BroadcastReceiver<T> {
-    type Item = T;
+    #[test]
+    fn test_filtered() {
+        let clones = AtomicUsize::new(0);
+        let (writer, reader) = broadcast_queue(4);
+        let filtered = reader.add_stream_filtered(|v: &CountedClone| v.val.is_multiple_of(3));
+        reader.unsubscribe();
+        for i in 0..30 {
+            // Values which get filtered out can't hold up the writer
+            writer
+                .try_send(CountedClone {
+                    val: i,
+                    clones: &clones,
+                })
+                .unwrap();
+            if i % 3 == 0 {
+                assert_eq!(i, filtered.try_recv().unwrap().val);
+            }
+            assert!(filtered.try_recv().is_err());
+        }
+        assert_eq!(10, clones.load(Ordering::Relaxed));
+        let single = filtered.into_single().unwrap();
+        writer
+            .try_send(CountedClone {
+                val: 1,
+                clones: &clones,
+            })
+            .unwrap();
+        writer
+            .try_send(CountedClone {
+                val: 3,
+                clones: &clones,
+            })
+            .unwrap();
+        assert_eq!(3, single.try_recv_view(|v| v.val).ok().unwrap());
+        assert_eq!(10, clones.load(Ordering::Relaxed));
+    }
 
-    type IntoIter = BroadcastRefIter<'a, T>;
+    #[test]
+    fn test_try_send_ref() {
+        let clones = AtomicUsize::new(0);
+        let val = CountedClone {
+            val: 7,
+            clones: &clones,
+        };
+        let (writer, reader) = broadcast_queue(2);
+        writer.try_send_ref(&val).unwrap();
+        writer.try_send_ref(&val).unwrap();
+        assert_eq!(2, clones.load(Ordering::Relaxed));
+        match writer.try_send_ref(&val) {
+            Err(FrozenTrySendError::Full(back)) => assert_eq!(7, back.val),
+            other => panic!("expected a full queue, got {:?}", other),
+        }
+        assert_eq!(2, clones.load(Ordering::Relaxed));
 
-    fn into_iter(self) -> BroadcastRefIter<'a, T> {
-        BroadcastRefIter { recv: self }
+        // With two writers sends go through the compare and swap instead
+        let writer2 = writer.clone();
+        assert!(writer2.try_send_ref(&val).is_err());
+        assert_eq!(2, clones.load(Ordering::Relaxed));
+        reader.try_recv().unwrap();
+        clones.store(0, Ordering::Relaxed);
+        writer2.try_send_ref(&val).unwrap();
+        assert_eq!(1, clones.load(Ordering::Relaxed));
+        assert_eq!(
+            vec![7, 7],
+            reader.try_iter().map(|v| v.val).collect::<Vec<_>>()
+        );
     }
-}
 
-pub struct BroadcastSCRefIter<'a, T: Clone + Sync + 'a> {
-    recv: &'a BroadcastUniReceiver<T>,
-}
+    #[test]
+    fn test_sampled() {
+        let clones = AtomicUsize::new(0);
+        let (writer, reader) = broadcast_queue(4);
+        let sampled = reader.add_stream_sampled(10);
+        reader.unsubscribe();
+        for i in 0..100 {
+            writer
+                .try_send(CountedClone {
+                    val: i,
+                    clones: &clones,
+                })
+                .unwrap();
+            if i % 10 == 0 {
+                assert_eq!(i, sampled.try_recv().unwrap().val);
+            }
+            assert!(sampled.try_recv().is_err());
+        }
+        // Only the sampled values were ever cloned
+        assert_eq!(10, clones.load(Ordering::Relaxed));
+    }
 
-impl<'a, T: Clone + Sync + 'a> Iterator for BroadcastSCRefIter<'a, T> {
-    type Item = T;
+    #[test]
+    #[should_panic]
+    fn test_sampled_zero() {
+        let (_writer, reader) = broadcast_queue::<usize>(4);
+        reader.add_stream_sampled(0);
+    }
 
-    #[inline(always)]
-    fn next(&mut self) -> Option<T> {
-        match self.recv.try_recv() {
-            Ok(val) => Some(val),
-            Err(_) => None,
+    #[test]
+    fn test_inspector() {
+        let (writer, reader) = broadcast_queue(4);
+        let inspector = writer.inspector();
+        let fast = reader.add_stream();
+        for i in 0..3 {
+            writer.try_send(i).unwrap();
         }
+        fast.try_recv().unwrap();
+        fast.try_recv().unwrap();
+        // The slowest stream decides what's pending
+        assert_eq!(Some(vec![0, 1, 2]), inspector.pending());
+        assert_eq!(3, inspector.occupancy());
+        let mut tails: Vec<_> = inspector.stats().streams.iter().map(|s| s.tail).collect();
+        tails.sort();
+        assert_eq!(vec![0, 2], tails);
+        // Writers don't wait on paused streams, so neither does the inspector
+        let paused = reader.pause().ok().unwrap();
+        assert_eq!(Some(vec![2]), inspector.pending());
+        assert_eq!(2, inspector.stream_count());
+        fast.unsubscribe();
+        assert_eq!(Some(vec![]), inspector.pending());
+        assert!(paused.unsubscribe());
+        // The inspector isn't a stream, so the writer sees them all gone
+        assert_eq!(0, inspector.stream_count());
+        assert_eq!(Err(FrozenTrySendError::Full(3)), writer.try_send(3));
+        drop(writer);
+        assert_eq!(0, inspector.writer_count());
+        assert_eq!(Some(vec![]), inspector.pending());
     }
-}
 
-impl<'a, T: Clone + Sync + 'a> IntoIterator for &'a BroadcastUniReceiver<T> {
-    type Item = T;
+    #[test]
+    fn test_memory_footprint() {
+        let (writer, reader) = broadcast_queue_with_reclaim::<u64, _>(16, LeakReclaim::new());
+        let start = writer.memory_footprint();
+        assert_eq!(start, reader.memory_footprint());
+        let (larger, _larger_reader) = broadcast_queue::<u64>(32);
+        assert_eq!(2 * start.ring, larger.memory_footprint().ring);
+        assert!(start.padding >= 16 * 64);
+        // More streams than fit in the first block need another one
+        let streams: Vec<_> = (0..8).map(|_| reader.add_stream()).collect();
+        let grown = writer.memory_footprint();
+        assert!(grown.streams > start.streams);
+        assert_eq!(start.ring, grown.ring);
+        // Removed streams are held on to until the queue goes away
+        drop(streams);
+        let shrunk = writer.memory_footprint();
+        assert!(shrunk.reclaim > grown.reclaim);
+        assert!(shrunk.streams < grown.streams);
+        let inspector = writer.inspector();
+        assert_eq!(shrunk.total(), inspector.memory_footprint().total());
+    }
 
-    type IntoIter = BroadcastSCRefIter<'a, T>;
+    #[test]
+    fn test_dump() {
+        let (writer, reader) = broadcast_queue(4);
+        let other = reader.add_stream();
+        for i in 0..10 {
+            writer.try_send(i).unwrap();
+            reader.try_recv().unwrap();
+            if i < 7 {
+                other.try_recv().unwrap();
+            }
+        }
+        // Sequence numbers keep counting past the capacity
+        let dump = reader.dump();
+        assert_eq!(vec![(7, 7), (8, 8), (9, 9)], dump);
+        assert_eq!(dump, writer.dump());
+        assert!(reader.snapshot().is_empty());
+        assert_eq!(vec![7, 8, 9], other.snapshot());
 
-    fn into_iter(self) -> BroadcastSCRefIter<'a, T> {
-        BroadcastSCRefIter { recv: self }
+        let (restored_writer, restored) = broadcast_queue_restored(4, dump);
+        assert_eq!(Ok((0, 7)), restored.try_recv_indexed());
+        assert_eq!(vec![(1, 8), (2, 9)], restored_writer.dump());
+        restored_writer.try_send(10).unwrap();
+        assert_eq!(vec![8, 9, 10], restored.try_iter().collect::<Vec<_>>());
+        // The original queue was left alone
+        assert_eq!(vec![7, 8, 9], other.try_iter().collect::<Vec<_>>());
+        assert!(writer.dump().is_empty());
     }
-}
 
-pub struct BroadcastUniIter<R, F: FnMut(&T) -> R, T: Clone + Sync> {
-    recv: BroadcastUniReceiver<T>,
-    op: F,
-}
+    #[test]
+    #[should_panic]
+    fn test_restored_too_large() {
+        let dump = (0..5).map(|i| (i, i)).collect();
+        broadcast_queue_restored::<u64>(4, dump);
+    }
 
-impl<R, F: FnMut(&T) -> R, T: Clone + Sync> Iterator for BroadcastUniIter<R, F, T> {
-    type Item = R;
+    #[test]
+    fn test_inspector_threaded() {
+        let num_loop = 10000;
+        let (writer, reader) = broadcast_queue(8);
+        let inspector = reader.inspector();
+        scope(|scope| {
+            scope.spawn(move |_| {
+                for i in 0..num_loop {
+                    while writer.try_send(Arc::new(i)).is_err() {
+                        yield_now();
+                    }
+                }
+            });
+            scope.spawn(move |_| {
+                for i in 0..num_loop {
+                    assert_eq!(i, *reader.recv().unwrap());
+                }
+            });
+            // Whatever is pending is always a run of the values in order
+            let mut last = 0;
+            while inspector.writer_count() != 0 || inspector.stream_count() != 0 {
+                let pending = inspector.pending().unwrap();
+                for (j, val) in pending.iter().enumerate() {
+                    assert_eq!(**val, *pending[0] + j);
+                }
+                if let Some(first) = pending.first() {
+                    assert!(**first >= last);
+                    last = **first;
+                }
+            }
+        })
+        .unwrap();
+    }
 
-    #[inline(always)]
-    fn next(&mut self) -> Option<R> {
-        let opref = &mut self.op;
-        match self.recv.recv_view(|v| opref(v)) {
-            Ok(val) => Some(val),
-            Err(_) => None,
+    #[test]
+    fn test_filtered_threaded() {
+        let (writer, reader) = broadcast_queue(8);
+        let num_loop = 10000;
+        scope(|scope| {
+            for modulus in 1..4 {
+                let filtered =
+                    reader.add_stream_filtered(move |v: &usize| v.is_multiple_of(modulus));
+                for _ in 0..2 {
+                    let consumer = filtered.clone();
+                    scope.spawn(move |_| {
+                        let mut last = None;
+                        for val in consumer {
+                            assert_eq!(0, val % modulus);
+                            if let Some(last) = last {
+                                assert!(val > last);
+                            }
+                            last = Some(val);
+                        }
+                    });
+                }
+            }
+            reader.unsubscribe();
+            for i in 0..num_loop {
+                while writer.try_send(i).is_err() {
+                    yield_now();
+                }
+            }
+            drop(writer);
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_shared_view() {
+        let clones = AtomicUsize::new(0);
+        let (writer, reader) = broadcast_queue(4);
+        let reader_2 = reader.clone();
+        let filtered = reader.add_stream_filtered(|v: &CountedClone| v.val != 1);
+        for i in 0..3 {
+            writer
+                .try_send(CountedClone {
+                    val: i,
+                    clones: &clones,
+                })
+                .unwrap();
         }
+        assert_eq!(0, reader.try_recv_view(|v| v.val).ok().unwrap());
+        assert_eq!(1, reader_2.recv_view(|v| v.val).ok().unwrap());
+        assert_eq!(2, reader.try_recv_view(|v| v.val).ok().unwrap());
+        assert!(reader_2.try_recv_view(|v| v.val).is_err());
+        assert_eq!(0, filtered.try_recv_view(|v| v.val).ok().unwrap());
+        assert_eq!(2, filtered.try_recv_view(|v| v.val).ok().unwrap());
+        assert_eq!(0, clones.load(Ordering::Relaxed));
     }
-}
 
-pub struct BroadcastUniRefIter<'a, R, F: FnMut(&T) -> R, T: Clone + Sync + 'a> {
-    recv: &'a BroadcastUniReceiver<T>,
-    op: F,
-}
+    #[test]
+    fn test_shared_view_threaded() {
+        let (writer, reader) = broadcast_queue(4);
+        let num_loop = 10000;
+        let seen = AtomicUsize::new(0);
+        scope(|scope| {
+            for _ in 0..3 {
+                let consumer = reader.clone();
+                let seen = &seen;
+                scope.spawn(move |_| {
+                    let mut last = None;
+                    while let Ok(first) = consumer.recv_view(|v: &Vec<usize>| {
+                        assert_eq!(*v, vec![v[0]; 8]);
+                        v[0]
+                    }) {
+                        if let Some(last) = last {
+                            assert!(first > last);
+                        }
+                        last = Some(first);
+                        seen.fetch_add(1, Ordering::Relaxed);
+                    }
+                });
+            }
+            reader.unsubscribe();
+            for i in 0..num_loop {
+                while writer.try_send(vec![i; 8]).is_err() {
+                    yield_now();
+                }
+            }
+            drop(writer);
+        })
+        .unwrap();
+        assert_eq!(num_loop, seen.load(Ordering::Relaxed));
+    }
 
-impl<'a, R, F: FnMut(&T) -> R, T: Clone + Sync + 'a> Iterator for BroadcastUniRefIter<'a, R, F, T> {
-    type Item = R;
+    #[test]
+    fn test_view_panic() {
+        let fails = |reader: &BroadcastReceiver<usize>| {
+            catch_unwind(AssertUnwindSafe(|| {
+                let _ = reader.try_recv_view(|_| -> usize { panic!("view failed") });
+            }))
+            .is_err()
+        };
+        let (writer, reader) = broadcast_queue(2);
+        let other = reader.clone();
+        writer.try_send(0).unwrap();
+        writer.try_send(1).unwrap();
+        assert!(fails(&reader));
+        assert_eq!(1, other.try_recv_view(|v| *v).ok().unwrap());
+        // The slot was given back even though the view panicked
+        writer.try_send(2).unwrap();
+        writer.try_send(3).unwrap();
+        drop(other);
+        assert!(fails(&reader));
+        let single = reader.into_single().unwrap();
+        assert!(catch_unwind(AssertUnwindSafe(|| {
+            let _ = single.recv_view(|_| -> usize { panic!("view failed") });
+        }))
+        .is_err());
+        assert_eq!(
+            Err(TryRecvError::Empty),
+            single.try_recv_view(|v| *v).map_err(|(_, e)| e)
+        );
+        writer.try_send(4).unwrap();
+        writer.try_send(5).unwrap();
+        assert_eq!(4, single.try_recv_view(|v| *v).ok().unwrap());
+    }
 
-    #[inline(always)]
-    fn next(&mut self) -> Option<R> {
-        let opref = &mut self.op;
-        match self.recv.try_recv_view(|v| opref(v)) {
-            Ok(val) => Some(val),
-            Err(_) => None,
+    #[test]
+    fn test_send_panicking_clock() {
+        struct FailingClock {
+            clock: MockClock,
+            failing: AtomicBool,
+        }
+
+        impl Clock for FailingClock {
+            fn now(&self) -> Instant {
+                if self.failing.load(Ordering::Relaxed) {
+                    panic!("clock failed");
+                }
+                self.clock.now()
+            }
         }
+
+        let clock = Arc::new(FailingClock {
+            clock: MockClock::new(),
+            failing: AtomicBool::new(false),
+        });
+        let (writer, reader) = broadcast_queue_with_clock(4, clock.clone());
+        writer.try_send(0).unwrap();
+        clock.failing.store(true, Ordering::Relaxed);
+        assert!(catch_unwind(AssertUnwindSafe(|| writer.try_send(1))).is_err());
+        // The clock is read before a slot is claimed, so the panic leaves no gap behind
+        clock.failing.store(false, Ordering::Relaxed);
+        writer.try_send(2).unwrap();
+        assert_eq!(vec![0, 2], reader.try_iter().collect::<Vec<_>>());
     }
-}
 
-/// Creates a (```BroadcastSender```, ```BroadcastReceiver```) pair with a capacity that's
-/// the next power of two >= the given capacity
-///
-/// # Example
-/// ```
-/// use multiqueue2::broadcast_queue;
-/// let (w, r) = broadcast_queue(10);
-/// w.try_send(10).unwrap();
-/// assert_eq!(10, r.try_recv().unwrap());
-/// ```
-pub fn broadcast_queue<T: Clone>(capacity: Index) -> (BroadcastSender<T>, BroadcastReceiver<T>) {
-    let (send, recv) = MultiQueue::<BCast<T>, T>::create_tx_rx(capacity);
-    (
-        BroadcastSender { sender: send },
-        BroadcastReceiver { receiver: recv },
-    )
-}
+    #[test]
+    fn test_send_all_panicking_drop() {
+        // Only the value first sent panics when dropped, not the clones received
+        struct FailingDrop(usize, bool);
 
-/// Creates a (```BroadcastSender```, ```BroadcastReceiver```) pair with a capacity that's
-/// the next power of two >= the given capacity and the specified wait strategy
-///
-/// # Example
-/// ```
-/// use multiqueue2::broadcast_queue_with;
-/// use multiqueue2::wait::BusyWait;
-/// let (w, r) = broadcast_queue_with(10, BusyWait::new());
-/// w.try_send(10).unwrap();
-/// assert_eq!(10, r.try_recv().unwrap());
-/// ```
+        impl Clone for FailingDrop {
+            fn clone(&self) -> Self {
+                FailingDrop(self.0, false)
+            }
+        }
 
-pub fn broadcast_queue_with<T: Clone, W: Wait + 'static>(
-    capacity: Index,
-    wait: W,
-) -> (BroadcastSender<T>, BroadcastReceiver<T>) {
-    let (send, recv) = MultiQueue::<BCast<T>, T>::create_tx_rx_with(capacity, wait);
-    (
-        BroadcastSender { sender: send },
-        BroadcastReceiver { receiver: recv },
-    )
-}
+        impl Drop for FailingDrop {
+            fn drop(&mut self) {
+                if self.1 && !thread::panicking() {
+                    panic!("drop failed");
+                }
+            }
+        }
 
-/// Futures variant of broadcast_queue - datastructures implement
-/// Sink + Stream at a minor (~30 ns) performance cost to BlockingWait
-pub fn broadcast_fut_queue<T: Clone>(
-    capacity: Index,
-) -> (BroadcastFutSender<T>, BroadcastFutReceiver<T>) {
-    let (isend, irecv) = futures_multiqueue::<BCast<T>, T>(capacity);
-    (
-        BroadcastFutSender { sender: isend },
-        BroadcastFutReceiver { receiver: irecv },
-    )
-}
+        let (writer, reader) = broadcast_queue(2);
+        writer.try_send(FailingDrop(0, true)).unwrap();
+        writer.try_send(FailingDrop(1, false)).unwrap();
+        assert_eq!(2, reader.try_iter().count());
+        let vals = [FailingDrop(2, false), FailingDrop(3, false)];
+        assert!(catch_unwind(AssertUnwindSafe(|| writer.try_send_all(&vals))).is_err());
+        // The values written over are dropped after the run is published
+        let got: Vec<_> = reader.try_iter().map(|val| val.0).collect();
+        assert_eq!(vec![2, 3], got);
+        writer.try_send(FailingDrop(4, false)).unwrap();
+        assert_eq!(4, reader.try_recv().unwrap().0);
+    }
 
-pub fn broadcast_fut_queue_with<T: Clone>(
-    capacity: Index,
-    try_spins: usize,
-    yield_spins: usize,
-) -> (BroadcastFutSender<T>, BroadcastFutReceiver<T>) {
-    let (send, recv) = futures_multiqueue_with::<BCast<T>, T>(capacity, try_spins, yield_spins);
-    (
-        BroadcastFutSender { sender: send },
-        BroadcastFutReceiver { receiver: recv },
-    )
-}
+    #[test]
+    fn test_snapshot() {
+        let (writer, reader) = broadcast_queue(4);
+        assert!(reader.snapshot().is_empty());
+        for i in 0..4 {
+            writer.try_send(i).unwrap();
+        }
+        let evens = reader.add_stream_filtered(|v| v % 2 == 0);
+        assert_eq!(vec![0, 1, 2, 3], reader.snapshot());
+        assert_eq!(vec![0, 2], evens.snapshot());
+        assert_eq!(0, reader.try_recv().unwrap());
+        assert_eq!(0, evens.try_recv().unwrap());
+        assert_eq!(vec![1, 2, 3], reader.snapshot());
+        assert_eq!(vec![2], evens.snapshot());
+        assert_eq!(3, reader.lag());
+    }
 
-unsafe impl<T: Send + Sync + Clone> Send for BroadcastSender<T> {}
-unsafe impl<T: Send + Sync + Clone> Send for BroadcastReceiver<T> {}
-unsafe impl<T: Send + Sync + Clone> Send for BroadcastUniReceiver<T> {}
+    #[test]
+    fn test_snapshot_threaded() {
+        let (writer, reader) = broadcast_queue(4);
+        let watcher = reader.add_stream();
+        let num_loop = 10000;
+        scope(|scope| {
+            scope.spawn(move |_| {
+                for i in 0..num_loop {
+                    while writer.try_send(i).is_err() {
+                        yield_now();
+                    }
+                }
+            });
+            let cloned = reader.clone();
+            for stream in [reader, cloned] {
+                scope.spawn(move |_| for _ in stream {});
+            }
+            scope.spawn(move |_| loop {
+                let seen = watcher.snapshot();
+                // Whatever was seen has to be in order and start where the stream is
+                if let Some(first) = seen.first() {
+                    for (i, v) in seen.iter().enumerate() {
+                        assert_eq!(first + i, *v);
+                    }
+                }
+                match watcher.try_recv() {
+                    Ok(v) if v + 1 == num_loop => break,
+                    Ok(v) => assert!(seen.is_empty() || seen[0] == v),
+                    Err(TryRecvError::Disconnected) => break,
+                    Err(TryRecvError::Empty) => yield_now(),
+                }
+            });
+        })
+        .unwrap();
+    }
 
-#[cfg(test)]
-mod test {
+    #[test]
+    fn test_named_streams() {
+        let (writer, reader) = broadcast_queue(4);
+        let fast = reader.add_stream_named("fast");
+        let slow = reader.add_stream_named(String::from("slow"));
+        let slow_worker = slow.clone_named("worker");
+        assert_eq!(Some("slow"), slow_worker.stream_name());
+        assert_eq!(Some("worker"), slow_worker.clone().name());
+        assert_eq!(None, slow.name());
+        for i in 0..4 {
+            writer.try_send(i).unwrap();
+            reader.try_recv().unwrap();
+            fast.try_recv().unwrap();
+        }
+        slow.try_recv().unwrap();
+        let lags = writer.stream_lags();
+        assert_eq!(3, lags.len());
+        let slowest = lags.iter().max_by_key(|&&(_, lag)| lag).unwrap();
+        assert_eq!((Some("slow".to_string()), 3), *slowest);
+        let debug = format!("{:?}", slow_worker);
+        assert!(debug.contains("worker") && debug.contains("slow"));
+        drop(slow);
+        drop(slow_worker);
+        assert_eq!(
+            vec![(None, 0), (Some("fast".to_string()), 0)],
+            writer.stream_lags()
+        );
+    }
+
+    #[test]
+    fn test_stalled_streams() {
+        let threshold = Duration::from_millis(10);
+        let (writer, reader) = broadcast_queue(8);
+        let slow = reader.add_stream_named("slow");
+        let paused = reader.add_stream_named("paused").pause().unwrap();
+        for i in 0..4 {
+            writer.try_send(i).unwrap();
+        }
+        assert!(writer.stalled_streams(threshold).is_empty());
+        sleep(Duration::from_millis(20));
+        // Both streams with items waiting are stuck, but not the paused one
+        reader.try_recv().unwrap();
+        let names: Vec<_> = writer
+            .stalled_streams(threshold)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(vec![Some("slow".to_string())], names);
+        // Moving restarts the clock
+        slow.try_recv().unwrap();
+        assert!(writer.stalled_streams(threshold).is_empty());
+        sleep(Duration::from_millis(20));
+        assert_eq!(2, writer.stalled_streams(threshold).len());
+        assert!(writer.stalled_streams(Duration::from_secs(60)).is_empty());
+        // Streams which caught up aren't stuck
+        while reader.try_recv().is_ok() {}
+        while slow.try_recv().is_ok() {}
+        sleep(Duration::from_millis(20));
+        assert!(writer.stalled_streams(threshold).is_empty());
+        drop(paused);
+    }
 
-    use super::broadcast_queue;
+    #[test]
+    fn test_debug() {
+        let (writer, reader) = broadcast_queue(4);
+        let _other = reader.add_stream();
+        writer.try_send(1).unwrap();
+        writer.try_send(2).unwrap();
+        reader.try_recv().unwrap();
+        let queue = "MultiQueue { capacity: 4, occupancy: 2, streams: 2, writers: 1, \
+                     wait: \"BlockingWait\" }";
+        assert_eq!(
+            format!(
+                "BroadcastSender {{ sender: Sender {{ queue: {} }} }}",
+                queue
+            ),
+            format!("{:?}", writer)
+        );
+        let debug = format!("{:?}", reader);
+        assert!(debug.contains("lag: 1") && debug.contains(queue));
+        // Receivers which aren't the only one on their stream still print fine
+        let clone = reader.clone();
+        assert!(format!("{:?}", clone.into_single().unwrap_err()).contains(queue));
+    }
 
-    extern crate crossbeam;
-    use self::crossbeam::scope;
+    #[test]
+    fn test_delayed() {
+        let (writer, reader) = broadcast_queue_delayed(4);
+        let other = reader.add_stream();
+        let deadline = Instant::now() + Duration::from_millis(30);
+        writer.try_send(1).unwrap();
+        writer.try_send_after(2, deadline).unwrap();
+        writer.try_send(3).unwrap();
+        for stream in &[&reader, &other] {
+            assert_eq!(1, stream.try_recv().unwrap());
+            // Everything after the delayed value waits for it too
+            assert_eq!(Err(TryRecvError::Empty), stream.try_recv());
+            assert!(stream.snapshot().is_empty());
+        }
+        assert_eq!(2, reader.recv().unwrap());
+        assert!(Instant::now() >= deadline);
+        assert_eq!(3, reader.try_recv().unwrap());
+        assert_eq!(vec![2, 3], other.try_iter().collect::<Vec<_>>());
+        // Deadlines which already passed don't hold anything up
+        writer.try_send_after(4, Instant::now()).unwrap();
+        assert_eq!(4, reader.try_recv().unwrap());
+        drop(writer);
+        assert_eq!(Err(RecvError), reader.recv());
+    }
 
-    use std::sync::atomic::{AtomicUsize, Ordering};
-    use std::sync::mpsc::TryRecvError;
-    use std::sync::{Arc, Barrier};
-    use std::thread::yield_now;
+    #[test]
+    fn test_send_or_replace() {
+        let (writer, reader) = broadcast_queue_replacing(2);
+        assert_eq!(None, writer.try_send_or_replace(0).unwrap());
+        assert_eq!(None, writer.try_send_or_replace(1).unwrap());
+        assert_eq!(Some(0), writer.try_send_or_replace(2).unwrap());
+        // The lone consumer can't get ahead of the writer taking values off
+        assert_eq!(1, reader.try_recv().unwrap());
+        assert_eq!(None, writer.try_send_or_replace(3).unwrap());
+        // Nothing gets taken off while there's more than one stream
+        let other = reader.add_stream();
+        assert_eq!(
+            Err(FrozenTrySendError::Full(4)),
+            writer.try_send_or_replace(4)
+        );
+        other.unsubscribe();
+        assert_eq!(Some(2), writer.try_send_or_replace(4).unwrap());
+        assert_eq!(vec![3, 4], reader.try_iter().collect::<Vec<_>>());
+    }
 
     #[test]
-    fn build_queue() {
-        let _ = broadcast_queue::<usize>(10);
+    fn test_barrier() {
+        let (writer, reader) = broadcast_queue(8);
+        let other = reader.add_stream();
+        let paused = reader.add_stream().pause().ok().unwrap();
+        writer.try_send(1).unwrap();
+        writer.try_send(2).unwrap();
+        let barrier = writer.barrier();
+        writer.try_send(3).unwrap();
+        assert_eq!(1, reader.try_recv().unwrap());
+        assert!(!writer.wait_for_barrier_timeout(barrier, Duration::from_millis(1)));
+        assert_eq!(vec![1, 2, 3], other.try_iter().collect::<Vec<_>>());
+        assert!(!writer.barrier_passed(barrier));
+        // The paused stream isn't waited on
+        assert_eq!(2, reader.try_recv().unwrap());
+        assert!(writer.barrier_passed(barrier));
+        let resumed = paused.resume();
+        assert!(!writer.barrier_passed(barrier));
+        resumed.unsubscribe();
+        assert!(writer.wait_for_barrier_timeout(barrier, Duration::from_millis(1)));
+        let marked = writer.try_send_barrier(0).unwrap();
+        assert_eq!(vec![3, 0], reader.try_iter().collect::<Vec<_>>());
+        assert!(!writer.barrier_passed(marked));
+        other.unsubscribe();
+        writer.wait_for_barrier(marked);
+    }
+
+    #[test]
+    fn test_wait_on_drop() {
+        let timeout = Duration::from_millis(20);
+        let (writer, reader) = broadcast_queue_with_drop_policy(4, DropPolicy::Wait(timeout));
+        let stuck = reader.add_stream();
+        writer.try_send(1).unwrap();
+        writer.try_send(2).unwrap();
+        reader.try_recv().unwrap();
+        let start = Instant::now();
+        // One stream never catches up, so this gives up after the timeout
+        drop(writer);
+        assert!(start.elapsed() >= timeout);
+        assert_eq!(2, reader.try_recv().unwrap());
+        assert_eq!(vec![1, 2], stuck.try_iter().collect::<Vec<_>>());
+        assert_eq!(Err(TryRecvError::Disconnected), stuck.try_recv());
+
+        let timeout = Duration::from_secs(10);
+        let (writer, reader) = broadcast_queue_with_drop_policy(4, DropPolicy::Wait(timeout));
+        let received = AtomicUsize::new(0);
+        scope(|scope| {
+            let received = &received;
+            scope.spawn(move |_| {
+                sleep(Duration::from_millis(5));
+                for _ in reader {
+                    received.fetch_add(1, Ordering::Relaxed);
+                }
+            });
+            writer.try_send(1).unwrap();
+            writer.try_send(2).unwrap();
+            writer.unsubscribe();
+            // The reader has received both, though it may not have counted the last yet
+            assert!(received.load(Ordering::Relaxed) >= 1);
+        })
+        .unwrap();
     }
 
     #[test]
-    fn push_pop_test() {
-        let (writer, reader) = broadcast_queue(1);
-        for _ in 0..100 {
-            assert!(reader.try_recv().is_err());
-            writer.try_send(1 as usize).expect("Push should succeed");
-            assert!(writer.try_send(1).is_err());
-            assert_eq!(1, reader.try_recv().unwrap());
-        }
+    #[should_panic]
+    fn test_discard_on_broadcast() {
+        let _ = broadcast_queue_with_drop_policy::<usize>(4, DropPolicy::Discard(Box::new(drop)));
     }
 
-    fn mpsc_broadcast(senders: usize, receivers: usize) {
+    #[test]
+    fn test_barrier_threaded() {
         let (writer, reader) = broadcast_queue(4);
-        let myb = Barrier::new(receivers + senders);
-        let bref = &myb;
-        let num_loop = 100000;
+        let num_loop = 1000;
+        let received = [AtomicUsize::new(0), AtomicUsize::new(0)];
         scope(|scope| {
-            for q in 0..senders {
-                let cur_writer = writer.clone();
+            for counter in &received {
+                let cur_reader = reader.add_stream();
                 scope.spawn(move |_| {
-                    bref.wait();
-                    'outer: for i in 0..num_loop {
-                        for _ in 0..100000000 {
-                            if cur_writer.try_send((q, i)).is_ok() {
-                                continue 'outer;
-                            }
-                            yield_now();
-                        }
-                        assert!(false, "Writer could not write");
+                    for _ in cur_reader {
+                        counter.fetch_add(1, Ordering::Relaxed);
                     }
                 });
             }
-            writer.unsubscribe();
-            for _ in 0..receivers {
-                let this_reader = reader.add_stream().into_single().unwrap();
-                scope.spawn(move |_| {
-                    let mut myv = Vec::new();
-                    for _ in 0..senders {
-                        myv.push(0);
-                    }
-                    bref.wait();
-                    for _ in 0..num_loop * senders {
-                        loop {
-                            if let Ok(val) = this_reader.try_recv_view(|x| *x) {
-                                assert_eq!(myv[val.0], val.1);
-                                myv[val.0] += 1;
-                                break;
-                            }
-                            yield_now();
-                        }
-                    }
-                    for val in myv {
-                        if val != num_loop {
-                            panic!("Wrong number of values obtained for this");
-                        }
+            reader.unsubscribe();
+            for i in 1..=num_loop {
+                while writer.try_send(i).is_err() {
+                    yield_now();
+                }
+                if i % 100 == 0 {
+                    let barrier = writer.barrier();
+                    writer.wait_for_barrier(barrier);
+                    // Every stream has received everything sent before the barrier,
+                    // though the last value may not have been counted yet
+                    for counter in &received {
+                        assert!(counter.load(Ordering::Relaxed) >= i - 1);
                     }
-                    assert!(this_reader.try_recv().is_err());
-                });
+                }
             }
-            reader.unsubscribe();
+            writer.unsubscribe();
         })
         .unwrap();
     }
 
     #[test]
-    fn test_spsc_this() {
-        mpsc_broadcast(1, 1);
+    fn test_expiring() {
+        let (writer, reader) = broadcast_queue_expiring(8);
+        let other = reader.add_stream();
+        writer
+            .try_send_with_ttl(1, Duration::from_millis(10))
+            .unwrap();
+        writer.try_send(2).unwrap();
+        writer
+            .try_send_with_ttl(3, Duration::from_millis(10))
+            .unwrap();
+        writer
+            .try_send_with_ttl(4, Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(vec![1, 2, 3, 4], other.snapshot());
+        sleep(Duration::from_millis(20));
+        assert_eq!(vec![2, 4], other.snapshot());
+        assert_eq!(2, reader.try_recv().unwrap());
+        assert_eq!(4, reader.try_recv().unwrap());
+        assert_eq!(Err(TryRecvError::Empty), reader.try_recv());
+        let view = other.clone();
+        assert_eq!(Some(2), view.try_recv_view(|v| *v).ok());
+        assert_eq!(4, other.try_recv().unwrap());
+        view.unsubscribe();
+        // Expired values can't be viewed either
+        writer
+            .try_send_with_ttl(5, Duration::from_millis(1))
+            .unwrap();
+        sleep(Duration::from_millis(5));
+        let uni = other.into_single().unwrap();
+        assert!(uni.try_recv_view(|v| *v).is_err());
     }
 
     #[test]
-    fn test_spsc_broadcast() {
-        mpsc_broadcast(1, 3);
+    fn test_mock_clock() {
+        let clock = Arc::new(MockClock::new());
+        let (writer, reader) = broadcast_queue_with_clock(8, clock.clone());
+        let slow = reader.add_stream_named("slow");
+        writer
+            .try_send_after(1, clock.now() + Duration::from_secs(1))
+            .unwrap();
+        writer.try_send_with_ttl(2, Duration::from_secs(5)).unwrap();
+        writer.try_send(3).unwrap();
+        assert_eq!(Err(TryRecvError::Empty), reader.try_recv());
+        assert!(writer.stalled_streams(Duration::from_secs(1)).is_empty());
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(
+            (1, Duration::from_secs(1)),
+            reader.try_recv_with_latency().unwrap()
+        );
+        // Stuck since the first check, which is when it was first seen with something to read
+        assert_eq!(
+            vec![(Some("slow".to_string()), Duration::from_secs(1))],
+            writer.stalled_streams(Duration::from_secs(1))
+        );
+        clock.advance(Duration::from_secs(4));
+        assert_eq!(3, reader.try_recv().unwrap());
+        assert_eq!(vec![1, 3], slow.try_iter().collect::<Vec<_>>());
+        // Blocked receives see the deadline pass without anything waking them
+        writer
+            .try_send_after(4, clock.now() + Duration::from_secs(1))
+            .unwrap();
+        let t = thread::spawn(move || reader.recv().unwrap());
+        sleep(Duration::from_millis(5));
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(4, t.join().unwrap());
     }
 
     #[test]
-    fn test_mpsc_single() {
-        mpsc_broadcast(2, 1);
+    #[should_panic]
+    fn test_ttl_on_plain_queue() {
+        let (writer, _reader) = broadcast_queue(4);
+        let _ = writer.try_send_with_ttl(1, Duration::from_secs(1));
     }
 
     #[test]
-    fn test_mpsc_broadcast() {
-        mpsc_broadcast(2, 3);
+    fn test_timestamped() {
+        let (writer, reader) = broadcast_queue_timestamped(4);
+        let other = reader.add_stream().into_single().unwrap();
+        writer.try_send(1).unwrap();
+        sleep(Duration::from_millis(20));
+        writer.try_send(2).unwrap();
+        let (val, first) = reader.try_recv_with_latency().unwrap();
+        assert_eq!(1, val);
+        assert!(first >= Duration::from_millis(20));
+        let (val, second) = reader.recv_with_latency().unwrap();
+        assert_eq!(2, val);
+        assert!(second < first);
+        // Every stream sees the same send time
+        sleep(Duration::from_millis(5));
+        let (val, later) = other.try_recv_with_latency().unwrap();
+        assert_eq!(1, val);
+        assert!(later >= first + Duration::from_millis(5));
+        // Plain receives still work on timestamped queues
+        assert_eq!(2, other.try_recv().unwrap());
+        assert_eq!(Err(TryRecvError::Empty), reader.try_recv_with_latency());
     }
 
     #[test]
-    fn test_remove_reader() {
-        let (writer, reader) = broadcast_queue(1);
-        assert!(writer.try_send(1).is_ok());
+    #[should_panic]
+    fn test_latency_on_plain_queue() {
+        let (writer, reader) = broadcast_queue(4);
+        writer.try_send(1).unwrap();
+        let _ = reader.try_recv_with_latency();
+    }
+
+    #[test]
+    fn test_pause() {
+        let (writer, reader) = broadcast_queue(4);
         let reader_2 = reader.add_stream();
-        assert!(writer.try_send(1).is_err());
-        assert_eq!(1, reader.try_recv().unwrap());
-        assert!(reader.try_recv().is_err());
-        assert_eq!(1, reader_2.try_recv().unwrap());
+        let reader_3 = reader_2.clone();
+        // Somebody else is still on the stream
+        let reader_2 = reader_2.pause().unwrap_err();
+        drop(reader_3);
+        let paused = reader_2.pause().unwrap();
+        // Nothing got overwritten, so the stream resumes where it was
+        writer.try_send(0).unwrap();
+        writer.try_send(1).unwrap();
+        let reader_2 = paused.resume();
+        assert_eq!(0, reader_2.try_recv().unwrap());
+        assert_eq!(0, reader.try_recv().unwrap());
+        let paused = reader_2.pause().unwrap();
+        for i in 2..20 {
+            writer.try_send(i).unwrap();
+            assert_eq!(i - 1, reader.try_recv().unwrap());
+        }
+        assert_eq!(19, reader.try_recv().unwrap());
+        let reader_2 = paused.resume();
+        for i in 17..20 {
+            assert_eq!(i, reader_2.try_recv().unwrap());
+        }
         assert!(reader_2.try_recv().is_err());
-        assert!(writer.try_send(1).is_ok());
-        assert!(writer.try_send(1).is_err());
-        assert_eq!(1, reader.try_recv().unwrap());
-        assert!(reader.try_recv().is_err());
-        reader_2.unsubscribe();
-        assert!(writer.try_send(2).is_ok());
-        assert_eq!(2, reader.try_recv().unwrap());
+        // The resumed stream holds up the writers again
+        for i in 20..24 {
+            writer.try_send(i).unwrap();
+        }
+        assert!(writer.try_send(24).is_err());
     }
 
-    fn mpmc_broadcast(senders: usize, receivers: usize, nclone: usize) {
-        let (writer, reader) = broadcast_queue(10);
-        let myb = Barrier::new((receivers * nclone) + senders);
-        let bref = &myb;
-        let num_loop = 1000000;
-        let counter = AtomicUsize::new(0);
-        let _do_panic = AtomicUsize::new(0);
-        let do_panic = &_do_panic;
-        let cref = &counter;
+    #[test]
+    fn test_add_stream_from_earliest() {
+        let (writer, reader) = broadcast_queue(4);
+        // Nothing has wrapped yet, so everything sent is still there
+        writer.try_send(0).unwrap();
+        writer.try_send(1).unwrap();
+        assert_eq!(0, reader.recv().unwrap());
+        assert_eq!(1, reader.recv().unwrap());
+        let early = reader.add_stream_from_earliest();
+        assert_eq!(0, early.try_recv().unwrap());
+        assert_eq!(1, early.try_recv().unwrap());
+        assert!(early.try_recv().is_err());
+        early.unsubscribe();
+
+        for i in 2..10 {
+            writer.try_send(i).unwrap();
+            assert_eq!(i, reader.recv().unwrap());
+        }
+        let early = reader.add_stream_from_earliest();
+        assert_eq!(3, early.lag());
+        // The new stream holds the writers back until it catches up
+        writer.try_send(10).unwrap();
+        assert!(writer.try_send(11).is_err());
+        for i in 7..11 {
+            assert_eq!(i, early.try_recv().unwrap());
+        }
+        assert_eq!(10, reader.try_recv().unwrap());
+        writer.try_send(11).unwrap();
+        assert_eq!(11, early.try_recv().unwrap());
+    }
+
+    #[test]
+    fn test_add_stream_from_earliest_threaded() {
+        let (writer, reader) = broadcast_queue(8);
+        let num_loop = 2000;
         scope(|scope| {
-            for _ in 0..senders {
-                let cur_writer = writer.clone();
-                scope.spawn(move |_| {
-                    bref.wait();
-                    'outer: for i in 0..num_loop {
-                        for _ in 0..100000000 {
-                            if do_panic.load(Ordering::Relaxed) > 0 {
-                                panic!("Somebody left");
-                            }
-                            if cur_writer.try_send(i).is_ok() {
-                                continue 'outer;
-                            }
-                            yield_now();
-                        }
-                        assert!(false, "Writer could not write");
+            scope.spawn(move |_| {
+                for i in 0..num_loop {
+                    while writer.try_send(i).is_err() {
+                        yield_now();
                     }
-                });
-            }
-            writer.unsubscribe();
-            for _ in 0..receivers {
-                let _this_reader = reader.add_stream();
-                for _ in 0..nclone {
-                    let this_reader = _this_reader.clone();
-                    scope.spawn(move |_| {
-                        let mut cur = 0;
-                        bref.wait();
-                        loop {
-                            match this_reader.try_recv() {
-                                Ok(val) => {
-                                    if (senders == 1) && (val != 0) && (val <= cur) {
-                                        do_panic.fetch_add(1, Ordering::Relaxed);
-                                        panic!(
-                                            "Non-increasing values read {} last, val was {}",
-                                            cur, val
-                                        );
-                                    }
-                                    cur = val;
-                                    cref.fetch_add(1, Ordering::Relaxed);
-                                }
-                                Err(TryRecvError::Disconnected) => break,
-                                _ => yield_now(),
+                }
+            });
+            let mut last = 0;
+            while last + 1 < num_loop {
+                let early = reader.add_stream_from_earliest();
+                let mut prev = None;
+                for _ in 0..4 {
+                    match early.try_recv() {
+                        Ok(val) => {
+                            if let Some(p) = prev {
+                                assert_eq!(p + 1, val);
                             }
+                            prev = Some(val);
                         }
-                    });
+                        Err(_) => break,
+                    }
+                }
+                early.unsubscribe();
+                while let Ok(val) = reader.try_recv() {
+                    last = val;
                 }
             }
-            reader.unsubscribe();
         })
         .unwrap();
-        assert_eq!(
-            senders * receivers * num_loop,
-            counter.load(Ordering::SeqCst)
-        );
     }
 
     #[test]
-    fn test_spmc() {
-        mpmc_broadcast(1, 1, 2);
+    fn test_add_stream_from_latest() {
+        let (writer, reader) = broadcast_queue(4);
+        writer.try_send(1).unwrap();
+        writer.try_send(2).unwrap();
+        let late = reader.add_stream_from_latest();
+        assert_eq!(0, late.lag());
+        assert!(late.try_recv().is_err());
+        writer.try_send(3).unwrap();
+        assert_eq!(3, late.try_recv().unwrap());
+        assert_eq!(1, reader.try_recv().unwrap());
     }
 
-    #[test]
-    fn test_spmc_broadcast() {
-        mpmc_broadcast(1, 2, 2);
+    fn churn_streams_with<R: Reclaim + 'static>(reclaim: R) {
+        let (writer, reader) = broadcast_queue_with_reclaim(4, reclaim);
+        let num_loop = 10000;
+        scope(|scope| {
+            scope.spawn(move |_| {
+                for i in 0..num_loop {
+                    while writer.try_send(i).is_err() {
+                        yield_now();
+                    }
+                }
+            });
+            scope.spawn(move |_| {
+                // Streams come and go while the writer looks at them
+                for i in 0..num_loop {
+                    let stream = reader.add_stream();
+                    let val = reader.recv().unwrap();
+                    assert_eq!(i, val);
+                    let _ = stream.try_recv();
+                }
+            });
+        })
+        .unwrap();
     }
 
     #[test]
-    fn test_mpmc() {
-        mpmc_broadcast(2, 1, 2);
+    fn test_reclaim_epoch() {
+        churn_streams_with(EpochReclaim::new());
     }
 
     #[test]
-    fn test_mpmc_broadcast() {
-        mpmc_broadcast(2, 2, 2);
+    fn test_reclaim_crossbeam() {
+        churn_streams_with(CrossbeamReclaim::new());
     }
 
     #[test]
-    fn test_baddrop() {
-        // This ensures that a bogus arc isn't dropped from the queue
-        let (writer, reader) = broadcast_queue(1);
-        for _ in 0..10 {
-            writer.try_send(Arc::new(10)).unwrap();
-            reader.recv().unwrap();
-        }
+    fn test_reclaim_leak() {
+        churn_streams_with(LeakReclaim::new());
     }
 
-    struct Dropper<'a> {
-        aref: &'a AtomicUsize,
+    #[test]
+    fn test_detach_refused() {
+        let (_writer, reader) = broadcast_queue::<usize>(4);
+        // The queue would close with its only stream detached
+        let reader = reader.detach().unwrap_err();
+        let r2 = reader.add_stream();
+        let r2_clone = r2.clone();
+        let r2 = r2.detach().unwrap_err();
+        drop(r2_clone);
+        assert!(r2.detach().is_ok());
     }
 
-    impl<'a> Dropper<'a> {
-        pub fn new(a: &AtomicUsize) -> Dropper {
-            a.fetch_add(1, Ordering::Relaxed);
-            Dropper { aref: a }
-        }
+    #[test]
+    fn test_attach_wrong_queue() {
+        let (_w1, r1) = broadcast_queue::<usize>(4);
+        let (_w2, r2) = broadcast_queue::<usize>(4);
+        let token = r1.add_stream_named("moved").detach().unwrap();
+        assert_eq!(AttachError::WrongQueue, r2.attach(token).unwrap_err());
     }
 
-    impl<'a> Drop for Dropper<'a> {
-        fn drop(&mut self) {
-            self.aref.fetch_sub(1, Ordering::Relaxed);
-        }
+    #[test]
+    fn test_detach_across_threads() {
+        let (writer, reader) = broadcast_queue(4);
+        let token = reader.add_stream_named("moved").detach().unwrap();
+        writer.try_send(1).unwrap();
+        let handle = thread::spawn(move || {
+            let moved = reader.attach(token).unwrap();
+            assert_eq!(Some("moved"), moved.stream_name());
+            assert_eq!(1, moved.try_recv().unwrap());
+            assert_eq!(1, reader.try_recv().unwrap());
+        });
+        handle.join().unwrap();
     }
 
-    impl<'a> Clone for Dropper<'a> {
-        fn clone(&self) -> Dropper<'a> {
-            self.aref.fetch_add(1, Ordering::Relaxed);
-            Dropper { aref: self.aref }
+    #[test]
+    fn test_many_streams() {
+        let (writer, reader) = broadcast_queue(4);
+        // Enough streams to need several blocks of slots
+        let mut streams: Vec<_> = (0..20).map(|_| reader.add_stream()).collect();
+        writer.try_send(1).unwrap();
+        assert_eq!(21, writer.stream_count());
+        let mut keep = false;
+        streams.retain(|stream| {
+            assert_eq!(1, stream.try_recv().unwrap());
+            keep = !keep;
+            keep
+        });
+        assert_eq!(11, writer.stream_count());
+        let late = reader.add_stream_from_latest();
+        writer.try_send(2).unwrap();
+        assert_eq!(1, reader.try_recv().unwrap());
+        for stream in streams.iter().chain(Some(&late)).chain(Some(&reader)) {
+            assert_eq!(2, stream.try_recv().unwrap());
         }
     }
 
     #[test]
-    fn test_gooddrop() {
-        // This counts the # of drops and creations
-        let count = AtomicUsize::new(0);
-        {
-            let (writer, reader) = broadcast_queue(1);
-            for _ in 0..10 {
-                writer.try_send(Dropper::new(&count)).unwrap();
-                reader.recv().unwrap();
+    fn test_fixed_streams() {
+        let (writer, reader) = broadcast_queue_fixed::<usize, 4>(4);
+        let streams: Vec<_> = (0..3).map(|_| reader.add_stream()).collect();
+        assert_eq!(Err(StreamsFullError), reader.try_add_stream().map(|_| ()));
+        for i in 0..10 {
+            writer.try_send(i).unwrap();
+            assert_eq!(i, reader.try_recv().unwrap());
+            for stream in &streams {
+                assert_eq!(i, stream.try_recv().unwrap());
             }
         }
-        assert_eq!(count.load(Ordering::Relaxed), 0);
+        drop(streams);
+        assert_eq!(1, writer.stream_count());
+        // Removed streams don't hold the writer up, and their slots are handed
+        // out again once the sender and receiver have moved on from them
+        let mut streams: Vec<BroadcastReceiver<usize>> = Vec::new();
+        for i in 0..4 {
+            writer.try_send(i).unwrap();
+            assert_eq!(i, reader.try_recv().unwrap());
+            for stream in &streams {
+                assert_eq!(i, stream.try_recv().unwrap());
+            }
+            while let Ok(stream) = reader.try_add_stream() {
+                streams.push(stream);
+            }
+        }
+        assert_eq!(3, streams.len());
+        assert_eq!(4, writer.stream_count());
+        let full = catch_unwind(AssertUnwindSafe(|| reader.add_stream()));
+        assert!(full.is_err());
+        assert_eq!(4, writer.stream_count());
     }
 
-    #[test]
-    fn test_iterator_comp() {
-        let (writer, reader) = broadcast_queue::<usize>(10);
-        drop(writer);
-        for _ in reader {}
+    /// Takes a while to drop the copy sitting in the queue, so a writer
+    /// overwriting it is held up partway through a batch
+    #[derive(Debug, PartialEq)]
+    struct SlowDrop(usize, bool);
+
+    impl Clone for SlowDrop {
+        fn clone(&self) -> SlowDrop {
+            SlowDrop(self.0, false)
+        }
+    }
+
+    impl Drop for SlowDrop {
+        fn drop(&mut self) {
+            if self.1 {
+                sleep(Duration::from_micros(20));
+            }
+        }
     }
 
     #[test]
-    fn test_single_leave_multi() {
-        let (writer, reader) = broadcast_queue::<usize>(10);
-        let reader2 = reader.clone();
-        writer.try_send(1).unwrap();
-        writer.try_send(1).unwrap();
-        assert_eq!(reader2.recv().unwrap(), 1);
-        drop(reader2);
-        let reader_s = reader.into_single().unwrap();
-        assert!(reader_s.recv_view(|x| *x).is_ok());
+    fn test_send_transaction_visible_together() {
+        let num_loop = 2000;
+        let (writer, reader) = broadcast_queue::<SlowDrop>(4);
+        scope(|scope| {
+            scope.spawn(move |_| {
+                for i in 0..num_loop {
+                    let first = loop {
+                        match reader.try_recv() {
+                            Err(TryRecvError::Empty) => (),
+                            other => break other,
+                        }
+                    };
+                    assert_eq!(Ok(2 * i), first.map(|v| v.0));
+                    // Once the first is visible, the second is already there
+                    assert_eq!(Ok(2 * i + 1), reader.try_recv().map(|v| v.0));
+                }
+            });
+            for i in 0..num_loop {
+                let mut vals = vec![SlowDrop(2 * i, true), SlowDrop(2 * i + 1, true)];
+                while let Err(FrozenTrySendError::Full(v)) = writer.send_transaction(vals) {
+                    // The reader is gone if it failed
+                    if writer.stream_count() == 0 {
+                        return;
+                    }
+                    vals = v;
+                    yield_now();
+                }
+            }
+        })
+        .unwrap();
     }
 }
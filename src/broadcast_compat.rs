@@ -0,0 +1,489 @@
+//! Wrappers around the broadcast queue with the same methods and error types as
+//! ```tokio::sync::broadcast```, so that code written against it can switch over
+//! by changing an import.
+//!
+//! Every receiver is a bounded stream whose max lag is the capacity of the queue,
+//! so like tokio's channel a send never waits on slow receivers. They get skipped
+//! forwards instead and their next receive returns ```RecvError::Lagged```.
+//!
+//! There are two differences from tokio:
+//!
+//! * The queue holds the next power of two >= capacity values
+//! * ```Sender``` is ```Send``` but not ```Sync```, so clone it for each task
+//!   instead of sharing one behind an ```Arc```
+//!
+//! # Examples
+//!
+//! ```
+//! // use tokio::sync::broadcast::{self, error::TryRecvError};
+//! use multiqueue2::broadcast_compat::{self as broadcast, error::TryRecvError};
+//!
+//! let (tx, mut rx1) = broadcast::channel(2);
+//! let mut rx2 = tx.subscribe();
+//! for i in 0..3 {
+//!     tx.send(i).unwrap();
+//! }
+//! assert_eq!(Err(TryRecvError::Lagged(1)), rx1.try_recv());
+//! assert_eq!(Ok(1), rx1.try_recv());
+//! assert_eq!(Err(TryRecvError::Lagged(1)), rx2.try_recv());
+//! assert_eq!(2, rx2.len());
+//! ```
+
+use crate::countedindex::effective_capacity;
+use crate::error::{FrozenTrySendError, LaggedTryRecvError};
+use crate::multiqueue::{BCast, InnerRecv, InnerSend, MultiQueue};
+use crate::ordering::{RELAXED, SEQ_CST};
+use crate::sync::{fence, yield_now, AtomicUsize};
+use crate::wait::{BlockingWait, Wait};
+
+use std::fmt;
+use std::future::Future;
+use std::mem;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+
+use self::error::{RecvError, SendError, TryRecvError};
+
+/// The errors of ```tokio::sync::broadcast::error```
+pub mod error {
+    use crate::error::{LaggedRecvError, LaggedTryRecvError};
+
+    use std::error::Error;
+    use std::fmt;
+
+    /// The error returned by ```Sender::send``` when there are no receivers,
+    /// holding the value which couldn't be sent
+    #[derive(PartialEq, Eq, Clone, Copy)]
+    pub struct SendError<T>(pub T);
+
+    /// The error returned by ```Receiver::recv```
+    #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+    pub enum RecvError {
+        /// Every sender has been dropped and nothing is left to receive
+        Closed,
+        /// The receiver was skipped past this many values, and
+        /// the next receive returns the oldest one still held
+        Lagged(u64),
+    }
+
+    /// The error returned by ```Receiver::try_recv```
+    #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+    pub enum TryRecvError {
+        /// Nothing has been sent since the last value received
+        Empty,
+        /// Every sender has been dropped and nothing is left to receive
+        Closed,
+        /// The receiver was skipped past this many values, and
+        /// the next receive returns the oldest one still held
+        Lagged(u64),
+    }
+
+    impl From<LaggedRecvError> for RecvError {
+        fn from(err: LaggedRecvError) -> RecvError {
+            match err {
+                LaggedRecvError::Disconnected => RecvError::Closed,
+                LaggedRecvError::Lagged(n) => RecvError::Lagged(n),
+            }
+        }
+    }
+
+    impl From<LaggedTryRecvError> for TryRecvError {
+        fn from(err: LaggedTryRecvError) -> TryRecvError {
+            match err {
+                LaggedTryRecvError::Empty => TryRecvError::Empty,
+                LaggedTryRecvError::Disconnected => TryRecvError::Closed,
+                LaggedTryRecvError::Lagged(n) => TryRecvError::Lagged(n),
+            }
+        }
+    }
+
+    impl<T> fmt::Debug for SendError<T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("SendError").finish_non_exhaustive()
+        }
+    }
+
+    impl<T> fmt::Display for SendError<T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            "channel closed".fmt(f)
+        }
+    }
+
+    impl fmt::Display for RecvError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match *self {
+                RecvError::Closed => "channel closed".fmt(f),
+                RecvError::Lagged(n) => write!(f, "channel lagged by {}", n),
+            }
+        }
+    }
+
+    impl fmt::Display for TryRecvError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match *self {
+                TryRecvError::Empty => "channel empty".fmt(f),
+                TryRecvError::Closed => "channel closed".fmt(f),
+                TryRecvError::Lagged(n) => write!(f, "channel lagged by {}", n),
+            }
+        }
+    }
+
+    impl<T> Error for SendError<T> {}
+    impl Error for RecvError {}
+    impl Error for TryRecvError {}
+}
+
+/// Blocks threads the same way as ```BlockingWait```, and also
+/// wakes the tasks of receive futures which found the queue empty
+struct TaskWait {
+    blocking: BlockingWait,
+    wakers: parking_lot::Mutex<Vec<Waker>>,
+    // Lets writers skip the lock while no task is waiting
+    num_wakers: AtomicUsize,
+}
+
+impl TaskWait {
+    fn new() -> TaskWait {
+        TaskWait {
+            blocking: BlockingWait::new(),
+            wakers: parking_lot::Mutex::new(Vec::new()),
+            num_wakers: AtomicUsize::new(0),
+        }
+    }
+
+    /// Has the next notify wake the task. The queue has to be checked
+    /// again afterwards in case a value came in before this
+    fn register(&self, waker: &Waker) {
+        let mut wakers = self.wakers.lock();
+        if !wakers.iter().any(|w| w.will_wake(waker)) {
+            wakers.push(waker.clone());
+        }
+        self.num_wakers.store(wakers.len(), RELAXED);
+        // Pairs with the fence in notify, either the writer sees
+        // this task or the check after registering sees the value
+        fence(SEQ_CST);
+    }
+}
+
+impl Wait for TaskWait {
+    fn wait(&self, seq: usize, w_pos: &AtomicUsize, wc: &AtomicUsize) {
+        self.blocking.wait(seq, w_pos, wc)
+    }
+
+    fn notify(&self) {
+        self.blocking.notify();
+        fence(SEQ_CST);
+        if self.num_wakers.load(RELAXED) != 0 {
+            let woken = {
+                let mut wakers = self.wakers.lock();
+                self.num_wakers.store(0, RELAXED);
+                mem::take(&mut *wakers)
+            };
+            for waker in woken {
+                waker.wake();
+            }
+        }
+    }
+
+    fn needs_notify(&self) -> bool {
+        true
+    }
+
+    fn name(&self) -> &'static str {
+        "TaskWait"
+    }
+}
+
+/// The sending half of a queue made by ```channel```,
+/// like ```tokio::sync::broadcast::Sender```
+pub struct Sender<T: Clone> {
+    sender: InnerSend<BCast<T>, T>,
+    wait: Arc<TaskWait>,
+    capacity: usize,
+}
+
+/// A receiver made by ```channel``` or ```Sender::subscribe```,
+/// like ```tokio::sync::broadcast::Receiver```
+pub struct Receiver<T: Clone> {
+    receiver: InnerRecv<BCast<T>, T>,
+    wait: Arc<TaskWait>,
+    capacity: usize,
+}
+
+/// The future returned by ```Receiver::recv```
+pub struct Recv<'a, T: Clone> {
+    rx: &'a mut Receiver<T>,
+}
+
+impl<T: Clone> Sender<T> {
+    /// Sends a value to every receiver, returning how many there were.
+    /// This never waits on slow receivers, they get skipped forwards instead.
+    /// Fails with the value if there are no receivers
+    pub fn send(&self, value: T) -> Result<usize, SendError<T>> {
+        let mut value = value;
+        loop {
+            // Values can still be written for a while after the receivers are gone
+            let receivers = self.sender.stream_count();
+            if receivers == 0 {
+                return Err(SendError(value));
+            }
+            match self.sender.try_send(value) {
+                Ok(()) => return Ok(receivers),
+                // Only while a receiver is still reading the oldest slot, or the queue is frozen
+                Err(FrozenTrySendError::Full(v))
+                | Err(FrozenTrySendError::Disconnected(v))
+                | Err(FrozenTrySendError::Frozen(v)) => value = v,
+            }
+            yield_now();
+        }
+    }
+
+    /// Returns a new receiver, which gets every value sent after this call
+    pub fn subscribe(&self) -> Receiver<T> {
+        Receiver {
+            receiver: self.sender.subscribe_bounded(self.capacity),
+            wait: self.wait.clone(),
+            capacity: self.capacity,
+        }
+    }
+
+    /// Returns the number of values the slowest receiver has yet to receive
+    pub fn len(&self) -> usize {
+        self.sender.max_lag().min(self.capacity)
+    }
+
+    /// Returns whether every receiver has received every value
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of receivers
+    pub fn receiver_count(&self) -> usize {
+        self.sender.stream_count()
+    }
+}
+
+impl<T: Clone> Receiver<T> {
+    /// Receives the next value, waiting for one to be sent. If the receiver was
+    /// skipped forwards since the last receive, this returns ```Lagged``` with
+    /// the number of values missed instead
+    pub fn recv(&mut self) -> Recv<'_, T> {
+        Recv { rx: self }
+    }
+
+    /// Identical to ```recv```, except this blocks the thread instead of
+    /// returning a future, so it mustn't be called from async code
+    pub fn blocking_recv(&mut self) -> Result<T, RecvError> {
+        self.receiver.recv_bounded().map_err(RecvError::from)
+    }
+
+    /// Receives a value if one is waiting, without blocking
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        self.receiver.try_recv_bounded().map_err(TryRecvError::from)
+    }
+
+    /// Returns the number of values sent which this receiver has yet to receive.
+    /// Unlike tokio this never counts values the receiver was skipped past,
+    /// which the next receive reports as ```Lagged``` instead
+    pub fn len(&self) -> usize {
+        self.receiver.lag()
+    }
+
+    /// Returns whether this receiver has received every value sent
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns whether every sender has been dropped
+    pub fn is_closed(&self) -> bool {
+        self.receiver.writer_count() == 0
+    }
+
+    /// Returns a new receiver, which gets every value sent after this call
+    pub fn resubscribe(&self) -> Receiver<T> {
+        Receiver {
+            receiver: self.receiver.add_bounded_stream_from_latest(self.capacity),
+            wait: self.wait.clone(),
+            capacity: self.capacity,
+        }
+    }
+}
+
+impl<'a, T: Clone> Future for Recv<'a, T> {
+    type Output = Result<T, RecvError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<T, RecvError>> {
+        let rx = &mut *self.get_mut().rx;
+        match rx.receiver.try_recv_bounded() {
+            Err(LaggedTryRecvError::Empty) => (),
+            other => return Poll::Ready(other.map_err(recv_error)),
+        }
+        rx.wait.register(cx.waker());
+        match rx.receiver.try_recv_bounded() {
+            Err(LaggedTryRecvError::Empty) => Poll::Pending,
+            other => Poll::Ready(other.map_err(recv_error)),
+        }
+    }
+}
+
+/// Only called on errors other than ```Empty```
+fn recv_error(err: LaggedTryRecvError) -> RecvError {
+    match err {
+        LaggedTryRecvError::Lagged(n) => RecvError::Lagged(n),
+        _ => RecvError::Closed,
+    }
+}
+
+impl<T: Clone> Clone for Sender<T> {
+    fn clone(&self) -> Sender<T> {
+        Sender {
+            sender: self.sender.clone(),
+            wait: self.wait.clone(),
+            capacity: self.capacity,
+        }
+    }
+}
+
+impl<T: Clone> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sender").finish()
+    }
+}
+
+impl<T: Clone> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Receiver").finish()
+    }
+}
+
+unsafe impl<T: Send + Sync + Clone> Send for Sender<T> {}
+unsafe impl<T: Send + Sync + Clone> Send for Receiver<T> {}
+
+/// Creates a (```Sender```, ```Receiver```) pair like ```tokio::sync::broadcast::channel```,
+/// holding the next power of two >= capacity values
+///
+/// # Panics
+///
+/// Panics if capacity is zero, like tokio
+pub fn channel<T: Clone>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "Multiqueue error - capacity is empty");
+    let capacity = effective_capacity(capacity);
+    let wait = Arc::new(TaskWait::new());
+    let (send, recv) = MultiQueue::<BCast<T>, T>::create_tx_rx_with(capacity, wait.clone());
+    let sender = Sender {
+        sender: send,
+        wait,
+        capacity,
+    };
+    // The first stream isn't bounded, so it's swapped for one that is
+    let receiver = sender.subscribe();
+    drop(recv);
+    (sender, receiver)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+    use std::task::Wake;
+    use std::thread::{self, Thread};
+
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    /// Drives a future to completion on this thread, parking while it's pending
+    fn block_on<F: Future>(fut: F) -> F::Output {
+        let mut fut = Box::pin(fut);
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(v) = fut.as_mut().poll(&mut cx) {
+                return v;
+            }
+            thread::park();
+        }
+    }
+
+    #[test]
+    fn test_send_recv() {
+        let (tx, mut rx) = channel(4);
+        assert_eq!(Ok(1), tx.send(1));
+        assert_eq!(1, tx.len());
+        assert_eq!(Ok(1), rx.try_recv());
+        assert_eq!(Err(TryRecvError::Empty), rx.try_recv());
+        assert!(tx.is_empty() && rx.is_empty() && !rx.is_closed());
+        drop(tx);
+        assert!(rx.is_closed());
+        assert_eq!(Err(TryRecvError::Closed), rx.try_recv());
+        assert_eq!(Err(RecvError::Closed), rx.blocking_recv());
+    }
+
+    #[test]
+    fn test_no_receivers() {
+        let (tx, rx) = channel(4);
+        drop(rx);
+        assert_eq!(0, tx.receiver_count());
+        assert_eq!(Err(SendError(1)), tx.send(1));
+        let mut rx = tx.subscribe();
+        assert_eq!(Ok(1), tx.send(2));
+        assert_eq!(Ok(2), rx.try_recv());
+    }
+
+    #[test]
+    fn test_subscribe_from_latest() {
+        let (tx, mut rx) = channel(4);
+        tx.send(1).unwrap();
+        let mut rx2 = tx.subscribe();
+        let mut rx3 = rx.resubscribe();
+        assert_eq!(3, tx.receiver_count());
+        tx.send(2).unwrap();
+        assert_eq!(Ok(1), rx.try_recv());
+        assert_eq!(Ok(2), rx.try_recv());
+        assert_eq!(Ok(2), rx2.try_recv());
+        assert_eq!(Ok(2), rx3.try_recv());
+    }
+
+    #[test]
+    fn test_lagged() {
+        let (tx, mut rx) = channel(2);
+        for i in 0..5 {
+            assert_eq!(Ok(1), tx.send(i));
+        }
+        assert_eq!(2, rx.len());
+        assert_eq!(2, tx.len());
+        assert_eq!(Err(RecvError::Lagged(3)), block_on(rx.recv()));
+        assert_eq!(Ok(3), block_on(rx.recv()));
+        assert_eq!(Ok(4), rx.blocking_recv());
+        assert!(rx.is_empty());
+    }
+
+    #[test]
+    fn test_recv_wakes_task() {
+        let (tx, mut rx) = channel(16);
+        let got = Arc::new(Mutex::new(Vec::new()));
+        let gref = got.clone();
+        let t = thread::spawn(move || {
+            while let Ok(v) = block_on(rx.recv()) {
+                gref.lock().unwrap().push(v);
+            }
+        });
+        for i in 0..10000 {
+            // Stays within the capacity so nothing lags
+            while tx.len() > 8 {
+                thread::yield_now();
+            }
+            tx.send(i).unwrap();
+        }
+        drop(tx);
+        t.join().unwrap();
+        assert_eq!((0..10000).collect::<Vec<_>>(), *got.lock().unwrap());
+    }
+}
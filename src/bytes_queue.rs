@@ -0,0 +1,211 @@
+//! A broadcast queue of ```bytes::Bytes```, for fanning frames out to many streams without copying them
+
+use crate::broadcast::{broadcast_queue, BroadcastReceiver, BroadcastSender};
+use crate::error::FrozenTrySendError;
+
+extern crate bytes;
+use self::bytes::{Bytes, BytesMut};
+
+use std::cell::RefCell;
+
+/// How many bytes a sender copies slices into before it needs a new block
+pub const DEFAULT_BLOCK_SIZE: usize = 64 * 1024;
+
+/// This is the sending half of a broadcast queue of ```Bytes```. Broadcast readers
+/// clone each value they receive, which for ```Bytes``` only bumps a reference count,
+/// so every stream shares the one copy of a frame.
+///
+/// Slices sent with ```try_send_slice``` are copied into a block owned by the sender
+/// and cut off of it. Once every frame cut from a block has been dropped by the
+/// readers, the sender copies into the same block again instead of allocating.
+///
+/// # Examples
+///
+/// ```
+/// use multiqueue2::broadcast_bytes_queue;
+///
+/// let (w, r) = broadcast_bytes_queue(10);
+/// let r2 = r.add_stream();
+/// w.try_send_slice(b"hello").unwrap();
+/// let (a, b) = (r.try_recv().unwrap(), r2.try_recv().unwrap());
+/// assert_eq!(&b"hello"[..], &a[..]);
+/// // Both streams see the same memory
+/// assert_eq!(a.as_ptr(), b.as_ptr());
+/// ```
+pub struct BroadcastBytesSender {
+    sender: BroadcastSender<Bytes>,
+    block: RefCell<BytesMut>,
+    block_size: usize,
+}
+
+impl BroadcastBytesSender {
+    /// Tries to send a frame into the queue.
+    /// If there is no space or all readers have been disconnected,
+    /// returns the frame in the error.
+    #[inline(always)]
+    pub fn try_send(&self, val: Bytes) -> Result<(), FrozenTrySendError<Bytes>> {
+        self.sender.try_send(val)
+    }
+
+    /// Copies data into this sender's block and tries to send it.
+    /// The copy has already been made when this fails, so the error
+    /// holds a ```Bytes``` which can be passed to ```try_send``` later
+    pub fn try_send_slice(&self, data: &[u8]) -> Result<(), FrozenTrySendError<Bytes>> {
+        self.sender.try_send(self.copy_in(data))
+    }
+
+    fn copy_in(&self, data: &[u8]) -> Bytes {
+        let mut block = self.block.borrow_mut();
+        if block.capacity() < data.len() {
+            // This takes back the whole block if the readers are done with
+            // everything cut from it, and otherwise leaves it to them
+            block.reserve(data.len().max(self.block_size));
+        }
+        block.extend_from_slice(data);
+        block.split().freeze()
+    }
+
+    /// Returns how far behind the writers the slowest stream is
+    pub fn max_lag(&self) -> usize {
+        self.sender.max_lag()
+    }
+
+    /// Returns the number of streams currently on the queue
+    pub fn stream_count(&self) -> usize {
+        self.sender.stream_count()
+    }
+
+    /// Returns the number of writers currently subscribed to the queue
+    pub fn writer_count(&self) -> usize {
+        self.sender.writer_count()
+    }
+
+    /// Removes this writer from the queue
+    pub fn unsubscribe(self) {
+        self.sender.unsubscribe()
+    }
+}
+
+/// Each sender copies into its own block, so clones start out with an empty one
+impl Clone for BroadcastBytesSender {
+    fn clone(&self) -> BroadcastBytesSender {
+        BroadcastBytesSender {
+            sender: self.sender.clone(),
+            block: RefCell::new(BytesMut::new()),
+            block_size: self.block_size,
+        }
+    }
+}
+
+/// Creates a (```BroadcastBytesSender```, ```BroadcastReceiver<Bytes>```) pair with a
+/// capacity that's the next power of two >= the given capacity, whose sender copies
+/// slices into blocks of ```DEFAULT_BLOCK_SIZE``` bytes
+///
+/// # Examples
+///
+/// ```
+/// use multiqueue2::broadcast_bytes_queue;
+/// let (w, r) = broadcast_bytes_queue(10);
+/// w.try_send(bytes::Bytes::from_static(b"frame")).unwrap();
+/// assert_eq!(&b"frame"[..], &r.try_recv().unwrap()[..]);
+/// ```
+pub fn broadcast_bytes_queue(capacity: usize) -> (BroadcastBytesSender, BroadcastReceiver<Bytes>) {
+    broadcast_bytes_queue_with(capacity, DEFAULT_BLOCK_SIZE)
+}
+
+/// Creates a (```BroadcastBytesSender```, ```BroadcastReceiver<Bytes>```) pair whose
+/// sender copies slices into blocks of block_size bytes. Slices larger than
+/// that get a block of their own
+pub fn broadcast_bytes_queue_with(
+    capacity: usize,
+    block_size: usize,
+) -> (BroadcastBytesSender, BroadcastReceiver<Bytes>) {
+    let (send, recv) = broadcast_queue(capacity);
+    (
+        BroadcastBytesSender {
+            sender: send,
+            block: RefCell::new(BytesMut::new()),
+            block_size,
+        },
+        recv,
+    )
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    extern crate crossbeam;
+    use self::crossbeam::scope;
+
+    use std::thread::yield_now;
+
+    #[test]
+    fn test_block_reused() {
+        let (writer, reader) = broadcast_bytes_queue_with(4, 16);
+        writer.try_send_slice(&[1; 8]).unwrap();
+        let first = reader.try_recv().unwrap();
+        let start = first.as_ptr();
+        writer.try_send_slice(&[2; 8]).unwrap();
+        assert_eq!(unsafe { start.add(8) }, reader.try_recv().unwrap().as_ptr());
+        // The block is full and a frame from it is still held
+        writer.try_send_slice(&[3; 8]).unwrap();
+        let second_block = reader.try_recv().unwrap().as_ptr();
+        assert!(second_block != start);
+        assert_eq!(&[1; 8][..], &first[..]);
+        // The queue still holds its clones of the frames, so push them out
+        for _ in 0..4 {
+            writer.try_send(Bytes::new()).unwrap();
+            reader.try_recv().unwrap();
+        }
+        // Nothing from the second block is held any more, so it's taken back
+        writer.try_send_slice(&[4; 16]).unwrap();
+        let fourth = reader.try_recv().unwrap();
+        assert_eq!((&[4; 16][..], second_block), (&fourth[..], fourth.as_ptr()));
+        assert_eq!(&[1; 8][..], &first[..]);
+    }
+
+    #[test]
+    fn test_failed_send_returns_copy() {
+        let (writer, reader) = broadcast_bytes_queue(1);
+        writer.try_send_slice(b"first").unwrap();
+        let held = match writer.try_send_slice(b"second") {
+            Err(FrozenTrySendError::Full(val)) => val,
+            _ => panic!("Queue should be full"),
+        };
+        assert_eq!(&b"second"[..], &held[..]);
+        assert_eq!(&b"first"[..], &reader.try_recv().unwrap()[..]);
+        writer.try_send(held).unwrap();
+        assert_eq!(&b"second"[..], &reader.try_recv().unwrap()[..]);
+    }
+
+    #[test]
+    fn test_bytes_threaded() {
+        let (writer, reader) = broadcast_bytes_queue_with(8, 256);
+        let num_loop = 10000;
+        scope(|scope| {
+            for _ in 0..2 {
+                let stream = reader.add_stream().into_single().unwrap();
+                scope.spawn(move |_| {
+                    let mut next = 0u32;
+                    for val in stream {
+                        assert_eq!(&next.to_le_bytes()[..], &val[..]);
+                        next += 1;
+                    }
+                    assert_eq!(num_loop, next);
+                });
+            }
+            reader.unsubscribe();
+            for i in 0..num_loop {
+                let mut val = writer.try_send_slice(&i.to_le_bytes());
+                while let Err(FrozenTrySendError::Full(v)) = val {
+                    yield_now();
+                    val = writer.try_send(v);
+                }
+            }
+            writer.unsubscribe();
+        })
+        .unwrap();
+    }
+}
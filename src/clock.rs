@@ -0,0 +1,103 @@
+//! Where queues read the time from for delays, time to live, latency and stalls
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The source of time for a queue's timed features: the deadlines of
+/// ```try_send_after```, the time to live of ```try_send_with_ttl```, the latency
+/// of ```recv_with_latency```, when ```iter_timeout``` and ```iter_until``` stop
+/// and how long ```stalled_streams``` thinks a stream has been stuck. Queues use
+/// ```MonotonicClock``` unless they're created with a clock of their own, such as
+/// with ```broadcast_queue_with_clock```.
+///
+/// The time returned must never go backwards. Blocking receives which are waiting
+/// on a deadline check the clock again at least once a millisecond, since nothing
+/// wakes them when it passes.
+///
+/// # Examples
+///
+/// ```
+/// use multiqueue2::{mpmc_queue_with_clock, MockClock};
+/// use std::sync::Arc;
+/// use std::time::Duration;
+///
+/// let clock = Arc::new(MockClock::new());
+/// let (w, r) = mpmc_queue_with_clock(10, clock.clone());
+/// w.try_send_with_ttl(1, Duration::from_secs(5)).unwrap();
+/// w.try_send_with_ttl(2, Duration::from_secs(60)).unwrap();
+/// clock.advance(Duration::from_secs(10));
+/// assert_eq!(2, r.try_recv().unwrap());
+/// ```
+pub trait Clock: Send + Sync {
+    /// Returns the current time
+    fn now(&self) -> Instant;
+}
+
+/// The clock queues use by default, which reads ```Instant::now```
+#[derive(Copy, Clone, Debug, Default)]
+pub struct MonotonicClock;
+
+/// A clock which only moves when it's told to, for testing timeouts without sleeping.
+/// It starts at the time it was created
+#[derive(Debug)]
+pub struct MockClock {
+    start: Instant,
+    nanos: AtomicU64,
+}
+
+impl Clock for MonotonicClock {
+    #[inline(always)]
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+impl<C: Clock + ?Sized> Clock for Arc<C> {
+    #[inline(always)]
+    fn now(&self) -> Instant {
+        (**self).now()
+    }
+}
+
+impl MockClock {
+    pub fn new() -> MockClock {
+        MockClock {
+            start: Instant::now(),
+            nanos: AtomicU64::new(0),
+        }
+    }
+
+    /// Moves the clock forwards by the passed duration
+    pub fn advance(&self, by: Duration) {
+        self.nanos.fetch_add(by.as_nanos() as u64, Ordering::SeqCst);
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> MockClock {
+        MockClock::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.start + Duration::from_nanos(self.nanos.load(Ordering::SeqCst))
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_mock_clock() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        assert_eq!(start, clock.now());
+        clock.advance(Duration::from_millis(3));
+        clock.advance(Duration::from_millis(4));
+        assert_eq!(Duration::from_millis(7), clock.now() - start);
+    }
+}
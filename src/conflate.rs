@@ -0,0 +1,86 @@
+//! Support for conflating queues, where a newer value replaces an older one
+//! with the same key which hasn't been read yet.
+
+use crate::countedindex::{effective_capacity, past};
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::mpsc::TrySendError;
+
+extern crate parking_lot;
+
+/// Decides which queued value, if any, a newly sent value replaces
+pub trait Conflate<T>: Send + Sync {
+    /// Sends the value with the passed function, which returns the sequence number
+    /// it was written at. Returns the sequence number of the value it replaces
+    fn send(
+        &self,
+        val: T,
+        send: &mut dyn FnMut(T) -> Result<usize, TrySendError<T>>,
+    ) -> Result<Option<usize>, TrySendError<T>>;
+}
+
+/// Conflates values which map to the same key. The table of keys is held
+/// while writing so that concurrent writers agree on which value is the newest.
+/// Keys whose value has been written over are dropped from the table once it
+/// grows to twice the slots in the queue, so it never holds many more keys than that
+pub struct KeyConflator<K, F> {
+    key: F,
+    slots: usize,
+    latest: parking_lot::Mutex<HashMap<K, usize>>,
+}
+
+impl<K, F> KeyConflator<K, F> {
+    pub fn new(capacity: usize, key: F) -> KeyConflator<K, F> {
+        KeyConflator {
+            key,
+            slots: effective_capacity(capacity),
+            latest: parking_lot::Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<T, K, F> Conflate<T> for KeyConflator<K, F>
+where
+    K: Hash + Eq + Send,
+    F: Fn(&T) -> K + Send + Sync,
+{
+    fn send(
+        &self,
+        val: T,
+        send: &mut dyn FnMut(T) -> Result<usize, TrySendError<T>>,
+    ) -> Result<Option<usize>, TrySendError<T>> {
+        let key = (self.key)(&val);
+        let mut latest = self.latest.lock();
+        let seq = send(val)?;
+        let replaced = latest.insert(key, seq);
+        if latest.len() > 2 * self.slots {
+            // A value sent a queue's worth of slots before this one has been written over,
+            // so there's nothing left to replace
+            latest.retain(|_, sent| past(seq, *sent).0 < self.slots);
+        }
+        Ok(replaced)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{Conflate, KeyConflator};
+
+    #[test]
+    fn test_forgets_overwritten_keys() {
+        let conflator = KeyConflator::new(4, |v: &usize| *v);
+        let mut next = 0;
+        let mut send = |_| {
+            next += 1;
+            Ok(next - 1)
+        };
+        for key in 0..1000 {
+            assert_eq!(None, conflator.send(key, &mut send).unwrap());
+            assert!(conflator.latest.lock().len() <= 8);
+        }
+        // The keys still in the queue are replaced as usual
+        assert_eq!(Some(999), conflator.send(999, &mut send).unwrap());
+    }
+}
@@ -3,26 +3,19 @@ use std::sync::atomic::Ordering;
 // f = load_consume(...); *a[f - f]; that isn't actually consume
 // This project uses it exclusively for things like b = *a, c = *b
 
-#[cfg(any(
-    target_arch = "x64",
-    target_arch = "x64_64",
-    target_arch = "aarch64",
-    target_arch = "arm"
-))]
+// Loom has no consume ordering to model, so it gets acquire on every target
+#[cfg(all(any(target_arch = "aarch64", target_arch = "arm"), not(loom)))]
 mod can_consume {
+    use crate::ordering::RELAXED;
     use std::sync::atomic::Ordering;
-    pub const CONSUME: Ordering = Ordering::Relaxed;
+    pub const CONSUME: Ordering = RELAXED;
 }
 
-#[cfg(not(any(
-    target_arch = "x64",
-    target_arch = "x64_64",
-    target_arch = "aarch64",
-    target_arch = "arm"
-)))]
+#[cfg(not(all(any(target_arch = "aarch64", target_arch = "arm"), not(loom))))]
 mod can_consume {
+    use crate::ordering::ACQUIRE;
     use std::sync::atomic::Ordering;
-    pub const CONSUME: Ordering = Ordering::Acquire;
+    pub const CONSUME: Ordering = ACQUIRE;
 }
 
 pub const CONSUME: Ordering = can_consume::CONSUME;
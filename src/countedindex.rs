@@ -1,4 +1,8 @@
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::convert::TryFrom;
+use std::sync::atomic::Ordering;
+
+use crate::ordering::RELAXED;
+use crate::sync::AtomicUsize;
 
 #[cfg(target_pointer_width = "32")]
 mod index_data {
@@ -9,7 +13,6 @@ mod index_data {
     pub const MAX_WRAP: Index = (1 << 30) - 1;
 
     pub const MASK_IND: Index = (1 << 31);
-
 }
 
 #[cfg(target_pointer_width = "64")]
@@ -55,6 +58,29 @@ pub fn get_valid_wrap(val: Index) -> Index {
     }
 }
 
+/// Converts a capacity from the public API into an Index, checking it's small
+/// enough for the counted indices to tell a full queue from an empty one
+pub fn capacity_index(capacity: usize) -> Index {
+    assert!(
+        capacity <= MAX_WRAP as usize,
+        "Multiqueue error - too large size received"
+    );
+    capacity as Index
+}
+
+/// Converts a capacity passed the way older versions took it
+pub fn capacity_from_u64(capacity: u64) -> usize {
+    match usize::try_from(capacity) {
+        Ok(capacity) => capacity,
+        Err(_) => panic!("Multiqueue error - too large size received"),
+    }
+}
+
+/// Returns how many values a queue made with the passed capacity actually holds
+pub fn effective_capacity(capacity: usize) -> usize {
+    get_valid_wrap(capacity_index(capacity)) as usize
+}
+
 fn validate_wrap(val: Index) {
     assert!(
         val.is_power_of_two(),
@@ -68,7 +94,7 @@ fn validate_wrap(val: Index) {
 }
 
 // A queue entry will never ever have this value as an initial valid flag
-pub const INITIAL_QUEUE_FLAG: usize = ::std::usize::MAX;
+pub const INITIAL_QUEUE_FLAG: usize = usize::MAX;
 
 pub struct CountedIndex {
     val: AtomicUsize,
@@ -121,7 +147,23 @@ impl CountedIndex {
     }
 
     #[inline(always)]
-    pub fn load_transaction(&self, ord: Ordering) -> Transaction {
+    pub fn store_raw(&self, val: usize, ord: Ordering) {
+        self.val.store(rm_tag(val), ord)
+    }
+
+    #[inline(always)]
+    pub fn compare_exchange_raw(
+        &self,
+        current: usize,
+        new: usize,
+        ord: Ordering,
+    ) -> Result<usize, usize> {
+        self.val
+            .compare_exchange(current, rm_tag(new), ord, RELAXED)
+    }
+
+    #[inline(always)]
+    pub fn load_transaction(&self, ord: Ordering) -> Transaction<'_> {
         Transaction {
             ptr: &self.val,
             loaded_vals: self.val.load(ord),
@@ -143,11 +185,19 @@ impl<'a> Transaction<'a> {
         ((self.loaded_vals & self.mask) as isize, self.loaded_vals)
     }
 
-    /// Returns true if the values passed in matches the previous wrap-around of the Transaction
+    /// Returns true if the transaction is at least one wrap-around past the value passed in.
+    /// Values ahead of the transaction are never considered to be a wrap behind it
     #[inline(always)]
-    pub fn matches_previous(&self, val: usize) -> bool {
+    pub fn wrapped_past(&self, val: usize) -> bool {
+        self.wrapped_past_by(0, val)
+    }
+
+    /// Identical to wrapped_past, for the index by past the transaction
+    #[inline(always)]
+    pub fn wrapped_past_by(&self, by: usize, val: usize) -> bool {
         let wrap = self.mask.wrapping_add(1);
-        rm_tag(self.loaded_vals.wrapping_sub(wrap)) == val
+        let diff = rm_tag(self.loaded_vals.wrapping_add(by).wrapping_sub(val));
+        diff >= wrap && diff <= MAX_WRAP as usize
     }
 
     #[inline(always)]
@@ -188,6 +238,8 @@ unsafe impl Send for CountedIndex {}
 unsafe impl Sync for CountedIndex {}
 
 #[cfg(test)]
+// Some of the original tests predate these lints
+#[allow(clippy::legacy_numeric_constants, clippy::while_let_loop)]
 mod tests {
     use super::*;
 
@@ -277,4 +329,13 @@ mod tests {
         trans2.commit_direct(1, Relaxed);
         trans.commit(1, Relaxed).unwrap();
     }
+
+    #[test]
+    fn test_capacity() {
+        assert_eq!(1, effective_capacity(0));
+        assert_eq!(8, effective_capacity(5));
+        assert_eq!(8, effective_capacity(8));
+        assert_eq!(16, capacity_from_u64(16));
+        assert!(std::panic::catch_unwind(|| capacity_index(usize::MAX)).is_err());
+    }
 }
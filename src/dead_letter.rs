@@ -0,0 +1,146 @@
+//! Support for dead letters, where values a queue would otherwise drop
+//! without delivering are handed to a separate sink instead
+
+use crate::error::FrozenTrySendError;
+use crate::mpmc::{mpmc_queue, MPMCReceiver, MPMCSender};
+
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+extern crate parking_lot;
+
+/// Why a value ended up as a dead letter
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeadLetterReason {
+    /// The value outlived its time to live before anybody received it
+    Expired,
+    /// The value was delivered as many times as allowed without being acknowledged
+    TooManyDeliveries,
+    /// The value was still in the queue, or waiting to be delivered again,
+    /// when the queue was destroyed
+    Undeliverable,
+}
+
+/// A value which couldn't be delivered, along with why
+pub struct DeadLetter<T> {
+    pub reason: DeadLetterReason,
+    pub val: T,
+}
+
+/// Sends a dead letter on, handing it back if it couldn't be sent
+type Sink<T> = dyn Fn(DeadLetter<T>) -> Result<(), DeadLetter<T>> + Send + Sync;
+
+/// Where a queue sends the values it gives up on delivering. This is cheap
+/// to clone, and every clone sends to the same place.
+///
+/// Dead letters are sent from inside whichever call gave up on the value, which
+/// may be a receive, so the sink shouldn't block. If a dead letter can't be sent,
+/// as when the dead letter queue is full, it's dropped and counted in ```lost```.
+pub struct DeadLetterSender<T> {
+    sink: Arc<Sink<T>>,
+    lost: Arc<AtomicUsize>,
+}
+
+impl<T> DeadLetterSender<T> {
+    /// Creates a ```DeadLetterSender``` which passes every dead letter to the callback
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::{mpmc_queue_expiring_with_dead_letters, DeadLetterSender};
+    /// use std::sync::{Arc, Mutex};
+    /// use std::time::Duration;
+    ///
+    /// let expired = Arc::new(Mutex::new(Vec::new()));
+    /// let sink = expired.clone();
+    /// let dead_letters = DeadLetterSender::from_fn(move |letter| {
+    ///     sink.lock().unwrap().push(letter.val);
+    /// });
+    /// let (w, r) = mpmc_queue_expiring_with_dead_letters(4, dead_letters);
+    /// w.try_send_with_ttl(1, Duration::from_secs(0)).unwrap();
+    /// assert!(r.try_recv().is_err());
+    /// assert_eq!(vec![1], *expired.lock().unwrap());
+    /// ```
+    pub fn from_fn<F: Fn(DeadLetter<T>) + Send + Sync + 'static>(f: F) -> DeadLetterSender<T> {
+        DeadLetterSender {
+            sink: Arc::new(move |letter| {
+                f(letter);
+                Ok(())
+            }),
+            lost: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Returns how many dead letters couldn't be sent and were dropped
+    pub fn lost(&self) -> usize {
+        self.lost.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn send(&self, reason: DeadLetterReason, val: T) {
+        if (self.sink)(DeadLetter { reason, val }).is_err() {
+            self.lost.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl<T: Send + 'static> From<MPMCSender<DeadLetter<T>>> for DeadLetterSender<T> {
+    /// Sends dead letters into the queue with ```try_send```
+    fn from(sender: MPMCSender<DeadLetter<T>>) -> DeadLetterSender<T> {
+        let sender = parking_lot::Mutex::new(sender);
+        DeadLetterSender {
+            sink: Arc::new(move |letter| match sender.lock().try_send(letter) {
+                Ok(()) => Ok(()),
+                Err(FrozenTrySendError::Full(letter))
+                | Err(FrozenTrySendError::Disconnected(letter))
+                | Err(FrozenTrySendError::Frozen(letter)) => Err(letter),
+            }),
+            lost: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+}
+
+impl<T> Clone for DeadLetterSender<T> {
+    fn clone(&self) -> Self {
+        DeadLetterSender {
+            sink: self.sink.clone(),
+            lost: self.lost.clone(),
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for DeadLetter<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DeadLetter")
+            .field("reason", &self.reason)
+            .field("val", &self.val)
+            .finish()
+    }
+}
+
+/// Creates a mpmc queue for dead letters with the passed capacity, returning the
+/// ```DeadLetterSender``` to create other queues with and the receiver to read
+/// the dead letters from. The receiver disconnects once every queue created with
+/// the sender is gone.
+///
+/// # Examples
+///
+/// ```
+/// use multiqueue2::{dead_letter_queue, mpmc_queue_expiring_with_dead_letters, DeadLetterReason};
+/// use std::time::Duration;
+///
+/// let (dead_letters, dead) = dead_letter_queue(16);
+/// let (w, r) = mpmc_queue_expiring_with_dead_letters(4, dead_letters);
+/// w.try_send_with_ttl("stale", Duration::from_secs(0)).unwrap();
+/// w.try_send("fresh").unwrap();
+/// assert_eq!("fresh", r.try_recv().unwrap());
+///
+/// let letter = dead.try_recv().unwrap();
+/// assert_eq!((DeadLetterReason::Expired, "stale"), (letter.reason, letter.val));
+/// ```
+pub fn dead_letter_queue<T: Send + 'static>(
+    capacity: usize,
+) -> (DeadLetterSender<T>, MPMCReceiver<DeadLetter<T>>) {
+    let (send, recv) = mpmc_queue(capacity);
+    (DeadLetterSender::from(send), recv)
+}
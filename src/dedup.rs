@@ -0,0 +1,76 @@
+//! Support for deduplicating queues, where a value is dropped when
+//! one with the same id was sent recently
+
+use std::collections::{HashSet, VecDeque};
+use std::hash::Hash;
+use std::sync::mpsc::TrySendError;
+
+extern crate parking_lot;
+
+/// Decides whether a newly sent value duplicates one sent before it
+pub trait Dedup<T>: Send + Sync {
+    /// Sends the value with the passed function, which returns the sequence number
+    /// it was written at, unless it's a duplicate. Returns whether it was sent
+    fn send(
+        &self,
+        val: T,
+        send: &mut dyn FnMut(T) -> Result<usize, TrySendError<T>>,
+    ) -> Result<bool, TrySendError<T>>;
+}
+
+/// Drops values whose id is among the ids of the last window values sent.
+/// The window is held while writing so that concurrent writers sending the same
+/// id agree on which one goes in. Ids are kept exactly rather than in a
+/// probabilistic filter, so a value is never dropped for an id it doesn't share
+pub struct IdWindow<K, F> {
+    id: F,
+    window: usize,
+    seen: parking_lot::Mutex<Seen<K>>,
+}
+
+struct Seen<K> {
+    // The ids in the window from oldest to newest, and the same ids for lookups
+    order: VecDeque<K>,
+    ids: HashSet<K>,
+}
+
+impl<K: Hash + Eq, F> IdWindow<K, F> {
+    pub fn new(window: usize, id: F) -> IdWindow<K, F> {
+        assert!(window > 0, "Multiqueue error - zero dedup window received");
+        IdWindow {
+            id,
+            window,
+            seen: parking_lot::Mutex::new(Seen {
+                order: VecDeque::with_capacity(window),
+                ids: HashSet::with_capacity(window),
+            }),
+        }
+    }
+}
+
+impl<T, K, F> Dedup<T> for IdWindow<K, F>
+where
+    K: Hash + Eq + Clone + Send,
+    F: Fn(&T) -> K + Send + Sync,
+{
+    fn send(
+        &self,
+        val: T,
+        send: &mut dyn FnMut(T) -> Result<usize, TrySendError<T>>,
+    ) -> Result<bool, TrySendError<T>> {
+        let id = (self.id)(&val);
+        let mut seen = self.seen.lock();
+        if seen.ids.contains(&id) {
+            return Ok(false);
+        }
+        // A value which doesn't go in can be retried, so its id isn't remembered
+        send(val)?;
+        if seen.order.len() == self.window {
+            let oldest = seen.order.pop_front().unwrap();
+            seen.ids.remove(&oldest);
+        }
+        seen.order.push_back(id.clone());
+        seen.ids.insert(id);
+        Ok(true)
+    }
+}
@@ -0,0 +1,173 @@
+//! Errors returned by bounded streams, which writers skip forwards
+//! instead of waiting on once they fall too far behind, by attaching
+//! a detached stream again, by adding a stream to a queue with no room
+//! for another, and by sends, which tell a frozen queue apart from a full one.
+
+use std::error::Error;
+use std::fmt;
+use std::sync::mpsc::{RecvError, TryRecvError, TrySendError};
+
+/// The error returned by ```try_recv``` on a bounded stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LaggedTryRecvError {
+    /// There is currently no data in the stream
+    Empty,
+    /// All writers have disconnected and the stream is empty
+    Disconnected,
+    /// The stream fell behind and was skipped past this many items.
+    /// The next receive returns the oldest item still held for the stream
+    Lagged(u64),
+}
+
+/// The error returned by ```recv``` on a bounded stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LaggedRecvError {
+    /// All writers have disconnected and the stream is empty
+    Disconnected,
+    /// The stream fell behind and was skipped past this many items.
+    /// The next receive returns the oldest item still held for the stream
+    Lagged(u64),
+}
+
+/// The error returned when a detached stream can't be attached again
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttachError {
+    /// The stream was detached from a different queue
+    WrongQueue,
+    /// Writers have overwritten items the stream hadn't received yet
+    Overwritten,
+    /// The queue holds a fixed number of streams, and they're all taken
+    StreamsFull,
+}
+
+/// The error returned when a stream is added to a queue which holds
+/// a fixed number of streams, and they're all taken
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamsFullError;
+
+/// The error returned by the ```try_send``` family, which hands the value back
+/// and tells a frozen queue apart from a full one. Up to 0.1 these returned
+/// ```std::sync::mpsc::TrySendError```, which has the same ```Full``` and
+/// ```Disconnected``` variants
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum FrozenTrySendError<T> {
+    /// The queue is full
+    Full(T),
+    /// Every stream has unsubscribed from the queue
+    Disconnected(T),
+    /// The queue is frozen
+    Frozen(T),
+}
+
+impl<T> FrozenTrySendError<T> {
+    /// Changes the value handed back, keeping why the send failed
+    pub(crate) fn map<U, F: FnOnce(T) -> U>(self, f: F) -> FrozenTrySendError<U> {
+        match self {
+            FrozenTrySendError::Full(val) => FrozenTrySendError::Full(f(val)),
+            FrozenTrySendError::Disconnected(val) => FrozenTrySendError::Disconnected(f(val)),
+            FrozenTrySendError::Frozen(val) => FrozenTrySendError::Frozen(f(val)),
+        }
+    }
+}
+
+impl From<TryRecvError> for LaggedTryRecvError {
+    fn from(err: TryRecvError) -> LaggedTryRecvError {
+        match err {
+            TryRecvError::Empty => LaggedTryRecvError::Empty,
+            TryRecvError::Disconnected => LaggedTryRecvError::Disconnected,
+        }
+    }
+}
+
+impl From<RecvError> for LaggedRecvError {
+    fn from(_: RecvError) -> LaggedRecvError {
+        LaggedRecvError::Disconnected
+    }
+}
+
+impl<T> From<TrySendError<T>> for FrozenTrySendError<T> {
+    fn from(err: TrySendError<T>) -> FrozenTrySendError<T> {
+        match err {
+            TrySendError::Full(val) => FrozenTrySendError::Full(val),
+            TrySendError::Disconnected(val) => FrozenTrySendError::Disconnected(val),
+        }
+    }
+}
+
+impl fmt::Display for LaggedTryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LaggedTryRecvError::Empty => "receiving on an empty stream".fmt(f),
+            LaggedTryRecvError::Disconnected => "receiving on a closed stream".fmt(f),
+            LaggedTryRecvError::Lagged(n) => write!(f, "stream lagged behind by {} items", n),
+        }
+    }
+}
+
+impl fmt::Display for LaggedRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LaggedRecvError::Disconnected => "receiving on a closed stream".fmt(f),
+            LaggedRecvError::Lagged(n) => write!(f, "stream lagged behind by {} items", n),
+        }
+    }
+}
+
+impl fmt::Display for AttachError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AttachError::WrongQueue => "attaching a stream to a different queue".fmt(f),
+            AttachError::Overwritten => "attaching a stream whose items were overwritten".fmt(f),
+            AttachError::StreamsFull => "attaching a stream to a queue with no room for it".fmt(f),
+        }
+    }
+}
+
+impl fmt::Display for StreamsFullError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        "adding a stream to a queue with no room for it".fmt(f)
+    }
+}
+
+impl<T> From<FrozenTrySendError<T>> for TrySendError<T> {
+    /// Counts a frozen queue as full, for senders which keep to std's error
+    fn from(err: FrozenTrySendError<T>) -> TrySendError<T> {
+        match err {
+            FrozenTrySendError::Full(val) | FrozenTrySendError::Frozen(val) => {
+                TrySendError::Full(val)
+            }
+            FrozenTrySendError::Disconnected(val) => TrySendError::Disconnected(val),
+        }
+    }
+}
+
+impl<T> fmt::Debug for FrozenTrySendError<T> {
+    /// Leaves the value out like std's errors do, so it needn't be Debug
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FrozenTrySendError::Full(_) => "Full(..)".fmt(f),
+            FrozenTrySendError::Disconnected(_) => "Disconnected(..)".fmt(f),
+            FrozenTrySendError::Frozen(_) => "Frozen(..)".fmt(f),
+        }
+    }
+}
+
+impl<T> fmt::Display for FrozenTrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FrozenTrySendError::Full(_) => "sending on a full queue".fmt(f),
+            FrozenTrySendError::Disconnected(_) => "sending on a closed queue".fmt(f),
+            FrozenTrySendError::Frozen(_) => "sending on a frozen queue".fmt(f),
+        }
+    }
+}
+
+impl Error for LaggedTryRecvError {}
+
+impl Error for LaggedRecvError {}
+
+impl Error for AttachError {}
+
+impl Error for StreamsFullError {}
+
+impl<T> Error for FrozenTrySendError<T> {}
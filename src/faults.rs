@@ -0,0 +1,224 @@
+//! Faults which can be injected into a queue, so that code using it can be tested
+//! against things which are hard to provoke, like a queue that's full for no reason
+//! or readers that wake up late. Only built with the fault_injection feature.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread::sleep;
+use std::time::Duration;
+
+/// Which faults a queue injects, and how often. Each send or notification rolls
+/// against the rates, which go from 0.0, never, to 1.0, every time. Queues inject
+/// nothing until they're given a config with ```inject_faults```, and
+/// ```FaultConfig::default()``` turns injection back off.
+///
+/// # Examples
+///
+/// ```
+/// use multiqueue2::{mpmc_queue, FaultConfig, FrozenTrySendError};
+///
+/// let (w, r) = mpmc_queue(16);
+/// w.inject_faults(FaultConfig {
+///     full_rate: 0.5,
+///     seed: 7,
+///     ..FaultConfig::default()
+/// });
+/// let mut full = 0;
+/// for i in 0..10 {
+///     let mut val = w.try_send(i);
+///     while let Err(FrozenTrySendError::Full(v)) = val {
+///         full += 1;
+///         val = w.try_send(v);
+///     }
+/// }
+/// assert!(full > 0);
+/// assert_eq!((0..10).collect::<Vec<_>>(), r.try_iter().collect::<Vec<_>>());
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FaultConfig {
+    /// How often a send fails with ```Full``` even though there's room
+    pub full_rate: f64,
+    /// How often waking up readers after a send is held up by notify_delay
+    pub delay_notify_rate: f64,
+    /// How long delayed wakeups are held up for, on the sending thread
+    pub notify_delay: Duration,
+    /// Whether every send takes the path used when there are several writers,
+    /// even when there's only one
+    pub force_multi: bool,
+    /// Where the rolls start from, so that a single thread sees the same faults every run
+    pub seed: u64,
+}
+
+/// The faults a queue is currently injecting
+pub(crate) struct Faults {
+    // Rolls below these inject the fault, so zero never does
+    full_below: AtomicU64,
+    delay_below: AtomicU64,
+    delay_nanos: AtomicU64,
+    force_multi: AtomicBool,
+    rng: AtomicU64,
+}
+
+impl Default for FaultConfig {
+    fn default() -> FaultConfig {
+        FaultConfig {
+            full_rate: 0.0,
+            delay_notify_rate: 0.0,
+            notify_delay: Duration::from_millis(1),
+            force_multi: false,
+            seed: 0,
+        }
+    }
+}
+
+/// Converts a rate into what rolls have to be under
+fn threshold(rate: f64) -> u64 {
+    if rate <= 0.0 {
+        0
+    } else if rate >= 1.0 {
+        u64::MAX
+    } else {
+        (rate * u64::MAX as f64) as u64
+    }
+}
+
+impl Faults {
+    pub fn new() -> Faults {
+        Faults {
+            full_below: AtomicU64::new(0),
+            delay_below: AtomicU64::new(0),
+            delay_nanos: AtomicU64::new(0),
+            force_multi: AtomicBool::new(false),
+            rng: AtomicU64::new(0),
+        }
+    }
+
+    pub fn set(&self, config: FaultConfig) {
+        self.rng.store(config.seed, Ordering::Relaxed);
+        self.delay_nanos
+            .store(config.notify_delay.as_nanos() as u64, Ordering::Relaxed);
+        self.force_multi
+            .store(config.force_multi, Ordering::Relaxed);
+        self.full_below
+            .store(threshold(config.full_rate), Ordering::Relaxed);
+        self.delay_below
+            .store(threshold(config.delay_notify_rate), Ordering::Relaxed);
+    }
+
+    /// Returns whether this send should fail as if the queue were full
+    #[inline(always)]
+    pub fn fail_send(&self) -> bool {
+        self.roll(&self.full_below)
+    }
+
+    #[inline(always)]
+    pub fn force_multi(&self) -> bool {
+        self.force_multi.load(Ordering::Relaxed)
+    }
+
+    /// Holds up the calling thread if this notification should be delayed
+    #[inline(always)]
+    pub fn delay_notify(&self) {
+        if self.roll(&self.delay_below) {
+            sleep(Duration::from_nanos(
+                self.delay_nanos.load(Ordering::Relaxed),
+            ));
+        }
+    }
+
+    #[inline(always)]
+    fn roll(&self, below: &AtomicU64) -> bool {
+        let below = below.load(Ordering::Relaxed);
+        below != 0 && self.next_rand() < below
+    }
+
+    // splitmix64, stepped with an add so that threads sharing the queue
+    // each get their own rolls without any lock
+    fn next_rand(&self) -> u64 {
+        let mut z = self
+            .rng
+            .fetch_add(0x9E37_79B9_7F4A_7C15, Ordering::Relaxed)
+            .wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::FaultConfig;
+    use crate::broadcast::broadcast_queue;
+    use crate::error::FrozenTrySendError;
+    use crate::mpmc::mpmc_queue;
+
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    fn count_full(seed: u64) -> Vec<bool> {
+        let (w, r) = mpmc_queue(64);
+        w.inject_faults(FaultConfig {
+            full_rate: 0.3,
+            seed,
+            ..FaultConfig::default()
+        });
+        let fails = (0..50).map(|i| w.try_send(i).is_err()).collect();
+        drop(r);
+        fails
+    }
+
+    #[test]
+    fn test_full_rate() {
+        let fails = count_full(3);
+        assert_eq!(fails, count_full(3));
+        let n = fails.iter().filter(|f| **f).count();
+        assert!(n > 0 && n < 50, "{} of 50 sends failed", n);
+
+        let (w, r) = broadcast_queue(4);
+        w.inject_faults(FaultConfig {
+            full_rate: 1.0,
+            ..FaultConfig::default()
+        });
+        assert_eq!(Err(FrozenTrySendError::Full(1)), w.try_send(1));
+        r.inject_faults(FaultConfig::default());
+        w.try_send(2).unwrap();
+        assert_eq!(2, r.try_recv().unwrap());
+    }
+
+    #[test]
+    fn test_force_multi() {
+        let (w, r) = mpmc_queue(8);
+        w.inject_faults(FaultConfig {
+            force_multi: true,
+            ..FaultConfig::default()
+        });
+        let t = thread::spawn(move || {
+            for i in 0..1000 {
+                let mut val = w.try_send(i);
+                while let Err(FrozenTrySendError::Full(v)) = val {
+                    thread::yield_now();
+                    val = w.try_send(v);
+                }
+            }
+        });
+        assert_eq!(
+            (0..1000).collect::<Vec<_>>(),
+            r.into_iter().collect::<Vec<_>>()
+        );
+        t.join().unwrap();
+    }
+
+    #[test]
+    fn test_delay_notify() {
+        let (w, r) = broadcast_queue(4);
+        w.inject_faults(FaultConfig {
+            delay_notify_rate: 1.0,
+            notify_delay: Duration::from_millis(20),
+            ..FaultConfig::default()
+        });
+        let start = Instant::now();
+        w.try_send(1).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert_eq!(1, r.recv().unwrap());
+    }
+}
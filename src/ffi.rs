@@ -0,0 +1,403 @@
+//! A C interface to broadcast queues of byte payloads, for feeding the queue from
+//! (or draining it into) code on the other side of an ```extern "C"``` boundary.
+//!
+//! Everything here is plain functions over opaque handles, so a header can be
+//! generated with cbindgen using the ```cbindgen.toml``` at the root of the repository:
+//!
+//! ```text
+//! cbindgen --config cbindgen.toml --crate multiqueue2 --output multiqueue2.h
+//! ```
+//!
+//! Payloads are copied into a ```Box<[u8]>``` on the way in, and out of it on the way
+//! out, so C never sees memory owned by the queue. Each handle must be destroyed
+//! exactly once with its ```mq_*_destroy``` function, and a handle may only be used
+//! by one thread at a time, although it may be moved between threads.
+//!
+//! A Rust program which creates the queue itself can hand one end to C and keep the other:
+//!
+//! ```
+//! use multiqueue2::broadcast_queue;
+//! use multiqueue2::ffi::{mq_sender_destroy, mq_sender_try_send, MqResult, MqSender};
+//!
+//! let (send, recv) = broadcast_queue::<Box<[u8]>>(10);
+//! let raw = MqSender::into_raw(send);
+//!
+//! // This is what the C side would do with the handle
+//! let data = [1u8, 2, 3];
+//! unsafe {
+//!     assert_eq!(MqResult::Ok, mq_sender_try_send(raw, data.as_ptr(), data.len()));
+//!     mq_sender_destroy(raw);
+//! }
+//!
+//! assert_eq!(&[1, 2, 3][..], &*recv.try_recv().unwrap());
+//! ```
+
+use crate::broadcast::{broadcast_queue, BroadcastReceiver, BroadcastSender};
+use crate::error::FrozenTrySendError;
+
+use std::ptr;
+use std::slice;
+use std::sync::mpsc::TryRecvError;
+
+/// What is sent through queues made by this module
+pub type Payload = Box<[u8]>;
+
+/// The outcome of a call into this module
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MqResult {
+    /// The call succeeded
+    Ok = 0,
+    /// The queue had no space for the payload
+    Full = 1,
+    /// There was nothing waiting to be received
+    Empty = 2,
+    /// Every sender on the queue has been destroyed and nothing is left to receive
+    Disconnected = 3,
+    /// The buffer given was too small, the length needed has been written out
+    /// and the payload is kept for the next receive
+    BufferTooSmall = 4,
+    /// A handle or buffer was null where it may not be
+    NullPointer = 5,
+}
+
+/// An opaque handle to the sending half of a queue
+pub struct MqSender {
+    sender: BroadcastSender<Payload>,
+}
+
+/// An opaque handle to a consumer on one stream of a queue
+pub struct MqReceiver {
+    receiver: BroadcastReceiver<Payload>,
+    // A payload which did not fit the buffer it was received into
+    pending: Option<Payload>,
+}
+
+impl MqSender {
+    /// Moves a sender behind a handle which can be passed to C
+    pub fn into_raw(sender: BroadcastSender<Payload>) -> *mut MqSender {
+        Box::into_raw(Box::new(MqSender { sender }))
+    }
+
+    /// Takes a sender back out of a handle, which must not be used afterwards
+    ///
+    /// # Safety
+    ///
+    /// ```handle``` must have come from this module and not been destroyed
+    pub unsafe fn from_raw(handle: *mut MqSender) -> BroadcastSender<Payload> {
+        Box::from_raw(handle).sender
+    }
+}
+
+impl MqReceiver {
+    /// Moves a receiver behind a handle which can be passed to C
+    pub fn into_raw(receiver: BroadcastReceiver<Payload>) -> *mut MqReceiver {
+        Box::into_raw(Box::new(MqReceiver {
+            receiver,
+            pending: None,
+        }))
+    }
+
+    /// Takes a receiver back out of a handle, which must not be used afterwards.
+    /// A payload held back by ```MqResult::BufferTooSmall``` is dropped
+    ///
+    /// # Safety
+    ///
+    /// ```handle``` must have come from this module and not been destroyed
+    pub unsafe fn from_raw(handle: *mut MqReceiver) -> BroadcastReceiver<Payload> {
+        Box::from_raw(handle).receiver
+    }
+}
+
+/// Creates a broadcast queue of byte payloads, writing a handle to each end
+/// out through ```sender``` and ```receiver```
+///
+/// # Safety
+///
+/// ```sender``` and ```receiver``` must be valid to write a pointer to
+#[no_mangle]
+pub unsafe extern "C" fn mq_broadcast_new(
+    capacity: usize,
+    sender: *mut *mut MqSender,
+    receiver: *mut *mut MqReceiver,
+) -> MqResult {
+    if sender.is_null() || receiver.is_null() {
+        return MqResult::NullPointer;
+    }
+    let (send, recv) = broadcast_queue(capacity);
+    *sender = MqSender::into_raw(send);
+    *receiver = MqReceiver::into_raw(recv);
+    MqResult::Ok
+}
+
+/// Creates another sender on the same queue, or returns null if ```sender``` is null
+///
+/// # Safety
+///
+/// ```sender``` must be null or a live handle from this module
+#[no_mangle]
+pub unsafe extern "C" fn mq_sender_clone(sender: *const MqSender) -> *mut MqSender {
+    match sender.as_ref() {
+        Some(s) => MqSender::into_raw(s.sender.clone()),
+        None => ptr::null_mut(),
+    }
+}
+
+/// Copies ```len``` bytes from ```data``` and tries to send them without blocking.
+/// ```data``` may only be null when ```len``` is zero
+///
+/// # Safety
+///
+/// ```sender``` must be null or a live handle from this module,
+/// and ```data``` must be valid to read ```len``` bytes from
+#[no_mangle]
+pub unsafe extern "C" fn mq_sender_try_send(
+    sender: *const MqSender,
+    data: *const u8,
+    len: usize,
+) -> MqResult {
+    let sender = match sender.as_ref() {
+        Some(s) => s,
+        None => return MqResult::NullPointer,
+    };
+    let payload: Payload = if len == 0 {
+        Box::new([])
+    } else if data.is_null() {
+        return MqResult::NullPointer;
+    } else {
+        slice::from_raw_parts(data, len).into()
+    };
+    match sender.sender.try_send(payload) {
+        Ok(()) => MqResult::Ok,
+        // Nothing outside this module has a handle which could freeze the queue
+        Err(FrozenTrySendError::Full(_)) | Err(FrozenTrySendError::Frozen(_)) => MqResult::Full,
+        Err(FrozenTrySendError::Disconnected(_)) => MqResult::Disconnected,
+    }
+}
+
+/// Destroys a sender. Passing null does nothing
+///
+/// # Safety
+///
+/// ```sender``` must be null or a live handle from this module, and not be used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn mq_sender_destroy(sender: *mut MqSender) {
+    if !sender.is_null() {
+        drop(Box::from_raw(sender));
+    }
+}
+
+/// Creates another consumer on the same stream as ```receiver```,
+/// or returns null if ```receiver``` is null
+///
+/// # Safety
+///
+/// ```receiver``` must be null or a live handle from this module
+#[no_mangle]
+pub unsafe extern "C" fn mq_receiver_clone(receiver: *const MqReceiver) -> *mut MqReceiver {
+    match receiver.as_ref() {
+        Some(r) => MqReceiver::into_raw(r.receiver.clone()),
+        None => ptr::null_mut(),
+    }
+}
+
+/// Creates a consumer on a new stream of the queue, which sees every payload
+/// sent from now on. Returns null if ```receiver``` is null
+///
+/// # Safety
+///
+/// ```receiver``` must be null or a live handle from this module
+#[no_mangle]
+pub unsafe extern "C" fn mq_receiver_add_stream(receiver: *const MqReceiver) -> *mut MqReceiver {
+    match receiver.as_ref() {
+        Some(r) => MqReceiver::into_raw(r.receiver.add_stream()),
+        None => ptr::null_mut(),
+    }
+}
+
+/// Tries to receive a payload without blocking, copying it into ```buf```
+/// and writing its length to ```len```.
+///
+/// If the payload is longer than ```capacity```, its length is written to ```len```,
+/// ```MqResult::BufferTooSmall``` is returned and the payload is handed out by the
+/// next call on this handle instead, so it can be retried with a larger buffer
+///
+/// # Safety
+///
+/// ```receiver``` must be null or a live handle from this module, ```buf``` must be
+/// valid to write ```capacity``` bytes to and ```len``` must be valid to write to
+#[no_mangle]
+pub unsafe extern "C" fn mq_receiver_try_recv(
+    receiver: *mut MqReceiver,
+    buf: *mut u8,
+    capacity: usize,
+    len: *mut usize,
+) -> MqResult {
+    let receiver = match receiver.as_mut() {
+        Some(r) => r,
+        None => return MqResult::NullPointer,
+    };
+    if len.is_null() {
+        return MqResult::NullPointer;
+    }
+    let payload = match receiver.pending.take() {
+        Some(p) => p,
+        None => match receiver.receiver.try_recv() {
+            Ok(p) => p,
+            Err(TryRecvError::Empty) => return MqResult::Empty,
+            Err(TryRecvError::Disconnected) => return MqResult::Disconnected,
+        },
+    };
+    *len = payload.len();
+    if payload.len() > capacity {
+        receiver.pending = Some(payload);
+        return MqResult::BufferTooSmall;
+    }
+    if !payload.is_empty() {
+        if buf.is_null() {
+            receiver.pending = Some(payload);
+            return MqResult::NullPointer;
+        }
+        ptr::copy_nonoverlapping(payload.as_ptr(), buf, payload.len());
+    }
+    MqResult::Ok
+}
+
+/// Destroys a receiver. Passing null does nothing
+///
+/// # Safety
+///
+/// ```receiver``` must be null or a live handle from this module, and not be used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn mq_receiver_destroy(receiver: *mut MqReceiver) {
+    if !receiver.is_null() {
+        drop(Box::from_raw(receiver));
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    fn new_queue(capacity: usize) -> (*mut MqSender, *mut MqReceiver) {
+        let mut send = ptr::null_mut();
+        let mut recv = ptr::null_mut();
+        unsafe {
+            assert_eq!(
+                MqResult::Ok,
+                mq_broadcast_new(capacity, &mut send, &mut recv)
+            );
+        }
+        (send, recv)
+    }
+
+    unsafe fn recv_vec(recv: *mut MqReceiver) -> Result<Vec<u8>, MqResult> {
+        let mut buf = [0u8; 16];
+        let mut len = 0;
+        match mq_receiver_try_recv(recv, buf.as_mut_ptr(), buf.len(), &mut len) {
+            MqResult::Ok => Ok(buf[..len].to_vec()),
+            e => Err(e),
+        }
+    }
+
+    #[test]
+    fn test_send_recv() {
+        let (send, recv) = new_queue(2);
+        unsafe {
+            assert_eq!(Err(MqResult::Empty), recv_vec(recv));
+            assert_eq!(MqResult::Ok, mq_sender_try_send(send, b"abc".as_ptr(), 3));
+            assert_eq!(MqResult::Ok, mq_sender_try_send(send, ptr::null(), 0));
+            assert_eq!(MqResult::Full, mq_sender_try_send(send, b"d".as_ptr(), 1));
+            assert_eq!(Ok(b"abc".to_vec()), recv_vec(recv));
+            assert_eq!(Ok(vec![]), recv_vec(recv));
+            mq_sender_destroy(send);
+            assert_eq!(Err(MqResult::Disconnected), recv_vec(recv));
+            mq_receiver_destroy(recv);
+        }
+    }
+
+    #[test]
+    fn test_buffer_too_small() {
+        let (send, recv) = new_queue(4);
+        let data = [7u8; 10];
+        let mut buf = [0u8; 10];
+        let mut len = 0;
+        unsafe {
+            mq_sender_try_send(send, data.as_ptr(), data.len());
+            mq_sender_try_send(send, data.as_ptr(), 1);
+            assert_eq!(
+                MqResult::BufferTooSmall,
+                mq_receiver_try_recv(recv, buf.as_mut_ptr(), 4, &mut len)
+            );
+            assert_eq!(10, len);
+            assert_eq!(
+                MqResult::Ok,
+                mq_receiver_try_recv(recv, buf.as_mut_ptr(), 10, &mut len)
+            );
+            assert_eq!((data, 10), (buf, len));
+            assert_eq!(Ok(vec![7]), recv_vec(recv));
+            mq_sender_destroy(send);
+            mq_receiver_destroy(recv);
+        }
+    }
+
+    #[test]
+    fn test_streams_and_clones() {
+        let (send, recv) = new_queue(4);
+        unsafe {
+            let stream = mq_receiver_add_stream(recv);
+            let clone = mq_receiver_clone(recv);
+            let send2 = mq_sender_clone(send);
+            mq_sender_try_send(send, b"a".as_ptr(), 1);
+            mq_sender_try_send(send2, b"b".as_ptr(), 1);
+            assert_eq!(Ok(b"a".to_vec()), recv_vec(recv));
+            assert_eq!(Ok(b"b".to_vec()), recv_vec(clone));
+            assert_eq!(Ok(b"a".to_vec()), recv_vec(stream));
+            assert_eq!(Ok(b"b".to_vec()), recv_vec(stream));
+            mq_sender_destroy(send);
+            assert_eq!(Err(MqResult::Empty), recv_vec(stream));
+            mq_sender_destroy(send2);
+            assert_eq!(Err(MqResult::Disconnected), recv_vec(stream));
+            for handle in &[recv, clone, stream] {
+                mq_receiver_destroy(*handle);
+            }
+        }
+    }
+
+    #[test]
+    fn test_null_handles() {
+        let mut len = 0;
+        unsafe {
+            assert_eq!(
+                MqResult::NullPointer,
+                mq_broadcast_new(4, ptr::null_mut(), ptr::null_mut())
+            );
+            assert_eq!(
+                MqResult::NullPointer,
+                mq_sender_try_send(ptr::null(), ptr::null(), 0)
+            );
+            assert_eq!(
+                MqResult::NullPointer,
+                mq_receiver_try_recv(ptr::null_mut(), ptr::null_mut(), 0, &mut len)
+            );
+            assert!(mq_sender_clone(ptr::null()).is_null());
+            assert!(mq_receiver_add_stream(ptr::null()).is_null());
+            mq_sender_destroy(ptr::null_mut());
+            mq_receiver_destroy(ptr::null_mut());
+        }
+    }
+
+    #[test]
+    fn test_rust_end() {
+        let (send, recv) = broadcast_queue::<Payload>(4);
+        let raw = MqReceiver::into_raw(recv);
+        send.try_send(vec![1, 2].into_boxed_slice()).unwrap();
+        unsafe {
+            assert_eq!(Ok(vec![1, 2]), recv_vec(raw));
+            let recv = MqReceiver::from_raw(raw);
+            send.try_send(vec![3].into_boxed_slice()).unwrap();
+            assert_eq!(&[3][..], &*recv.try_recv().unwrap());
+        }
+    }
+}
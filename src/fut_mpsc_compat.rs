@@ -0,0 +1,584 @@
+//! Wrappers around the futures mpmc queue with the same methods, error types and
+//! backpressure as ```futures::channel::mpsc```, which is ```futures::sync::mpsc```
+//! in the futures 0.1 this crate is built on. Code written against it can switch
+//! over by changing an import.
+//!
+//! Like the futures channel, every ```Sender``` gets a slot of its own on top of
+//! the shared buffer, so a sender which was told it's ready can always send one
+//! value. Once it has used that slot, ```poll_ready``` parks it until the receiver
+//! has made room for the value in the buffer, which keeps a sender that's far
+//! ahead of the receiver from taking every slot.
+//!
+//! The differences from the futures channel are:
+//!
+//! * The buffer holds the next power of two >= buffer values, and at least one
+//! * There's no unbounded channel
+//! * The receiver spins and yields on an empty queue before parking,
+//!   like ```MPMCFutReceiver```
+//!
+//! # Examples
+//!
+//! ```
+//! extern crate futures;
+//! // use futures::sync::mpsc::channel;
+//! use multiqueue2::fut_mpsc_compat::channel;
+//! use futures::{Future, Sink, Stream};
+//! use std::thread;
+//!
+//! let (tx, rx) = channel(4);
+//! let t = thread::spawn(move || {
+//!     let tx = tx.send(1).wait().unwrap();
+//!     tx.send(2).wait().unwrap();
+//! });
+//! assert_eq!(vec![1, 2], rx.collect().wait().unwrap());
+//! t.join().unwrap();
+//! ```
+
+use crate::error::FrozenTrySendError;
+use crate::multiqueue::{futures_multiqueue, FutInnerRecv, FutInnerSend, MPMC};
+use crate::ordering::{RELAXED, SEQ_CST};
+use crate::sync::{fence, AtomicBool, AtomicUsize};
+
+extern crate futures;
+use self::futures::task::{current, Task};
+use self::futures::{Async, AsyncSink, Poll, Sink, StartSend, Stream};
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+
+/// The error returned when a value can't be sent
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SendError {
+    kind: SendErrorKind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SendErrorKind {
+    Full,
+    Disconnected,
+}
+
+/// The error returned by ```Sender::try_send```, holding the value which wasn't sent
+#[derive(Clone, PartialEq, Eq)]
+pub struct TrySendError<T> {
+    err: SendError,
+    val: T,
+}
+
+/// The error returned by ```Receiver::try_next``` when nothing is waiting
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TryRecvError {
+    _priv: (),
+}
+
+/// A sender's own slot, which holds a value while the buffer is full
+struct SenderSlot {
+    taken: AtomicBool,
+    task: parking_lot::Mutex<Option<Task>>,
+}
+
+/// What a channel's senders and receiver share besides the queue
+struct Shared<T> {
+    // Values sent into senders' own slots, oldest first
+    overflow: parking_lot::Mutex<VecDeque<(T, Arc<SenderSlot>)>>,
+    // Lets the receiver skip the lock while nothing is in there
+    num_overflow: AtomicUsize,
+    // The receiver keeps a writer on the queue to move values
+    // out of the slots, so the queue can't count the senders
+    senders: AtomicUsize,
+    closed: AtomicBool,
+}
+
+/// The sending half of a queue made by ```channel```,
+/// like ```futures::channel::mpsc::Sender```
+pub struct Sender<T> {
+    sender: Option<FutInnerSend<MPMC<T>, T>>,
+    slot: Arc<SenderSlot>,
+    shared: Arc<Shared<T>>,
+}
+
+/// The receiving half of a queue made by ```channel```,
+/// like ```futures::channel::mpsc::Receiver```
+pub struct Receiver<T> {
+    receiver: FutInnerRecv<MPMC<T>, T>,
+    mover: FutInnerSend<MPMC<T>, T>,
+    shared: Arc<Shared<T>>,
+}
+
+impl SendError {
+    /// Returns whether the value wasn't sent because the sender's slot was taken
+    pub fn is_full(&self) -> bool {
+        self.kind == SendErrorKind::Full
+    }
+
+    /// Returns whether the value wasn't sent because the channel was closed
+    pub fn is_disconnected(&self) -> bool {
+        self.kind == SendErrorKind::Disconnected
+    }
+}
+
+impl<T> TrySendError<T> {
+    /// Identical to ```SendError::is_full```
+    pub fn is_full(&self) -> bool {
+        self.err.is_full()
+    }
+
+    /// Identical to ```SendError::is_disconnected```
+    pub fn is_disconnected(&self) -> bool {
+        self.err.is_disconnected()
+    }
+
+    /// Returns the value which wasn't sent
+    pub fn into_inner(self) -> T {
+        self.val
+    }
+
+    /// Drops the value, leaving the reason it wasn't sent
+    pub fn into_send_error(self) -> SendError {
+        self.err
+    }
+}
+
+impl SenderSlot {
+    fn new() -> Arc<SenderSlot> {
+        Arc::new(SenderSlot {
+            taken: AtomicBool::new(false),
+            task: parking_lot::Mutex::new(None),
+        })
+    }
+
+    /// Frees the slot, waking its sender if it's parked
+    fn release(&self) {
+        self.taken.store(false, SEQ_CST);
+        self.wake();
+    }
+
+    fn wake(&self) {
+        if let Some(task) = self.task.lock().take() {
+            task.notify();
+        }
+    }
+}
+
+impl<T> Shared<T> {
+    fn is_closed(&self) -> bool {
+        self.closed.load(SEQ_CST) || self.senders.load(SEQ_CST) == 0
+    }
+
+    /// Wakes every sender parked on its slot, so they notice the channel closed
+    fn wake_senders(&self) {
+        for (_, slot) in self.overflow.lock().iter() {
+            slot.wake();
+        }
+    }
+}
+
+impl<T> Sender<T> {
+    /// Returns ready if a value can be sent, or parks the current task until one can.
+    /// Fails if the channel has been closed
+    pub fn poll_ready(&mut self) -> Poll<(), SendError> {
+        if self.is_closed() {
+            return Err(SendError {
+                kind: SendErrorKind::Disconnected,
+            });
+        }
+        if !self.slot.taken.load(SEQ_CST) {
+            return Ok(Async::Ready(()));
+        }
+        *self.slot.task.lock() = Some(current());
+        // The slot may have been freed before the task was stored
+        if self.slot.taken.load(SEQ_CST) && !self.is_closed() {
+            Ok(Async::NotReady)
+        } else {
+            self.poll_ready()
+        }
+    }
+
+    /// Sends a value without waiting, failing with it if this sender's
+    /// slot is taken or the channel has been closed
+    pub fn try_send(&mut self, msg: T) -> Result<(), TrySendError<T>> {
+        let kind = if self.is_closed() {
+            SendErrorKind::Disconnected
+        } else if self.slot.taken.load(SEQ_CST) {
+            SendErrorKind::Full
+        } else {
+            return self.send_unchecked(msg).map_err(|val| TrySendError {
+                err: SendError {
+                    kind: SendErrorKind::Disconnected,
+                },
+                val,
+            });
+        };
+        Err(TrySendError {
+            err: SendError { kind },
+            val: msg,
+        })
+    }
+
+    /// Sends a value after ```poll_ready``` has returned ready
+    pub fn start_send(&mut self, msg: T) -> Result<(), SendError> {
+        self.try_send(msg).map_err(TrySendError::into_send_error)
+    }
+
+    /// Puts msg in the buffer, or in this sender's slot if the buffer is full
+    fn send_unchecked(&mut self, msg: T) -> Result<(), T> {
+        let sender = match self.sender {
+            Some(ref sender) => sender,
+            None => return Err(msg),
+        };
+        let msg = match sender.try_send(msg) {
+            Ok(()) => return Ok(()),
+            Err(FrozenTrySendError::Full(msg))
+            | Err(FrozenTrySendError::Disconnected(msg))
+            | Err(FrozenTrySendError::Frozen(msg)) => msg,
+        };
+        if sender.stream_count() == 0 {
+            return Err(msg);
+        }
+        self.slot.taken.store(true, SEQ_CST);
+        {
+            let mut overflow = self.shared.overflow.lock();
+            overflow.push_back((msg, self.slot.clone()));
+            self.shared.num_overflow.store(overflow.len(), RELAXED);
+        }
+        // Pairs with the fence in Receiver::poll, either the receiver
+        // sees the value or this wakes it after it's parked
+        fence(SEQ_CST);
+        sender.notify_readers();
+        Ok(())
+    }
+
+    /// Returns whether the receiver has been dropped or closed,
+    /// or this sender has been disconnected
+    pub fn is_closed(&self) -> bool {
+        match self.sender {
+            Some(ref sender) => self.shared.closed.load(SEQ_CST) || sender.stream_count() == 0,
+            None => true,
+        }
+    }
+
+    /// Closes the channel for every sender. The receiver gets
+    /// what's already been sent and then the end of the stream
+    pub fn close_channel(&self) {
+        self.shared.closed.store(true, SEQ_CST);
+        self.shared.wake_senders();
+        if let Some(ref sender) = self.sender {
+            sender.notify_readers();
+        }
+    }
+
+    /// Disconnects this sender, closing the channel if it was the last one
+    pub fn disconnect(&mut self) {
+        if let Some(sender) = self.sender.take() {
+            self.shared.senders.fetch_sub(1, SEQ_CST);
+            // The receiver checks the count when it's woken
+            sender.notify_readers();
+        }
+    }
+
+    /// Returns whether both senders send to the same receiver
+    pub fn same_receiver(&self, other: &Sender<T>) -> bool {
+        Arc::ptr_eq(&self.shared, &other.shared)
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Closes the channel, so that nothing more can be sent.
+    /// What's already been sent can still be received
+    pub fn close(&mut self) {
+        self.shared.closed.store(true, SEQ_CST);
+        self.shared.wake_senders();
+    }
+
+    /// Receives a value without waiting. Returns ```Ok(None)``` once the
+    /// channel is closed and empty, and an error if it's only empty
+    pub fn try_next(&mut self) -> Result<Option<T>, TryRecvError> {
+        self.refill();
+        if let Ok(v) = self.receiver.try_recv() {
+            // Taking it made room for the oldest value in the slots
+            self.refill();
+            return Ok(Some(v));
+        }
+        if self.is_done() {
+            // Anything sent before the last sender left is in the queue by now
+            return Ok(self.receiver.try_recv().ok());
+        }
+        Err(TryRecvError { _priv: () })
+    }
+
+    /// Moves values out of the senders' slots while there's room in the buffer
+    fn refill(&self) {
+        if self.shared.num_overflow.load(RELAXED) == 0 {
+            return;
+        }
+        let mut overflow = self.shared.overflow.lock();
+        while let Some((msg, slot)) = overflow.pop_front() {
+            match self.mover.try_send(msg) {
+                Ok(()) => slot.release(),
+                Err(FrozenTrySendError::Full(msg))
+                | Err(FrozenTrySendError::Disconnected(msg))
+                | Err(FrozenTrySendError::Frozen(msg)) => {
+                    overflow.push_front((msg, slot));
+                    break;
+                }
+            }
+        }
+        self.shared.num_overflow.store(overflow.len(), RELAXED);
+    }
+
+    /// Returns whether nothing more can come in besides what's in the slots
+    fn is_done(&self) -> bool {
+        self.shared.is_closed() && self.shared.num_overflow.load(RELAXED) == 0
+    }
+}
+
+impl<T> Stream for Receiver<T> {
+    type Item = T;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Option<T>, ()> {
+        loop {
+            match self.try_next() {
+                Ok(v) => return Ok(Async::Ready(v)),
+                Err(_) => match (&self.receiver).poll() {
+                    Ok(Async::Ready(v)) => {
+                        self.refill();
+                        return Ok(Async::Ready(v));
+                    }
+                    _ => {
+                        // Pairs with the fence in Sender::send_unchecked
+                        fence(SEQ_CST);
+                        if self.shared.num_overflow.load(RELAXED) == 0 && !self.shared.is_closed() {
+                            return Ok(Async::NotReady);
+                        }
+                    }
+                },
+            }
+        }
+    }
+}
+
+impl<T> Sink for Sender<T> {
+    type SinkItem = T;
+    type SinkError = SendError;
+
+    fn start_send(&mut self, msg: T) -> StartSend<T, SendError> {
+        match self.poll_ready()? {
+            Async::Ready(()) => self
+                .try_send(msg)
+                .map(|()| AsyncSink::Ready)
+                .map_err(TrySendError::into_send_error),
+            Async::NotReady => Ok(AsyncSink::NotReady(msg)),
+        }
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), SendError> {
+        Ok(Async::Ready(()))
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    /// The new sender gets a slot of its own
+    fn clone(&self) -> Sender<T> {
+        if self.sender.is_some() {
+            self.shared.senders.fetch_add(1, SEQ_CST);
+        }
+        Sender {
+            sender: self.sender.clone(),
+            slot: SenderSlot::new(),
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.disconnect();
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_full() {
+            "send failed because channel is full".fmt(f)
+        } else {
+            "send failed because receiver is gone".fmt(f)
+        }
+    }
+}
+
+impl<T> fmt::Debug for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TrySendError")
+            .field("kind", &self.err.kind)
+            .finish()
+    }
+}
+
+impl<T> fmt::Display for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.err.fmt(f)
+    }
+}
+
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        "receiver channel is empty".fmt(f)
+    }
+}
+
+impl Error for SendError {}
+impl<T> Error for TrySendError<T> {}
+impl Error for TryRecvError {}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sender").finish()
+    }
+}
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Receiver").finish()
+    }
+}
+
+unsafe impl<T: Send> Send for Sender<T> {}
+unsafe impl<T: Send> Send for Receiver<T> {}
+
+/// Creates a (```Sender```, ```Receiver```) pair like ```futures::channel::mpsc::channel```,
+/// whose buffer holds the next power of two >= buffer values, and at least one.
+/// Each sender can hold one more value on top of that
+pub fn channel<T>(buffer: usize) -> (Sender<T>, Receiver<T>) {
+    let (send, recv) = futures_multiqueue::<MPMC<T>, T>(buffer);
+    let shared = Arc::new(Shared {
+        overflow: parking_lot::Mutex::new(VecDeque::new()),
+        num_overflow: AtomicUsize::new(0),
+        senders: AtomicUsize::new(1),
+        closed: AtomicBool::new(false),
+    });
+    let receiver = Receiver {
+        receiver: recv,
+        mover: send.clone(),
+        shared: shared.clone(),
+    };
+    let sender = Sender {
+        sender: Some(send),
+        slot: SenderSlot::new(),
+        shared,
+    };
+    (sender, receiver)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    use self::futures::future::lazy;
+    use self::futures::Future;
+
+    use std::thread;
+
+    #[test]
+    fn test_slot_per_sender() {
+        let (mut tx, mut rx) = channel(1);
+        tx.try_send(1).unwrap();
+        // The buffer is full, so this takes the sender's own slot
+        tx.try_send(2).unwrap();
+        assert!(tx.try_send(3).unwrap_err().is_full());
+        let mut tx2 = tx.clone();
+        tx2.try_send(3).unwrap();
+        assert!(tx2.try_send(4).unwrap_err().is_full());
+        assert_eq!(Ok(Some(1)), rx.try_next());
+        // Making room moved the oldest value in the slots into the buffer
+        tx.try_send(4).unwrap();
+        assert!(tx2.try_send(5).unwrap_err().is_full());
+        assert_eq!(Ok(Some(2)), rx.try_next());
+        assert_eq!(Ok(Some(3)), rx.try_next());
+        assert_eq!(Ok(Some(4)), rx.try_next());
+        assert!(rx.try_next().is_err());
+    }
+
+    #[test]
+    fn test_poll_ready_parks() {
+        let (mut tx, mut rx) = channel(1);
+        tx.try_send(1).unwrap();
+        tx.try_send(2).unwrap();
+        lazy(|| {
+            assert_eq!(Ok(Async::NotReady), tx.poll_ready());
+            assert_eq!(Ok(Some(1)), rx.try_next());
+            assert_eq!(Ok(Async::Ready(())), tx.poll_ready());
+            Ok::<(), ()>(())
+        })
+        .wait()
+        .unwrap();
+    }
+
+    #[test]
+    fn test_close() {
+        let (mut tx, mut rx) = channel(1);
+        tx.try_send(1).unwrap();
+        tx.try_send(2).unwrap();
+        rx.close();
+        assert!(tx.is_closed());
+        assert!(tx.try_send(3).unwrap_err().is_disconnected());
+        assert_eq!(vec![1, 2], rx.collect().wait().unwrap());
+    }
+
+    #[test]
+    fn test_close_channel_and_disconnect() {
+        let (mut tx, mut rx) = channel::<i32>(4);
+        let mut tx2 = tx.clone();
+        assert!(tx.same_receiver(&tx2));
+        tx.try_send(1).unwrap();
+        tx.disconnect();
+        assert!(tx.is_closed() && !tx2.is_closed());
+        assert_eq!(Ok(Some(1)), rx.try_next());
+        assert!(rx.try_next().is_err());
+        tx2.close_channel();
+        assert!(tx2.try_send(2).unwrap_err().is_disconnected());
+        assert_eq!(Ok(None), rx.try_next());
+    }
+
+    #[test]
+    fn test_receiver_dropped() {
+        let (mut tx, rx) = channel(4);
+        drop(rx);
+        assert!(tx.is_closed());
+        assert_eq!(1, tx.try_send(1).unwrap_err().into_inner());
+    }
+
+    #[test]
+    fn test_threaded_senders() {
+        let (tx, rx) = channel(2);
+        let mut handles = Vec::new();
+        for t in 0..4 {
+            let mut tx = tx.clone();
+            handles.push(thread::spawn(move || {
+                for i in 0..1000 {
+                    tx = tx.send((t, i)).wait().unwrap();
+                }
+            }));
+        }
+        drop(tx);
+        let mut next = [0; 4];
+        for (t, i) in rx.wait().map(|v| v.unwrap()) {
+            // Each sender's values come through in order
+            assert_eq!(next[t], i);
+            next[t] += 1;
+        }
+        assert_eq!([1000; 4], next);
+        for h in handles {
+            h.join().unwrap();
+        }
+    }
+}
@@ -0,0 +1,241 @@
+//! Support for running a task on the tokio runtime for each of several streams
+//! of a broadcast queue
+
+extern crate futures;
+extern crate tokio;
+
+use crate::broadcast::BroadcastFutReceiver;
+
+use self::futures::sync::oneshot;
+use self::futures::{Async, Future, IntoFuture, Poll, Stream};
+
+use std::sync::Arc;
+
+/// A set of tasks on the tokio runtime, each reading its own stream of the same
+/// broadcast queue and passing every value to the same handler. Each task stops once
+/// every sender is gone and its stream has been drained, or once the future
+/// the handler returned fails. A task which stops drops its stream, so writers
+/// stop waiting on it and the queue disconnects as usual once every task has stopped.
+///
+/// This is a future which resolves once every task has stopped. It fails if any
+/// of them stopped because a handler failed or panicked, or if the runtime was shut
+/// down before it finished. Dropping it leaves the tasks running.
+/// These are made with ```BroadcastFutReceiver::spawn_streams```.
+///
+/// # Examples
+///
+/// ```
+/// extern crate futures;
+/// extern crate tokio;
+/// # extern crate multiqueue2;
+///
+/// use futures::future::lazy;
+/// use futures::Future;
+/// use multiqueue2::broadcast_fut_queue;
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// use std::sync::Arc;
+///
+/// let (w, r) = broadcast_fut_queue(16);
+/// let total = Arc::new(AtomicUsize::new(0));
+/// let sum = total.clone();
+/// let mut runtime = tokio::runtime::Runtime::new().unwrap();
+/// let tasks = runtime
+///     .block_on(lazy(move || {
+///         Ok::<_, ()>(r.spawn_streams(3, move |val: &usize| {
+///             sum.fetch_add(*val, Ordering::Relaxed);
+///             Ok(())
+///         }))
+///     }))
+///     .unwrap();
+/// for i in 0..10 {
+///     while w.try_send(i).is_err() {}
+/// }
+/// // The tasks stop once the senders are gone and every stream was drained
+/// drop(w);
+/// tasks.wait().unwrap();
+/// // Every stream sees every value
+/// assert_eq!(3 * 45, total.load(Ordering::Relaxed));
+/// ```
+pub struct StreamTasks {
+    running: Vec<oneshot::Receiver<Result<(), ()>>>,
+    failed: bool,
+}
+
+impl StreamTasks {
+    pub(crate) fn new<T, F, U>(
+        receiver: BroadcastFutReceiver<T>,
+        streams: usize,
+        f: F,
+    ) -> StreamTasks
+    where
+        T: Clone + Send + Sync + 'static,
+        F: Fn(&T) -> U + Send + Sync + 'static,
+        U: IntoFuture<Item = (), Error = ()>,
+        U::Future: Send + 'static,
+    {
+        assert!(streams > 0, "Multiqueue error - zero streams spawned");
+        let f = Arc::new(f);
+        // Every stream is added before the passed receiver unsubscribes, so the
+        // queue never goes without a stream and disconnects in the meantime
+        let added: Vec<_> = (0..streams).map(|_| receiver.add_stream()).collect();
+        receiver.unsubscribe();
+        let running = added
+            .into_iter()
+            .map(|stream| {
+                let f = f.clone();
+                // Each stream has just been added, so nothing else is on it
+                let single = match stream.into_single(move |val: &T| f(val).into_future()) {
+                    Ok(single) => single,
+                    Err(_) => unreachable!(),
+                };
+                let (done, running) = oneshot::channel();
+                tokio::spawn(single.for_each(|handled| handled).then(move |rval| {
+                    let _ = done.send(rval);
+                    Ok(())
+                }));
+                running
+            })
+            .collect();
+        StreamTasks {
+            running,
+            failed: false,
+        }
+    }
+
+    /// Returns the number of tasks which haven't been seen to stop yet
+    pub fn running(&self) -> usize {
+        self.running.len()
+    }
+}
+
+impl Future for StreamTasks {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        let mut failed = self.failed;
+        self.running.retain_mut(|running| match running.poll() {
+            Ok(Async::NotReady) => true,
+            Ok(Async::Ready(Ok(()))) => false,
+            // A task which panicked or got dropped with the runtime never reports back
+            Ok(Async::Ready(Err(()))) | Err(oneshot::Canceled) => {
+                failed = true;
+                false
+            }
+        });
+        self.failed = failed;
+        match (self.running.is_empty(), self.failed) {
+            (false, _) => Ok(Async::NotReady),
+            (true, false) => Ok(Async::Ready(())),
+            (true, true) => Err(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::futures::future::lazy;
+    use super::futures::Future;
+    use super::tokio::runtime::Runtime;
+    use super::StreamTasks;
+    use crate::broadcast::{broadcast_fut_queue, BroadcastFutReceiver, BroadcastFutSender};
+    use crate::error::FrozenTrySendError;
+
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    use std::sync::{Arc, Mutex};
+    use std::thread::yield_now;
+
+    fn spawn_on<F, U>(
+        runtime: &mut Runtime,
+        receiver: BroadcastFutReceiver<usize>,
+        streams: usize,
+        f: F,
+    ) -> StreamTasks
+    where
+        F: Fn(&usize) -> U + Send + Sync + 'static,
+        U: super::IntoFuture<Item = (), Error = ()>,
+        U::Future: Send + 'static,
+    {
+        runtime
+            .block_on(lazy(move || {
+                Ok::<_, ()>(receiver.spawn_streams(streams, f))
+            }))
+            .unwrap()
+    }
+
+    fn send_all(writer: &BroadcastFutSender<usize>, num: usize) {
+        for i in 0..num {
+            let mut val = writer.try_send(i);
+            while let Err(FrozenTrySendError::Full(v)) = val {
+                yield_now();
+                val = writer.try_send(v);
+            }
+        }
+    }
+
+    #[test]
+    fn test_spawn_streams() {
+        let mut runtime = Runtime::new().unwrap();
+        let (writer, reader) = broadcast_fut_queue(8);
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = seen.clone();
+        let tasks = spawn_on(&mut runtime, reader, 3, move |val| {
+            sink.lock().unwrap().push(*val);
+            Ok(())
+        });
+        assert_eq!(3, tasks.running());
+        // The passed receiver is gone, leaving only the spawned streams
+        assert_eq!(3, writer.stream_count());
+        send_all(&writer, 100);
+        drop(writer);
+        tasks.wait().unwrap();
+        let mut seen = seen.lock().unwrap().clone();
+        seen.sort_unstable();
+        let expected: Vec<_> = (0..100).flat_map(|i| vec![i; 3]).collect();
+        assert_eq!(expected, seen);
+    }
+
+    #[test]
+    fn test_spawn_streams_failed() {
+        let mut runtime = Runtime::new().unwrap();
+        let (writer, reader) = broadcast_fut_queue(8);
+        let failed = Arc::new(AtomicBool::new(false));
+        let handled = Arc::new(AtomicUsize::new(0));
+        let count = handled.clone();
+        let tasks = spawn_on(&mut runtime, reader, 2, move |val| {
+            if *val == 5 && !failed.swap(true, Ordering::Relaxed) {
+                return Err(());
+            }
+            count.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        });
+        // Writers stop waiting on the failed stream, so the other one gets everything
+        send_all(&writer, 100);
+        drop(writer);
+        assert!(tasks.wait().is_err());
+        assert_eq!(100 + 5, handled.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_spawn_streams_disconnect() {
+        let mut runtime = Runtime::new().unwrap();
+        let (writer, reader) = broadcast_fut_queue(8);
+        let tasks = spawn_on(&mut runtime, reader, 2, |_| -> Result<(), ()> {
+            panic!("handler failed")
+        });
+        writer.try_send(0).unwrap();
+        assert!(tasks.wait().is_err());
+        // Nothing is left holding the queue open once every task has stopped
+        assert!(writer.try_send(1).is_err());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_spawn_streams_zero() {
+        let mut runtime = Runtime::new().unwrap();
+        let (_writer, reader) = broadcast_fut_queue(8);
+        spawn_on(&mut runtime, reader, 0, |_| Ok(()));
+    }
+}
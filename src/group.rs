@@ -0,0 +1,448 @@
+//! Consumer groups, which share broadcast streams out among their members
+
+use crate::broadcast::BroadcastReceiver;
+use crate::multiqueue::{BCast, InnerRecv};
+use crate::wait::Backoff;
+
+use std::cell::{Cell, RefCell};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{RecvError, TryRecvError};
+use std::sync::Arc;
+
+extern crate parking_lot;
+
+/// A stream the group receives from. Only the member it's assigned to receives from it,
+/// but the member which had it before a rebalance may not have noticed yet,
+/// so the stream is locked around each receive
+struct Shard<T: Clone> {
+    id: usize,
+    stream: parking_lot::Mutex<InnerRecv<BCast<T>, T>>,
+    disconnected: AtomicBool,
+}
+
+/// Keeps track of the group's shards and members, and which member gets which shard
+struct Coordinator<T: Clone> {
+    state: parking_lot::Mutex<GroupState<T>>,
+    // Bumped on every rebalance, so members know when to look at their shards again
+    generation: AtomicUsize,
+    // How many of the shards are disconnected and drained, and how many there are.
+    // Both only change under the lock
+    disconnected: AtomicUsize,
+    num_shards: AtomicUsize,
+}
+
+struct GroupState<T: Clone> {
+    shards: Vec<Arc<Shard<T>>>,
+    // In the order they joined
+    members: Vec<usize>,
+    next_shard: usize,
+    next_member: usize,
+}
+
+impl<T: Clone> Coordinator<T> {
+    /// Deals the shards out to the members in turn, in the order
+    /// the shards were added and the members joined
+    fn assignment(&self, state: &GroupState<T>, member: usize) -> Vec<Arc<Shard<T>>> {
+        let turn = match state.members.iter().position(|m| *m == member) {
+            Some(turn) => turn,
+            None => return Vec::new(),
+        };
+        let num_members = state.members.len();
+        state
+            .shards
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| i % num_members == turn)
+            .map(|(_, shard)| shard.clone())
+            .collect()
+    }
+
+    fn rebalance(&self) {
+        self.generation.fetch_add(1, Ordering::Release);
+    }
+
+    /// Counts the shard as disconnected the first time it's found drained
+    #[cold]
+    fn shard_disconnected(&self, shard: &Arc<Shard<T>>) {
+        let state = self.state.lock();
+        if !shard.disconnected.swap(true, Ordering::Relaxed)
+            && state.shards.iter().any(|s| Arc::ptr_eq(s, shard))
+        {
+            self.disconnected.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns whether the group has shards and all of them are disconnected and drained
+    fn is_disconnected(&self) -> bool {
+        let num_shards = self.num_shards.load(Ordering::Relaxed);
+        num_shards != 0 && self.disconnected.load(Ordering::Relaxed) == num_shards
+    }
+}
+
+/// A consumer group shares the values on a set of broadcast streams, its shards,
+/// out among its members, so that each value goes to just one member. Every shard
+/// is assigned to one member at a time, which receives all of its values in order.
+///
+/// Whenever a member joins or leaves, or a shard is added or removed, the shards
+/// are dealt out again. The members pick up their new shards the next time they
+/// receive, and a shard which moves to another member carries on where the last
+/// member left off. A member with no shards, because there are more members than
+/// shards, receives nothing until one is freed up.
+///
+/// The group receives from its own stream on each shard's queue, so the values
+/// are shared out among the members while other streams still see all of them.
+/// Cloning a group gives another handle to it, for joining from other threads.
+///
+/// # Examples
+///
+/// ```
+/// use multiqueue2::{broadcast_queue, ConsumerGroup};
+///
+/// let (orders, orders_recv) = broadcast_queue(16);
+/// let (refunds, refunds_recv) = broadcast_queue(16);
+///
+/// let group = ConsumerGroup::new();
+/// let orders_shard = group.add_shard(&orders_recv);
+/// let refunds_shard = group.add_shard(&refunds_recv);
+///
+/// let first = group.join();
+/// assert_eq!(vec![orders_shard, refunds_shard], first.assignment());
+///
+/// // A second member takes one of the shards off the first
+/// let second = group.join();
+/// assert_eq!(vec![orders_shard], first.assignment());
+/// assert_eq!(vec![refunds_shard], second.assignment());
+///
+/// orders.try_send(1).unwrap();
+/// refunds.try_send(2).unwrap();
+/// assert_eq!(1, first.try_recv().unwrap());
+/// assert_eq!(2, second.try_recv().unwrap());
+///
+/// // Streams outside the group still see everything
+/// assert_eq!(1, orders_recv.try_recv().unwrap());
+///
+/// // Once the second leaves, the first has both shards again
+/// second.leave();
+/// refunds.try_send(3).unwrap();
+/// assert_eq!(3, first.try_recv().unwrap());
+/// ```
+pub struct ConsumerGroup<T: Clone> {
+    group: Arc<Coordinator<T>>,
+}
+
+/// A member of a ```ConsumerGroup```, which receives from the shards it's been assigned.
+/// It takes turns between its shards so that a busy one can't starve the rest.
+/// Dropping it leaves the group, and its shards go to the other members
+pub struct GroupMember<T: Clone> {
+    group: Arc<Coordinator<T>>,
+    id: usize,
+    shards: RefCell<Vec<Arc<Shard<T>>>>,
+    seen: Cell<usize>,
+    next: Cell<usize>,
+}
+
+impl<T: Clone> ConsumerGroup<T> {
+    /// Creates a group without any shards or members
+    pub fn new() -> ConsumerGroup<T> {
+        ConsumerGroup {
+            group: Arc::new(Coordinator {
+                state: parking_lot::Mutex::new(GroupState {
+                    shards: Vec::new(),
+                    members: Vec::new(),
+                    next_shard: 0,
+                    next_member: 0,
+                }),
+                generation: AtomicUsize::new(0),
+                disconnected: AtomicUsize::new(0),
+                num_shards: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    /// Adds a stream to the receiver's queue at the same spot as the receiver,
+    /// and shares it out among the members as a shard. Returns the shard's id
+    pub fn add_shard(&self, receiver: &BroadcastReceiver<T>) -> usize {
+        let stream = receiver.add_stream().into_inner();
+        let mut state = self.group.state.lock();
+        let id = state.next_shard;
+        state.next_shard += 1;
+        state.shards.push(Arc::new(Shard {
+            id,
+            stream: parking_lot::Mutex::new(stream),
+            disconnected: AtomicBool::new(false),
+        }));
+        self.group.num_shards.fetch_add(1, Ordering::Relaxed);
+        self.group.rebalance();
+        id
+    }
+
+    /// Stops sharing out the shard, returning whether the group had it. The group's
+    /// stream is unsubscribed once the member which had the shard notices it's gone
+    pub fn remove_shard(&self, id: usize) -> bool {
+        let mut state = self.group.state.lock();
+        let at = match state.shards.iter().position(|shard| shard.id == id) {
+            Some(at) => at,
+            None => return false,
+        };
+        let shard = state.shards.remove(at);
+        if shard.disconnected.load(Ordering::Relaxed) {
+            self.group.disconnected.fetch_sub(1, Ordering::Relaxed);
+        }
+        self.group.num_shards.fetch_sub(1, Ordering::Relaxed);
+        self.group.rebalance();
+        true
+    }
+
+    /// Adds a member to the group, which takes its share of the shards
+    /// from the members already in it
+    pub fn join(&self) -> GroupMember<T> {
+        let mut state = self.group.state.lock();
+        let id = state.next_member;
+        state.next_member += 1;
+        state.members.push(id);
+        self.group.rebalance();
+        // Nothing has been assigned yet, so the first receive looks
+        let seen = self
+            .group
+            .generation
+            .load(Ordering::Relaxed)
+            .wrapping_sub(1);
+        GroupMember {
+            group: self.group.clone(),
+            id,
+            shards: RefCell::new(Vec::new()),
+            seen: Cell::new(seen),
+            next: Cell::new(0),
+        }
+    }
+
+    /// Returns the number of members in the group
+    pub fn num_members(&self) -> usize {
+        self.group.state.lock().members.len()
+    }
+
+    /// Returns the number of shards shared out among the members
+    pub fn num_shards(&self) -> usize {
+        self.group.num_shards.load(Ordering::Relaxed)
+    }
+}
+
+impl<T: Clone> GroupMember<T> {
+    /// Picks up the member's shards again if the group has been rebalanced
+    fn refresh(&self) {
+        if self.group.generation.load(Ordering::Acquire) == self.seen.get() {
+            return;
+        }
+        let state = self.group.state.lock();
+        self.seen.set(self.group.generation.load(Ordering::Relaxed));
+        *self.shards.borrow_mut() = self.group.assignment(&state, self.id);
+        self.next.set(0);
+    }
+
+    /// Returns the ids of the shards this member receives from
+    pub fn assignment(&self) -> Vec<usize> {
+        self.refresh();
+        self.shards.borrow().iter().map(|shard| shard.id).collect()
+    }
+
+    /// Tries to receive a value from the next of this member's shards which has one.
+    /// Returns Disconnected once every shard in the group is disconnected and drained
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.refresh();
+        let shards = self.shards.borrow();
+        let start = self.next.get();
+        for i in 0..shards.len() {
+            let at = (start + i) % shards.len();
+            let shard = &shards[at];
+            // The member which had this before the rebalance is still receiving from it
+            let stream = match shard.stream.try_lock() {
+                Some(stream) => stream,
+                None => continue,
+            };
+            match stream.try_recv() {
+                Ok(val) => {
+                    self.next.set(at + 1);
+                    return Ok(val);
+                }
+                Err(TryRecvError::Disconnected) => {
+                    if !shard.disconnected.load(Ordering::Relaxed) {
+                        self.group.shard_disconnected(shard);
+                    }
+                }
+                Err(TryRecvError::Empty) => (),
+            }
+        }
+        if self.group.is_disconnected() {
+            Err(TryRecvError::Disconnected)
+        } else {
+            Err(TryRecvError::Empty)
+        }
+    }
+
+    /// Receives a value from one of this member's shards, waiting for one if they're
+    /// all empty. Shards are polled, sleeping for at most
+    /// DEFAULT_SELECT_MAX_SLEEP_US microseconds at a time, so that a rebalance
+    /// is picked up while waiting. Fails once every shard in the group is
+    /// disconnected and drained
+    pub fn recv(&self) -> Result<T, RecvError> {
+        let mut backoff = Backoff::new();
+        loop {
+            match self.try_recv() {
+                Ok(val) => return Ok(val),
+                Err(TryRecvError::Disconnected) => return Err(RecvError),
+                Err(TryRecvError::Empty) => backoff.snooze(None),
+            }
+        }
+    }
+
+    /// Leaves the group, handing this member's shards to the others
+    pub fn leave(self) {}
+}
+
+impl<T: Clone> Clone for ConsumerGroup<T> {
+    fn clone(&self) -> ConsumerGroup<T> {
+        ConsumerGroup {
+            group: self.group.clone(),
+        }
+    }
+}
+
+impl<T: Clone> Default for ConsumerGroup<T> {
+    fn default() -> ConsumerGroup<T> {
+        ConsumerGroup::new()
+    }
+}
+
+impl<T: Clone> Drop for GroupMember<T> {
+    fn drop(&mut self) {
+        let mut state = self.group.state.lock();
+        state.members.retain(|m| *m != self.id);
+        self.group.rebalance();
+    }
+}
+
+impl<T: Clone> Iterator for GroupMember<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.recv().ok()
+    }
+}
+
+unsafe impl<T: Send + Sync + Clone> Send for ConsumerGroup<T> {}
+unsafe impl<T: Send + Sync + Clone> Send for GroupMember<T> {}
+
+#[cfg(test)]
+mod test {
+
+    use super::ConsumerGroup;
+    use crate::broadcast::broadcast_queue;
+
+    extern crate crossbeam;
+    use self::crossbeam::scope;
+
+    use std::sync::mpsc::TryRecvError;
+    use std::thread::yield_now;
+
+    #[test]
+    fn test_rebalance() {
+        let group = ConsumerGroup::<usize>::new();
+        let queues: Vec<_> = (0..5).map(|_| broadcast_queue(4)).collect();
+        let shards: Vec<_> = queues.iter().map(|(_, r)| group.add_shard(r)).collect();
+        assert_eq!(5, group.num_shards());
+
+        let a = group.join();
+        assert_eq!(shards, a.assignment());
+        let b = group.join();
+        let c = group.join();
+        assert_eq!(3, group.num_members());
+        assert_eq!(vec![shards[0], shards[3]], a.assignment());
+        assert_eq!(vec![shards[1], shards[4]], b.assignment());
+        assert_eq!(vec![shards[2]], c.assignment());
+
+        b.leave();
+        assert_eq!(vec![shards[0], shards[2], shards[4]], a.assignment());
+        assert_eq!(vec![shards[1], shards[3]], c.assignment());
+
+        assert!(group.remove_shard(shards[0]));
+        assert!(!group.remove_shard(shards[0]));
+        assert_eq!(vec![shards[1], shards[3]], a.assignment());
+        assert_eq!(vec![shards[2], shards[4]], c.assignment());
+    }
+
+    #[test]
+    fn test_disconnect() {
+        let group = ConsumerGroup::new();
+        let (send_a, recv_a) = broadcast_queue(4);
+        let (send_b, recv_b) = broadcast_queue(4);
+        group.add_shard(&recv_a);
+        group.add_shard(&recv_b);
+        let first = group.join();
+        let second = group.join();
+        // Members without any shards are disconnected along with the rest of the group
+        let idle = group.join();
+        send_a.try_send(1).unwrap();
+        drop(send_a);
+        drop(send_b);
+        assert_eq!(Err(TryRecvError::Empty), second.try_recv());
+        assert_eq!(Err(TryRecvError::Empty), idle.try_recv());
+        assert_eq!(Ok(1), first.try_recv());
+        assert_eq!(Err(TryRecvError::Disconnected), first.try_recv());
+        assert_eq!(Err(TryRecvError::Disconnected), idle.try_recv());
+    }
+
+    #[test]
+    fn test_each_value_once() {
+        let num_loop = 20000;
+        let num_shards = 4;
+        let group = ConsumerGroup::new();
+        let queues: Vec<_> = (0..num_shards).map(|_| broadcast_queue(16)).collect();
+        for (_, recv) in queues.iter() {
+            group.add_shard(recv);
+        }
+        let senders: Vec<_> = queues.into_iter().map(|(send, _)| send).collect();
+        scope(|scope| {
+            let mut members = Vec::new();
+            for _ in 0..3 {
+                let member = group.join();
+                members.push(scope.spawn(move |_| {
+                    let mut got = Vec::new();
+                    for val in member {
+                        got.push(val);
+                    }
+                    got
+                }));
+            }
+            // Members come and go while the values are being sent
+            let churn = group.clone();
+            members.push(scope.spawn(move |_| {
+                let mut got = Vec::new();
+                for _ in 0..50 {
+                    let member = churn.join();
+                    for _ in 0..10 {
+                        if let Ok(val) = member.try_recv() {
+                            got.push(val);
+                        }
+                        yield_now();
+                    }
+                    member.leave();
+                }
+                got
+            }));
+            for i in 0..num_loop {
+                let sender = &senders[i % num_shards];
+                while sender.try_send(i).is_err() {
+                    yield_now();
+                }
+            }
+            drop(senders);
+            let mut got: Vec<usize> = members
+                .into_iter()
+                .flat_map(|member| member.join().unwrap())
+                .collect();
+            got.sort_unstable();
+            assert_eq!((0..num_loop).collect::<Vec<_>>(), got);
+        })
+        .unwrap();
+    }
+}
@@ -0,0 +1,181 @@
+//! Support for looking at a queue without taking part in it
+
+use crate::multiqueue::{BCast, MultiQueue, MPMC};
+use crate::stats::{MemoryFootprint, QueueStats};
+
+use std::sync::Arc;
+
+pub(crate) trait Inspect<T> {
+    fn stats(&self) -> QueueStats;
+    fn memory_footprint(&self) -> MemoryFootprint;
+    fn stream_lags(&self) -> Vec<(Option<String>, usize)>;
+    fn stream_count(&self) -> usize;
+    fn writer_count(&self) -> usize;
+    fn capacity(&self) -> usize;
+    fn dump(&self) -> Option<Vec<(u64, T)>>;
+}
+
+impl<T: Clone> Inspect<T> for Arc<MultiQueue<BCast<T>, T>> {
+    fn stats(&self) -> QueueStats {
+        MultiQueue::stats(self)
+    }
+
+    fn memory_footprint(&self) -> MemoryFootprint {
+        MultiQueue::memory_footprint(self)
+    }
+
+    fn stream_lags(&self) -> Vec<(Option<String>, usize)> {
+        MultiQueue::stream_lags(self)
+    }
+
+    fn stream_count(&self) -> usize {
+        MultiQueue::stream_count(self)
+    }
+
+    fn writer_count(&self) -> usize {
+        MultiQueue::writer_count(self)
+    }
+
+    fn capacity(&self) -> usize {
+        MultiQueue::capacity(self)
+    }
+
+    fn dump(&self) -> Option<Vec<(u64, T)>> {
+        Some(MultiQueue::dump(self))
+    }
+}
+
+impl<T> Inspect<T> for Arc<MultiQueue<MPMC<T>, T>> {
+    fn stats(&self) -> QueueStats {
+        MultiQueue::stats(self)
+    }
+
+    fn memory_footprint(&self) -> MemoryFootprint {
+        MultiQueue::memory_footprint(self)
+    }
+
+    fn stream_lags(&self) -> Vec<(Option<String>, usize)> {
+        MultiQueue::stream_lags(self)
+    }
+
+    fn stream_count(&self) -> usize {
+        MultiQueue::stream_count(self)
+    }
+
+    fn writer_count(&self) -> usize {
+        MultiQueue::writer_count(self)
+    }
+
+    fn capacity(&self) -> usize {
+        MultiQueue::capacity(self)
+    }
+
+    // Receivers move values out of their slots, so nothing can look at them in place
+    fn dump(&self) -> Option<Vec<(u64, T)>> {
+        None
+    }
+}
+
+/// A read only handle on a queue, for monitoring it. An inspector is neither
+/// a writer nor a stream: writers never wait on it, receivers never disconnect
+/// because of it, and the queue stays alive until it and every other handle are gone.
+/// Every reading is a snapshot and may be stale by the time it is used.
+/// These are made with ```inspector``` on the senders and receivers of
+/// broadcast and mpmc queues, and are cheap to clone.
+///
+/// # Examples
+///
+/// ```
+/// use multiqueue2::broadcast_queue;
+///
+/// let (w, r) = broadcast_queue(4);
+/// let inspector = w.inspector();
+/// w.try_send(1).unwrap();
+/// w.try_send(2).unwrap();
+/// assert_eq!(2, inspector.occupancy());
+/// assert_eq!(Some(vec![1, 2]), inspector.pending());
+///
+/// // Looking at the values doesn't receive them
+/// assert_eq!(1, r.try_recv().unwrap());
+/// assert_eq!(Some(vec![2]), inspector.pending());
+/// ```
+pub struct Inspector<T> {
+    queue: Arc<dyn Inspect<T>>,
+}
+
+impl<T> Inspector<T> {
+    pub(crate) fn new<Q: Inspect<T> + 'static>(queue: Q) -> Inspector<T> {
+        Inspector {
+            queue: Arc::new(queue),
+        }
+    }
+
+    /// Returns a snapshot of the queue's state, which holds the head
+    /// and the position of every stream along with the occupancy
+    pub fn stats(&self) -> QueueStats {
+        self.queue.stats()
+    }
+
+    /// Returns how many bytes the queue takes up
+    pub fn memory_footprint(&self) -> MemoryFootprint {
+        self.queue.memory_footprint()
+    }
+
+    /// Returns the number of values the furthest behind stream has left to read
+    pub fn occupancy(&self) -> usize {
+        self.queue.stats().occupancy
+    }
+
+    /// Returns the label of each stream along with how many items it's behind the writers
+    pub fn stream_lags(&self) -> Vec<(Option<String>, usize)> {
+        self.queue.stream_lags()
+    }
+
+    /// Returns the number of streams subscribed to the queue
+    pub fn stream_count(&self) -> usize {
+        self.queue.stream_count()
+    }
+
+    /// Returns the number of writers subscribed to the queue
+    pub fn writer_count(&self) -> usize {
+        self.queue.writer_count()
+    }
+
+    /// Returns how many values the queue holds
+    pub fn capacity(&self) -> usize {
+        self.queue.capacity()
+    }
+
+    /// Returns clones of everything the furthest behind stream has yet to receive,
+    /// oldest first, without receiving any of it. Paused streams are left out,
+    /// since writers don't wait on them either.
+    ///
+    /// Writers are only held off of a slot while the value in it is being cloned,
+    /// the same as when a receiver views a value in place.
+    ///
+    /// Returns None for mpmc queues, since their receivers move values out
+    /// of the queue, so there's nothing to clone them from without holding
+    /// the receivers up.
+    pub fn pending(&self) -> Option<Vec<T>> {
+        self.queue
+            .dump()
+            .map(|dump| dump.into_iter().map(|(_, val)| val).collect())
+    }
+
+    /// Identical to ```pending```, but also returns the sequence number each
+    /// value was written at, the same as ```BroadcastSender::dump```
+    pub fn dump(&self) -> Option<Vec<(u64, T)>> {
+        self.queue.dump()
+    }
+}
+
+impl<T> Clone for Inspector<T> {
+    fn clone(&self) -> Self {
+        Inspector {
+            queue: self.queue.clone(),
+        }
+    }
+}
+
+unsafe impl<T: Send + Sync> Send for Inspector<T> {}
+unsafe impl<T: Send + Sync> Sync for Inspector<T> {}
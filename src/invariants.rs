@@ -0,0 +1,194 @@
+//! Consistency checks of a queue's internals, for stress tests to run between
+//! phases so that corruption shows up near whatever caused it.
+//! Only built with the test-util feature.
+
+use std::fmt;
+
+/// What ```check_invariants``` found when it looked over a queue.
+///
+/// The checks expect the queue to be at rest, with nothing sending or receiving
+/// while they run. A send or receive in the middle of happening looks just
+/// like the corruption being looked for.
+///
+/// # Examples
+///
+/// ```
+/// use multiqueue2::broadcast_queue;
+///
+/// let (w, r) = broadcast_queue(4);
+/// for i in 0..10 {
+///     w.try_send(i).unwrap();
+///     r.try_recv().unwrap();
+/// }
+/// let report = w.check_invariants();
+/// assert!(report.is_ok(), "{}", report);
+/// assert_eq!(10, report.tail);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct InvariantReport {
+    /// The position the next value will be written at
+    pub head: usize,
+    /// The number of values the queue can hold
+    pub capacity: usize,
+    /// Where writers last saw the slowest stream
+    pub tail_cache: usize,
+    /// The position of the slowest stream writers wait on, or the head without one
+    pub tail: usize,
+    /// The number of streams subscribed to the queue
+    pub streams: usize,
+    /// A description of each inconsistency found, empty if there were none
+    pub violations: Vec<String>,
+}
+
+/// How a stream looked to the checks
+pub(crate) struct StreamCheck {
+    pub name: Option<String>,
+    pub pos: usize,
+    pub paused: bool,
+    pub consumers: usize,
+}
+
+impl InvariantReport {
+    /// Returns whether the queue was consistent
+    pub fn is_ok(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+impl StreamCheck {
+    pub fn describe(&self) -> String {
+        match self.name {
+            Some(ref name) => format!("stream {:?} at {}", name, self.pos),
+            None => format!("stream at {}", self.pos),
+        }
+    }
+}
+
+impl fmt::Display for InvariantReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "head {}, tail {}, tail cache {}, {} streams",
+            self.head, self.tail, self.tail_cache, self.streams
+        )?;
+        if self.violations.is_empty() {
+            return " and no violations".fmt(f);
+        }
+        write!(f, " and {} violations:", self.violations.len())?;
+        for violation in &self.violations {
+            write!(f, "\n  {}", violation)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::InvariantReport;
+    use crate::broadcast::broadcast_queue;
+    use crate::error::FrozenTrySendError;
+    use crate::mpmc::mpmc_queue;
+
+    extern crate crossbeam;
+    use self::crossbeam::scope;
+
+    use std::thread::yield_now;
+
+    fn assert_ok(report: InvariantReport) -> InvariantReport {
+        assert!(report.is_ok(), "{}", report);
+        report
+    }
+
+    #[test]
+    fn test_fresh_queue() {
+        let (w, r) = broadcast_queue::<usize>(4);
+        let report = assert_ok(w.check_invariants());
+        assert_eq!(
+            (0, 0, 4, 1),
+            (report.head, report.tail, report.capacity, report.streams)
+        );
+        drop(r);
+    }
+
+    #[test]
+    fn test_broadcast_streams() {
+        let (w, r) = broadcast_queue(4);
+        let slow = r.add_stream();
+        let bounded = r.add_bounded_stream(2);
+        let paused = r.add_stream().pause().unwrap();
+        for i in 0..4 {
+            w.try_send(i).unwrap();
+        }
+        assert!(w.try_send(4).is_err());
+        let report = assert_ok(r.check_invariants());
+        assert_eq!((4, 0, 4), (report.head, report.tail, report.streams));
+        for _ in 0..3 {
+            r.try_recv().unwrap();
+            slow.try_recv().unwrap();
+        }
+        for i in 4..7 {
+            w.try_send(i).unwrap();
+        }
+        // The paused stream is left far behind, and the bounded one
+        // is skipped once the writer runs out of room
+        let report = assert_ok(w.check_invariants());
+        assert_eq!((7, 3), (report.head, report.tail));
+        drop(bounded);
+        paused.resume();
+        assert_ok(w.check_invariants());
+    }
+
+    #[test]
+    fn test_mpmc_phases() {
+        let (w, r) = mpmc_queue(16);
+        for phase in 0..5 {
+            scope(|scope| {
+                for _ in 0..2 {
+                    let w = w.clone();
+                    scope.spawn(move |_| {
+                        for i in 0..1000 {
+                            let mut val = w.try_send(i);
+                            while let Err(FrozenTrySendError::Full(v)) = val {
+                                yield_now();
+                                val = w.try_send(v);
+                            }
+                        }
+                    });
+                }
+                for _ in 0..2 {
+                    let r = r.clone();
+                    scope.spawn(move |_| {
+                        for _ in 0..1000 {
+                            while r.try_recv().is_err() {
+                                yield_now();
+                            }
+                        }
+                    });
+                }
+            })
+            .unwrap();
+            let report = assert_ok(r.check_invariants());
+            assert_eq!((phase + 1) * 2000, report.head);
+            assert_eq!(report.head, report.tail);
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        let report = InvariantReport {
+            head: 9,
+            capacity: 4,
+            tail_cache: 6,
+            tail: 5,
+            streams: 1,
+            violations: vec!["the tail cache at 6 is past the slowest stream at 5".to_string()],
+        };
+        assert!(!report.is_ok());
+        assert_eq!(
+            "head 9, tail 5, tail cache 6, 1 streams and 1 violations:\n  \
+             the tail cache at 6 is past the slowest stream at 5",
+            report.to_string()
+        );
+    }
+}
@@ -0,0 +1,282 @@
+//! Adapters between queues of byte frames and ```std::io```, for code which
+//! would otherwise need a pipe to produce into or consume from a queue
+
+use crate::error::FrozenTrySendError;
+use crate::multiqueue::{InnerRecv, InnerSend, QueueRW};
+use crate::sync::yield_now;
+
+use std::io::{self, BufRead, Read, Write};
+use std::mem;
+
+trait FrameSink {
+    fn try_send(&self, frame: Vec<u8>) -> Result<(), FrozenTrySendError<Vec<u8>>>;
+
+    fn stream_count(&self) -> usize;
+}
+
+impl<RW: QueueRW<Vec<u8>>> FrameSink for InnerSend<RW, Vec<u8>> {
+    fn try_send(&self, frame: Vec<u8>) -> Result<(), FrozenTrySendError<Vec<u8>>> {
+        InnerSend::try_send(self, frame)
+    }
+
+    fn stream_count(&self) -> usize {
+        InnerSend::stream_count(self)
+    }
+}
+
+trait FrameSource {
+    /// Copies the next frame over buf, returning false once the writers are gone
+    fn recv_into(&self, buf: &mut Vec<u8>) -> bool;
+}
+
+impl<RW: QueueRW<Vec<u8>>> FrameSource for InnerRecv<RW, Vec<u8>> {
+    fn recv_into(&self, buf: &mut Vec<u8>) -> bool {
+        self.recv_view(|frame| {
+            buf.clear();
+            buf.extend_from_slice(frame);
+        })
+        .is_ok()
+    }
+}
+
+/// This writes into a queue of ```Vec<u8>```, cutting what's written into frames
+/// of a fixed size. A frame is sent as soon as it's full, and whatever is left
+/// over is sent by ```flush``` or when the writer is dropped.
+///
+/// Senders can't block, so when the queue is full this yields until there's space.
+/// Once every stream has unsubscribed, writes fail with ```ErrorKind::BrokenPipe```.
+///
+/// These are made with ```BroadcastSender::into_writer``` and ```MPMCSender::into_writer```
+///
+/// # Examples
+///
+/// ```
+/// use multiqueue2::mpmc_queue;
+/// use std::io::{Read, Write};
+///
+/// let (w, r) = mpmc_queue(10);
+/// let mut writer = w.into_writer(4);
+/// let mut reader = r.into_single().unwrap().into_reader();
+///
+/// write!(writer, "hello world").unwrap();
+/// drop(writer);
+///
+/// let mut read = String::new();
+/// reader.read_to_string(&mut read).unwrap();
+/// assert_eq!("hello world", read);
+/// ```
+pub struct QueueWriter {
+    sender: Box<dyn FrameSink>,
+    frame: Vec<u8>,
+    frame_size: usize,
+}
+
+/// This reads from a queue of ```Vec<u8>``` as one continuous stream of bytes,
+/// blocking while the queue is empty and reaching the end once the writers are gone.
+///
+/// These are made with ```BroadcastUniReceiver::into_reader``` and
+/// ```MPMCUniReceiver::into_reader```
+pub struct QueueReader {
+    receiver: Box<dyn FrameSource>,
+    frame: Vec<u8>,
+    pos: usize,
+}
+
+impl QueueWriter {
+    pub(crate) fn new<RW: QueueRW<Vec<u8>> + 'static>(
+        sender: InnerSend<RW, Vec<u8>>,
+        frame_size: usize,
+    ) -> QueueWriter {
+        assert!(
+            frame_size > 0,
+            "Multiqueue error - frames must be at least one byte"
+        );
+        QueueWriter {
+            sender: Box::new(sender),
+            frame: Vec::with_capacity(frame_size),
+            frame_size,
+        }
+    }
+
+    /// Returns the number of bytes written which are waiting for their frame to be sent
+    pub fn buffered(&self) -> usize {
+        self.frame.len()
+    }
+
+    fn send_frame(&mut self) -> io::Result<()> {
+        let mut frame = mem::replace(&mut self.frame, Vec::with_capacity(self.frame_size));
+        loop {
+            match self.sender.try_send(frame) {
+                Ok(()) => return Ok(()),
+                Err(FrozenTrySendError::Full(f))
+                | Err(FrozenTrySendError::Disconnected(f))
+                | Err(FrozenTrySendError::Frozen(f)) => {
+                    if self.sender.stream_count() == 0 {
+                        self.frame = f;
+                        return Err(io::Error::new(
+                            io::ErrorKind::BrokenPipe,
+                            "every stream on the queue has unsubscribed",
+                        ));
+                    }
+                    frame = f;
+                    yield_now();
+                }
+            }
+        }
+    }
+}
+
+impl Write for QueueWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Only left full when sending it failed before
+        if self.frame.len() == self.frame_size {
+            self.send_frame()?;
+        }
+        let n = buf.len().min(self.frame_size - self.frame.len());
+        self.frame.extend_from_slice(&buf[..n]);
+        if self.frame.len() == self.frame_size {
+            // What was written has been taken either way, so a failure
+            // here is left for the next write or flush to report
+            let _ = self.send_frame();
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.frame.is_empty() {
+            Ok(())
+        } else {
+            self.send_frame()
+        }
+    }
+}
+
+impl Drop for QueueWriter {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+impl QueueReader {
+    pub(crate) fn new<RW: QueueRW<Vec<u8>> + 'static>(
+        receiver: InnerRecv<RW, Vec<u8>>,
+    ) -> QueueReader {
+        QueueReader {
+            receiver: Box::new(receiver),
+            frame: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+
+impl Read for QueueReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = {
+            let available = self.fill_buf()?;
+            let n = available.len().min(buf.len());
+            buf[..n].copy_from_slice(&available[..n]);
+            n
+        };
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for QueueReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        // Empty frames are skipped, since handing back nothing means the end
+        while self.pos == self.frame.len() {
+            self.pos = 0;
+            if !self.receiver.recv_into(&mut self.frame) {
+                self.frame.clear();
+                break;
+            }
+        }
+        Ok(&self.frame[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.frame.len());
+    }
+}
+
+unsafe impl Send for QueueWriter {}
+unsafe impl Send for QueueReader {}
+
+#[cfg(test)]
+mod test {
+
+    use crate::broadcast::broadcast_queue;
+    use crate::mpmc::mpmc_queue;
+
+    extern crate crossbeam;
+    use self::crossbeam::scope;
+
+    use std::io::{BufRead, ErrorKind, Read, Write};
+
+    #[test]
+    fn test_frames() {
+        let (send, recv) = mpmc_queue(10);
+        let mut writer = send.into_writer(4);
+        writer.write_all(b"abcdefghij").unwrap();
+        assert_eq!(2, writer.buffered());
+        assert_eq!(b"abcd".to_vec(), recv.try_recv().unwrap());
+        assert_eq!(b"efgh".to_vec(), recv.try_recv().unwrap());
+        assert!(recv.try_recv().is_err());
+        writer.flush().unwrap();
+        assert_eq!(0, writer.buffered());
+        assert_eq!(b"ij".to_vec(), recv.try_recv().unwrap());
+        writer.write_all(b"k").unwrap();
+        drop(writer);
+        assert_eq!(b"k".to_vec(), recv.try_recv().unwrap());
+    }
+
+    #[test]
+    fn test_read_lines() {
+        let (send, recv) = broadcast_queue(10);
+        send.try_send(b"first li".to_vec()).unwrap();
+        send.try_send(Vec::new()).unwrap();
+        send.try_send(b"ne\nsecond line\nthird".to_vec()).unwrap();
+        drop(send);
+        let reader = recv.into_single().unwrap().into_reader();
+        let lines: Vec<String> = reader.lines().map(|l| l.unwrap()).collect();
+        assert_eq!(vec!["first line", "second line", "third"], lines);
+    }
+
+    #[test]
+    fn test_broken_pipe() {
+        let (send, recv) = broadcast_queue(1);
+        let mut writer = send.into_writer(2);
+        writer.write_all(b"ab").unwrap();
+        recv.unsubscribe();
+        // The frame is taken, and sending it fails on the next write
+        assert_eq!(2, writer.write(b"cd").unwrap());
+        assert_eq!(
+            ErrorKind::BrokenPipe,
+            writer.write(b"e").unwrap_err().kind()
+        );
+        assert_eq!(ErrorKind::BrokenPipe, writer.flush().unwrap_err().kind());
+        assert_eq!(2, writer.buffered());
+    }
+
+    #[test]
+    fn test_threaded_streams() {
+        let (send, recv) = broadcast_queue(4);
+        let data: Vec<u8> = (0..100_000).map(|i| i as u8).collect();
+        let dref = &data;
+        scope(|scope| {
+            for _ in 0..2 {
+                let mut reader = recv.add_stream().into_single().unwrap().into_reader();
+                scope.spawn(move |_| {
+                    let mut read = Vec::new();
+                    reader.read_to_end(&mut read).unwrap();
+                    assert!(read == *dref);
+                });
+            }
+            recv.unsubscribe();
+            let mut writer = send.into_writer(7);
+            writer.write_all(dref).unwrap();
+        })
+        .unwrap();
+    }
+}
@@ -0,0 +1,632 @@
+//! A mpmc queue where every value with the same key goes to the same consumer
+
+use crate::error::FrozenTrySendError;
+use crate::multiqueue::{InnerRecv, InnerSend, MPMC};
+use crate::shards::{hash_key, rings};
+use crate::wait::Doorbell;
+
+extern crate crossbeam;
+extern crate parking_lot;
+use self::crossbeam::channel::Sender;
+
+use std::cell::{Cell, RefCell};
+use std::hash::Hash;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{RecvError, TryRecvError};
+use std::sync::Arc;
+
+type KeyHash<T> = Arc<dyn Fn(&T) -> u64 + Send + Sync>;
+
+/// Keeps track of the queue's receivers, and which receiver gets which shard.
+/// Each ring is locked around a receive, and a receiver checks that its shards
+/// haven't moved once it holds the lock, so only the owner ever receives from one
+#[derive(Debug)]
+struct ShardTable<T> {
+    rings: Vec<parking_lot::Mutex<InnerRecv<MPMC<T>, T>>>,
+    state: parking_lot::Mutex<TableState>,
+    // Bumped whenever a shard moves, so receivers know when to look at theirs again
+    generation: AtomicUsize,
+}
+
+#[derive(Debug)]
+struct TableState {
+    // The receiver each shard is assigned to
+    owners: Vec<usize>,
+    // Every receiver along with its doorbell, in the order they were made
+    receivers: Vec<(usize, Sender<()>)>,
+    next_receiver: usize,
+}
+
+impl TableState {
+    fn owned_by(&self, receiver: usize) -> Vec<usize> {
+        (0..self.owners.len())
+            .filter(|&shard| self.owners[shard] == receiver)
+            .collect()
+    }
+}
+
+impl<T> ShardTable<T> {
+    /// Adds a receiver which doesn't own any shards yet
+    fn join(self: &Arc<Self>, state: &mut TableState) -> MPMCKeyedReceiver<T> {
+        let id = state.next_receiver;
+        state.next_receiver += 1;
+        let doorbell = Doorbell::new();
+        state.receivers.push((id, doorbell.ringer().clone()));
+        // Nothing has been picked up yet, so the first receive looks
+        let seen = self.generation.load(Ordering::Relaxed).wrapping_sub(1);
+        MPMCKeyedReceiver {
+            table: self.clone(),
+            id,
+            shards: RefCell::new(Vec::new()),
+            seen: Cell::new(seen),
+            next: Cell::new(0),
+            doorbell,
+        }
+    }
+
+    /// Lets every receiver know that shards have moved, waking those which are blocked
+    fn moved(&self, state: &TableState) {
+        self.generation.fetch_add(1, Ordering::Release);
+        // Waits out any receive already under way, so every later one sees the
+        // new generation, and nobody picks up a moved shard until this is done
+        for ring in &self.rings {
+            drop(ring.lock());
+        }
+        for (_, bell) in &state.receivers {
+            let _ = bell.try_send(());
+        }
+    }
+}
+
+/// This is the sending half of a keyed mpmc queue. Values are split between
+/// the shards of the queue by the hash of their key.
+#[derive(Clone)]
+pub struct MPMCKeyedSender<T> {
+    senders: Vec<InnerSend<MPMC<T>, T>>,
+    hash: KeyHash<T>,
+}
+
+/// This is the receiving half of a keyed mpmc queue. Each shard is assigned to
+/// one receiver, and nobody else receives from it, so every value with a given
+/// key is received by the same receiver in the order it was sent.
+///
+/// A receiver starts out with every shard and hands them out with ```split```
+/// or ```split_into```. The assignments are shared by every receiver on the queue,
+/// so ```rebalance``` can even them out from any receiver while the others keep
+/// receiving on their own threads. A receiver picks up shards which moved the next
+/// time it receives, and a shard only changes hands between two receives, so
+/// its values are still received in order. Dropping a receiver hands its shards
+/// to the others, and the queue disconnects once the last one is gone.
+///
+/// # Examples
+///
+/// ```
+/// use multiqueue2::mpmc_keyed_queue;
+/// use std::thread;
+///
+/// let (send, recv) = mpmc_keyed_queue(16, 4, |v: &(u32, u32)| v.0);
+/// let other = recv.split().unwrap();
+///
+/// let handles: Vec<_> = vec![recv, other]
+///     .into_iter()
+///     .map(|recv| {
+///         thread::spawn(move || {
+///             let mut got = Vec::new();
+///             for (user, val) in recv {
+///                 got.push((user, val));
+///             }
+///             got
+///         })
+///     })
+///     .collect();
+///
+/// for val in 0..4 {
+///     for user in 0..3 {
+///         send.try_send((user, val)).unwrap();
+///     }
+/// }
+/// drop(send);
+///
+/// for handle in handles {
+///     let got = handle.join().unwrap();
+///     // Each user's values all went to one receiver, in order
+///     for user in 0..3 {
+///         let vals: Vec<_> = got.iter().filter(|v| v.0 == user).map(|v| v.1).collect();
+///         assert!(vals.is_empty() || vals == vec![0, 1, 2, 3]);
+///     }
+/// }
+/// ```
+#[derive(Debug)]
+pub struct MPMCKeyedReceiver<T> {
+    table: Arc<ShardTable<T>>,
+    id: usize,
+    shards: RefCell<Vec<usize>>,
+    seen: Cell<usize>,
+    next: Cell<usize>,
+    doorbell: Doorbell,
+}
+
+impl<T> MPMCKeyedSender<T> {
+    /// Tries to send a value to the shard its key hashes to.
+    /// Each shard has its own capacity, so a full shard doesn't stop the others.
+    /// Sending always fails once every receiver is gone.
+    #[inline(always)]
+    pub fn try_send(&self, val: T) -> Result<(), FrozenTrySendError<T>> {
+        let shard = self.shard_of(&val);
+        self.senders[shard].try_send(val)
+    }
+
+    /// Returns the shard values like the passed one are sent to
+    pub fn shard_of(&self, val: &T) -> usize {
+        ((self.hash)(val) % self.senders.len() as u64) as usize
+    }
+
+    /// Returns the number of shards in the queue
+    pub fn num_shards(&self) -> usize {
+        self.senders.len()
+    }
+
+    /// Removes this writer from the queue
+    pub fn unsubscribe(self) {
+        for sender in self.senders {
+            sender.unsubscribe();
+        }
+    }
+}
+
+impl<T> MPMCKeyedReceiver<T> {
+    /// Picks up the receiver's shards again if any have moved
+    fn refresh(&self) {
+        if self.table.generation.load(Ordering::Acquire) == self.seen.get() {
+            return;
+        }
+        let state = self.table.state.lock();
+        self.seen.set(self.table.generation.load(Ordering::Relaxed));
+        *self.shards.borrow_mut() = state.owned_by(self.id);
+        self.next.set(0);
+    }
+
+    /// Tries to receive a value from one of the owned shards without blocking,
+    /// taking turns between them so a busy shard can't starve the rest.
+    /// Returns Disconnected once the writers are gone and the owned shards are empty.
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        'refresh: loop {
+            self.refresh();
+            let shards = self.shards.borrow();
+            let num = shards.len();
+            if num == 0 {
+                // Every ring has the same writers, so any of them says whether they're gone
+                return match self.table.rings[0].lock().writer_count() {
+                    0 => Err(TryRecvError::Disconnected),
+                    _ => Err(TryRecvError::Empty),
+                };
+            }
+            let start = self.next.get();
+            let mut any_empty = false;
+            for i in 0..num {
+                let at = (start + i) % num;
+                let ring = self.table.rings[shards[at]].lock();
+                // The shard may have moved since the last look
+                if self.table.generation.load(Ordering::Acquire) != self.seen.get() {
+                    continue 'refresh;
+                }
+                match ring.try_recv() {
+                    Ok(val) => {
+                        self.next.set((at + 1) % num);
+                        return Ok(val);
+                    }
+                    Err(TryRecvError::Empty) => any_empty = true,
+                    Err(TryRecvError::Disconnected) => (),
+                }
+            }
+            return if any_empty {
+                Err(TryRecvError::Empty)
+            } else {
+                Err(TryRecvError::Disconnected)
+            };
+        }
+    }
+
+    /// Receives a value from one of the owned shards, blocking on all of them
+    /// if they're empty. A rebalance wakes it up to pick up its new shards.
+    pub fn recv(&self) -> Result<T, RecvError> {
+        let rung_by = RefCell::new(Vec::new());
+        self.doorbell.recv(
+            || self.try_recv(),
+            |bell| {
+                let mut rung_by = rung_by.borrow_mut();
+                rung_by.clone_from(&self.shards.borrow());
+                // Without any shards, the writers leaving is all there is to wait for
+                if rung_by.is_empty() {
+                    rung_by.push(0);
+                }
+                let mut rung = true;
+                for &shard in rung_by.iter() {
+                    rung &= self.table.rings[shard].lock().add_doorbell(bell);
+                }
+                rung
+            },
+            |bell| {
+                for &shard in rung_by.borrow().iter() {
+                    self.table.rings[shard].lock().remove_doorbell(bell);
+                }
+            },
+        )
+    }
+
+    /// Hands half of the owned shards to a new receiver, spreading the load.
+    /// Returns None if this receiver owns fewer than two shards.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::mpmc_keyed_queue;
+    ///
+    /// let (_send, recv) = mpmc_keyed_queue::<u32, _, _>(4, 4, |v: &u32| *v);
+    /// let other = recv.split().unwrap();
+    /// let last = other.split().unwrap();
+    /// assert_eq!(vec![0, 1], recv.shards());
+    /// assert_eq!(vec![2], other.shards());
+    /// assert_eq!(vec![3], last.shards());
+    /// assert!(other.split().is_none());
+    /// ```
+    pub fn split(&self) -> Option<MPMCKeyedReceiver<T>> {
+        let mut state = self.table.state.lock();
+        let owned = state.owned_by(self.id);
+        if owned.len() < 2 {
+            return None;
+        }
+        let other = self.table.join(&mut state);
+        let keep = owned.len() - owned.len() / 2;
+        for &shard in &owned[keep..] {
+            state.owners[shard] = other.id;
+        }
+        self.table.moved(&state);
+        Some(other)
+    }
+
+    /// Splits the owned shards between n receivers, as evenly as possible,
+    /// so that n consumers can each take one. Panics if n is zero
+    /// or larger than the number of shards owned.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::mpmc_keyed_queue;
+    ///
+    /// let (_send, recv) = mpmc_keyed_queue::<u32, _, _>(4, 5, |v: &u32| *v);
+    /// let recvs = recv.split_into(3);
+    /// assert_eq!(vec![0, 3], recvs[0].shards());
+    /// assert_eq!(vec![1, 4], recvs[1].shards());
+    /// assert_eq!(vec![2], recvs[2].shards());
+    /// ```
+    pub fn split_into(self, n: usize) -> Vec<MPMCKeyedReceiver<T>> {
+        let mut state = self.table.state.lock();
+        let owned = state.owned_by(self.id);
+        assert!(
+            n > 0 && n <= owned.len(),
+            "Multiqueue error - can't split {} shards between {} receivers",
+            owned.len(),
+            n
+        );
+        let others: Vec<_> = (1..n).map(|_| self.table.join(&mut state)).collect();
+        for (i, &shard) in owned.iter().enumerate() {
+            if i % n != 0 {
+                state.owners[shard] = others[i % n - 1].id;
+            }
+        }
+        self.table.moved(&state);
+        drop(state);
+        let mut receivers = vec![self];
+        receivers.extend(others);
+        receivers
+    }
+
+    /// Moves shards between every receiver on the queue so that each owns about
+    /// as many as the others, with the values waiting on them spread as evenly as
+    /// possible. This can be called from any receiver while the others keep
+    /// receiving. Shards move along with everything waiting on them, and each
+    /// receiver picks up its new shards the next time it receives, so each key's
+    /// values are still received in order by whoever owns its shard.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::mpmc_keyed_queue;
+    /// use std::thread;
+    ///
+    /// let (send, recv) = mpmc_keyed_queue(8, 4, |v: &usize| *v);
+    /// let other = recv.split().unwrap();
+    /// // Pile everything onto the first receiver's shards
+    /// for val in (0..64).filter(|v| recv.shards().contains(&send.shard_of(v))) {
+    ///     let _ = send.try_send(val);
+    /// }
+    /// drop(send);
+    /// // The other receiver is busy on its own thread the whole time
+    /// let handle = thread::spawn(move || other.count());
+    /// recv.rebalance();
+    /// assert_eq!(2, recv.shards().len());
+    /// let got = recv.count() + handle.join().unwrap();
+    /// assert_eq!(16, got);
+    /// ```
+    pub fn rebalance(&self) {
+        let mut state = self.table.state.lock();
+        let n = state.receivers.len();
+        let mut shards: Vec<_> = (0..state.owners.len())
+            .map(|shard| (self.table.rings[shard].lock().lag(), shard))
+            .collect();
+        // The busiest shards are placed first, each with whoever has the least waiting
+        shards.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+        let (base, extra) = (shards.len() / n, shards.len() % n);
+        let mut loads = vec![0; n];
+        let mut counts = vec![0; n];
+        for (lag, shard) in shards {
+            let at = (0..n)
+                .filter(|&i| counts[i] < base + (i < extra) as usize)
+                .min_by_key(|&i| (loads[i], counts[i]))
+                .unwrap();
+            loads[at] += lag;
+            counts[at] += 1;
+            state.owners[shard] = state.receivers[at].0;
+        }
+        self.table.moved(&state);
+    }
+
+    /// Takes over the shards owned by other
+    pub fn merge(&self, other: MPMCKeyedReceiver<T>) {
+        let mut state = self.table.state.lock();
+        for owner in state.owners.iter_mut() {
+            if *owner == other.id {
+                *owner = self.id;
+            }
+        }
+        self.table.moved(&state);
+    }
+
+    /// Returns the shards this receiver owns
+    pub fn shards(&self) -> Vec<usize> {
+        self.refresh();
+        self.shards.borrow().clone()
+    }
+
+    /// Returns the number of items waiting on each owned shard
+    pub fn shard_lags(&self) -> Vec<(usize, usize)> {
+        self.refresh();
+        self.shards
+            .borrow()
+            .iter()
+            .map(|&shard| (shard, self.table.rings[shard].lock().lag()))
+            .collect()
+    }
+
+    /// Removes this receiver from the queue, handing the shards it owns to the
+    /// others. The queue is disconnected once the last receiver is gone
+    pub fn unsubscribe(self) {}
+}
+
+impl<T> Drop for MPMCKeyedReceiver<T> {
+    fn drop(&mut self) {
+        let mut state = self.table.state.lock();
+        state.receivers.retain(|&(id, _)| id != self.id);
+        if state.receivers.is_empty() {
+            return;
+        }
+        // Each shard goes to whoever has the fewest
+        for shard in state.owned_by(self.id) {
+            let to = state
+                .receivers
+                .iter()
+                .map(|&(id, _)| id)
+                .min_by_key(|&id| state.owners.iter().filter(|&&o| o == id).count())
+                .unwrap();
+            state.owners[shard] = to;
+        }
+        self.table.moved(&state);
+    }
+}
+
+impl<T> Iterator for MPMCKeyedReceiver<T> {
+    type Item = T;
+
+    #[inline(always)]
+    fn next(&mut self) -> Option<T> {
+        self.recv().ok()
+    }
+}
+
+/// Creates a (```MPMCKeyedSender```, ```MPMCKeyedReceiver```) pair with the passed
+/// number of shards, each of which holds capacity values. Values are sent to
+/// the shard their key hashes to.
+///
+/// # Examples
+///
+/// ```
+/// use multiqueue2::mpmc_keyed_queue;
+/// let (w, r) = mpmc_keyed_queue(4, 2, |v: &u32| *v % 10);
+/// w.try_send(11).unwrap();
+/// w.try_send(21).unwrap();
+/// assert_eq!(w.shard_of(&11), w.shard_of(&21));
+/// assert_eq!(11, r.try_recv().unwrap());
+/// assert_eq!(21, r.try_recv().unwrap());
+/// ```
+pub fn mpmc_keyed_queue<T, K, F>(
+    capacity: usize,
+    shards: usize,
+    key: F,
+) -> (MPMCKeyedSender<T>, MPMCKeyedReceiver<T>)
+where
+    K: Hash,
+    F: Fn(&T) -> K + Send + Sync + 'static,
+{
+    assert!(shards > 0, "Multiqueue error - zero shards received");
+    let (senders, receivers) = rings(capacity, shards);
+    let table = Arc::new(ShardTable {
+        rings: receivers.into_iter().map(parking_lot::Mutex::new).collect(),
+        state: parking_lot::Mutex::new(TableState {
+            owners: vec![0; shards],
+            receivers: Vec::new(),
+            next_receiver: 0,
+        }),
+        generation: AtomicUsize::new(0),
+    });
+    let receiver = table.join(&mut table.state.lock());
+    (
+        MPMCKeyedSender {
+            senders,
+            hash: Arc::new(move |val: &T| hash_key(&key(val))),
+        },
+        receiver,
+    )
+}
+
+unsafe impl<T: Send> Send for MPMCKeyedSender<T> {}
+unsafe impl<T: Send> Send for MPMCKeyedReceiver<T> {}
+
+#[cfg(test)]
+mod test {
+
+    use super::mpmc_keyed_queue;
+
+    extern crate crossbeam;
+    use self::crossbeam::scope;
+
+    use std::sync::mpsc::TryRecvError;
+    use std::thread::yield_now;
+
+    #[test]
+    fn test_split_merge() {
+        let (writer, reader) = mpmc_keyed_queue(4, 5, |v: &usize| *v);
+        assert_eq!(5, writer.num_shards());
+        let other = reader.split().unwrap();
+        assert_eq!(vec![0, 1, 2], reader.shards());
+        assert_eq!(vec![3, 4], other.shards());
+        let vals: Vec<_> = (0..40)
+            .filter(|v| writer.shard_of(v) == 3)
+            .take(2)
+            .collect();
+        for &val in &vals {
+            writer.try_send(val).unwrap();
+        }
+        assert_eq!(Err(TryRecvError::Empty), reader.try_recv());
+        assert_eq!(vec![(3, 2), (4, 0)], other.shard_lags());
+        assert_eq!(vals[0], other.try_recv().unwrap());
+        reader.merge(other);
+        assert_eq!(vec![0, 1, 2, 3, 4], reader.shards());
+        assert_eq!(vals[1], reader.try_recv().unwrap());
+        // Dropping a receiver hands its shards back
+        let other = reader.split().unwrap();
+        let moved = (0..40).find(|v| writer.shard_of(v) == 4).unwrap();
+        writer.try_send(moved).unwrap();
+        drop(other);
+        assert_eq!(vec![0, 1, 2, 3, 4], reader.shards());
+        assert_eq!(moved, reader.try_recv().unwrap());
+        drop(writer);
+        assert_eq!(Err(TryRecvError::Disconnected), reader.try_recv());
+    }
+
+    #[test]
+    fn test_rebalance() {
+        let (writer, reader) = mpmc_keyed_queue(8, 6, |v: &usize| *v);
+        let readers = reader.split_into(3);
+        let busy = readers[0].shards();
+        let vals: Vec<_> = (0..200)
+            .filter(|v| busy.contains(&writer.shard_of(v)))
+            .collect();
+        let mut sent = Vec::new();
+        for &val in &vals {
+            if writer.try_send(val).is_ok() {
+                sent.push(val);
+            }
+        }
+        readers[2].rebalance();
+        let mut all_shards = Vec::new();
+        let mut got = Vec::new();
+        for reader in &readers {
+            assert_eq!(2, reader.shards().len());
+            all_shards.extend(reader.shards());
+            while let Ok(val) = reader.try_recv() {
+                got.push(val);
+            }
+        }
+        // Both busy shards went to different receivers, and nothing was lost
+        assert!(readers.iter().all(|r| r.shards() != busy));
+        all_shards.sort();
+        assert_eq!(vec![0, 1, 2, 3, 4, 5], all_shards);
+        got.sort();
+        sent.sort();
+        assert_eq!(sent, got);
+    }
+
+    #[test]
+    fn test_live_rebalance() {
+        let num_loop = 20000;
+        let num_keys = 16;
+        let (writer, reader) = mpmc_keyed_queue(8, 8, |v: &(usize, usize)| v.0);
+        let mut readers = reader.split_into(3);
+        let rebalancer = readers.pop().unwrap();
+        scope(|scope| {
+            let handles: Vec<_> = readers
+                .into_iter()
+                .map(|reader| {
+                    scope.spawn(move |_| {
+                        let mut got = Vec::new();
+                        for val in reader {
+                            got.push(val);
+                        }
+                        got
+                    })
+                })
+                .collect();
+            // Shards move around while the other receivers are blocked or receiving
+            let mover = scope.spawn(move |_| {
+                let mut got = Vec::new();
+                for _ in 0..200 {
+                    rebalancer.rebalance();
+                    for _ in 0..10 {
+                        if let Ok(val) = rebalancer.try_recv() {
+                            got.push(val);
+                        }
+                    }
+                    yield_now();
+                }
+                got
+            });
+            for i in 0..num_loop {
+                while writer.try_send((i % num_keys, i / num_keys)).is_err() {
+                    yield_now();
+                }
+            }
+            drop(writer);
+            let mut by_key = vec![Vec::new(); num_keys];
+            let mut total = 0;
+            let moved = mover.join().unwrap();
+            for got in handles
+                .into_iter()
+                .map(|h| h.join().unwrap())
+                .chain(Some(moved))
+            {
+                // Each receiver got every key's values in order
+                let mut last = vec![None; num_keys];
+                for (key, i) in got {
+                    assert!(last[key] < Some(i));
+                    last[key] = Some(i);
+                    by_key[key].push(i);
+                    total += 1;
+                }
+            }
+            assert_eq!(num_loop, total);
+            for vals in by_key.iter_mut() {
+                vals.sort_unstable();
+                assert_eq!((0..num_loop / num_keys).collect::<Vec<_>>(), *vals);
+            }
+        })
+        .unwrap();
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_split_into_too_many() {
+        let (_writer, reader) = mpmc_keyed_queue(4, 2, |v: &usize| *v);
+        let _ = reader.split_into(3);
+    }
+}
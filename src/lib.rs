@@ -301,27 +301,169 @@
 //! }
 //! ```
 
-#![cfg_attr(feature = "cargo-clippy", allow(clippy::inline_always))]
+#![allow(clippy::inline_always, clippy::upper_case_acronyms)]
+// Atomics.wait and notify are still unstable, as is building for wasm32 with atomics
+#![cfg_attr(
+    all(target_arch = "wasm32", target_feature = "atomics"),
+    feature(stdarch_wasm_atomic_wait)
+)]
 
+mod acked;
 mod alloc;
+mod arc;
 mod atomicsignal;
+mod batched;
+mod boxed;
+mod bridge;
 mod broadcast;
+pub mod broadcast_compat;
+#[cfg(feature = "bytes")]
+mod bytes_queue;
+mod clock;
+mod conflate;
 mod consume;
 mod countedindex;
+mod dead_letter;
+mod dedup;
+mod error;
+#[cfg(feature = "fault_injection")]
+mod faults;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod fut_mpsc_compat;
+#[cfg(feature = "tokio")]
+mod fut_streams;
+mod group;
+mod inspector;
+#[cfg(feature = "test-util")]
+mod invariants;
+mod io;
+mod keyed;
 mod maybe_acquire;
-mod memory;
+pub mod memory;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod merged;
 mod mpmc;
+pub mod mpsc_compat;
 mod multiqueue;
+#[cfg(feature = "order_checks")]
+mod order_check;
+mod ordering;
+#[cfg(feature = "rayon")]
+mod par_iter;
+mod partitioned;
+mod priority;
+mod rate_limited;
 mod read_cursor;
+#[cfg(feature = "registry")]
+pub mod registry;
+mod router;
+pub mod rpc;
+mod shards;
+#[cfg(feature = "test-util")]
+pub mod sim;
+mod stats;
+mod sync;
+mod tee;
+#[cfg(feature = "tracing")]
+mod trace;
 pub mod wait;
+mod workers;
+
+pub use crate::acked::{
+    mpmc_queue_acked, mpmc_queue_acked_with_clock, mpmc_queue_acked_with_dead_letters, Delivery,
+    MPMCAckedReceiver, MPMCAckedSender,
+};
+
+pub use crate::arc::{broadcast_queue_arc, BroadcastArcReceiver, BroadcastArcSender};
+
+pub use crate::batched::BatchedSender;
 
 pub use crate::broadcast::{
-    broadcast_fut_queue, broadcast_fut_queue_with, broadcast_queue, broadcast_queue_with,
-    BroadcastFutReceiver, BroadcastFutSender, BroadcastFutUniReceiver, BroadcastReceiver,
-    BroadcastSender, BroadcastUniReceiver,
+    broadcast_fut_queue, broadcast_fut_queue_with, broadcast_queue, broadcast_queue_conflated,
+    broadcast_queue_deduplicated, broadcast_queue_delayed, broadcast_queue_expiring,
+    broadcast_queue_fixed, broadcast_queue_replacing, broadcast_queue_restored,
+    broadcast_queue_timestamped, broadcast_queue_with, broadcast_queue_with_clock,
+    broadcast_queue_with_drop_policy, broadcast_queue_with_reclaim, BroadcastBoundedReceiver,
+    BroadcastFutReceiver, BroadcastFutSender, BroadcastFutUniReceiver, BroadcastPausedReceiver,
+    BroadcastReaderToken, BroadcastReceiver, BroadcastSender, BroadcastUniReceiver,
+};
+#[cfg(feature = "metrics")]
+pub use crate::broadcast::broadcast_queue_with_metrics;
+#[allow(deprecated)]
+pub use crate::broadcast::{
+    broadcast_fut_queue_u64, broadcast_fut_queue_with_u64, broadcast_queue_u64,
+    broadcast_queue_with_u64,
+};
+
+pub use crate::bridge::{BlockingSinkAdapter, BlockingStreamAdapter};
+
+pub use crate::boxed::{mpmc_queue_boxed, MPMCBoxedReceiver, MPMCBoxedSender};
+
+#[cfg(feature = "bytes")]
+pub use crate::bytes_queue::{
+    broadcast_bytes_queue, broadcast_bytes_queue_with, BroadcastBytesSender, DEFAULT_BLOCK_SIZE,
+};
+
+pub use crate::clock::{Clock, MockClock, MonotonicClock};
+
+pub use crate::dead_letter::{dead_letter_queue, DeadLetter, DeadLetterReason, DeadLetterSender};
+
+pub use crate::error::{
+    AttachError, FrozenTrySendError, LaggedRecvError, LaggedTryRecvError, StreamsFullError,
 };
 
+#[cfg(feature = "fault_injection")]
+pub use crate::faults::FaultConfig;
+
+#[cfg(feature = "tokio")]
+pub use crate::fut_streams::StreamTasks;
+
+pub use crate::group::{ConsumerGroup, GroupMember};
+
+pub use crate::inspector::Inspector;
+
+#[cfg(feature = "test-util")]
+pub use crate::invariants::InvariantReport;
+
+pub use crate::io::{QueueReader, QueueWriter};
+
+pub use crate::keyed::{mpmc_keyed_queue, MPMCKeyedReceiver, MPMCKeyedSender};
+
+#[cfg(feature = "metrics")]
+pub use crate::metrics::QueueMetrics;
+
+pub use crate::merged::{MergeSource, MergedReceiver};
+
 pub use crate::mpmc::{
-    mpmc_fut_queue, mpmc_queue, mpmc_queue_with, MPMCFutReceiver, MPMCFutSender,
+    mpmc_fut_queue, mpmc_queue, mpmc_queue_conflated, mpmc_queue_deduplicated, mpmc_queue_delayed,
+    mpmc_queue_expiring, mpmc_queue_expiring_with_dead_letters, mpmc_queue_replacing,
+    mpmc_queue_timestamped, mpmc_queue_weighted, mpmc_queue_with, mpmc_queue_with_clock,
+    mpmc_queue_with_drop_policy, mpmc_queue_with_reclaim, MPMCFutReceiver, MPMCFutSender,
     MPMCFutUniReceiver, MPMCReceiver, MPMCSender, MPMCUniReceiver,
 };
+#[cfg(feature = "metrics")]
+pub use crate::mpmc::mpmc_queue_with_metrics;
+#[allow(deprecated)]
+pub use crate::mpmc::{mpmc_fut_queue_u64, mpmc_queue_u64, mpmc_queue_with_u64};
+pub use crate::multiqueue::{Barrier, DropPolicy, RecvGuard};
+
+#[cfg(feature = "rayon")]
+pub use crate::par_iter::MPMCParIter;
+
+pub use crate::partitioned::{
+    partitioned_queue, Partition, PartitionedReceiver, PartitionedSender,
+};
+
+pub use crate::priority::{mpmc_priority_queue, MPMCPriorityReceiver, MPMCPrioritySender};
+
+pub use crate::rate_limited::{PacedReceiver, RateLimitedFutSender, RateLimitedSender};
+
+pub use crate::router::{Router, RouterReceiver};
+
+pub use crate::stats::{MemoryFootprint, QueueStats, StreamStats};
+
+pub use crate::tee::{Tee, TeeTarget};
+
+pub use crate::workers::WorkerPool;
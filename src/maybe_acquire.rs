@@ -2,19 +2,22 @@ use std::sync::atomic::Ordering;
 
 #[cfg(not(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64")))]
 mod theimpl {
-    use std::sync::atomic::{fence, Ordering};
-    pub const MAYBE_ACQUIRE: Ordering = Ordering::Relaxed;
+    use crate::ordering::{ACQUIRE, RELAXED};
+    use crate::sync::fence;
+    use std::sync::atomic::Ordering;
+    pub const MAYBE_ACQUIRE: Ordering = RELAXED;
 
     #[inline(always)]
     pub fn maybe_acquire_fence() {
-        fence(Ordering::Acquire)
+        fence(ACQUIRE)
     }
 }
 
 #[cfg(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64"))]
 mod theimpl {
+    use crate::ordering::ACQUIRE;
     use std::sync::atomic::Ordering;
-    pub const MAYBE_ACQUIRE: Ordering = Ordering::Acquire;
+    pub const MAYBE_ACQUIRE: Ordering = ACQUIRE;
 
     #[inline(always)]
     pub fn maybe_acquire_fence() {}
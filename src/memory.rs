@@ -1,38 +1,140 @@
+//! This module contains the ways the queue can reclaim the memory it
+//! replaces when streams are added or removed. Writers may still be looking
+//! at the old set of streams, so it can't just be freed on the spot.
+//! Users should not find themselves directly accessing these except for
+//! construction unless a custom Reclaim is being written.
+//!
+//! # Examples
+//!
+//! ```
+//! use multiqueue2::memory::*;
+//! use multiqueue2::mpmc_queue_with_reclaim;
+//! let _ = mpmc_queue_with_reclaim::<usize, _>(10, EpochReclaim::new());
+//! let _ = mpmc_queue_with_reclaim::<usize, _>(10, CrossbeamReclaim::new());
+//! let _ = mpmc_queue_with_reclaim::<usize, _>(10, LeakReclaim::new());
+//! ```
 use std::mem;
 use std::ptr;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Mutex;
+use std::sync::Arc;
+
+extern crate crossbeam;
+use self::crossbeam::epoch::{self, Guard};
 
 use crate::alloc;
 use crate::atomicsignal::AtomicSignal;
 use crate::maybe_acquire::{maybe_acquire_fence, MAYBE_ACQUIRE};
+use crate::ordering::{ACQUIRE, RELAXED, RELEASE, SEQ_CST};
+use crate::sync::{AtomicUsize, Mutex};
 
-struct ToFree {
+/// Memory the queue no longer points to, along with how to free it
+pub struct Garbage {
     mem: *mut u8,
     num_param: usize,
+    bytes: usize,
     freer: unsafe fn(*mut u8, usize),
 }
 
+/// Identifies a sender or receiver to the backend it was registered with.
+/// Backends which don't keep track of handles can hand out anything
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ReclaimToken(pub usize);
+
+/// Lets a backend ask every sender and receiver to call
+/// ```Reclaim::quiesce``` on their next send or receive
+pub struct ReclaimSignal<'a> {
+    signal: &'a AtomicSignal,
+}
+
+/// Keeps retired memory alive while the queue looks at its streams
+/// outside of a send or receive, for backends which need it
+pub struct ReclaimGuard {
+    _guard: Option<Guard>,
+}
+
+/// This is the trait that something must implement to decide when memory
+/// the queue has replaced can be freed. Sends and receives only pay for
+/// a load of the signal unless the backend raises it
+pub trait Reclaim: Send + Sync {
+    /// Called once for each sender and receiver as it's created
+    fn register(&self) -> ReclaimToken;
+
+    /// Called once for each sender and receiver as it goes away
+    fn unregister(&self, token: ReclaimToken, signal: ReclaimSignal<'_>);
+
+    /// Called by the handle with the token at the start of a send or receive,
+    /// after the signal was raised. The handle holds no pointers into
+    /// retired memory at this point
+    fn quiesce(&self, token: ReclaimToken);
+
+    /// Hands over memory which must be freed once no handle can be looking at it
+    fn retire(&self, garbage: Garbage, signal: ReclaimSignal<'_>);
+
+    /// Asks the backend to free what it's holding as soon as it can, since
+    /// something is waiting on it. Backends which free promptly can leave this be
+    fn hurry(&self, _signal: ReclaimSignal<'_>) {}
+
+    /// Whether the queue has to call ```protect``` around looking at its streams
+    fn needs_protect(&self) -> bool {
+        false
+    }
+
+    /// Returns a guard which keeps retired memory alive until it's dropped
+    fn protect(&self) -> ReclaimGuard {
+        ReclaimGuard::none()
+    }
+
+    /// Returns how many bytes the backend is holding on to for the queue:
+    /// its own bookkeeping along with retired memory it hasn't freed yet
+    fn footprint(&self) -> usize {
+        0
+    }
+}
+
 /// This is a unique token representing a subscriber to the multiqueue
-pub struct MemToken {
+struct MemToken {
     epoch: AtomicUsize,
 }
 
-struct MemoryManagerInner {
+struct EpochInner {
     tokens: Vec<*const MemToken>,
-    tofree: Vec<ToFree>,
+    tofree: Vec<Garbage>,
     epoch: usize,
 }
 
-pub struct MemoryManager {
-    mem_manager: Mutex<MemoryManagerInner>,
-    wait_to_free: Mutex<Vec<ToFree>>,
+/// The default backend. Every sender and receiver holds a token which it moves
+/// up to the current epoch when signalled, and memory is freed once each
+/// token has seen the epoch after the one it was retired in
+pub struct EpochReclaim {
+    mem_manager: Mutex<EpochInner>,
+    wait_to_free: Mutex<Vec<Garbage>>,
     epoch: AtomicUsize,
+}
+
+/// Frees memory through crossbeam's global epoch collector instead of tokens,
+/// so senders and receivers never have to be signalled. Looking at the streams
+/// pins the current thread instead, which makes writers that have to reload
+/// where the streams are a bit slower
+pub struct CrossbeamReclaim {
+    _priv: (),
+}
+
+/// Never frees anything until the queue itself is dropped. This is
+/// for queues whose streams are all set up front, where nothing is
+/// retired after that and token bookkeeping would be wasted
+pub struct LeakReclaim {
+    leaked: Mutex<Vec<Garbage>>,
+}
+
+/// What the queue holds on to: the backend, along with the signal
+/// the senders and receivers check on each send or receive
+pub(crate) struct MemoryManager {
+    backend: Arc<dyn Reclaim>,
+    protects: bool,
     pub signal: AtomicSignal,
 }
 
-impl ToFree {
-    pub fn new<T>(val: *mut T, num: usize) -> ToFree {
+impl Garbage {
+    fn new<T>(val: *mut T, num: usize) -> Garbage {
         unsafe fn do_free<F>(pt: *mut u8, num: usize) {
             let to_free: *mut F = pt as *mut F;
             for i in 0..num as isize {
@@ -40,28 +142,73 @@ impl ToFree {
             }
             alloc::deallocate(to_free, num);
         }
-        ToFree {
+        Garbage {
             mem: val as *mut u8,
             num_param: num,
+            bytes: mem::size_of::<T>() * num,
             freer: do_free::<T>,
         }
     }
 
+    /// Memory which stays where it is and is handed back by running release
+    /// on it instead of being freed, so it doesn't count as taking anything up
+    fn release<T>(val: *mut T, release: unsafe fn(*mut u8, usize)) -> Garbage {
+        Garbage {
+            mem: val as *mut u8,
+            num_param: 1,
+            bytes: 0,
+            freer: release,
+        }
+    }
+
+    /// Returns how many bytes the memory takes up
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Drops and frees the memory
     pub fn delete(self) {
         unsafe { (self.freer)(self.mem, self.num_param) }
     }
 }
 
-impl MemoryManagerInner {
-    pub fn new() -> MemoryManagerInner {
-        MemoryManagerInner {
+impl<'a> ReclaimSignal<'a> {
+    /// Asks every sender and receiver to call quiesce
+    pub fn raise(&self) {
+        self.signal.set_epoch(RELEASE);
+    }
+
+    /// Stops senders and receivers from calling quiesce
+    pub fn clear(&self) {
+        self.signal.clear_epoch(RELEASE);
+    }
+}
+
+impl ReclaimGuard {
+    /// A guard which doesn't protect anything
+    pub fn none() -> ReclaimGuard {
+        ReclaimGuard { _guard: None }
+    }
+}
+
+impl From<Guard> for ReclaimGuard {
+    fn from(guard: Guard) -> ReclaimGuard {
+        ReclaimGuard {
+            _guard: Some(guard),
+        }
+    }
+}
+
+impl EpochInner {
+    fn new() -> EpochInner {
+        EpochInner {
             tokens: Vec::new(),
             tofree: Vec::new(),
             epoch: 0,
         }
     }
 
-    pub fn get_token(&mut self, at: usize) -> *const MemToken {
+    fn get_token(&mut self, at: usize) -> *const MemToken {
         let token = alloc::allocate(1);
         self.tokens.push(token as *const MemToken);
         unsafe {
@@ -75,11 +222,11 @@ impl MemoryManagerInner {
         token as *const MemToken
     }
 
-    pub fn remove_token(&mut self, token: *const MemToken) {
+    fn remove_token(&mut self, token: *const MemToken) {
         self.tokens.retain(|x| *x != token);
     }
 
-    pub fn try_freeing(&mut self, at: usize) -> bool {
+    fn try_freeing(&mut self, at: usize) -> bool {
         if self.tokens.is_empty() {
             return false;
         }
@@ -100,79 +247,259 @@ impl MemoryManagerInner {
         true
     }
 
-    pub fn add_freeable(&mut self, vec: Vec<ToFree>) {
+    fn add_freeable(&mut self, vec: Vec<Garbage>) {
         self.tofree = vec;
     }
 }
 
-impl MemoryManager {
-    pub fn new() -> MemoryManager {
-        MemoryManager {
-            mem_manager: Mutex::new(MemoryManagerInner::new()),
+impl EpochReclaim {
+    pub fn new() -> EpochReclaim {
+        EpochReclaim {
+            mem_manager: Mutex::new(EpochInner::new()),
             wait_to_free: Mutex::new(Vec::new()),
             epoch: AtomicUsize::new(0),
-            signal: AtomicSignal::new(),
         }
     }
 
-    pub fn get_token(&self) -> *const MemToken {
-        let mut inner = self.mem_manager.lock().unwrap();
-        inner.get_token(self.epoch.load(Ordering::Acquire))
-    }
-
-    pub fn remove_token(&self, token: *const MemToken) {
-        self.update_token(token);
-        let mut inner = self.mem_manager.lock().unwrap();
-        inner.remove_token(token);
-        self.free(token as *mut MemToken, 1);
-    }
-
     #[cold]
-    fn start_free(&self, elemvec: &mut Vec<ToFree>) {
+    fn start_free(&self, elemvec: &mut Vec<Garbage>, signal: &ReclaimSignal<'_>) {
         let _lock = self.mem_manager.try_lock().map(|mut inner| {
-            let cur_epoch = self.epoch.load(Ordering::Relaxed);
+            let cur_epoch = self.epoch.load(RELAXED);
             if inner.epoch == cur_epoch {
                 let mut newv = Vec::new();
                 mem::swap(&mut newv, elemvec);
                 inner.add_freeable(newv);
-                self.epoch
-                    .store(cur_epoch.wrapping_add(1), Ordering::Release);
-                self.signal.set_epoch(Ordering::Release);
+                self.epoch.store(cur_epoch.wrapping_add(1), RELEASE);
+                signal.raise();
             }
         });
     }
+}
+
+impl Reclaim for EpochReclaim {
+    fn register(&self) -> ReclaimToken {
+        let mut inner = self.mem_manager.lock().unwrap();
+        ReclaimToken(inner.get_token(self.epoch.load(ACQUIRE)) as usize)
+    }
+
+    fn unregister(&self, token: ReclaimToken, signal: ReclaimSignal<'_>) {
+        self.quiesce(token);
+        let token = token.0 as *const MemToken;
+        // The lock has to be let go first, since retire tries to take it
+        self.mem_manager.lock().unwrap().remove_token(token);
+        self.retire(Garbage::new(token as *mut MemToken, 1), signal);
+    }
 
     #[cold]
-    pub fn free<T>(&self, pt: *mut T, num: usize) {
+    fn quiesce(&self, token: ReclaimToken) {
+        unsafe {
+            let token = &*(token.0 as *const MemToken);
+            let epoch = self.epoch.load(RELAXED);
+            let token_e = token.epoch.load(RELAXED);
+            if token_e != epoch {
+                token.epoch.store(epoch, RELEASE);
+            }
+        }
+    }
+
+    #[cold]
+    fn hurry(&self, signal: ReclaimSignal<'_>) {
+        let mut elemvec = self.wait_to_free.lock().unwrap();
+        {
+            let _lock = self.mem_manager.try_lock().map(|mut inner| {
+                let epoch = self.epoch.load(SEQ_CST);
+                if inner.try_freeing(epoch) {
+                    signal.clear();
+                }
+            });
+        }
+        // Starts on whatever else is waiting without holding out for a full batch
+        if !elemvec.is_empty() {
+            self.start_free(&mut elemvec, &signal);
+        }
+    }
+
+    #[cold]
+    fn retire(&self, garbage: Garbage, signal: ReclaimSignal<'_>) {
         let mut elemvec = self.wait_to_free.lock().unwrap();
-        elemvec.push(ToFree::new(pt, num));
+        elemvec.push(garbage);
         {
             let _lock = self.mem_manager.try_lock().map(|mut inner| {
-                let epoch = self.epoch.load(Ordering::SeqCst);
+                let epoch = self.epoch.load(SEQ_CST);
                 if inner.try_freeing(epoch) {
-                    self.signal.clear_epoch(Ordering::Release);
+                    signal.clear();
                 }
             });
         }
         if elemvec.len() > 20 {
-            self.start_free(&mut elemvec);
+            self.start_free(&mut elemvec, &signal);
+        }
+    }
+
+    fn footprint(&self) -> usize {
+        // The locks are taken one at a time, since retire holds the first while taking the second
+        let waiting = garbage_footprint(&self.wait_to_free.lock().unwrap());
+        let inner = self.mem_manager.lock().unwrap();
+        waiting
+            + garbage_footprint(&inner.tofree)
+            + inner.tokens.capacity() * mem::size_of::<*const MemToken>()
+            + inner.tokens.len() * mem::size_of::<MemToken>()
+    }
+}
+
+impl Default for EpochReclaim {
+    fn default() -> EpochReclaim {
+        EpochReclaim::new()
+    }
+}
+
+impl CrossbeamReclaim {
+    pub fn new() -> CrossbeamReclaim {
+        CrossbeamReclaim { _priv: () }
+    }
+}
+
+impl Reclaim for CrossbeamReclaim {
+    fn register(&self) -> ReclaimToken {
+        ReclaimToken(0)
+    }
+
+    fn unregister(&self, _token: ReclaimToken, _signal: ReclaimSignal<'_>) {}
+
+    fn quiesce(&self, _token: ReclaimToken) {}
+
+    #[cold]
+    fn retire(&self, garbage: Garbage, _signal: ReclaimSignal<'_>) {
+        let guard = epoch::pin();
+        guard.defer(move || garbage.delete());
+        // Values can sit around in the thread local bags for a long time otherwise
+        guard.flush();
+    }
+
+    fn needs_protect(&self) -> bool {
+        true
+    }
+
+    fn protect(&self) -> ReclaimGuard {
+        ReclaimGuard::from(epoch::pin())
+    }
+}
+
+impl Default for CrossbeamReclaim {
+    fn default() -> CrossbeamReclaim {
+        CrossbeamReclaim::new()
+    }
+}
+
+impl LeakReclaim {
+    pub fn new() -> LeakReclaim {
+        LeakReclaim {
+            leaked: Mutex::new(Vec::new()),
         }
     }
+}
+
+impl Reclaim for LeakReclaim {
+    fn register(&self) -> ReclaimToken {
+        ReclaimToken(0)
+    }
+
+    fn unregister(&self, _token: ReclaimToken, _signal: ReclaimSignal<'_>) {}
+
+    fn quiesce(&self, _token: ReclaimToken) {}
 
     #[cold]
-    pub fn update_token(&self, val: *const MemToken) {
-        unsafe {
-            let token = &*val;
-            let epoch = self.epoch.load(Ordering::Relaxed);
-            let token_e = token.epoch.load(Ordering::Relaxed);
-            if token_e != epoch {
-                token.epoch.store(epoch, Ordering::Release);
-            }
+    fn retire(&self, garbage: Garbage, _signal: ReclaimSignal<'_>) {
+        self.leaked.lock().unwrap().push(garbage);
+    }
+
+    fn footprint(&self) -> usize {
+        garbage_footprint(&self.leaked.lock().unwrap())
+    }
+}
+
+impl Default for LeakReclaim {
+    fn default() -> LeakReclaim {
+        LeakReclaim::new()
+    }
+}
+
+impl MemoryManager {
+    pub fn new() -> MemoryManager {
+        MemoryManager::with_backend(Arc::new(EpochReclaim::new()))
+    }
+
+    pub fn with_backend(backend: Arc<dyn Reclaim>) -> MemoryManager {
+        MemoryManager {
+            protects: backend.needs_protect(),
+            backend,
+            signal: AtomicSignal::new(),
+        }
+    }
+
+    fn reclaim_signal(&self) -> ReclaimSignal<'_> {
+        ReclaimSignal {
+            signal: &self.signal,
         }
     }
+
+    pub fn get_token(&self) -> ReclaimToken {
+        self.backend.register()
+    }
+
+    pub fn remove_token(&self, token: ReclaimToken) {
+        self.backend.unregister(token, self.reclaim_signal());
+    }
+
+    #[cold]
+    pub fn update_token(&self, token: ReclaimToken) {
+        self.backend.quiesce(token);
+    }
+
+    #[cold]
+    pub fn free<T>(&self, pt: *mut T, num: usize) {
+        self.backend
+            .retire(Garbage::new(pt, num), self.reclaim_signal());
+    }
+
+    /// Runs release on the passed memory once nobody can be looking at it,
+    /// instead of freeing it
+    #[cold]
+    pub fn release<T>(&self, pt: *mut T, release: unsafe fn(*mut u8, usize)) {
+        self.backend
+            .retire(Garbage::release(pt, release), self.reclaim_signal());
+    }
+
+    /// Asks the backend to free what it's holding as soon as it can
+    #[cold]
+    pub fn hurry(&self) {
+        self.backend.hurry(self.reclaim_signal());
+    }
+
+    /// Returns how many bytes the backend takes up along with what it's holding on to
+    pub fn footprint(&self) -> usize {
+        // The backend lives behind an Arc, whose counts come before it
+        2 * mem::size_of::<usize>() + mem::size_of_val(&*self.backend) + self.backend.footprint()
+    }
+
+    /// Has to be held while looking at the streams outside of a send or receive
+    #[inline(always)]
+    pub fn protect(&self) -> ReclaimGuard {
+        if self.protects {
+            self.backend.protect()
+        } else {
+            ReclaimGuard::none()
+        }
+    }
+}
+
+/// Returns how many bytes a list of retired memory takes up, along with the memory itself
+fn garbage_footprint(garbage: &Vec<Garbage>) -> usize {
+    garbage.capacity() * mem::size_of::<Garbage>()
+        + garbage.iter().map(Garbage::bytes).sum::<usize>()
 }
 
-impl Drop for MemoryManagerInner {
+impl Drop for EpochInner {
     fn drop(&mut self) {
         for val in self.tofree.drain(..) {
             val.delete();
@@ -180,4 +507,22 @@ impl Drop for MemoryManagerInner {
     }
 }
 
-unsafe impl Send for ToFree {}
+impl Drop for EpochReclaim {
+    fn drop(&mut self) {
+        // Nobody is left to be reading what's waiting for an epoch to pass
+        for val in self.wait_to_free.lock().unwrap().drain(..) {
+            val.delete();
+        }
+    }
+}
+
+impl Drop for LeakReclaim {
+    fn drop(&mut self) {
+        for val in self.leaked.lock().unwrap().drain(..) {
+            val.delete();
+        }
+    }
+}
+
+unsafe impl Send for Garbage {}
+unsafe impl Send for EpochInner {}
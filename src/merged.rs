@@ -0,0 +1,430 @@
+//! Support for fanning several queues into a single receiver
+
+use crate::multiqueue::{InnerRecv, QueueRW};
+use crate::wait::Doorbell;
+
+extern crate crossbeam;
+use self::crossbeam::channel::Sender;
+
+use std::cell::Cell;
+use std::sync::mpsc::{RecvError, TryRecvError};
+
+trait Source<T> {
+    fn try_recv(&self) -> Result<T, TryRecvError>;
+    fn add_doorbell(&self, bell: &Sender<()>) -> bool;
+    fn remove_doorbell(&self, bell: &Sender<()>);
+}
+
+impl<RW: QueueRW<T>, T> Source<T> for InnerRecv<RW, T> {
+    #[inline(always)]
+    fn try_recv(&self) -> Result<T, TryRecvError> {
+        InnerRecv::try_recv(self)
+    }
+
+    fn add_doorbell(&self, bell: &Sender<()>) -> bool {
+        InnerRecv::add_doorbell(self, bell)
+    }
+
+    fn remove_doorbell(&self, bell: &Sender<()>) {
+        InnerRecv::remove_doorbell(self, bell)
+    }
+}
+
+/// A receiver which has been handed over to a ```MergedReceiver```.
+/// These are made from ```BroadcastReceiver```s and ```MPMCReceiver```s with ```into```
+pub struct MergeSource<T> {
+    receiver: Box<dyn Source<T>>,
+}
+
+impl<T> MergeSource<T> {
+    pub(crate) fn new<RW: QueueRW<T> + 'static>(receiver: InnerRecv<RW, T>) -> MergeSource<T>
+    where
+        T: 'static,
+    {
+        MergeSource {
+            receiver: Box::new(receiver),
+        }
+    }
+}
+
+/// This receives from several queues at once, taking turns between the ones
+/// with data available so that a busy queue can't starve the rest.
+/// One made with ```MergedReceiver::prioritized``` instead always receives
+/// from the first queue added which has data available.
+/// It is disconnected once every queue it receives from is disconnected.
+///
+/// # Examples
+///
+/// ```
+/// use multiqueue2::{broadcast_queue, mpmc_queue, MergedReceiver};
+///
+/// let (bsend, brecv) = broadcast_queue(4);
+/// let (msend, mrecv) = mpmc_queue(4);
+///
+/// let mut merged = MergedReceiver::new();
+/// merged.add(brecv);
+/// merged.add(mrecv);
+///
+/// bsend.try_send(1).unwrap();
+/// bsend.try_send(2).unwrap();
+/// msend.try_send(10).unwrap();
+///
+/// // The queues take turns
+/// assert_eq!(1, merged.try_recv().unwrap());
+/// assert_eq!(10, merged.try_recv().unwrap());
+/// assert_eq!(2, merged.try_recv().unwrap());
+/// ```
+pub struct MergedReceiver<T> {
+    sources: Vec<MergeSource<T>>,
+    next: Cell<usize>,
+    prioritized: bool,
+    doorbell: Doorbell,
+}
+
+impl<T> MergedReceiver<T> {
+    /// Creates a ```MergedReceiver``` without any queues to receive from
+    pub fn new() -> MergedReceiver<T> {
+        MergedReceiver {
+            sources: Vec::new(),
+            next: Cell::new(0),
+            prioritized: false,
+            doorbell: Doorbell::new(),
+        }
+    }
+
+    /// Creates a ```MergedReceiver``` without any queues to receive from, which
+    /// always receives from the first queue added that has a value. A later queue is
+    /// only received from while every queue added before it is empty, so a busy
+    /// queue starves the ones after it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::{mpmc_queue, MergedReceiver};
+    ///
+    /// let (control_send, control_recv) = mpmc_queue(4);
+    /// let (data_send, data_recv) = mpmc_queue(4);
+    ///
+    /// let mut merged = MergedReceiver::prioritized();
+    /// merged.add(control_recv);
+    /// merged.add(data_recv);
+    ///
+    /// data_send.try_send("data 1").unwrap();
+    /// data_send.try_send("data 2").unwrap();
+    /// assert_eq!("data 1", merged.try_recv().unwrap());
+    /// control_send.try_send("stop").unwrap();
+    /// // The control queue goes first whenever it has something
+    /// assert_eq!("stop", merged.try_recv().unwrap());
+    /// assert_eq!("data 2", merged.try_recv().unwrap());
+    /// ```
+    pub fn prioritized() -> MergedReceiver<T> {
+        MergedReceiver {
+            prioritized: true,
+            ..MergedReceiver::new()
+        }
+    }
+
+    /// Returns whether this always prefers the queues added first
+    pub fn is_prioritized(&self) -> bool {
+        self.prioritized
+    }
+
+    /// Adds a receiver to take values from. For a prioritized
+    /// ```MergedReceiver``` this comes after every receiver already added
+    pub fn add<S: Into<MergeSource<T>>>(&mut self, source: S) {
+        self.sources.push(source.into());
+    }
+
+    /// Returns the number of receivers being merged
+    pub fn num_sources(&self) -> usize {
+        self.sources.len()
+    }
+
+    /// Tries to receive a value from the next queue which has one,
+    /// starting after the queue which was last received from, or from
+    /// the first queue added if this is prioritized.
+    /// Returns Disconnected once all of the queues are disconnected and empty
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::{mpmc_queue, MergedReceiver};
+    /// use std::sync::mpsc::TryRecvError;
+    ///
+    /// let (send_a, recv_a) = mpmc_queue(4);
+    /// let (send_b, recv_b) = mpmc_queue(4);
+    ///
+    /// let mut merged = MergedReceiver::new();
+    /// merged.add(recv_a);
+    /// merged.add(recv_b);
+    ///
+    /// assert_eq!(Err(TryRecvError::Empty), merged.try_recv());
+    /// send_b.try_send(1).unwrap();
+    /// drop(send_a);
+    /// assert_eq!(Ok(1), merged.try_recv());
+    /// drop(send_b);
+    /// assert_eq!(Err(TryRecvError::Disconnected), merged.try_recv());
+    /// ```
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let num = self.sources.len();
+        let start = if self.prioritized { 0 } else { self.next.get() };
+        let mut any_empty = false;
+        for i in 0..num {
+            let at = (start + i) % num;
+            match self.sources[at].receiver.try_recv() {
+                Ok(val) => {
+                    self.next.set((at + 1) % num);
+                    return Ok(val);
+                }
+                Err(TryRecvError::Empty) => any_empty = true,
+                Err(TryRecvError::Disconnected) => (),
+            }
+        }
+        if any_empty {
+            Err(TryRecvError::Empty)
+        } else {
+            Err(TryRecvError::Disconnected)
+        }
+    }
+
+    /// Receives a value from the next queue which has one, blocking on all of them
+    /// at once if they're empty. Every queue rings one doorbell shared between them
+    /// when something is sent, except for queues whose writers never notify, like
+    /// those made with ```YieldingWait```, which are checked every so often instead.
+    /// Returns Err(RecvError) once all of the queues are disconnected and empty
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::{broadcast_queue, mpmc_queue, MergedReceiver};
+    /// use std::thread;
+    ///
+    /// let (bsend, brecv) = broadcast_queue(4);
+    /// let (msend, mrecv) = mpmc_queue(4);
+    ///
+    /// let mut merged = MergedReceiver::new();
+    /// merged.add(brecv);
+    /// merged.add(mrecv);
+    ///
+    /// let handle = thread::spawn(move || {
+    ///     let mut got = Vec::new();
+    ///     while let Ok(val) = merged.recv() {
+    ///         got.push(val);
+    ///     }
+    ///     got.sort();
+    ///     got
+    /// });
+    ///
+    /// bsend.try_send(1).unwrap();
+    /// msend.try_send(2).unwrap();
+    /// drop(bsend);
+    /// drop(msend);
+    /// assert_eq!(vec![1, 2], handle.join().unwrap());
+    /// ```
+    pub fn recv(&self) -> Result<T, RecvError> {
+        self.doorbell.recv(
+            || self.try_recv(),
+            |bell| {
+                let mut rung = true;
+                for source in &self.sources {
+                    rung &= source.receiver.add_doorbell(bell);
+                }
+                rung
+            },
+            |bell| {
+                for source in &self.sources {
+                    source.receiver.remove_doorbell(bell);
+                }
+            },
+        )
+    }
+}
+
+impl<T> Default for MergedReceiver<T> {
+    fn default() -> MergedReceiver<T> {
+        MergedReceiver::new()
+    }
+}
+
+unsafe impl<T: Send + Sync> Send for MergeSource<T> {}
+unsafe impl<T: Send + Sync> Send for MergedReceiver<T> {}
+
+#[cfg(test)]
+mod test {
+
+    use super::MergedReceiver;
+    use crate::broadcast::broadcast_queue;
+    use crate::mpmc::{mpmc_queue, mpmc_queue_with};
+    use crate::wait::YieldingWait;
+
+    extern crate crossbeam;
+    use self::crossbeam::scope;
+
+    use std::sync::mpsc::TryRecvError;
+    use std::thread::{sleep, yield_now};
+    use std::time::Duration;
+
+    #[test]
+    fn test_round_robin() {
+        let (send_a, recv_a) = mpmc_queue(8);
+        let (send_b, recv_b) = mpmc_queue(8);
+        let (send_c, recv_c) = broadcast_queue(8);
+        let mut merged = MergedReceiver::new();
+        merged.add(recv_a);
+        merged.add(recv_b);
+        merged.add(recv_c);
+        assert_eq!(3, merged.num_sources());
+        for i in 0..4 {
+            send_a.try_send(i).unwrap();
+            send_c.try_send(100 + i).unwrap();
+        }
+        send_b.try_send(10).unwrap();
+        let got: Vec<_> = (0..9).map(|_| merged.try_recv().unwrap()).collect();
+        assert_eq!(vec![0, 10, 100, 1, 101, 2, 102, 3, 103], got);
+        assert_eq!(Err(TryRecvError::Empty), merged.try_recv());
+    }
+
+    #[test]
+    fn test_disconnect() {
+        let merged: MergedReceiver<usize> = MergedReceiver::new();
+        assert_eq!(Err(TryRecvError::Disconnected), merged.try_recv());
+        assert!(merged.recv().is_err());
+
+        let (send_a, recv_a) = mpmc_queue(4);
+        let (send_b, recv_b) = broadcast_queue(4);
+        let mut merged = MergedReceiver::new();
+        merged.add(recv_a);
+        merged.add(recv_b);
+        send_a.try_send(1).unwrap();
+        drop(send_a);
+        assert_eq!(Ok(1), merged.try_recv());
+        assert_eq!(Err(TryRecvError::Empty), merged.try_recv());
+        send_b.try_send(2).unwrap();
+        drop(send_b);
+        assert_eq!(Ok(2), merged.recv());
+        assert!(merged.recv().is_err());
+    }
+
+    #[test]
+    fn test_prioritized() {
+        let (send_a, recv_a) = mpmc_queue(8);
+        let (send_b, recv_b) = broadcast_queue(8);
+        let (send_c, recv_c) = mpmc_queue(8);
+        let mut merged = MergedReceiver::prioritized();
+        assert!(merged.is_prioritized());
+        merged.add(recv_a);
+        merged.add(recv_b);
+        merged.add(recv_c);
+        for i in 0..2 {
+            send_a.try_send(i).unwrap();
+            send_b.try_send(10 + i).unwrap();
+            send_c.try_send(100 + i).unwrap();
+        }
+        let got: Vec<_> = (0..4).map(|_| merged.try_recv().unwrap()).collect();
+        assert_eq!(vec![0, 1, 10, 11], got);
+        send_a.try_send(2).unwrap();
+        assert_eq!(Ok(2), merged.try_recv());
+        // Disconnected queues are passed over
+        drop(send_a);
+        drop(send_b);
+        assert_eq!(Ok(100), merged.try_recv());
+        assert_eq!(Ok(101), merged.try_recv());
+        assert_eq!(Err(TryRecvError::Empty), merged.try_recv());
+        drop(send_c);
+        assert_eq!(Err(TryRecvError::Disconnected), merged.try_recv());
+    }
+
+    #[test]
+    fn test_prioritized_threaded() {
+        let num_loop = 10000;
+        let (control_send, control_recv) = mpmc_queue(4);
+        let (data_send, data_recv) = mpmc_queue(4);
+        let mut merged = MergedReceiver::prioritized();
+        merged.add(control_recv);
+        merged.add(data_recv);
+        scope(|scope| {
+            scope.spawn(move |_| {
+                for i in 0..num_loop {
+                    while data_send.try_send(i).is_err() {
+                        yield_now();
+                    }
+                }
+            });
+            scope.spawn(move |_| {
+                let mut got = 0;
+                let mut stopped = false;
+                while let Ok(val) = merged.recv() {
+                    if val == num_loop {
+                        stopped = true;
+                    } else {
+                        assert_eq!(got, val);
+                        got += 1;
+                    }
+                }
+                assert!(stopped);
+                assert_eq!(num_loop, got);
+            });
+            control_send.try_send(num_loop).unwrap();
+            drop(control_send);
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_merged_threaded() {
+        let num_loop = 10000;
+        let num_queues = 4;
+        let mut merged = MergedReceiver::new();
+        let mut senders = Vec::new();
+        for _ in 0..num_queues {
+            let (send, recv) = mpmc_queue(4);
+            merged.add(recv);
+            senders.push(send);
+        }
+        scope(|scope| {
+            for (q, send) in senders.into_iter().enumerate() {
+                scope.spawn(move |_| {
+                    for i in 0..num_loop {
+                        while send.try_send((q, i)).is_err() {
+                            yield_now();
+                        }
+                    }
+                });
+            }
+            scope.spawn(move |_| {
+                let mut counts = vec![0; num_queues];
+                while let Ok((q, i)) = merged.recv() {
+                    assert_eq!(counts[q], i);
+                    counts[q] += 1;
+                }
+                assert_eq!(vec![num_loop; num_queues], counts);
+            });
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_blocking_wakeup() {
+        let (send_a, recv_a) = mpmc_queue(4);
+        let (send_b, recv_b) = mpmc_queue_with(4, YieldingWait::new());
+        let mut merged = MergedReceiver::new();
+        merged.add(recv_a);
+        merged.add(recv_b);
+        scope(|scope| {
+            scope.spawn(move |_| {
+                // The receiver is blocked by now, and has to be woken by each of these
+                sleep(Duration::from_millis(20));
+                send_a.try_send(1).unwrap();
+                sleep(Duration::from_millis(20));
+                send_b.try_send(2).unwrap();
+                sleep(Duration::from_millis(20));
+                drop(send_a);
+                drop(send_b);
+            });
+            assert_eq!(Ok(1), merged.recv());
+            assert_eq!(Ok(2), merged.recv());
+            assert!(merged.recv().is_err());
+        })
+        .unwrap();
+    }
+}
@@ -0,0 +1,259 @@
+//! Hooks for reporting what happens on a queue to a metrics system, only
+//! compiled in when the "metrics" feature is enabled
+
+use std::sync::Arc;
+
+/// Receives events from a queue created with metrics, such as
+/// ```broadcast_queue_with_metrics```. Every hook does nothing by default,
+/// and queues created without metrics skip them entirely. Without the
+/// "metrics" feature there's nothing to check at all.
+///
+/// Hooks are called on the sending and receiving threads in the middle of
+/// queue operations, so they should be cheap, such as bumping an atomic counter.
+///
+/// The ```on_became_``` hooks are edge triggered, for things like switching pollers
+/// on and off. The queue counts as full once a send fails and as empty once a receive
+/// finds nothing, as seen by its senders and receivers rather than by looking
+/// at the whole queue. Hooks for opposite edges can run at the same time on different
+/// threads, so to make sure the last one called is right, ```on_became_not_full```
+/// and ```on_became_not_empty``` may be called again without the other in between.
+///
+/// # Examples
+///
+/// ```
+/// use multiqueue2::{mpmc_queue_with_metrics, QueueMetrics};
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// use std::sync::Arc;
+///
+/// #[derive(Default)]
+/// struct Counts {
+///     sent: AtomicUsize,
+///     full: AtomicUsize,
+/// }
+///
+/// impl QueueMetrics for Counts {
+///     fn on_send(&self) {
+///         self.sent.fetch_add(1, Ordering::Relaxed);
+///     }
+///
+///     fn on_full(&self) {
+///         self.full.fetch_add(1, Ordering::Relaxed);
+///     }
+/// }
+///
+/// let counts = Arc::new(Counts::default());
+/// let (w, _r) = mpmc_queue_with_metrics(2, counts.clone());
+/// for i in 0..3 {
+///     let _ = w.try_send(i);
+/// }
+/// assert_eq!(2, counts.sent.load(Ordering::Relaxed));
+/// assert_eq!(1, counts.full.load(Ordering::Relaxed));
+/// ```
+pub trait QueueMetrics: Send + Sync {
+    /// Called after a value is written to the queue
+    fn on_send(&self) {}
+
+    /// Called after a value is taken off the queue, once for each stream that receives it
+    fn on_recv(&self) {}
+
+    /// Called when a send fails because the queue is full
+    fn on_full(&self) {}
+
+    /// Called when a blocking receive finds nothing and is about to wait
+    fn on_empty_wait(&self) {}
+
+    /// Called when the last writer or the last stream leaves the queue
+    fn on_disconnect(&self) {}
+
+    /// Called when a send fails because the queue is full,
+    /// unless the queue has already been reported full since it last had room
+    fn on_became_full(&self) {}
+
+    /// Called when a receive makes room in a queue which was reported full
+    fn on_became_not_full(&self) {}
+
+    /// Called when a receive finds nothing on its stream,
+    /// unless the queue has already been reported empty since the last send
+    fn on_became_empty(&self) {}
+
+    /// Called when a send puts a value into a queue which was reported empty
+    fn on_became_not_empty(&self) {}
+}
+
+impl<M: QueueMetrics + ?Sized> QueueMetrics for Arc<M> {
+    fn on_send(&self) {
+        (**self).on_send()
+    }
+
+    fn on_recv(&self) {
+        (**self).on_recv()
+    }
+
+    fn on_full(&self) {
+        (**self).on_full()
+    }
+
+    fn on_empty_wait(&self) {
+        (**self).on_empty_wait()
+    }
+
+    fn on_disconnect(&self) {
+        (**self).on_disconnect()
+    }
+
+    fn on_became_full(&self) {
+        (**self).on_became_full()
+    }
+
+    fn on_became_not_full(&self) {
+        (**self).on_became_not_full()
+    }
+
+    fn on_became_empty(&self) {
+        (**self).on_became_empty()
+    }
+
+    fn on_became_not_empty(&self) {
+        (**self).on_became_not_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::QueueMetrics;
+    use crate::broadcast::broadcast_queue_with_metrics;
+    use crate::mpmc::mpmc_queue_with_metrics;
+
+    extern crate crossbeam;
+    use self::crossbeam::scope;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread::yield_now;
+
+    #[derive(Default)]
+    struct Counts {
+        sent: AtomicUsize,
+        received: AtomicUsize,
+        full: AtomicUsize,
+        waits: AtomicUsize,
+        disconnects: AtomicUsize,
+    }
+
+    impl QueueMetrics for Counts {
+        fn on_send(&self) {
+            self.sent.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn on_recv(&self) {
+            self.received.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn on_full(&self) {
+            self.full.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn on_empty_wait(&self) {
+            self.waits.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn on_disconnect(&self) {
+            self.disconnects.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_hooks() {
+        let counts = Arc::new(Counts::default());
+        let (writer, reader) = broadcast_queue_with_metrics(2, counts.clone());
+        let stream = reader.add_stream();
+        writer.try_send(1).unwrap();
+        writer.try_send(2).unwrap();
+        assert!(writer.try_send(3).is_err());
+        assert_eq!(1, reader.try_recv().unwrap());
+        assert_eq!(Ok(2), reader.try_recv_view(|v| *v).map_err(|_| ()));
+        assert!(reader.try_recv().is_err());
+        assert_eq!(1, stream.recv().unwrap());
+        assert_eq!(2, counts.sent.load(Ordering::Relaxed));
+        assert_eq!(3, counts.received.load(Ordering::Relaxed));
+        assert_eq!(1, counts.full.load(Ordering::Relaxed));
+        assert_eq!(0, counts.waits.load(Ordering::Relaxed));
+        // Only the last stream leaving disconnects the queue
+        stream.unsubscribe();
+        assert_eq!(0, counts.disconnects.load(Ordering::Relaxed));
+        reader.unsubscribe();
+        assert_eq!(1, counts.disconnects.load(Ordering::Relaxed));
+        drop(writer);
+        assert_eq!(2, counts.disconnects.load(Ordering::Relaxed));
+    }
+
+    #[derive(Default)]
+    struct Edges(Mutex<Vec<&'static str>>);
+
+    impl QueueMetrics for Edges {
+        fn on_became_full(&self) {
+            self.0.lock().unwrap().push("full");
+        }
+
+        fn on_became_not_full(&self) {
+            self.0.lock().unwrap().push("not full");
+        }
+
+        fn on_became_empty(&self) {
+            self.0.lock().unwrap().push("empty");
+        }
+
+        fn on_became_not_empty(&self) {
+            self.0.lock().unwrap().push("not empty");
+        }
+    }
+
+    #[test]
+    fn test_edges() {
+        let edges = Arc::new(Edges::default());
+        let (writer, reader) = mpmc_queue_with_metrics(2, edges.clone());
+        assert!(reader.try_recv().is_err());
+        assert!(reader.try_recv().is_err());
+        writer.try_send(1).unwrap();
+        writer.try_send(2).unwrap();
+        assert!(writer.try_send(3).is_err());
+        assert!(writer.try_send(3).is_err());
+        assert_eq!(1, reader.try_recv().unwrap());
+        assert_eq!(2, reader.try_recv().unwrap());
+        assert!(reader.try_recv().is_err());
+        assert_eq!(
+            vec!["empty", "not empty", "full", "not full", "empty"],
+            *edges.0.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_hooks_threaded() {
+        let counts = Arc::new(Counts::default());
+        let (writer, reader) = mpmc_queue_with_metrics(4, counts.clone());
+        let num_loop = 10000;
+        scope(|scope| {
+            for _ in 0..2 {
+                let cur_writer = writer.clone();
+                scope.spawn(move |_| {
+                    for i in 0..num_loop {
+                        while cur_writer.try_send(i).is_err() {
+                            yield_now();
+                        }
+                    }
+                });
+            }
+            writer.unsubscribe();
+            for _ in 0..2 {
+                let cur_reader = reader.clone();
+                scope.spawn(move |_| for _ in cur_reader {});
+            }
+            reader.unsubscribe();
+        })
+        .unwrap();
+        assert_eq!(2 * num_loop, counts.sent.load(Ordering::Relaxed));
+        assert_eq!(2 * num_loop, counts.received.load(Ordering::Relaxed));
+        assert_eq!(2, counts.disconnects.load(Ordering::Relaxed));
+    }
+}
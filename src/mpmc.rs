@@ -1,20 +1,52 @@
-use crate::countedindex::Index;
+use crate::batched::BatchedSender;
+use crate::bridge::{BlockingSinkAdapter, BlockingStreamAdapter};
+use crate::clock::Clock;
+use crate::conflate::KeyConflator;
+use crate::countedindex::capacity_from_u64;
+use crate::dead_letter::DeadLetterSender;
+use crate::dedup::IdWindow;
+use crate::error::FrozenTrySendError;
+#[cfg(feature = "fault_injection")]
+use crate::faults::FaultConfig;
+use crate::inspector::Inspector;
+#[cfg(feature = "test-util")]
+use crate::invariants::InvariantReport;
+use crate::io::{QueueReader, QueueWriter};
+use crate::memory::Reclaim;
+use crate::merged::MergeSource;
+#[cfg(feature = "metrics")]
+use crate::metrics::QueueMetrics;
 use crate::multiqueue::{
-    futures_multiqueue, FutInnerRecv, FutInnerSend, FutInnerUniRecv, InnerRecv, InnerSend,
-    MultiQueue, MPMC,
+    futures_multiqueue, Barrier, DropPolicy, FutInnerRecv, FutInnerSend, FutInnerUniRecv,
+    InnerRecv, InnerSend, MultiQueue, RecvGuard, MPMC,
 };
-use crate::wait::Wait;
-
-use std::sync::mpsc::{RecvError, SendError, TryRecvError, TrySendError};
+#[cfg(feature = "rayon")]
+use crate::par_iter::MPMCParIter;
+use crate::rate_limited::{PacedReceiver, RateLimitedFutSender, RateLimitedSender};
+use crate::stats::{MemoryFootprint, QueueStats};
+use crate::tee::TeeTarget;
+use crate::wait::{DefaultWait, Wait};
+use crate::workers::WorkerPool;
+
+use std::borrow::Cow;
+use std::fmt;
+use std::hash::Hash;
+use std::sync::mpsc::{RecvError, SendError, TryRecvError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::vec;
 
 extern crate futures;
-use self::futures::{Async, Poll, Sink, StartSend, Stream};
+use self::futures::{Poll, Sink, StartSend, Stream};
 
 /// This class is the sending half of the mpmc ```MultiQueue```. It supports both
 /// single and multi consumer modes with competitive performance in each case.
 /// It only supports nonblocking writes (the futures sender being an exception)
 /// as well as being the conduit for adding new writers.
 ///
+/// Counts and lags such as ```max_lag```, ```writer_count``` and ```MPMCReceiver::lag```
+/// are read while the queue keeps running, so they may be stale by the time they are used.
+///
 /// # Examples
 ///
 /// ```
@@ -57,9 +89,9 @@ use self::futures::{Async, Poll, Sink, StartSend, Stream};
 /// // Consumer 0 got 1
 /// // etc
 /// ```
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct MPMCSender<T> {
-    sender: InnerSend<MPMC<T>, T>,
+    pub(crate) sender: InnerSend<MPMC<T>, T>,
 }
 
 /// This is the receiving end of a standard mpmc view of the queue
@@ -67,7 +99,7 @@ pub struct MPMCSender<T> {
 /// is only ever one stream. As a result, the type doesn't need to be clone
 #[derive(Debug)]
 pub struct MPMCReceiver<T> {
-    receiver: InnerRecv<MPMC<T>, T>,
+    pub(crate) receiver: InnerRecv<MPMC<T>, T>,
 }
 
 impl<T> Clone for MPMCReceiver<T> {
@@ -82,18 +114,21 @@ impl<T> Clone for MPMCReceiver<T> {
 /// for when it's statically know that there is only one receiver.
 /// It functions similarly to the ```BroadcastUniReceiver``` execpt there
 /// is only ever one stream. As a result, the type doesn't need to be clone or sync
+#[derive(Debug)]
 pub struct MPMCUniReceiver<T> {
     receiver: InnerRecv<MPMC<T>, T>,
 }
 
 /// This is the futures-compatible version of ```MPMCSender```
 /// It implements Sink
+#[derive(Debug)]
 pub struct MPMCFutSender<T> {
     sender: FutInnerSend<MPMC<T>, T>,
 }
 
 /// This is the futures-compatible version of ```MPMCReceiver```
 /// It implements Stream
+#[derive(Debug)]
 pub struct MPMCFutReceiver<T> {
     receiver: FutInnerRecv<MPMC<T>, T>,
 }
@@ -102,18 +137,283 @@ pub struct MPMCFutReceiver<T> {
 /// It implements ```Stream``` and behaves like the iterator would.
 /// To use a different function must transform itself into a different
 /// UniRecveiver use ```transform_operation```
+///
+/// Unlike ```BroadcastFutUniReceiver``` it can't be cloned. Another consumer
+/// on the stream couldn't view values in place, and another stream would take
+/// every value a second time. To share the work, turn it back into a
+/// ```MPMCFutReceiver``` with ```into_multi``` and clone that
 pub struct MPMCFutUniReceiver<R, F: FnMut(&T) -> R, T> {
     receiver: FutInnerUniRecv<MPMC<T>, R, F, T>,
 }
 
 impl<T> MPMCSender<T> {
     /// Tries to send a value into the queue
-    /// If there is no space, returns ```Err(TrySendError::Full(val))```
-    /// If there are no readers, returns ```Err(TrySendError::Disconnected(val))```
-    pub fn try_send(&self, val: T) -> Result<(), TrySendError<T>> {
+    /// If there is no space, returns ```Err(FrozenTrySendError::Full(val))```
+    /// If there are no readers, returns ```Err(FrozenTrySendError::Disconnected(val))```
+    /// If the queue is frozen, returns ```Err(FrozenTrySendError::Frozen(val))```
+    pub fn try_send(&self, val: T) -> Result<(), FrozenTrySendError<T>> {
         self.sender.try_send(val)
     }
 
+    /// Identical to ```BroadcastSender::try_send_spin```
+    pub fn try_send_spin(&self, val: T, max_attempts: usize) -> Result<(), FrozenTrySendError<T>> {
+        self.sender.try_send_spin(val, max_attempts)
+    }
+
+    /// Identical to ```BroadcastSender::write_from```
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::mpmc_queue;
+    /// let (w, r) = mpmc_queue(8);
+    /// let samples: Vec<i16> = (0..10).collect();
+    /// assert_eq!(8, w.write_from(&samples));
+    /// let mut buf = [0; 8];
+    /// assert_eq!(Ok(8), r.read_exact(&mut buf));
+    /// assert_eq!(samples[..8], buf);
+    /// ```
+    pub fn write_from(&self, vals: &[T]) -> usize
+    where
+        T: Copy,
+    {
+        self.sender.try_send_slice(vals)
+    }
+
+    /// Identical to ```BroadcastSender::try_send_all```
+    pub fn try_send_all<const N: usize>(&self, vals: &[T; N]) -> Result<(), FrozenTrySendError<()>>
+    where
+        T: Clone,
+    {
+        match self.sender.try_send_all(vals.to_vec()) {
+            Ok(()) => Ok(()),
+            Err(FrozenTrySendError::Full(_)) => Err(FrozenTrySendError::Full(())),
+            Err(FrozenTrySendError::Disconnected(_)) => Err(FrozenTrySendError::Disconnected(())),
+            Err(FrozenTrySendError::Frozen(_)) => Err(FrozenTrySendError::Frozen(())),
+        }
+    }
+
+    /// Identical to ```BroadcastSender::send_transaction```
+    pub fn send_transaction<I: IntoIterator<Item = T>>(
+        &self,
+        vals: I,
+    ) -> Result<(), FrozenTrySendError<Vec<T>>> {
+        self.sender.try_send_all(vals.into_iter().collect())
+    }
+
+    /// Identical to ```BroadcastSender::try_send_after```, except it
+    /// panics unless the queue was created with ```mpmc_queue_delayed```
+    pub fn try_send_after(&self, val: T, deadline: Instant) -> Result<(), FrozenTrySendError<T>> {
+        self.sender.try_send_after(val, deadline)
+    }
+
+    /// Identical to ```BroadcastSender::try_send_or_replace```, except it
+    /// panics unless the queue was created with ```mpmc_queue_replacing```
+    pub fn try_send_or_replace(&self, val: T) -> Result<Option<T>, FrozenTrySendError<T>> {
+        self.sender.try_send_or_replace(val)
+    }
+
+    /// Identical to ```BroadcastSender::try_send_with_ttl```, except it
+    /// panics unless the queue was created with ```mpmc_queue_expiring```
+    pub fn try_send_with_ttl(&self, val: T, ttl: Duration) -> Result<(), FrozenTrySendError<T>> {
+        self.sender.try_send_with_ttl(val, ttl)
+    }
+
+    /// Identical to ```BroadcastSender::try_send_ref```
+    pub fn try_send_ref<'a>(&self, val: &'a T) -> Result<(), FrozenTrySendError<&'a T>>
+    where
+        T: Clone,
+    {
+        self.sender.try_send_ref(val)
+    }
+
+    /// Identical to ```BroadcastSender::barrier```. Every receiver shares one stream,
+    /// so a barrier is passed once everything before it has been received by somebody
+    pub fn barrier(&self) -> Barrier {
+        self.sender.barrier()
+    }
+
+    /// Identical to ```BroadcastSender::try_send_barrier```. Only one
+    /// receiver gets the marker, like any other value
+    pub fn try_send_barrier(&self, marker: T) -> Result<Barrier, FrozenTrySendError<T>> {
+        self.sender.try_send_barrier(marker)
+    }
+
+    /// Identical to ```BroadcastSender::barrier_passed```
+    pub fn barrier_passed(&self, barrier: Barrier) -> bool {
+        self.sender.barrier_passed(barrier)
+    }
+
+    /// Identical to ```BroadcastSender::wait_for_barrier```
+    pub fn wait_for_barrier(&self, barrier: Barrier) {
+        self.sender.wait_for_barrier(barrier)
+    }
+
+    /// Identical to ```BroadcastSender::wait_for_barrier_timeout```
+    pub fn wait_for_barrier_timeout(&self, barrier: Barrier, timeout: Duration) -> bool {
+        self.sender.wait_for_barrier_timeout(barrier, timeout)
+    }
+
+    /// Returns how many items are waiting to be received.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::mpmc_queue;
+    /// let (w, r) = mpmc_queue(4);
+    /// w.try_send(1).unwrap();
+    /// w.try_send(2).unwrap();
+    /// assert_eq!(2, w.max_lag());
+    /// r.try_recv().unwrap();
+    /// assert_eq!(1, w.max_lag());
+    /// ```
+    pub fn max_lag(&self) -> usize {
+        self.sender.max_lag()
+    }
+
+    /// Returns how many values the queue holds, which is the capacity it was
+    /// made with rounded up to a power of two
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::mpmc_queue;
+    /// let (w, r) = mpmc_queue::<usize>(5);
+    /// assert_eq!(8, w.capacity());
+    /// assert_eq!(8, r.capacity());
+    /// ```
+    pub fn capacity(&self) -> usize {
+        self.sender.capacity()
+    }
+
+    /// Returns the number of writers subscribed to the queue.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::mpmc_queue;
+    /// let (w, r) = mpmc_queue::<usize>(4);
+    /// let w2 = w.clone();
+    /// assert_eq!(2, w.writer_count());
+    /// w2.unsubscribe();
+    /// assert_eq!(1, r.writer_count());
+    /// ```
+    pub fn writer_count(&self) -> usize {
+        self.sender.writer_count()
+    }
+
+    /// Identical to ```BroadcastSender::freeze```
+    pub fn freeze(&self) {
+        self.sender.freeze()
+    }
+
+    /// Identical to ```BroadcastSender::unfreeze```
+    pub fn unfreeze(&self) {
+        self.sender.unfreeze()
+    }
+
+    /// Identical to ```BroadcastSender::is_frozen```
+    pub fn is_frozen(&self) -> bool {
+        self.sender.is_frozen()
+    }
+
+    /// Identical to ```BroadcastSender::stats```
+    pub fn stats(&self) -> QueueStats {
+        self.sender.stats()
+    }
+
+    /// Identical to ```BroadcastSender::memory_footprint```
+    pub fn memory_footprint(&self) -> MemoryFootprint {
+        self.sender.memory_footprint()
+    }
+
+    /// Identical to ```BroadcastSender::inspector```, except the inspector
+    /// can't clone out the values waiting in the queue
+    pub fn inspector(&self) -> Inspector<T>
+    where
+        T: 'static,
+    {
+        Inspector::new(self.sender.shared_queue())
+    }
+
+    /// Identical to ```BroadcastSender::check_invariants```
+    #[cfg(feature = "test-util")]
+    pub fn check_invariants(&self) -> InvariantReport {
+        self.sender.check_invariants()
+    }
+
+    /// Identical to ```BroadcastSender::inject_faults```
+    #[cfg(feature = "fault_injection")]
+    pub fn inject_faults(&self, config: FaultConfig) {
+        self.sender.inject_faults(config)
+    }
+
+    /// Identical to ```BroadcastSender::set_label```
+    #[cfg(feature = "registry")]
+    pub fn set_label<N: Into<Cow<'static, str>>>(&self, label: N) {
+        self.sender.set_label(label.into())
+    }
+
+    /// Identical to ```BroadcastSender::reset_high_water```
+    #[cfg(feature = "stats")]
+    pub fn reset_high_water(&self) -> usize {
+        self.sender.reset_high_water()
+    }
+
+    /// Returns the total weight of the values waiting to be received on a queue
+    /// created with ```mpmc_queue_weighted```, and zero on any other queue.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::mpmc_queue_weighted;
+    /// let (w, r) = mpmc_queue_weighted(16, 100, |v: &Vec<u8>| v.len());
+    /// w.try_send(vec![0; 30]).unwrap();
+    /// w.try_send(vec![0; 50]).unwrap();
+    /// assert_eq!(80, w.outstanding_weight());
+    /// r.recv().unwrap();
+    /// assert_eq!(50, w.outstanding_weight());
+    /// ```
+    pub fn outstanding_weight(&self) -> usize {
+        self.sender.outstanding_weight()
+    }
+
+    /// Identical to ```BroadcastSender::batched```
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::mpmc_queue;
+    /// let (w, r) = mpmc_queue(4);
+    /// let mut w = w.batched(16);
+    /// w.try_send(1).unwrap();
+    /// assert!(r.try_recv().is_err());
+    /// w.flush().unwrap();
+    /// assert_eq!(1, r.try_recv().unwrap());
+    /// ```
+    pub fn batched(self, max_batch: usize) -> BatchedSender<T>
+    where
+        T: 'static,
+    {
+        BatchedSender::new(self.sender, max_batch, None)
+    }
+
+    /// Identical to ```BroadcastSender::batched_with_delay```
+    pub fn batched_with_delay(self, max_batch: usize, max_delay: Duration) -> BatchedSender<T>
+    where
+        T: 'static,
+    {
+        BatchedSender::new(self.sender, max_batch, Some(max_delay))
+    }
+
+    /// Identical to ```BroadcastSender::rate_limited```
+    pub fn rate_limited(self, per_second: u32, burst: u32) -> RateLimitedSender<T>
+    where
+        T: 'static,
+    {
+        RateLimitedSender::new(self.sender, per_second, burst)
+    }
+
     /// Removes this writer from the queue
     pub fn unsubscribe(self) {
         self.sender.unsubscribe()
@@ -207,6 +507,261 @@ impl<T> MPMCReceiver<T> {
         self.receiver.recv()
     }
 
+    /// Tries to view the next value in place without moving it out, even when
+    /// there are several consumers on the queue. The value is claimed for this
+    /// consumer before op runs, and writers can't reuse its slot until op is done
+    /// and the value has been dropped, so op should be quick. If there's nothing
+    /// to view, op is handed back. If op panics, the value is still received and dropped.
+    ///
+    /// Once a consumer on a queue has viewed a value this way, writers also
+    /// check whether the slot they're about to write is being viewed, for as long
+    /// as the queue lives. A slow op holds its slot, so once the ring wraps around
+    /// to it every writer stalls until op is done
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::mpmc_queue;
+    /// let (w, r) = mpmc_queue(10);
+    /// let r2 = r.clone();
+    /// w.try_send(vec![1, 2, 3]).unwrap();
+    /// w.try_send(vec![4, 5, 6]).unwrap();
+    /// assert_eq!(3, r.try_recv_view(|v| v.len()).ok().unwrap());
+    /// assert_eq!(4, r2.try_recv_view(|v| v[0]).ok().unwrap());
+    /// assert!(r.try_recv_view(|v| v.len()).is_err());
+    /// ```
+    #[inline(always)]
+    pub fn try_recv_view<R, F: FnOnce(&T) -> R>(&self, op: F) -> Result<R, (F, TryRecvError)> {
+        self.receiver.try_recv_view_claimed(op)
+    }
+
+    /// Identical to ```MPMCReceiver::try_recv_view```, except it blocks until
+    /// there is data to view
+    #[inline(always)]
+    pub fn recv_view<R, F: FnOnce(&T) -> R>(&self, op: F) -> Result<R, (F, RecvError)> {
+        self.receiver.recv_view_claimed(op)
+    }
+
+    /// Tries to take up to n values from the queue at once without blocking.
+    /// All of them are claimed with a single update of the shared read position,
+    /// so consumers contending on a busy queue pay for one claim per batch instead
+    /// of one per value. Returns ```Err(TryRecvError::Empty)``` if nothing could be taken.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// use multiqueue2::mpmc_queue;
+    /// let (w, r) = mpmc_queue(10);
+    /// for i in 0..5 {
+    ///     w.try_send(i).unwrap();
+    /// }
+    /// let r2 = r.clone();
+    /// assert_eq!(vec![0, 1, 2], r.try_steal_batch(3).unwrap().collect::<Vec<_>>());
+    /// assert_eq!(vec![3, 4], r2.try_steal_batch(3).unwrap().collect::<Vec<_>>());
+    /// assert!(r.try_steal_batch(3).is_err());
+    /// ```
+    pub fn try_steal_batch(&self, n: usize) -> Result<vec::IntoIter<T>, TryRecvError> {
+        self.receiver
+            .try_recv_batch(n)
+            .map(|batch| batch.into_iter())
+    }
+
+    /// Copies as many waiting values as fit into buf without blocking, returning how
+    /// many it copied. When this is the only receiver on the queue, the values are
+    /// copied straight out of it and the stream moves past them all at once, which
+    /// suits sample buffers that are too busy for a call per value.
+    /// If there is no data in the queue or the writers have disconnected,
+    /// returns an ```Err(TryRecvError)```
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::mpmc_queue;
+    /// let (w, r) = mpmc_queue(8);
+    /// for i in 0..6_i16 {
+    ///     w.try_send(i).unwrap();
+    /// }
+    /// let mut samples = [0; 4];
+    /// assert_eq!(Ok(4), r.read_exact(&mut samples));
+    /// assert_eq!([0, 1, 2, 3], samples);
+    /// assert_eq!(Ok(2), r.read_exact(&mut samples));
+    /// assert_eq!([4, 5], samples[..2]);
+    /// ```
+    pub fn read_exact(&self, buf: &mut [T]) -> Result<usize, TryRecvError>
+    where
+        T: Copy,
+    {
+        self.receiver.try_recv_slice(buf)
+    }
+
+    /// Identical to ```MPMCReceiver::try_recv```, but also returns the sequence
+    /// number the value was written at. Sequence numbers start at zero and
+    /// increase by one for every value written to the queue, so they can be
+    /// used to deduplicate or persist values downstream.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// use multiqueue2::mpmc_queue;
+    /// let (w, r) = mpmc_queue(10);
+    /// w.try_send("a").unwrap();
+    /// w.try_send("b").unwrap();
+    /// assert_eq!((0, "a"), r.try_recv_indexed().unwrap());
+    /// assert_eq!((1, "b"), r.try_recv_indexed().unwrap());
+    /// ```
+    #[inline(always)]
+    pub fn try_recv_indexed(&self) -> Result<(u64, T), TryRecvError> {
+        self.receiver.try_recv_indexed()
+    }
+
+    /// Identical to ```MPMCReceiver::recv```, but also returns the sequence
+    /// number the value was written at
+    #[inline(always)]
+    pub fn recv_indexed(&self) -> Result<(u64, T), RecvError> {
+        self.receiver.recv_indexed()
+    }
+
+    /// Identical to ```BroadcastReceiver::try_recv_with_latency```
+    #[inline(always)]
+    pub fn try_recv_with_latency(&self) -> Result<(T, Duration), TryRecvError> {
+        self.receiver.try_recv_with_latency()
+    }
+
+    /// Identical to ```BroadcastReceiver::recv_with_latency```
+    pub fn recv_with_latency(&self) -> Result<(T, Duration), RecvError> {
+        self.receiver.recv_with_latency()
+    }
+
+    /// Returns how many items sit between the receivers and the writers.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::mpmc_queue;
+    /// let (w, r) = mpmc_queue(4);
+    /// w.try_send(1).unwrap();
+    /// w.try_send(2).unwrap();
+    /// assert_eq!(2, r.lag());
+    /// r.try_recv().unwrap();
+    /// assert_eq!(1, r.lag());
+    /// ```
+    pub fn lag(&self) -> usize {
+        self.receiver.lag()
+    }
+
+    /// Identical to ```BroadcastReceiver::clear```. Every receiver shares one
+    /// stream, so the values are dropped for all of them
+    pub fn clear(&self) -> usize {
+        self.receiver.clear()
+    }
+
+    /// Returns the number of receivers on the queue, including this one.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::mpmc_queue;
+    /// let (_w, r) = mpmc_queue::<usize>(4);
+    /// let r2 = r.clone();
+    /// assert_eq!(2, r.consumers_on_stream());
+    /// drop(r2);
+    /// assert_eq!(1, r.consumers_on_stream());
+    /// ```
+    pub fn consumers_on_stream(&self) -> usize {
+        self.receiver.consumers_on_stream()
+    }
+
+    /// Identical to ```MPMCSender::writer_count```
+    pub fn writer_count(&self) -> usize {
+        self.receiver.writer_count()
+    }
+
+    /// Identical to ```BroadcastSender::freeze```
+    pub fn freeze(&self) {
+        self.receiver.freeze()
+    }
+
+    /// Identical to ```BroadcastSender::unfreeze```
+    pub fn unfreeze(&self) {
+        self.receiver.unfreeze()
+    }
+
+    /// Identical to ```BroadcastSender::is_frozen```
+    pub fn is_frozen(&self) -> bool {
+        self.receiver.is_frozen()
+    }
+
+    /// Identical to ```MPMCSender::capacity```
+    pub fn capacity(&self) -> usize {
+        self.receiver.capacity()
+    }
+
+    /// Identical to ```MPMCSender::stats```
+    pub fn stats(&self) -> QueueStats {
+        self.receiver.stats()
+    }
+
+    /// Identical to ```MPMCSender::memory_footprint```
+    pub fn memory_footprint(&self) -> MemoryFootprint {
+        self.receiver.memory_footprint()
+    }
+
+    /// Identical to ```MPMCSender::inspector```
+    pub fn inspector(&self) -> Inspector<T>
+    where
+        T: 'static,
+    {
+        Inspector::new(self.receiver.shared_queue())
+    }
+
+    /// Identical to ```MPMCSender::check_invariants```
+    #[cfg(feature = "test-util")]
+    pub fn check_invariants(&self) -> InvariantReport {
+        self.receiver.check_invariants()
+    }
+
+    /// Identical to ```MPMCSender::inject_faults```
+    #[cfg(feature = "fault_injection")]
+    pub fn inject_faults(&self, config: FaultConfig) {
+        self.receiver.inject_faults(config)
+    }
+
+    /// Identical to ```MPMCSender::set_label```
+    #[cfg(feature = "registry")]
+    pub fn set_label<N: Into<Cow<'static, str>>>(&self, label: N) {
+        self.receiver.set_label(label.into())
+    }
+
+    /// Identical to ```MPMCSender::reset_high_water```
+    #[cfg(feature = "stats")]
+    pub fn reset_high_water(&self) -> usize {
+        self.receiver.reset_high_water()
+    }
+
+    /// Identical to ```clone```, except the new receiver carries the passed label.
+    /// Receiver labels show up in Debug output, and are kept by plain clones.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::mpmc_queue;
+    /// let (w, r) = mpmc_queue::<usize>(4);
+    /// let worker = r.clone_named("worker");
+    /// assert_eq!(Some("worker"), worker.name());
+    /// assert_eq!(None, r.name());
+    /// assert!(format!("{:?}", worker).contains("worker"));
+    /// ```
+    pub fn clone_named<N: Into<Cow<'static, str>>>(&self, name: N) -> MPMCReceiver<T> {
+        MPMCReceiver {
+            receiver: self.receiver.clone_named(name.into()),
+        }
+    }
+
+    /// Returns the label given to this receiver by ```clone_named```, if any
+    pub fn name(&self) -> Option<&str> {
+        self.receiver.name()
+    }
+
     /// Removes the given reader from the queue subscription lib
     /// Returns true if this is the last reader in a given broadcast unit
     ///
@@ -225,6 +780,47 @@ impl<T> MPMCReceiver<T> {
         self.receiver.unsubscribe()
     }
 
+    /// Identical to ```BroadcastReceiver::paced```
+    pub fn paced(self, per_second: u32, burst: u32) -> PacedReceiver<T>
+    where
+        T: 'static,
+    {
+        PacedReceiver::new(self.receiver, per_second, burst)
+    }
+
+    /// Spawns the passed number of threads which each receive from a clone
+    /// of this receiver and pass every value to f, until every sender is gone
+    /// and the queue is empty. This receiver goes to the last of them,
+    /// so once the workers stop the queue has no receivers left.
+    /// See ```WorkerPool``` for joining them.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::mpmc_queue;
+    /// use std::sync::mpsc::channel;
+    ///
+    /// let (w, r) = mpmc_queue(4);
+    /// let (results, done) = channel();
+    /// let pool = r.spawn_workers(2, move |val: usize| {
+    ///     results.send(val * 2).unwrap();
+    /// });
+    /// w.try_send(1).unwrap();
+    /// w.try_send(2).unwrap();
+    /// drop(w);
+    /// pool.join();
+    /// let mut got: Vec<_> = done.try_iter().collect();
+    /// got.sort();
+    /// assert_eq!(vec![2, 4], got);
+    /// ```
+    pub fn spawn_workers<F>(self, workers: usize, f: F) -> WorkerPool
+    where
+        T: Send + 'static,
+        F: Fn(T) + Send + Sync + 'static,
+    {
+        WorkerPool::new(self, workers, f)
+    }
+
     /// If there is only one ```MPMCReceiver``` on the stream, converts the
     /// Receiver into a ```MPMCUniReceiver``` otherwise returns the ```MPMCReceiver```.
     ///
@@ -275,27 +871,120 @@ impl<T> MPMCReceiver<T> {
     pub fn try_iter(&self) -> MPMCRefIter<'_, T> {
         MPMCRefIter { recv: self }
     }
-}
-
-impl<T> MPMCUniReceiver<T> {
-    /// Identical to ```MPMCReceiver::try_recv```
-    pub fn try_recv(&self) -> Result<T, TryRecvError> {
-        self.receiver.try_recv()
-    }
-
-    /// Identical to ```MPMCReceiver::recv```
-    pub fn recv(&self) -> Result<T, RecvError> {
-        self.receiver.recv()
-    }
 
-    /// Applies the passed function to the value in the queue without copying it out
-    /// If there is no data in the queue or the writers have disconnected,
-    /// returns an ```Err((F, TryRecvError))```
+    /// Returns a non-owning iterator that blocks until each item arrives, like
+    /// ```recv```, and ends once the queue is disconnected. Unlike ```into_iter```
+    /// this leaves the receiver usable, so it can be looped over in a scoped thread.
+    ///
+    /// # Examples:
     ///
-    /// # Example
     /// ```
     /// use multiqueue2::mpmc_queue;
-    ///
+    /// let (w, r) = mpmc_queue(16);
+    /// for i in 0..10 {
+    ///     w.try_send(i).unwrap();
+    /// }
+    /// drop(w);
+    /// assert_eq!(45, r.iter().sum::<i32>());
+    /// assert!(r.try_recv().is_err());
+    /// ```
+    pub fn iter(&self) -> MPMCBlockingRefIter<'_, T> {
+        MPMCBlockingRefIter { recv: self }
+    }
+
+    /// Returns a non-owning iterator that blocks on each item like ```iter```, but
+    /// ends once nothing has arrived for timeout, or once the queue is disconnected.
+    /// The timeout starts over each time it waits for an item, so this drains a
+    /// burst of values and stops when the queue goes quiet. Waiting polls the queue
+    /// rather than blocking, since nothing wakes a receiver when its time runs out.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// use multiqueue2::mpmc_queue;
+    /// use std::time::Duration;
+    ///
+    /// let (w, r) = mpmc_queue(16);
+    /// for i in 0..5 {
+    ///     w.try_send(i).unwrap();
+    /// }
+    /// let batch: Vec<_> = r.iter_timeout(Duration::from_millis(10)).collect();
+    /// assert_eq!(vec![0, 1, 2, 3, 4], batch);
+    /// ```
+    pub fn iter_timeout(&self, timeout: Duration) -> MPMCTimeoutIter<'_, T> {
+        MPMCTimeoutIter {
+            recv: self,
+            deadline: None,
+            idle: Some(timeout),
+        }
+    }
+
+    /// Returns a non-owning iterator that blocks on each item like ```iter```, but
+    /// ends once the queue's clock reaches deadline, or once the queue is disconnected.
+    /// Values which are still waiting at the deadline are left in the queue
+    pub fn iter_until(&self, deadline: Instant) -> MPMCTimeoutIter<'_, T> {
+        MPMCTimeoutIter {
+            recv: self,
+            deadline: Some(deadline),
+            idle: None,
+        }
+    }
+}
+
+impl<T> MPMCUniReceiver<T> {
+    /// Identical to ```MPMCReceiver::try_recv```
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.receiver.try_recv()
+    }
+
+    /// Identical to ```MPMCReceiver::recv```
+    pub fn recv(&self) -> Result<T, RecvError> {
+        self.receiver.recv()
+    }
+
+    /// Identical to ```MPMCReceiver::try_recv_indexed```
+    pub fn try_recv_indexed(&self) -> Result<(u64, T), TryRecvError> {
+        self.receiver.try_recv_indexed()
+    }
+
+    /// Identical to ```MPMCReceiver::recv_indexed```
+    pub fn recv_indexed(&self) -> Result<(u64, T), RecvError> {
+        self.receiver.recv_indexed()
+    }
+
+    /// Identical to ```MPMCReceiver::try_recv_with_latency```
+    #[inline(always)]
+    pub fn try_recv_with_latency(&self) -> Result<(T, Duration), TryRecvError> {
+        self.receiver.try_recv_with_latency()
+    }
+
+    /// Identical to ```MPMCReceiver::recv_with_latency```
+    pub fn recv_with_latency(&self) -> Result<(T, Duration), RecvError> {
+        self.receiver.recv_with_latency()
+    }
+
+    /// Identical to ```MPMCReceiver::lag```
+    pub fn lag(&self) -> usize {
+        self.receiver.lag()
+    }
+
+    /// Identical to ```MPMCReceiver::read_exact```
+    pub fn read_exact(&self, buf: &mut [T]) -> Result<usize, TryRecvError>
+    where
+        T: Copy,
+    {
+        self.receiver.try_recv_slice(buf)
+    }
+
+    /// Applies the passed function to the value in the queue without copying it out
+    /// If there is no data in the queue or the writers have disconnected,
+    /// returns an ```Err((F, TryRecvError))```
+    /// If the function panics, the value still counts as received and is dropped.
+    ///
+    /// # Example
+    /// ```
+    /// use multiqueue2::mpmc_queue;
+    ///
     /// let (w, r) = mpmc_queue(10);
     /// let single_r = r.into_single().unwrap();
     /// for i in 0..5 {
@@ -346,6 +1035,32 @@ impl<T> MPMCUniReceiver<T> {
         self.receiver.recv_view(op)
     }
 
+    /// Identical to ```BroadcastUniReceiver::try_recv_guard```. The value is dropped
+    /// along with the guard
+    ///
+    /// # Example
+    /// ```
+    /// use multiqueue2::mpmc_queue;
+    ///
+    /// let (w, r) = mpmc_queue(10);
+    /// let mut single_r = r.into_single().unwrap();
+    /// w.try_send(String::from("hello")).unwrap();
+    ///
+    /// let len = single_r.try_recv_guard().map(|s| s.len());
+    /// assert_eq!(Ok(5), len);
+    /// assert!(single_r.try_recv_guard().is_err());
+    /// ```
+    #[inline(always)]
+    pub fn try_recv_guard(&mut self) -> Result<RecvGuard<'_, T>, TryRecvError> {
+        self.receiver.try_recv_guard()
+    }
+
+    /// Identical to ```BroadcastUniReceiver::recv_guard```
+    #[inline(always)]
+    pub fn recv_guard(&mut self) -> Result<RecvGuard<'_, T>, RecvError> {
+        self.receiver.recv_guard()
+    }
+
     /// Removes the given reader from the queue subscription lib
     /// Returns true if this is the last reader in a given broadcast unit
     ///
@@ -422,7 +1137,7 @@ impl<T> MPMCUniReceiver<T> {
     ///     }
     /// }
     /// ```
-    pub fn try_iter_with<R, F: FnMut(&T) -> R>(&self, op: F) -> MPMCUniRefIter<R, F, T> {
+    pub fn try_iter_with<R, F: FnMut(&T) -> R>(&self, op: F) -> MPMCUniRefIter<'_, R, F, T> {
         MPMCUniRefIter { recv: self, op }
     }
 }
@@ -430,10 +1145,60 @@ impl<T> MPMCUniReceiver<T> {
 impl<T> MPMCFutSender<T> {
     /// Equivalent to ```MPMCSender::try_send```
     #[inline(always)]
-    pub fn try_send(&self, val: T) -> Result<(), TrySendError<T>> {
+    pub fn try_send(&self, val: T) -> Result<(), FrozenTrySendError<T>> {
         self.sender.try_send(val)
     }
 
+    /// Equivalent to ```MPMCSender::max_lag```
+    pub fn max_lag(&self) -> usize {
+        self.sender.max_lag()
+    }
+
+    /// Equivalent to ```MPMCSender::writer_count```
+    pub fn writer_count(&self) -> usize {
+        self.sender.writer_count()
+    }
+
+    /// Equivalent to ```BroadcastSender::freeze```
+    pub fn freeze(&self) {
+        self.sender.freeze()
+    }
+
+    /// Equivalent to ```BroadcastSender::unfreeze```
+    pub fn unfreeze(&self) {
+        self.sender.unfreeze()
+    }
+
+    /// Equivalent to ```BroadcastSender::is_frozen```
+    pub fn is_frozen(&self) -> bool {
+        self.sender.is_frozen()
+    }
+
+    /// Equivalent to ```MPMCSender::stats```
+    pub fn stats(&self) -> QueueStats {
+        self.sender.stats()
+    }
+
+    /// Equivalent to ```MPMCSender::reset_high_water```
+    #[cfg(feature = "stats")]
+    pub fn reset_high_water(&self) -> usize {
+        self.sender.reset_high_water()
+    }
+
+    /// Changes how many times senders and receivers on this queue spin and
+    /// then yield before parking. This takes effect for every handle on the queue
+    pub fn set_spins(&self, try_spins: usize, yield_spins: usize) {
+        self.sender.set_spins(try_spins, yield_spins)
+    }
+
+    /// Equivalent to ```BroadcastFutSender::rate_limited```
+    pub fn rate_limited(self, per_second: u32, burst: u32) -> RateLimitedFutSender<T>
+    where
+        T: 'static,
+    {
+        RateLimitedFutSender::new(self.sender, per_second, burst)
+    }
+
     /// Equivalent to ```MPMCSender::unsubscribe```
     pub fn unsubscribe(self) {
         self.sender.unsubscribe()
@@ -453,6 +1218,77 @@ impl<T> MPMCFutReceiver<T> {
         self.receiver.recv()
     }
 
+    /// Equivalent to ```MPMCReceiver::try_recv_indexed```
+    #[inline(always)]
+    pub fn try_recv_indexed(&self) -> Result<(u64, T), TryRecvError> {
+        self.receiver.try_recv_indexed()
+    }
+
+    /// Equivalent to ```MPMCReceiver::recv_indexed```
+    #[inline(always)]
+    pub fn recv_indexed(&self) -> Result<(u64, T), RecvError> {
+        self.receiver.recv_indexed()
+    }
+
+    /// Equivalent to ```MPMCReceiver::try_recv_with_latency```
+    #[inline(always)]
+    pub fn try_recv_with_latency(&self) -> Result<(T, Duration), TryRecvError> {
+        self.receiver.try_recv_with_latency()
+    }
+
+    /// Equivalent to ```MPMCReceiver::recv_with_latency```
+    pub fn recv_with_latency(&self) -> Result<(T, Duration), RecvError> {
+        self.receiver.recv_with_latency()
+    }
+
+    /// Equivalent to ```MPMCReceiver::lag```
+    pub fn lag(&self) -> usize {
+        self.receiver.lag()
+    }
+
+    /// Equivalent to ```MPMCReceiver::clear```
+    pub fn clear(&self) -> usize {
+        self.receiver.clear()
+    }
+
+    /// Equivalent to ```MPMCReceiver::consumers_on_stream```
+    pub fn consumers_on_stream(&self) -> usize {
+        self.receiver.consumers_on_stream()
+    }
+
+    /// Equivalent to ```MPMCReceiver::clone_named```
+    pub fn clone_named<N: Into<Cow<'static, str>>>(&self, name: N) -> MPMCFutReceiver<T> {
+        MPMCFutReceiver {
+            receiver: self.receiver.clone_named(name.into()),
+        }
+    }
+
+    /// Equivalent to ```MPMCReceiver::name```
+    pub fn name(&self) -> Option<&str> {
+        self.receiver.name()
+    }
+
+    /// Equivalent to ```MPMCSender::writer_count```
+    pub fn writer_count(&self) -> usize {
+        self.receiver.writer_count()
+    }
+
+    /// Equivalent to ```MPMCSender::stats```
+    pub fn stats(&self) -> QueueStats {
+        self.receiver.stats()
+    }
+
+    /// Equivalent to ```MPMCSender::reset_high_water```
+    #[cfg(feature = "stats")]
+    pub fn reset_high_water(&self) -> usize {
+        self.receiver.reset_high_water()
+    }
+
+    /// Equivalent to ```MPMCFutSender::set_spins```
+    pub fn set_spins(&self, try_spins: usize, yield_spins: usize) {
+        self.receiver.set_spins(try_spins, yield_spins)
+    }
+
     /// Identical to ```MPMCReceiver::unsubscribe```
     pub fn unsubscribe(self) -> bool {
         self.receiver.unsubscribe()
@@ -461,6 +1297,8 @@ impl<T> MPMCFutReceiver<T> {
     /// Analog of ```MPMCReceiver::into_single```
     /// Since the ```FutUniReceiver``` acts more like an iterator,
     /// this takes the operation to be applied to each value
+    // Too large for clippy only with order_checks, as with FutInnerRecv::into_single
+    #[cfg_attr(feature = "order_checks", allow(clippy::result_large_err))]
     pub fn into_single<R, F: FnMut(&T) -> R>(
         self,
         op: F,
@@ -487,6 +1325,11 @@ impl<R, F: FnMut(&T) -> R, T> MPMCFutUniReceiver<R, F, T> {
         self.receiver.recv()
     }
 
+    /// Equivalent to ```MPMCReceiver::lag```
+    pub fn lag(&self) -> usize {
+        self.receiver.lag()
+    }
+
     /// Adds a stream with the specified method
     pub fn add_stream_with<RQ, FQ: FnMut(&T) -> RQ>(
         &self,
@@ -539,7 +1382,7 @@ impl<T> Sink for &MPMCFutSender<T> {
 
     #[inline(always)]
     fn poll_complete(&mut self) -> Poll<(), SendError<T>> {
-        Ok(Async::Ready(()))
+        (&self.sender).poll_complete()
     }
 }
 
@@ -586,6 +1429,14 @@ impl<T> Stream for MPMCFutReceiver<T> {
     }
 }
 
+impl<R, F: FnMut(&T) -> R, T: fmt::Debug> fmt::Debug for MPMCFutUniReceiver<R, F, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MPMCFutUniReceiver")
+            .field("receiver", &self.receiver)
+            .finish()
+    }
+}
+
 impl<R, F: FnMut(&T) -> R, T> Stream for MPMCFutUniReceiver<R, F, T> {
     type Item = R;
     type Error = ();
@@ -605,10 +1456,19 @@ impl<T> Iterator for MPMCIter<T> {
 
     #[inline(always)]
     fn next(&mut self) -> Option<T> {
-        match self.recv.recv() {
-            Ok(val) => Some(val),
-            Err(_) => None,
-        }
+        self.recv.recv().ok()
+    }
+}
+
+impl<T: 'static> From<MPMCReceiver<T>> for MergeSource<T> {
+    fn from(recv: MPMCReceiver<T>) -> MergeSource<T> {
+        MergeSource::new(recv.receiver)
+    }
+}
+
+impl<T: 'static> From<MPMCSender<T>> for TeeTarget<T> {
+    fn from(send: MPMCSender<T>) -> TeeTarget<T> {
+        TeeTarget::new(send.sender)
     }
 }
 
@@ -631,10 +1491,62 @@ impl<T> Iterator for MPSCIter<T> {
 
     #[inline(always)]
     fn next(&mut self) -> Option<T> {
-        match self.recv.recv() {
-            Ok(val) => Some(val),
-            Err(_) => None,
-        }
+        self.recv.recv().ok()
+    }
+}
+
+impl MPMCSender<Vec<u8>> {
+    /// Turns this sender into an ```io::Write``` which cuts what's written
+    /// into frames of frame_size bytes, see ```QueueWriter```
+    pub fn into_writer(self, frame_size: usize) -> QueueWriter {
+        QueueWriter::new(self.sender, frame_size)
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl<T: Send> MPMCReceiver<T> {
+    /// Returns a rayon ```ParallelIterator``` over the values in the queue, which
+    /// gives each worker thread a clone of this receiver, see ```MPMCParIter```
+    pub fn par_iter(&self) -> MPMCParIter<T> {
+        MPMCParIter::new(self.clone())
+    }
+
+    /// Identical to ```par_iter```, but takes this receiver
+    pub fn par_drain(self) -> MPMCParIter<T> {
+        MPMCParIter::new(self)
+    }
+}
+
+impl MPMCUniReceiver<Vec<u8>> {
+    /// Turns this receiver into an ```io::Read``` and ```io::BufRead```
+    /// over the bytes of the frames it receives, see ```QueueReader```
+    pub fn into_reader(self) -> QueueReader {
+        QueueReader::new(self.receiver)
+    }
+}
+
+impl<T: Send + 'static> MPMCSender<T> {
+    /// Turns this sender into a ```Sink``` which sends from a thread
+    /// of its own, see ```BlockingSinkAdapter```. hand_off is the capacity
+    /// of the futures queue values are handed to the thread through
+    pub fn into_sink_adapter(self, hand_off: usize) -> BlockingSinkAdapter<T> {
+        BlockingSinkAdapter::new(self.sender, hand_off)
+    }
+}
+
+impl<T: Send + 'static> MPMCReceiver<T> {
+    /// Turns this receiver into a ```Stream``` which receives on a thread
+    /// of its own, see ```BlockingStreamAdapter```. hand_off is the capacity
+    /// of the futures queue the thread hands values over through
+    pub fn into_stream_adapter(self, hand_off: usize) -> BlockingStreamAdapter<T> {
+        BlockingStreamAdapter::new(self.receiver, hand_off)
+    }
+}
+
+impl<T: Send + 'static> MPMCUniReceiver<T> {
+    /// Identical to ```MPMCReceiver::into_stream_adapter```
+    pub fn into_stream_adapter(self, hand_off: usize) -> BlockingStreamAdapter<T> {
+        BlockingStreamAdapter::new(self.receiver, hand_off)
     }
 }
 
@@ -657,10 +1569,7 @@ impl<'a, T> Iterator for MPMCRefIter<'a, T> {
 
     #[inline(always)]
     fn next(&mut self) -> Option<T> {
-        match self.recv.try_recv() {
-            Ok(val) => Some(val),
-            Err(_) => None,
-        }
+        self.recv.try_recv().ok()
     }
 }
 
@@ -674,6 +1583,47 @@ impl<'a, T: 'a> IntoIterator for &'a MPMCReceiver<T> {
     }
 }
 
+pub struct MPMCBlockingRefIter<'a, T: 'a> {
+    recv: &'a MPMCReceiver<T>,
+}
+
+impl<'a, T> Iterator for MPMCBlockingRefIter<'a, T> {
+    type Item = T;
+
+    #[inline(always)]
+    fn next(&mut self) -> Option<T> {
+        self.recv.recv().ok()
+    }
+}
+
+pub struct MPMCTimeoutIter<'a, T: 'a> {
+    recv: &'a MPMCReceiver<T>,
+    // Set for iter_until, where the deadline is fixed
+    deadline: Option<Instant>,
+    // Set for iter_timeout, where each wait gets a deadline of its own
+    idle: Option<Duration>,
+}
+
+impl<'a, T> Iterator for MPMCTimeoutIter<'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        let deadline = match self.idle {
+            Some(idle) => self.recv.receiver.now().checked_add(idle),
+            None => match self.deadline {
+                // Values left at the deadline stay queued, even if they keep coming
+                Some(deadline) if self.recv.receiver.now() >= deadline => return None,
+                deadline => deadline,
+            },
+        };
+        match deadline {
+            Some(deadline) => self.recv.receiver.recv_until(deadline).ok(),
+            // Too far off to represent, so it never comes
+            None => self.recv.recv().ok(),
+        }
+    }
+}
+
 pub struct MPSCRefIter<'a, T: 'a> {
     recv: &'a MPMCUniReceiver<T>,
 }
@@ -683,10 +1633,7 @@ impl<'a, T> Iterator for MPSCRefIter<'a, T> {
 
     #[inline(always)]
     fn next(&mut self) -> Option<T> {
-        match self.recv.try_recv() {
-            Ok(val) => Some(val),
-            Err(_) => None,
-        }
+        self.recv.try_recv().ok()
     }
 }
 
@@ -711,10 +1658,7 @@ impl<R, F: FnMut(&T) -> R, T> Iterator for MPMCUniIter<R, F, T> {
     #[inline(always)]
     fn next(&mut self) -> Option<R> {
         let opref = &mut self.op;
-        match self.recv.recv_view(|v| opref(v)) {
-            Ok(val) => Some(val),
-            Err(_) => None,
-        }
+        self.recv.recv_view(|v| opref(v)).ok()
     }
 }
 
@@ -729,16 +1673,17 @@ impl<'a, R, F: FnMut(&T) -> R, T: 'a> Iterator for MPMCUniRefIter<'a, R, F, T> {
     #[inline(always)]
     fn next(&mut self) -> Option<R> {
         let opref = &mut self.op;
-        match self.recv.try_recv_view(|v| opref(v)) {
-            Ok(val) => Some(val),
-            Err(_) => None,
-        }
+        self.recv.try_recv_view(|v| opref(v)).ok()
     }
 }
 
 /// Creates a (```MPMCSender```, ```MPMCReceiver```) pair with a capacity that's
 /// the next power of two >= the given capacity
 ///
+/// # Panics
+///
+/// Panics if the capacity is too large for the queue to tell full from empty
+///
 /// # Example
 /// ```
 /// use multiqueue2::mpmc_queue;
@@ -746,53 +1691,390 @@ impl<'a, R, F: FnMut(&T) -> R, T: 'a> Iterator for MPMCUniRefIter<'a, R, F, T> {
 /// w.try_send(10).unwrap();
 /// assert_eq!(10, r.try_recv().unwrap());
 /// ```
-
-pub fn mpmc_queue<T>(capacity: Index) -> (MPMCSender<T>, MPMCReceiver<T>) {
+pub fn mpmc_queue<T>(capacity: usize) -> (MPMCSender<T>, MPMCReceiver<T>) {
     let (send, recv) = MultiQueue::<MPMC<T>, T>::create_tx_rx(capacity);
     (MPMCSender { sender: send }, MPMCReceiver { receiver: recv })
 }
 
 pub fn mpmc_queue_with<T, W: Wait + 'static>(
-    capacity: Index,
+    capacity: usize,
     w: W,
 ) -> (MPMCSender<T>, MPMCReceiver<T>) {
     let (send, recv) = MultiQueue::<MPMC<T>, T>::create_tx_rx_with(capacity, w);
     (MPMCSender { sender: send }, MPMCReceiver { receiver: recv })
 }
 
-/// Futures variant of ```mpmc_queue``` - datastructures implement
-/// Sink + Stream at a minor (~30 ns) performance cost to ```BlockingWait```
-pub fn mpmc_fut_queue<T>(capacity: Index) -> (MPMCFutSender<T>, MPMCFutReceiver<T>) {
-    let (isend, irecv) = futures_multiqueue::<MPMC<T>, T>(capacity);
-    (
-        MPMCFutSender { sender: isend },
-        MPMCFutReceiver { receiver: irecv },
-    )
+/// Creates a (```MPMCSender```, ```MPMCReceiver```) pair which reports
+/// sends, receives and the like to the passed ```QueueMetrics```
+///
+/// # Example
+/// ```
+/// use multiqueue2::{mpmc_queue_with_metrics, QueueMetrics};
+/// use std::sync::atomic::{AtomicBool, Ordering};
+/// use std::sync::Arc;
+///
+/// #[derive(Default)]
+/// struct Closed(AtomicBool);
+///
+/// impl QueueMetrics for Closed {
+///     fn on_disconnect(&self) {
+///         self.0.store(true, Ordering::Relaxed);
+///     }
+/// }
+///
+/// let closed = Arc::new(Closed::default());
+/// let (w, _r) = mpmc_queue_with_metrics::<usize, _>(10, closed.clone());
+/// drop(w);
+/// assert!(closed.0.load(Ordering::Relaxed));
+/// ```
+#[cfg(feature = "metrics")]
+pub fn mpmc_queue_with_metrics<T, M: QueueMetrics + 'static>(
+    capacity: usize,
+    metrics: M,
+) -> (MPMCSender<T>, MPMCReceiver<T>) {
+    let (send, recv) = MultiQueue::<MPMC<T>, T>::create_tx_rx_with_metrics(
+        capacity,
+        DefaultWait::new(),
+        Arc::new(metrics),
+    );
+    (MPMCSender { sender: send }, MPMCReceiver { receiver: recv })
 }
 
-unsafe impl<T: Send> Send for MPMCSender<T> {}
-unsafe impl<T: Send> Send for MPMCReceiver<T> {}
-unsafe impl<T: Send> Send for MPMCUniReceiver<T> {}
+/// Creates a (```MPMCSender```, ```MPMCReceiver```) pair which frees the memory
+/// replaced as streams come and go through the passed ```Reclaim``` backend
+///
+/// # Example
+/// ```
+/// use multiqueue2::mpmc_queue_with_reclaim;
+/// use multiqueue2::memory::CrossbeamReclaim;
+/// let (w, r) = mpmc_queue_with_reclaim(10, CrossbeamReclaim::new());
+/// let r2 = r.clone();
+/// w.try_send(10).unwrap();
+/// w.try_send(11).unwrap();
+/// assert_eq!(10, r.try_recv().unwrap());
+/// assert_eq!(11, r2.try_recv().unwrap());
+/// ```
+pub fn mpmc_queue_with_reclaim<T, R: Reclaim + 'static>(
+    capacity: usize,
+    reclaim: R,
+) -> (MPMCSender<T>, MPMCReceiver<T>) {
+    let (send, recv) = MultiQueue::<MPMC<T>, T>::create_tx_rx_with_reclaim(
+        capacity,
+        DefaultWait::new(),
+        Arc::new(reclaim),
+    );
+    (MPMCSender { sender: send }, MPMCReceiver { receiver: recv })
+}
 
-#[cfg(test)]
-mod test {
+/// Creates a (```MPMCSender```, ```MPMCReceiver```) pair where senders can
+/// make room with ```try_send_or_replace``` by taking the oldest value off the queue.
+///
+/// # Example
+/// ```
+/// use multiqueue2::mpmc_queue_replacing;
+/// let (w, r) = mpmc_queue_replacing(1);
+/// w.try_send_or_replace(10).unwrap();
+/// assert_eq!(Some(10), w.try_send_or_replace(11).unwrap());
+/// assert_eq!(11, r.try_recv().unwrap());
+/// ```
+pub fn mpmc_queue_replacing<T>(capacity: usize) -> (MPMCSender<T>, MPMCReceiver<T>) {
+    let (send, recv) = MultiQueue::<MPMC<T>, T>::create_tx_rx_replacing(capacity);
+    (MPMCSender { sender: send }, MPMCReceiver { receiver: recv })
+}
 
-    use super::mpmc_queue;
+/// Creates a (```MPMCSender```, ```MPMCReceiver```) pair where the policy decides
+/// what happens to the values still in the queue when the last sender drops.
+///
+/// # Example
+/// ```
+/// use multiqueue2::{mpmc_queue_with_drop_policy, DropPolicy};
+/// use std::sync::{Arc, Mutex};
+///
+/// let lost = Arc::new(Mutex::new(Vec::new()));
+/// let on_dropped = lost.clone();
+/// let policy = DropPolicy::Discard(Box::new(move |val| on_dropped.lock().unwrap().push(val)));
+/// let (w, r) = mpmc_queue_with_drop_policy(4, policy);
+/// w.try_send(1).unwrap();
+/// w.try_send(2).unwrap();
+/// assert_eq!(1, r.try_recv().unwrap());
+/// drop(w);
+/// assert!(r.try_recv().is_err());
+/// assert_eq!(vec![2], *lost.lock().unwrap());
+/// ```
+pub fn mpmc_queue_with_drop_policy<T>(
+    capacity: usize,
+    drop_policy: DropPolicy<T>,
+) -> (MPMCSender<T>, MPMCReceiver<T>) {
+    let (send, recv) =
+        MultiQueue::<MPMC<T>, T>::create_tx_rx_with_drop_policy(capacity, drop_policy);
+    (MPMCSender { sender: send }, MPMCReceiver { receiver: recv })
+}
 
-    extern crate crossbeam;
-    use self::crossbeam::scope;
+/// Creates a (```MPMCSender```, ```MPMCReceiver```) pair where values can be
+/// sent with ```try_send_after``` so that receivers can't see them until a deadline.
+///
+/// # Example
+/// ```
+/// use multiqueue2::mpmc_queue_delayed;
+/// use std::time::Instant;
+/// let (w, r) = mpmc_queue_delayed(10);
+/// w.try_send_after(10, Instant::now()).unwrap();
+/// assert_eq!(10, r.try_recv().unwrap());
+/// ```
+pub fn mpmc_queue_delayed<T>(capacity: usize) -> (MPMCSender<T>, MPMCReceiver<T>) {
+    let (send, recv) = MultiQueue::<MPMC<T>, T>::create_tx_rx_delayed(capacity);
+    (MPMCSender { sender: send }, MPMCReceiver { receiver: recv })
+}
 
-    use std::sync::atomic::{AtomicUsize, Ordering};
-    use std::sync::mpsc::TryRecvError;
-    use std::sync::{Arc, Barrier};
-    use std::thread::yield_now;
+/// Creates a (```MPMCSender```, ```MPMCReceiver```) pair which records when each
+/// value was sent, so receivers can find out how long it waited with
+/// ```try_recv_with_latency``` and ```recv_with_latency```.
+///
+/// # Example
+/// ```
+/// use multiqueue2::mpmc_queue_timestamped;
+/// let (w, r) = mpmc_queue_timestamped(10);
+/// w.try_send(10).unwrap();
+/// let (val, _latency) = r.try_recv_with_latency().unwrap();
+/// assert_eq!(10, val);
+/// ```
+pub fn mpmc_queue_timestamped<T>(capacity: usize) -> (MPMCSender<T>, MPMCReceiver<T>) {
+    let (send, recv) = MultiQueue::<MPMC<T>, T>::create_tx_rx_timestamped(capacity);
+    (MPMCSender { sender: send }, MPMCReceiver { receiver: recv })
+}
 
-    #[test]
-    fn build_queue() {
-        let _ = mpmc_queue::<usize>(10);
-    }
+/// Creates a (```MPMCSender```, ```MPMCReceiver```) pair where values can be
+/// sent with ```try_send_with_ttl``` so that receivers drop them once they're too old.
+///
+/// # Example
+/// ```
+/// use multiqueue2::mpmc_queue_expiring;
+/// use std::time::Duration;
+/// let (w, r) = mpmc_queue_expiring(10);
+/// w.try_send_with_ttl(10, Duration::from_secs(60)).unwrap();
+/// assert_eq!(10, r.try_recv().unwrap());
+/// ```
+pub fn mpmc_queue_expiring<T>(capacity: usize) -> (MPMCSender<T>, MPMCReceiver<T>) {
+    let (send, recv) = MultiQueue::<MPMC<T>, T>::create_tx_rx_expiring(capacity);
+    (MPMCSender { sender: send }, MPMCReceiver { receiver: recv })
+}
 
-    #[test]
+/// Creates a (```MPMCSender```, ```MPMCReceiver```) pair where values can be sent
+/// with ```try_send_with_ttl```, and values which expire before anybody receives them
+/// go to the dead letters instead of being dropped. So do the values still in the queue
+/// when it's destroyed, and values taken off by a ```DropPolicy::Discard``` go
+/// to the policy's callback as usual.
+///
+/// # Example
+/// ```
+/// use multiqueue2::{dead_letter_queue, mpmc_queue_expiring_with_dead_letters, DeadLetterReason};
+/// use std::time::Duration;
+/// let (dead_letters, dead) = dead_letter_queue(10);
+/// let (w, r) = mpmc_queue_expiring_with_dead_letters(10, dead_letters);
+/// w.try_send_with_ttl(10, Duration::from_secs(0)).unwrap();
+/// w.try_send(11).unwrap();
+/// w.try_send(12).unwrap();
+/// assert_eq!(11, r.try_recv().unwrap());
+/// drop((w, r));
+/// let letters: Vec<_> = dead.into_iter().map(|letter| (letter.reason, letter.val)).collect();
+/// assert_eq!(
+///     vec![(DeadLetterReason::Expired, 10), (DeadLetterReason::Undeliverable, 12)],
+///     letters
+/// );
+/// ```
+pub fn mpmc_queue_expiring_with_dead_letters<T>(
+    capacity: usize,
+    dead_letters: DeadLetterSender<T>,
+) -> (MPMCSender<T>, MPMCReceiver<T>) {
+    let (send, recv) =
+        MultiQueue::<MPMC<T>, T>::create_tx_rx_expiring_with_dead_letters(capacity, dead_letters);
+    (MPMCSender { sender: send }, MPMCReceiver { receiver: recv })
+}
+
+/// Creates a (```MPMCSender```, ```MPMCReceiver```) pair which reads the time from the
+/// passed ```Clock``` instead of ```Instant::now```, so that timed code can be tested
+/// without sleeping. Values can be sent with both ```try_send_after``` and
+/// ```try_send_with_ttl```, and receivers can ask for latency.
+///
+/// # Example
+/// ```
+/// use multiqueue2::{mpmc_queue_with_clock, Clock, MockClock};
+/// use std::sync::Arc;
+/// use std::time::Duration;
+/// let clock = Arc::new(MockClock::new());
+/// let (w, r) = mpmc_queue_with_clock(10, clock.clone());
+/// w.try_send_after(10, clock.now() + Duration::from_secs(1)).unwrap();
+/// assert!(r.try_recv().is_err());
+/// clock.advance(Duration::from_secs(1));
+/// assert_eq!(10, r.try_recv().unwrap());
+/// ```
+pub fn mpmc_queue_with_clock<T, C: Clock + 'static>(
+    capacity: usize,
+    clock: C,
+) -> (MPMCSender<T>, MPMCReceiver<T>) {
+    let (send, recv) = MultiQueue::<MPMC<T>, T>::create_tx_rx_with_clock(capacity, Arc::new(clock));
+    (MPMCSender { sender: send }, MPMCReceiver { receiver: recv })
+}
+
+/// Creates a (```MPMCSender```, ```MPMCReceiver```) pair where sending fails once the
+/// values waiting to be received weigh more than budget, as measured by the passed
+/// function. This is for bounding memory when values vary a lot in size.
+/// The function must give the same weight every time it sees a value. The queue still
+/// holds at most capacity values, and a value heavier than the whole budget can be
+/// sent when the queue is empty.
+///
+/// # Example
+/// ```
+/// use multiqueue2::mpmc_queue_weighted;
+/// let (w, r) = mpmc_queue_weighted(16, 1024, |msg: &String| msg.len());
+/// w.try_send("a".repeat(1000)).unwrap();
+/// assert!(w.try_send("b".repeat(100)).is_err());
+/// w.try_send("c".repeat(10)).unwrap();
+/// assert_eq!(1000, r.try_recv().unwrap().len());
+/// w.try_send("b".repeat(100)).unwrap();
+/// ```
+pub fn mpmc_queue_weighted<T, F>(
+    capacity: usize,
+    budget: usize,
+    weigh: F,
+) -> (MPMCSender<T>, MPMCReceiver<T>)
+where
+    F: Fn(&T) -> usize + Send + Sync + 'static,
+{
+    let (send, recv) =
+        MultiQueue::<MPMC<T>, T>::create_tx_rx_weighted(capacity, budget, Box::new(weigh));
+    (MPMCSender { sender: send }, MPMCReceiver { receiver: recv })
+}
+
+/// Creates a (```MPMCSender```, ```MPMCReceiver```) pair where values are conflated by
+/// the key the passed function returns. When a value is sent while an older one with the
+/// same key hasn't been received yet, the older one is dropped instead of being handed out.
+/// The newer value is received in the spot it was sent at, and the older value takes up
+/// space in the queue until the receivers move past it.
+///
+/// # Example
+/// ```
+/// use multiqueue2::mpmc_queue_conflated;
+/// let (w, r) = mpmc_queue_conflated(10, |job: &(u32, String)| job.0);
+/// w.try_send((1, "stale".to_string())).unwrap();
+/// w.try_send((2, "other".to_string())).unwrap();
+/// w.try_send((1, "fresh".to_string())).unwrap();
+/// assert_eq!((2, "other".to_string()), r.try_recv().unwrap());
+/// assert_eq!((1, "fresh".to_string()), r.try_recv().unwrap());
+/// assert!(r.try_recv().is_err());
+/// ```
+pub fn mpmc_queue_conflated<T, K, F>(capacity: usize, key: F) -> (MPMCSender<T>, MPMCReceiver<T>)
+where
+    K: Hash + Eq + Send + 'static,
+    F: Fn(&T) -> K + Send + Sync + 'static,
+{
+    let (send, recv) = MultiQueue::<MPMC<T>, T>::create_tx_rx_conflated(
+        capacity,
+        KeyConflator::new(capacity, key),
+    );
+    (MPMCSender { sender: send }, MPMCReceiver { receiver: recv })
+}
+
+/// Creates a (```MPMCSender```, ```MPMCReceiver```) pair which drops a sent value if
+/// one with the same id was among the last window values sent, so writers can retry
+/// sends without a value being received twice. Identical to
+/// ```broadcast_queue_deduplicated``` otherwise.
+///
+/// # Example
+/// ```
+/// use multiqueue2::mpmc_queue_deduplicated;
+/// let (w, r) = mpmc_queue_deduplicated(10, 64, |job: &(u64, &str)| job.0);
+/// w.try_send((7, "resize")).unwrap();
+/// w.try_send((7, "resize")).unwrap();
+/// assert_eq!((7, "resize"), r.try_recv().unwrap());
+/// assert!(r.try_recv().is_err());
+/// ```
+pub fn mpmc_queue_deduplicated<T, K, F>(
+    capacity: usize,
+    window: usize,
+    id: F,
+) -> (MPMCSender<T>, MPMCReceiver<T>)
+where
+    K: Hash + Eq + Clone + Send + 'static,
+    F: Fn(&T) -> K + Send + Sync + 'static,
+{
+    let (send, recv) =
+        MultiQueue::<MPMC<T>, T>::create_tx_rx_deduplicated(capacity, IdWindow::new(window, id));
+    (MPMCSender { sender: send }, MPMCReceiver { receiver: recv })
+}
+
+/// Futures variant of ```mpmc_queue``` - datastructures implement
+/// Sink + Stream at a minor (~30 ns) performance cost to ```BlockingWait```
+pub fn mpmc_fut_queue<T>(capacity: usize) -> (MPMCFutSender<T>, MPMCFutReceiver<T>) {
+    let (isend, irecv) = futures_multiqueue::<MPMC<T>, T>(capacity);
+    (
+        MPMCFutSender { sender: isend },
+        MPMCFutReceiver { receiver: irecv },
+    )
+}
+
+/// Identical to ```mpmc_queue```, taking the capacity as a u64 like older versions did
+#[deprecated(since = "0.1.8", note = "capacities are usize now, use mpmc_queue")]
+pub fn mpmc_queue_u64<T>(capacity: u64) -> (MPMCSender<T>, MPMCReceiver<T>) {
+    mpmc_queue(capacity_from_u64(capacity))
+}
+
+/// Identical to ```mpmc_queue_with```, taking the capacity as a u64 like older versions did
+#[deprecated(
+    since = "0.1.8",
+    note = "capacities are usize now, use mpmc_queue_with"
+)]
+pub fn mpmc_queue_with_u64<T, W: Wait + 'static>(
+    capacity: u64,
+    w: W,
+) -> (MPMCSender<T>, MPMCReceiver<T>) {
+    mpmc_queue_with(capacity_from_u64(capacity), w)
+}
+
+/// Identical to ```mpmc_fut_queue```, taking the capacity as a u64 like older versions did
+#[deprecated(since = "0.1.8", note = "capacities are usize now, use mpmc_fut_queue")]
+pub fn mpmc_fut_queue_u64<T>(capacity: u64) -> (MPMCFutSender<T>, MPMCFutReceiver<T>) {
+    mpmc_fut_queue(capacity_from_u64(capacity))
+}
+
+unsafe impl<T: Send> Send for MPMCSender<T> {}
+unsafe impl<T: Send> Send for MPMCReceiver<T> {}
+unsafe impl<T: Send> Send for MPMCUniReceiver<T> {}
+
+#[cfg(test)]
+// Some of the original tests predate these lints
+#[allow(
+    clippy::assertions_on_constants,
+    clippy::same_item_push,
+    clippy::unnecessary_cast
+)]
+mod test {
+
+    use super::{
+        mpmc_queue, mpmc_queue_conflated, mpmc_queue_deduplicated, mpmc_queue_delayed,
+        mpmc_queue_expiring, mpmc_queue_expiring_with_dead_letters, mpmc_queue_replacing,
+        mpmc_queue_timestamped, mpmc_queue_weighted, mpmc_queue_with_drop_policy,
+    };
+    use crate::dead_letter::{dead_letter_queue, DeadLetterReason};
+    use crate::error::FrozenTrySendError;
+    use crate::multiqueue::DropPolicy;
+
+    extern crate crossbeam;
+    use self::crossbeam::scope;
+
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc::TryRecvError;
+    use std::sync::{Arc, Barrier};
+    use std::thread::{sleep, yield_now};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn build_queue() {
+        let _ = mpmc_queue::<usize>(10);
+    }
+
+    #[test]
     fn push_pop_test() {
         let (writer, reader) = mpmc_queue(1);
         for _ in 0..100 {
@@ -933,7 +2215,7 @@ mod test {
     }
 
     impl<'a> Dropper<'a> {
-        pub fn new(a: &AtomicUsize) -> Dropper {
+        pub fn new(a: &AtomicUsize) -> Dropper<'_> {
             a.fetch_add(1, Ordering::Relaxed);
             Dropper { aref: a }
         }
@@ -966,6 +2248,998 @@ mod test {
         assert_eq!(count.load(Ordering::Relaxed), 0);
     }
 
+    #[test]
+    fn test_clear() {
+        let count = AtomicUsize::new(0);
+        {
+            let (writer, reader) = mpmc_queue(4);
+            let reader2 = reader.clone();
+            for _ in 0..3 {
+                writer.try_send(Dropper::new(&count)).unwrap();
+            }
+            assert_eq!(3, reader.clear());
+            assert_eq!(0, count.load(Ordering::Relaxed));
+            assert!(reader2.try_recv().is_err());
+            assert_eq!(0, reader2.clear());
+            for _ in 0..4 {
+                writer.try_send(Dropper::new(&count)).unwrap();
+            }
+            reader2.recv().unwrap();
+            assert_eq!(3, reader.clear());
+        }
+        assert_eq!(count.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_clear_threaded() {
+        let (writer, reader) = mpmc_queue(16);
+        let num_loop = 10000;
+        let received = AtomicUsize::new(0);
+        let cleared = AtomicUsize::new(0);
+        scope(|scope| {
+            for _ in 0..2 {
+                let cur_reader = reader.clone();
+                let (received, cleared) = (&received, &cleared);
+                scope.spawn(move |_| {
+                    while cur_reader.recv().is_ok() {
+                        received.fetch_add(1, Ordering::Relaxed);
+                        cleared.fetch_add(cur_reader.clear(), Ordering::Relaxed);
+                    }
+                });
+            }
+            reader.unsubscribe();
+            for i in 0..num_loop {
+                while writer.try_send(i).is_err() {
+                    yield_now();
+                }
+            }
+            writer.unsubscribe();
+        })
+        .unwrap();
+        // Every value is either received or cleared, and only once
+        let total = received.load(Ordering::Relaxed) + cleared.load(Ordering::Relaxed);
+        assert_eq!(num_loop, total);
+    }
+
+    #[test]
+    fn test_recv_guard() {
+        let count = AtomicUsize::new(0);
+        {
+            let (writer, reader) = mpmc_queue(1);
+            let mut reader = reader.into_single().ok().unwrap();
+            writer.try_send(Dropper::new(&count)).unwrap();
+            {
+                let _guard = reader.try_recv_guard().unwrap();
+                // The value stays in the queue until the guard is dropped
+                assert!(writer.try_send(Dropper::new(&count)).is_err());
+                assert_eq!(1, count.load(Ordering::Relaxed));
+            }
+            assert_eq!(0, count.load(Ordering::Relaxed));
+            writer.try_send(Dropper::new(&count)).unwrap();
+            drop(writer);
+            drop(reader.recv_guard().unwrap());
+            assert_eq!(0, count.load(Ordering::Relaxed));
+            assert!(reader.recv_guard().is_err());
+        }
+        assert_eq!(0, count.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_recv_guard_weighted() {
+        let (writer, reader) = mpmc_queue_weighted(4, 10, |v: &usize| *v);
+        let mut reader = reader.into_single().unwrap();
+        writer.try_send(6).unwrap();
+        assert_eq!(Err(FrozenTrySendError::Full(6)), writer.try_send(6));
+        let guard = reader.try_recv_guard().unwrap();
+        assert_eq!(6, *guard);
+        assert_eq!(Err(FrozenTrySendError::Full(6)), writer.try_send(6));
+        drop(guard);
+        writer.try_send(6).unwrap();
+    }
+
+    #[test]
+    fn test_recv_guard_threaded() {
+        let num_loop = 10000;
+        let (writer, reader) = mpmc_queue(4);
+        let mut reader = reader.into_single().unwrap();
+        scope(|scope| {
+            scope.spawn(move |_| {
+                for i in 0..num_loop {
+                    while writer.try_send(i).is_err() {
+                        yield_now();
+                    }
+                }
+            });
+            for i in 0..num_loop {
+                assert_eq!(i, *reader.recv_guard().unwrap());
+            }
+            assert!(reader.recv_guard().is_err());
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_read_exact() {
+        let (writer, reader) = mpmc_queue(4);
+        let mut buf = [0; 8];
+        assert_eq!(Err(TryRecvError::Empty), reader.read_exact(&mut buf));
+        for i in 0..3 {
+            writer.try_send(i).unwrap();
+        }
+        assert_eq!(Ok(3), reader.read_exact(&mut buf));
+        // These wrap around the end of the ring
+        for i in 3..7 {
+            writer.try_send(i).unwrap();
+        }
+        assert_eq!(Ok(4), reader.read_exact(&mut buf));
+        assert_eq!([3, 4, 5, 6], buf[..4]);
+        assert_eq!(Ok(0), reader.read_exact(&mut []));
+        // A shared stream takes them one at a time, but ends up the same
+        let other = reader.clone();
+        writer.try_send(7).unwrap();
+        writer.try_send(8).unwrap();
+        assert_eq!(Ok(2), other.read_exact(&mut buf));
+        assert_eq!([7, 8], buf[..2]);
+        drop(writer);
+        assert_eq!(Err(TryRecvError::Disconnected), reader.read_exact(&mut buf));
+    }
+
+    #[test]
+    fn test_read_exact_weighted() {
+        let (writer, reader) = mpmc_queue_weighted(8, 10, |v: &usize| *v);
+        writer.try_send(4).unwrap();
+        writer.try_send(5).unwrap();
+        assert_eq!(Err(FrozenTrySendError::Full(2)), writer.try_send(2));
+        let mut buf = [0; 4];
+        assert_eq!(Ok(2), reader.read_exact(&mut buf));
+        assert_eq!([4, 5], buf[..2]);
+        writer.try_send(10).unwrap();
+    }
+
+    #[test]
+    fn test_read_exact_threaded() {
+        let num_loop = 100000;
+        let (writer, reader) = mpmc_queue(16);
+        let reader = reader.into_single().unwrap();
+        scope(|scope| {
+            scope.spawn(move |_| {
+                for i in 0..num_loop {
+                    while writer.try_send(i).is_err() {
+                        yield_now();
+                    }
+                }
+            });
+            let mut buf = [0; 7];
+            let mut next = 0;
+            loop {
+                match reader.read_exact(&mut buf) {
+                    Ok(n) => {
+                        for v in &buf[..n] {
+                            assert_eq!(next, *v);
+                            next += 1;
+                        }
+                    }
+                    Err(TryRecvError::Empty) => yield_now(),
+                    Err(TryRecvError::Disconnected) => break,
+                }
+            }
+            assert_eq!(num_loop, next);
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_write_from() {
+        let (writer, reader) = mpmc_queue(4);
+        let mut buf = [0; 8];
+        assert_eq!(0, writer.write_from(&[]));
+        assert_eq!(3, writer.write_from(&[0, 1, 2]));
+        assert_eq!(Ok(3), reader.read_exact(&mut buf));
+        // These wrap around the end of the ring, and the last doesn't fit
+        assert_eq!(4, writer.write_from(&[3, 4, 5, 6, 7]));
+        assert_eq!(0, writer.write_from(&[7]));
+        assert_eq!(3, reader.recv().unwrap());
+        assert_eq!(1, writer.write_from(&[7, 8]));
+        assert_eq!(Ok(4), reader.read_exact(&mut buf));
+        assert_eq!([4, 5, 6, 7], buf[..4]);
+        // With another sender around, they go in one at a time
+        let other = writer.clone();
+        assert_eq!(2, writer.write_from(&[8, 9]));
+        drop(other);
+        assert_eq!(2, writer.write_from(&[10, 11]));
+        assert_eq!(Ok(4), reader.read_exact(&mut buf));
+        assert_eq!([8, 9, 10, 11], buf[..4]);
+        drop(reader);
+        assert_eq!(0, writer.write_from(&[12]));
+    }
+
+    #[test]
+    fn test_write_from_weighted() {
+        let (writer, reader) = mpmc_queue_weighted(8, 10, |v: &usize| *v);
+        assert_eq!(2, writer.write_from(&[4, 5, 2]));
+        assert_eq!(4, reader.try_recv().unwrap());
+        assert_eq!(1, writer.write_from(&[2, 4]));
+    }
+
+    #[test]
+    fn test_try_send_spin_threaded() {
+        let num_loop = 10000;
+        let num_writers = 4;
+        let (writer, reader) = mpmc_queue(8);
+        scope(|scope| {
+            for q in 0..num_writers {
+                let cur_writer = writer.clone();
+                scope.spawn(move |_| {
+                    for i in 0..num_loop {
+                        let mut val = (q, i);
+                        // Giving up early looks just like a full queue
+                        while let Err(FrozenTrySendError::Full(v)) =
+                            cur_writer.try_send_spin(val, 2)
+                        {
+                            val = v;
+                            yield_now();
+                        }
+                    }
+                });
+            }
+            writer.unsubscribe();
+            // Every writer's values still all get through, in order
+            let mut next = vec![0; num_writers];
+            for (q, i) in reader {
+                assert_eq!(next[q], i);
+                next[q] += 1;
+            }
+            assert_eq!(vec![num_loop; num_writers], next);
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_send_transaction_threaded() {
+        let num_loop = 10000;
+        let num_writers = 4;
+        let (writer, reader) = mpmc_queue(8);
+        scope(|scope| {
+            for q in 0..num_writers {
+                let cur_writer = writer.clone();
+                scope.spawn(move |_| {
+                    for i in 0..num_loop {
+                        let mut vals = vec![(q, i, 0), (q, i, 1), (q, i, 2)];
+                        while let Err(FrozenTrySendError::Full(v)) =
+                            cur_writer.send_transaction(vals)
+                        {
+                            vals = v;
+                            yield_now();
+                        }
+                    }
+                });
+            }
+            writer.unsubscribe();
+            // Each writer's values come through three at a time with nothing in between
+            let mut next = vec![0; num_writers];
+            let mut reader = reader.into_iter();
+            while let Some((q, i, k)) = reader.next() {
+                assert_eq!((next[q], 0), (i, k));
+                assert_eq!(Some((q, i, 1)), reader.next());
+                assert_eq!(Some((q, i, 2)), reader.next());
+                next[q] += 1;
+            }
+            assert_eq!(vec![num_loop; num_writers], next);
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_try_send_all_weighted() {
+        let (writer, reader) = mpmc_queue_weighted(8, 10, |v: &usize| *v);
+        writer.try_send_all(&[4, 4]).unwrap();
+        // This would take the queue over its budget, so none of it goes in
+        assert_eq!(
+            Err(FrozenTrySendError::Full(())),
+            writer.try_send_all(&[1, 2])
+        );
+        assert_eq!(8, writer.outstanding_weight());
+        writer.try_send_all(&[1, 1]).unwrap();
+        assert_eq!(vec![4, 4, 1, 1], reader.try_iter().collect::<Vec<_>>());
+        assert_eq!(Ok(()), writer.try_send_all(&[]));
+        assert!(writer.try_send_all(&[0; 9]).is_err());
+    }
+
+    #[test]
+    fn test_write_from_threaded() {
+        let num_loop = 100000;
+        let (writer, reader) = mpmc_queue(16);
+        let reader = reader.into_single().unwrap();
+        scope(|scope| {
+            scope.spawn(move |_| {
+                let vals: Vec<usize> = (0..num_loop).collect();
+                let mut rest = &vals[..];
+                while !rest.is_empty() {
+                    let sent = writer.write_from(&rest[..rest.len().min(5)]);
+                    rest = &rest[sent..];
+                    if sent == 0 {
+                        yield_now();
+                    }
+                }
+            });
+            let mut buf = [0; 7];
+            let mut next = 0;
+            loop {
+                match reader.read_exact(&mut buf) {
+                    Ok(n) => {
+                        for v in &buf[..n] {
+                            assert_eq!(next, *v);
+                            next += 1;
+                        }
+                    }
+                    Err(TryRecvError::Empty) => yield_now(),
+                    Err(TryRecvError::Disconnected) => break,
+                }
+            }
+            assert_eq!(num_loop, next);
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_view_panic() {
+        let count = AtomicUsize::new(0);
+        {
+            let (writer, reader) = mpmc_queue(2);
+            let reader = reader.into_single().ok().unwrap();
+            for _ in 0..2 {
+                writer.try_send(Dropper::new(&count)).ok().unwrap();
+            }
+            assert!(catch_unwind(AssertUnwindSafe(|| {
+                let _ = reader.try_recv_view(|_| -> usize { panic!("view failed") });
+            }))
+            .is_err());
+            // The value was dropped and the stream moved past it
+            assert_eq!(1, count.load(Ordering::Relaxed));
+            assert_eq!(1, reader.lag());
+            assert!(catch_unwind(AssertUnwindSafe(|| {
+                let _ = reader.recv_view(|_| -> usize { panic!("view failed") });
+            }))
+            .is_err());
+            assert_eq!(0, count.load(Ordering::Relaxed));
+            for _ in 0..2 {
+                writer.try_send(Dropper::new(&count)).ok().unwrap();
+            }
+            assert!(reader.try_recv_view(|_| ()).is_ok());
+        }
+        assert_eq!(0, count.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_conflated() {
+        let (writer, reader) = mpmc_queue_conflated(8, |v: &(usize, usize)| v.0);
+        for i in 0..4 {
+            writer.try_send((i % 2, i)).unwrap();
+        }
+        assert_eq!((0, 2), reader.try_recv().unwrap());
+        assert_eq!((1, 3), reader.try_recv().unwrap());
+        assert_eq!(Err(TryRecvError::Empty), reader.try_recv());
+        // Already received values don't get replaced
+        writer.try_send((0, 4)).unwrap();
+        assert_eq!((0, 4), reader.recv().unwrap());
+        writer.try_send((0, 5)).unwrap();
+        writer.try_send((0, 6)).unwrap();
+        let single = reader.into_single().unwrap();
+        assert_eq!(6, single.try_recv_view(|v| v.1).ok().unwrap());
+    }
+
+    #[test]
+    fn test_deduplicated() {
+        let (writer, reader) = mpmc_queue_deduplicated(2, 3, |v: &(usize, usize)| v.0);
+        writer.try_send((0, 0)).unwrap();
+        writer.try_send((0, 1)).unwrap();
+        writer.try_send_ref(&(0, 2)).unwrap();
+        writer.try_send((1, 3)).unwrap();
+        // A value which didn't go in can be sent again
+        assert_eq!(
+            Err(FrozenTrySendError::Full((2, 4))),
+            writer.try_send((2, 4))
+        );
+        assert_eq!((0, 0), reader.try_recv().unwrap());
+        assert_eq!((1, 3), reader.try_recv().unwrap());
+        writer.try_send((2, 4)).unwrap();
+        writer.try_send((3, 5)).unwrap();
+        assert_eq!((2, 4), reader.try_recv().unwrap());
+        // The first id is out of the window now
+        writer.try_send((0, 6)).unwrap();
+        writer.try_send((3, 7)).unwrap();
+        assert_eq!((3, 5), reader.try_recv().unwrap());
+        assert_eq!((0, 6), reader.try_recv().unwrap());
+        assert_eq!(Err(TryRecvError::Empty), reader.try_recv());
+        #[cfg(feature = "stats")]
+        assert_eq!(3, reader.stats().duplicates);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_deduplicated_send_all() {
+        let (writer, _reader) = mpmc_queue_deduplicated(4, 4, |v: &usize| *v);
+        let _ = writer.try_send_all(&[1, 2]);
+    }
+
+    #[test]
+    fn test_deduplicated_threaded() {
+        let num_loop = 2000;
+        let (writer, reader) = mpmc_queue_deduplicated(8, num_loop, |v: &usize| *v);
+        scope(|scope| {
+            // Both writers send every value, as if retrying each other's sends
+            for _ in 0..2 {
+                let cur_writer = writer.clone();
+                scope.spawn(move |_| {
+                    for i in 0..num_loop {
+                        while cur_writer.try_send(i).is_err() {
+                            yield_now();
+                        }
+                    }
+                });
+            }
+            writer.unsubscribe();
+            let mut got: Vec<_> = reader.into_iter().collect();
+            got.sort_unstable();
+            assert_eq!((0..num_loop).collect::<Vec<_>>(), got);
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_delayed_threaded() {
+        let (writer, reader) = mpmc_queue_delayed(4);
+        let num_loop = 200;
+        scope(|scope| {
+            for _ in 0..2 {
+                let cur_writer = writer.clone();
+                scope.spawn(move |_| {
+                    for i in 0..num_loop {
+                        let deadline = Instant::now() + Duration::from_micros(i % 7 * 100);
+                        while cur_writer.try_send_after(deadline, deadline).is_err() {
+                            yield_now();
+                        }
+                    }
+                });
+            }
+            writer.unsubscribe();
+            for _ in 0..2 {
+                let cur_reader = reader.clone();
+                scope.spawn(move |_| {
+                    for deadline in cur_reader {
+                        assert!(Instant::now() >= deadline);
+                    }
+                });
+            }
+            reader.unsubscribe();
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_send_or_replace_threaded() {
+        let (writer, reader) = mpmc_queue_replacing(4);
+        let num_loop = 10000;
+        let received = AtomicUsize::new(0);
+        let mut displaced = 0;
+        scope(|scope| {
+            for _ in 0..2 {
+                let cur_reader = reader.clone();
+                let received = &received;
+                scope.spawn(move |_| {
+                    for val in cur_reader {
+                        received.fetch_add(val, Ordering::Relaxed);
+                    }
+                });
+            }
+            reader.unsubscribe();
+            for i in 1..=num_loop {
+                displaced += writer.try_send_or_replace(i).unwrap().unwrap_or(0);
+            }
+            writer.unsubscribe();
+        })
+        .unwrap();
+        // Every value is either received or handed back, and only once
+        let total = received.load(Ordering::Relaxed) + displaced;
+        assert_eq!(num_loop * (num_loop + 1) / 2, total);
+    }
+
+    #[test]
+    fn test_barrier() {
+        let (writer, reader) = mpmc_queue(4);
+        let reader2 = reader.clone();
+        writer.try_send(1).unwrap();
+        let barrier = writer.try_send_barrier(2).unwrap();
+        assert_eq!(1, reader.try_recv().unwrap());
+        assert!(!writer.barrier_passed(barrier));
+        // Receivers share the stream, so it only takes one of them
+        assert_eq!(2, reader2.try_recv().unwrap());
+        assert!(writer.barrier_passed(barrier));
+        writer.try_send(3).unwrap();
+        assert!(writer.barrier_passed(barrier));
+        assert!(!writer.wait_for_barrier_timeout(writer.barrier(), Duration::from_millis(1)));
+    }
+
+    #[test]
+    fn test_discard_on_drop() {
+        let discarded = Arc::new(AtomicUsize::new(0));
+        let on_dropped = discarded.clone();
+        let policy = DropPolicy::Discard(Box::new(move |val| {
+            on_dropped.fetch_add(val, Ordering::Relaxed);
+        }));
+        let (writer, reader) = mpmc_queue_with_drop_policy(64, policy);
+        let num_loop = 10000;
+        let received = AtomicUsize::new(0);
+        scope(|scope| {
+            for _ in 0..2 {
+                let cur_reader = reader.clone();
+                let received = &received;
+                scope.spawn(move |_| {
+                    for val in cur_reader {
+                        received.fetch_add(val, Ordering::Relaxed);
+                    }
+                });
+            }
+            reader.unsubscribe();
+            for i in 1..=num_loop {
+                while writer.try_send(i).is_err() {
+                    yield_now();
+                }
+            }
+            writer.unsubscribe();
+        })
+        .unwrap();
+        // Every value is either received or discarded, and only once
+        let total = received.load(Ordering::Relaxed) + discarded.load(Ordering::Relaxed);
+        assert_eq!(num_loop * (num_loop + 1) / 2, total);
+    }
+
+    #[test]
+    fn test_discard_without_receivers() {
+        let discarded = Arc::new(AtomicUsize::new(0));
+        let on_dropped = discarded.clone();
+        let policy = DropPolicy::Discard(Box::new(move |val| {
+            on_dropped.fetch_add(val, Ordering::Relaxed);
+        }));
+        let (writer, reader) = mpmc_queue_with_drop_policy(4, policy);
+        writer.try_send(1).unwrap();
+        writer.try_send(2).unwrap();
+        assert_eq!(1, reader.try_recv().unwrap());
+        writer.try_send(4).unwrap();
+        reader.unsubscribe();
+        assert_eq!(0, discarded.load(Ordering::Relaxed));
+        // What's left goes to the callback once the queue is destroyed
+        writer.unsubscribe();
+        assert_eq!(6, discarded.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_replace_on_plain_queue() {
+        let (writer, _reader) = mpmc_queue(4);
+        let _ = writer.try_send_or_replace(1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_delay_on_plain_queue() {
+        let (writer, _reader) = mpmc_queue(4);
+        let _ = writer.try_send_after(1, Instant::now());
+    }
+
+    #[test]
+    fn test_weighted() {
+        let (writer, reader) = mpmc_queue_weighted(8, 10, |v: &usize| *v);
+        writer.try_send(4).unwrap();
+        writer.try_send(6).unwrap();
+        assert_eq!(10, writer.outstanding_weight());
+        assert!(writer.try_send(1).is_err());
+        // Weightless values still count against the capacity
+        for _ in 0..6 {
+            writer.try_send(0).unwrap();
+        }
+        assert!(writer.try_send(0).is_err());
+        assert_eq!(4, reader.try_recv().unwrap());
+        assert!(writer.try_send(5).is_err());
+        writer.try_send(4).unwrap();
+        assert_eq!(
+            vec![6, 0, 0],
+            reader.try_steal_batch(3).unwrap().collect::<Vec<_>>()
+        );
+        assert_eq!(4, writer.outstanding_weight());
+        let single = reader.into_single().unwrap();
+        while single.try_recv_view(|_| ()).is_ok() {}
+        assert_eq!(0, writer.outstanding_weight());
+        // Something heavier than the whole budget gets in on an empty queue
+        writer.try_send(50).unwrap();
+        assert!(writer.try_send(0).is_err());
+        assert_eq!(50, single.try_recv().unwrap());
+    }
+
+    #[test]
+    fn test_try_send_ref_weighted() {
+        let (writer, reader) = mpmc_queue_weighted(8, 10, |v: &usize| *v);
+        let (four, eight) = (4, 8);
+        writer.try_send_ref(&four).unwrap();
+        assert_eq!(
+            Err(FrozenTrySendError::Full(&eight)),
+            writer.try_send_ref(&eight)
+        );
+        assert_eq!(4, writer.outstanding_weight());
+        writer.try_send_ref(&four).unwrap();
+        assert_eq!(8, writer.outstanding_weight());
+        assert_eq!(vec![4, 4], reader.try_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_weighted_threaded() {
+        let (writer, reader) = mpmc_queue_weighted(64, 100, |v: &usize| v % 10);
+        let num_loop = 100000;
+        scope(|scope| {
+            for _ in 0..2 {
+                let cur_writer = writer.clone();
+                scope.spawn(move |_| {
+                    for i in 0..num_loop {
+                        while cur_writer.try_send(i).is_err() {
+                            assert!(cur_writer.outstanding_weight() <= 100);
+                            yield_now();
+                        }
+                    }
+                });
+            }
+            for _ in 0..2 {
+                let cur_reader = reader.clone();
+                scope.spawn(move |_| for _ in cur_reader {});
+            }
+            reader.unsubscribe();
+            writer.unsubscribe();
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_steal_batch() {
+        let (writer, reader) = mpmc_queue(8);
+        assert_eq!(
+            Err(TryRecvError::Empty),
+            reader.try_steal_batch(4).map(|_| ())
+        );
+        for i in 0..8 {
+            writer.try_send(i).unwrap();
+        }
+        assert_eq!(0, reader.try_steal_batch(0).unwrap().len());
+        assert_eq!(
+            vec![0, 1, 2],
+            reader.try_steal_batch(3).unwrap().collect::<Vec<_>>()
+        );
+        // The claimed space is free for the writers again
+        for i in 8..11 {
+            writer.try_send(i).unwrap();
+        }
+        assert_eq!(
+            (3..11).collect::<Vec<_>>(),
+            reader.try_steal_batch(20).unwrap().collect::<Vec<_>>()
+        );
+        drop(writer);
+        assert_eq!(
+            Err(TryRecvError::Disconnected),
+            reader.try_steal_batch(4).map(|_| ())
+        );
+    }
+
+    #[test]
+    fn test_steal_batch_threaded() {
+        let (writer, reader) = mpmc_queue(16);
+        let num_loop = 100000;
+        let total = Arc::new(AtomicUsize::new(0));
+        scope(|scope| {
+            for _ in 0..2 {
+                let cur_writer = writer.clone();
+                scope.spawn(move |_| {
+                    for i in 0..num_loop {
+                        while cur_writer.try_send(i).is_err() {
+                            yield_now();
+                        }
+                    }
+                });
+            }
+            writer.unsubscribe();
+            for _ in 0..4 {
+                let cur_reader = reader.clone();
+                let cur_total = total.clone();
+                scope.spawn(move |_| loop {
+                    match cur_reader.try_steal_batch(5) {
+                        Ok(batch) => {
+                            let batch: Vec<_> = batch.collect();
+                            assert!(!batch.is_empty() && batch.len() <= 5);
+                            cur_total.fetch_add(batch.len(), Ordering::Relaxed);
+                        }
+                        Err(TryRecvError::Empty) => yield_now(),
+                        Err(TryRecvError::Disconnected) => break,
+                    }
+                });
+            }
+            reader.unsubscribe();
+        })
+        .unwrap();
+        assert_eq!(2 * num_loop, total.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_expiring_gooddrop() {
+        let item = Arc::new(0);
+        let (writer, reader) = mpmc_queue_expiring(8);
+        for _ in 0..3 {
+            writer
+                .try_send_with_ttl(item.clone(), Duration::from_millis(10))
+                .unwrap();
+        }
+        writer.try_send(item.clone()).unwrap();
+        assert_eq!(5, Arc::strong_count(&item));
+        sleep(Duration::from_millis(20));
+        // Expired values are dropped by the receiver which skips them
+        let got = reader.try_recv().unwrap();
+        assert_eq!(2, Arc::strong_count(&item));
+        drop(got);
+        assert_eq!(Err(TryRecvError::Empty), reader.try_recv());
+        writer
+            .try_send_with_ttl(item.clone(), Duration::from_millis(1))
+            .unwrap();
+        sleep(Duration::from_millis(5));
+        let reader = reader.into_single().unwrap();
+        assert!(reader.try_recv_view(|_| ()).is_err());
+        assert_eq!(1, Arc::strong_count(&item));
+    }
+
+    #[test]
+    fn test_freeze() {
+        let (writer, reader) = mpmc_queue(4);
+        writer.try_send(0).unwrap();
+        writer.try_send(1).unwrap();
+        reader.freeze();
+        assert!(writer.is_frozen());
+        assert_eq!(Err(FrozenTrySendError::Frozen(2)), writer.try_send(2));
+        assert_eq!(
+            Err(FrozenTrySendError::Frozen(2)),
+            writer.try_send_spin(2, 4)
+        );
+        assert_eq!(Err(FrozenTrySendError::Frozen(&2)), writer.try_send_ref(&2));
+        assert_eq!(
+            Err(FrozenTrySendError::Frozen(())),
+            writer.try_send_all(&[2, 3])
+        );
+        // The receivers drain the queue and then wait instead of disconnecting
+        assert_eq!(vec![0, 1], reader.try_iter().collect::<Vec<_>>());
+        assert_eq!(Err(TryRecvError::Empty), reader.try_recv());
+        writer.unfreeze();
+        assert!(!reader.is_frozen());
+        writer.try_send(2).unwrap();
+        assert_eq!(2, reader.try_recv().unwrap());
+    }
+
+    #[test]
+    fn test_freeze_threaded() {
+        let num_loop = 10000;
+        let (writer, reader) = mpmc_queue(16);
+        let sent = AtomicUsize::new(0);
+        scope(|scope| {
+            for _ in 0..2 {
+                let cur_writer = writer.clone();
+                let sent = &sent;
+                scope.spawn(move |_| {
+                    for i in 0..num_loop {
+                        while cur_writer.try_send(i).is_err() {
+                            yield_now();
+                        }
+                        sent.fetch_add(1, Ordering::SeqCst);
+                    }
+                });
+            }
+            let mut got = 0;
+            while got < num_loop {
+                got += reader.try_iter().count();
+                yield_now();
+            }
+            writer.freeze();
+            // Each writer may have had one send under way, and nothing goes in after that
+            let settled = sent.load(Ordering::SeqCst);
+            while got != sent.load(Ordering::SeqCst) {
+                got += reader.try_iter().count();
+                yield_now();
+            }
+            for _ in 0..100 {
+                yield_now();
+            }
+            assert_eq!(0, reader.try_iter().count());
+            assert!(settled + 2 >= got);
+            writer.unfreeze();
+            writer.unsubscribe();
+            got += reader.iter().count();
+            assert_eq!(2 * num_loop, got);
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_inspector() {
+        let (writer, reader) = mpmc_queue(4);
+        let inspector = reader.inspector();
+        writer.try_send(0).unwrap();
+        writer.try_send(1).unwrap();
+        assert_eq!(2, inspector.occupancy());
+        assert_eq!(None, inspector.pending());
+        assert_eq!(1, inspector.writer_count());
+        drop(writer);
+        assert_eq!(vec![0, 1], reader.try_iter().collect::<Vec<_>>());
+        assert_eq!(Err(TryRecvError::Disconnected), reader.try_recv());
+        assert_eq!(0, inspector.occupancy());
+        drop(reader);
+        assert_eq!(0, inspector.stream_count());
+    }
+
+    #[test]
+    fn test_recv_view() {
+        let count = AtomicUsize::new(0);
+        {
+            let (writer, reader) = mpmc_queue(1);
+            let other = reader.clone();
+            writer.try_send(Dropper::new(&count)).unwrap();
+            let viewed = other.try_recv_view(|_| {
+                // The slot is held until the view is done, even though the stream is past it
+                assert!(writer.try_send(Dropper::new(&count)).is_err());
+                assert!(reader.try_recv().is_err());
+            });
+            assert!(viewed.is_ok());
+            assert_eq!(0, count.load(Ordering::Relaxed));
+            writer.try_send(Dropper::new(&count)).unwrap();
+            assert!(catch_unwind(AssertUnwindSafe(|| {
+                let _ = reader.try_recv_view(|_| -> usize { panic!("view failed") });
+            }))
+            .is_err());
+            // The value was still dropped and the slot given back
+            assert_eq!(0, count.load(Ordering::Relaxed));
+            writer.try_send(Dropper::new(&count)).unwrap();
+            assert!(other.recv_view(|_| ()).is_ok());
+            drop(writer);
+            assert!(reader.recv_view(|_| ()).is_err());
+        }
+        assert_eq!(0, count.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_recv_view_threaded() {
+        let num_loop = 10000;
+        let (writer, reader) = mpmc_queue(4);
+        let total = AtomicUsize::new(0);
+        let viewed = AtomicUsize::new(0);
+        scope(|scope| {
+            for _ in 0..3 {
+                let reader = reader.clone();
+                let (total, viewed) = (&total, &viewed);
+                scope.spawn(move |_| {
+                    let view = |val: &Vec<usize>| {
+                        // A writer reusing the slot mid view would tear the value
+                        assert!(val.iter().all(|v| *v == val[0]));
+                        total.fetch_add(val.iter().sum(), Ordering::Relaxed);
+                        viewed.fetch_add(1, Ordering::Relaxed);
+                    };
+                    while reader.recv_view(view).is_ok() {}
+                });
+            }
+            drop(reader);
+            for i in 0..num_loop {
+                let mut val = writer.try_send(vec![i; 4]);
+                while let Err(FrozenTrySendError::Full(v)) = val {
+                    yield_now();
+                    val = writer.try_send(v);
+                }
+            }
+            drop(writer);
+        })
+        .unwrap();
+        assert_eq!(num_loop, viewed.load(Ordering::Relaxed));
+        assert_eq!(
+            4 * (0..num_loop).sum::<usize>(),
+            total.load(Ordering::Relaxed)
+        );
+    }
+
+    #[test]
+    fn test_expiring_dead_letters() {
+        let (dead_letters, dead) = dead_letter_queue(2);
+        let (writer, reader) = mpmc_queue_expiring_with_dead_letters(8, dead_letters.clone());
+        let ttl = Duration::from_millis(1);
+        for i in 0..3 {
+            writer.try_send_with_ttl(i, ttl).unwrap();
+        }
+        writer.try_send(3).unwrap();
+        sleep(Duration::from_millis(5));
+        assert_eq!(
+            vec![3],
+            reader.try_steal_batch(4).unwrap().collect::<Vec<_>>()
+        );
+
+        // The dead letter queue only had room for two of them
+        let expired: Vec<_> = dead.try_iter().map(|l| (l.reason, l.val)).collect();
+        let reason = DeadLetterReason::Expired;
+        assert_eq!(vec![(reason, 0), (reason, 1)], expired);
+        assert_eq!(1, dead_letters.lost());
+
+        writer.try_send_with_ttl(4, ttl).unwrap();
+        writer.try_send(5).unwrap();
+        sleep(Duration::from_millis(5));
+        let reader = reader.into_single().unwrap();
+        assert_eq!(Ok(5), reader.try_recv_view(|v| *v).map_err(|(_, e)| e));
+        assert_eq!(
+            (reason, 4),
+            dead.try_recv().map(|l| (l.reason, l.val)).unwrap()
+        );
+
+        // Whatever is left goes to the dead letters along with the queue
+        writer.try_send_with_ttl(6, ttl).unwrap();
+        writer.try_send(7).unwrap();
+        sleep(Duration::from_millis(5));
+        drop((writer, reader, dead_letters));
+        let left: Vec<_> = dead.into_iter().map(|l| (l.reason, l.val)).collect();
+        assert_eq!(
+            vec![(reason, 6), (DeadLetterReason::Undeliverable, 7)],
+            left
+        );
+    }
+
+    #[test]
+    fn test_timestamped_threaded() {
+        let (writer, reader) = mpmc_queue_timestamped(4);
+        let num_loop = 1000;
+        let start = Instant::now();
+        scope(|scope| {
+            for _ in 0..2 {
+                let cur_writer = writer.clone();
+                scope.spawn(move |_| {
+                    for i in 0..num_loop {
+                        while cur_writer.try_send(i).is_err() {
+                            yield_now();
+                        }
+                    }
+                });
+            }
+            writer.unsubscribe();
+            for _ in 0..2 {
+                let cur_reader = reader.clone();
+                scope.spawn(move |_| {
+                    while let Ok((_, latency)) = cur_reader.recv_with_latency() {
+                        assert!(latency <= start.elapsed());
+                    }
+                });
+            }
+            reader.unsubscribe();
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_conflated_gooddrop() {
+        let count = Arc::new(AtomicUsize::new(0));
+        {
+            let (writer, reader) = mpmc_queue_conflated(4, |v: &(usize, Arc<AtomicUsize>)| v.0);
+            let make = |key| {
+                count.fetch_add(1, Ordering::Relaxed);
+                (key, count.clone())
+            };
+            for i in 0..20 {
+                writer.try_send(make(i % 3)).unwrap();
+                writer.try_send(make(i % 3)).unwrap();
+                let (_, val) = reader.recv().unwrap();
+                val.fetch_sub(1, Ordering::Relaxed);
+            }
+            writer.try_send(make(7)).unwrap();
+            writer.try_send(make(7)).unwrap();
+        }
+        // Everything which got replaced was dropped exactly once
+        assert_eq!(count.load(Ordering::Relaxed), 20 + 2);
+        assert_eq!(Arc::strong_count(&count), 1);
+    }
+
     #[test]
     fn test_iterator_comp() {
         let (writer, reader) = mpmc_queue::<usize>(10);
@@ -973,6 +3247,68 @@ mod test {
         for _ in reader {}
     }
 
+    #[test]
+    fn test_borrowed_iter() {
+        let (writer, reader) = mpmc_queue::<usize>(4);
+        scope(|scope| {
+            scope.spawn(|_| {
+                for i in 0..1000 {
+                    let mut val = writer.try_send(i);
+                    while let Err(FrozenTrySendError::Full(v)) = val {
+                        yield_now();
+                        val = writer.try_send(v);
+                    }
+                }
+                writer.unsubscribe();
+            });
+            let got: Vec<usize> = reader.iter().collect();
+            assert_eq!((0..1000).collect::<Vec<_>>(), got);
+        })
+        .unwrap();
+        assert_eq!(Err(TryRecvError::Disconnected), reader.try_recv());
+    }
+
+    #[test]
+    fn test_iter_timeout() {
+        let (writer, reader) = mpmc_queue::<usize>(16);
+        for i in 0..3 {
+            writer.try_send(i).unwrap();
+        }
+        let start = Instant::now();
+        let got: Vec<usize> = reader.iter_timeout(Duration::from_millis(20)).collect();
+        assert_eq!(vec![0, 1, 2], got);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+
+        // Whatever is still queued at the deadline stays there
+        writer.try_send(3).unwrap();
+        assert_eq!(0, reader.iter_until(Instant::now()).count());
+        assert_eq!(3, reader.try_recv().unwrap());
+
+        // A writer which never stops doesn't keep iter_until going
+        let stop = AtomicUsize::new(0);
+        scope(|scope| {
+            let (writer, stop) = (writer.clone(), &stop);
+            scope.spawn(move |_| {
+                while stop.load(Ordering::SeqCst) == 0 {
+                    if writer.try_send(1).is_err() {
+                        yield_now();
+                    }
+                }
+            });
+            let deadline = Instant::now() + Duration::from_millis(30);
+            reader.iter_until(deadline).count();
+            assert!(Instant::now() >= deadline);
+            stop.store(1, Ordering::SeqCst);
+        })
+        .unwrap();
+
+        writer.unsubscribe();
+        let start = Instant::now();
+        reader.iter_timeout(Duration::from_secs(60)).count();
+        assert!(start.elapsed() < Duration::from_secs(60));
+        assert_eq!(Err(TryRecvError::Disconnected), reader.try_recv());
+    }
+
     #[test]
     fn test_single_leave_multi() {
         let (writer, reader) = mpmc_queue::<usize>(10);
@@ -985,6 +3321,102 @@ mod test {
         assert!(reader_s.recv_view(|x| *x).is_ok());
     }
 
+    #[test]
+    fn test_recv_indexed_shared() {
+        let (writer, reader) = mpmc_queue(4);
+        let reader2 = reader.clone();
+        for i in 0..20 {
+            writer.try_send(i).unwrap();
+            let (seq, val) = if i % 2 == 0 {
+                reader.try_recv_indexed().unwrap()
+            } else {
+                reader2.recv_indexed().unwrap()
+            };
+            assert_eq!(seq, val);
+        }
+    }
+
+    // Zero sized values can't point at a counter, so they count drops in a static
+    static ZST_LIVE: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Debug)]
+    struct ZstDropper;
+
+    impl ZstDropper {
+        fn new() -> ZstDropper {
+            ZST_LIVE.fetch_add(1, Ordering::Relaxed);
+            ZstDropper
+        }
+    }
+
+    impl Drop for ZstDropper {
+        fn drop(&mut self) {
+            ZST_LIVE.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_zero_sized() {
+        let (writer, reader) = mpmc_queue(4);
+        for _ in 0..4 {
+            writer.try_send(()).unwrap();
+        }
+        assert!(writer.try_send(()).is_err());
+        assert_eq!(4, writer.max_lag());
+        for _ in 0..4 {
+            assert_eq!(Ok(()), reader.try_recv());
+        }
+        assert_eq!(Err(TryRecvError::Empty), reader.try_recv());
+
+        let num_loop = 100000;
+        let received = AtomicUsize::new(0);
+        scope(|scope| {
+            for _ in 0..2 {
+                let cur_writer = writer.clone();
+                scope.spawn(move |_| {
+                    for _ in 0..num_loop {
+                        while cur_writer.try_send(()).is_err() {
+                            yield_now();
+                        }
+                    }
+                });
+            }
+            writer.unsubscribe();
+            for _ in 0..2 {
+                let cur_reader = reader.clone();
+                let received = &received;
+                scope.spawn(move |_| {
+                    for () in cur_reader {
+                        received.fetch_add(1, Ordering::Relaxed);
+                    }
+                });
+            }
+            reader.unsubscribe();
+        })
+        .unwrap();
+        assert_eq!(2 * num_loop, received.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_zero_sized_gooddrop() {
+        {
+            let (writer, reader) = mpmc_queue(4);
+            for _ in 0..10 {
+                writer.try_send(ZstDropper::new()).unwrap();
+                writer.try_send(ZstDropper::new()).unwrap();
+                drop(reader.recv().unwrap());
+                drop(reader.recv().unwrap());
+            }
+            let reader = reader.into_single().unwrap();
+            writer.try_send(ZstDropper::new()).unwrap();
+            assert!(reader.try_recv_view(|_| ()).is_ok());
+            writer.try_send(ZstDropper::new()).unwrap();
+            writer.try_send(ZstDropper::new()).unwrap();
+            assert_eq!(2, ZST_LIVE.load(Ordering::Relaxed));
+        }
+        assert_eq!(0, ZST_LIVE.load(Ordering::Relaxed));
+    }
+
     #[test]
     fn test_recv_clone_item_noclone() {
         struct NoClone;
@@ -0,0 +1,323 @@
+//! Wrappers around the mpmc queue with the same methods and error types as
+//! ```std::sync::mpsc```, so that code written against it can switch over by
+//! changing an import.
+//!
+//! The queue always has a fixed capacity, so there are two differences from std:
+//!
+//! * ```channel``` holds ```DEFAULT_CAPACITY``` values, and ```Sender::send```
+//!   waits for space when they're all taken instead of growing the channel
+//! * ```sync_channel(0)``` holds one value instead of handing values over directly
+//!
+//! Nothing wakes senders waiting for space or receivers waiting with a timeout, so
+//! those poll the queue, spinning and yielding first and then sleeping for up to
+//! ```DEFAULT_SELECT_MAX_SLEEP_US``` microseconds at a time. ```Receiver::recv```
+//! blocks as usual.
+//!
+//! # Examples
+//!
+//! ```
+//! // use std::sync::mpsc::channel;
+//! use multiqueue2::mpsc_compat::channel;
+//! use std::thread;
+//!
+//! let (tx, rx) = channel();
+//! for i in 0..4 {
+//!     let tx = tx.clone();
+//!     thread::spawn(move || tx.send(i).unwrap());
+//! }
+//! drop(tx);
+//!
+//! let mut got: Vec<_> = rx.iter().collect();
+//! got.sort();
+//! assert_eq!(vec![0, 1, 2, 3], got);
+//! ```
+
+use crate::error::FrozenTrySendError;
+use crate::multiqueue::{InnerRecv, InnerSend, MultiQueue, MPMC};
+use crate::wait::Backoff;
+
+pub use std::sync::mpsc::{RecvError, RecvTimeoutError, SendError, TryRecvError, TrySendError};
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// How many values a queue made by ```channel``` holds
+pub const DEFAULT_CAPACITY: usize = 1024;
+
+/// The sending half of a queue made by ```channel```, like ```std::sync::mpsc::Sender```
+pub struct Sender<T> {
+    sender: InnerSend<MPMC<T>, T>,
+}
+
+/// The sending half of a queue made by ```sync_channel```,
+/// like ```std::sync::mpsc::SyncSender```
+pub struct SyncSender<T> {
+    sender: InnerSend<MPMC<T>, T>,
+}
+
+/// The receiving half of a queue, like ```std::sync::mpsc::Receiver```
+pub struct Receiver<T> {
+    receiver: InnerRecv<MPMC<T>, T>,
+}
+
+/// Blocks on each value from a ```Receiver``` in turn, see ```Receiver::iter```
+pub struct Iter<'a, T: 'a> {
+    rx: &'a Receiver<T>,
+}
+
+/// Takes the values waiting in a ```Receiver```, see ```Receiver::try_iter```
+pub struct TryIter<'a, T: 'a> {
+    rx: &'a Receiver<T>,
+}
+
+/// Blocks on each value from a ```Receiver``` it owns, see ```Receiver::into_iter```
+pub struct IntoIter<T> {
+    rx: Receiver<T>,
+}
+
+/// Sends val, waiting for space if the queue is full. Fails once the receiver is gone
+fn send_waiting<T>(sender: &InnerSend<MPMC<T>, T>, mut val: T) -> Result<(), SendError<T>> {
+    let mut backoff = Backoff::new();
+    loop {
+        // Values can still be written for a while after the receiver is gone
+        if sender.stream_count() == 0 {
+            return Err(SendError(val));
+        }
+        match sender.try_send(val) {
+            Ok(()) => return Ok(()),
+            Err(FrozenTrySendError::Full(v))
+            | Err(FrozenTrySendError::Disconnected(v))
+            | Err(FrozenTrySendError::Frozen(v)) => val = v,
+        }
+        backoff.snooze(None);
+    }
+}
+
+impl<T> Sender<T> {
+    /// Sends a value, waiting for space if the queue is full.
+    /// Fails with the value if the receiver has been dropped
+    pub fn send(&self, t: T) -> Result<(), SendError<T>> {
+        send_waiting(&self.sender, t)
+    }
+}
+
+impl<T> SyncSender<T> {
+    /// Sends a value, waiting for space if the queue is full.
+    /// Fails with the value if the receiver has been dropped
+    pub fn send(&self, t: T) -> Result<(), SendError<T>> {
+        send_waiting(&self.sender, t)
+    }
+
+    /// Sends a value if there's space for it, without waiting
+    pub fn try_send(&self, t: T) -> Result<(), TrySendError<T>> {
+        if self.sender.stream_count() == 0 {
+            return Err(TrySendError::Disconnected(t));
+        }
+        // A frozen queue has no room for now, which is all std's error can say
+        self.sender.try_send(t).map_err(TrySendError::from)
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Receives a value if one is waiting, without blocking
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.receiver.try_recv()
+    }
+
+    /// Blocks until a value can be received, failing once
+    /// the queue is empty and every sender has been dropped
+    pub fn recv(&self) -> Result<T, RecvError> {
+        self.receiver.recv()
+    }
+
+    /// Like ```recv```, but gives up once timeout has passed
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.receiver.recv_until(deadline),
+            None => self.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        }
+    }
+
+    /// Returns an iterator which blocks on each value, ending once
+    /// the queue is empty and every sender has been dropped
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter { rx: self }
+    }
+
+    /// Returns an iterator over the values which can be received without blocking
+    pub fn try_iter(&self) -> TryIter<'_, T> {
+        TryIter { rx: self }
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Sender<T> {
+        Sender {
+            sender: self.sender.clone(),
+        }
+    }
+}
+
+impl<T> Clone for SyncSender<T> {
+    fn clone(&self) -> SyncSender<T> {
+        SyncSender {
+            sender: self.sender.clone(),
+        }
+    }
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.rx.recv().ok()
+    }
+}
+
+impl<'a, T> Iterator for TryIter<'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.rx.try_recv().ok()
+    }
+}
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.rx.recv().ok()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Receiver<T> {
+    type Item = T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<T> IntoIterator for Receiver<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter { rx: self }
+    }
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sender").finish()
+    }
+}
+
+impl<T> fmt::Debug for SyncSender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SyncSender").finish()
+    }
+}
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Receiver").finish()
+    }
+}
+
+unsafe impl<T: Send> Send for Sender<T> {}
+unsafe impl<T: Send> Send for SyncSender<T> {}
+unsafe impl<T: Send> Send for Receiver<T> {}
+
+/// Creates a (```Sender```, ```Receiver```) pair like ```std::sync::mpsc::channel```,
+/// except that it holds ```DEFAULT_CAPACITY``` values and sends wait when it's full
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let (send, recv) = MultiQueue::<MPMC<T>, T>::create_tx_rx(DEFAULT_CAPACITY);
+    (Sender { sender: send }, Receiver { receiver: recv })
+}
+
+/// Creates a (```SyncSender```, ```Receiver```) pair like ```std::sync::mpsc::sync_channel```,
+/// holding the next power of two >= bound values, and at least one
+pub fn sync_channel<T>(bound: usize) -> (SyncSender<T>, Receiver<T>) {
+    let (send, recv) = MultiQueue::<MPMC<T>, T>::create_tx_rx(bound);
+    (SyncSender { sender: send }, Receiver { receiver: recv })
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    use std::thread::{self, sleep};
+
+    #[test]
+    fn test_send_recv() {
+        let (tx, rx) = channel();
+        tx.send(1).unwrap();
+        assert_eq!(Ok(1), rx.recv());
+        assert_eq!(Err(TryRecvError::Empty), rx.try_recv());
+        drop(tx);
+        assert_eq!(Err(TryRecvError::Disconnected), rx.try_recv());
+        assert_eq!(Err(RecvError), rx.recv());
+    }
+
+    #[test]
+    fn test_send_after_receiver_dropped() {
+        let (tx, rx) = channel();
+        drop(rx);
+        assert_eq!(Err(SendError(1)), tx.send(1));
+        let (stx, srx) = sync_channel(1);
+        drop(srx);
+        assert_eq!(Err(TrySendError::Disconnected(1)), stx.try_send(1));
+        assert_eq!(Err(SendError(2)), stx.send(2));
+    }
+
+    #[test]
+    fn test_sync_send_waits() {
+        let (tx, rx) = sync_channel(1);
+        tx.send(0).unwrap();
+        assert_eq!(Err(TrySendError::Full(1)), tx.try_send(1));
+        let t = thread::spawn(move || {
+            for i in 1..100 {
+                tx.send(i).unwrap();
+            }
+        });
+        assert_eq!((0..100).collect::<Vec<_>>(), rx.iter().collect::<Vec<_>>());
+        t.join().unwrap();
+    }
+
+    #[test]
+    fn test_recv_timeout() {
+        let (tx, rx) = channel();
+        let start = Instant::now();
+        assert_eq!(
+            Err(RecvTimeoutError::Timeout),
+            rx.recv_timeout(Duration::from_millis(20))
+        );
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        let t = thread::spawn(move || {
+            sleep(Duration::from_millis(5));
+            tx.send(1).unwrap();
+        });
+        assert_eq!(Ok(1), rx.recv_timeout(Duration::from_secs(10)));
+        t.join().unwrap();
+        assert_eq!(
+            Err(RecvTimeoutError::Disconnected),
+            rx.recv_timeout(Duration::from_secs(10))
+        );
+    }
+
+    #[test]
+    fn test_iters() {
+        let (tx, rx) = channel();
+        for i in 0..3 {
+            tx.send(i).unwrap();
+        }
+        assert_eq!(vec![0, 1, 2], rx.try_iter().collect::<Vec<_>>());
+        assert_eq!(0, rx.try_iter().count());
+        tx.send(3).unwrap();
+        drop(tx);
+        assert_eq!(vec![3], rx.into_iter().collect::<Vec<_>>());
+    }
+}
@@ -1,34 +1,79 @@
-use std::cell::Cell;
+use std::borrow::Cow;
+use std::cell::{Cell, UnsafeCell};
 use std::collections::VecDeque;
 use std::fmt;
 use std::marker::PhantomData;
-use std::mem;
+use std::mem::{self, ManuallyDrop, MaybeUninit};
+use std::panic::{self, AssertUnwindSafe};
 use std::ptr;
-use std::sync::atomic::Ordering::*;
-use std::sync::atomic::{fence, AtomicUsize};
-use std::sync::mpsc::{RecvError, SendError, TryRecvError, TrySendError};
-use std::sync::Arc;
-use std::thread::yield_now;
+use std::sync::mpsc::{RecvError, RecvTimeoutError, SendError, TryRecvError, TrySendError};
+use std::sync::{Arc, Weak};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
 
 use crate::alloc;
 use crate::atomicsignal::LoadedSignal;
+use crate::clock::{Clock, MonotonicClock};
+use crate::conflate::Conflate;
 use crate::countedindex::{
-    get_valid_wrap, is_tagged, rm_tag, CountedIndex, Index, INITIAL_QUEUE_FLAG,
+    capacity_index, get_valid_wrap, is_tagged, past, rm_tag, CountedIndex, Index,
+    INITIAL_QUEUE_FLAG,
 };
-use crate::memory::{MemToken, MemoryManager};
+use crate::dead_letter::{DeadLetterReason, DeadLetterSender};
+use crate::dedup::Dedup;
+use crate::error::{
+    AttachError, FrozenTrySendError, LaggedRecvError, LaggedTryRecvError, StreamsFullError,
+};
+#[cfg(feature = "fault_injection")]
+use crate::faults::{FaultConfig, Faults};
+#[cfg(feature = "test-util")]
+use crate::invariants::InvariantReport;
+use crate::memory::{MemoryManager, Reclaim, ReclaimToken};
+#[cfg(feature = "metrics")]
+use crate::metrics::QueueMetrics;
+#[cfg(feature = "order_checks")]
+use crate::order_check::OrderCheck;
+use crate::ordering::{ACQUIRE, ACQ_REL, RELAXED, RELEASE, SEQ_CST};
+#[cfg(feature = "registry")]
+use crate::registry;
+#[cfg(feature = "stats")]
+use crate::stats::Counters;
+use crate::stats::{MemoryFootprint, QueueStats};
+use crate::sync::{fence, yield_now, AtomicBool, AtomicStamp, AtomicUsize};
+#[cfg(feature = "tracing")]
+use crate::trace::QueueTrace;
 use crate::wait::*;
 
-use crate::read_cursor::{ReadCursor, Reader};
+use crate::read_cursor::{FixedSlot, ReadAttempt, ReadCursor, Reader};
 
 extern crate atomic_utilities;
+extern crate crossbeam;
 extern crate futures;
 extern crate parking_lot;
 extern crate smallvec;
 
+use self::crossbeam::channel::Sender;
 use self::futures::task::{current, Task};
 use self::futures::{Async, AsyncSink, Poll, Sink, StartSend, Stream};
 
-use self::atomic_utilities::artificial_dep::{dependently_mut, DepOrd};
+use self::atomic_utilities::artificial_dep::{dependently, DepOrd};
+
+/// How many times in a row a writer can lose the swap on the head
+/// before the other writers hold back to let it through
+const STARVING_AFTER: usize = 8;
+
+/// How long writers hold back before trying while another is starving
+const STARVING_HOLD_SPINS: usize = 64;
+
+/// Writers back off for at most twice 1 << this many spins between tries
+const MAX_BACKOFF_SHIFT: usize = 6;
+
+#[inline(always)]
+fn spin(times: usize) {
+    for _ in 0..times {
+        std::hint::spin_loop();
+    }
+}
 
 /// This is basically acting as a static bool
 /// so the queue can act as a normal mpmc in other circumstances
@@ -36,10 +81,16 @@ pub trait QueueRW<T> {
     fn inc_ref(_: &AtomicUsize);
     fn dec_ref(_: &AtomicUsize);
     fn check_ref(_: &AtomicUsize) -> bool;
+    /// Whether consumers on a stream with several of them always hold the slot
+    /// they're reading with the refcount, so that check_ref covers every hold
+    fn counts_refs() -> bool;
     fn do_drop() -> bool;
-    unsafe fn get_val(_: &mut T) -> T;
-    fn forget_val(_: T);
-    unsafe fn drop_in_place(_: &mut T);
+    /// Reads a value out before the stream is moved past it. Until then
+    /// another consumer may own it, so it isn't a T yet
+    unsafe fn get_val(_: *mut T) -> MaybeUninit<T>;
+    /// Gets rid of a value from get_val when the stream couldn't be moved past it
+    unsafe fn forget_val(_: MaybeUninit<T>);
+    unsafe fn drop_in_place(_: *mut T);
 }
 
 #[derive(Clone)]
@@ -58,12 +109,17 @@ impl<T: Clone> QueueRW<T> for BCast<T> {
     // TODO: Skip refcount when type is copyable or clone is safe on junk data
     #[inline(always)]
     fn dec_ref(r: &AtomicUsize) {
-        r.fetch_sub(1, Relaxed);
+        r.fetch_sub(1, RELAXED);
     }
 
     #[inline(always)]
     fn check_ref(r: &AtomicUsize) -> bool {
-        r.load(Relaxed) == 0
+        r.load(RELAXED) == 0
+    }
+
+    #[inline(always)]
+    fn counts_refs() -> bool {
+        true
     }
 
     #[inline(always)]
@@ -72,15 +128,17 @@ impl<T: Clone> QueueRW<T> for BCast<T> {
     }
 
     #[inline(always)]
-    unsafe fn get_val(val: &mut T) -> T {
-        val.clone()
+    unsafe fn get_val(val: *mut T) -> MaybeUninit<T> {
+        MaybeUninit::new((*val).clone())
     }
 
     #[inline(always)]
-    fn forget_val(_v: T) {}
+    unsafe fn forget_val(mut val: MaybeUninit<T>) {
+        val.assume_init_drop();
+    }
 
     #[inline(always)]
-    unsafe fn drop_in_place(_v: &mut T) {}
+    unsafe fn drop_in_place(_v: *mut T) {}
 }
 
 #[derive(Clone)]
@@ -100,23 +158,27 @@ impl<T> QueueRW<T> for MPMC<T> {
         true
     }
 
+    // Only try_recv_view_claimed holds slots, see MultiQueue::slot_free
     #[inline(always)]
-    fn do_drop() -> bool {
+    fn counts_refs() -> bool {
         false
     }
 
     #[inline(always)]
-    unsafe fn get_val(val: &mut T) -> T {
-        ptr::read(val)
+    fn do_drop() -> bool {
+        false
     }
 
     #[inline(always)]
-    fn forget_val(val: T) {
-        mem::forget(val);
+    unsafe fn get_val(val: *mut T) -> MaybeUninit<T> {
+        ptr::read(val as *const MaybeUninit<T>)
     }
 
     #[inline(always)]
-    unsafe fn drop_in_place(val: &mut T) {
+    unsafe fn forget_val(_val: MaybeUninit<T>) {}
+
+    #[inline(always)]
+    unsafe fn drop_in_place(val: *mut T) {
         ptr::drop_in_place(val);
     }
 }
@@ -127,66 +189,281 @@ enum QueueState {
     Multi,
 }
 
-/// This holds entries in the queue
+/// This holds entries in the queue. Several threads look at a slot at once,
+/// so it's only ever borrowed shared, and the value goes through the UnsafeCell.
+/// For zero sized values this is just the tag, and moving values in and out
+/// compiles down to nothing, so the queue is only counting and waiting.
 struct QueueEntry<T> {
-    val: T,
+    val: UnsafeCell<T>,
     wraps: AtomicUsize,
 }
 
+impl<T> QueueEntry<T> {
+    /// Returns a pointer to the value, which reads through
+    /// will be ordered after the load of tag
+    #[inline(always)]
+    fn val_after(&self, tag: usize) -> *mut T {
+        dependently(tag, &self.val, |val| val.get())
+    }
+}
+
 /// This holds the refcount object
 struct RefCnt {
     refcnt: AtomicUsize,
+    // The sequence number of the value in this slot if it has been replaced by a newer one
+    superseded: AtomicUsize,
     _buffer: [u8; 64],
 }
 
+/// The times kept for each slot on delay, expiring and timestamped queues.
+/// Other queues don't allocate these at all
+struct SlotTimes {
+    // On delay queues, the nanoseconds after delay_base at which the value can be read
+    ready_at: AtomicStamp,
+    // On expiring queues, the nanoseconds after expiry_base at which the value is dropped
+    expires_at: AtomicStamp,
+    // On timestamped queues, the nanoseconds after stamp_base at which the value was sent
+    sent_at: AtomicStamp,
+}
+
+impl SlotTimes {
+    /// Records the times for a value about to be published in the slot
+    #[inline(always)]
+    fn store(&self, ready_at: u64, expires_at: u64, stamp: Option<u64>) {
+        self.ready_at.store(ready_at, RELAXED);
+        self.expires_at.store(expires_at, RELAXED);
+        if let Some(stamp) = stamp {
+            self.sent_at.store(stamp, RELAXED);
+        }
+    }
+}
+
+/// A value being sent. One sent by reference is only cloned
+/// once there's room for it, by the function held alongside it
+pub enum Payload<'a, T> {
+    Owned(T),
+    Cloned(&'a T, fn(&T) -> T),
+}
+
+impl<'a, T> Payload<'a, T> {
+    #[inline(always)]
+    fn get(&self) -> &T {
+        match *self {
+            Payload::Owned(ref val) => val,
+            Payload::Cloned(val, _) => val,
+        }
+    }
+
+    #[inline(always)]
+    fn into_val(self) -> T {
+        match self {
+            Payload::Owned(val) => val,
+            Payload::Cloned(val, clone) => clone(val),
+        }
+    }
+
+    /// Clones a value sent by reference, so that it can be retried without cloning again
+    #[inline(always)]
+    fn into_owned(self) -> Payload<'a, T> {
+        Payload::Owned(self.into_val())
+    }
+}
+
 /// A bounded queue that supports multiple reader and writers
 /// and supports effecient methods for single consumers and producers
 #[repr(C)]
-pub struct MultiQueue<RW: QueueRW<T>, T> {
+pub struct MultiQueue<RW: QueueRW<T>, T, S: ?Sized = [FixedSlot]> {
     d1: [u8; 64],
 
     // Writer data
     head: CountedIndex,
     tail_cache: AtomicUsize,
     writers: AtomicUsize,
+    // Writers which have lost the compare and swap on the head too often in a row
+    starving: AtomicUsize,
+    // Readers which found their stream empty and are waiting to be woken.
+    // Only kept when the wait strategy doesn't need every notify
+    waiting: AtomicUsize,
     d2: [u8; 64],
 
     // Shared Data
     // The data and the wraps flag are in the same location
     // to reduce the # of distinct cache lines read when getting an item
-    // The tail itself is rarely modified, making it a suitable candidate
-    // to be in the shared space
-    tail: ReadCursor,
     data: *mut QueueEntry<T>,
     refs: *mut RefCnt,
     capacity: isize,
     pub waiter: Arc<dyn Wait>,
     needs_notify: bool,
+    skip_idle_notify: bool,
+    // Rung along with the readers, for receivers blocked on several queues at once
+    bells: parking_lot::Mutex<Vec<Sender<()>>>,
+    num_bells: AtomicUsize,
+    // Set once an mpmc consumer first holds a slot with try_recv_view_claimed,
+    // after which writers have to check the refcounts of mpmc slots as well
+    claimed_views: AtomicBool,
+    conflator: Option<Box<dyn Conflate<T>>>,
+    conflating: bool,
+    dedup: Option<Box<dyn Dedup<T>>>,
+    replacing: bool,
+    drop_policy: DropPolicy<T>,
+    dead_letters: Option<DeadLetterSender<T>>,
+    delay_base: Option<Instant>,
+    expiry_base: Option<Instant>,
+    stamp_base: Option<Instant>,
+    times: Option<Box<[SlotTimes]>>,
+    created: Option<Instant>,
+    clock: Arc<dyn Clock>,
+    weigher: Option<Weigher<T>>,
+    weight_budget: usize,
+    weight: AtomicUsize,
+    #[cfg(feature = "metrics")]
+    metrics: Option<Arc<dyn QueueMetrics>>,
+    // Whether the metrics were last told the queue became full or empty
+    #[cfg(feature = "metrics")]
+    full_edge: AtomicBool,
+    #[cfg(feature = "metrics")]
+    empty_edge: AtomicBool,
+    #[cfg(feature = "stats")]
+    counters: Counters,
+    #[cfg(feature = "tracing")]
+    trace: QueueTrace,
+    #[cfg(feature = "fault_injection")]
+    faults: Faults,
+    #[cfg(feature = "registry")]
+    registry_id: usize,
     mk: PhantomData<RW>,
     d3: [u8; 64],
 
     pub manager: MemoryManager,
     d4: [u8; 64],
+
+    // The tail is rarely modified, but it holds the streams of queues with a fixed
+    // number of them in place, so it comes last where it can be any size.
+    // It also drops after the manager, which may still hand back slots in it
+    tail: ReadCursor<S>,
 }
 
 pub struct InnerSend<RW: QueueRW<T>, T> {
     queue: Arc<MultiQueue<RW, T>>,
-    token: *const MemToken,
+    token: ReclaimToken,
     state: Cell<QueueState>,
+    #[cfg(feature = "order_checks")]
+    sent: OrderCheck,
+}
+
+/// A predicate deciding which values a filtered stream receives
+type Filter<T> = Arc<dyn Fn(&T) -> bool + Send + Sync>;
+
+/// Returns how much of a weighted queue's budget a value takes up
+pub type Weigher<T> = Box<dyn Fn(&T) -> usize + Send + Sync>;
+
+/// Room taken in a queue by ```InnerSend::reserve```, which has to be handed back
+/// to either ```InnerSend::commit``` or ```InnerSend::release```
+#[must_use]
+pub(crate) struct Reservation {
+    weight: usize,
+}
+
+/// A point in a queue between the values written before it and those written after.
+/// Streams have passed it once they've received everything written before it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Barrier {
+    at: usize,
+}
+
+/// What happens to the values still in a queue when its last sender drops
+pub enum DropPolicy<T> {
+    /// Receivers go on receiving them, and disconnect once they've received everything
+    Drain,
+    /// Receivers go on receiving them, and the dropping sender blocks until every
+    /// stream which isn't paused has received them or the timeout has passed
+    Wait(Duration),
+    /// The dropping sender takes them out of the queue and passes each one to the
+    /// callback instead of delivering it. Receivers can still be receiving while this
+    /// happens, so each value either gets received or goes to the callback, never both.
+    /// Values left in the queue when it's destroyed go to the callback as well
+    Discard(Box<dyn Fn(T) + Send + Sync>),
+}
+
+/// The optional behaviours a queue can be created with
+struct QueueOptions<T> {
+    conflator: Option<Box<dyn Conflate<T>>>,
+    dedup: Option<Box<dyn Dedup<T>>>,
+    replacing: bool,
+    drop_policy: DropPolicy<T>,
+    dead_letters: Option<DeadLetterSender<T>>,
+    delayed: bool,
+    expiring: bool,
+    timestamped: bool,
+    weigher: Option<(usize, Weigher<T>)>,
+    #[cfg(feature = "metrics")]
+    metrics: Option<Arc<dyn QueueMetrics>>,
+    reclaim: Option<Arc<dyn Reclaim>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl<T> Default for QueueOptions<T> {
+    fn default() -> QueueOptions<T> {
+        QueueOptions {
+            conflator: None,
+            dedup: None,
+            replacing: false,
+            drop_policy: DropPolicy::Drain,
+            dead_letters: None,
+            delayed: false,
+            expiring: false,
+            timestamped: false,
+            weigher: None,
+            #[cfg(feature = "metrics")]
+            metrics: None,
+            reclaim: None,
+            clock: Arc::new(MonotonicClock),
+        }
+    }
 }
 
 pub struct InnerRecv<RW: QueueRW<T>, T> {
     queue: Arc<MultiQueue<RW, T>>,
     reader: Reader,
-    token: *const MemToken,
+    token: ReclaimToken,
+    filter: Option<Filter<T>>,
+    name: Option<Cow<'static, str>>,
     alive: bool,
 }
 
+/// The next value on a stream, borrowed in place from the queue.
+/// It counts as received once the guard is dropped, and until then
+/// the stream stays on it
+pub struct RecvGuard<'a, T> {
+    queue: &'a dyn TakeVal<T>,
+    val: *mut T,
+    attempt: Option<ReadAttempt<'a>>,
+}
+
+/// Lets a RecvGuard take its value without knowing what kind of queue it came from
+trait TakeVal<T> {
+    unsafe fn take_val(&self, val: *mut T);
+}
+
+/// A stream taken off a queue with ```InnerRecv::detach```. It holds where the
+/// stream was, but writers don't wait on it, so it can only be attached again
+/// while nothing it hadn't received has been overwritten
+pub struct ReaderToken<RW: QueueRW<T>, T> {
+    queue: Weak<MultiQueue<RW, T>>,
+    pos: usize,
+    max_lag: usize,
+    stream_name: Option<Cow<'static, str>>,
+    filter: Option<Filter<T>>,
+    name: Option<Cow<'static, str>>,
+}
+
 /// This is a sender that can transparently act as a futures stream
 pub struct FutInnerSend<RW: QueueRW<T>, T> {
     writer: InnerSend<RW, T>,
     wait: Arc<FutWait>,
     prod_wait: Arc<FutWait>,
+    // Whether start_send has written values the receivers haven't been woken for
+    unflushed: AtomicBool,
 }
 
 /// This is a receiver that can transparently act as a futures stream
@@ -194,170 +471,650 @@ pub struct FutInnerRecv<RW: QueueRW<T>, T> {
     reader: InnerRecv<RW, T>,
     wait: Arc<FutWait>,
     prod_wait: Arc<FutWait>,
+    // Whether a task parked on this is counted as waiting on the queue
+    waiting: Cell<bool>,
 }
 
 pub struct FutInnerUniRecv<RW: QueueRW<T>, R, F: FnMut(&T) -> R, T> {
     reader: InnerRecv<RW, T>,
     wait: Arc<FutWait>,
     prod_wait: Arc<FutWait>,
+    waiting: Cell<bool>,
     pub op: F,
 }
 
 struct FutWait {
-    spins_first: usize,
-    spins_yield: usize,
+    // Atomics so that they can be tuned while the queue is in use
+    spins_first: AtomicUsize,
+    spins_yield: AtomicUsize,
     parked: parking_lot::Mutex<VecDeque<Task>>,
 }
 
 impl<RW: QueueRW<T>, T> MultiQueue<RW, T> {
-    pub fn create_tx_rx(_capacity: Index) -> (InnerSend<RW, T>, InnerRecv<RW, T>) {
-        MultiQueue::create_tx_rx_with(_capacity, BlockingWait::new())
+    pub fn create_tx_rx(_capacity: usize) -> (InnerSend<RW, T>, InnerRecv<RW, T>) {
+        MultiQueue::create_tx_rx_with(_capacity, DefaultWait::new())
     }
 
     pub fn create_tx_rx_with<W: Wait + 'static>(
-        capacity: Index,
+        capacity: usize,
+        wait: W,
+    ) -> (InnerSend<RW, T>, InnerRecv<RW, T>) {
+        MultiQueue::new_internal(capacity, Arc::new(wait), QueueOptions::default())
+    }
+
+    /// Creates a queue where sent values can replace older ones which haven't been read yet
+    pub fn create_tx_rx_conflated<C: Conflate<T> + 'static>(
+        capacity: usize,
+        conflator: C,
+    ) -> (InnerSend<RW, T>, InnerRecv<RW, T>) {
+        let options = QueueOptions {
+            conflator: Some(Box::new(conflator)),
+            ..QueueOptions::default()
+        };
+        MultiQueue::new_internal(capacity, Arc::new(DefaultWait::new()), options)
+    }
+
+    /// Creates a queue where sent values are dropped if they duplicate one sent recently
+    pub fn create_tx_rx_deduplicated<D: Dedup<T> + 'static>(
+        capacity: usize,
+        dedup: D,
+    ) -> (InnerSend<RW, T>, InnerRecv<RW, T>) {
+        let options = QueueOptions {
+            dedup: Some(Box::new(dedup)),
+            ..QueueOptions::default()
+        };
+        MultiQueue::new_internal(capacity, Arc::new(DefaultWait::new()), options)
+    }
+
+    /// Creates a queue where writers can take the oldest value off a full
+    /// queue's only stream to make room for a new one
+    pub fn create_tx_rx_replacing(capacity: usize) -> (InnerSend<RW, T>, InnerRecv<RW, T>) {
+        let options = QueueOptions {
+            replacing: true,
+            ..QueueOptions::default()
+        };
+        MultiQueue::new_internal(capacity, Arc::new(DefaultWait::new()), options)
+    }
+
+    /// Creates a queue which handles the values left when the last writer drops as the policy says
+    pub fn create_tx_rx_with_drop_policy(
+        capacity: usize,
+        drop_policy: DropPolicy<T>,
+    ) -> (InnerSend<RW, T>, InnerRecv<RW, T>) {
+        let options = QueueOptions {
+            drop_policy,
+            ..QueueOptions::default()
+        };
+        MultiQueue::new_internal(capacity, Arc::new(DefaultWait::new()), options)
+    }
+
+    /// Creates a queue where values can be sent with a deadline before which readers can't see them
+    pub fn create_tx_rx_delayed(capacity: usize) -> (InnerSend<RW, T>, InnerRecv<RW, T>) {
+        let options = QueueOptions {
+            delayed: true,
+            ..QueueOptions::default()
+        };
+        MultiQueue::new_internal(capacity, Arc::new(DefaultWait::new()), options)
+    }
+
+    /// Creates a queue where values can be sent with a time to live,
+    /// after which readers drop them instead of receiving them
+    pub fn create_tx_rx_expiring(capacity: usize) -> (InnerSend<RW, T>, InnerRecv<RW, T>) {
+        let options = QueueOptions {
+            expiring: true,
+            ..QueueOptions::default()
+        };
+        MultiQueue::new_internal(capacity, Arc::new(DefaultWait::new()), options)
+    }
+
+    /// Creates an expiring queue which passes values to the dead letters instead of
+    /// dropping them when they expire or are left in the queue when it's destroyed
+    pub fn create_tx_rx_expiring_with_dead_letters(
+        capacity: usize,
+        dead_letters: DeadLetterSender<T>,
+    ) -> (InnerSend<RW, T>, InnerRecv<RW, T>) {
+        let options = QueueOptions {
+            expiring: true,
+            dead_letters: Some(dead_letters),
+            ..QueueOptions::default()
+        };
+        MultiQueue::new_internal(capacity, Arc::new(DefaultWait::new()), options)
+    }
+
+    /// Creates a queue with the passed wait strategy which passes the values
+    /// left in it when it's destroyed to the dead letters
+    pub fn create_tx_rx_with_dead_letters<W: Wait + 'static>(
+        capacity: usize,
+        wait: W,
+        dead_letters: DeadLetterSender<T>,
+    ) -> (InnerSend<RW, T>, InnerRecv<RW, T>) {
+        let options = QueueOptions {
+            dead_letters: Some(dead_letters),
+            ..QueueOptions::default()
+        };
+        MultiQueue::new_internal(capacity, Arc::new(wait), options)
+    }
+
+    /// Creates a queue which records when each value was sent,
+    /// so readers can tell how long it waited to be received
+    pub fn create_tx_rx_timestamped(capacity: usize) -> (InnerSend<RW, T>, InnerRecv<RW, T>) {
+        let options = QueueOptions {
+            timestamped: true,
+            ..QueueOptions::default()
+        };
+        MultiQueue::new_internal(capacity, Arc::new(DefaultWait::new()), options)
+    }
+
+    /// Creates a queue with delays, expiry and timestamps which reads the time from the passed clock
+    pub fn create_tx_rx_with_clock(
+        capacity: usize,
+        clock: Arc<dyn Clock>,
+    ) -> (InnerSend<RW, T>, InnerRecv<RW, T>) {
+        let options = QueueOptions {
+            delayed: true,
+            expiring: true,
+            timestamped: true,
+            clock,
+            ..QueueOptions::default()
+        };
+        MultiQueue::new_internal(capacity, Arc::new(DefaultWait::new()), options)
+    }
+
+    /// Creates a queue where writers fail once the values waiting to be read
+    /// weigh more than the budget, along with being limited by capacity
+    pub fn create_tx_rx_weighted(
+        capacity: usize,
+        budget: usize,
+        weigher: Weigher<T>,
+    ) -> (InnerSend<RW, T>, InnerRecv<RW, T>) {
+        let options = QueueOptions {
+            weigher: Some((budget, weigher)),
+            ..QueueOptions::default()
+        };
+        MultiQueue::new_internal(capacity, Arc::new(DefaultWait::new()), options)
+    }
+
+    /// Creates a queue which reports what happens on it to the passed metrics
+    #[cfg(feature = "metrics")]
+    pub fn create_tx_rx_with_metrics<W: Wait + 'static>(
+        capacity: usize,
+        wait: W,
+        metrics: Arc<dyn QueueMetrics>,
+    ) -> (InnerSend<RW, T>, InnerRecv<RW, T>) {
+        let options = QueueOptions {
+            metrics: Some(metrics),
+            ..QueueOptions::default()
+        };
+        MultiQueue::new_internal(capacity, Arc::new(wait), options)
+    }
+
+    /// Creates a queue which frees the memory it replaces through the passed backend
+    pub fn create_tx_rx_with_reclaim<W: Wait + 'static>(
+        capacity: usize,
         wait: W,
+        reclaim: Arc<dyn Reclaim>,
+    ) -> (InnerSend<RW, T>, InnerRecv<RW, T>) {
+        let options = QueueOptions {
+            reclaim: Some(reclaim),
+            ..QueueOptions::default()
+        };
+        MultiQueue::new_internal(capacity, Arc::new(wait), options)
+    }
+
+    /// Creates a queue which holds up to STREAMS streams directly in its cursor.
+    /// A removed stream's room is taken again once nobody can be looking at it
+    pub fn create_tx_rx_fixed<const STREAMS: usize>(
+        capacity: usize,
+    ) -> (InnerSend<RW, T>, InnerRecv<RW, T>) {
+        assert!(STREAMS > 0, "Multiqueue error - zero streams received");
+        MultiQueue::with_streams::<STREAMS>(
+            capacity,
+            Arc::new(DefaultWait::new()),
+            QueueOptions::default(),
+        )
+    }
+
+    fn new_internal(
+        capacity: usize,
+        wait: Arc<dyn Wait>,
+        options: QueueOptions<T>,
     ) -> (InnerSend<RW, T>, InnerRecv<RW, T>) {
-        MultiQueue::new_internal(capacity, Arc::new(wait))
+        MultiQueue::with_streams::<0>(capacity, wait, options)
     }
 
-    fn new_internal(_capacity: Index, wait: Arc<dyn Wait>) -> (InnerSend<RW, T>, InnerRecv<RW, T>) {
-        let capacity = get_valid_wrap(_capacity);
+    /// Creates a queue whose cursor holds STREAMS streams in place,
+    /// or any number of them out of line if that's zero
+    fn with_streams<const STREAMS: usize>(
+        _capacity: usize,
+        wait: Arc<dyn Wait>,
+        options: QueueOptions<T>,
+    ) -> (InnerSend<RW, T>, InnerRecv<RW, T>) {
+        let capacity = get_valid_wrap(capacity_index(_capacity));
         let queuedat: *mut QueueEntry<T> = alloc::allocate(capacity as usize);
         let refdat: *mut RefCnt = alloc::allocate(capacity as usize);
         unsafe {
             for i in 0..capacity as isize {
-                let elem: &QueueEntry<T> = &*queuedat.offset(i);
-                elem.wraps.store(INITIAL_QUEUE_FLAG, Relaxed);
-
-                let refd: &RefCnt = &*refdat.offset(i);
-                refd.refcnt.store(0, Relaxed);
+                // Only the tag is written, values are moved in by the writers
+                let elem = queuedat.offset(i);
+                ptr::write(
+                    ptr::addr_of_mut!((*elem).wraps),
+                    AtomicUsize::new(INITIAL_QUEUE_FLAG),
+                );
+
+                ptr::write(
+                    refdat.offset(i),
+                    RefCnt {
+                        refcnt: AtomicUsize::new(0),
+                        superseded: AtomicUsize::new(INITIAL_QUEUE_FLAG),
+                        _buffer: [0; 64],
+                    },
+                );
             }
         }
 
-        let (cursor, reader) = ReadCursor::new(capacity);
+        // Writers can only take values off a stream which is always committed with a CAS
+        let shared = options.replacing || matches!(options.drop_policy, DropPolicy::Discard(_));
         let needs_notify = wait.needs_notify();
+        let skip_idle_notify = !wait.needs_every_notify();
+        let QueueOptions {
+            conflator,
+            dedup,
+            replacing,
+            drop_policy,
+            dead_letters,
+            delayed,
+            expiring,
+            timestamped,
+            weigher,
+            #[cfg(feature = "metrics")]
+            metrics,
+            reclaim,
+            clock,
+        } = options;
+        let (weight_budget, weigher) = match weigher {
+            Some((budget, weigher)) => (budget, Some(weigher)),
+            None => (0, None),
+        };
         let queue = MultiQueue {
-            d1: unsafe { mem::MaybeUninit::uninit().assume_init() },
+            d1: [0; 64],
 
             head: CountedIndex::new(capacity),
             tail_cache: AtomicUsize::new(0),
             writers: AtomicUsize::new(1),
-            d2: unsafe { mem::MaybeUninit::uninit().assume_init() },
+            starving: AtomicUsize::new(0),
+            waiting: AtomicUsize::new(0),
+            d2: [0; 64],
 
-            tail: cursor,
             data: queuedat,
             refs: refdat,
             capacity: capacity as isize,
             waiter: wait,
             needs_notify,
+            skip_idle_notify,
+            bells: parking_lot::Mutex::new(Vec::new()),
+            num_bells: AtomicUsize::new(0),
+            claimed_views: AtomicBool::new(false),
+            conflating: conflator.is_some(),
+            conflator,
+            dedup,
+            replacing,
+            drop_policy,
+            dead_letters,
+            delay_base: if delayed { Some(clock.now()) } else { None },
+            expiry_base: if expiring { Some(clock.now()) } else { None },
+            stamp_base: if timestamped { Some(clock.now()) } else { None },
+            times: if delayed || expiring || timestamped {
+                Some(
+                    (0..capacity)
+                        .map(|_| SlotTimes {
+                            ready_at: AtomicStamp::new(0),
+                            expires_at: AtomicStamp::new(0),
+                            sent_at: AtomicStamp::new(0),
+                        })
+                        .collect(),
+                )
+            } else {
+                None
+            },
+            created: clock_start(&*clock),
+            clock,
+            weigher,
+            weight_budget,
+            weight: AtomicUsize::new(0),
+            #[cfg(feature = "metrics")]
+            metrics,
+            #[cfg(feature = "metrics")]
+            full_edge: AtomicBool::new(false),
+            #[cfg(feature = "metrics")]
+            empty_edge: AtomicBool::new(false),
+            #[cfg(feature = "stats")]
+            counters: Counters::new(),
+            #[cfg(feature = "tracing")]
+            trace: QueueTrace::new(capacity as usize),
+            #[cfg(feature = "fault_injection")]
+            faults: Faults::new(),
+            #[cfg(feature = "registry")]
+            registry_id: registry::next_id(),
             mk: PhantomData,
-            d3: unsafe { mem::MaybeUninit::uninit().assume_init() },
+            d3: [0; 64],
 
-            manager: MemoryManager::new(),
+            manager: match reclaim {
+                Some(reclaim) => MemoryManager::with_backend(reclaim),
+                None => MemoryManager::new(),
+            },
 
-            d4: unsafe { mem::MaybeUninit::uninit().assume_init() },
+            d4: [0; 64],
+
+            tail: ReadCursor::new::<STREAMS>(shared),
         };
 
-        let qarc = Arc::new(queue);
+        let qarc: Arc<MultiQueue<RW, T>> = Arc::new(queue);
+        // Streams held in place can only be added once the queue is where it stays
+        let reader = qarc.tail.add_stream_at(0, capacity, 0, None, &qarc.manager);
+        #[cfg(feature = "registry")]
+        registry::register(qarc.registry_id, Arc::as_ptr(&qarc));
 
         let mwriter = InnerSend {
             queue: qarc.clone(),
             state: Cell::new(QueueState::Uni),
             token: qarc.manager.get_token(),
+            #[cfg(feature = "order_checks")]
+            sent: OrderCheck::new(),
         };
 
         let mreader = InnerRecv {
             queue: qarc.clone(),
             reader,
             token: qarc.manager.get_token(),
+            filter: None,
+            name: None,
             alive: true,
         };
 
         (mwriter, mreader)
     }
 
-    pub fn try_send_multi(&self, val: T) -> Result<(), TrySendError<T>> {
-        let mut transaction = self.head.load_transaction(Relaxed);
-
-        unsafe {
+    /// Writes the value with a compare and swap on the head, which is safe with any
+    /// number of writers. A writer which loses the swap backs off for longer each time
+    /// so the others can get their values in, and gives up with Full once it's lost
+    /// max_attempts times. Once it's lost STARVING_AFTER times in a row, it stops
+    /// backing off and the other writers hold back before trying until it's through.
+    /// seed staggers how long writers back off for, so they don't retry in lockstep
+    pub fn try_send_multi<'a>(
+        &self,
+        mut val: Payload<'a, T>,
+        ready_at: u64,
+        expires_at: u64,
+        max_attempts: usize,
+        seed: usize,
+    ) -> Result<usize, TrySendError<Payload<'a, T>>> {
+        if self.starving.load(RELAXED) != 0 {
+            spin(STARVING_HOLD_SPINS);
+        }
+        let mut transaction = self.head.load_transaction(RELAXED);
+        let mut attempts = 0;
+        let mut starving = false;
+        let mut replaced = None;
+        let rval = unsafe {
             loop {
                 let (chead, wrap_valid_tag) = transaction.get();
-                let tail_cache = self.tail_cache.load(Relaxed);
-                if transaction.matches_previous(tail_cache) {
+                let tail_cache = self.tail_cache.load(RELAXED);
+                if transaction.wrapped_past(tail_cache) {
                     let new_tail = self.reload_tail_multi(tail_cache, wrap_valid_tag);
-                    if transaction.matches_previous(new_tail) {
-                        return Err(TrySendError::Full(val));
+                    if transaction.wrapped_past(new_tail) {
+                        break Err(TrySendError::Full(val));
                     }
                 }
-                let write_cell = &mut *self.data.offset(chead);
+                let write_cell = &*self.data.offset(chead);
                 let ref_cell = &*self.refs.offset(chead);
-                if !RW::check_ref(&ref_cell.refcnt) {
-                    return Err(TrySendError::Full(val));
+                if !self.slot_free(ref_cell) {
+                    break Err(TrySendError::Full(val));
                 }
-                fence(Acquire);
-
-                match transaction.commit(1, Relaxed) {
-                    Some(new_transaction) => transaction = new_transaction,
+                fence(ACQUIRE);
+                // There's room, so this is the time to clone a value sent by reference
+                // and read the clock. Nothing between the commit and storing the tag
+                // may panic, since that would leave the slot empty for good
+                val = val.into_owned();
+                let stamp = self.stamp();
+
+                match transaction.commit(1, RELAXED) {
+                    Some(new_transaction) => {
+                        attempts += 1;
+                        if attempts >= max_attempts {
+                            break Err(TrySendError::Full(val));
+                        }
+                        if attempts == STARVING_AFTER {
+                            starving = true;
+                            self.starving.fetch_add(1, RELAXED);
+                        }
+                        if starving {
+                            transaction = new_transaction;
+                        } else {
+                            // Seeds come from token addresses, whose low bits are all the same
+                            let shift = attempts.min(MAX_BACKOFF_SHIFT);
+                            spin((1 << shift) + (seed >> 4) % (1 << shift));
+                            transaction = new_transaction.reload();
+                        }
+                    }
                     None => {
-                        let current_tag = write_cell.wraps.load(Relaxed);
+                        let current_tag = write_cell.wraps.load(RELAXED);
 
                         // This will delay the dropping of the exsisting item until
                         // after the write is done. This will have a marginal effect on
                         // throughput in most cases but will really help latency.
                         // Hopefully the compiler is smart enough to get rid of this
                         // when there's no drop
-                        let _possible_drop = if RW::do_drop() && !is_tagged(current_tag) {
-                            Some(ptr::read(&write_cell.val))
-                        } else {
-                            None
-                        };
-                        ptr::write(&mut write_cell.val, val);
-                        write_cell.wraps.store(wrap_valid_tag, Release);
-                        return Ok(());
+                        if RW::do_drop() && !is_tagged(current_tag) {
+                            replaced = Some(ptr::read(write_cell.val.get()));
+                        }
+                        ptr::write(write_cell.val.get(), val.into_val());
+                        if let Some(times) = self.times(chead as usize) {
+                            times.store(ready_at, expires_at, stamp);
+                        }
+                        write_cell.wraps.store(wrap_valid_tag, RELEASE);
+                        break Ok(wrap_valid_tag);
                     }
                 }
             }
+        };
+        if starving {
+            self.starving.fetch_sub(1, RELAXED);
         }
+        // Dropped once this writer is done with the queue, in case the drop panics
+        drop(replaced);
+        rval
     }
 
-    pub fn try_send_single(&self, val: T) -> Result<(), TrySendError<T>> {
-        let transaction = self.head.load_transaction(Relaxed);
+    pub fn try_send_single<'a>(
+        &self,
+        val: Payload<'a, T>,
+        ready_at: u64,
+        expires_at: u64,
+    ) -> Result<usize, TrySendError<Payload<'a, T>>> {
+        let transaction = self.head.load_transaction(RELAXED);
         let (chead, wrap_valid_tag) = transaction.get();
         unsafe {
-            let tail_cache = self.tail_cache.load(Relaxed);
-            if transaction.matches_previous(tail_cache) {
-                let new_tail = self.reload_tail_single(wrap_valid_tag);
-                if transaction.matches_previous(new_tail) {
+            let tail_cache = self.tail_cache.load(RELAXED);
+            if transaction.wrapped_past(tail_cache) {
+                let new_tail = self.reload_tail_single(tail_cache, wrap_valid_tag);
+                if transaction.wrapped_past(new_tail) {
                     return Err(TrySendError::Full(val));
                 }
             }
-            let write_cell = &mut *self.data.offset(chead);
+            let write_cell = &*self.data.offset(chead);
             let ref_cell = &*self.refs.offset(chead);
-            if !RW::check_ref(&ref_cell.refcnt) {
+            if !self.slot_free(ref_cell) {
                 return Err(TrySendError::Full(val));
             }
-            fence(Acquire);
-            transaction.commit_direct(1, Relaxed);
-            let current_tag = write_cell.wraps.load(Relaxed);
+            fence(ACQUIRE);
+            let val = val.into_val();
+            let stamp = self.stamp();
+            transaction.commit_direct(1, RELAXED);
+            let current_tag = write_cell.wraps.load(RELAXED);
             let _possible_drop = if RW::do_drop() && !is_tagged(current_tag) {
-                Some(ptr::read(&write_cell.val))
+                Some(ptr::read(write_cell.val.get()))
             } else {
                 None
             };
-            ptr::write(&mut write_cell.val, val);
-            write_cell.wraps.store(wrap_valid_tag, Release);
-            Ok(())
+            ptr::write(write_cell.val.get(), val);
+            if let Some(times) = self.times(chead as usize) {
+                times.store(ready_at, expires_at, stamp);
+            }
+            write_cell.wraps.store(wrap_valid_tag, RELEASE);
+            Ok(wrap_valid_tag)
+        }
+    }
+
+    /// Writes as many of vals as there's room for, claiming all of their slots with
+    /// a single move of the head, and returns the sequence number of the first along
+    /// with how many it wrote. Only valid for a sole writer, since nothing else may
+    /// then write to the slots it claims
+    pub fn try_send_slice(&self, vals: &[T]) -> (usize, usize)
+    where
+        T: Copy,
+    {
+        let capacity = self.capacity as usize;
+        let transaction = self.head.load_transaction(RELAXED);
+        let (chead, start) = transaction.get();
+        let room = |tail: usize| match past(start, tail) {
+            // A tail ahead of the head is never a wrap behind it
+            (_, true) => capacity,
+            (used, false) => capacity.saturating_sub(used),
+        };
+        unsafe {
+            let tail_cache = self.tail_cache.load(RELAXED);
+            let mut count = room(tail_cache).min(vals.len());
+            if count < vals.len() {
+                count = room(self.reload_tail_single(tail_cache, start)).min(vals.len());
+            }
+            // The run up to the end of the ring, then the one wrapping around to its start
+            let at = chead as usize;
+            let cells = (at..capacity).chain(0..at);
+            let claimable = cells
+                .clone()
+                .take(count)
+                .take_while(|idx| self.slot_free(&*self.refs.add(*idx)))
+                .count();
+            if claimable == 0 {
+                return (start, 0);
+            }
+            fence(ACQUIRE);
+            let stamp = self.stamp();
+            transaction.commit_direct(claimable as Index, RELAXED);
+            for (i, (idx, val)) in cells.zip(vals).take(claimable).enumerate() {
+                let write_cell = &*self.data.add(idx);
+                // Copy values have nothing to drop, so they're just written over
+                ptr::write(write_cell.val.get(), *val);
+                if let Some(times) = self.times(idx) {
+                    times.store(0, 0, stamp);
+                }
+                write_cell
+                    .wraps
+                    .store(rm_tag(start.wrapping_add(i)), RELEASE);
+            }
+            // Only once every claimed slot is written, since these may call into user code
+            for i in 0..claimable {
+                self.note_send(rm_tag(start.wrapping_add(i)));
+            }
+            (start, claimable)
+        }
+    }
+
+    /// Claims slots for every value in vals with a single compare and swap on the head,
+    /// then writes them, so they either all go in back to back or none of them do.
+    /// The first slot's tag is only stored once all the others have been, and since
+    /// streams read in order, none of the values can be seen until all are written.
+    /// Safe with any number of writers. Returns the sequence number of the first
+    pub fn try_send_all(&self, vals: Vec<T>) -> Result<usize, Vec<T>> {
+        let capacity = self.capacity as usize;
+        let count = vals.len();
+        debug_assert!(count > 0);
+        if count > capacity {
+            return Err(vals);
+        }
+        let mut transaction = self.head.load_transaction(RELAXED);
+        // The values written over are only dropped once the run is published, in case
+        // a drop panics, so room for them is made before the head is moved
+        let mut replaced = Vec::with_capacity(if RW::do_drop() && mem::needs_drop::<T>() {
+            count
+        } else {
+            0
+        });
+        unsafe {
+            loop {
+                let (chead, start) = transaction.get();
+                // Only the last slot has to be checked against the tail,
+                // since the ones before it are further from wrapping past it
+                let tail_cache = self.tail_cache.load(RELAXED);
+                if transaction.wrapped_past_by(count - 1, tail_cache) {
+                    let new_tail = self.reload_tail_multi(tail_cache, start);
+                    if transaction.wrapped_past_by(count - 1, new_tail) {
+                        return Err(vals);
+                    }
+                }
+                let at = chead as usize;
+                let cells = (at..capacity).chain(0..at).take(count);
+                if !cells
+                    .clone()
+                    .all(|idx| self.slot_free(&*self.refs.add(idx)))
+                {
+                    return Err(vals);
+                }
+                fence(ACQUIRE);
+                let stamp = self.stamp();
+                match transaction.commit(count as Index, RELAXED) {
+                    Some(new_transaction) => transaction = new_transaction,
+                    None => {
+                        let first = &*self.data.add(at);
+                        for (i, (idx, val)) in cells.zip(vals).enumerate() {
+                            let write_cell = &*self.data.add(idx);
+                            let current_tag = write_cell.wraps.load(RELAXED);
+                            if replaced.capacity() != 0 && !is_tagged(current_tag) {
+                                replaced.push(ptr::read(write_cell.val.get()));
+                            }
+                            ptr::write(write_cell.val.get(), val);
+                            if let Some(times) = self.times(idx) {
+                                times.store(0, 0, stamp);
+                            }
+                            if i != 0 {
+                                write_cell
+                                    .wraps
+                                    .store(rm_tag(start.wrapping_add(i)), RELEASE);
+                            }
+                        }
+                        // Publishes the whole run, since nothing reads past the first
+                        // slot before it's valid. The release keeps the rest before it
+                        first.wraps.store(rm_tag(start), RELEASE);
+                        return Ok(start);
+                    }
+                }
+            }
         }
     }
 
-    pub fn try_recv(&self, reader: &Reader) -> Result<T, (*const AtomicUsize, TryRecvError)> {
-        let mut ctail_attempt = reader.load_attempt(Relaxed);
+    /// Receives a value along with the wrap-counted index it was written at
+    #[inline(always)]
+    pub fn try_recv_indexed(
+        &self,
+        reader: &Reader,
+    ) -> Result<(usize, u64, T), (*const AtomicUsize, TryRecvError)> {
+        self.try_recv_where(reader, |_| true)
+    }
+
+    /// Receives the next value which passes keep, moving the stream past the rest.
+    /// Values are looked at in place, so only broadcast streams may filter
+    /// since other consumers could be moving values out from under us otherwise
+    pub fn try_recv_where<P: Fn(&T) -> bool>(
+        &self,
+        reader: &Reader,
+        keep: P,
+    ) -> Result<(usize, u64, T), (*const AtomicUsize, TryRecvError)> {
+        let mut ctail_attempt = reader.load_attempt(RELAXED);
         let is_single = reader.is_single();
         unsafe {
             loop {
                 let (ctail, wrap_valid_tag) = ctail_attempt.get();
-                let read_cell = &mut *self.data.offset(ctail);
+                let read_cell = &*self.data.offset(ctail);
 
                 // For any curious readers, this gnarly if block catchs a race between
                 // advancing the write index and unsubscribing from the queue. in short,
@@ -367,138 +1124,2131 @@ impl<RW: QueueRW<T>, T> MultiQueue<RW, T> {
                 // we had actually seen a race. Doing it this way removes fences on the fast path
                 let seen_tag = read_cell.wraps.load(DepOrd);
                 if rm_tag(seen_tag) != wrap_valid_tag {
-                    if self.writers.load(Relaxed) == 0 {
-                        fence(Acquire);
-                        if rm_tag(read_cell.wraps.load(Acquire)) != wrap_valid_tag {
+                    if self.writers.load(RELAXED) == 0 {
+                        fence(ACQUIRE);
+                        if rm_tag(read_cell.wraps.load(ACQUIRE)) != wrap_valid_tag {
                             return Err((ptr::null(), TryRecvError::Disconnected));
                         }
                     }
                     return Err((&read_cell.wraps, TryRecvError::Empty));
                 }
                 let ref_cell = &*self.refs.offset(ctail);
+                if !self.is_ready(ctail as usize) {
+                    return Err((&read_cell.wraps, TryRecvError::Empty));
+                }
+                let replaced = self.is_superseded(ref_cell, wrap_valid_tag);
+                let expired = !replaced && self.is_expired(ctail as usize);
+                let superseded = replaced || expired;
+                if superseded && RW::do_drop() {
+                    // The writers drop replaced and expired values when overwriting them,
+                    // so there's no need to even look at this one
+                    ctail_attempt = match ctail_attempt.commit_attempt(1, RELAXED) {
+                        Some(new_attempt) => new_attempt,
+                        None => reader.load_attempt(RELAXED),
+                    };
+                    continue;
+                }
                 if !is_single {
                     RW::inc_ref(&ref_cell.refcnt);
-                    if reader.load_count(Relaxed) != wrap_valid_tag {
+                    if reader.load_count(RELAXED) != wrap_valid_tag {
                         RW::dec_ref(&ref_cell.refcnt);
                         ctail_attempt = ctail_attempt.reload();
                         continue;
                     }
                 }
-                let rval = dependently_mut(seen_tag, &mut read_cell.val, |rc| RW::get_val(rc));
-                fence(Release);
+                if !superseded && !keep(&*read_cell.val_after(seen_tag)) {
+                    fence(RELEASE);
+                    if !is_single {
+                        RW::dec_ref(&ref_cell.refcnt);
+                    }
+                    ctail_attempt = match ctail_attempt.commit_attempt(1, RELAXED) {
+                        Some(new_attempt) => new_attempt,
+                        None => reader.load_attempt(RELAXED),
+                    };
+                    continue;
+                }
+                let rval = RW::get_val(read_cell.val_after(seen_tag));
+                // Like the value, this has to be read before writers can reuse the slot
+                let sent_at = self.sent_at(ctail as usize);
+                fence(RELEASE);
                 if !is_single {
                     RW::dec_ref(&ref_cell.refcnt);
                 }
-                match ctail_attempt.commit_attempt(1, Relaxed) {
+                match ctail_attempt.commit_attempt(1, RELAXED) {
                     Some(new_attempt) => {
                         ctail_attempt = new_attempt;
                         RW::forget_val(rval);
                     }
-                    None => return Ok(rval),
+                    None if superseded => {
+                        let rval = rval.assume_init();
+                        self.release_weight(&rval);
+                        self.discard(rval, expired);
+                        ctail_attempt = reader.load_attempt(RELAXED);
+                    }
+                    None => {
+                        let rval = rval.assume_init();
+                        self.release_weight(&rval);
+                        #[cfg(feature = "order_checks")]
+                        reader.check_order(wrap_valid_tag);
+                        return Ok((wrap_valid_tag, sent_at, rval));
+                    }
                 }
             }
         }
     }
 
-    pub fn try_recv_view<R, F: FnOnce(&T) -> R>(
+    /// Receives up to max values, claiming all of them with a single move of the stream.
+    /// Like try_recv_where, values are read out before the claim and forgotten if it
+    /// fails, so this is only valid for mpmc queues
+    pub fn try_recv_batch(
         &self,
-        op: F,
         reader: &Reader,
-    ) -> Result<R, (F, *const AtomicUsize, TryRecvError)> {
-        let ctail_attempt = reader.load_attempt(Relaxed);
+        max: usize,
+    ) -> Result<Vec<T>, (*const AtomicUsize, TryRecvError)> {
+        let mask = self.capacity as usize - 1;
+        if max == 0 {
+            return Ok(Vec::new());
+        }
+        let mut batch = Vec::with_capacity(max.min(self.capacity as usize));
+        let mut ctail_attempt = reader.load_attempt(RELAXED);
         unsafe {
-            let (ctail, wrap_valid_tag) = ctail_attempt.get();
-            let read_cell = &mut *self.data.offset(ctail);
-            let seen_tag = rm_tag(read_cell.wraps.load(DepOrd));
-            if seen_tag != wrap_valid_tag {
-                if self.writers.load(Relaxed) == 0 {
-                    fence(Acquire);
-                    if rm_tag(read_cell.wraps.load(Acquire)) != wrap_valid_tag {
-                        return Err((op, ptr::null(), TryRecvError::Disconnected));
+            loop {
+                let (_, start) = ctail_attempt.get();
+                while batch.len() < max {
+                    let seq = rm_tag(start.wrapping_add(batch.len()));
+                    let read_cell = &*self.data.add(seq & mask);
+                    let seen_tag = read_cell.wraps.load(DepOrd);
+                    let ref_cell = &*self.refs.add(seq & mask);
+                    if rm_tag(seen_tag) != seq || !self.is_ready(seq & mask) {
+                        break;
+                    }
+                    let replaced = self.is_superseded(ref_cell, seq);
+                    let expired = !replaced && self.is_expired(seq & mask);
+                    let val = RW::get_val(read_cell.val_after(seen_tag));
+                    batch.push((replaced, expired, val));
+                }
+                if batch.is_empty() {
+                    // Same race with unsubscribing writers as in try_recv_where
+                    let read_cell = &*self.data.add(start & mask);
+                    if rm_tag(read_cell.wraps.load(RELAXED)) != start
+                        && self.writers.load(RELAXED) == 0
+                    {
+                        fence(ACQUIRE);
+                        if rm_tag(read_cell.wraps.load(ACQUIRE)) != start {
+                            return Err((ptr::null(), TryRecvError::Disconnected));
+                        }
+                    }
+                    return Err((&read_cell.wraps, TryRecvError::Empty));
+                }
+                fence(RELEASE);
+                match ctail_attempt.commit_attempt(batch.len() as Index, RELAXED) {
+                    Some(new_attempt) => {
+                        for (_, _, val) in batch.drain(..) {
+                            RW::forget_val(val);
+                        }
+                        ctail_attempt = new_attempt;
+                    }
+                    None => {
+                        #[cfg(feature = "order_checks")]
+                        let claimed = batch.len();
+                        // Replaced and expired values are dropped here
+                        let rval: Vec<T> = batch
+                            .into_iter()
+                            .filter_map(|(replaced, expired, val)| {
+                                let val = val.assume_init();
+                                self.release_weight(&val);
+                                if replaced || expired {
+                                    self.discard(val, expired);
+                                    None
+                                } else {
+                                    Some(val)
+                                }
+                            })
+                            .collect();
+                        if !rval.is_empty() {
+                            #[cfg(feature = "order_checks")]
+                            {
+                                reader.check_order(start);
+                                if claimed > 1 {
+                                    reader.check_order(rm_tag(start.wrapping_add(claimed - 1)));
+                                }
+                            }
+                            return Ok(rval);
+                        }
+                        batch = Vec::with_capacity(max.min(self.capacity as usize));
+                        ctail_attempt = reader.load_attempt(RELAXED);
                     }
                 }
-                return Err((op, &read_cell.wraps, TryRecvError::Empty));
             }
-            dependently_mut(seen_tag, &mut read_cell.val, |rv_ref| {
-                let rval = op(rv_ref);
-                RW::drop_in_place(rv_ref);
-                ctail_attempt.commit_direct(1, Release);
-                Ok(rval)
-            })
         }
     }
 
-    fn reload_tail_multi(&self, tail_cache: usize, count: usize) -> usize {
-        if let Some(max_diff_from_head) = self.tail.get_max_diff(count) {
-            let current_tail = CountedIndex::get_previous(count, max_diff_from_head);
-            if tail_cache == current_tail {
-                return current_tail;
+    /// Copies up to buf.len() values straight out of the ring into buf, then moves the
+    /// stream past all of them at once. Only valid for streams with a single consumer
+    /// which writers don't move, since nothing else can then overwrite the values
+    pub fn try_recv_slice(
+        &self,
+        reader: &Reader,
+        buf: &mut [T],
+    ) -> Result<usize, (*const AtomicUsize, TryRecvError)>
+    where
+        T: Copy,
+    {
+        debug_assert!(reader.is_single());
+        let capacity = self.capacity as usize;
+        let mask = capacity - 1;
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        unsafe {
+            loop {
+                let ctail_attempt = reader.load_attempt(RELAXED);
+                let (_, start) = ctail_attempt.get();
+                let at = start & mask;
+                let mut claimed = 0;
+                let mut copied = 0;
+                let mut weight = 0;
+                // The run up to the end of the ring, then the one wrapping around to its start
+                for idx in (at..capacity).chain(0..at) {
+                    let seq = rm_tag(start.wrapping_add(claimed));
+                    let read_cell = &*self.data.add(idx);
+                    let seen_tag = read_cell.wraps.load(DepOrd);
+                    let ref_cell = &*self.refs.add(idx);
+                    if rm_tag(seen_tag) != seq || !self.is_ready(idx) {
+                        break;
+                    }
+                    let val = *read_cell.val_after(seen_tag);
+                    if let Some(ref weigher) = self.weigher {
+                        weight += weigher(&val);
+                    }
+                    claimed += 1;
+                    if self.is_superseded(ref_cell, seq) {
+                        continue;
+                    }
+                    if self.is_expired(idx) {
+                        self.discard(val, true);
+                        continue;
+                    }
+                    buf[copied] = val;
+                    copied += 1;
+                    if copied == buf.len() {
+                        break;
+                    }
+                }
+                if claimed == 0 {
+                    // Same race with unsubscribing writers as in try_recv_where
+                    let read_cell = &*self.data.add(at);
+                    if rm_tag(read_cell.wraps.load(RELAXED)) != start
+                        && self.writers.load(RELAXED) == 0
+                    {
+                        fence(ACQUIRE);
+                        if rm_tag(read_cell.wraps.load(ACQUIRE)) != start {
+                            return Err((ptr::null(), TryRecvError::Disconnected));
+                        }
+                    }
+                    return Err((&read_cell.wraps, TryRecvError::Empty));
+                }
+                if weight != 0 {
+                    self.weight.fetch_sub(weight, RELAXED);
+                }
+                ctail_attempt.commit_direct(claimed as Index, RELEASE);
+                if copied != 0 {
+                    #[cfg(feature = "order_checks")]
+                    {
+                        reader.check_order(start);
+                        if claimed > 1 {
+                            reader.check_order(rm_tag(start.wrapping_add(claimed - 1)));
+                        }
+                    }
+                    return Ok(copied);
+                }
             }
-            match self
-                .tail_cache
-                .compare_exchange(tail_cache, current_tail, AcqRel, Relaxed)
-            {
-                Ok(_) => current_tail,
-                Err(val) => val,
+        }
+    }
+
+    pub fn try_recv_view<R, F: FnOnce(&T) -> R, P: Fn(&T) -> bool>(
+        &self,
+        op: F,
+        reader: &Reader,
+        keep: P,
+    ) -> Result<R, (F, *const AtomicUsize, TryRecvError)> {
+        let mut ctail_attempt = reader.load_attempt(RELAXED);
+        unsafe {
+            loop {
+                let (ctail, wrap_valid_tag) = ctail_attempt.get();
+                let read_cell = &*self.data.offset(ctail);
+                let seen_tag = rm_tag(read_cell.wraps.load(DepOrd));
+                if seen_tag != wrap_valid_tag {
+                    if self.writers.load(RELAXED) == 0 {
+                        fence(ACQUIRE);
+                        if rm_tag(read_cell.wraps.load(ACQUIRE)) != wrap_valid_tag {
+                            return Err((op, ptr::null(), TryRecvError::Disconnected));
+                        }
+                    }
+                    return Err((op, &read_cell.wraps, TryRecvError::Empty));
+                }
+                let ref_cell = &*self.refs.offset(ctail);
+                if !self.is_ready(ctail as usize) {
+                    return Err((op, &read_cell.wraps, TryRecvError::Empty));
+                }
+                let rv_ptr = read_cell.val_after(seen_tag);
+                let replaced = self.is_superseded(ref_cell, wrap_valid_tag);
+                let expired = !replaced && self.is_expired(ctail as usize);
+                if !replaced && !expired && keep(&*rv_ptr) {
+                    // The value is taken even if op panics, so it's still dropped
+                    // exactly once and the stream doesn't get stuck on it
+                    let _taken = OnDrop::new(|| {
+                        self.release_weight(&*rv_ptr);
+                        RW::drop_in_place(rv_ptr);
+                        ctail_attempt.commit_direct(1, RELEASE);
+                    });
+                    #[cfg(feature = "order_checks")]
+                    reader.check_order(wrap_valid_tag);
+                    return Ok(op(&*rv_ptr));
+                }
+                self.release_weight(&*rv_ptr);
+                self.discard_in_place(rv_ptr, expired);
+                ctail_attempt.commit_direct(1, RELEASE);
+                ctail_attempt = reader.load_attempt(RELAXED);
             }
-        } else {
-            self.tail_cache.load(Acquire)
         }
     }
 
-    fn reload_tail_single(&self, count: usize) -> usize {
-        let max_diff_from_head = self.tail.get_max_diff(count).expect(
-            "The write head got ran over by consumers in single writer mode. This \
-             process is borked!",
+    /// Identical to try_recv_view, except the value is handed back in a RecvGuard
+    /// and only taken once the guard is dropped
+    pub fn try_recv_guard<'a, P: Fn(&T) -> bool>(
+        self: &'a Arc<Self>,
+        reader: &'a Reader,
+        keep: P,
+    ) -> Result<RecvGuard<'a, T>, (*const AtomicUsize, TryRecvError)> {
+        let mut ctail_attempt = reader.load_attempt(RELAXED);
+        unsafe {
+            loop {
+                let (ctail, wrap_valid_tag) = ctail_attempt.get();
+                let read_cell = &*self.data.offset(ctail);
+                let seen_tag = rm_tag(read_cell.wraps.load(DepOrd));
+                if seen_tag != wrap_valid_tag {
+                    if self.writers.load(RELAXED) == 0 {
+                        fence(ACQUIRE);
+                        if rm_tag(read_cell.wraps.load(ACQUIRE)) != wrap_valid_tag {
+                            return Err((ptr::null(), TryRecvError::Disconnected));
+                        }
+                    }
+                    return Err((&read_cell.wraps, TryRecvError::Empty));
+                }
+                let ref_cell = &*self.refs.offset(ctail);
+                if !self.is_ready(ctail as usize) {
+                    return Err((&read_cell.wraps, TryRecvError::Empty));
+                }
+                let rv_ptr = read_cell.val_after(seen_tag);
+                let replaced = self.is_superseded(ref_cell, wrap_valid_tag);
+                let expired = !replaced && self.is_expired(ctail as usize);
+                if !replaced && !expired && keep(&*rv_ptr) {
+                    #[cfg(feature = "order_checks")]
+                    reader.check_order(wrap_valid_tag);
+                    return Ok(RecvGuard {
+                        queue: self,
+                        val: rv_ptr,
+                        attempt: Some(ctail_attempt),
+                    });
+                }
+                self.release_weight(&*rv_ptr);
+                self.discard_in_place(rv_ptr, expired);
+                ctail_attempt.commit_direct(1, RELEASE);
+                ctail_attempt = reader.load_attempt(RELAXED);
+            }
+        }
+    }
+
+    /// Views the next value in place on a stream which may have several consumers.
+    /// The value is claimed before op runs and the refcount keeps writers off of it
+    /// until op is done, so this is only valid for broadcast queues
+    pub fn try_recv_view_shared<R, F: FnOnce(&T) -> R, P: Fn(&T) -> bool>(
+        &self,
+        op: F,
+        reader: &Reader,
+        keep: P,
+    ) -> Result<R, (F, *const AtomicUsize, TryRecvError)> {
+        let mut ctail_attempt = reader.load_attempt(RELAXED);
+        let is_single = reader.is_single();
+        unsafe {
+            loop {
+                let (ctail, wrap_valid_tag) = ctail_attempt.get();
+                let read_cell = &*self.data.offset(ctail);
+                let seen_tag = rm_tag(read_cell.wraps.load(DepOrd));
+                if seen_tag != wrap_valid_tag {
+                    if self.writers.load(RELAXED) == 0 {
+                        fence(ACQUIRE);
+                        if rm_tag(read_cell.wraps.load(ACQUIRE)) != wrap_valid_tag {
+                            return Err((op, ptr::null(), TryRecvError::Disconnected));
+                        }
+                    }
+                    return Err((op, &read_cell.wraps, TryRecvError::Empty));
+                }
+                let ref_cell = &*self.refs.offset(ctail);
+                if !self.is_ready(ctail as usize) {
+                    return Err((op, &read_cell.wraps, TryRecvError::Empty));
+                }
+                if !is_single {
+                    RW::inc_ref(&ref_cell.refcnt);
+                    if reader.load_count(RELAXED) != wrap_valid_tag {
+                        RW::dec_ref(&ref_cell.refcnt);
+                        ctail_attempt = ctail_attempt.reload();
+                        continue;
+                    }
+                }
+                let rv_ptr = read_cell.val_after(seen_tag);
+                let wanted = !self.is_superseded(ref_cell, wrap_valid_tag)
+                    && !self.is_expired(ctail as usize)
+                    && keep(&*rv_ptr);
+                if wanted && is_single {
+                    let _taken = OnDrop::new(|| ctail_attempt.commit_direct(1, RELEASE));
+                    #[cfg(feature = "order_checks")]
+                    reader.check_order(wrap_valid_tag);
+                    return Ok(op(&*rv_ptr));
+                }
+                match ctail_attempt.commit_attempt(1, RELEASE) {
+                    Some(new_attempt) => {
+                        RW::dec_ref(&ref_cell.refcnt);
+                        ctail_attempt = new_attempt;
+                    }
+                    None if wanted => {
+                        // Other consumers can't get to this value now that the stream
+                        // is past it, and the refcount keeps writers from overwriting it.
+                        // The refcount has to be given back even if op panics,
+                        // or writers could never get past the value again
+                        let _viewed = OnDrop::new(|| {
+                            fence(RELEASE);
+                            RW::dec_ref(&ref_cell.refcnt);
+                        });
+                        #[cfg(feature = "order_checks")]
+                        reader.check_order(wrap_valid_tag);
+                        return Ok(op(&*rv_ptr));
+                    }
+                    None => {
+                        if !is_single {
+                            RW::dec_ref(&ref_cell.refcnt);
+                        }
+                        ctail_attempt = reader.load_attempt(RELAXED);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Clones everything between the stream and the head of the queue which passes keep,
+    /// without moving the stream. Only valid for broadcast queues, since the
+    /// refcount is what keeps writers off of the slots being cloned
+    pub fn snapshot<P: Fn(&T) -> bool>(&self, reader: &Reader, keep: P) -> Vec<T>
+    where
+        T: Clone,
+    {
+        self.walk_held(
+            |_| Some(reader.load_count(RELAXED)),
+            // Writers can only get to a slot once the stream is past it
+            |seq| past(seq, reader.load_count(RELAXED)).1,
+            |_, val| if keep(val) { Some(val.clone()) } else { None },
+        )
+    }
+
+    /// Walks the values from the sequence number start picks up to the head as it
+    /// was beforehand, collecting what visit returns for each one still held.
+    /// A slot's refcount is only taken once its tag says it holds seq, so slots
+    /// which are unwritten or being written over are never pinned, and it keeps
+    /// writers off of the slot while visit runs. Once moved says writers may have
+    /// gotten to seq, what was collected so far has been received, so the walk
+    /// starts over from start to keep the values a run
+    fn walk_held<U, S, M, V>(&self, start: S, moved: M, mut visit: V) -> Vec<U>
+    where
+        S: Fn(usize) -> Option<usize>,
+        M: Fn(usize) -> bool,
+        V: FnMut(usize, &T) -> Option<U>,
+    {
+        let mask = self.capacity as usize - 1;
+        let mut rval = Vec::new();
+        unsafe {
+            'restart: loop {
+                let head = self.head.load_count(ACQUIRE);
+                let mut seq = match start(head) {
+                    Some(seq) => seq,
+                    None => return rval,
+                };
+                loop {
+                    let (left, beyond) = past(head, seq);
+                    if left == 0 || beyond {
+                        return rval;
+                    }
+                    let read_cell = &*self.data.add(seq & mask);
+                    let ref_cell = &*self.refs.add(seq & mask);
+                    if rm_tag(read_cell.wraps.load(ACQUIRE)) != seq || !self.is_ready(seq & mask) {
+                        if moved(seq) {
+                            rval.clear();
+                            continue 'restart;
+                        }
+                        return rval;
+                    }
+                    RW::inc_ref(&ref_cell.refcnt);
+                    if moved(seq) {
+                        RW::dec_ref(&ref_cell.refcnt);
+                        rval.clear();
+                        continue 'restart;
+                    }
+                    if rm_tag(read_cell.wraps.load(ACQUIRE)) != seq {
+                        RW::dec_ref(&ref_cell.refcnt);
+                        continue;
+                    }
+                    if !self.is_superseded(ref_cell, seq) && !self.is_expired(seq & mask) {
+                        if let Some(val) = visit(seq, &*read_cell.val.get()) {
+                            rval.push(val);
+                        }
+                    }
+                    fence(RELEASE);
+                    RW::dec_ref(&ref_cell.refcnt);
+                    seq = rm_tag(seq.wrapping_add(1));
+                }
+            }
+        }
+    }
+
+    /// Clones everything the furthest behind stream has yet to receive along with
+    /// their sequence numbers, without being a stream or moving any. Only valid for
+    /// broadcast queues, since the refcount is what keeps writers off of the slots being cloned
+    pub fn dump(&self) -> Vec<(u64, T)>
+    where
+        T: Clone,
+    {
+        let _guard = self.manager.protect();
+        self.walk_held(
+            |head| self.tail.slowest_pos(head),
+            // Writers can only get to a slot once every stream they wait on is past it
+            |seq| self.tail.all_past(rm_tag(seq.wrapping_add(1))),
+            |seq, val| Some((seq as u64, val.clone())),
+        )
+    }
+
+    /// Returns whether nothing holds the slot, so a writer may reuse it. Mpmc consumers
+    /// only hold slots with try_recv_view_claimed, so mpmc queues only look at
+    /// the refcount once one has, and sends on the rest stay clear of it
+    #[inline(always)]
+    fn slot_free(&self, ref_cell: &RefCnt) -> bool {
+        if RW::counts_refs() {
+            RW::check_ref(&ref_cell.refcnt)
+        } else {
+            !self.claimed_views.load(RELAXED) || ref_cell.refcnt.load(RELAXED) == 0
+        }
+    }
+
+    /// Returns whether the value in the slot at idx may be read yet.
+    /// Values on queues without delays always can be
+    #[inline(always)]
+    fn is_ready(&self, idx: usize) -> bool {
+        match self.delay_base {
+            None => true,
+            Some(base) => self.ready_after(base, idx) == 0,
+        }
+    }
+
+    /// Returns how much longer the value in the slot at idx has to wait before it can be read
+    #[cold]
+    fn ready_after(&self, base: Instant, idx: usize) -> u64 {
+        // Pairs with the Release store of the slot's tag
+        fence(ACQUIRE);
+        let ready_at = self
+            .times(idx)
+            .map_or(0, |times| times.ready_at.load(RELAXED));
+        if ready_at == 0 {
+            return 0;
+        }
+        ready_at.saturating_sub(self.elapsed(base).as_nanos() as u64)
+    }
+
+    /// Takes the oldest value off the only stream so that a writer can use its slot.
+    /// Returns None if the queue has more than one stream, or a paused one, and
+    /// the error if readers got to everything first
+    fn displace_oldest(&self) -> Option<Result<T, TryRecvError>> {
+        let _guard = self.manager.protect();
+        let reader = self.tail.sole_stream()?;
+        Some(
+            self.try_recv_where(&reader, |_| true)
+                .map(|(_, _, val)| val)
+                .map_err(|(_, e)| e),
+        )
+    }
+
+    /// Carries out the drop policy once the last writer has gone
+    fn writers_gone(&self) {
+        match self.drop_policy {
+            DropPolicy::Drain => (),
+            DropPolicy::Wait(timeout) => {
+                let barrier = self.barrier();
+                let deadline = self.clock.now() + timeout;
+                let mut backoff = Backoff::new();
+                while !self.barrier_passed(barrier) {
+                    let now = self.clock.now();
+                    if now >= deadline {
+                        return;
+                    }
+                    backoff.snooze(Some(deadline - now));
+                }
+            }
+            DropPolicy::Discard(ref on_dropped) => {
+                while let Some(Ok(val)) = self.displace_oldest() {
+                    on_dropped(val);
+                }
+            }
+        }
+    }
+
+    /// Converts the deadline into the form stored in a slot, where zero means
+    /// the value can be read right away
+    fn ready_at(&self, deadline: Instant) -> u64 {
+        assert!(
+            self.delay_base.is_some(),
+            "Multiqueue error - sending with a deadline on a queue without delays"
+        );
+        let base = self.delay_base.unwrap();
+        if deadline <= self.clock.now() {
+            return 0;
+        }
+        // Deadlines at the base itself still have to be nonzero
+        (deadline.duration_since(base).as_nanos() as u64).max(1)
+    }
+
+    /// If the passed stream is waiting on a value which was sent with a deadline,
+    /// returns how long until the deadline
+    fn pending_delay(&self, reader: &Reader) -> Option<Duration> {
+        let base = self.delay_base?;
+        let seq = reader.load_count(RELAXED);
+        let mask = self.capacity as usize - 1;
+        unsafe {
+            let cell = &*self.data.add(seq & mask);
+            if rm_tag(cell.wraps.load(ACQUIRE)) != seq {
+                return None;
+            }
+            match self.ready_after(base, seq & mask) {
+                0 => None,
+                nanos => Some(Duration::from_nanos(nanos)),
+            }
+        }
+    }
+
+    /// Returns when the value in the slot at idx was sent on timestamped queues,
+    /// and zero otherwise
+    #[inline(always)]
+    fn sent_at(&self, idx: usize) -> u64 {
+        match self.stamp_base {
+            None => 0,
+            Some(_) => {
+                // Pairs with the Release store of the slot's tag
+                fence(ACQUIRE);
+                self.times(idx)
+                    .map_or(0, |times| times.sent_at.load(RELAXED))
+            }
+        }
+    }
+
+    /// Returns the time timestamps are measured from,
+    /// panicking if the queue doesn't record them
+    fn stamp_base(&self) -> Instant {
+        assert!(
+            self.stamp_base.is_some(),
+            "Multiqueue error - asking for latency on a queue without timestamps"
+        );
+        self.stamp_base.unwrap()
+    }
+
+    /// Gets rid of a value a stream moved past without receiving it,
+    /// passing it to the dead letters if it expired
+    #[inline(always)]
+    fn discard(&self, val: T, expired: bool) {
+        match self.dead_letters {
+            Some(ref dead_letters) if expired => dead_letters.send(DeadLetterReason::Expired, val),
+            _ => drop(val),
+        }
+    }
+
+    /// Identical to discard, for a value still in its slot. Values in broadcast
+    /// slots are left for the writers to drop, so they never become dead letters
+    #[inline(always)]
+    unsafe fn discard_in_place(&self, val: *mut T, expired: bool) {
+        if expired && !RW::do_drop() && self.dead_letters.is_some() {
+            self.discard(ptr::read(val), true);
+        } else {
+            RW::drop_in_place(val);
+        }
+    }
+
+    /// Converts the time to live into the form stored in a slot, where zero means
+    /// the value never expires
+    fn expires_at(&self, ttl: Duration) -> u64 {
+        assert!(
+            self.expiry_base.is_some(),
+            "Multiqueue error - sending with a time to live on a queue without expiry"
         );
-        let current_tail = CountedIndex::get_previous(count, max_diff_from_head);
-        self.tail_cache.store(current_tail, Relaxed);
-        current_tail
+        let base = self.expiry_base.unwrap();
+        ((self.elapsed(base) + ttl).as_nanos() as u64).max(1)
+    }
+
+    /// Returns the time to record as a value's send time on timestamped queues
+    #[inline(always)]
+    fn stamp(&self) -> Option<u64> {
+        self.stamp_base
+            .map(|base| self.elapsed(base).as_nanos() as u64)
+    }
+
+    /// Returns how long ago a value stamped sent_at nanoseconds after base was sent
+    fn latency(&self, base: Instant, sent_at: u64) -> Duration {
+        Duration::from_nanos((self.elapsed(base).as_nanos() as u64).saturating_sub(sent_at))
+    }
+
+    /// Takes the value's weight out of the budget, returning it if the value doesn't fit.
+    /// A value is always let in when nothing else is queued so oversized ones can't get stuck
+    #[inline(always)]
+    fn reserve_weight<'a>(
+        &self,
+        val: Payload<'a, T>,
+    ) -> Result<(Payload<'a, T>, usize), TrySendError<Payload<'a, T>>> {
+        let weigher = match self.weigher {
+            None => return Ok((val, 0)),
+            Some(ref weigher) => weigher,
+        };
+        let weight = weigher(val.get());
+        if self.reserve_weight_of(weight) {
+            Ok((val, weight))
+        } else {
+            Err(TrySendError::Full(val))
+        }
+    }
+
+    /// Takes weight out of the budget, returning whether it fit
+    #[inline(always)]
+    fn reserve_weight_of(&self, weight: usize) -> bool {
+        let mut cur = self.weight.load(RELAXED);
+        loop {
+            if cur != 0 && cur.saturating_add(weight) > self.weight_budget {
+                return false;
+            }
+            match self
+                .weight
+                .compare_exchange_weak(cur, cur + weight, RELAXED, RELAXED)
+            {
+                Ok(_) => return true,
+                Err(actual) => cur = actual,
+            }
+        }
+    }
+
+    /// Gives the value's weight back to the budget once it has been taken off the queue
+    #[inline(always)]
+    fn release_weight(&self, val: &T) {
+        if let Some(ref weigher) = self.weigher {
+            self.weight.fetch_sub(weigher(val), RELAXED);
+        }
+    }
+
+    /// Returns how much of the weight budget the queued values take up
+    pub fn outstanding_weight(&self) -> usize {
+        self.weight.load(RELAXED)
+    }
+
+    /// Runs the passed hook if the queue was created with metrics
+    #[cfg(feature = "metrics")]
+    #[inline(always)]
+    fn report<F: FnOnce(&dyn QueueMetrics)>(&self, hook: F) {
+        if let Some(ref metrics) = self.metrics {
+            hook(&**metrics);
+        }
+    }
+
+    #[inline(always)]
+    fn note_send(&self, _seq: usize) {
+        #[cfg(feature = "stats")]
+        {
+            self.counters.sends.fetch_add(1, RELAXED);
+            self.note_occupancy(_seq);
+        }
+        #[cfg(feature = "tracing")]
+        self.trace.sent();
+        #[cfg(feature = "metrics")]
+        if let Some(ref metrics) = self.metrics {
+            metrics.on_send();
+            // Pairs with the fence in edge_empty, so that either this sees
+            // the queue marked empty or the receiver sees the new value
+            fence(SEQ_CST);
+            if self.empty_edge.load(RELAXED) && self.empty_edge.swap(false, RELAXED) {
+                metrics.on_became_not_empty();
+            }
+        }
+    }
+
+    #[inline(always)]
+    fn note_full(&self) {
+        #[cfg(feature = "stats")]
+        self.counters.full.fetch_add(1, RELAXED);
+        #[cfg(feature = "tracing")]
+        self.trace.full();
+        #[cfg(feature = "metrics")]
+        if let Some(ref metrics) = self.metrics {
+            metrics.on_full();
+            self.edge_full(&**metrics);
+        }
+    }
+
+    #[cold]
+    fn note_duplicate(&self) {
+        #[cfg(feature = "stats")]
+        self.counters.duplicates.fetch_add(1, RELAXED);
+    }
+
+    /// Called by receivers after taking values off the queue
+    #[cfg(feature = "metrics")]
+    #[inline(always)]
+    fn note_taken(&self, metrics: &dyn QueueMetrics) {
+        // Pairs with the fence in edge_full, like in note_send
+        fence(SEQ_CST);
+        if self.full_edge.load(RELAXED) && self.full_edge.swap(false, RELAXED) {
+            metrics.on_became_not_full();
+        }
+    }
+
+    /// Marks the queue full if it wasn't already. Receivers may have made room while
+    /// the metrics were being told, without seeing the mark, so the queue is checked again
+    #[cfg(feature = "metrics")]
+    #[cold]
+    fn edge_full(&self, metrics: &dyn QueueMetrics) {
+        if self.full_edge.swap(true, RELAXED) {
+            return;
+        }
+        metrics.on_became_full();
+        fence(SEQ_CST);
+        if self.max_lag() < self.capacity as usize {
+            self.full_edge.store(false, RELAXED);
+            metrics.on_became_not_full();
+        }
+    }
+
+    /// Marks the queue empty if it wasn't already, checking the stream again like edge_full
+    #[cfg(feature = "metrics")]
+    #[cold]
+    fn edge_empty(&self, metrics: &dyn QueueMetrics, reader: &Reader) {
+        if self.empty_edge.swap(true, RELAXED) {
+            return;
+        }
+        metrics.on_became_empty();
+        fence(SEQ_CST);
+        let seq = reader.load_count(RELAXED);
+        let mask = self.capacity as usize - 1;
+        let written = unsafe { rm_tag((*self.data.add(seq & mask)).wraps.load(ACQUIRE)) == seq };
+        if written {
+            self.empty_edge.store(false, RELAXED);
+            metrics.on_became_not_empty();
+        }
+    }
+
+    /// Raises the high watermark if the queue is fuller than it's ever been
+    /// after the value at seq was written. The cached tail is never ahead of
+    /// the streams, so they're only looked at when it says the watermark may be passed
+    #[cfg(feature = "stats")]
+    #[inline(always)]
+    fn note_occupancy(&self, seq: usize) {
+        let written = seq.wrapping_add(1);
+        let high_water = self.counters.high_water.load(RELAXED);
+        if rm_tag(written.wrapping_sub(self.tail_cache.load(RELAXED))) <= high_water {
+            return;
+        }
+        // Paused streams aren't counted, since they can fall further behind than the queue holds
+        let _guard = self.manager.protect();
+        if let Some(occupancy) = self.tail.get_max_diff(written, false) {
+            let occupancy = (occupancy as usize).min(self.capacity as usize);
+            self.counters.high_water.fetch_max(occupancy, RELAXED);
+        }
+    }
+
+    /// Sets the high watermark back to zero and returns what it was
+    #[cfg(feature = "stats")]
+    pub fn reset_high_water(&self) -> usize {
+        self.counters.high_water.swap(0, RELAXED)
+    }
+
+    /// Wakes up readers waiting on the queue
+    #[inline(always)]
+    fn notify(&self) {
+        #[cfg(feature = "fault_injection")]
+        self.faults.delay_notify();
+        if self.skip_idle_notify {
+            // Pairs with the fence in start_waiting, so either this sees
+            // the reader or the reader sees what was written
+            fence(SEQ_CST);
+            if self.waiting.load(RELAXED) == 0 {
+                return;
+            }
+        }
+        #[cfg(feature = "stats")]
+        self.counters.notifies.fetch_add(1, RELAXED);
+        self.waiter.notify();
+        self.ring_bells();
+    }
+
+    /// Wakes up a reader for a single value which was just sent. When there's only
+    /// one stream its readers all compete for the value, so only one of them is woken.
+    /// That's only done for waits which don't need every notify, where the fence
+    /// that's taken anyways to check for waiting readers also covers the stream count
+    #[inline(always)]
+    fn notify_one(&self) {
+        #[cfg(feature = "fault_injection")]
+        self.faults.delay_notify();
+        if !self.skip_idle_notify {
+            #[cfg(feature = "stats")]
+            self.counters.notifies.fetch_add(1, RELAXED);
+            self.waiter.notify();
+            self.ring_bells();
+            return;
+        }
+        // Pairs with the fence in start_waiting, so either this sees the reader
+        // or the reader sees what was written. A stream added after the value
+        // went in is likewise either counted below or sees the value
+        fence(SEQ_CST);
+        if self.waiting.load(RELAXED) == 0 {
+            return;
+        }
+        #[cfg(feature = "stats")]
+        self.counters.notifies.fetch_add(1, RELAXED);
+        if self.tail.num_streams() == 1 {
+            self.waiter.notify_one();
+        } else {
+            self.waiter.notify();
+        }
+        self.ring_bells();
+    }
+
+    /// Rings every doorbell added with add_doorbell
+    #[inline(always)]
+    fn ring_bells(&self) {
+        // Pairs with the fence in add_doorbell, so either this sees the doorbell
+        // or its owner sees what was written when checking the queue again
+        fence(SEQ_CST);
+        if self.num_bells.load(RELAXED) != 0 {
+            for bell in self.bells.lock().iter() {
+                let _ = bell.try_send(());
+            }
+        }
+    }
+
+    /// Has the doorbell rung whenever readers of the queue would be woken, until it's
+    /// removed with remove_doorbell. It counts as a waiting reader, so the caller has
+    /// to check the queue again afterwards. Returns false without adding it when the
+    /// writers never notify, since then nothing would ring it
+    pub fn add_doorbell(&self, bell: &Sender<()>) -> bool {
+        if !self.needs_notify {
+            return false;
+        }
+        let mut bells = self.bells.lock();
+        bells.push(bell.clone());
+        self.num_bells.store(bells.len(), RELAXED);
+        drop(bells);
+        self.start_waiting();
+        fence(SEQ_CST);
+        true
+    }
+
+    /// Stops ringing a doorbell added with add_doorbell
+    pub fn remove_doorbell(&self, bell: &Sender<()>) {
+        let mut bells = self.bells.lock();
+        if let Some(at) = bells.iter().position(|b| b.same_channel(bell)) {
+            bells.swap_remove(at);
+            self.num_bells.store(bells.len(), RELAXED);
+            drop(bells);
+            self.stop_waiting();
+        }
+    }
+
+    /// Counts a reader which found its stream empty as waiting, so that writers
+    /// know to notify. The reader has to check the stream again afterwards
+    #[inline(always)]
+    fn start_waiting(&self) {
+        if self.skip_idle_notify {
+            self.waiting.fetch_add(1, RELAXED);
+            fence(SEQ_CST);
+        }
+    }
+
+    /// Stops counting a reader from start_waiting
+    #[inline(always)]
+    fn stop_waiting(&self) {
+        if self.skip_idle_notify {
+            self.waiting.fetch_sub(1, RELAXED);
+        }
+    }
+
+    /// Starts injecting the faults in the passed config, replacing any from before
+    #[cfg(feature = "fault_injection")]
+    pub fn inject_faults(&self, config: FaultConfig) {
+        self.faults.set(config);
+    }
+
+    /// Sets the label the queue is listed under in the registry
+    #[cfg(feature = "registry")]
+    pub fn set_label(&self, label: Cow<'static, str>) {
+        registry::set_label(self.registry_id, label);
+    }
+
+    /// Returns whether sends have to take the path for several writers
+    #[inline(always)]
+    fn forces_multi(&self) -> bool {
+        #[cfg(feature = "fault_injection")]
+        return self.faults.force_multi();
+        #[cfg(not(feature = "fault_injection"))]
+        false
+    }
+
+    /// Returns a snapshot of the queue's state and counters
+    pub fn stats(&self) -> QueueStats {
+        // The streams are loaded first so that none of them can appear to be past the head
+        let streams = {
+            let _guard = self.manager.protect();
+            self.tail.stream_stats()
+        };
+        fence(ACQUIRE);
+        let head = self.head.load_count(RELAXED);
+        let writers = self.writer_count();
+        let capacity = self.capacity as usize;
+        let occupancy = streams
+            .iter()
+            .map(|stream| rm_tag(head.wrapping_sub(stream.tail)))
+            .max()
+            .unwrap_or(0);
+        QueueStats {
+            head,
+            capacity,
+            // Paused and bounded streams can be further behind than the queue holds
+            occupancy: occupancy.min(capacity),
+            writers,
+            closed: writers == 0 || streams.is_empty(),
+            streams,
+            #[cfg(feature = "stats")]
+            sends: self.counters.sends.load(RELAXED),
+            #[cfg(feature = "stats")]
+            full: self.counters.full.load(RELAXED),
+            #[cfg(feature = "stats")]
+            notifies: self.counters.notifies.load(RELAXED),
+            #[cfg(feature = "stats")]
+            high_water: self.counters.high_water.load(RELAXED),
+            #[cfg(feature = "stats")]
+            duplicates: self.counters.duplicates.load(RELAXED),
+        }
+    }
+
+    /// Returns how many bytes the queue takes up
+    pub fn memory_footprint(&self) -> MemoryFootprint {
+        let capacity = self.capacity as usize;
+        let line = mem::size_of::<[u8; 64]>();
+        // The queue is split into four padded sections, and each refcount has a line to itself
+        let queue_padding = 4 * line;
+        MemoryFootprint {
+            ring: capacity * mem::size_of::<QueueEntry<T>>(),
+            refcounts: capacity * (mem::size_of::<RefCnt>() - line)
+                + self
+                    .times
+                    .as_ref()
+                    .map_or(0, |times| mem::size_of_val(&**times)),
+            padding: capacity * line + queue_padding,
+            // The queue lives behind an Arc, whose counts come before it
+            header: 2 * mem::size_of::<usize>() + mem::size_of_val(self) - queue_padding,
+            streams: self.tail.footprint(),
+            reclaim: self.manager.footprint(),
+        }
+    }
+
+    /// Looks over the queue for internal inconsistencies. Only meaningful
+    /// while nothing is sending to or receiving from the queue
+    #[cfg(feature = "test-util")]
+    pub fn check_invariants(&self) -> InvariantReport {
+        let streams = {
+            let _guard = self.manager.protect();
+            self.tail.stream_checks()
+        };
+        fence(ACQUIRE);
+        let head = self.head.load_count(RELAXED);
+        let tail_cache = self.tail_cache.load(RELAXED);
+        let capacity = self.capacity as usize;
+        let mask = capacity - 1;
+        let mut violations = Vec::new();
+
+        if streams.len() != self.tail.num_streams() {
+            violations.push(format!(
+                "{} streams are counted but {} are in the cursor",
+                self.tail.num_streams(),
+                streams.len()
+            ));
+        }
+        let mut max_diff = 0;
+        for stream in &streams {
+            let (diff, ahead) = past(head, stream.pos);
+            if ahead {
+                violations.push(format!("{} is past the head", stream.describe()));
+                continue;
+            }
+            if stream.consumers == 0 {
+                violations.push(format!("{} has no consumers", stream.describe()));
+            }
+            // Writers don't wait on paused streams, so they can fall any distance behind.
+            // Bounded streams are only skipped once a writer runs out of room, so they
+            // can be past their max lag, but never by more than the queue holds
+            if stream.paused {
+                continue;
+            }
+            if diff > capacity {
+                violations.push(format!(
+                    "{} is {} behind the head, more than the queue holds",
+                    stream.describe(),
+                    diff
+                ));
+            }
+            max_diff = max_diff.max(diff.min(capacity));
+        }
+        let tail = head.wrapping_sub(max_diff);
+        if past(tail, tail_cache).1 {
+            violations.push(format!(
+                "the tail cache at {} is past the slowest stream at {}",
+                tail_cache, tail
+            ));
+        }
+
+        for i in 0..capacity {
+            let (cell, ref_cell) = unsafe { (&*self.data.add(i), &*self.refs.add(i)) };
+            let tag = cell.wraps.load(RELAXED);
+            if tag == INITIAL_QUEUE_FLAG {
+                if head > i {
+                    violations.push(format!(
+                        "slot {} was never written, but the head is at {}",
+                        i, head
+                    ));
+                }
+            } else {
+                let (diff, ahead) = past(head, tag);
+                if is_tagged(tag) || tag & mask != i || ahead || diff == 0 || diff > capacity {
+                    violations.push(format!(
+                        "slot {} is tagged {:#x}, which isn't the last value written there with the head at {}",
+                        i, tag, head
+                    ));
+                }
+            }
+            let refs = ref_cell.refcnt.load(RELAXED);
+            if refs != 0 {
+                violations.push(format!(
+                    "slot {} is still referenced by {} readers",
+                    i, refs
+                ));
+            }
+        }
+        // Everything the slowest stream hasn't read has to still be there
+        let mut seq = tail;
+        while seq != head {
+            let tag = unsafe { (*self.data.add(seq & mask)).wraps.load(RELAXED) };
+            if tag != seq {
+                violations.push(format!(
+                    "value {} hasn't been read by every stream, but its slot is tagged {:#x}",
+                    seq, tag
+                ));
+            }
+            seq = seq.wrapping_add(1);
+        }
+
+        InvariantReport {
+            head,
+            capacity,
+            tail_cache,
+            tail,
+            streams: streams.len(),
+            violations,
+        }
+    }
+
+    #[inline(always)]
+    fn is_superseded(&self, ref_cell: &RefCnt, seq: usize) -> bool {
+        self.conflating && ref_cell.superseded.load(RELAXED) == seq
+    }
+
+    /// Marks the value written at the passed sequence number as replaced,
+    /// so that readers skip it
+    fn supersede(&self, seq: usize) {
+        unsafe {
+            let ref_cell = &*self.refs.add(seq & (self.capacity as usize - 1));
+            ref_cell.superseded.store(seq, RELEASE);
+        }
+    }
+
+    fn reload_tail_multi(&self, tail_cache: usize, count: usize) -> usize {
+        let _guard = self.manager.protect();
+        if let Some(max_diff_from_head) = self.tail.get_max_diff(count, true) {
+            let current_tail =
+                CountedIndex::get_previous(count, self.clamp_diff(max_diff_from_head));
+            if tail_cache == current_tail {
+                return current_tail;
+            }
+            match self
+                .tail_cache
+                .compare_exchange(tail_cache, current_tail, ACQ_REL, RELAXED)
+            {
+                Ok(_) => current_tail,
+                Err(val) => val,
+            }
+        } else {
+            self.tail_cache.load(ACQUIRE)
+        }
+    }
+
+    fn reload_tail_single(&self, tail_cache: usize, count: usize) -> usize {
+        let _guard = self.manager.protect();
+        let max_diff_from_head = self.tail.get_max_diff(count, true).expect(
+            "The write head got ran over by consumers in single writer mode. This \
+             process is borked!",
+        );
+        let current_tail = CountedIndex::get_previous(count, self.clamp_diff(max_diff_from_head));
+        // This can't be a plain store since add_stream_from_earliest
+        // may have moved the cache backwards while we were looking at the readers
+        match self
+            .tail_cache
+            .compare_exchange(tail_cache, current_tail, RELAXED, RELAXED)
+        {
+            Ok(_) => current_tail,
+            Err(val) => val,
+        }
+    }
+
+    /// A stream which was just added behind the others can briefly appear to be
+    /// more than a full queue behind the head. Treating that as merely full keeps
+    /// writers from reusing slots the new stream has yet to read
+    #[inline(always)]
+    fn clamp_diff(&self, diff: Index) -> Index {
+        if diff > self.capacity as Index {
+            self.capacity as Index
+        } else {
+            diff
+        }
+    }
+
+    /// Moves the tail cache back to the passed count if it's ahead of it
+    fn lower_tail_cache(&self, to: usize) {
+        let mut tail_cache = self.tail_cache.load(SEQ_CST);
+        loop {
+            let (diff, behind) = past(tail_cache, to);
+            if diff == 0 || behind {
+                return;
+            }
+            match self
+                .tail_cache
+                .compare_exchange(tail_cache, to, SEQ_CST, SEQ_CST)
+            {
+                Ok(_) => return,
+                Err(val) => tail_cache = val,
+            }
+        }
+    }
+
+    /// Returns the count of the oldest item which is safe for a new stream to start at.
+    /// The slot the next write lands in is never included.
+    fn earliest_retained(&self, chead: usize) -> usize {
+        let wrap = self.capacity as usize;
+        let start = rm_tag(chead.wrapping_sub(wrap - 1));
+        unsafe {
+            let cell = &*self.data.add(start & (wrap - 1));
+            // Only slots which have never been written are tagged,
+            // so the queue hasn't wrapped around yet
+            if is_tagged(cell.wraps.load(RELAXED)) {
+                return 0;
+            }
+        }
+        start
+    }
+
+    /// Adds a stream which starts at the head of the queue
+    pub fn add_stream_from_latest(&self) -> Reader {
+        let chead = self.head.load_count(SEQ_CST);
+        self.tail
+            .add_stream_at(chead, self.capacity as Index, 0, None, &self.manager)
+    }
+
+    /// Adds a stream which starts at the oldest item still held in the queue
+    pub fn add_stream_from_earliest(&self) -> Reader {
+        let start = self.earliest_retained(self.head.load_count(SEQ_CST));
+        let reader = self
+            .tail
+            .add_stream_at(start, self.capacity as Index, 0, None, &self.manager);
+        self.settle_stream(&reader, start);
+        reader
+    }
+
+    /// Writers which didn't see the stream when looking at the readers may still be
+    /// committing, so keep moving the stream forwards until it's clear of them
+    fn settle_stream(&self, reader: &Reader, mut start: usize) {
+        loop {
+            self.lower_tail_cache(start);
+            fence(SEQ_CST);
+            let chead = self.head.load_count(SEQ_CST);
+            if past(chead, start).0 < self.capacity as usize {
+                return;
+            }
+            start = self.earliest_retained(chead);
+            reader.store_count(start, SEQ_CST);
+        }
+    }
+
+    /// Adds a stream at the passed count, unless writers have already overwritten it
+    pub fn attach_stream(
+        &self,
+        pos: usize,
+        max_lag: usize,
+        name: Option<Cow<'static, str>>,
+    ) -> Result<Reader, AttachError> {
+        if past(self.head.load_count(SEQ_CST), pos).0 >= self.capacity as usize {
+            return Err(AttachError::Overwritten);
+        }
+        let reader = self
+            .tail
+            .try_add_stream_at(pos, self.capacity as Index, max_lag, name, &self.manager)
+            .map_err(|_| AttachError::StreamsFull)?;
+        // Writers which didn't see the stream may have gone past it in the meantime,
+        // and unlike settle_stream it can't be moved forwards to get clear of them
+        self.lower_tail_cache(pos);
+        fence(SEQ_CST);
+        if past(self.head.load_count(SEQ_CST), pos).0 < self.capacity as usize {
+            return Ok(reader);
+        }
+        self.tail.remove_reader(&reader, &self.manager);
+        Err(AttachError::Overwritten)
+    }
+
+    /// Stops writers from waiting on the passed stream. Nothing may read from it until it's resumed
+    pub fn pause(&self, reader: &Reader) {
+        reader.set_paused(true);
+    }
+
+    /// Makes writers wait on the passed stream again. It stays where it was if nothing it
+    /// was waiting on got overwritten, and otherwise moves to the oldest item still held
+    pub fn resume(&self, reader: &Reader) {
+        let chead = self.head.load_count(SEQ_CST);
+        let ctail = reader.load_count(RELAXED);
+        let start = if past(chead, ctail).0 < self.capacity as usize {
+            ctail
+        } else {
+            self.earliest_retained(chead)
+        };
+        reader.store_count(start, SEQ_CST);
+        reader.set_paused(false);
+        self.settle_stream(reader, start);
+    }
+
+    /// Adds a stream at the same spot as the passed reader which writers
+    /// skip forwards instead of waiting on once it's max_lag items behind
+    pub fn add_bounded_stream(&self, reader: &Reader, max_lag: usize) -> Reader {
+        assert!(max_lag > 0, "Multiqueue error - zero max lag received");
+        let max_lag = self.clamp_diff(max_lag as Index) as usize;
+        let raw = reader.load_count(RELAXED);
+        self.tail
+            .add_stream_at(raw, self.capacity as Index, max_lag, None, &self.manager)
+    }
+
+    /// Adds a stream at the head of the queue which writers skip forwards
+    /// instead of waiting on once it's max_lag items behind
+    pub fn add_bounded_stream_from_latest(&self, max_lag: usize) -> Reader {
+        assert!(max_lag > 0, "Multiqueue error - zero max lag received");
+        let max_lag = self.clamp_diff(max_lag as Index) as usize;
+        let chead = self.head.load_count(SEQ_CST);
+        self.tail
+            .add_stream_at(chead, self.capacity as Index, max_lag, None, &self.manager)
+    }
+
+    /// Moves a bounded stream forwards if it has fallen more than its max lag behind
+    fn enforce_max_lag(&self, reader: &Reader) {
+        let max_lag = reader.max_lag();
+        loop {
+            let ctail = reader.load_count(RELAXED);
+            let chead = self.head.load_count(RELAXED);
+            let (diff, behind) = past(chead, ctail);
+            if behind || diff <= max_lag {
+                return;
+            }
+            if reader.skip_forward(ctail, rm_tag(chead.wrapping_sub(max_lag))) {
+                return;
+            }
+        }
+    }
+
+    /// Returns the number of items a bounded stream was skipped past since this was last called
+    pub fn take_lagged(&self, reader: &Reader) -> usize {
+        self.enforce_max_lag(reader);
+        reader.take_skipped()
+    }
+
+    /// Returns the number of items between the passed reader and the write head
+    pub fn lag(&self, reader: &Reader) -> usize {
+        // The reader is loaded first so that it can never appear to be past the head
+        let ctail = reader.load_count(RELAXED);
+        let chead = self.head.load_count(RELAXED);
+        rm_tag(chead.wrapping_sub(ctail))
+    }
+
+    /// Returns whether the next slot could be written right now.
+    /// Readers only ever make more room, so for a sole writer this holds until it sends
+    fn has_room(&self) -> bool {
+        let transaction = self.head.load_transaction(RELAXED);
+        let (chead, wrap_valid_tag) = transaction.get();
+        let tail_cache = self.tail_cache.load(RELAXED);
+        if transaction.wrapped_past(tail_cache) {
+            let new_tail = self.reload_tail_multi(tail_cache, wrap_valid_tag);
+            if transaction.wrapped_past(new_tail) {
+                return false;
+            }
+        }
+        let ref_cell = unsafe { &*self.refs.offset(chead) };
+        self.slot_free(ref_cell)
+    }
+
+    /// Returns a barrier after every value which has been written so far,
+    /// or which a writer has started writing
+    pub fn barrier(&self) -> Barrier {
+        Barrier {
+            at: self.head.load_count(ACQUIRE),
+        }
+    }
+
+    /// Returns whether every stream which isn't paused has passed the barrier
+    pub fn barrier_passed(&self, barrier: Barrier) -> bool {
+        let _guard = self.manager.protect();
+        self.tail.all_past(barrier.at)
+    }
+
+    /// Stops the writers from sending until unfreeze is called. The writers
+    /// check the signal before every send, so this costs them nothing. Writers
+    /// which claimed a slot before seeing the signal are waited for, so that
+    /// everything which went in before this returns can be received
+    pub fn freeze(&self) {
+        self.manager.signal.set_frozen(SEQ_CST);
+        self.wait_for_published();
+    }
+
+    /// Waits until every slot claimed from the head so far has had its value stored
+    fn wait_for_published(&self) {
+        let chead = self.head.load_count(SEQ_CST);
+        let mask = self.capacity as usize - 1;
+        let mut backoff = Backoff::new();
+        // Slots a whole ring back were claimed before the ones in front of them
+        // could be, so only the last capacity of them can still be in flight
+        for back in 1..=chead.min(self.capacity as usize) {
+            let seq = chead.wrapping_sub(back);
+            let cell = unsafe { &*self.data.add(seq & mask) };
+            loop {
+                // A value from a later lap means this one went in long ago
+                let tag = cell.wraps.load(ACQUIRE);
+                if !is_tagged(tag) && !past(tag, seq).1 {
+                    break;
+                }
+                backoff.snooze(None);
+            }
+        }
+    }
+
+    pub fn unfreeze(&self) {
+        self.manager.signal.clear_frozen(SEQ_CST);
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.manager.signal.load(SEQ_CST).get_frozen()
+    }
+
+    /// Returns the number of streams currently subscribed to the queue
+    pub fn stream_count(&self) -> usize {
+        let _guard = self.manager.protect();
+        self.tail.num_streams()
+    }
+
+    /// Returns the label of each stream along with how many items it's behind the write head
+    pub fn stream_lags(&self) -> Vec<(Option<String>, usize)> {
+        // The head is loaded first so that no stream can appear to be past it
+        let chead = self.head.load_count(RELAXED);
+        fence(ACQUIRE);
+        let _guard = self.manager.protect();
+        self.tail.stream_lags(chead)
+    }
+
+    /// Returns the label of each stream which has had items to read without moving
+    /// for at least threshold, along with how long it's been stuck
+    pub fn stalled_streams(&self, threshold: Duration) -> Vec<(Option<String>, Duration)> {
+        let created = self
+            .created
+            .expect("Multiqueue error - stalled_streams needs a clock, which this target lacks");
+        let now = self.elapsed(created).as_nanos() as u64;
+        // Streams which appear to be past the head have moved since it was loaded
+        let chead = self.head.load_count(RELAXED);
+        fence(ACQUIRE);
+        let _guard = self.manager.protect();
+        self.tail
+            .stalled_streams(chead, now, threshold.as_nanos() as u64)
+            .into_iter()
+            .map(|(name, stuck)| (name, Duration::from_nanos(stuck)))
+            .collect()
+    }
+
+    /// Returns the number of writers currently subscribed to the queue
+    pub fn writer_count(&self) -> usize {
+        self.writers.load(RELAXED)
+    }
+
+    /// Returns how many values the queue holds
+    pub fn capacity(&self) -> usize {
+        self.capacity as usize
+    }
+
+    /// Returns the largest number of items any stream is behind the write head
+    pub fn max_lag(&self) -> usize {
+        let _guard = self.manager.protect();
+        loop {
+            let chead = self.head.load_count(RELAXED);
+            if let Some(max_diff) = self.tail.get_max_diff(chead, false) {
+                return max_diff as usize;
+            }
+        }
     }
 }
 
 impl<RW: QueueRW<T>, T> InnerSend<RW, T> {
     #[inline(always)]
-    pub fn try_send(&self, val: T) -> Result<(), TrySendError<T>> {
-        let signal = self.queue.manager.signal.load(Relaxed);
-        if signal.has_action() {
-            let disconnected = self.handle_signals(signal);
-            if disconnected {
-                return Err(TrySendError::Full(val));
+    pub fn try_send(&self, val: T) -> Result<(), FrozenTrySendError<T>> {
+        let val = self.try_send_unnotified(val);
+        // Putting this in the send functions
+        // greatly confuses the compiler and literally halfs
+        // the performance of the queue. I suspect the compiler
+        // always sets up a stack from regardless of the condition
+        // and that hurts optimizations around it.
+        if val.is_ok() && self.queue.needs_notify {
+            self.queue.notify_one();
+        }
+        val
+    }
+
+    /// Identical to try_send, except that with several writers it gives up with Full
+    /// once it's lost the race to commit max_attempts times, bounding how long it spins
+    pub fn try_send_spin(&self, val: T, max_attempts: usize) -> Result<(), FrozenTrySendError<T>> {
+        let val = self.check_signals(val)?;
+        let mut send = |v| match self.try_send_payload(Payload::Owned(v), 0, 0, max_attempts) {
+            Ok(seq) => Ok(seq),
+            Err(TrySendError::Full(v)) => Err(TrySendError::Full(v.into_val())),
+            Err(TrySendError::Disconnected(v)) => Err(TrySendError::Disconnected(v.into_val())),
+        };
+        let rval = match (&self.queue.conflator, &self.queue.dedup) {
+            (Some(conflator), _) => conflator.send(val, &mut send).map(|replaced| {
+                if let Some(replaced) = replaced {
+                    self.queue.supersede(replaced);
+                }
+            }),
+            (None, Some(dedup)) => dedup.send(val, &mut send).map(|sent| {
+                if !sent {
+                    self.queue.note_duplicate();
+                }
+            }),
+            (None, None) => send(val).map(|_| ()),
+        };
+        if rval.is_ok() && self.queue.needs_notify {
+            self.queue.notify_one();
+        }
+        rval.map_err(FrozenTrySendError::from)
+    }
+
+    /// Sends as many of vals as there's room for and wakes the readers once,
+    /// returning how many it sent. The sole writer on a plain queue copies them
+    /// into the ring with one move of the head, others send them one at a time
+    pub fn try_send_slice(&self, vals: &[T]) -> usize
+    where
+        T: Copy,
+    {
+        let signal = self.queue.manager.signal.load(RELAXED);
+        if signal.has_action() && self.handle_signals(signal) {
+            return 0;
+        }
+        let plain = self.queue.conflator.is_none()
+            && self.queue.dedup.is_none()
+            && self.queue.weigher.is_none()
+            && !self.queue.forces_multi();
+        let sole = match self.state.get() {
+            QueueState::Uni => true,
+            QueueState::Multi if self.queue.writers.load(RELAXED) == 1 => {
+                fence(ACQUIRE);
+                self.state.set(QueueState::Uni);
+                true
+            }
+            QueueState::Multi => false,
+        };
+        let sent = if plain && sole {
+            let (_first, sent) = self.queue.try_send_slice(vals);
+            #[cfg(feature = "order_checks")]
+            for i in 0..sent {
+                self.sent.saw(rm_tag(_first.wrapping_add(i)), "writer");
+            }
+            if sent < vals.len() {
+                self.queue.note_full();
+            }
+            sent
+        } else {
+            vals.iter()
+                .take_while(|val| self.try_send_unnotified(**val).is_ok())
+                .count()
+        };
+        if sent != 0 && self.queue.needs_notify {
+            self.queue.notify();
+        }
+        sent
+    }
+
+    /// Sends all of vals back to back with nothing from other writers between them,
+    /// or none of them if they don't all fit. None of them are visible to readers
+    /// until all of them have been written. Readers are woken once for all of them.
+    /// Panics on conflated queues, since the values could replace each other,
+    /// and on deduplicated queues, since some of them could be dropped
+    pub fn try_send_all(&self, vals: Vec<T>) -> Result<(), FrozenTrySendError<Vec<T>>> {
+        assert!(
+            self.queue.conflator.is_none(),
+            "Multiqueue error - conflated queues can't send values all together"
+        );
+        assert!(
+            self.queue.dedup.is_none(),
+            "Multiqueue error - deduplicated queues can't send values all together"
+        );
+        if vals.is_empty() {
+            return Ok(());
+        }
+        let vals = self.check_signals(vals)?;
+        #[cfg(feature = "fault_injection")]
+        if self.queue.faults.fail_send() {
+            self.queue.note_full();
+            return Err(FrozenTrySendError::Full(vals));
+        }
+        let weight = match self.queue.weigher {
+            Some(ref weigher) => vals.iter().map(weigher).sum(),
+            None => 0,
+        };
+        if weight != 0 && !self.queue.reserve_weight_of(weight) {
+            self.queue.note_full();
+            return Err(FrozenTrySendError::Full(vals));
+        }
+        let count = vals.len();
+        match self.queue.try_send_all(vals) {
+            Ok(first) => {
+                for i in 0..count {
+                    let seq = rm_tag(first.wrapping_add(i));
+                    #[cfg(feature = "order_checks")]
+                    self.sent.saw(seq, "writer");
+                    self.queue.note_send(seq);
+                }
+                if self.queue.needs_notify {
+                    self.queue.notify();
+                }
+                Ok(())
+            }
+            Err(vals) => {
+                if weight != 0 {
+                    self.queue.weight.fetch_sub(weight, RELAXED);
+                }
+                self.queue.note_full();
+                Err(FrozenTrySendError::Full(vals))
+            }
+        }
+    }
+
+    /// Sends values off the front of vals until it runs out or one doesn't go in,
+    /// waking the readers once for all of them. Whatever wasn't sent is left in vals
+    pub fn try_send_from(&self, vals: &mut VecDeque<T>) -> Result<(), FrozenTrySendError<()>> {
+        let mut sent = false;
+        let mut rval = Ok(());
+        while let Some(val) = vals.pop_front() {
+            match self.try_send_unnotified(val) {
+                Ok(()) => sent = true,
+                Err(FrozenTrySendError::Full(val)) => {
+                    vals.push_front(val);
+                    rval = Err(FrozenTrySendError::Full(()));
+                    break;
+                }
+                Err(FrozenTrySendError::Disconnected(val)) => {
+                    vals.push_front(val);
+                    rval = Err(FrozenTrySendError::Disconnected(()));
+                    break;
+                }
+                Err(FrozenTrySendError::Frozen(val)) => {
+                    vals.push_front(val);
+                    rval = Err(FrozenTrySendError::Frozen(()));
+                    break;
+                }
+            }
+        }
+        if sent && self.queue.needs_notify {
+            self.queue.notify();
+        }
+        rval
+    }
+
+    /// Identical to try_send, except it leaves waking the readers to the caller
+    #[inline(always)]
+    fn try_send_unnotified(&self, val: T) -> Result<(), FrozenTrySendError<T>> {
+        let val = self.check_signals(val)?;
+        Ok(self.try_send_owned(val)?)
+    }
+
+    /// Sends the value through the queue's conflator or dedup window, if it has one
+    #[inline(always)]
+    fn try_send_owned(&self, val: T) -> Result<(), TrySendError<T>> {
+        match (&self.queue.conflator, &self.queue.dedup) {
+            (Some(conflator), _) => self.try_send_conflated(&**conflator, val),
+            (None, Some(dedup)) => self.try_send_deduplicated(&**dedup, val),
+            (None, None) => self.try_send_raw(val, 0, 0).map(|_| ()),
+        }
+    }
+
+    /// Identical to try_send, except the value is only cloned into the queue once
+    /// there's room for it. Conflated and deduplicated queues clone it first, since
+    /// they have to own it before they know whether it goes in
+    pub fn try_send_ref<'a>(&self, val: &'a T) -> Result<(), FrozenTrySendError<&'a T>>
+    where
+        T: Clone,
+    {
+        let val = self.check_signals(val)?;
+        let rval = if self.queue.conflator.is_some() || self.queue.dedup.is_some() {
+            self.try_send_owned(val.clone()).map_err(|e| match e {
+                TrySendError::Full(_) => TrySendError::Full(val),
+                TrySendError::Disconnected(_) => TrySendError::Disconnected(val),
+            })
+        } else {
+            match self.try_send_payload(Payload::Cloned(val, T::clone), 0, 0, usize::MAX) {
+                Ok(_) => Ok(()),
+                Err(TrySendError::Full(_)) => Err(TrySendError::Full(val)),
+                Err(TrySendError::Disconnected(_)) => Err(TrySendError::Disconnected(val)),
+            }
+        };
+        if rval.is_ok() && self.queue.needs_notify {
+            self.queue.notify_one();
+        }
+        rval.map_err(FrozenTrySendError::from)
+    }
+
+    /// Identical to try_send, except that on a full queue with a single stream the oldest
+    /// value nobody has received yet is taken off to make room, and handed back. Queues
+    /// with several streams or a paused one fail like try_send. If other writers take the
+    /// room first, any further values displaced are dropped, as is the first should the
+    /// send fail after all. Only valid for queues created with replacement
+    pub fn try_send_or_replace(&self, val: T) -> Result<Option<T>, FrozenTrySendError<T>> {
+        assert!(
+            self.queue.replacing,
+            "Multiqueue error - replacing values on a queue created without replacement"
+        );
+        let mut val = self.check_signals(val)?;
+        let mut displaced = None;
+        let mut emptied = false;
+        let rval = loop {
+            match self.try_send_raw(val, 0, 0) {
+                Ok(_) => break Ok(displaced),
+                Err(TrySendError::Full(rejected)) => match self.queue.displace_oldest() {
+                    Some(Ok(oldest)) => {
+                        displaced = displaced.or(Some(oldest));
+                        val = rejected;
+                    }
+                    // Readers made room in the meantime, so it's worth another try
+                    Some(Err(TryRecvError::Empty)) if !emptied => {
+                        emptied = true;
+                        val = rejected;
+                    }
+                    _ => break Err(TrySendError::Full(rejected)),
+                },
+                Err(e) => break Err(e),
+            }
+        };
+        if rval.is_ok() && self.queue.needs_notify {
+            self.queue.notify_one();
+        }
+        rval.map_err(FrozenTrySendError::from)
+    }
+
+    /// Identical to try_send, except readers can't see the value until the deadline has
+    /// passed. Values behind it wait as well. Only valid for queues created with delays
+    pub fn try_send_after(&self, val: T, deadline: Instant) -> Result<(), FrozenTrySendError<T>> {
+        let ready_at = self.queue.ready_at(deadline);
+        let val = self.check_signals(val)?;
+        let val = self.try_send_raw(val, ready_at, 0).map(|_| ());
+        if val.is_ok() && self.queue.needs_notify {
+            self.queue.notify_one();
+        }
+        val.map_err(FrozenTrySendError::from)
+    }
+
+    /// Identical to try_send, except readers drop the value instead of receiving it
+    /// once ttl has passed. Only valid for queues created with expiry
+    pub fn try_send_with_ttl(&self, val: T, ttl: Duration) -> Result<(), FrozenTrySendError<T>> {
+        let expires_at = self.queue.expires_at(ttl);
+        let val = self.check_signals(val)?;
+        let val = self.try_send_raw(val, 0, expires_at).map(|_| ());
+        if val.is_ok() && self.queue.needs_notify {
+            self.queue.notify_one();
+        }
+        val.map_err(FrozenTrySendError::from)
+    }
+
+    /// Adds a stream at the head of the queue which gets skipped forwards
+    /// once it falls max_lag items behind the writers. This lets writers
+    /// hand out new streams without holding on to a receiver
+    pub fn subscribe_bounded(&self, max_lag: usize) -> InnerRecv<RW, T> {
+        self.subscribe_with(self.queue.add_bounded_stream_from_latest(max_lag))
+    }
+
+    /// Adds a stream at the head of the queue which writers wait on like any other
+    pub fn subscribe(&self) -> InnerRecv<RW, T> {
+        self.subscribe_with(self.queue.add_stream_from_latest())
+    }
+
+    fn subscribe_with(&self, reader: Reader) -> InnerRecv<RW, T> {
+        // Sends fail once every stream is gone, and there's one again
+        self.queue.manager.signal.clear_reader(SEQ_CST);
+        #[cfg(feature = "tracing")]
+        self.queue
+            .trace
+            .stream_added(reader.stream_name(), self.queue.stream_count());
+        InnerRecv {
+            queue: self.queue.clone(),
+            reader,
+            token: self.queue.manager.get_token(),
+            filter: None,
+            name: None,
+            alive: true,
+        }
+    }
+
+    /// Returns the largest number of items any stream is behind the write head
+    pub fn max_lag(&self) -> usize {
+        self.queue.max_lag()
+    }
+
+    /// Returns how many values the queue holds
+    pub fn capacity(&self) -> usize {
+        self.queue.capacity()
+    }
+
+    /// Returns the queue itself, for handles which look at it
+    /// without being a writer or a stream
+    pub(crate) fn shared_queue(&self) -> Arc<MultiQueue<RW, T>> {
+        self.queue.clone()
+    }
+
+    /// Returns clones of everything the furthest behind stream has yet to receive,
+    /// along with their sequence numbers. Only valid for broadcast queues
+    pub fn dump(&self) -> Vec<(u64, T)>
+    where
+        T: Clone,
+    {
+        self.queue.dump()
+    }
+
+    /// Takes room in the queue for the value without sending it, deciding everything
+    /// which could keep it out so that ```commit``` can't fail. The weight is taken
+    /// out of the budget, and the next slot is claimed by seeing it free with every
+    /// stream past it. After that no stream can be added behind it, and anything taking
+    /// its refcount gives it back as soon as it sees its stream has moved past the slot,
+    /// so only the sole writer can write there. That only holds for the sole writer on a
+    /// queue without a conflator or dedup window, which are the only ones this may be called on
+    pub(crate) fn reserve(&self, val: &T) -> Result<Reservation, FrozenTrySendError<()>> {
+        debug_assert!(self.queue.conflator.is_none() && self.queue.dedup.is_none());
+        let signal = self.queue.manager.signal.load(RELAXED);
+        let frozen = signal.get_frozen();
+        if signal.has_action() && self.handle_signals(signal) {
+            if frozen {
+                return Err(FrozenTrySendError::Frozen(()));
+            }
+            return Err(FrozenTrySendError::Disconnected(()));
+        }
+        #[cfg(feature = "fault_injection")]
+        if self.queue.faults.fail_send() {
+            self.queue.note_full();
+            return Err(FrozenTrySendError::Full(()));
+        }
+        let weight = match self.queue.weigher {
+            Some(ref weigher) => {
+                let weight = weigher(val);
+                if !self.queue.reserve_weight_of(weight) {
+                    self.queue.note_full();
+                    return Err(FrozenTrySendError::Full(()));
+                }
+                weight
+            }
+            None => 0,
+        };
+        let reservation = Reservation { weight };
+        if !self.queue.has_room() {
+            self.release(reservation);
+            self.queue.note_full();
+            return Err(FrozenTrySendError::Full(()));
+        }
+        Ok(reservation)
+    }
+
+    /// Writes the value into the room taken for it by ```reserve```. A freeze or the
+    /// last stream leaving since then doesn't keep it out, just like for a send which
+    /// checked for them right before they happened
+    pub(crate) fn commit(&self, reservation: Reservation, val: T) {
+        let Reservation { weight: _ } = reservation;
+        // Pairs with the release of any writer which left before this one was alone
+        fence(ACQUIRE);
+        let mut val = Payload::Owned(val);
+        let seq = loop {
+            match self.queue.try_send_single(val, 0, 0) {
+                Ok(seq) => break seq,
+                // Somebody took the slot's refcount and is about to see their
+                // stream is past it and give it back
+                Err(TrySendError::Full(back)) | Err(TrySendError::Disconnected(back)) => {
+                    val = back;
+                    yield_now();
+                }
+            }
+        };
+        #[cfg(feature = "order_checks")]
+        self.sent.saw(seq, "writer");
+        self.queue.note_send(seq);
+        if self.queue.needs_notify {
+            self.queue.notify_one();
+        }
+    }
+
+    /// Gives back the room taken by ```reserve``` without sending anything
+    pub(crate) fn release(&self, reservation: Reservation) {
+        if reservation.weight != 0 {
+            self.queue.weight.fetch_sub(reservation.weight, RELAXED);
+        }
+    }
+
+    /// Returns whether the queue has a conflator or dedup window,
+    /// which decide for themselves whether a value takes up a slot
+    pub(crate) fn filters_sends(&self) -> bool {
+        self.queue.conflator.is_some() || self.queue.dedup.is_some()
+    }
+
+    /// Returns a barrier after everything sent so far. Nothing is written into the queue,
+    /// so the receivers don't see it
+    pub fn barrier(&self) -> Barrier {
+        self.queue.barrier()
+    }
+
+    /// Sends the marker and returns a barrier right after it, so the receivers
+    /// see where the barrier is. Sending the marker works like try_send
+    pub fn try_send_barrier(&self, marker: T) -> Result<Barrier, FrozenTrySendError<T>> {
+        self.try_send(marker)?;
+        Ok(self.queue.barrier())
+    }
+
+    /// Returns whether every stream which isn't paused has received
+    /// everything sent before the barrier
+    pub fn barrier_passed(&self, barrier: Barrier) -> bool {
+        self.queue.barrier_passed(barrier)
+    }
+
+    /// Blocks until every stream which isn't paused has received everything sent
+    /// before the barrier. Nothing wakes the sender when a stream moves, so it polls
+    /// the streams, sleeping for at most DEFAULT_SELECT_MAX_SLEEP_US microseconds at a time
+    pub fn wait_for_barrier(&self, barrier: Barrier) {
+        let mut backoff = Backoff::new();
+        while !self.queue.barrier_passed(barrier) {
+            backoff.snooze(None);
+        }
+    }
+
+    /// Identical to wait_for_barrier, except it gives up once timeout has passed
+    /// on the queue's clock. Returns whether the streams passed the barrier
+    pub fn wait_for_barrier_timeout(&self, barrier: Barrier, timeout: Duration) -> bool {
+        let deadline = self.queue.clock.now() + timeout;
+        let mut backoff = Backoff::new();
+        while !self.queue.barrier_passed(barrier) {
+            let now = self.queue.clock.now();
+            if now >= deadline {
+                return false;
+            }
+            backoff.snooze(Some(deadline - now));
+        }
+        true
+    }
+
+    /// Identical to InnerRecv::freeze()
+    pub fn freeze(&self) {
+        self.queue.freeze()
+    }
+
+    /// Lets the writers send again after freeze
+    pub fn unfreeze(&self) {
+        self.queue.unfreeze()
+    }
+
+    /// Returns whether the queue is frozen
+    pub fn is_frozen(&self) -> bool {
+        self.queue.is_frozen()
+    }
+
+    /// Returns the number of streams subscribed to the queue
+    pub fn stream_count(&self) -> usize {
+        self.queue.stream_count()
+    }
+
+    /// Returns the label of each stream along with how many items it's behind the writers
+    pub fn stream_lags(&self) -> Vec<(Option<String>, usize)> {
+        self.queue.stream_lags()
+    }
+
+    /// Returns the label of each stream which has been stuck for at least threshold
+    pub fn stalled_streams(&self, threshold: Duration) -> Vec<(Option<String>, Duration)> {
+        self.queue.stalled_streams(threshold)
+    }
+
+    /// Returns the number of writers subscribed to the queue
+    pub fn writer_count(&self) -> usize {
+        self.queue.writer_count()
+    }
+
+    /// Returns how much of the weight budget the queued values take up
+    pub fn outstanding_weight(&self) -> usize {
+        self.queue.outstanding_weight()
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        self.queue.stats()
+    }
+
+    pub fn memory_footprint(&self) -> MemoryFootprint {
+        self.queue.memory_footprint()
+    }
+
+    #[cfg(feature = "test-util")]
+    pub fn check_invariants(&self) -> InvariantReport {
+        self.queue.check_invariants()
+    }
+
+    #[cfg(feature = "fault_injection")]
+    pub fn inject_faults(&self, config: FaultConfig) {
+        self.queue.inject_faults(config)
+    }
+
+    #[cfg(feature = "registry")]
+    pub fn set_label(&self, label: Cow<'static, str>) {
+        self.queue.set_label(label)
+    }
+
+    #[cfg(feature = "stats")]
+    pub fn reset_high_water(&self) -> usize {
+        self.queue.reset_high_water()
+    }
+
+    /// Writes the value and returns the sequence number it was written at
+    #[inline(always)]
+    fn try_send_raw(
+        &self,
+        val: T,
+        ready_at: u64,
+        expires_at: u64,
+    ) -> Result<usize, TrySendError<T>> {
+        match self.try_send_payload(Payload::Owned(val), ready_at, expires_at, usize::MAX) {
+            Ok(seq) => Ok(seq),
+            Err(TrySendError::Full(val)) => Err(TrySendError::Full(val.into_val())),
+            Err(TrySendError::Disconnected(val)) => Err(TrySendError::Disconnected(val.into_val())),
+        }
+    }
+
+    /// Identical to try_send_raw, except the value may be sent by reference, and
+    /// with several writers it gives up with Full once it's lost max_attempts times
+    fn try_send_payload<'a>(
+        &self,
+        val: Payload<'a, T>,
+        ready_at: u64,
+        expires_at: u64,
+        max_attempts: usize,
+    ) -> Result<usize, TrySendError<Payload<'a, T>>> {
+        #[cfg(feature = "fault_injection")]
+        if self.queue.faults.fail_send() {
+            self.queue.note_full();
+            return Err(TrySendError::Full(val));
+        }
+        let (val, weight) = match self.queue.reserve_weight(val) {
+            Ok(reserved) => reserved,
+            Err(e) => {
+                self.queue.note_full();
+                return Err(e);
             }
+        };
+        let forced = self.queue.forces_multi();
+        let rval = match self.state.get() {
+            QueueState::Uni if !forced => self.queue.try_send_single(val, ready_at, expires_at),
+            QueueState::Multi if !forced && self.queue.writers.load(RELAXED) == 1 => {
+                fence(ACQUIRE);
+                self.state.set(QueueState::Uni);
+                self.queue.try_send_single(val, ready_at, expires_at)
+            }
+            // Committing with a compare and swap is safe with any number of writers
+            _ => self
+                .queue
+                .try_send_multi(val, ready_at, expires_at, max_attempts, self.token.0),
+        };
+        if rval.is_err() && weight != 0 {
+            self.queue.weight.fetch_sub(weight, RELAXED);
+        }
+        #[cfg(feature = "order_checks")]
+        if let Ok(seq) = rval {
+            self.sent.saw(seq, "writer");
+        }
+        match rval {
+            Ok(seq) => self.queue.note_send(seq),
+            Err(TrySendError::Full(_)) => self.queue.note_full(),
+            Err(_) => (),
+        }
+        rval
+    }
+
+    #[cold]
+    fn try_send_conflated(
+        &self,
+        conflator: &dyn Conflate<T>,
+        val: T,
+    ) -> Result<(), TrySendError<T>> {
+        if let Some(replaced) = conflator.send(val, &mut |v| self.try_send_raw(v, 0, 0))? {
+            self.queue.supersede(replaced);
         }
-        let val = match self.state.get() {
-            QueueState::Uni => self.queue.try_send_single(val),
-            QueueState::Multi => {
-                if self.queue.writers.load(Relaxed) == 1 {
-                    fence(Acquire);
-                    self.state.set(QueueState::Uni);
-                    self.queue.try_send_single(val)
-                } else {
-                    self.queue.try_send_multi(val)
-                }
-            }
-        };
-        // Putting this in the send functions
-        // greatly confuses the compiler and literally halfs
-        // the performance of the queue. I suspect the compiler
-        // always sets up a stack from regardless of the condition
-        // and that hurts optimizations around it.
-        if val.is_ok() && self.queue.needs_notify {
-            self.queue.waiter.notify();
+        Ok(())
+    }
+
+    #[cold]
+    fn try_send_deduplicated(&self, dedup: &dyn Dedup<T>, val: T) -> Result<(), TrySendError<T>> {
+        if !dedup.send(val, &mut |v| self.try_send_raw(v, 0, 0))? {
+            self.queue.note_duplicate();
         }
-        val
+        Ok(())
     }
 
     /// Removes the writer as a producer to the queue
     pub fn unsubscribe(self) {}
 
+    /// Hands the value back if the send should be refused, with Frozen if the
+    /// queue is frozen. That's decided from the same load of the signal the
+    /// refusal goes by, so Frozen always means the send was refused because of it
+    #[inline(always)]
+    fn check_signals<V>(&self, val: V) -> Result<V, FrozenTrySendError<V>> {
+        let signal = self.queue.manager.signal.load(RELAXED);
+        let frozen = signal.get_frozen();
+        if signal.has_action() && self.handle_signals(signal) {
+            if frozen {
+                return Err(FrozenTrySendError::Frozen(val));
+            }
+            return Err(FrozenTrySendError::Full(val));
+        }
+        Ok(val)
+    }
+
+    /// Returns whether the send should be refused, because
+    /// the readers are gone or the queue is frozen
     #[cold]
     fn handle_signals(&self, signal: LoadedSignal) -> bool {
         if signal.get_epoch() {
             self.queue.manager.update_token(self.token);
         }
-        signal.get_reader()
+        signal.get_reader() || signal.get_frozen()
     }
 }
 
@@ -506,36 +3256,326 @@ impl<RW: QueueRW<T>, T> InnerRecv<RW, T> {
     #[inline(always)]
     pub fn try_recv(&self) -> Result<T, TryRecvError> {
         self.examine_signals();
-        match self.queue.try_recv(&self.reader) {
-            Ok(v) => Ok(v),
+        match self.try_recv_raw() {
+            Ok((_, _, v)) => Ok(v),
             Err((_, e)) => Err(e),
         }
     }
 
     pub fn recv(&self) -> Result<T, RecvError> {
+        self.recv_indexed().map(|(_, v)| v)
+    }
+
+    /// Receives up to max values with one move of the stream. Only valid for mpmc queues
+    pub fn try_recv_batch(&self, max: usize) -> Result<Vec<T>, TryRecvError> {
+        self.examine_signals();
+        let batch = match self.queue.try_recv_batch(&self.reader, max) {
+            Ok(batch) => batch,
+            Err((_, e)) => {
+                if e == TryRecvError::Empty {
+                    self.note_empty();
+                }
+                return Err(e);
+            }
+        };
+        self.note_recv(batch.len());
+        Ok(batch)
+    }
+
+    /// Copies as many waiting values as fit into buf. A stream with a single consumer
+    /// copies them straight out of the ring and moves once, others receive them one at a time
+    pub fn try_recv_slice(&self, buf: &mut [T]) -> Result<usize, TryRecvError>
+    where
+        T: Copy,
+    {
+        self.examine_signals();
+        if self.filter.is_none() && self.reader.is_single() {
+            return match self.queue.try_recv_slice(&self.reader, buf) {
+                Ok(copied) => {
+                    self.note_recv(copied);
+                    Ok(copied)
+                }
+                Err((_, e)) => {
+                    if e == TryRecvError::Empty {
+                        self.note_empty();
+                    }
+                    Err(e)
+                }
+            };
+        }
+        let mut copied = 0;
+        while copied < buf.len() {
+            match self.try_recv_raw() {
+                Ok((_, _, val)) => {
+                    buf[copied] = val;
+                    copied += 1;
+                }
+                Err((_, e)) if copied == 0 => return Err(e),
+                Err(_) => break,
+            }
+        }
+        Ok(copied)
+    }
+
+    /// Stops the writers from waiting on this stream. Nothing may receive
+    /// from the stream until it's resumed
+    pub fn pause(&self) {
+        self.queue.pause(&self.reader)
+    }
+
+    /// Makes the writers wait on this stream again
+    pub fn resume(&self) {
+        self.queue.resume(&self.reader)
+    }
+
+    /// Identical to try_recv, but also returns the sequence number of the value
+    #[inline(always)]
+    pub fn try_recv_indexed(&self) -> Result<(u64, T), TryRecvError> {
+        self.examine_signals();
+        match self.try_recv_raw() {
+            Ok((seq, _, v)) => Ok((seq as u64, v)),
+            Err((_, e)) => Err(e),
+        }
+    }
+
+    /// Identical to recv, but also returns the sequence number of the value
+    pub fn recv_indexed(&self) -> Result<(u64, T), RecvError> {
         self.examine_signals();
         loop {
-            match self.queue.try_recv(&self.reader) {
-                Ok(v) => return Ok(v),
+            match self.try_recv_raw() {
+                Ok((seq, _, v)) => return Ok((seq as u64, v)),
+                Err((_, TryRecvError::Disconnected)) => return Err(RecvError),
+                Err((pt, TryRecvError::Empty)) => {
+                    self.wait_for(pt);
+                }
+            }
+        }
+    }
+
+    /// Identical to try_recv, but also returns how long the value waited in the queue.
+    /// Panics if the queue wasn't created with timestamps
+    #[inline(always)]
+    pub fn try_recv_with_latency(&self) -> Result<(T, Duration), TryRecvError> {
+        let base = self.queue.stamp_base();
+        self.examine_signals();
+        match self.try_recv_raw() {
+            Ok((_, sent_at, v)) => Ok((v, self.queue.latency(base, sent_at))),
+            Err((_, e)) => Err(e),
+        }
+    }
+
+    /// Identical to recv, but also returns how long the value waited in the queue.
+    /// Panics if the queue wasn't created with timestamps
+    pub fn recv_with_latency(&self) -> Result<(T, Duration), RecvError> {
+        let base = self.queue.stamp_base();
+        self.examine_signals();
+        loop {
+            match self.try_recv_raw() {
+                Ok((_, sent_at, v)) => return Ok((v, self.queue.latency(base, sent_at))),
                 Err((_, TryRecvError::Disconnected)) => return Err(RecvError),
                 Err((pt, TryRecvError::Empty)) => {
-                    let count = self.reader.load_count(Relaxed);
-                    unsafe {
-                        self.queue.waiter.wait(count, &*pt, &self.queue.writers);
+                    self.wait_for(pt);
+                }
+            }
+        }
+    }
+
+    /// Identical to recv, except it gives up once the queue's clock reaches deadline.
+    /// Nothing wakes it when the deadline passes, so it polls the stream instead of
+    /// blocking, sleeping for at most DEFAULT_SELECT_MAX_SLEEP_US microseconds at a time
+    pub fn recv_until(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        let mut backoff = Backoff::new();
+        loop {
+            match self.try_recv() {
+                Ok(v) => return Ok(v),
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+                Err(TryRecvError::Empty) => {
+                    let now = self.now();
+                    if now >= deadline {
+                        return Err(RecvTimeoutError::Timeout);
                     }
+                    backoff.snooze(Some(deadline - now));
                 }
             }
         }
     }
 
+    /// Returns the time on the queue's clock
+    pub fn now(&self) -> Instant {
+        self.queue.clock.now()
+    }
+
+    /// Identical to try_recv, except an empty stream also hands back what to wait on
+    /// so that several streams can be waited on at once with ```wait::select_wait```
+    pub fn try_recv_select(&self) -> Result<T, (Option<SelectTarget<'_>>, TryRecvError)> {
+        self.examine_signals();
+        match self.try_recv_raw() {
+            Ok((_, _, v)) => Ok(v),
+            Err((pt, TryRecvError::Empty)) => {
+                let count = self.reader.load_count(RELAXED);
+                let target = unsafe { (count, &*pt, &self.queue.writers) };
+                Err((Some(target), TryRecvError::Empty))
+            }
+            Err((_, e)) => Err((None, e)),
+        }
+    }
+
+    /// Identical to MultiQueue::add_doorbell
+    pub fn add_doorbell(&self, bell: &Sender<()>) -> bool {
+        self.queue.add_doorbell(bell)
+    }
+
+    /// Identical to MultiQueue::remove_doorbell
+    pub fn remove_doorbell(&self, bell: &Sender<()>) {
+        self.queue.remove_doorbell(bell)
+    }
+
     pub fn is_single(&self) -> bool {
         self.reader.get_consumers() == 1
     }
 
+    /// Returns the number of items between this stream and the write head
+    pub fn lag(&self) -> usize {
+        self.queue.lag(&self.reader)
+    }
+
+    /// Drops the values waiting on the stream when this is called, returning how many
+    /// it dropped. Values which other consumers on the stream get to first aren't counted
+    pub fn clear(&self) -> usize {
+        self.examine_signals();
+        let mut cleared = 0;
+        // Every value takes up at least one slot, so this can't go on forever
+        // when the writers keep sending
+        for _ in 0..self.lag() {
+            let got = if RW::do_drop() {
+                // The writers drop broadcast values, so they're only passed over
+                self.try_recv_view_shared_raw(|_| ()).is_ok()
+            } else {
+                self.try_recv_raw().is_ok()
+            };
+            if !got {
+                break;
+            }
+            cleared += 1;
+        }
+        cleared
+    }
+
+    /// Returns the number of receivers on this stream
+    pub fn consumers_on_stream(&self) -> usize {
+        self.reader.get_consumers()
+    }
+
+    /// Stops the writers from sending until unfreeze is called. Sends fail as though
+    /// the queue were full, while receivers go on receiving what's already in the
+    /// queue and then wait for more instead of disconnecting. Sends which already
+    /// claimed their slot are waited for, so everything they sent can be received
+    /// once this returns. A send which checked the signal before it was set but
+    /// hasn't claimed a slot yet can still go in
+    pub fn freeze(&self) {
+        self.queue.freeze()
+    }
+
+    /// Lets the writers send again after freeze
+    pub fn unfreeze(&self) {
+        self.queue.unfreeze()
+    }
+
+    /// Returns whether the queue is frozen
+    pub fn is_frozen(&self) -> bool {
+        self.queue.is_frozen()
+    }
+
+    /// Returns the number of streams subscribed to the queue
+    pub fn stream_count(&self) -> usize {
+        self.queue.stream_count()
+    }
+
+    /// Returns the number of writers subscribed to the queue
+    pub fn writer_count(&self) -> usize {
+        self.queue.writer_count()
+    }
+
+    /// Returns how many values the queue holds
+    pub fn capacity(&self) -> usize {
+        self.queue.capacity()
+    }
+
+    /// Identical to InnerSend::shared_queue()
+    pub(crate) fn shared_queue(&self) -> Arc<MultiQueue<RW, T>> {
+        self.queue.clone()
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        self.queue.stats()
+    }
+
+    pub fn memory_footprint(&self) -> MemoryFootprint {
+        self.queue.memory_footprint()
+    }
+
+    #[cfg(feature = "test-util")]
+    pub fn check_invariants(&self) -> InvariantReport {
+        self.queue.check_invariants()
+    }
+
+    #[cfg(feature = "fault_injection")]
+    pub fn inject_faults(&self, config: FaultConfig) {
+        self.queue.inject_faults(config)
+    }
+
+    #[cfg(feature = "registry")]
+    pub fn set_label(&self, label: Cow<'static, str>) {
+        self.queue.set_label(label)
+    }
+
+    #[cfg(feature = "stats")]
+    pub fn reset_high_water(&self) -> usize {
+        self.queue.reset_high_water()
+    }
+
+    /// Returns the most items this stream may fall behind, or zero if it's unbounded
+    pub fn max_lag(&self) -> usize {
+        self.reader.max_lag()
+    }
+
+    /// Identical to try_recv, except the stream must be bounded and
+    /// reports the number of items it was skipped past
+    pub fn try_recv_bounded(&self) -> Result<T, LaggedTryRecvError> {
+        self.examine_signals();
+        let skipped = self.queue.take_lagged(&self.reader);
+        if skipped != 0 {
+            return Err(LaggedTryRecvError::Lagged(skipped as u64));
+        }
+        match self.try_recv_raw() {
+            Ok((_, _, v)) => Ok(v),
+            Err((_, e)) => Err(e.into()),
+        }
+    }
+
+    /// Identical to recv, except the stream must be bounded and
+    /// reports the number of items it was skipped past
+    pub fn recv_bounded(&self) -> Result<T, LaggedRecvError> {
+        self.examine_signals();
+        loop {
+            let skipped = self.queue.take_lagged(&self.reader);
+            if skipped != 0 {
+                return Err(LaggedRecvError::Lagged(skipped as u64));
+            }
+            match self.try_recv_raw() {
+                Ok((_, _, v)) => return Ok(v),
+                Err((_, TryRecvError::Disconnected)) => return Err(LaggedRecvError::Disconnected),
+                Err((pt, TryRecvError::Empty)) => {
+                    self.wait_for(pt);
+                }
+            }
+        }
+    }
+
     #[inline(always)]
     pub fn try_recv_view<R, F: FnOnce(&T) -> R>(&self, op: F) -> Result<R, (F, TryRecvError)> {
         self.examine_signals();
-        match self.queue.try_recv_view(op, &self.reader) {
+        match self.try_recv_view_raw(op) {
             Ok(v) => Ok(v),
             Err((op, _, e)) => Err((op, e)),
         }
@@ -544,35 +3584,341 @@ impl<RW: QueueRW<T>, T> InnerRecv<RW, T> {
     pub fn recv_view<R, F: FnOnce(&T) -> R>(&self, mut op: F) -> Result<R, (F, RecvError)> {
         self.examine_signals();
         loop {
-            match self.queue.try_recv_view(op, &self.reader) {
+            match self.try_recv_view_raw(op) {
                 Ok(v) => return Ok(v),
                 Err((o, _, TryRecvError::Disconnected)) => return Err((o, RecvError)),
                 Err((o, pt, TryRecvError::Empty)) => {
                     op = o;
-                    let count = self.reader.load_count(Relaxed);
-                    unsafe {
-                        self.queue.waiter.wait(count, &*pt, &self.queue.writers);
-                    }
+                    self.wait_for(pt);
+                }
+            }
+        }
+    }
+
+    /// Identical to try_recv_view, except the value is borrowed through the returned guard.
+    /// Nothing else may receive from the stream while the guard is alive
+    pub fn try_recv_guard(&self) -> Result<RecvGuard<'_, T>, TryRecvError> {
+        self.examine_signals();
+        self.try_recv_guard_raw().map_err(|(_, e)| e)
+    }
+
+    /// Identical to recv_view, except the value is borrowed through the returned guard.
+    /// Nothing else may receive from the stream while the guard is alive
+    pub fn recv_guard(&self) -> Result<RecvGuard<'_, T>, RecvError> {
+        self.examine_signals();
+        loop {
+            match self.try_recv_guard_raw() {
+                Ok(guard) => return Ok(guard),
+                Err((_, TryRecvError::Disconnected)) => return Err(RecvError),
+                Err((pt, TryRecvError::Empty)) => self.wait_for(pt),
+            }
+        }
+    }
+
+    /// Identical to try_recv_view, except it's fine for the stream to have other consumers.
+    /// Only valid for broadcast queues
+    #[inline(always)]
+    pub fn try_recv_view_shared<R, F: FnOnce(&T) -> R>(
+        &self,
+        op: F,
+    ) -> Result<R, (F, TryRecvError)> {
+        self.examine_signals();
+        match self.try_recv_view_shared_raw(op) {
+            Ok(v) => Ok(v),
+            Err((op, _, e)) => Err((op, e)),
+        }
+    }
+
+    /// Identical to recv_view, except it's fine for the stream to have other consumers.
+    /// Only valid for broadcast queues
+    pub fn recv_view_shared<R, F: FnOnce(&T) -> R>(&self, mut op: F) -> Result<R, (F, RecvError)> {
+        self.examine_signals();
+        loop {
+            match self.try_recv_view_shared_raw(op) {
+                Ok(v) => return Ok(v),
+                Err((o, _, TryRecvError::Disconnected)) => return Err((o, RecvError)),
+                Err((o, pt, TryRecvError::Empty)) => {
+                    op = o;
+                    self.wait_for(pt);
                 }
             }
         }
     }
 
-    pub fn add_stream(&self) -> InnerRecv<RW, T> {
-        InnerRecv {
-            queue: self.queue.clone(),
-            reader: self
-                .queue
-                .tail
-                .add_stream(&self.reader, &self.queue.manager),
-            token: self.queue.manager.get_token(),
-            alive: true,
+    pub fn add_stream(&self) -> InnerRecv<RW, T> {
+        let reader = self
+            .queue
+            .tail
+            .add_stream(&self.reader, None, &self.queue.manager);
+        self.with_reader(reader)
+    }
+
+    /// Identical to add_stream, but returns an error if the queue holds
+    /// a fixed number of streams and they're all taken
+    pub fn try_add_stream(&self) -> Result<InnerRecv<RW, T>, StreamsFullError> {
+        let reader = self
+            .queue
+            .tail
+            .try_add_stream(&self.reader, None, &self.queue.manager)?;
+        Ok(self.with_reader(reader))
+    }
+
+    /// Identical to add_stream, but the new stream carries the passed label
+    pub fn add_stream_named(&self, name: Cow<'static, str>) -> InnerRecv<RW, T> {
+        let reader = self
+            .queue
+            .tail
+            .add_stream(&self.reader, Some(name), &self.queue.manager);
+        self.with_reader(reader)
+    }
+
+    /// Identical to clone, but the new receiver carries the passed label
+    pub fn clone_named(&self, name: Cow<'static, str>) -> InnerRecv<RW, T> {
+        let mut recv = self.clone();
+        recv.name = Some(name);
+        recv
+    }
+
+    /// Returns the label of this receiver, if any
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Returns the label of this receiver's stream, if any
+    pub fn stream_name(&self) -> Option<&str> {
+        self.reader.stream_name()
+    }
+
+    /// Adds a stream at the same spot as this one which gets skipped
+    /// forwards once it falls max_lag items behind the writers
+    pub fn add_bounded_stream(&self, max_lag: usize) -> InnerRecv<RW, T> {
+        self.with_reader(self.queue.add_bounded_stream(&self.reader, max_lag))
+    }
+
+    /// Identical to add_bounded_stream, but the new stream starts at the head of the queue
+    pub fn add_bounded_stream_from_latest(&self, max_lag: usize) -> InnerRecv<RW, T> {
+        self.with_reader(self.queue.add_bounded_stream_from_latest(max_lag))
+    }
+
+    /// Adds a stream starting at the oldest item still held in the queue
+    pub fn add_stream_from_earliest(&self) -> InnerRecv<RW, T> {
+        self.with_reader(self.queue.add_stream_from_earliest())
+    }
+
+    /// Adds a stream starting at the head of the queue
+    pub fn add_stream_from_latest(&self) -> InnerRecv<RW, T> {
+        self.with_reader(self.queue.add_stream_from_latest())
+    }
+
+    /// Adds a stream at the same spot as this one which only receives values passing keep
+    pub fn add_stream_filtered<P: Fn(&T) -> bool + Send + Sync + 'static>(
+        &self,
+        keep: P,
+    ) -> InnerRecv<RW, T> {
+        let mut recv = self.add_stream();
+        recv.filter = Some(Arc::new(keep));
+        recv
+    }
+
+    /// Adds a stream at the same spot as this one which only receives every nth value.
+    /// The sample is taken with a filter counting the values it looks at, so consumers
+    /// racing for the same value on the stream can throw the count off
+    pub fn add_stream_sampled(&self, n: usize) -> InnerRecv<RW, T> {
+        assert!(n > 0, "Multiqueue error - zero sample interval received");
+        let seen = AtomicUsize::new(0);
+        self.add_stream_filtered(move |_| seen.fetch_add(1, RELAXED).is_multiple_of(n))
+    }
+
+    /// Returns clones of everything this stream has yet to receive without receiving it.
+    /// Only valid for broadcast queues
+    pub fn snapshot(&self) -> Vec<T>
+    where
+        T: Clone,
+    {
+        match self.filter {
+            None => self.queue.snapshot(&self.reader, |_| true),
+            Some(ref keep) => self.queue.snapshot(&self.reader, |v| keep(v)),
+        }
+    }
+
+    /// Identical to InnerSend::dump()
+    pub fn dump(&self) -> Vec<(u64, T)>
+    where
+        T: Clone,
+    {
+        self.queue.dump()
+    }
+
+    /// Takes the stream off the queue, returning where it was so it can be attached
+    /// again later. Only valid when this is the only consumer on the stream
+    /// and there's another stream left on the queue
+    pub fn detach(self) -> ReaderToken<RW, T> {
+        ReaderToken {
+            queue: Arc::downgrade(&self.queue),
+            pos: self.reader.load_count(SEQ_CST),
+            max_lag: self.reader.max_lag(),
+            stream_name: self.stream_name().map(|n| Cow::Owned(n.to_owned())),
+            filter: self.filter.clone(),
+            name: self.name.clone(),
+        }
+    }
+
+    /// Adds the detached stream back to the queue at the same spot, if it was detached
+    /// from this queue and nothing it hadn't received has been overwritten since
+    pub fn attach(&self, token: ReaderToken<RW, T>) -> Result<InnerRecv<RW, T>, AttachError> {
+        if !ptr::addr_eq(token.queue.as_ptr(), Arc::as_ptr(&self.queue)) {
+            return Err(AttachError::WrongQueue);
+        }
+        let reader = self
+            .queue
+            .attach_stream(token.pos, token.max_lag, token.stream_name)?;
+        let mut recv = self.with_reader(reader);
+        recv.filter = token.filter;
+        recv.name = token.name;
+        Ok(recv)
+    }
+
+    fn with_reader(&self, reader: Reader) -> InnerRecv<RW, T> {
+        #[cfg(feature = "tracing")]
+        self.queue
+            .trace
+            .stream_added(reader.stream_name(), self.queue.stream_count());
+        InnerRecv {
+            queue: self.queue.clone(),
+            reader,
+            token: self.queue.manager.get_token(),
+            filter: self.filter.clone(),
+            name: None,
+            alive: true,
+        }
+    }
+
+    /// Counts values received from the stream
+    #[inline(always)]
+    fn note_recv(&self, _count: usize) {
+        #[cfg(feature = "stats")]
+        self.reader.add_received(_count);
+        #[cfg(feature = "metrics")]
+        if let Some(ref metrics) = self.queue.metrics {
+            for _ in 0.._count {
+                metrics.on_recv();
+            }
+            self.queue.note_taken(&**metrics);
+        }
+    }
+
+    /// Called when the stream has nothing to receive
+    #[inline(always)]
+    fn note_empty(&self) {
+        #[cfg(feature = "metrics")]
+        if let Some(ref metrics) = self.queue.metrics {
+            self.queue.edge_empty(&**metrics, &self.reader);
+        }
+    }
+
+    /// Blocks until there may be something to receive at pt
+    fn wait_for(&self, pt: *const AtomicUsize) {
+        if let Some(delay) = self.queue.pending_delay(&self.reader) {
+            // Nobody gets notified when a deadline passes, and somebody else
+            // on the stream may take the value, so this only sleeps for a bit
+            sleep(delay.min(Duration::from_millis(1)));
+            return;
+        }
+        #[cfg(feature = "tracing")]
+        self.queue.trace.empty();
+        #[cfg(feature = "metrics")]
+        self.queue.report(|m| m.on_empty_wait());
+        let count = self.reader.load_count(RELAXED);
+        self.queue.start_waiting();
+        unsafe {
+            self.queue.waiter.wait(count, &*pt, &self.queue.writers);
+        }
+        self.queue.stop_waiting();
+    }
+
+    /// Counts a futures receiver as waiting until it next gets something, since its
+    /// task parks and returns rather than staying in wait. Dropping the receiver while
+    /// it's parked stops counting it, or writers would notify for good
+    fn park_waiting(&self, waiting: &Cell<bool>) {
+        if !waiting.replace(true) {
+            self.queue.start_waiting();
+        }
+    }
+
+    /// Stops counting a futures receiver from park_waiting
+    fn unpark_waiting(&self, waiting: &Cell<bool>) {
+        if waiting.replace(false) {
+            self.queue.stop_waiting();
+        }
+    }
+
+    #[inline(always)]
+    fn try_recv_raw(&self) -> Result<(usize, u64, T), (*const AtomicUsize, TryRecvError)> {
+        let rval = match self.filter {
+            None => self.queue.try_recv_indexed(&self.reader),
+            Some(ref keep) => self.queue.try_recv_where(&self.reader, |v| keep(v)),
+        };
+        match rval {
+            Ok(_) => self.note_recv(1),
+            Err((.., TryRecvError::Empty)) => self.note_empty(),
+            Err(_) => (),
+        }
+        rval
+    }
+
+    #[inline(always)]
+    fn try_recv_view_shared_raw<R, F: FnOnce(&T) -> R>(
+        &self,
+        op: F,
+    ) -> Result<R, (F, *const AtomicUsize, TryRecvError)> {
+        let rval = match self.filter {
+            None => self.queue.try_recv_view_shared(op, &self.reader, |_| true),
+            Some(ref keep) => self
+                .queue
+                .try_recv_view_shared(op, &self.reader, |v| keep(v)),
+        };
+        match rval {
+            Ok(_) => self.note_recv(1),
+            Err((.., TryRecvError::Empty)) => self.note_empty(),
+            Err(_) => (),
+        }
+        rval
+    }
+
+    #[inline(always)]
+    fn try_recv_view_raw<R, F: FnOnce(&T) -> R>(
+        &self,
+        op: F,
+    ) -> Result<R, (F, *const AtomicUsize, TryRecvError)> {
+        let rval = match self.filter {
+            None => self.queue.try_recv_view(op, &self.reader, |_| true),
+            Some(ref keep) => self.queue.try_recv_view(op, &self.reader, |v| keep(v)),
+        };
+        match rval {
+            Ok(_) => self.note_recv(1),
+            Err((.., TryRecvError::Empty)) => self.note_empty(),
+            Err(_) => (),
+        }
+        rval
+    }
+
+    #[inline(always)]
+    fn try_recv_guard_raw(&self) -> Result<RecvGuard<'_, T>, (*const AtomicUsize, TryRecvError)> {
+        let rval = match self.filter {
+            None => self.queue.try_recv_guard(&self.reader, |_| true),
+            Some(ref keep) => self.queue.try_recv_guard(&self.reader, |v| keep(v)),
+        };
+        match rval {
+            Ok(_) => self.note_recv(1),
+            Err((_, TryRecvError::Empty)) => self.note_empty(),
+            Err(_) => (),
         }
+        rval
     }
 
     #[inline(always)]
     fn examine_signals(&self) {
-        let signal = self.queue.manager.signal.load(Relaxed);
+        let signal = self.queue.manager.signal.load(RELAXED);
         if signal.has_action() {
             self.handle_signals(signal);
         }
@@ -594,27 +3940,308 @@ impl<RW: QueueRW<T>, T> InnerRecv<RW, T> {
         if self.alive {
             self.alive = false;
             if self.reader.remove_consumer() == 1 {
+                #[cfg(feature = "tracing")]
+                self.queue.trace.stream_removed(self.reader.stream_name());
                 if self
                     .queue
                     .tail
                     .remove_reader(&self.reader, &self.queue.manager)
                 {
-                    self.queue.manager.signal.set_reader(SeqCst);
+                    self.queue.manager.signal.set_reader(SEQ_CST);
+                    #[cfg(feature = "tracing")]
+                    self.queue.trace.readers_gone();
+                    #[cfg(feature = "metrics")]
+                    self.queue.report(|m| m.on_disconnect());
                 }
-                self.queue.manager.remove_token(self.token);
             }
-            fence(SeqCst);
+            // Every consumer has its own token, not just the last one on the stream
+            self.queue.manager.remove_token(self.token);
+            fence(SEQ_CST);
             f()
         }
     }
 }
 
+type IntoMPMCResult<T> = Result<
+    (InnerSend<MPMC<T>, T>, InnerRecv<MPMC<T>, T>),
+    (InnerSend<BCast<T>, T>, InnerRecv<BCast<T>, T>),
+>;
+
+impl<T: Clone> InnerRecv<BCast<T>, T> {
+    /// Turns this receiver and the passed sender into handles for the same queue
+    /// read as an mpmc queue, so values are moved out instead of cloned. This only
+    /// works when they're the only handles the queue has, with no detached streams,
+    /// and the stream is neither bounded nor paused. Otherwise both are handed back
+    // Both handles are handed back whole on failure, same as into_single does with one
+    #[allow(clippy::result_large_err)]
+    pub fn into_mpmc(self, sender: InnerSend<BCast<T>, T>) -> IntoMPMCResult<T> {
+        let alone = Arc::ptr_eq(&self.queue, &sender.queue)
+            && Arc::strong_count(&self.queue) == 2
+            && Arc::weak_count(&self.queue) == 0;
+        if !alone || self.reader.is_bounded() || self.reader.is_paused() {
+            return Err((sender, self));
+        }
+        fence(ACQUIRE);
+        // Nothing else can reach the queue, so nothing is halfway through a send or receive
+        unsafe {
+            self.queue.drop_read(self.reader.load_count(RELAXED));
+            let sender = ManuallyDrop::new(sender);
+            let recv = ManuallyDrop::new(self);
+            Ok((
+                InnerSend {
+                    queue: as_mpmc(ptr::read(&sender.queue)),
+                    token: sender.token,
+                    state: ptr::read(&sender.state),
+                    #[cfg(feature = "order_checks")]
+                    sent: ptr::read(&sender.sent),
+                },
+                InnerRecv {
+                    queue: as_mpmc(ptr::read(&recv.queue)),
+                    reader: ptr::read(&recv.reader),
+                    token: recv.token,
+                    filter: ptr::read(&recv.filter),
+                    name: ptr::read(&recv.name),
+                    alive: recv.alive,
+                },
+            ))
+        }
+    }
+}
+
+impl<T: Clone> MultiQueue<BCast<T>, T> {
+    /// Drops the values which have already been read by a stream at pos.
+    /// Broadcast queues leave them for writers to drop when they're overwritten,
+    /// where mpmc queues expect readers to have taken them
+    unsafe fn drop_read(&self, pos: usize) {
+        for i in 0..self.capacity as usize {
+            let cell = &*self.data.add(i);
+            let tag = cell.wraps.load(RELAXED);
+            if is_tagged(tag) {
+                continue;
+            }
+            let (diff, ahead) = past(pos, tag);
+            if !ahead && diff != 0 {
+                ptr::drop_in_place(cell.val.get());
+            }
+        }
+    }
+}
+
+/// Reads a broadcast queue as an mpmc one. The queue is repr(C) and only
+/// holds RW in a PhantomData, so both have the same layout
+unsafe fn as_mpmc<T: Clone>(queue: Arc<MultiQueue<BCast<T>, T>>) -> Arc<MultiQueue<MPMC<T>, T>> {
+    Arc::from_raw(Arc::into_raw(queue) as *const MultiQueue<MPMC<T>, T>)
+}
+
+impl<T> MultiQueue<MPMC<T>, T> {
+    /// Views the next value in place on a stream which may have several consumers.
+    /// Unlike try_recv_where, which reads the value out and forgets it if another
+    /// consumer claims it first, the slot is held with its refcount before the stream
+    /// is moved past it. Once the claim goes through no other consumer can get to
+    /// the value, and writers can't reuse the slot until op is done and it's dropped
+    pub fn try_recv_view_claimed<R, F: FnOnce(&T) -> R>(
+        &self,
+        op: F,
+        reader: &Reader,
+    ) -> Result<R, (F, *const AtomicUsize, TryRecvError)> {
+        if !self.claimed_views.load(RELAXED) {
+            // Made visible to writers by the fence in holding the slot,
+            // which comes before they could see the stream past it
+            self.claimed_views.store(true, RELAXED);
+        }
+        let mut ctail_attempt = reader.load_attempt(RELAXED);
+        unsafe {
+            loop {
+                let (ctail, wrap_valid_tag) = ctail_attempt.get();
+                let read_cell = &*self.data.offset(ctail);
+                let seen_tag = rm_tag(read_cell.wraps.load(DepOrd));
+                if seen_tag != wrap_valid_tag {
+                    if self.writers.load(RELAXED) == 0 {
+                        fence(ACQUIRE);
+                        if rm_tag(read_cell.wraps.load(ACQUIRE)) != wrap_valid_tag {
+                            return Err((op, ptr::null(), TryRecvError::Disconnected));
+                        }
+                    }
+                    return Err((op, &read_cell.wraps, TryRecvError::Empty));
+                }
+                let ref_cell = &*self.refs.offset(ctail);
+                if !self.is_ready(ctail as usize) {
+                    return Err((op, &read_cell.wraps, TryRecvError::Empty));
+                }
+                ref_cell
+                    .refcnt
+                    .fetch_add(1, atomic_utilities::fence_rmw::RMWOrder);
+                atomic_utilities::fence_rmw::fence_rmw();
+                // Writers can only get to the slot once the stream is past it,
+                // so if it hasn't moved yet they'll see the slot held
+                if reader.load_count(RELAXED) != wrap_valid_tag {
+                    ref_cell.refcnt.fetch_sub(1, RELAXED);
+                    ctail_attempt = ctail_attempt.reload();
+                    continue;
+                }
+                if let Some(new_attempt) = ctail_attempt.commit_attempt(1, RELEASE) {
+                    ref_cell.refcnt.fetch_sub(1, RELAXED);
+                    ctail_attempt = new_attempt;
+                    continue;
+                }
+                // The slot is given back even if op or a drop panics,
+                // or writers could never get past it again
+                let _held = OnDrop::new(|| {
+                    fence(RELEASE);
+                    ref_cell.refcnt.fetch_sub(1, RELAXED);
+                });
+                let rv_ptr = read_cell.val_after(seen_tag);
+                let replaced = self.is_superseded(ref_cell, wrap_valid_tag);
+                let expired = !replaced && self.is_expired(ctail as usize);
+                if replaced || expired {
+                    self.release_weight(&*rv_ptr);
+                    self.discard_in_place(rv_ptr, expired);
+                    ctail_attempt = reader.load_attempt(RELAXED);
+                    continue;
+                }
+                // This consumer owns the value now, so it's dropped once op is done
+                let _taken = OnDrop::new(|| {
+                    self.release_weight(&*rv_ptr);
+                    ptr::drop_in_place(rv_ptr);
+                });
+                #[cfg(feature = "order_checks")]
+                reader.check_order(wrap_valid_tag);
+                return Ok(op(&*rv_ptr));
+            }
+        }
+    }
+}
+
+impl<T> InnerRecv<MPMC<T>, T> {
+    /// Views the next value in place on an mpmc stream which may have other consumers.
+    /// The slot's refcount is taken before the stream is moved past it, and the
+    /// cursor is only committed if the stream is still at the slot afterwards, so
+    /// once the claim goes through no other consumer can get the value and writers
+    /// see the slot held until op is done and the value is dropped.
+    ///
+    /// The first call sets a flag on the queue which is never cleared. From then on
+    /// every writer checks the refcount of each slot before reusing it, which mpmc
+    /// queues otherwise skip, and a slow op stalls every writer once the ring wraps
+    #[inline(always)]
+    pub fn try_recv_view_claimed<R, F: FnOnce(&T) -> R>(
+        &self,
+        op: F,
+    ) -> Result<R, (F, TryRecvError)> {
+        self.examine_signals();
+        match self.try_recv_view_claimed_raw(op) {
+            Ok(v) => Ok(v),
+            Err((op, _, e)) => Err((op, e)),
+        }
+    }
+
+    /// Identical to try_recv_view_claimed, but blocks until a value can be viewed
+    pub fn recv_view_claimed<R, F: FnOnce(&T) -> R>(&self, mut op: F) -> Result<R, (F, RecvError)> {
+        self.examine_signals();
+        loop {
+            match self.try_recv_view_claimed_raw(op) {
+                Ok(v) => return Ok(v),
+                Err((o, _, TryRecvError::Disconnected)) => return Err((o, RecvError)),
+                Err((o, pt, TryRecvError::Empty)) => {
+                    op = o;
+                    self.wait_for(pt);
+                }
+            }
+        }
+    }
+
+    #[inline(always)]
+    fn try_recv_view_claimed_raw<R, F: FnOnce(&T) -> R>(
+        &self,
+        op: F,
+    ) -> Result<R, (F, *const AtomicUsize, TryRecvError)> {
+        // Mpmc streams are never filtered
+        let rval = self.queue.try_recv_view_claimed(op, &self.reader);
+        match rval {
+            Ok(_) => self.note_recv(1),
+            Err((.., TryRecvError::Empty)) => self.note_empty(),
+            Err(_) => (),
+        }
+        rval
+    }
+}
+
 impl<RW: QueueRW<T>, T> FutInnerSend<RW, T> {
+    /// Changes how many times everything on the queue spins and then
+    /// yields before parking, for the senders and receivers alike
+    pub fn set_spins(&self, try_spins: usize, yield_spins: usize) {
+        self.wait.set_spins(try_spins, yield_spins);
+        self.prod_wait.set_spins(try_spins, yield_spins);
+    }
+
     /// Identical to InnerSend::try_send()
-    pub fn try_send(&self, val: T) -> Result<(), TrySendError<T>> {
+    pub fn try_send(&self, val: T) -> Result<(), FrozenTrySendError<T>> {
         self.writer.try_send(val)
     }
 
+    /// Wakes the receivers parked on the queue, for when something
+    /// besides a value coming in can change what they'd find
+    pub fn notify_readers(&self) {
+        self.wait.notify();
+    }
+
+    /// Wakes the receivers for the values start_send has written since this was last called
+    fn notify_unflushed(&self) {
+        if self.unflushed.load(RELAXED) && self.unflushed.swap(false, RELAXED) {
+            self.writer.queue.notify();
+        }
+    }
+
+    /// Identical to InnerSend::max_lag()
+    pub fn max_lag(&self) -> usize {
+        self.writer.max_lag()
+    }
+
+    /// Identical to InnerSend::freeze()
+    pub fn freeze(&self) {
+        self.writer.freeze()
+    }
+
+    /// Identical to InnerSend::unfreeze(), and wakes futures
+    /// senders which parked while the queue was frozen
+    pub fn unfreeze(&self) {
+        self.writer.unfreeze();
+        self.prod_wait.notify_all();
+    }
+
+    /// Identical to InnerSend::is_frozen()
+    pub fn is_frozen(&self) -> bool {
+        self.writer.is_frozen()
+    }
+
+    /// Identical to InnerSend::stream_count()
+    pub fn stream_count(&self) -> usize {
+        self.writer.stream_count()
+    }
+
+    /// Identical to InnerSend::stream_lags()
+    pub fn stream_lags(&self) -> Vec<(Option<String>, usize)> {
+        self.writer.stream_lags()
+    }
+
+    /// Identical to InnerSend::stalled_streams()
+    pub fn stalled_streams(&self, threshold: Duration) -> Vec<(Option<String>, Duration)> {
+        self.writer.stalled_streams(threshold)
+    }
+
+    /// Identical to InnerSend::writer_count()
+    pub fn writer_count(&self) -> usize {
+        self.writer.writer_count()
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        self.writer.stats()
+    }
+
+    #[cfg(feature = "stats")]
+    pub fn reset_high_water(&self) -> usize {
+        self.writer.reset_high_water()
+    }
+
     /// Identical to InnerSend::unsubscribe()
     pub fn unsubscribe(self) {
         self.writer.unsubscribe()
@@ -624,6 +4251,12 @@ impl<RW: QueueRW<T>, T> FutInnerSend<RW, T> {
 type IntoSingleResult<RW, R, F, T> = Result<FutInnerUniRecv<RW, R, F, T>, (F, FutInnerRecv<RW, T>)>;
 
 impl<RW: QueueRW<T>, T> FutInnerRecv<RW, T> {
+    /// Identical to FutInnerSend::set_spins()
+    pub fn set_spins(&self, try_spins: usize, yield_spins: usize) {
+        self.wait.set_spins(try_spins, yield_spins);
+        self.prod_wait.set_spins(try_spins, yield_spins);
+    }
+
     /// Identical to InnerRecv::try_recv()
     #[inline(always)]
     pub fn try_recv(&self) -> Result<T, TryRecvError> {
@@ -635,18 +4268,154 @@ impl<RW: QueueRW<T>, T> FutInnerRecv<RW, T> {
         self.reader.recv()
     }
 
+    /// Identical to InnerRecv::try_recv_indexed()
+    #[inline(always)]
+    pub fn try_recv_indexed(&self) -> Result<(u64, T), TryRecvError> {
+        self.reader.try_recv_indexed()
+    }
+
+    /// Identical to InnerRecv::try_recv_with_latency()
+    #[inline(always)]
+    pub fn try_recv_with_latency(&self) -> Result<(T, Duration), TryRecvError> {
+        self.reader.try_recv_with_latency()
+    }
+
+    /// Identical to InnerRecv::recv_with_latency()
+    pub fn recv_with_latency(&self) -> Result<(T, Duration), RecvError> {
+        self.reader.recv_with_latency()
+    }
+
+    /// Identical to InnerRecv::recv_indexed()
+    #[inline(always)]
+    pub fn recv_indexed(&self) -> Result<(u64, T), RecvError> {
+        self.reader.recv_indexed()
+    }
+
+    /// Identical to InnerRecv::lag()
+    pub fn lag(&self) -> usize {
+        self.reader.lag()
+    }
+
+    /// Identical to InnerRecv::clear(), except it also wakes writers waiting for room
+    pub fn clear(&self) -> usize {
+        let cleared = self.reader.clear();
+        if cleared != 0 {
+            self.prod_wait.notify_all();
+        }
+        cleared
+    }
+
+    /// Identical to InnerRecv::consumers_on_stream()
+    pub fn consumers_on_stream(&self) -> usize {
+        self.reader.consumers_on_stream()
+    }
+
+    /// Identical to InnerRecv::freeze()
+    pub fn freeze(&self) {
+        self.reader.freeze()
+    }
+
+    /// Identical to InnerRecv::unfreeze(), and wakes futures
+    /// senders which parked while the queue was frozen
+    pub fn unfreeze(&self) {
+        self.reader.unfreeze();
+        self.prod_wait.notify_all();
+    }
+
+    /// Identical to InnerRecv::is_frozen()
+    pub fn is_frozen(&self) -> bool {
+        self.reader.is_frozen()
+    }
+
+    /// Identical to InnerRecv::stream_count()
+    pub fn stream_count(&self) -> usize {
+        self.reader.stream_count()
+    }
+
+    /// Identical to InnerRecv::writer_count()
+    pub fn writer_count(&self) -> usize {
+        self.reader.writer_count()
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        self.reader.stats()
+    }
+
+    #[cfg(feature = "stats")]
+    pub fn reset_high_water(&self) -> usize {
+        self.reader.reset_high_water()
+    }
+
     /// Creates a new stream and returns a FutInnerRecv on that stream
     pub fn add_stream(&self) -> FutInnerRecv<RW, T> {
-        let rx = self.reader.add_stream();
+        self.with_reader(self.reader.add_stream())
+    }
+
+    /// Identical to InnerRecv::snapshot()
+    pub fn snapshot(&self) -> Vec<T>
+    where
+        T: Clone,
+    {
+        self.reader.snapshot()
+    }
+
+    /// Identical to InnerRecv::add_stream_named()
+    pub fn add_stream_named(&self, name: Cow<'static, str>) -> FutInnerRecv<RW, T> {
+        self.with_reader(self.reader.add_stream_named(name))
+    }
+
+    /// Identical to InnerRecv::clone_named()
+    pub fn clone_named(&self, name: Cow<'static, str>) -> FutInnerRecv<RW, T> {
+        self.with_reader(self.reader.clone_named(name))
+    }
+
+    /// Identical to InnerRecv::name()
+    pub fn name(&self) -> Option<&str> {
+        self.reader.name()
+    }
+
+    /// Identical to InnerRecv::stream_name()
+    pub fn stream_name(&self) -> Option<&str> {
+        self.reader.stream_name()
+    }
+
+    /// Identical to InnerRecv::add_stream_filtered()
+    pub fn add_stream_filtered<P: Fn(&T) -> bool + Send + Sync + 'static>(
+        &self,
+        keep: P,
+    ) -> FutInnerRecv<RW, T> {
+        self.with_reader(self.reader.add_stream_filtered(keep))
+    }
+
+    /// Identical to InnerRecv::add_stream_sampled()
+    pub fn add_stream_sampled(&self, n: usize) -> FutInnerRecv<RW, T> {
+        self.with_reader(self.reader.add_stream_sampled(n))
+    }
+
+    /// Identical to InnerRecv::add_stream_from_earliest()
+    pub fn add_stream_from_earliest(&self) -> FutInnerRecv<RW, T> {
+        self.with_reader(self.reader.add_stream_from_earliest())
+    }
+
+    /// Identical to InnerRecv::add_stream_from_latest()
+    pub fn add_stream_from_latest(&self) -> FutInnerRecv<RW, T> {
+        self.with_reader(self.reader.add_stream_from_latest())
+    }
+
+    fn with_reader(&self, reader: InnerRecv<RW, T>) -> FutInnerRecv<RW, T> {
         FutInnerRecv {
-            reader: rx,
+            reader,
             wait: self.wait.clone(),
             prod_wait: self.prod_wait.clone(),
+            waiting: Cell::new(false),
         }
     }
 
     /// Attempts to transform this receiver into a FutInnerUniRecv
     /// calling the passed function on the input data.
+    // Only builds with order_checks, whose per-stream checker makes the receiver
+    // this large, go over clippy's limit, and the receiver is handed back whole
+    #[cfg_attr(feature = "order_checks", allow(clippy::result_large_err))]
     pub fn into_single<R, F: FnMut(&T) -> R>(self, op: F) -> IntoSingleResult<RW, R, F, T> {
         let new_mreader;
         let new_pwait = self.prod_wait.clone();
@@ -660,6 +4429,7 @@ impl<RW: QueueRW<T>, T> FutInnerRecv<RW, T> {
                 reader: new_mreader,
                 wait: new_wait,
                 prod_wait: new_pwait,
+                waiting: Cell::new(false),
                 op,
             })
         } else {
@@ -669,6 +4439,7 @@ impl<RW: QueueRW<T>, T> FutInnerRecv<RW, T> {
                     reader: new_mreader,
                     wait: new_wait,
                     prod_wait: new_pwait,
+                    waiting: Cell::new(false),
                 },
             ))
         }
@@ -704,6 +4475,11 @@ impl<RW: QueueRW<T>, R, F: FnMut(&T) -> R, T> FutInnerUniRecv<RW, R, F, T> {
         rval.map_err(|x| x.1)
     }
 
+    /// Identical to InnerRecv::lag()
+    pub fn lag(&self) -> usize {
+        self.reader.lag()
+    }
+
     /// Adds another stream to the queue with a FutInnerUniRecv using the passed function
     pub fn add_stream_with<Q, FQ: FnMut(&T) -> Q>(&self, op: FQ) -> FutInnerUniRecv<RW, Q, FQ, T> {
         let rx = self.reader.add_stream();
@@ -711,6 +4487,7 @@ impl<RW: QueueRW<T>, R, F: FnMut(&T) -> R, T> FutInnerUniRecv<RW, R, F, T> {
             reader: rx,
             wait: self.wait.clone(),
             prod_wait: self.prod_wait.clone(),
+            waiting: Cell::new(false),
             op,
         }
     }
@@ -726,6 +4503,7 @@ impl<RW: QueueRW<T>, R, F: FnMut(&T) -> R, T> FutInnerUniRecv<RW, R, F, T> {
             reader: new_reader,
             wait: self.wait.clone(),
             prod_wait: self.prod_wait.clone(),
+            waiting: Cell::new(false),
         }
     }
 }
@@ -736,17 +4514,30 @@ impl<RW: QueueRW<T>, T> Sink for &FutInnerSend<RW, T> {
     type SinkItem = T;
     type SinkError = SendError<T>;
 
-    /// Essentially try_send except parks if the queue is full
+    /// Essentially try_send except parks if the queue is full. The receivers
+    /// aren't woken until poll_complete, so forwarding a stream with send_all
+    /// wakes them once per batch instead of once per value
     fn start_send(&mut self, msg: T) -> StartSend<T, SendError<T>> {
-        match self
-            .prod_wait
-            .send_or_park(|m| self.writer.try_send(m), msg)
-        {
-            Ok(_) => {
-                // see InnerSend::try_recv for why this isn't in the queue
-                if self.writer.queue.needs_notify {
-                    self.writer.queue.waiter.notify();
+        let sender: &FutInnerSend<RW, T> = self;
+        let sent = sender.prod_wait.send_or_park(
+            |m| {
+                // Frozen queues park the sender like full ones, unfreeze wakes it
+                let sent = sender
+                    .writer
+                    .try_send_unnotified(m)
+                    .map_err(TrySendError::from);
+                if let Err(TrySendError::Full(_)) = sent {
+                    // The receivers have to get to the values already written
+                    // before there's room, so they can't wait for poll_complete
+                    sender.notify_unflushed();
                 }
+                sent
+            },
+            msg,
+        );
+        match sent {
+            Ok(_) => {
+                self.unflushed.store(true, RELAXED);
                 Ok(AsyncSink::Ready)
             }
             Err(TrySendError::Full(msg)) => Ok(AsyncSink::NotReady(msg)),
@@ -754,8 +4545,10 @@ impl<RW: QueueRW<T>, T> Sink for &FutInnerSend<RW, T> {
         }
     }
 
+    /// Wakes the receivers for everything start_send has written
     #[inline(always)]
     fn poll_complete(&mut self) -> Poll<(), SendError<T>> {
+        self.notify_unflushed();
         Ok(Async::Ready(()))
     }
 }
@@ -784,14 +4577,19 @@ impl<RW: QueueRW<T>, T> Stream for &FutInnerRecv<RW, T> {
     fn poll(&mut self) -> Poll<Option<T>, ()> {
         self.reader.examine_signals();
         loop {
-            match self.reader.queue.try_recv(&self.reader.reader) {
-                Ok(msg) => {
+            match self.reader.try_recv_raw() {
+                Ok((_, _, msg)) => {
+                    self.reader.unpark_waiting(&self.waiting);
                     self.prod_wait.notify_all();
                     return Ok(Async::Ready(Some(msg)));
                 }
-                Err((_, TryRecvError::Disconnected)) => return Ok(Async::Ready(None)),
+                Err((_, TryRecvError::Disconnected)) => {
+                    self.reader.unpark_waiting(&self.waiting);
+                    return Ok(Async::Ready(None));
+                }
                 Err((pt, _)) => {
-                    let count = self.reader.reader.load_count(Relaxed);
+                    self.reader.park_waiting(&self.waiting);
+                    let count = self.reader.reader.load_count(RELAXED);
                     if unsafe { self.wait.fut_wait(count, &*pt, &self.reader.queue.writers) } {
                         return Ok(Async::NotReady);
                     }
@@ -820,14 +4618,32 @@ impl<RW: QueueRW<T>, R, F: for<'r> FnMut(&T) -> R, T> Stream for FutInnerUniRecv
         self.reader.examine_signals();
         loop {
             let opref = &mut self.op;
-            match self.reader.queue.try_recv_view(opref, &self.reader.reader) {
+            let reader = &self.reader;
+            let viewed = panic::catch_unwind(AssertUnwindSafe(|| reader.try_recv_view_raw(opref)));
+            let rval = match viewed {
+                Ok(rval) => rval,
+                Err(payload) => {
+                    // The value was still taken, so the stream has moved past it and
+                    // there's room for any writers waiting on this receiver to wake up for
+                    self.reader.note_recv(1);
+                    self.reader.unpark_waiting(&self.waiting);
+                    self.prod_wait.notify_all();
+                    panic::resume_unwind(payload);
+                }
+            };
+            match rval {
                 Ok(msg) => {
+                    self.reader.unpark_waiting(&self.waiting);
                     self.prod_wait.notify_all();
                     return Ok(Async::Ready(Some(msg)));
                 }
-                Err((_, _, TryRecvError::Disconnected)) => return Ok(Async::Ready(None)),
+                Err((_, _, TryRecvError::Disconnected)) => {
+                    self.reader.unpark_waiting(&self.waiting);
+                    return Ok(Async::Ready(None));
+                }
                 Err((_, pt, _)) => {
-                    let count = self.reader.reader.load_count(Relaxed);
+                    self.reader.park_waiting(&self.waiting);
+                    let count = self.reader.reader.load_count(RELAXED);
                     if unsafe { self.wait.fut_wait(count, &*pt, &self.reader.queue.writers) } {
                         return Ok(Async::NotReady);
                     }
@@ -846,12 +4662,17 @@ impl FutWait {
 
     pub fn with_spins(spins_first: usize, spins_yield: usize) -> FutWait {
         FutWait {
-            spins_first,
-            spins_yield,
+            spins_first: AtomicUsize::new(spins_first),
+            spins_yield: AtomicUsize::new(spins_yield),
             parked: parking_lot::Mutex::new(VecDeque::new()),
         }
     }
 
+    pub fn set_spins(&self, spins_first: usize, spins_yield: usize) {
+        self.spins_first.store(spins_first, RELAXED);
+        self.spins_yield.store(spins_yield, RELAXED);
+    }
+
     pub fn fut_wait(&self, seq: usize, at: &AtomicUsize, wc: &AtomicUsize) -> bool {
         if self.spin(seq, at, wc) && self.park(seq, at, wc) {
             ::std::thread::sleep(::std::time::Duration::from_millis(100));
@@ -862,13 +4683,13 @@ impl FutWait {
     }
 
     pub fn spin(&self, seq: usize, at: &AtomicUsize, wc: &AtomicUsize) -> bool {
-        for _ in 0..self.spins_first {
+        for _ in 0..self.spins_first.load(RELAXED) {
             if check(seq, at, wc) {
                 return false;
             }
         }
 
-        for _ in 0..self.spins_yield {
+        for _ in 0..self.spins_yield.load(RELAXED) {
             yield_now();
             if check(seq, at, wc) {
                 return false;
@@ -891,14 +4712,14 @@ impl FutWait {
         f: F,
         mut val: T,
     ) -> Result<(), TrySendError<T>> {
-        for _ in 0..self.spins_first {
+        for _ in 0..self.spins_first.load(RELAXED) {
             match f(val) {
                 Err(TrySendError::Full(v)) => val = v,
                 v => return v,
             }
         }
 
-        for _ in 0..self.spins_yield {
+        for _ in 0..self.spins_yield.load(RELAXED) {
             yield_now();
             match f(val) {
                 Err(TrySendError::Full(v)) => val = v,
@@ -930,9 +4751,19 @@ impl Wait for FutWait {
         panic!("Somehow normal wait got called in futures queue");
     }
 
+    fn needs_every_notify(&self) -> bool {
+        false
+    }
+
+    fn notify_one(&self) {
+        // A task can be dropped after it's woken without polling the stream again,
+        // so waking just one of them could leave the value with nobody to take it
+        self.notify()
+    }
+
     fn notify(&self) {
         let mut parked = self.parked.lock();
-        if parked.len() > 0 {
+        if !parked.is_empty() {
             if parked.len() > 8 {
                 for val in parked.drain(..) {
                     val.notify();
@@ -951,6 +4782,10 @@ impl Wait for FutWait {
     fn needs_notify(&self) -> bool {
         true
     }
+
+    fn name(&self) -> &'static str {
+        "FutWait"
+    }
 }
 
 //////// Clone implementations
@@ -962,8 +4797,10 @@ impl<RW: QueueRW<T>, T> Clone for InnerSend<RW, T> {
             queue: self.queue.clone(),
             state: Cell::new(QueueState::Multi),
             token: self.queue.manager.get_token(),
+            #[cfg(feature = "order_checks")]
+            sent: OrderCheck::new(),
         };
-        self.queue.writers.fetch_add(1, SeqCst);
+        self.queue.writers.fetch_add(1, SEQ_CST);
         rval
     }
 }
@@ -975,6 +4812,8 @@ impl<RW: QueueRW<T>, T> Clone for InnerRecv<RW, T> {
             queue: self.queue.clone(),
             reader: self.reader.clone(),
             token: self.queue.manager.get_token(),
+            filter: self.filter.clone(),
+            name: self.name.clone(),
             alive: true,
         }
     }
@@ -986,6 +4825,7 @@ impl<RW: QueueRW<T>, T> Clone for FutInnerSend<RW, T> {
             writer: self.writer.clone(),
             wait: self.wait.clone(),
             prod_wait: self.prod_wait.clone(),
+            unflushed: AtomicBool::new(false),
         }
     }
 }
@@ -996,13 +4836,17 @@ impl<RW: QueueRW<T>, T> Clone for FutInnerRecv<RW, T> {
             reader: self.reader.clone(),
             wait: self.wait.clone(),
             prod_wait: self.prod_wait.clone(),
+            waiting: Cell::new(false),
         }
     }
 }
 
 impl Clone for FutWait {
     fn clone(&self) -> FutWait {
-        FutWait::with_spins(self.spins_first, self.spins_yield)
+        FutWait::with_spins(
+            self.spins_first.load(RELAXED),
+            self.spins_yield.load(RELAXED),
+        )
     }
 }
 
@@ -1010,10 +4854,16 @@ impl Clone for FutWait {
 
 impl<RW: QueueRW<T>, T> Drop for InnerSend<RW, T> {
     fn drop(&mut self) {
-        self.queue.writers.fetch_sub(1, SeqCst);
-        fence(SeqCst);
+        if self.queue.writers.fetch_sub(1, SEQ_CST) == 1 {
+            #[cfg(feature = "tracing")]
+            self.queue.trace.writers_gone();
+            #[cfg(feature = "metrics")]
+            self.queue.report(|m| m.on_disconnect());
+            self.queue.writers_gone();
+        }
+        fence(SEQ_CST);
         self.queue.manager.remove_token(self.token);
-        self.queue.waiter.notify();
+        self.queue.notify();
     }
 }
 
@@ -1023,37 +4873,91 @@ impl<RW: QueueRW<T>, T> Drop for InnerRecv<RW, T> {
     }
 }
 
-impl<RW: QueueRW<T>, T> Drop for MultiQueue<RW, T> {
+// Dropping the queue needs these, and it has to work whatever holds its streams
+impl<RW: QueueRW<T>, T, S: ?Sized> MultiQueue<RW, T, S> {
+    /// Returns the times kept for the slot at idx, on queues which keep them
+    #[inline(always)]
+    fn times(&self, idx: usize) -> Option<&SlotTimes> {
+        self.times
+            .as_ref()
+            .map(|times| unsafe { times.get_unchecked(idx) })
+    }
+
+    /// Returns whether the value in the slot at idx has outlived its time to live.
+    /// Values on queues without expiry never do
+    #[inline(always)]
+    fn is_expired(&self, idx: usize) -> bool {
+        match self.expiry_base {
+            None => false,
+            Some(base) => self.expired(base, idx),
+        }
+    }
+
+    #[cold]
+    fn expired(&self, base: Instant, idx: usize) -> bool {
+        // Pairs with the Release store of the slot's tag
+        fence(ACQUIRE);
+        let expires_at = self
+            .times(idx)
+            .map_or(0, |times| times.expires_at.load(RELAXED));
+        expires_at != 0 && self.elapsed(base).as_nanos() as u64 >= expires_at
+    }
+
+    /// Returns how long it's been since base by the queue's clock
+    #[inline(always)]
+    fn elapsed(&self, base: Instant) -> Duration {
+        self.clock.now().saturating_duration_since(base)
+    }
+}
+
+impl<RW: QueueRW<T>, T, S: ?Sized> Drop for MultiQueue<RW, T, S> {
     fn drop(&mut self) {
+        #[cfg(feature = "registry")]
+        registry::unregister(self.registry_id);
         if RW::do_drop() {
             // everything that's tagged shouldn't be dropped
             // otherwise, everything else is valid and waiting to be read
             // or invalid and waiting to be overwritten/dropped
             for i in 0..self.capacity {
                 unsafe {
-                    let cell = &mut *self.data.offset(i);
-                    if !is_tagged(cell.wraps.load(Relaxed)) {
-                        ptr::read(&cell.val);
+                    let cell = &*self.data.offset(i);
+                    if !is_tagged(cell.wraps.load(RELAXED)) {
+                        ptr::drop_in_place(cell.val.get());
                     }
                 }
             }
         } else {
             let last_read =
                 CountedIndex::from_usize(self.tail.last_pos.get(), self.capacity as Index);
-            while last_read.load_count(Relaxed) != self.head.load_count(Relaxed) {
+            while last_read.load_count(RELAXED) != self.head.load_count(RELAXED) {
                 unsafe {
-                    let cur_pos = last_read.load_transaction(Relaxed);
+                    let cur_pos = last_read.load_transaction(RELAXED);
                     let (cur_ind, _) = cur_pos.get();
-                    ptr::drop_in_place(&mut (*self.data.offset(cur_ind)).val);
-                    cur_pos.commit_direct(1, Relaxed);
+                    let val = (*self.data.offset(cur_ind)).val.get();
+                    match (&self.drop_policy, &self.dead_letters) {
+                        (DropPolicy::Discard(ref on_dropped), _) => on_dropped(ptr::read(val)),
+                        (_, Some(ref dead_letters)) => {
+                            let reason = if self.is_expired(cur_ind as usize) {
+                                DeadLetterReason::Expired
+                            } else {
+                                DeadLetterReason::Undeliverable
+                            };
+                            dead_letters.send(reason, ptr::read(val));
+                        }
+                        _ => ptr::drop_in_place(val),
+                    }
+                    cur_pos.commit_direct(1, RELAXED);
                 }
             }
         }
+        alloc::deallocate(self.data, self.capacity as usize);
+        alloc::deallocate(self.refs, self.capacity as usize);
     }
 }
 
 impl<RW: QueueRW<T>, T> Drop for FutInnerRecv<RW, T> {
     fn drop(&mut self) {
+        self.reader.unpark_waiting(&self.waiting);
         let prod_wait = self.prod_wait.clone();
         unsafe {
             self.reader.do_unsubscribe_with(|| {
@@ -1065,6 +4969,7 @@ impl<RW: QueueRW<T>, T> Drop for FutInnerRecv<RW, T> {
 
 impl<RW: QueueRW<T>, R, F: for<'r> FnMut(&T) -> R, T> Drop for FutInnerUniRecv<RW, R, F, T> {
     fn drop(&mut self) {
+        self.reader.unpark_waiting(&self.waiting);
         let prod_wait = self.prod_wait.clone();
         unsafe {
             self.reader.do_unsubscribe_with(|| {
@@ -1074,21 +4979,64 @@ impl<RW: QueueRW<T>, R, F: for<'r> FnMut(&T) -> R, T> Drop for FutInnerUniRecv<R
     }
 }
 
+impl<RW: QueueRW<T>, T> fmt::Debug for MultiQueue<RW, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Occupancy is how far the slowest stream is behind,
+        // which may already be stale by the time it's printed
+        f.debug_struct("MultiQueue")
+            .field("capacity", &self.capacity)
+            .field("occupancy", &self.max_lag())
+            .field("streams", &self.stream_count())
+            .field("writers", &self.writer_count())
+            .field("wait", &self.waiter.name())
+            .finish()
+    }
+}
+
+impl<RW: QueueRW<T>, T> fmt::Debug for InnerSend<RW, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Sender")
+            .field("queue", &self.queue)
+            .finish()
+    }
+}
+
 impl<RW: QueueRW<T>, T> fmt::Debug for InnerRecv<RW, T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "MultiQueue error message - you probably tried to unwrap the result of into_single"
-        )
+        f.debug_struct("Receiver")
+            .field("name", &self.name())
+            .field("stream", &self.stream_name())
+            .field("lag", &self.lag())
+            .field("queue", &self.queue)
+            .finish()
+    }
+}
+
+impl<RW: QueueRW<T>, T> fmt::Debug for ReaderToken<RW, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ReaderToken")
+            .field("name", &self.name)
+            .field("stream", &self.stream_name)
+            .field("pos", &self.pos)
+            .finish()
+    }
+}
+
+impl<RW: QueueRW<T>, T> fmt::Debug for FutInnerSend<RW, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.writer.fmt(f)
     }
 }
 
 impl<RW: QueueRW<T>, T> fmt::Debug for FutInnerRecv<RW, T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "MultiQueue error message - you probably tried to unwrap the result of into_single"
-        )
+        self.reader.fmt(f)
+    }
+}
+
+impl<RW: QueueRW<T>, R, F: FnMut(&T) -> R, T> fmt::Debug for FutInnerUniRecv<RW, R, F, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.reader.fmt(f)
     }
 }
 
@@ -1100,23 +5048,90 @@ unsafe impl<RW: QueueRW<T>, T: Send> Send for FutInnerSend<RW, T> {}
 unsafe impl<RW: QueueRW<T>, T: Send> Send for FutInnerRecv<RW, T> {}
 unsafe impl<RW: QueueRW<T>, R, F: FnMut(&T) -> R, T> Send for FutInnerUniRecv<RW, R, F, T> {}
 
+//////// RecvGuard
+
+// On the Arc, since the queue itself has no fixed size
+impl<RW: QueueRW<T>, T> TakeVal<T> for Arc<MultiQueue<RW, T>> {
+    unsafe fn take_val(&self, val: *mut T) {
+        self.release_weight(&*val);
+        RW::drop_in_place(val);
+    }
+}
+
+impl<'a, T> std::ops::Deref for RecvGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.val }
+    }
+}
+
+impl<'a, T> Drop for RecvGuard<'a, T> {
+    fn drop(&mut self) {
+        if let Some(attempt) = self.attempt.take() {
+            unsafe { self.queue.take_val(self.val) };
+            attempt.commit_direct(1, RELEASE);
+        }
+    }
+}
+
+impl<'a, T: fmt::Debug> fmt::Debug for RecvGuard<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("RecvGuard").field(&**self).finish()
+    }
+}
+
+/// Runs the passed function when dropped, so that views finish the same way
+/// whether op returns or panics
+struct OnDrop<F: FnOnce()> {
+    done: Option<F>,
+}
+
+impl<F: FnOnce()> OnDrop<F> {
+    fn new(done: F) -> OnDrop<F> {
+        OnDrop { done: Some(done) }
+    }
+}
+
+impl<F: FnOnce()> Drop for OnDrop<F> {
+    fn drop(&mut self) {
+        if let Some(done) = self.done.take() {
+            done();
+        }
+    }
+}
+
+/// Returns when the queue was created, on targets which have a clock to read.
+/// Instant::now panics on wasm32-unknown-unknown, so queues there go without
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+fn clock_start(clock: &dyn Clock) -> Option<Instant> {
+    Some(clock.now())
+}
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+fn clock_start(_clock: &dyn Clock) -> Option<Instant> {
+    None
+}
+
 /// Usage: futures_multiqueue(`capacity`)
 /// This is equivalent to `futures_multiqueue_with(capacity,50,20)`.
 pub fn futures_multiqueue<RW: QueueRW<T>, T>(
-    capacity: Index,
+    capacity: usize,
 ) -> (FutInnerSend<RW, T>, FutInnerRecv<RW, T>) {
     let cons_arc = Arc::new(FutWait::new());
     let prod_arc = Arc::new(FutWait::new());
-    let (tx, rx) = MultiQueue::new_internal(capacity, cons_arc.clone());
+    let (tx, rx) = MultiQueue::new_internal(capacity, cons_arc.clone(), QueueOptions::default());
     let ftx = FutInnerSend {
         writer: tx,
         wait: cons_arc.clone(),
         prod_wait: prod_arc.clone(),
+        unflushed: AtomicBool::new(false),
     };
     let rtx = FutInnerRecv {
         reader: rx,
         wait: cons_arc,
         prod_wait: prod_arc,
+        waiting: Cell::new(false),
     };
     (ftx, rtx)
 }
@@ -1129,22 +5144,24 @@ pub fn futures_multiqueue<RW: QueueRW<T>, T>(
 /// `futures_multiqueue_with(1000,0,0)` is possible, which  will turn this hybrid-lock into a kernal lock.
 /// Feel free to test different setting that matches your system.
 pub fn futures_multiqueue_with<RW: QueueRW<T>, T>(
-    capacity: Index,
+    capacity: usize,
     try_spins: usize,
     yield_spins: usize,
 ) -> (FutInnerSend<RW, T>, FutInnerRecv<RW, T>) {
     let cons_arc = Arc::new(FutWait::with_spins(try_spins, yield_spins));
     let prod_arc = Arc::new(FutWait::with_spins(try_spins, yield_spins));
-    let (tx, rx) = MultiQueue::new_internal(capacity, cons_arc.clone());
+    let (tx, rx) = MultiQueue::new_internal(capacity, cons_arc.clone(), QueueOptions::default());
     let ftx = FutInnerSend {
         writer: tx,
         wait: cons_arc.clone(),
         prod_wait: prod_arc.clone(),
+        unflushed: AtomicBool::new(false),
     };
     let rtx = FutInnerRecv {
         reader: rx,
         wait: cons_arc,
         prod_wait: prod_arc,
+        waiting: Cell::new(false),
     };
     (ftx, rtx)
 }
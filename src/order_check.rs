@@ -0,0 +1,89 @@
+//! With the ```order_checks``` feature, every writer checks that the slots it claims
+//! only ever move forwards, and every consumer checks the same of the values it
+//! receives. Between them that covers values from one writer arriving in the
+//! order they were sent on every stream, and any violation panics with what was seen.
+
+use crate::countedindex::{past, rm_tag};
+
+use std::cell::Cell;
+
+/// The last sequence number a writer or consumer got from the queue
+#[derive(Clone)]
+pub struct OrderCheck {
+    last: Cell<Option<usize>>,
+}
+
+impl OrderCheck {
+    pub fn new() -> OrderCheck {
+        OrderCheck {
+            last: Cell::new(None),
+        }
+    }
+
+    /// Panics unless seq comes after everything seen so far.
+    /// Who says which writer or consumer this is in the message
+    #[inline(always)]
+    pub fn saw(&self, seq: usize, who: &str) {
+        if let Some(last) = self.last.get() {
+            // Sequence numbers wrap around within the untagged bits
+            let (ahead, behind) = past(rm_tag(seq.wrapping_sub(last)), 0);
+            if ahead == 0 || behind {
+                panic!(
+                    "Multiqueue error - {} got sequence {} after {}, values are out of order",
+                    who, seq, last
+                );
+            }
+        }
+        self.last.set(Some(seq));
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::OrderCheck;
+    use crate::countedindex::rm_tag;
+    use crate::mpmc_queue;
+
+    #[test]
+    fn test_forwards_is_fine() {
+        let check = OrderCheck::new();
+        check.saw(3, "writer");
+        check.saw(4, "writer");
+        // Skipping ahead is fine, streams do that past filtered and replaced values
+        check.saw(10, "writer");
+        // And so is wrapping around the counter
+        let check = OrderCheck::new();
+        let top = rm_tag(usize::MAX);
+        check.saw(top, "writer");
+        check.saw(rm_tag(top.wrapping_add(2)), "writer");
+    }
+
+    #[test]
+    #[should_panic(expected = "consumer got sequence 4 after 4")]
+    fn test_repeat_panics() {
+        let check = OrderCheck::new();
+        check.saw(4, "consumer");
+        check.saw(4, "consumer");
+    }
+
+    #[test]
+    #[should_panic(expected = "got sequence 2 after 5")]
+    fn test_backwards_panics() {
+        let check = OrderCheck::new();
+        check.saw(5, "writer");
+        check.saw(2, "writer");
+    }
+
+    #[test]
+    fn test_checked_queue() {
+        let (writer, reader) = mpmc_queue(4);
+        let other = reader.clone();
+        for i in 0..20 {
+            writer.try_send(i).unwrap();
+            writer.try_send(i).unwrap();
+            assert_eq!(i, reader.try_recv().unwrap());
+            assert_eq!(i, other.try_recv().unwrap());
+        }
+    }
+}
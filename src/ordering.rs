@@ -0,0 +1,59 @@
+//! The orderings the core of the queue is written with. Building with the
+//! ```strict_orderings``` feature turns every one of them into SeqCst, which
+//! makes it quick to tell whether a misbehaving queue is down to a missing
+//! ordering or something else entirely.
+
+use std::sync::atomic::Ordering;
+
+#[cfg(not(feature = "strict_orderings"))]
+mod table {
+    use std::sync::atomic::Ordering;
+    pub const RELAXED: Ordering = Ordering::Relaxed;
+    pub const ACQUIRE: Ordering = Ordering::Acquire;
+    pub const RELEASE: Ordering = Ordering::Release;
+    pub const ACQ_REL: Ordering = Ordering::AcqRel;
+}
+
+#[cfg(feature = "strict_orderings")]
+mod table {
+    use std::sync::atomic::Ordering;
+    pub const RELAXED: Ordering = Ordering::SeqCst;
+    pub const ACQUIRE: Ordering = Ordering::SeqCst;
+    pub const RELEASE: Ordering = Ordering::SeqCst;
+    pub const ACQ_REL: Ordering = Ordering::SeqCst;
+}
+
+pub use self::table::*;
+pub const SEQ_CST: Ordering = Ordering::SeqCst;
+
+/// Whether an ordering gives at least the guarantees of wanted. SeqCst loads and
+/// stores can stand in for anything, so this is all that's needed to swap them in
+const fn at_least(ord: Ordering, wanted: Ordering) -> bool {
+    use std::sync::atomic::Ordering::*;
+    matches!(
+        (ord, wanted),
+        (SeqCst, _)
+            | (_, Relaxed)
+            | (AcqRel, Acquire)
+            | (AcqRel, Release)
+            | (AcqRel, AcqRel)
+            | (Acquire, Acquire)
+            | (Release, Release)
+    )
+}
+
+const fn is_seq_cst(ord: Ordering) -> bool {
+    matches!(ord, Ordering::SeqCst)
+}
+
+// Nothing in the table may be weaker than the name it goes by,
+// and the strict build may not leave anything weaker than SeqCst
+const _: () = assert!(at_least(RELAXED, Ordering::Relaxed));
+const _: () = assert!(at_least(ACQUIRE, Ordering::Acquire));
+const _: () = assert!(at_least(RELEASE, Ordering::Release));
+const _: () = assert!(at_least(ACQ_REL, Ordering::AcqRel));
+const _: () = assert!(is_seq_cst(SEQ_CST));
+#[cfg(feature = "strict_orderings")]
+const _: () = assert!(
+    is_seq_cst(RELAXED) && is_seq_cst(ACQUIRE) && is_seq_cst(RELEASE) && is_seq_cst(ACQ_REL)
+);
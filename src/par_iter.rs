@@ -0,0 +1,138 @@
+//! Feeds the values of an mpmc queue to a rayon thread pool
+
+use crate::mpmc::MPMCReceiver;
+
+extern crate rayon;
+use self::rayon::current_num_threads;
+use self::rayon::iter::plumbing::{bridge_unindexed, Folder, UnindexedConsumer, UnindexedProducer};
+use self::rayon::iter::ParallelIterator;
+
+use std::sync::mpsc::TryRecvError;
+
+/// How many values a worker takes from the queue at once
+const STEAL_BATCH: usize = 32;
+
+/// This is a rayon ```ParallelIterator``` over the values of an mpmc queue. Every
+/// worker thread gets its own receiver on the queue and takes values in batches
+/// with ```try_steal_batch```, so workers don't take turns on a lock the way they
+/// would bridging a shared receiver with ```par_bridge```.
+///
+/// While the queue is empty the workers block waiting for values, and the
+/// iterator ends once the queue is empty and every sender has been dropped.
+/// Like ```par_bridge```, the values don't come through in any particular order,
+/// and values a worker has taken but not consumed when the iterator stops
+/// early, such as with ```find_any```, are dropped.
+///
+/// These are made with ```MPMCReceiver::par_iter``` and ```MPMCReceiver::par_drain```
+///
+/// # Examples
+///
+/// ```
+/// use multiqueue2::mpmc_queue;
+/// use rayon::prelude::*;
+///
+/// let (w, r) = mpmc_queue(100);
+/// for i in 0..100 {
+///     w.try_send(i).unwrap();
+/// }
+/// drop(w);
+/// assert_eq!(4950, r.par_drain().sum::<u64>());
+/// ```
+pub struct MPMCParIter<T> {
+    receiver: MPMCReceiver<T>,
+}
+
+struct QueueProducer<T> {
+    receiver: MPMCReceiver<T>,
+    // How many more times this may be split, so that there's about a receiver per thread
+    splits: usize,
+}
+
+impl<T> MPMCParIter<T> {
+    pub(crate) fn new(receiver: MPMCReceiver<T>) -> MPMCParIter<T> {
+        MPMCParIter { receiver }
+    }
+}
+
+impl<T: Send> ParallelIterator for MPMCParIter<T> {
+    type Item = T;
+
+    fn drive_unindexed<C: UnindexedConsumer<T>>(self, consumer: C) -> C::Result {
+        let producer = QueueProducer {
+            receiver: self.receiver,
+            splits: current_num_threads(),
+        };
+        bridge_unindexed(producer, consumer)
+    }
+}
+
+impl<T: Send> UnindexedProducer for QueueProducer<T> {
+    type Item = T;
+
+    fn split(mut self) -> (Self, Option<Self>) {
+        if self.splits == 0 {
+            return (self, None);
+        }
+        self.splits /= 2;
+        let other = QueueProducer {
+            receiver: self.receiver.clone(),
+            splits: self.splits,
+        };
+        (self, Some(other))
+    }
+
+    fn fold_with<F: Folder<T>>(self, mut folder: F) -> F {
+        while !folder.full() {
+            match self.receiver.try_steal_batch(STEAL_BATCH) {
+                Ok(batch) => folder = folder.consume_iter(batch),
+                Err(TryRecvError::Disconnected) => break,
+                Err(TryRecvError::Empty) => match self.receiver.recv() {
+                    Ok(val) => folder = folder.consume(val),
+                    Err(_) => break,
+                },
+            }
+        }
+        folder
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use crate::error::FrozenTrySendError;
+    use crate::mpmc::mpmc_queue;
+
+    use super::rayon::prelude::*;
+
+    use std::sync::mpsc::TryRecvError;
+    use std::thread;
+
+    #[test]
+    fn test_par_drain() {
+        let (w, r) = mpmc_queue(64);
+        let t = thread::spawn(move || {
+            for i in 0..100_000u64 {
+                let mut val = w.try_send(i);
+                while let Err(FrozenTrySendError::Full(v)) = val {
+                    thread::yield_now();
+                    val = w.try_send(v);
+                }
+            }
+        });
+        let mut got: Vec<u64> = r.par_drain().collect();
+        t.join().unwrap();
+        got.sort_unstable();
+        assert_eq!((0..100_000).collect::<Vec<_>>(), got);
+    }
+
+    #[test]
+    fn test_par_iter_keeps_receiver() {
+        let (w, r) = mpmc_queue(16);
+        for i in 0..10 {
+            w.try_send(i).unwrap();
+        }
+        drop(w);
+        assert_eq!(45, r.par_iter().sum::<i32>());
+        assert_eq!(Err(TryRecvError::Disconnected), r.try_recv());
+    }
+}
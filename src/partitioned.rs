@@ -0,0 +1,344 @@
+//! A mpmc queue split into partitions, each of which is received from by one owner
+
+use crate::error::FrozenTrySendError;
+use crate::multiqueue::{InnerRecv, InnerSend, MPMC};
+use crate::shards::{hash_key, Shards};
+use crate::stats::QueueStats;
+
+use std::hash::Hash;
+use std::sync::mpsc::{RecvError, TryRecvError};
+
+/// This is the sending half of a partitioned queue. Each partition is its own ring
+/// with its own capacity, and values are sent to a partition picked by their key
+/// or named directly.
+#[derive(Clone)]
+pub struct PartitionedSender<T> {
+    senders: Vec<InnerSend<MPMC<T>, T>>,
+}
+
+/// A handle on a single partition of a partitioned queue. It's the only way to
+/// receive from the partition, so values sent to it are received in the order
+/// they were sent. Dropping it disconnects the partition.
+#[derive(Debug)]
+pub struct Partition<T> {
+    id: usize,
+    receiver: InnerRecv<MPMC<T>, T>,
+}
+
+/// This is the receiving half of a partitioned queue, which owns one or more
+/// ```Partition```s and receives from all of them. Values with the same key go
+/// to the same partition, so they're received in the order they were sent,
+/// but values on different partitions may be received in any order.
+///
+/// A receiver starts out owning every partition. They're handed to other
+/// receivers or threads with ```take_partition``` and ```into_partitions```,
+/// and gathered back up with ```add_partition```.
+///
+/// # Examples
+///
+/// ```
+/// use multiqueue2::partitioned_queue;
+/// use std::thread;
+///
+/// let (send, recv) = partitioned_queue(16, 2);
+/// let handles: Vec<_> = recv
+///     .into_partitions()
+///     .into_iter()
+///     .map(|partition| {
+///         thread::spawn(move || (partition.id(), partition.collect::<Vec<_>>()))
+///     })
+///     .collect();
+///
+/// for val in 0..8 {
+///     send.try_send("user", val).unwrap();
+/// }
+/// let used = send.partition_of("user");
+/// drop(send);
+///
+/// for handle in handles {
+///     let (id, got) = handle.join().unwrap();
+///     // The user's values all went to one partition, in order
+///     if id == used {
+///         assert_eq!((0..8).collect::<Vec<_>>(), got);
+///     } else {
+///         assert!(got.is_empty());
+///     }
+/// }
+/// ```
+#[derive(Debug)]
+pub struct PartitionedReceiver<T> {
+    partitions: Shards<T>,
+}
+
+impl<T> PartitionedSender<T> {
+    /// Tries to send a value to the partition its key hashes to.
+    /// Sending always fails once the partition's owner is gone.
+    #[inline(always)]
+    pub fn try_send<K: Hash + ?Sized>(&self, key: &K, val: T) -> Result<(), FrozenTrySendError<T>> {
+        self.try_send_to(self.partition_of(key), val)
+    }
+
+    /// Tries to send a value to the passed partition.
+    /// Panics if the queue doesn't have that partition.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::partitioned_queue;
+    /// let (w, r) = partitioned_queue(1, 2);
+    /// w.try_send_to(1, 10).unwrap();
+    /// // Each partition holds its own values
+    /// assert!(w.try_send_to(1, 11).is_err());
+    /// w.try_send_to(0, 12).unwrap();
+    /// assert_eq!(vec![(0, 1), (1, 1)], r.partition_lags());
+    /// ```
+    #[inline(always)]
+    pub fn try_send_to(&self, partition: usize, val: T) -> Result<(), FrozenTrySendError<T>> {
+        self.senders[partition].try_send(val)
+    }
+
+    /// Returns the partition values with the passed key are sent to
+    pub fn partition_of<K: Hash + ?Sized>(&self, key: &K) -> usize {
+        (hash_key(key) % self.senders.len() as u64) as usize
+    }
+
+    /// Returns the number of partitions in the queue
+    pub fn num_partitions(&self) -> usize {
+        self.senders.len()
+    }
+
+    /// Returns a snapshot of each partition's state, in partition order
+    pub fn partition_stats(&self) -> Vec<(usize, QueueStats)> {
+        self.senders
+            .iter()
+            .enumerate()
+            .map(|(id, sender)| (id, sender.stats()))
+            .collect()
+    }
+
+    /// Removes this writer from the queue
+    pub fn unsubscribe(self) {
+        for sender in self.senders {
+            sender.unsubscribe();
+        }
+    }
+}
+
+impl<T> Partition<T> {
+    /// Returns which partition of the queue this is
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Tries to receive a value from the partition without blocking
+    #[inline(always)]
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.receiver.try_recv()
+    }
+
+    /// Receives a value from the partition, waiting for one if it's empty
+    pub fn recv(&self) -> Result<T, RecvError> {
+        self.receiver.recv()
+    }
+
+    /// Returns the number of items waiting on the partition
+    pub fn lag(&self) -> usize {
+        self.receiver.lag()
+    }
+
+    /// Returns how many values the partition holds
+    pub fn capacity(&self) -> usize {
+        self.receiver.capacity()
+    }
+
+    /// Returns a snapshot of the partition's state
+    pub fn stats(&self) -> QueueStats {
+        self.receiver.stats()
+    }
+
+    /// Removes this partition from the queue, disconnecting it
+    pub fn unsubscribe(self) {
+        self.receiver.unsubscribe();
+    }
+}
+
+impl<T> PartitionedReceiver<T> {
+    /// Creates a receiver owning the passed partitions
+    pub fn from_partitions(partitions: Vec<Partition<T>>) -> PartitionedReceiver<T> {
+        PartitionedReceiver {
+            partitions: Shards::new(partitions.into_iter().map(|p| (p.id, p.receiver)).collect()),
+        }
+    }
+
+    /// Tries to receive a value from one of the owned partitions without blocking,
+    /// taking turns between them so a busy partition can't starve the rest.
+    /// Returns Disconnected once the writers are gone and the owned partitions are empty.
+    #[inline(always)]
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.partitions.try_recv()
+    }
+
+    /// Receives a value from one of the owned partitions,
+    /// waiting on all of them if they're empty.
+    pub fn recv(&self) -> Result<T, RecvError> {
+        self.partitions.recv()
+    }
+
+    /// Gives up ownership of the passed partition, returning None
+    /// if this receiver doesn't own it
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::partitioned_queue;
+    ///
+    /// let (send, mut recv) = partitioned_queue(4, 3);
+    /// send.try_send_to(1, "a").unwrap();
+    /// let partition = recv.take_partition(1).unwrap();
+    /// assert_eq!(vec![0, 2], recv.partitions());
+    /// assert!(recv.take_partition(1).is_none());
+    /// assert_eq!(1, partition.lag());
+    /// assert_eq!("a", partition.try_recv().unwrap());
+    /// recv.add_partition(partition);
+    /// assert_eq!(vec![0, 1, 2], recv.partitions());
+    /// ```
+    pub fn take_partition(&mut self, id: usize) -> Option<Partition<T>> {
+        let receiver = self.partitions.take(id)?;
+        Some(Partition { id, receiver })
+    }
+
+    /// Takes ownership of the passed partition
+    pub fn add_partition(&mut self, partition: Partition<T>) {
+        self.partitions.insert(partition.id, partition.receiver);
+    }
+
+    /// Splits the receiver up into the partitions it owns
+    pub fn into_partitions(self) -> Vec<Partition<T>> {
+        self.partitions
+            .into_inner()
+            .into_iter()
+            .map(|(id, receiver)| Partition { id, receiver })
+            .collect()
+    }
+
+    /// Returns the partitions this receiver owns
+    pub fn partitions(&self) -> Vec<usize> {
+        self.partitions.ids()
+    }
+
+    /// Returns the number of items waiting on each owned partition
+    pub fn partition_lags(&self) -> Vec<(usize, usize)> {
+        self.partitions.lags()
+    }
+
+    /// Returns a snapshot of each owned partition's state
+    pub fn partition_stats(&self) -> Vec<(usize, QueueStats)> {
+        self.partitions.stats()
+    }
+
+    /// Removes this receiver from the queue, disconnecting the partitions it owns
+    pub fn unsubscribe(self) {
+        self.partitions.unsubscribe();
+    }
+}
+
+impl<T> Iterator for Partition<T> {
+    type Item = T;
+
+    #[inline(always)]
+    fn next(&mut self) -> Option<T> {
+        self.recv().ok()
+    }
+}
+
+impl<T> Iterator for PartitionedReceiver<T> {
+    type Item = T;
+
+    #[inline(always)]
+    fn next(&mut self) -> Option<T> {
+        self.recv().ok()
+    }
+}
+
+/// Creates a (```PartitionedSender```, ```PartitionedReceiver```) pair with the passed
+/// number of partitions, each of which holds capacity values.
+///
+/// # Examples
+///
+/// ```
+/// use multiqueue2::partitioned_queue;
+/// let (w, r) = partitioned_queue(4, 2);
+/// w.try_send("user", 1).unwrap();
+/// w.try_send("user", 2).unwrap();
+/// assert_eq!(1, r.try_recv().unwrap());
+/// assert_eq!(2, r.try_recv().unwrap());
+/// ```
+pub fn partitioned_queue<T>(
+    capacity: usize,
+    partitions: usize,
+) -> (PartitionedSender<T>, PartitionedReceiver<T>) {
+    assert!(
+        partitions > 0,
+        "Multiqueue error - zero partitions received"
+    );
+    let (senders, partitions) = Shards::create(capacity, partitions);
+    (
+        PartitionedSender { senders },
+        PartitionedReceiver { partitions },
+    )
+}
+
+unsafe impl<T: Send> Send for PartitionedSender<T> {}
+unsafe impl<T: Send> Send for Partition<T> {}
+unsafe impl<T: Send> Send for PartitionedReceiver<T> {}
+
+#[cfg(test)]
+mod test {
+
+    use super::partitioned_queue;
+
+    use std::sync::mpsc::TryRecvError;
+
+    #[test]
+    fn test_partition_handles() {
+        let (writer, mut reader) = partitioned_queue(4, 3);
+        assert_eq!(3, writer.num_partitions());
+        let key = (0..100).find(|k| writer.partition_of(k) == 2).unwrap();
+        writer.try_send(&key, 1).unwrap();
+        writer.try_send(&key, 2).unwrap();
+        writer.try_send_to(0, 3).unwrap();
+
+        let stats = writer.partition_stats();
+        assert_eq!(vec![0, 1, 2], stats.iter().map(|s| s.0).collect::<Vec<_>>());
+        assert_eq!(
+            vec![1, 0, 2],
+            stats.iter().map(|s| s.1.occupancy).collect::<Vec<_>>()
+        );
+
+        let partition = reader.take_partition(2).unwrap();
+        assert_eq!(2, partition.id());
+        assert_eq!(4, partition.capacity());
+        assert_eq!(vec![(0, 1), (1, 0)], reader.partition_lags());
+        assert_eq!(3, reader.try_recv().unwrap());
+        assert_eq!(Err(TryRecvError::Empty), reader.try_recv());
+        assert_eq!(1, partition.try_recv().unwrap());
+        assert_eq!(1, partition.lag());
+        reader.add_partition(partition);
+        assert_eq!(vec![0, 1, 2], reader.partitions());
+        assert_eq!(2, reader.try_recv().unwrap());
+
+        // Dropping a partition disconnects it
+        drop(reader.take_partition(1));
+        for _ in 0..8 {
+            assert!(writer.try_send_to(1, 0).is_err());
+        }
+        drop(writer);
+        assert_eq!(Err(TryRecvError::Disconnected), reader.try_recv());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_zero_partitions() {
+        let _ = partitioned_queue::<usize>(4, 0);
+    }
+}
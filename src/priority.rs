@@ -0,0 +1,338 @@
+//! A multi-lane mpmc queue where receivers take from higher priority lanes first
+
+use crate::error::FrozenTrySendError;
+use crate::multiqueue::{InnerRecv, InnerSend, MPMC};
+use crate::shards::rings;
+use crate::wait::Doorbell;
+
+use std::cell::Cell;
+use std::sync::mpsc::{RecvError, TryRecvError};
+
+/// This is the sending half of the priority mpmc queue. Every value is sent
+/// on one of the lanes, and the highest lane is received from first.
+///
+/// # Examples
+///
+/// ```
+/// use multiqueue2::mpmc_priority_queue;
+///
+/// let (send, recv) = mpmc_priority_queue(4, 3);
+/// send.try_send(0, "low").unwrap();
+/// send.try_send(2, "urgent").unwrap();
+/// send.try_send(1, "normal").unwrap();
+///
+/// assert_eq!("urgent", recv.try_recv().unwrap());
+/// assert_eq!("normal", recv.try_recv().unwrap());
+/// assert_eq!("low", recv.try_recv().unwrap());
+/// ```
+#[derive(Clone)]
+pub struct MPMCPrioritySender<T> {
+    senders: Vec<InnerSend<MPMC<T>, T>>,
+}
+
+/// This is the receiving half of the priority mpmc queue. Values are always
+/// taken from the highest lane which has any unless a starvation limit is set.
+#[derive(Debug)]
+pub struct MPMCPriorityReceiver<T> {
+    receivers: Vec<InnerRecv<MPMC<T>, T>>,
+    starvation_limit: usize,
+    streak: Cell<usize>,
+    next_turn: Cell<usize>,
+    doorbell: Doorbell,
+}
+
+impl<T> MPMCPrioritySender<T> {
+    /// Tries to send a value on the passed lane, where higher lanes are received first.
+    /// Each lane has its own capacity, so a full lane doesn't stop the others.
+    /// Panics if the lane doesn't exist.
+    #[inline(always)]
+    pub fn try_send(&self, lane: usize, val: T) -> Result<(), FrozenTrySendError<T>> {
+        assert!(
+            lane < self.senders.len(),
+            "Multiqueue error - priority lane out of range"
+        );
+        self.senders[lane].try_send(val)
+    }
+
+    /// Returns the number of lanes in the queue
+    pub fn lanes(&self) -> usize {
+        self.senders.len()
+    }
+
+    /// Removes this writer from the queue
+    pub fn unsubscribe(self) {
+        for sender in self.senders {
+            sender.unsubscribe();
+        }
+    }
+}
+
+impl<T> MPMCPriorityReceiver<T> {
+    /// Tries to receive a value from the highest lane which has one without blocking.
+    /// Returns Disconnected once the writers are gone and every lane is empty.
+    #[inline(always)]
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.try_recv_with_lane().map(|(_, v)| v)
+    }
+
+    /// Identical to ```try_recv```, but also returns the lane the value was sent on
+    pub fn try_recv_with_lane(&self) -> Result<(usize, T), TryRecvError> {
+        let num = self.receivers.len();
+        let by_turn = self.starvation_limit != 0 && self.streak.get() >= self.starvation_limit;
+        let start = self.next_turn.get();
+        let mut any_empty = false;
+        for i in 0..num {
+            let lane = if by_turn {
+                (start + num - i) % num
+            } else {
+                num - 1 - i
+            };
+            match self.receivers[lane].try_recv() {
+                Ok(val) => {
+                    if by_turn {
+                        self.streak.set(0);
+                        self.next_turn.set((lane + num - 1) % num);
+                    } else {
+                        self.streak.set(self.streak.get() + 1);
+                    }
+                    return Ok((lane, val));
+                }
+                Err(TryRecvError::Empty) => any_empty = true,
+                Err(TryRecvError::Disconnected) => (),
+            }
+        }
+        if any_empty {
+            Err(TryRecvError::Empty)
+        } else {
+            Err(TryRecvError::Disconnected)
+        }
+    }
+
+    /// Receives a value from the highest lane which has one,
+    /// blocking on all of the lanes if they're empty.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::mpmc_priority_queue;
+    /// use std::thread;
+    ///
+    /// let (send, recv) = mpmc_priority_queue(4, 2);
+    ///
+    /// let handle = thread::spawn(move || {
+    ///     let mut got = Vec::new();
+    ///     while let Ok(val) = recv.recv() {
+    ///         got.push(val);
+    ///     }
+    ///     got
+    /// });
+    ///
+    /// send.try_send(1, 10).unwrap();
+    /// drop(send);
+    /// assert_eq!(vec![10], handle.join().unwrap());
+    /// ```
+    pub fn recv(&self) -> Result<T, RecvError> {
+        self.recv_with_lane().map(|(_, v)| v)
+    }
+
+    /// Identical to ```recv```, but also returns the lane the value was sent on
+    pub fn recv_with_lane(&self) -> Result<(usize, T), RecvError> {
+        self.doorbell.recv(
+            || self.try_recv_with_lane(),
+            |bell| {
+                let mut rung = true;
+                for recv in &self.receivers {
+                    rung &= recv.add_doorbell(bell);
+                }
+                rung
+            },
+            |bell| {
+                for recv in &self.receivers {
+                    recv.remove_doorbell(bell);
+                }
+            },
+        )
+    }
+
+    /// Lets lower lanes through once in a while so that a steady stream of high
+    /// priority values can't starve them. After limit values in a row, the next value
+    /// is taken from the lanes in turn instead of by priority. Zero, the default,
+    /// means values are always taken strictly by priority.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::mpmc_priority_queue;
+    ///
+    /// let (send, mut recv) = mpmc_priority_queue(8, 2);
+    /// recv.set_starvation_limit(2);
+    /// for i in 0..4 {
+    ///     send.try_send(1, i).unwrap();
+    /// }
+    /// send.try_send(0, 100).unwrap();
+    ///
+    /// assert_eq!(0, recv.try_recv().unwrap());
+    /// assert_eq!(1, recv.try_recv().unwrap());
+    /// // The low lane gets a turn
+    /// assert_eq!(100, recv.try_recv().unwrap());
+    /// assert_eq!(2, recv.try_recv().unwrap());
+    /// ```
+    pub fn set_starvation_limit(&mut self, limit: usize) {
+        self.starvation_limit = limit;
+        self.streak.set(0);
+    }
+
+    /// Returns the number of lanes in the queue
+    pub fn lanes(&self) -> usize {
+        self.receivers.len()
+    }
+
+    /// Returns the number of items waiting on each lane, lowest lane first
+    pub fn lane_lags(&self) -> Vec<usize> {
+        self.receivers.iter().map(|recv| recv.lag()).collect()
+    }
+
+    /// Removes this receiver from the queue.
+    /// Returns true if this was the last receiver
+    pub fn unsubscribe(self) -> bool {
+        let mut last = false;
+        for receiver in self.receivers {
+            last = receiver.unsubscribe();
+        }
+        last
+    }
+}
+
+impl<T> Clone for MPMCPriorityReceiver<T> {
+    fn clone(&self) -> MPMCPriorityReceiver<T> {
+        MPMCPriorityReceiver {
+            receivers: self.receivers.clone(),
+            starvation_limit: self.starvation_limit,
+            streak: Cell::new(0),
+            next_turn: Cell::new(self.next_turn.get()),
+            doorbell: Doorbell::new(),
+        }
+    }
+}
+
+/// Creates a (```MPMCPrioritySender```, ```MPMCPriorityReceiver```) pair with the
+/// passed number of lanes, each of which holds capacity values.
+///
+/// # Examples
+///
+/// ```
+/// use multiqueue2::mpmc_priority_queue;
+/// let (w, r) = mpmc_priority_queue(4, 2);
+/// w.try_send(0, 1).unwrap();
+/// w.try_send(1, 2).unwrap();
+/// assert_eq!((1, 2), r.try_recv_with_lane().unwrap());
+/// assert_eq!((0, 1), r.try_recv_with_lane().unwrap());
+/// ```
+pub fn mpmc_priority_queue<T>(
+    capacity: usize,
+    lanes: usize,
+) -> (MPMCPrioritySender<T>, MPMCPriorityReceiver<T>) {
+    assert!(lanes > 0, "Multiqueue error - zero lanes received");
+    let (senders, receivers) = rings(capacity, lanes);
+    (
+        MPMCPrioritySender { senders },
+        MPMCPriorityReceiver {
+            receivers,
+            starvation_limit: 0,
+            streak: Cell::new(0),
+            // Turns start below the highest lane, which doesn't need them
+            next_turn: Cell::new(lanes.saturating_sub(2)),
+            doorbell: Doorbell::new(),
+        },
+    )
+}
+
+unsafe impl<T: Send> Send for MPMCPrioritySender<T> {}
+unsafe impl<T: Send> Send for MPMCPriorityReceiver<T> {}
+
+#[cfg(test)]
+mod test {
+
+    use super::mpmc_priority_queue;
+
+    extern crate crossbeam;
+    use self::crossbeam::scope;
+
+    use std::sync::mpsc::TryRecvError;
+    use std::thread::yield_now;
+
+    #[test]
+    fn test_priority_order() {
+        let (writer, reader) = mpmc_priority_queue(4, 3);
+        assert_eq!(3, writer.lanes());
+        for i in 0..4 {
+            for lane in 0..3 {
+                writer.try_send(lane, (lane, i)).unwrap();
+            }
+        }
+        // Each lane has its own capacity
+        assert!(writer.try_send(1, (1, 4)).is_err());
+        assert_eq!(vec![4, 4, 4], reader.lane_lags());
+        for lane in (0..3).rev() {
+            for i in 0..4 {
+                assert_eq!((lane, (lane, i)), reader.try_recv_with_lane().unwrap());
+            }
+        }
+        assert_eq!(Err(TryRecvError::Empty), reader.try_recv());
+        drop(writer);
+        assert_eq!(Err(TryRecvError::Disconnected), reader.try_recv());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_bad_lane() {
+        let (writer, _reader) = mpmc_priority_queue(4, 2);
+        let _ = writer.try_send(2, 0);
+    }
+
+    #[test]
+    fn test_starvation_limit() {
+        let (writer, mut reader) = mpmc_priority_queue(16, 3);
+        reader.set_starvation_limit(3);
+        for i in 0..8 {
+            writer.try_send(2, 200 + i).unwrap();
+        }
+        writer.try_send(1, 100).unwrap();
+        writer.try_send(0, 0).unwrap();
+        let got: Vec<_> = (0..10).map(|_| reader.try_recv().unwrap()).collect();
+        assert_eq!(vec![200, 201, 202, 100, 203, 204, 205, 0, 206, 207], got);
+    }
+
+    #[test]
+    fn test_priority_threaded() {
+        let (writer, reader) = mpmc_priority_queue(4, 3);
+        let num_loop = 10000;
+        scope(|scope| {
+            for lane in 0..3 {
+                let cur_writer = writer.clone();
+                scope.spawn(move |_| {
+                    for i in 0..num_loop {
+                        while cur_writer.try_send(lane, i).is_err() {
+                            yield_now();
+                        }
+                    }
+                });
+            }
+            drop(writer);
+            for _ in 0..2 {
+                let cur_reader = reader.clone();
+                scope.spawn(move |_| {
+                    let mut last = [None; 3];
+                    while let Ok((lane, i)) = cur_reader.recv_with_lane() {
+                        if let Some(prev) = last[lane] {
+                            assert!(prev < i);
+                        }
+                        last[lane] = Some(i);
+                    }
+                });
+            }
+            reader.unsubscribe();
+        })
+        .unwrap();
+    }
+}
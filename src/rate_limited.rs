@@ -0,0 +1,732 @@
+//! Support for senders and receivers which hold the values they handle to a rate
+
+extern crate futures;
+extern crate parking_lot;
+
+use crate::clock::{Clock, MonotonicClock};
+use crate::error::FrozenTrySendError;
+use crate::multiqueue::{FutInnerSend, InnerRecv, InnerSend, QueueRW};
+use crate::wait::Backoff;
+
+use self::futures::task::{current, Task};
+use self::futures::{AsyncSink, Poll, Sink, StartSend};
+
+use std::cell::Cell;
+use std::sync::mpsc::{RecvError, SendError, TryRecvError};
+use std::sync::{Arc, Once};
+use std::thread::{self, sleep};
+use std::time::{Duration, Instant};
+
+/// Hands out up to burst tokens at once, which come back at per_second a second
+struct TokenBucket {
+    clock: Arc<dyn Clock>,
+    // The time it takes for one token to come back, at least a nanosecond
+    interval: Duration,
+    burst: u32,
+    // How far ahead of the clock next_at can get before the bucket is empty
+    tolerance: Duration,
+    // When the bucket will be full again if no more tokens are taken. The bucket
+    // is empty once this is more than tolerance ahead of the clock
+    next_at: Cell<Instant>,
+}
+
+impl TokenBucket {
+    fn new(per_second: u32, burst: u32, clock: Arc<dyn Clock>) -> TokenBucket {
+        assert!(per_second > 0, "Multiqueue error - zero rate received");
+        assert!(burst > 0, "Multiqueue error - zero burst received");
+        // Tokens come back a whole number of nanoseconds apart
+        assert!(
+            per_second <= 1_000_000_000,
+            "Multiqueue error - rate above a billion a second received"
+        );
+        let interval = Duration::from_secs(1) / per_second;
+        TokenBucket {
+            next_at: Cell::new(clock.now()),
+            clock,
+            interval,
+            burst,
+            tolerance: interval * (burst - 1),
+        }
+    }
+
+    /// Returns how far the bucket is from being full
+    fn used(&self) -> Duration {
+        let now = self.clock.now();
+        self.next_at.get().max(now) - now
+    }
+
+    fn has_token(&self) -> bool {
+        self.used() <= self.tolerance
+    }
+
+    fn take(&self) {
+        let now = self.clock.now();
+        self.next_at
+            .set(self.next_at.get().max(now) + self.interval);
+    }
+
+    fn until_token(&self) -> Option<Duration> {
+        self.used()
+            .checked_sub(self.tolerance)
+            .filter(|d| *d > Duration::from_secs(0))
+    }
+
+    fn available(&self) -> usize {
+        let used = self.used().as_nanos().div_ceil(self.interval.as_nanos()) as usize;
+        (self.burst as usize).saturating_sub(used)
+    }
+
+    /// Sleeps until there's a token. Nobody else takes tokens from the bucket,
+    /// so one can't come any sooner
+    fn wait_for_token(&self) {
+        while let Some(wait) = self.until_token() {
+            sleep(wait);
+        }
+    }
+}
+
+/// Wakes up tasks once their deadlines pass, from a thread which is started the
+/// first time a task is handed over
+struct Wakeups {
+    pending: parking_lot::Mutex<Vec<(Instant, Task)>>,
+    changed: parking_lot::Condvar,
+}
+
+static WAKEUPS: Wakeups = Wakeups {
+    pending: parking_lot::const_mutex(Vec::new()),
+    changed: parking_lot::Condvar::new(),
+};
+
+static WAKEUPS_STARTED: Once = Once::new();
+
+impl Wakeups {
+    /// Wakes up the current task once wait has passed
+    fn wake_current_after(wait: Duration) {
+        WAKEUPS_STARTED.call_once(|| {
+            thread::Builder::new()
+                .name("multiqueue-wakeups".into())
+                .spawn(|| WAKEUPS.run())
+                .expect("Multiqueue error - couldn't start the wake-up thread");
+        });
+        WAKEUPS
+            .pending
+            .lock()
+            .push((Instant::now() + wait, current()));
+        WAKEUPS.changed.notify_one();
+    }
+
+    fn run(&self) {
+        let mut pending = self.pending.lock();
+        loop {
+            let now = Instant::now();
+            pending.retain(|&(at, ref task)| {
+                if at <= now {
+                    task.notify();
+                }
+                at > now
+            });
+            match pending.iter().map(|&(at, _)| at).min() {
+                Some(at) => {
+                    self.changed.wait_until(&mut pending, at);
+                }
+                None => self.changed.wait(&mut pending),
+            }
+        }
+    }
+}
+
+trait Target<T> {
+    fn try_send(&self, val: T) -> Result<(), FrozenTrySendError<T>>;
+    fn stream_count(&self) -> usize;
+}
+
+impl<RW: QueueRW<T>, T> Target<T> for InnerSend<RW, T> {
+    fn try_send(&self, val: T) -> Result<(), FrozenTrySendError<T>> {
+        InnerSend::try_send(self, val)
+    }
+
+    fn stream_count(&self) -> usize {
+        InnerSend::stream_count(self)
+    }
+}
+
+/// This sends at most a given number of values a second into the queue, with
+/// room for a burst of values sent all at once after it's been quiet. Each value
+/// takes a token from a bucket holding up to burst of them, which refills at
+/// the rate. ```try_send``` fails with ```Full``` when the bucket is empty, just as
+/// it does when the queue is, while ```send``` waits for both a token and space.
+///
+/// A token is only taken when the value goes into the queue, so values which
+/// don't fit don't use up the budget. The budget belongs to this sender, so
+/// senders which should share one have to share this sender. Rates above a
+/// billion a second are rejected, since tokens come back whole nanoseconds apart.
+/// These are made with ```rate_limited``` on ```BroadcastSender``` and ```MPMCSender```.
+///
+/// # Examples
+///
+/// ```
+/// use multiqueue2::{mpmc_queue, FrozenTrySendError};
+///
+/// let (w, r) = mpmc_queue(8);
+/// let w = w.rate_limited(10, 2);
+/// w.try_send(1).unwrap();
+/// w.try_send(2).unwrap();
+/// // The burst is used up, so the next token comes in a tenth of a second
+/// assert_eq!(Err(FrozenTrySendError::Full(3)), w.try_send(3));
+/// w.send(3).unwrap();
+/// assert_eq!(vec![1, 2, 3], r.try_iter().collect::<Vec<_>>());
+/// ```
+pub struct RateLimitedSender<T> {
+    sender: Box<dyn Target<T>>,
+    bucket: TokenBucket,
+}
+
+impl<T> RateLimitedSender<T> {
+    pub(crate) fn new<RW: QueueRW<T> + 'static>(
+        sender: InnerSend<RW, T>,
+        per_second: u32,
+        burst: u32,
+    ) -> RateLimitedSender<T>
+    where
+        T: 'static,
+    {
+        RateLimitedSender::with_clock(sender, per_second, burst, Arc::new(MonotonicClock))
+    }
+
+    pub(crate) fn with_clock<RW: QueueRW<T> + 'static>(
+        sender: InnerSend<RW, T>,
+        per_second: u32,
+        burst: u32,
+        clock: Arc<dyn Clock>,
+    ) -> RateLimitedSender<T>
+    where
+        T: 'static,
+    {
+        RateLimitedSender {
+            sender: Box::new(sender),
+            bucket: TokenBucket::new(per_second, burst, clock),
+        }
+    }
+
+    /// Tries to send a value into the queue. Fails with ```Full``` if there's no
+    /// token for it yet as well as when the queue is full
+    pub fn try_send(&self, val: T) -> Result<(), FrozenTrySendError<T>> {
+        if !self.bucket.has_token() {
+            return Err(FrozenTrySendError::Full(val));
+        }
+        self.sender.try_send(val)?;
+        self.bucket.take();
+        Ok(())
+    }
+
+    /// Sends a value into the queue, waiting for a token and for space in the queue
+    /// if there isn't any. The wait for a token sleeps until it's due. Receivers
+    /// don't wake writers on these queues, so while the queue is full it's polled,
+    /// sleeping for at most DEFAULT_SELECT_MAX_SLEEP_US microseconds at a time.
+    /// ```RateLimitedFutSender``` parks until there's space instead.
+    /// Fails once every stream on the queue has unsubscribed
+    pub fn send(&self, mut val: T) -> Result<(), SendError<T>> {
+        let mut backoff = Backoff::new();
+        loop {
+            // Values can still be written for a while after the streams are gone
+            if self.sender.stream_count() == 0 {
+                return Err(SendError(val));
+            }
+            self.bucket.wait_for_token();
+            match self.sender.try_send(val) {
+                Ok(()) => {
+                    self.bucket.take();
+                    return Ok(());
+                }
+                Err(FrozenTrySendError::Full(v))
+                | Err(FrozenTrySendError::Disconnected(v))
+                | Err(FrozenTrySendError::Frozen(v)) => val = v,
+            }
+            backoff.snooze(None);
+        }
+    }
+
+    /// Returns how long until there's a token, if there isn't one now
+    pub fn until_token(&self) -> Option<Duration> {
+        self.bucket.until_token()
+    }
+
+    /// Returns how many values can be sent right now without waiting for a token
+    pub fn available(&self) -> usize {
+        self.bucket.available()
+    }
+
+    /// Returns the most values which can be sent at once after the sender's been quiet
+    pub fn burst(&self) -> u32 {
+        self.bucket.burst
+    }
+
+    /// Removes the writer from the queue
+    pub fn unsubscribe(self) {}
+}
+
+trait FutTarget<T> {
+    fn try_send(&self, val: T) -> Result<(), FrozenTrySendError<T>>;
+    fn start_send(&self, val: T) -> StartSend<T, SendError<T>>;
+    fn poll_complete(&self) -> Poll<(), SendError<T>>;
+}
+
+impl<RW: QueueRW<T>, T> FutTarget<T> for FutInnerSend<RW, T> {
+    fn try_send(&self, val: T) -> Result<(), FrozenTrySendError<T>> {
+        FutInnerSend::try_send(self, val)
+    }
+
+    fn start_send(&self, val: T) -> StartSend<T, SendError<T>> {
+        Sink::start_send(&mut &*self, val)
+    }
+
+    fn poll_complete(&self) -> Poll<(), SendError<T>> {
+        Sink::poll_complete(&mut &*self)
+    }
+}
+
+/// The futures variant of ```RateLimitedSender```, which implements ```Sink```.
+/// When there's no token ```start_send``` returns ```NotReady``` and has the task
+/// woken once the token is due. When the queue is full it parks the task until
+/// a receiver makes room, just as the sender it was made from does.
+/// These are made with ```rate_limited``` on ```BroadcastFutSender``` and ```MPMCFutSender```.
+///
+/// # Examples
+///
+/// ```
+/// extern crate futures;
+/// # extern crate multiqueue2;
+///
+/// use futures::{Future, Sink, Stream};
+/// use multiqueue2::mpmc_fut_queue;
+///
+/// let (w, r) = mpmc_fut_queue(8);
+/// let w = w.rate_limited(100, 2);
+/// // The first two go straight in and the last one waits for its token
+/// let w = w.send_all(futures::stream::iter_ok(vec![1, 2, 3])).wait().unwrap().0;
+/// drop(w);
+/// assert_eq!(vec![1, 2, 3], r.wait().map(|v| v.unwrap()).collect::<Vec<_>>());
+/// ```
+pub struct RateLimitedFutSender<T> {
+    sender: Box<dyn FutTarget<T>>,
+    bucket: TokenBucket,
+}
+
+impl<T> RateLimitedFutSender<T> {
+    pub(crate) fn new<RW: QueueRW<T> + 'static>(
+        sender: FutInnerSend<RW, T>,
+        per_second: u32,
+        burst: u32,
+    ) -> RateLimitedFutSender<T>
+    where
+        T: 'static,
+    {
+        RateLimitedFutSender::with_clock(sender, per_second, burst, Arc::new(MonotonicClock))
+    }
+
+    pub(crate) fn with_clock<RW: QueueRW<T> + 'static>(
+        sender: FutInnerSend<RW, T>,
+        per_second: u32,
+        burst: u32,
+        clock: Arc<dyn Clock>,
+    ) -> RateLimitedFutSender<T>
+    where
+        T: 'static,
+    {
+        RateLimitedFutSender {
+            sender: Box::new(sender),
+            bucket: TokenBucket::new(per_second, burst, clock),
+        }
+    }
+
+    /// Equivalent to ```RateLimitedSender::try_send```
+    pub fn try_send(&self, val: T) -> Result<(), FrozenTrySendError<T>> {
+        if !self.bucket.has_token() {
+            return Err(FrozenTrySendError::Full(val));
+        }
+        self.sender.try_send(val)?;
+        self.bucket.take();
+        Ok(())
+    }
+
+    /// Equivalent to ```RateLimitedSender::until_token```
+    pub fn until_token(&self) -> Option<Duration> {
+        self.bucket.until_token()
+    }
+
+    /// Equivalent to ```RateLimitedSender::available```
+    pub fn available(&self) -> usize {
+        self.bucket.available()
+    }
+
+    /// Equivalent to ```RateLimitedSender::burst```
+    pub fn burst(&self) -> u32 {
+        self.bucket.burst
+    }
+
+    /// Removes the writer from the queue
+    pub fn unsubscribe(self) {}
+}
+
+impl<T> Sink for &RateLimitedFutSender<T> {
+    type SinkItem = T;
+    type SinkError = SendError<T>;
+
+    fn start_send(&mut self, msg: T) -> StartSend<T, SendError<T>> {
+        if let Some(wait) = self.bucket.until_token() {
+            Wakeups::wake_current_after(wait);
+            return Ok(AsyncSink::NotReady(msg));
+        }
+        let sent = self.sender.start_send(msg)?;
+        if sent.is_ready() {
+            self.bucket.take();
+        }
+        Ok(sent)
+    }
+
+    #[inline(always)]
+    fn poll_complete(&mut self) -> Poll<(), SendError<T>> {
+        self.sender.poll_complete()
+    }
+}
+
+impl<T> Sink for RateLimitedFutSender<T> {
+    type SinkItem = T;
+    type SinkError = SendError<T>;
+
+    #[inline(always)]
+    fn start_send(&mut self, msg: T) -> StartSend<T, SendError<T>> {
+        (&*self).start_send(msg)
+    }
+
+    #[inline(always)]
+    fn poll_complete(&mut self) -> Poll<(), SendError<T>> {
+        (&*self).poll_complete()
+    }
+}
+
+trait Source<T> {
+    fn try_recv(&self) -> Result<T, TryRecvError>;
+    fn recv(&self) -> Result<T, RecvError>;
+    fn lag(&self) -> usize;
+}
+
+impl<RW: QueueRW<T>, T> Source<T> for InnerRecv<RW, T> {
+    fn try_recv(&self) -> Result<T, TryRecvError> {
+        InnerRecv::try_recv(self)
+    }
+
+    fn recv(&self) -> Result<T, RecvError> {
+        InnerRecv::recv(self)
+    }
+
+    fn lag(&self) -> usize {
+        InnerRecv::lag(self)
+    }
+}
+
+/// This receives at most a given number of values a second from the queue, with
+/// room for a burst of values received all at once after it's been quiet. It's
+/// for consumers feeding something downstream which can only take values at a rate.
+/// Values are taken from the stream as they're received, so the ones waiting
+/// for their turn stay in the queue and show up in ```lag```. A stream which is
+/// paced more slowly than values are sent falls behind, and holds up the writers
+/// once the queue fills up.
+///
+/// ```try_recv``` fails with ```Empty``` when it's too soon for another value, just as
+/// it does when there isn't one, while ```recv``` waits for its turn and then for a value.
+/// These are made with ```paced``` on ```BroadcastReceiver``` and ```MPMCReceiver```.
+///
+/// # Examples
+///
+/// ```
+/// use multiqueue2::mpmc_queue;
+/// use std::sync::mpsc::TryRecvError;
+///
+/// let (w, r) = mpmc_queue(8);
+/// let r = r.paced(10, 2);
+/// for i in 0..3 {
+///     w.try_send(i).unwrap();
+/// }
+/// assert_eq!(0, r.try_recv().unwrap());
+/// assert_eq!(1, r.try_recv().unwrap());
+/// // The last one has to wait a tenth of a second for its turn
+/// assert_eq!(Err(TryRecvError::Empty), r.try_recv());
+/// assert_eq!(1, r.lag());
+/// assert_eq!(2, r.recv().unwrap());
+/// ```
+pub struct PacedReceiver<T> {
+    receiver: Box<dyn Source<T>>,
+    bucket: TokenBucket,
+}
+
+impl<T> PacedReceiver<T> {
+    pub(crate) fn new<RW: QueueRW<T> + 'static>(
+        receiver: InnerRecv<RW, T>,
+        per_second: u32,
+        burst: u32,
+    ) -> PacedReceiver<T>
+    where
+        T: 'static,
+    {
+        PacedReceiver::with_clock(receiver, per_second, burst, Arc::new(MonotonicClock))
+    }
+
+    pub(crate) fn with_clock<RW: QueueRW<T> + 'static>(
+        receiver: InnerRecv<RW, T>,
+        per_second: u32,
+        burst: u32,
+        clock: Arc<dyn Clock>,
+    ) -> PacedReceiver<T>
+    where
+        T: 'static,
+    {
+        PacedReceiver {
+            receiver: Box::new(receiver),
+            bucket: TokenBucket::new(per_second, burst, clock),
+        }
+    }
+
+    /// Tries to receive a value without blocking. Fails with ```Empty``` if it's too
+    /// soon for another value as well as when there isn't one
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        if !self.bucket.has_token() {
+            return Err(TryRecvError::Empty);
+        }
+        let val = self.receiver.try_recv()?;
+        self.bucket.take();
+        Ok(val)
+    }
+
+    /// Receives a value, first sleeping until it's time for another one and then
+    /// waiting for a value to be sent if there isn't one
+    pub fn recv(&self) -> Result<T, RecvError> {
+        self.bucket.wait_for_token();
+        let val = self.receiver.recv()?;
+        self.bucket.take();
+        Ok(val)
+    }
+
+    /// Returns the number of items between this stream and the write head,
+    /// including the ones waiting for their turn
+    pub fn lag(&self) -> usize {
+        self.receiver.lag()
+    }
+
+    /// Returns how long until another value can be received, if one can't be now
+    pub fn until_token(&self) -> Option<Duration> {
+        self.bucket.until_token()
+    }
+
+    /// Returns how many values can be received right now without waiting for their turn
+    pub fn available(&self) -> usize {
+        self.bucket.available()
+    }
+
+    /// Returns the most values which can be received at once after the receiver's been quiet
+    pub fn burst(&self) -> u32 {
+        self.bucket.burst
+    }
+
+    /// Removes the receiver from the queue
+    pub fn unsubscribe(self) {}
+}
+
+impl<T> Iterator for PacedReceiver<T> {
+    type Item = T;
+
+    #[inline(always)]
+    fn next(&mut self) -> Option<T> {
+        self.recv().ok()
+    }
+}
+
+unsafe impl<T: Send> Send for RateLimitedSender<T> {}
+unsafe impl<T: Send> Send for RateLimitedFutSender<T> {}
+unsafe impl<T: Send> Send for PacedReceiver<T> {}
+
+#[cfg(test)]
+mod test {
+
+    use super::{PacedReceiver, RateLimitedFutSender, RateLimitedSender};
+    use crate::clock::MockClock;
+    use crate::error::FrozenTrySendError;
+    use crate::multiqueue::{futures_multiqueue, MultiQueue, MPMC};
+
+    extern crate crossbeam;
+    use self::crossbeam::scope;
+
+    use super::futures::future::lazy;
+    use super::futures::{stream, AsyncSink, Future, Sink, Stream};
+
+    use std::sync::mpsc::{RecvError, TryRecvError};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_rate_limited() {
+        let clock = Arc::new(MockClock::new());
+        let (writer, reader) = MultiQueue::<MPMC<usize>, usize>::create_tx_rx(4);
+        let writer = RateLimitedSender::with_clock(writer, 10, 3, clock.clone());
+        assert_eq!((3, 3), (writer.burst(), writer.available()));
+        for i in 0..3 {
+            writer.try_send(i).unwrap();
+        }
+        assert_eq!(0, writer.available());
+        assert_eq!(Some(Duration::from_millis(100)), writer.until_token());
+        assert_eq!(Err(FrozenTrySendError::Full(3)), writer.try_send(3));
+        clock.advance(Duration::from_millis(150));
+        assert_eq!(None, writer.until_token());
+        assert_eq!(1, writer.available());
+        writer.try_send(3).unwrap();
+        assert_eq!(Some(Duration::from_millis(50)), writer.until_token());
+
+        // A full queue doesn't use up tokens
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(Err(FrozenTrySendError::Full(4)), writer.try_send(4));
+        assert_eq!(3, writer.available());
+        for i in 0..4 {
+            assert_eq!(i, reader.try_recv().unwrap());
+        }
+        assert_eq!(Err(TryRecvError::Empty), reader.try_recv());
+        writer.try_send(4).unwrap();
+        assert_eq!(2, writer.available());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_rate_limited_zero() {
+        let (writer, _reader) = MultiQueue::<MPMC<usize>, usize>::create_tx_rx(4);
+        RateLimitedSender::new(writer, 0, 1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_rate_limited_too_fast() {
+        let (writer, _reader) = MultiQueue::<MPMC<usize>, usize>::create_tx_rx(4);
+        RateLimitedSender::new(writer, 2_000_000_000, 1);
+    }
+
+    #[test]
+    fn test_rate_limited_fastest() {
+        let (writer, _reader) = MultiQueue::<MPMC<usize>, usize>::create_tx_rx(4);
+        let writer = RateLimitedSender::new(writer, 1_000_000_000, 3);
+        assert_eq!(3, writer.burst());
+        writer.try_send(0).unwrap();
+        assert!(writer.available() >= 2);
+    }
+
+    #[test]
+    fn test_rate_limited_threaded() {
+        let num_loop = 50;
+        let (writer, reader) = MultiQueue::<MPMC<usize>, usize>::create_tx_rx(4);
+        let start = Instant::now();
+        scope(|scope| {
+            scope.spawn(move |_| {
+                let writer = RateLimitedSender::new(writer, 1000, 10);
+                for i in 0..num_loop {
+                    writer.send(i).unwrap();
+                }
+            });
+            let mut got = Vec::new();
+            while let Ok(val) = reader.recv() {
+                got.push(val);
+            }
+            assert_eq!((0..num_loop).collect::<Vec<_>>(), got);
+        })
+        .unwrap();
+        // The burst goes out straight away and the rest at a millisecond each
+        assert!(start.elapsed() >= Duration::from_millis(40));
+    }
+
+    #[test]
+    fn test_rate_limited_fut() {
+        let clock = Arc::new(MockClock::new());
+        let (writer, reader) = futures_multiqueue::<MPMC<usize>, usize>(4);
+        let mut writer = RateLimitedFutSender::with_clock(writer, 10, 2, clock.clone());
+        lazy(|| {
+            assert_eq!(AsyncSink::Ready, writer.start_send(0).unwrap());
+            assert_eq!(AsyncSink::Ready, writer.start_send(1).unwrap());
+            assert_eq!(AsyncSink::NotReady(2), writer.start_send(2).unwrap());
+            assert_eq!(Err(FrozenTrySendError::Full(2)), writer.try_send(2));
+            clock.advance(Duration::from_millis(100));
+            assert_eq!(AsyncSink::Ready, writer.start_send(2).unwrap());
+            writer.poll_complete().unwrap();
+            Ok::<_, ()>(())
+        })
+        .wait()
+        .unwrap();
+        drop(writer);
+        let got: Vec<_> = reader.wait().map(|v| v.unwrap()).collect();
+        assert_eq!(vec![0, 1, 2], got);
+    }
+
+    #[test]
+    fn test_rate_limited_fut_threaded() {
+        let num_loop = 50;
+        let (writer, reader) = futures_multiqueue::<MPMC<usize>, usize>(4);
+        let start = Instant::now();
+        scope(|scope| {
+            scope.spawn(move |_| {
+                // The task is woken for each token and when the receiver makes room
+                let writer = RateLimitedFutSender::new(writer, 1000, 10);
+                let sent = writer.send_all(stream::iter_ok(0..num_loop)).wait();
+                assert!(sent.is_ok());
+            });
+            let got: Vec<_> = reader.wait().map(|v| v.unwrap()).collect();
+            assert_eq!((0..num_loop).collect::<Vec<_>>(), got);
+        })
+        .unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(40));
+    }
+
+    #[test]
+    fn test_paced() {
+        let clock = Arc::new(MockClock::new());
+        let (writer, reader) = MultiQueue::<MPMC<usize>, usize>::create_tx_rx(8);
+        let reader = PacedReceiver::with_clock(reader, 4, 2, clock.clone());
+        assert_eq!(Err(TryRecvError::Empty), reader.try_recv());
+        // Receiving nothing doesn't use up turns
+        assert_eq!(2, reader.available());
+        for i in 0..5 {
+            writer.try_send(i).unwrap();
+        }
+        assert_eq!(0, reader.try_recv().unwrap());
+        assert_eq!(1, reader.try_recv().unwrap());
+        assert_eq!(Err(TryRecvError::Empty), reader.try_recv());
+        assert_eq!(3, reader.lag());
+        assert_eq!(Some(Duration::from_millis(250)), reader.until_token());
+        clock.advance(Duration::from_millis(250));
+        assert_eq!(2, reader.recv().unwrap());
+        assert_eq!(Err(TryRecvError::Empty), reader.try_recv());
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(2, reader.available());
+        drop(writer);
+        assert_eq!(3, reader.recv().unwrap());
+        assert_eq!(4, reader.recv().unwrap());
+        clock.advance(Duration::from_millis(250));
+        assert_eq!(Err(RecvError), reader.recv());
+    }
+
+    #[test]
+    fn test_paced_threaded() {
+        let num_loop = 50;
+        let (writer, reader) = MultiQueue::<MPMC<usize>, usize>::create_tx_rx(64);
+        let start = Instant::now();
+        scope(|scope| {
+            scope.spawn(move |_| {
+                for i in 0..num_loop {
+                    writer.try_send(i).unwrap();
+                }
+            });
+            let reader = PacedReceiver::new(reader, 1000, 10);
+            assert_eq!(
+                (0..num_loop).collect::<Vec<_>>(),
+                reader.collect::<Vec<_>>()
+            );
+        })
+        .unwrap();
+        // The burst is received straight away and the rest at a millisecond each
+        assert!(start.elapsed() >= Duration::from_millis(40));
+    }
+}
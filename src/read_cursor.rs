@@ -1,12 +1,22 @@
-use std::cell::Cell;
+use std::borrow::Cow;
+use std::cell::{Cell, UnsafeCell};
+use std::mem::{self, MaybeUninit};
 use std::ptr;
-use std::sync::atomic::{fence, AtomicPtr, AtomicUsize, Ordering};
+use std::sync::atomic::Ordering;
 
 use crate::alloc;
 use crate::consume::CONSUME;
-use crate::countedindex::{past, CountedIndex, Index, Transaction};
+use crate::countedindex::{past, rm_tag, CountedIndex, Index, Transaction};
+use crate::error::StreamsFullError;
+#[cfg(feature = "test-util")]
+use crate::invariants::StreamCheck;
 use crate::maybe_acquire::{maybe_acquire_fence, MAYBE_ACQUIRE};
 use crate::memory::MemoryManager;
+#[cfg(feature = "order_checks")]
+use crate::order_check::OrderCheck;
+use crate::ordering::{ACQUIRE, RELAXED, SEQ_CST};
+use crate::stats::StreamStats;
+use crate::sync::{fence, AtomicBool, AtomicPtr, AtomicU64, AtomicUsize};
 
 #[derive(Clone, Copy, PartialEq)]
 enum ReaderState {
@@ -16,6 +26,22 @@ enum ReaderState {
 
 struct ReaderPos {
     pos_data: CountedIndex,
+    // Zero for streams which writers have to wait on
+    max_lag: usize,
+    // Set when writers may take values off the stream, which then always has to be claimed
+    shared: bool,
+    skipped: AtomicUsize,
+    // Writers don't wait on paused streams
+    paused: AtomicBool,
+    // Only set when the stream is created, so writers can read it freely
+    name: Option<Cow<'static, str>>,
+    // Freed along with the position so that writers can count the consumers too
+    meta: *const ReaderMeta,
+    // Where stalled_streams last saw the stream, and when it was last seen moving
+    seen_pos: AtomicUsize,
+    seen_at: AtomicU64,
+    #[cfg(feature = "stats")]
+    received: AtomicUsize,
 }
 
 struct ReaderMeta {
@@ -27,6 +53,8 @@ pub struct Reader {
     state: Cell<ReaderState>,
     pos: *const ReaderPos,
     meta: *const ReaderMeta,
+    #[cfg(feature = "order_checks")]
+    seen: OrderCheck,
 }
 
 /// This represents the reader attempt at loading a transaction
@@ -37,16 +65,44 @@ pub struct ReadAttempt<'a> {
     state: ReaderState,
 }
 
-/// This holds the set of readers currently active.
-/// This struct is held out of line from the cursor so it's easy to atomically replace it
-struct ReaderGroup {
-    readers: Vec<*const ReaderPos>,
+/// The number of streams each block of the cursor has room for
+const SLOTS_PER_BLOCK: usize = 8;
+
+/// This holds a fixed number of slots for the streams currently active.
+/// Streams are added by filling an empty slot and removed by emptying theirs,
+/// so nothing is copied and existing readers and writers are left alone.
+/// Blocks are only ever appended once the others are full, and live as long as the cursor
+struct ReaderBlock {
+    slots: [AtomicPtr<ReaderPos>; SLOTS_PER_BLOCK],
+    next: AtomicPtr<ReaderBlock>,
+}
+
+const SLOT_FREE: usize = 0;
+const SLOT_CLAIMED: usize = 1;
+const SLOT_LIVE: usize = 2;
+const SLOT_DEAD: usize = 3;
+
+/// A stream held directly in the cursor, for queues with a fixed number of streams.
+/// Writers look at them without chasing a pointer per stream. A removed stream's
+/// slot stays dead until nobody can be looking at it, and is then free to be reused
+pub struct FixedSlot {
+    state: AtomicUsize,
+    pos: UnsafeCell<MaybeUninit<ReaderPos>>,
+    meta: UnsafeCell<MaybeUninit<ReaderMeta>>,
 }
 
 #[repr(C)]
-pub struct ReadCursor {
-    readers: AtomicPtr<ReaderGroup>,
+pub struct ReadCursor<S: ?Sized = [FixedSlot]> {
+    first: ReaderBlock,
+    // Whether streams get created shared, for queues where writers displace values
+    shared: bool,
+    // Bumped after every stream that's added, so writers can tell they may have missed one
+    added: AtomicUsize,
+    // Raised before a stream is put in a slot and lowered after it's taken out
+    streams: AtomicUsize,
     pub last_pos: Cell<usize>,
+    // Only filled for queues with a fixed number of streams, which live here instead of in blocks
+    fixed: S,
 }
 
 impl<'a> ReadAttempt<'a> {
@@ -62,13 +118,10 @@ impl<'a> ReadAttempt<'a> {
                 self.linked.commit_direct(by, ord);
                 None
             }
-            ReaderState::Multi => match self.linked.commit(by, ord) {
-                Some(transaction) => Some(ReadAttempt {
-                    linked: transaction,
-                    state: ReaderState::Multi,
-                }),
-                None => None,
-            },
+            ReaderState::Multi => self.linked.commit(by, ord).map(|transaction| ReadAttempt {
+                linked: transaction,
+                state: ReaderState::Multi,
+            }),
         }
     }
 
@@ -89,11 +142,14 @@ impl<'a> ReadAttempt<'a> {
 impl Reader {
     /// Could this be done in a more compiler-friendly way
     #[inline(always)]
-    pub fn load_attempt(&self, ord: Ordering) -> ReadAttempt {
+    pub fn load_attempt(&self, ord: Ordering) -> ReadAttempt<'_> {
+        // Writers move bounded and shared streams forwards, so those always
+        // have to be read like there's somebody else on the stream
         if self.state.get() == ReaderState::Multi
-            && unsafe { (*self.meta).num_consumers.load(Ordering::Relaxed) } == 1
+            && unsafe { (*self.meta).num_consumers.load(RELAXED) } == 1
+            && !self.writers_move()
         {
-            fence(Ordering::Acquire);
+            fence(ACQUIRE);
             self.state.set(ReaderState::Single);
         }
         unsafe {
@@ -109,44 +165,198 @@ impl Reader {
         unsafe { (*self.pos).pos_data.load_count(ord) }
     }
 
+    /// Moves a reader which nobody else can see yet to the passed count
+    pub fn store_count(&self, raw: usize, ord: Ordering) {
+        unsafe { (*self.pos).pos_data.store_raw(raw, ord) }
+    }
+
+    /// Returns the largest number of items this stream may fall behind, or zero if unbounded
+    #[inline(always)]
+    pub fn max_lag(&self) -> usize {
+        unsafe { (*self.pos).max_lag }
+    }
+
+    #[inline(always)]
+    pub fn is_bounded(&self) -> bool {
+        self.max_lag() != 0
+    }
+
+    /// Returns whether writers may move the stream, by skipping it or taking values off it
+    #[inline(always)]
+    fn writers_move(&self) -> bool {
+        unsafe { (*self.pos).max_lag != 0 || (*self.pos).shared }
+    }
+
+    /// Moves the stream from the passed count forwards to the other one,
+    /// recording the items that got skipped over
+    pub fn skip_forward(&self, from: usize, to: usize) -> bool {
+        unsafe { (*self.pos).skip_forward(from, to) }
+    }
+
+    /// Returns the number of items skipped over since this was last called
+    #[inline(always)]
+    pub fn take_skipped(&self) -> usize {
+        unsafe {
+            let pos = &*self.pos;
+            if pos.skipped.load(RELAXED) == 0 {
+                0
+            } else {
+                pos.skipped.swap(0, RELAXED)
+            }
+        }
+    }
+
+    /// Excludes the stream from or returns it to the set of streams writers wait on
+    pub fn set_paused(&self, paused: bool) {
+        unsafe { (*self.pos).paused.store(paused, SEQ_CST) }
+    }
+
+    #[inline(always)]
+    pub fn is_paused(&self) -> bool {
+        unsafe { (*self.pos).paused.load(RELAXED) }
+    }
+
+    /// Counts values received from the stream
+    #[cfg(feature = "stats")]
+    #[inline(always)]
+    pub fn add_received(&self, count: usize) {
+        unsafe {
+            (*self.pos).received.fetch_add(count, RELAXED);
+        }
+    }
+
+    /// Panics unless this consumer got seq after everything it got before
+    #[cfg(feature = "order_checks")]
+    #[inline(always)]
+    pub fn check_order(&self, seq: usize) {
+        match self.stream_name() {
+            Some(name) => self.seen.saw(seq, &format!("consumer on stream {}", name)),
+            None => self.seen.saw(seq, "consumer"),
+        }
+    }
+
+    /// Returns the label the stream was created with, if any
+    pub fn stream_name(&self) -> Option<&str> {
+        unsafe { (*self.pos).name.as_deref() }
+    }
+
     pub fn dup_consumer(&self) {
         unsafe {
-            (*self.meta).num_consumers.fetch_add(1, Ordering::SeqCst);
+            (*self.meta).num_consumers.fetch_add(1, SEQ_CST);
         }
         self.state.set(ReaderState::Multi);
     }
 
     pub fn remove_consumer(&self) -> usize {
-        unsafe { (*self.meta).num_consumers.fetch_sub(1, Ordering::SeqCst) }
+        unsafe { (*self.meta).num_consumers.fetch_sub(1, SEQ_CST) }
     }
 
     #[inline(always)]
     pub fn get_consumers(&self) -> usize {
-        unsafe { (*self.meta).num_consumers.load(Ordering::Relaxed) }
+        unsafe { (*self.meta).num_consumers.load(RELAXED) }
     }
 
+    /// Returns whether values can be read without guarding them with the refcount
     #[inline(always)]
     pub fn is_single(&self) -> bool {
-        self.get_consumers() == 1
+        self.get_consumers() == 1 && !self.writers_move()
     }
 }
 
-impl ReaderGroup {
-    pub fn new() -> ReaderGroup {
-        ReaderGroup {
-            readers: Vec::new(),
+impl ReaderPos {
+    fn skip_forward(&self, from: usize, to: usize) -> bool {
+        let pos = &self.pos_data;
+        match pos.compare_exchange_raw(from, to, SEQ_CST) {
+            Ok(_) => {
+                self.skipped
+                    .fetch_add(rm_tag(to.wrapping_sub(from)), RELAXED);
+                // Pairs with the refcount taken by readers before they recheck their position,
+                // so that anybody still reading a skipped slot keeps writers off it
+                fence(SEQ_CST);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+impl ReaderBlock {
+    fn new() -> ReaderBlock {
+        ReaderBlock {
+            slots: [(); SLOTS_PER_BLOCK].map(|_| AtomicPtr::new(ptr::null_mut())),
+            next: AtomicPtr::new(ptr::null_mut()),
         }
     }
 
-    /// Only safe to call from a consumer of the queue!
-    pub unsafe fn add_stream(&self, raw: usize, wrap: Index) -> (*mut ReaderGroup, Reader) {
-        let new_meta = alloc::allocate(1);
-        let new_group = alloc::allocate(1);
-        let new_pos = alloc::allocate(1);
+    /// Puts the stream in the first empty slot, returning whether there was one
+    fn insert(&self, pos: *mut ReaderPos) -> bool {
+        self.slots.iter().any(|slot| {
+            slot.load(RELAXED).is_null()
+                && slot
+                    .compare_exchange(ptr::null_mut(), pos, SEQ_CST, RELAXED)
+                    .is_ok()
+        })
+    }
+
+    /// Empties the slot holding the stream, returning whether it was in this block
+    fn remove(&self, pos: *const ReaderPos) -> bool {
+        self.slots.iter().any(|slot| {
+            ptr::eq(slot.load(RELAXED), pos)
+                && slot
+                    .compare_exchange(pos as *mut ReaderPos, ptr::null_mut(), SEQ_CST, RELAXED)
+                    .is_ok()
+        })
+    }
+}
+
+/// Creates a stream at the passed count, along with the reader for its first consumer
+fn new_stream(
+    raw: usize,
+    wrap: Index,
+    max_lag: usize,
+    shared: bool,
+    name: Option<Cow<'static, str>>,
+) -> (*mut ReaderPos, Reader) {
+    let new_pos = alloc::allocate(1);
+    let new_reader = unsafe {
+        init_stream(
+            new_pos,
+            alloc::allocate(1),
+            raw,
+            wrap,
+            max_lag,
+            shared,
+            name,
+        )
+    };
+    (new_pos, new_reader)
+}
+
+/// Writes a stream at the passed count into the passed memory
+unsafe fn init_stream(
+    new_pos: *mut ReaderPos,
+    new_meta: *mut ReaderMeta,
+    raw: usize,
+    wrap: Index,
+    max_lag: usize,
+    shared: bool,
+    name: Option<Cow<'static, str>>,
+) -> Reader {
+    {
         ptr::write(
             new_pos,
             ReaderPos {
                 pos_data: CountedIndex::from_usize(raw, wrap),
+                max_lag,
+                shared,
+                skipped: AtomicUsize::new(0),
+                paused: AtomicBool::new(false),
+                name,
+                meta: new_meta as *const ReaderMeta,
+                seen_pos: AtomicUsize::new(usize::MAX),
+                seen_at: AtomicU64::new(0),
+                #[cfg(feature = "stats")]
+                received: AtomicUsize::new(0),
             },
         );
         ptr::write(
@@ -155,167 +365,511 @@ impl ReaderGroup {
                 num_consumers: AtomicUsize::new(1),
             },
         );
-        let new_reader = Reader {
-            state: Cell::new(ReaderState::Single),
-            pos: new_pos,
-            meta: new_meta as *const ReaderMeta,
+    }
+    // Streams writers can move are never read directly, even by a lone consumer
+    let state = if max_lag != 0 || shared {
+        ReaderState::Multi
+    } else {
+        ReaderState::Single
+    };
+    Reader {
+        state: Cell::new(state),
+        pos: new_pos,
+        meta: new_meta as *const ReaderMeta,
+        #[cfg(feature = "order_checks")]
+        seen: OrderCheck::new(),
+    }
+}
+
+impl FixedSlot {
+    fn new() -> FixedSlot {
+        FixedSlot {
+            state: AtomicUsize::new(SLOT_FREE),
+            pos: UnsafeCell::new(MaybeUninit::uninit()),
+            meta: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// Empties a dead slot once nobody can be looking at it, so it can be used again
+    unsafe fn reclaim(slot: *mut u8, _: usize) {
+        let slot = &*(slot as *const FixedSlot);
+        ptr::drop_in_place((*slot.pos.get()).as_mut_ptr());
+        ptr::drop_in_place((*slot.meta.get()).as_mut_ptr());
+        slot.state.store(SLOT_FREE, SEQ_CST);
+    }
+
+    /// Returns the stream in the slot if it's currently subscribed
+    #[inline(always)]
+    fn live(&self) -> Option<&ReaderPos> {
+        if self.state.load(ACQUIRE) == SLOT_LIVE {
+            Some(unsafe { (*self.pos.get()).assume_init_ref() })
+        } else {
+            None
+        }
+    }
+}
+
+impl ReadCursor {
+    /// Creates a cursor without any streams, which holds up to STREAMS of them in
+    /// place or, if that's zero, any number of them out of line. If shared is set,
+    /// every stream is created so that writers can take values off it.
+    /// Streams can only be added once the cursor is where it's going to stay
+    pub fn new<const STREAMS: usize>(shared: bool) -> ReadCursor<[FixedSlot; STREAMS]> {
+        ReadCursor {
+            first: ReaderBlock::new(),
+            shared,
+            added: AtomicUsize::new(0),
+            streams: AtomicUsize::new(0),
+            last_pos: Cell::new(0),
+            fixed: [(); STREAMS].map(|_| FixedSlot::new()),
+        }
+    }
+
+    /// Takes the first free slot, if there is one
+    fn claim_slot(&self) -> Option<&FixedSlot> {
+        self.fixed.iter().find(|slot| {
+            slot.state.load(RELAXED) == SLOT_FREE
+                && slot
+                    .state
+                    .compare_exchange(SLOT_FREE, SLOT_CLAIMED, SEQ_CST, RELAXED)
+                    .is_ok()
+        })
+    }
+
+    /// Fills the first free slot, which may have held a stream which was removed since
+    fn add_fixed_stream(
+        &self,
+        raw: usize,
+        wrap: Index,
+        max_lag: usize,
+        name: Option<Cow<'static, str>>,
+        mem: &MemoryManager,
+    ) -> Result<Reader, StreamsFullError> {
+        let slot = match self.claim_slot() {
+            Some(slot) => slot,
+            None => {
+                // Dead slots are handed back once nobody can be looking at them,
+                // which the backend may be holding off on until it has more to free
+                mem.hurry();
+                self.claim_slot().ok_or(StreamsFullError)?
+            }
         };
-        let mut new_readers = self.readers.clone();
-        new_readers.push(new_pos as *const ReaderPos);
-        ptr::write(
-            new_group,
-            ReaderGroup {
-                readers: new_readers,
-            },
-        );
-        (new_group, new_reader)
+        self.streams.fetch_add(1, SEQ_CST);
+        let reader = unsafe {
+            init_stream(
+                (*slot.pos.get()).as_mut_ptr(),
+                (*slot.meta.get()).as_mut_ptr(),
+                raw,
+                wrap,
+                max_lag,
+                self.shared,
+                name,
+            )
+        };
+        slot.state.store(SLOT_LIVE, SEQ_CST);
+        Ok(reader)
     }
 
-    pub unsafe fn remove_reader(&self, reader: *const ReaderPos) -> *mut ReaderGroup {
-        let new_group = alloc::allocate(1);
-        let mut new_readers = self.readers.clone();
-        new_readers.retain(|pt| *pt != reader);
-        ptr::write(
-            new_group,
-            ReaderGroup {
-                readers: new_readers,
-            },
-        );
-        new_group
+    /// Marks the reader's slot as dead, returning it
+    fn remove_fixed_stream(&self, reader: &Reader) -> &FixedSlot {
+        let slot = self
+            .fixed
+            .iter()
+            .find(|slot| unsafe { ptr::eq((*slot.pos.get()).as_ptr(), reader.pos) })
+            .expect("Multiqueue error - removing a stream which isn't in the queue");
+        slot.state.store(SLOT_DEAD, SEQ_CST);
+        slot
     }
 
-    pub fn get_max_diff(&self, cur_writer: usize) -> Option<Index> {
-        let mut max_diff: usize = 0;
-        unsafe {
-            for reader_ptr in &self.readers {
+    /// Runs the passed function on every stream currently in a slot,
+    /// stopping early if it returns false
+    #[inline(always)]
+    fn for_each_stream<F: FnMut(&ReaderPos) -> bool>(&self, mut f: F) {
+        if !self.fixed.is_empty() {
+            for slot in &self.fixed {
+                if let Some(pos) = slot.live() {
+                    if !f(pos) {
+                        return;
+                    }
+                }
+            }
+            return;
+        }
+        let mut block = &self.first;
+        loop {
+            for slot in &block.slots {
+                let pos = slot.load(CONSUME);
+                if !pos.is_null() && !f(unsafe { &*pos }) {
+                    return;
+                }
+            }
+            let next = block.next.load(CONSUME);
+            if next.is_null() {
+                return;
+            }
+            block = unsafe { &*next };
+        }
+    }
+
+    /// Returns how far behind the passed writer position the slowest stream is.
+    /// If skip is set, bounded streams which are too far behind get moved forwards
+    pub fn get_max_diff(&self, cur_writer: usize, skip: bool) -> Option<Index> {
+        loop {
+            let added = self.added.load(ACQUIRE);
+            let mut max_diff: usize = 0;
+            let mut ran_over = false;
+            self.for_each_stream(|reader| {
                 // If a reader has passed the writer during this function call
                 // then what must have happened is that somebody else has completed this
                 // written to the queue, and a reader has bypassed it. We should retry
-                let rpos = (**reader_ptr).pos_data.load_count(MAYBE_ACQUIRE);
-                let (diff, tofar) = past(cur_writer, rpos);
+                if reader.paused.load(RELAXED) {
+                    return true;
+                }
+                let mut rpos = reader.pos_data.load_count(MAYBE_ACQUIRE);
+                let (mut diff, tofar) = past(cur_writer, rpos);
                 if tofar {
-                    return None;
+                    ran_over = true;
+                    return false;
+                }
+                // Bounded streams get pushed forwards so that once the
+                // writer is done they are exactly max_lag behind
+                while skip && reader.max_lag != 0 && diff >= reader.max_lag {
+                    let to = cur_writer.wrapping_add(1).wrapping_sub(reader.max_lag);
+                    if reader.skip_forward(rpos, to) {
+                        diff = reader.max_lag - 1;
+                        break;
+                    }
+                    rpos = reader.pos_data.load_count(MAYBE_ACQUIRE);
+                    let (new_diff, tofar) = past(cur_writer, rpos);
+                    if tofar {
+                        ran_over = true;
+                        return false;
+                    }
+                    diff = new_diff;
                 }
                 max_diff = if diff > max_diff { diff } else { max_diff };
+                true
+            });
+            if ran_over {
+                return None;
+            }
+            maybe_acquire_fence();
+            // Streams which were removed during the scan can only have made the
+            // answer more conservative, but one which was added may have been missed.
+            // This is like a seqlock where only the final check is needed,
+            // since every slot stays valid to look at throughout
+            if self.added.load(RELAXED) == added {
+                return Some(max_diff as Index);
             }
         }
-        maybe_acquire_fence();
+    }
 
-        Some(max_diff as Index)
+    pub fn add_stream(
+        &self,
+        reader: &Reader,
+        name: Option<Cow<'static, str>>,
+        mem: &MemoryManager,
+    ) -> Reader {
+        self.try_add_stream(reader, name, mem)
+            .unwrap_or_else(|_| self.streams_full())
     }
-}
 
-impl ReadCursor {
-    pub fn new(wrap: Index) -> (ReadCursor, Reader) {
-        let rg = ReaderGroup::new();
+    /// Identical to add_stream, but returns an error instead of panicking
+    /// when the cursor has no room for another stream
+    pub fn try_add_stream(
+        &self,
+        reader: &Reader,
+        name: Option<Cow<'static, str>>,
+        mem: &MemoryManager,
+    ) -> Result<Reader, StreamsFullError> {
         unsafe {
-            let (real_group, reader) = rg.add_stream(0, wrap);
-            (
-                ReadCursor {
-                    readers: AtomicPtr::new(real_group),
-                    last_pos: Cell::new(0),
-                },
-                reader,
-            )
+            let raw = (*reader.pos).pos_data.load_raw(RELAXED);
+            let wrap = (*reader.pos).pos_data.wrap_at();
+            self.try_add_stream_at(raw, wrap, 0, name, mem)
         }
     }
 
-    pub fn get_max_diff(&self, cur_writer: usize) -> Option<Index> {
-        loop {
-            unsafe {
-                let first_ptr = self.readers.load(CONSUME);
-                let rg = &*first_ptr;
-                let rval = rg.get_max_diff(cur_writer);
-                // This check ensures that the pointer hasn't changed
-                // We must first read the diff, *and then* check the pointer
-                // for changes.
-                //
-                // We can also get away with having a writer change this during
-                // the load as long as it doesn't finish the change since the
-                // reader doing the change is pinned to the same spot. It's basically
-                // like a seqlock where only the final check is needed and not the first
-                // since the data remains valid throughout the write cycle
-                //
-                // We don't need another acquire fence here since the
-                // relevant loads in get_max_diff are going to ordered
-                // before all loads after the function exit, and also
-                // ordered after the original pointer load
-                let second_ptr = self.readers.load(Ordering::Relaxed);
-                if second_ptr == first_ptr {
-                    return rval;
-                }
-            }
-        }
+    /// Adds a stream starting at the passed count. The caller must ensure
+    /// that the writers can't be overwriting anything at or past that count.
+    /// A nonzero max_lag lets writers skip the stream forwards instead of waiting on it
+    pub fn add_stream_at(
+        &self,
+        raw: usize,
+        wrap: Index,
+        max_lag: usize,
+        name: Option<Cow<'static, str>>,
+        mem: &MemoryManager,
+    ) -> Reader {
+        self.try_add_stream_at(raw, wrap, max_lag, name, mem)
+            .unwrap_or_else(|_| self.streams_full())
     }
 
-    pub fn add_stream(&self, reader: &Reader, manager: &MemoryManager) -> Reader {
-        let mut current_ptr = self.readers.load(CONSUME);
-        loop {
-            unsafe {
-                let current_group = &*current_ptr;
-                let raw = (*reader.pos).pos_data.load_raw(Ordering::Relaxed);
-                let wrap = (*reader.pos).pos_data.wrap_at();
-                let (new_group, new_reader) = current_group.add_stream(raw, wrap);
-                fence(Ordering::SeqCst);
-                match self.readers.compare_exchange(
-                    current_ptr,
-                    new_group,
-                    Ordering::Relaxed,
-                    Ordering::Relaxed,
-                ) {
-                    Ok(_) => {
-                        fence(Ordering::SeqCst);
-                        manager.free(current_ptr, 1);
-                        return new_reader;
-                    }
+    #[cold]
+    fn streams_full(&self) -> ! {
+        panic!(
+            "Multiqueue error - all {} of the queue's streams are taken",
+            self.fixed.len()
+        )
+    }
+
+    /// Identical to add_stream_at, but returns an error instead of panicking
+    /// when the cursor has no room for another stream
+    pub fn try_add_stream_at(
+        &self,
+        raw: usize,
+        wrap: Index,
+        max_lag: usize,
+        name: Option<Cow<'static, str>>,
+        mem: &MemoryManager,
+    ) -> Result<Reader, StreamsFullError> {
+        if !self.fixed.is_empty() {
+            let new_reader = self.add_fixed_stream(raw, wrap, max_lag, name, mem)?;
+            self.added.fetch_add(1, SEQ_CST);
+            fence(SEQ_CST);
+            return Ok(new_reader);
+        }
+        let (pos, new_reader) = new_stream(raw, wrap, max_lag, self.shared, name);
+        self.streams.fetch_add(1, SEQ_CST);
+        let mut block = &self.first;
+        while !block.insert(pos) {
+            let mut next = block.next.load(CONSUME);
+            if next.is_null() {
+                let new_block = alloc::allocate(1);
+                unsafe { ptr::write(new_block, ReaderBlock::new()) };
+                match block
+                    .next
+                    .compare_exchange(ptr::null_mut(), new_block, SEQ_CST, CONSUME)
+                {
+                    Ok(_) => next = new_block,
                     Err(val) => {
-                        current_ptr = val;
-                        fence(Ordering::Acquire);
-                        ptr::read(new_group);
-                        alloc::deallocate(new_reader.meta as *mut ReaderMeta, 1);
-                        alloc::deallocate(new_reader.pos as *mut ReaderPos, 1);
-                        alloc::deallocate(new_group, 1);
+                        // Nobody else could have seen it yet
+                        alloc::deallocate(new_block, 1);
+                        next = val;
                     }
                 }
             }
+            block = unsafe { &*next };
+        }
+        self.added.fetch_add(1, SEQ_CST);
+        fence(SEQ_CST);
+        Ok(new_reader)
+    }
+
+    /// Returns a reader for the only stream, if there's exactly one, it isn't paused,
+    /// and it was created shared. The caller has to keep the stream's memory protected
+    /// while using it, and mustn't count it as a consumer
+    pub fn sole_stream(&self) -> Option<Reader> {
+        if !self.shared || self.streams.load(SEQ_CST) != 1 {
+            return None;
+        }
+        let mut sole = None;
+        self.for_each_stream(|reader| {
+            sole = Some(reader as *const ReaderPos);
+            false
+        });
+        let pos = sole?;
+        unsafe {
+            if (*pos).paused.load(RELAXED) {
+                return None;
+            }
+            Some(Reader {
+                state: Cell::new(ReaderState::Multi),
+                pos,
+                meta: (*pos).meta,
+                #[cfg(feature = "order_checks")]
+                seen: OrderCheck::new(),
+            })
         }
     }
 
+    /// Removes the reader's stream, returning whether it was the last one
     pub fn remove_reader(&self, reader: &Reader, mem: &MemoryManager) -> bool {
-        let mut current_group = self.readers.load(CONSUME);
-        loop {
+        let fixed = if self.fixed.is_empty() {
+            let mut block = &self.first;
+            while !block.remove(reader.pos) {
+                block = unsafe { &*block.next.load(CONSUME) };
+            }
+            None
+        } else {
+            Some(self.remove_fixed_stream(reader))
+        };
+        fence(SEQ_CST);
+        let was_last = self.streams.fetch_sub(1, SEQ_CST) == 1;
+        if was_last {
+            self.last_pos.set(reader.load_count(RELAXED));
+        }
+        // Writers which loaded the slot before it was emptied may still be looking
+        match fixed {
+            // The backend of a queue with fixed streams is its own, and hands back
+            // everything it still holds when it drops, which is before the cursor does
+            Some(slot) => mem.release(
+                slot as *const FixedSlot as *mut FixedSlot,
+                FixedSlot::reclaim,
+            ),
+            None => {
+                mem.free(reader.pos as *mut ReaderPos, 1);
+                mem.free(reader.meta as *mut ReaderMeta, 1);
+            }
+        }
+        was_last
+    }
+
+    /// Returns the label of each stream along with how far it is behind the passed writer position
+    pub fn stream_lags(&self, cur_writer: usize) -> Vec<(Option<String>, usize)> {
+        let mut lags = Vec::new();
+        self.for_each_stream(|reader| {
+            let (diff, tofar) = past(cur_writer, reader.pos_data.load_count(RELAXED));
+            let name = reader.name.as_ref().map(|name| name.to_string());
+            lags.push((name, if tofar { 0 } else { diff }));
+            true
+        });
+        lags
+    }
+
+    /// Returns the label of each stream which had items to read but didn't move between
+    /// threshold ago and now, along with how long it's been stuck. Streams are only seen
+    /// moving when this is called, so times are measured from the call which last saw them move
+    pub fn stalled_streams(
+        &self,
+        cur_writer: usize,
+        now: u64,
+        threshold: u64,
+    ) -> Vec<(Option<String>, u64)> {
+        let mut stalled = Vec::new();
+        self.for_each_stream(|reader| {
+            // Writers don't wait on paused streams, so they can't hold anything up
+            if reader.paused.load(RELAXED) {
+                return true;
+            }
+            let pos = reader.pos_data.load_count(RELAXED);
+            let (diff, tofar) = past(cur_writer, pos);
+            let moved = reader.seen_pos.swap(pos, RELAXED) != pos;
+            // Streams with nothing to read are waiting on the writers, not stuck
+            if moved || tofar || diff == 0 {
+                reader.seen_at.store(now, RELAXED);
+                return true;
+            }
+            let stuck = now.saturating_sub(reader.seen_at.load(RELAXED));
+            if stuck >= threshold {
+                let name = reader.name.as_ref().map(|name| name.to_string());
+                stalled.push((name, stuck));
+            }
+            true
+        });
+        stalled
+    }
+
+    /// Returns the state of every stream
+    pub fn stream_stats(&self) -> Vec<StreamStats> {
+        let mut stats = Vec::new();
+        self.for_each_stream(|reader| {
+            stats.push(StreamStats {
+                name: reader.name.as_ref().map(|name| name.to_string()),
+                tail: reader.pos_data.load_count(RELAXED),
+                consumers: unsafe { (*reader.meta).num_consumers.load(RELAXED) },
+                #[cfg(feature = "stats")]
+                received: reader.received.load(RELAXED),
+            });
+            true
+        });
+        stats
+    }
+
+    /// Returns how every stream looks, for checking the queue is consistent
+    #[cfg(feature = "test-util")]
+    pub fn stream_checks(&self) -> Vec<StreamCheck> {
+        let mut checks = Vec::new();
+        self.for_each_stream(|reader| {
+            checks.push(StreamCheck {
+                name: reader.name.as_ref().map(|name| name.to_string()),
+                pos: reader.pos_data.load_count(RELAXED),
+                paused: reader.paused.load(RELAXED),
+                consumers: unsafe { (*reader.meta).num_consumers.load(RELAXED) },
+            });
+            true
+        });
+        checks
+    }
+
+    /// Returns whether every stream which isn't paused has read up to the passed count
+    pub fn all_past(&self, count: usize) -> bool {
+        let mut passed = true;
+        self.for_each_stream(|reader| {
+            if reader.paused.load(RELAXED) {
+                return true;
+            }
+            // A stream past the count looks like it's too far behind it
+            let (diff, tofar) = past(count, reader.pos_data.load_count(MAYBE_ACQUIRE));
+            passed = diff == 0 || tofar;
+            passed
+        });
+        passed
+    }
+
+    /// Returns the position of the stream furthest behind the passed writer position,
+    /// leaving out paused streams since writers don't wait on them
+    pub fn slowest_pos(&self, cur_writer: usize) -> Option<usize> {
+        let mut slowest: Option<(usize, usize)> = None;
+        self.for_each_stream(|reader| {
+            if reader.paused.load(RELAXED) {
+                return true;
+            }
+            let pos = reader.pos_data.load_count(MAYBE_ACQUIRE);
+            let (diff, tofar) = past(cur_writer, pos);
+            let diff = if tofar { 0 } else { diff };
+            match slowest {
+                Some((most, _)) if most >= diff => (),
+                _ => slowest = Some((diff, pos)),
+            }
+            true
+        });
+        slowest.map(|(_, pos)| pos)
+    }
+
+    /// Returns how many bytes the cursor has allocated for streams, beyond itself
+    pub fn footprint(&self) -> usize {
+        // Fixed streams are part of the cursor
+        if !self.fixed.is_empty() {
+            return 0;
+        }
+        let mut blocks = 0;
+        let mut block = self.first.next.load(CONSUME);
+        while !block.is_null() {
+            blocks += 1;
+            block = unsafe { (*block).next.load(CONSUME) };
+        }
+        blocks * mem::size_of::<ReaderBlock>()
+            + self.num_streams() * (mem::size_of::<ReaderPos>() + mem::size_of::<ReaderMeta>())
+    }
+
+    pub fn num_streams(&self) -> usize {
+        self.streams.load(RELAXED)
+    }
+}
+
+impl<S: ?Sized> Drop for ReadCursor<S> {
+    fn drop(&mut self) {
+        // Every stream is gone by now, so only the blocks past the first are left.
+        // Fixed slots drop whatever streams they still hold themselves
+        let mut next = self.first.next.load(RELAXED);
+        while !next.is_null() {
             unsafe {
-                let new_group = (*current_group).remove_reader(reader.pos);
-                match self.readers.compare_exchange(
-                    current_group,
-                    new_group,
-                    Ordering::SeqCst,
-                    Ordering::SeqCst,
-                ) {
-                    Ok(_) => {
-                        fence(Ordering::SeqCst);
-                        if (*current_group).readers.len() == 1 {
-                            self.last_pos.set(reader.load_count(Ordering::Relaxed));
-                        }
-                        mem.free(current_group, 1);
-                        mem.free(reader.pos as *mut ReaderPos, 1);
-                        alloc::deallocate(reader.meta as *mut ReaderMeta, 1);
-                        return self.has_readers();
-                    }
-                    Err(val) => {
-                        current_group = val;
-                        ptr::read(new_group);
-                        alloc::deallocate(new_group, 1);
-                    }
-                }
+                let following = (*next).next.load(RELAXED);
+                ptr::read(next);
+                alloc::deallocate(next, 1);
+                next = following;
             }
         }
     }
+}
 
-    pub fn has_readers(&self) -> bool {
-        unsafe {
-            let current_group = &*self.readers.load(CONSUME);
-            current_group.readers.is_empty()
+impl Drop for FixedSlot {
+    fn drop(&mut self) {
+        if self.state.load(RELAXED) != SLOT_FREE {
+            unsafe {
+                ptr::drop_in_place((*self.pos.get()).as_mut_ptr());
+                ptr::drop_in_place((*self.meta.get()).as_mut_ptr());
+            }
         }
     }
 }
@@ -0,0 +1,198 @@
+//! A process wide list of every live queue, kept when the "registry" feature
+//! is enabled, so that something like a debug endpoint can look them all over.
+//!
+//! Queues are spread over a fixed number of shards by the id they get when
+//! they're created, so queues created on different threads at the same time
+//! rarely wait on each other. Each shard is only locked while a queue is added
+//! to or removed from it, or while ```live_queues``` reads the queues in it.
+//!
+//! # Examples
+//!
+//! ```
+//! use multiqueue2::broadcast_queue;
+//! use multiqueue2::registry::live_queues;
+//!
+//! let (w, _r) = broadcast_queue::<u64>(4);
+//! w.set_label("prices");
+//! w.try_send(1).unwrap();
+//!
+//! let report = live_queues()
+//!     .into_iter()
+//!     .find(|queue| queue.label.as_deref() == Some("prices"))
+//!     .unwrap();
+//! assert_eq!(1, report.stats.occupancy);
+//! assert_eq!("u64", report.value_type);
+//! ```
+
+use crate::multiqueue::{MultiQueue, QueueRW};
+use crate::stats::{MemoryFootprint, QueueStats};
+
+use std::borrow::Cow;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+extern crate parking_lot;
+
+/// The number of shards queues are spread over
+const SHARDS: usize = 16;
+
+static NEXT_QUEUE_ID: AtomicUsize = AtomicUsize::new(0);
+
+static REGISTRY: [parking_lot::Mutex<Vec<Entry>>; SHARDS] =
+    [const { parking_lot::const_mutex(Vec::new()) }; SHARDS];
+
+/// A queue in the registry. The queue takes itself out before it's freed,
+/// and has to take the lock on its shard to do so, so the pointer
+/// is valid for as long as the entry can be reached
+struct Entry {
+    id: usize,
+    label: Option<Cow<'static, str>>,
+    value_type: &'static str,
+    // Keeps the length of the streams held in place at the end of the queue
+    queue: *const [u8],
+    report: unsafe fn(*const [u8]) -> (QueueStats, MemoryFootprint),
+}
+
+unsafe impl Send for Entry {}
+
+/// What the registry knows about a live queue, taken with ```live_queues```
+#[derive(Clone, Debug)]
+pub struct QueueReport {
+    /// A number unique to the queue among every queue created by the process
+    pub id: usize,
+    /// The label given to the queue with ```set_label```, if any
+    pub label: Option<String>,
+    /// The name of the type of value the queue holds
+    pub value_type: &'static str,
+    /// A snapshot of the queue's state
+    pub stats: QueueStats,
+    /// How many bytes the queue takes up
+    pub memory: MemoryFootprint,
+}
+
+fn shard(id: usize) -> &'static parking_lot::Mutex<Vec<Entry>> {
+    &REGISTRY[id % SHARDS]
+}
+
+unsafe fn report<RW: QueueRW<T>, T>(queue: *const [u8]) -> (QueueStats, MemoryFootprint) {
+    let queue = &*(queue as *const MultiQueue<RW, T>);
+    (queue.stats(), queue.memory_footprint())
+}
+
+/// Hands out the id for a queue about to be created
+pub(crate) fn next_id() -> usize {
+    NEXT_QUEUE_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Adds the queue with the passed id. The queue has to call ```unregister```
+/// before it's freed
+pub(crate) fn register<RW: QueueRW<T>, T>(id: usize, queue: *const MultiQueue<RW, T>) {
+    shard(id).lock().push(Entry {
+        id,
+        label: None,
+        value_type: std::any::type_name::<T>(),
+        queue: queue as *const [u8],
+        report: report::<RW, T>,
+    });
+}
+
+/// Takes the queue with the passed id out, waiting for anything reading it to finish
+pub(crate) fn unregister(id: usize) {
+    let mut entries = shard(id).lock();
+    if let Some(at) = entries.iter().position(|entry| entry.id == id) {
+        entries.swap_remove(at);
+    }
+}
+
+/// Sets the label the queue with the passed id is listed under
+pub(crate) fn set_label(id: usize, label: Cow<'static, str>) {
+    if let Some(entry) = shard(id).lock().iter_mut().find(|entry| entry.id == id) {
+        entry.label = Some(label);
+    }
+}
+
+/// Returns a report on every queue which is currently alive, in no particular order.
+/// Each queue is looked at in turn, so the reports aren't all from the same moment
+pub fn live_queues() -> Vec<QueueReport> {
+    let mut reports = Vec::new();
+    for shard in REGISTRY.iter() {
+        for entry in shard.lock().iter() {
+            let (stats, memory) = unsafe { (entry.report)(entry.queue) };
+            reports.push(QueueReport {
+                id: entry.id,
+                label: entry.label.as_ref().map(|label| label.to_string()),
+                value_type: entry.value_type,
+                stats,
+                memory,
+            });
+        }
+    }
+    reports
+}
+
+/// Returns the number of queues which are currently alive
+pub fn live_count() -> usize {
+    REGISTRY.iter().map(|shard| shard.lock().len()).sum()
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{live_queues, QueueReport};
+    use crate::{broadcast_queue, mpmc_queue};
+
+    extern crate crossbeam;
+    use self::crossbeam::scope;
+
+    fn labelled(label: &str) -> Vec<QueueReport> {
+        live_queues()
+            .into_iter()
+            .filter(|queue| queue.label.as_deref() == Some(label))
+            .collect()
+    }
+
+    #[test]
+    fn test_registry() {
+        let (writer, reader) = mpmc_queue::<String>(8);
+        assert!(labelled("registry-test").is_empty());
+        reader.set_label("registry-test");
+        writer.try_send("a".to_string()).unwrap();
+        let reports = labelled("registry-test");
+        assert_eq!(1, reports.len());
+        assert_eq!(1, reports[0].stats.occupancy);
+        assert_eq!(writer.memory_footprint(), reports[0].memory);
+        assert!(reports[0].value_type.ends_with("String"));
+        // The label can be changed later
+        writer.set_label("registry-renamed");
+        assert!(labelled("registry-test").is_empty());
+        assert_eq!(reports[0].id, labelled("registry-renamed")[0].id);
+        drop(writer);
+        assert!(labelled("registry-renamed")[0].stats.closed);
+        drop(reader);
+        assert!(labelled("registry-renamed").is_empty());
+    }
+
+    #[test]
+    fn test_registry_threaded() {
+        scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|_| {
+                    for _ in 0..1000 {
+                        let (writer, reader) = broadcast_queue::<usize>(4);
+                        writer.set_label("registry-threaded");
+                        writer.try_send(1).unwrap();
+                        drop(writer);
+                        assert_eq!(1, reader.try_recv().unwrap());
+                    }
+                });
+            }
+            // Queues come and go while they're being looked at
+            for _ in 0..100 {
+                for report in labelled("registry-threaded") {
+                    assert!(report.stats.occupancy <= 1);
+                }
+            }
+        })
+        .unwrap();
+        assert!(labelled("registry-threaded").is_empty());
+    }
+}
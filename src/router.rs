@@ -0,0 +1,436 @@
+//! A publish/subscribe router which sends each message to the subscribers of its topic
+
+use crate::error::FrozenTrySendError;
+use crate::multiqueue::{BCast, InnerRecv, InnerSend, MultiQueue};
+use crate::wait::{select_wait, SelectTarget, YieldingWait};
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{RecvError, TryRecvError};
+use std::sync::Arc;
+
+extern crate parking_lot;
+
+/// The topics which currently have subscribers, each of which is its own broadcast queue.
+/// The registry holds a sender for every topic, which publishers clone,
+/// and subscribers add their streams through
+struct Registry<K, T: Clone> {
+    state: parking_lot::Mutex<RegistryState<K, T>>,
+    // Bumped whenever a topic comes or goes, so publishers know when to look again
+    changes: AtomicUsize,
+    capacity: usize,
+}
+
+struct RegistryState<K, T: Clone> {
+    topics: HashMap<K, InnerSend<BCast<T>, T>>,
+    publishers: usize,
+}
+
+impl<K: Hash + Eq + Clone, T: Clone> Registry<K, T> {
+    /// Adds a stream to the topic, creating it if nobody is subscribed yet.
+    /// Once the publishers are gone, new topics are disconnected from the start
+    fn subscribe(&self, topic: &K) -> InnerRecv<BCast<T>, T> {
+        let mut state = self.state.lock();
+        if let Some(sender) = state.topics.get(topic) {
+            return sender.subscribe();
+        }
+        // Subscribers wait on all of their topics at once with select_wait,
+        // so there's no point in having publishers notify anybody
+        let (send, recv) =
+            MultiQueue::<BCast<T>, T>::create_tx_rx_with(self.capacity, YieldingWait::new());
+        if state.publishers > 0 {
+            state.topics.insert(topic.clone(), send);
+            self.changes.fetch_add(1, Ordering::Release);
+        }
+        recv
+    }
+
+    /// Removes a stream from the topic, forgetting the topic if it was the last one
+    fn unsubscribe(&self, topic: &K, recv: InnerRecv<BCast<T>, T>) {
+        let mut state = self.state.lock();
+        recv.unsubscribe();
+        let gone = match state.topics.get(topic) {
+            Some(sender) => sender.stream_count() == 0,
+            None => false,
+        };
+        if gone {
+            state.topics.remove(topic);
+            self.changes.fetch_add(1, Ordering::Release);
+        }
+    }
+}
+
+/// This publishes messages to topics, which are received by every ```RouterReceiver```
+/// subscribed to the topic when the message is sent. Each topic is a broadcast queue
+/// of its own, so a full or slow topic doesn't hold up the others.
+///
+/// Publishing only takes a lock when topics have come or gone since the last
+/// message, and otherwise goes straight to a sender cached by this router.
+/// Cloning a router adds another publisher.
+///
+/// # Examples
+///
+/// ```
+/// use multiqueue2::Router;
+/// use std::thread;
+///
+/// let router = Router::new(16);
+/// let mut prices = router.subscriber();
+/// prices.subscribe("btc");
+/// prices.subscribe("eth");
+/// let mut everything = router.subscriber();
+/// everything.subscribe("btc");
+/// everything.subscribe("eth");
+/// everything.subscribe("news");
+///
+/// let publisher = router.clone();
+/// thread::spawn(move || {
+///     publisher.try_publish(&"btc", 1).unwrap();
+///     publisher.try_publish(&"news", 2).unwrap();
+///     publisher.try_publish(&"eth", 3).unwrap();
+/// })
+/// .join()
+/// .unwrap();
+/// drop(router);
+///
+/// let mut got: Vec<_> = prices.collect();
+/// got.sort();
+/// assert_eq!(vec![("btc", 1), ("eth", 3)], got);
+/// assert_eq!(3, everything.count());
+/// ```
+pub struct Router<K: Hash + Eq + Clone, T: Clone> {
+    registry: Arc<Registry<K, T>>,
+    senders: RefCell<HashMap<K, InnerSend<BCast<T>, T>>>,
+    seen: Cell<usize>,
+}
+
+/// This receives the messages published to a set of topics, along with their topic.
+/// Each topic's messages are received in the order they were published,
+/// and the topics take turns so that a busy one can't starve the rest.
+///
+/// Topics can be subscribed to and unsubscribed from at any time. Messages
+/// published to a topic before subscribing to it are never received.
+pub struct RouterReceiver<K: Hash + Eq + Clone, T: Clone> {
+    registry: Arc<Registry<K, T>>,
+    topics: Vec<(K, InnerRecv<BCast<T>, T>)>,
+    next: Cell<usize>,
+}
+
+impl<K: Hash + Eq + Clone, T: Clone> Router<K, T> {
+    /// Creates a router where each topic holds up to capacity messages
+    /// which some subscriber hasn't received yet
+    pub fn new(capacity: usize) -> Router<K, T> {
+        let state = RegistryState {
+            topics: HashMap::new(),
+            publishers: 1,
+        };
+        Router {
+            registry: Arc::new(Registry {
+                state: parking_lot::Mutex::new(state),
+                changes: AtomicUsize::new(0),
+                capacity,
+            }),
+            senders: RefCell::new(HashMap::new()),
+            seen: Cell::new(0),
+        }
+    }
+
+    /// Tries to publish a message to everybody subscribed to the topic.
+    /// Returns Disconnected if the topic has no subscribers, and Full if
+    /// one of them is a whole topic's capacity behind
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use multiqueue2::{FrozenTrySendError, Router};
+    ///
+    /// let router = Router::new(1);
+    /// assert_eq!(Err(FrozenTrySendError::Disconnected(1)), router.try_publish(&"a", 1));
+    /// let mut sub = router.subscriber();
+    /// sub.subscribe("a");
+    /// router.try_publish(&"a", 2).unwrap();
+    /// assert_eq!(Err(FrozenTrySendError::Full(3)), router.try_publish(&"a", 3));
+    /// assert_eq!(("a", 2), sub.try_recv().unwrap());
+    /// ```
+    pub fn try_publish(&self, topic: &K, val: T) -> Result<(), FrozenTrySendError<T>> {
+        let changes = self.registry.changes.load(Ordering::Acquire);
+        if changes != self.seen.get() {
+            // Senders for topics which have since gone can't reach anybody
+            self.senders.borrow_mut().clear();
+            self.seen.set(changes);
+        }
+        let mut senders = self.senders.borrow_mut();
+        if !senders.contains_key(topic) {
+            match self.registry.state.lock().topics.get(topic) {
+                Some(sender) => senders.insert(topic.clone(), sender.clone()),
+                None => return Err(FrozenTrySendError::Disconnected(val)),
+            };
+        }
+        let sender = &senders[topic];
+        match sender.try_send(val) {
+            // The last subscriber may have left after the sender was looked up
+            Err(FrozenTrySendError::Full(val)) if sender.stream_count() == 0 => {
+                Err(FrozenTrySendError::Disconnected(val))
+            }
+            rval => rval,
+        }
+    }
+
+    /// Creates a receiver which isn't subscribed to any topics yet
+    pub fn subscriber(&self) -> RouterReceiver<K, T> {
+        RouterReceiver {
+            registry: self.registry.clone(),
+            topics: Vec::new(),
+            next: Cell::new(0),
+        }
+    }
+
+    /// Returns the topics which currently have subscribers
+    pub fn topics(&self) -> Vec<K> {
+        self.registry.state.lock().topics.keys().cloned().collect()
+    }
+
+    /// Removes this publisher from the router
+    pub fn unsubscribe(self) {}
+}
+
+impl<K: Hash + Eq + Clone, T: Clone> Clone for Router<K, T> {
+    fn clone(&self) -> Router<K, T> {
+        self.registry.state.lock().publishers += 1;
+        Router {
+            registry: self.registry.clone(),
+            senders: RefCell::new(HashMap::new()),
+            seen: Cell::new(0),
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, T: Clone> Drop for Router<K, T> {
+    fn drop(&mut self) {
+        let mut state = self.registry.state.lock();
+        state.publishers -= 1;
+        if state.publishers == 0 {
+            // Once every sender is gone, the subscribers see each topic disconnect
+            state.topics.clear();
+            self.registry.changes.fetch_add(1, Ordering::Release);
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, T: Clone> RouterReceiver<K, T> {
+    /// Subscribes to the topic, receiving everything published to it from now on.
+    /// Returns false if this receiver was already subscribed to it
+    pub fn subscribe(&mut self, topic: K) -> bool {
+        if self.topics.iter().any(|(t, _)| *t == topic) {
+            return false;
+        }
+        let recv = self.registry.subscribe(&topic);
+        self.topics.push((topic, recv));
+        true
+    }
+
+    /// Stops receiving from the topic, dropping anything published to it which
+    /// hasn't been received yet. Returns false if this receiver wasn't subscribed to it
+    pub fn unsubscribe_from(&mut self, topic: &K) -> bool {
+        match self.topics.iter().position(|(t, _)| t == topic) {
+            Some(at) => {
+                let (topic, recv) = self.topics.remove(at);
+                self.registry.unsubscribe(&topic, recv);
+                self.next.set(0);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the topics this receiver is subscribed to
+    pub fn topics(&self) -> Vec<K> {
+        self.topics.iter().map(|(topic, _)| topic.clone()).collect()
+    }
+
+    /// Tries to receive a message from one of the subscribed topics without blocking,
+    /// taking turns between them. Returns Disconnected once the publishers are gone
+    /// and the subscribed topics are empty, or if there aren't any
+    #[inline(always)]
+    pub fn try_recv(&self) -> Result<(K, T), TryRecvError> {
+        self.poll(&mut None)
+    }
+
+    /// Receives a message from one of the subscribed topics,
+    /// waiting on all of them if they're empty
+    pub fn recv(&self) -> Result<(K, T), RecvError> {
+        let mut targets = Some(Vec::with_capacity(self.topics.len()));
+        loop {
+            match self.poll(&mut targets) {
+                Ok(val) => return Ok(val),
+                Err(TryRecvError::Disconnected) => return Err(RecvError),
+                Err(TryRecvError::Empty) => {
+                    if let Some(ref mut waiting) = targets {
+                        select_wait(waiting);
+                        waiting.clear();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Removes this receiver from every topic it's subscribed to
+    pub fn unsubscribe(self) {}
+
+    /// Goes through the topics once starting at next,
+    /// collecting what to wait on from the empty ones
+    fn poll<'a>(
+        &'a self,
+        targets: &mut Option<Vec<SelectTarget<'a>>>,
+    ) -> Result<(K, T), TryRecvError> {
+        let num = self.topics.len();
+        let start = self.next.get();
+        let mut any_empty = false;
+        for i in 0..num {
+            let at = (start + i) % num;
+            let (ref topic, ref recv) = self.topics[at];
+            match recv.try_recv_select() {
+                Ok(val) => {
+                    self.next.set((at + 1) % num);
+                    return Ok((topic.clone(), val));
+                }
+                Err((Some(target), _)) => {
+                    any_empty = true;
+                    if let Some(ref mut waiting) = *targets {
+                        waiting.push(target);
+                    }
+                }
+                Err((None, _)) => (),
+            }
+        }
+        if any_empty {
+            Err(TryRecvError::Empty)
+        } else {
+            Err(TryRecvError::Disconnected)
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, T: Clone> Drop for RouterReceiver<K, T> {
+    fn drop(&mut self) {
+        for (topic, recv) in self.topics.drain(..) {
+            self.registry.unsubscribe(&topic, recv);
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, T: Clone> Iterator for RouterReceiver<K, T> {
+    type Item = (K, T);
+
+    #[inline(always)]
+    fn next(&mut self) -> Option<(K, T)> {
+        self.recv().ok()
+    }
+}
+
+unsafe impl<K: Hash + Eq + Clone + Send, T: Send + Sync + Clone> Send for Router<K, T> {}
+unsafe impl<K: Hash + Eq + Clone + Send, T: Send + Sync + Clone> Send for RouterReceiver<K, T> {}
+
+#[cfg(test)]
+mod test {
+
+    use super::Router;
+    use crate::error::FrozenTrySendError;
+
+    extern crate crossbeam;
+    use self::crossbeam::scope;
+
+    use std::sync::mpsc::{RecvError, TryRecvError};
+    use std::thread::yield_now;
+
+    #[test]
+    fn test_subscribe_unsubscribe() {
+        let router = Router::new(4);
+        let mut first = router.subscriber();
+        let mut second = router.subscriber();
+        assert!(first.subscribe(1));
+        assert!(!first.subscribe(1));
+        assert!(second.subscribe(1));
+        assert!(second.subscribe(2));
+        router.try_publish(&1, 'a').unwrap();
+        router.try_publish(&2, 'b').unwrap();
+        assert_eq!(
+            Err(FrozenTrySendError::Disconnected('c')),
+            router.try_publish(&3, 'c')
+        );
+        assert_eq!(Ok((1, 'a')), first.try_recv());
+        assert_eq!(Err(TryRecvError::Empty), first.try_recv());
+        let mut got = vec![second.try_recv().unwrap(), second.try_recv().unwrap()];
+        got.sort();
+        assert_eq!(vec![(1, 'a'), (2, 'b')], got);
+        // The topic is kept for as long as anybody is subscribed to it
+        assert!(second.unsubscribe_from(&1));
+        assert!(!second.unsubscribe_from(&1));
+        router.try_publish(&1, 'd').unwrap();
+        assert_eq!(Ok((1, 'd')), first.try_recv());
+        assert_eq!(Err(TryRecvError::Empty), second.try_recv());
+        drop(first);
+        assert_eq!(vec![2], router.topics());
+        assert_eq!(
+            Err(FrozenTrySendError::Disconnected('e')),
+            router.try_publish(&1, 'e')
+        );
+        // Topics can come back, but nothing published while they were gone is received
+        let mut third = router.subscriber();
+        third.subscribe(1);
+        router.try_publish(&1, 'f').unwrap();
+        assert_eq!(Ok((1, 'f')), third.try_recv());
+        drop(router);
+        assert_eq!(Err(TryRecvError::Disconnected), third.try_recv());
+        assert_eq!(Err(TryRecvError::Disconnected), second.try_recv());
+        // Topics subscribed to after the publishers are gone never get anything
+        third.subscribe(4);
+        assert_eq!(Err(TryRecvError::Disconnected), third.try_recv());
+        assert_eq!(Err(RecvError), third.recv());
+    }
+
+    #[test]
+    fn test_router_threaded() {
+        let router = Router::new(4);
+        let num_loop = 5000;
+        let num_topics = 4;
+        let mut subscribers = Vec::new();
+        for i in 0..num_topics {
+            let mut sub = router.subscriber();
+            // Each subscriber gets its own topic and the ones after it
+            for topic in i..num_topics {
+                sub.subscribe(topic);
+            }
+            subscribers.push((i, sub));
+        }
+        scope(|scope| {
+            for topic in 0..num_topics {
+                let cur_router = router.clone();
+                scope.spawn(move |_| {
+                    for i in 0..num_loop {
+                        while cur_router.try_publish(&topic, i).is_err() {
+                            yield_now();
+                        }
+                    }
+                });
+            }
+            drop(router);
+            for (first, sub) in subscribers {
+                scope.spawn(move |_| {
+                    let mut counts = vec![0; num_topics];
+                    for (topic, i) in sub {
+                        assert_eq!(counts[topic], i);
+                        counts[topic] += 1;
+                    }
+                    for (topic, count) in counts.into_iter().enumerate() {
+                        let expected = if topic >= first { num_loop } else { 0 };
+                        assert_eq!(expected, count);
+                    }
+                });
+            }
+        })
+        .unwrap();
+    }
+}
@@ -0,0 +1,633 @@
+//! Request/response channels built on a mpmc queue of requests. Each request
+//! carries an id and a slot of its own for the response, so responses go
+//! straight back to whoever made the call, in whatever order the servers finish.
+//!
+//! ```channel``` makes blocking clients and servers, and ```fut_channel``` makes
+//! ones which act as futures. Calls always get an answer: if a server drops
+//! a request without responding, the caller gets an error instead of waiting forever.
+//!
+//! # Examples
+//!
+//! ```
+//! use multiqueue2::rpc;
+//! use std::thread;
+//!
+//! let (client, server) = rpc::channel(8);
+//! let worker = thread::spawn(move || {
+//!     for request in server {
+//!         let double = request.body() * 2;
+//!         request.respond(double).unwrap();
+//!     }
+//! });
+//!
+//! assert_eq!(Ok(42), client.call(21));
+//! let first = client.try_call(1).unwrap();
+//! let second = client.try_call(2).unwrap();
+//! assert_eq!(Ok(4), second.wait());
+//! assert_eq!(Ok(2), first.wait());
+//! drop(client);
+//! worker.join().unwrap();
+//! ```
+
+use crate::error::FrozenTrySendError;
+use crate::multiqueue::{
+    futures_multiqueue, FutInnerRecv, FutInnerSend, InnerRecv, InnerSend, MultiQueue, MPMC,
+};
+use crate::wait::Backoff;
+
+extern crate futures;
+use self::futures::task::{current, Task};
+use self::futures::{Async, AsyncSink, Future, Poll, Sink, Stream};
+
+extern crate parking_lot;
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{RecvError, TryRecvError};
+use std::sync::Arc;
+use std::thread::{self, Thread};
+
+/// Whoever is waiting on a response
+enum Waiter {
+    Nobody,
+    Thread(Thread),
+    Task(Task),
+}
+
+struct SlotState<Resp> {
+    resp: Option<Resp>,
+    // Set once the responder is gone, whether or not it responded
+    closed: bool,
+    waiter: Waiter,
+}
+
+/// Where the response to a single request is left for the caller
+struct Slot<Resp> {
+    state: parking_lot::Mutex<SlotState<Resp>>,
+}
+
+impl<Resp> Slot<Resp> {
+    fn new() -> Slot<Resp> {
+        Slot {
+            state: parking_lot::Mutex::new(SlotState {
+                resp: None,
+                closed: false,
+                waiter: Waiter::Nobody,
+            }),
+        }
+    }
+
+    /// Closes the slot, leaving the response in it if there is one, and wakes the caller
+    fn close(&self, resp: Option<Resp>) {
+        let waiter = {
+            let mut state = self.state.lock();
+            state.resp = resp;
+            state.closed = true;
+            std::mem::replace(&mut state.waiter, Waiter::Nobody)
+        };
+        match waiter {
+            Waiter::Nobody => (),
+            Waiter::Thread(thread) => thread.unpark(),
+            Waiter::Task(task) => task.notify(),
+        }
+    }
+
+    /// Takes the response if the slot has been closed, or
+    /// registers the passed waiter to be woken when it is
+    fn take_or_wait<F: FnOnce() -> Waiter>(&self, waiter: F) -> Result<Resp, TryRecvError> {
+        let mut state = self.state.lock();
+        if !state.closed {
+            state.waiter = waiter();
+            return Err(TryRecvError::Empty);
+        }
+        state.resp.take().ok_or(TryRecvError::Disconnected)
+    }
+}
+
+/// A request along with where to send the response
+struct Envelope<Req, Resp> {
+    id: u64,
+    body: Req,
+    slot: Arc<Slot<Resp>>,
+}
+
+/// Numbers the requests made by a client and all of its clones
+#[derive(Clone)]
+struct Ids {
+    next: Arc<AtomicU64>,
+}
+
+impl Ids {
+    fn new() -> Ids {
+        Ids {
+            next: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Wraps the request up with a new id, returning it along with the caller's end
+    fn wrap<Req, Resp>(&self, body: Req) -> (Envelope<Req, Resp>, Call<Resp>) {
+        let id = self.next.fetch_add(1, Ordering::Relaxed);
+        let slot = Arc::new(Slot::new());
+        let call = Call {
+            id,
+            slot: slot.clone(),
+        };
+        (Envelope { id, body, slot }, call)
+    }
+}
+
+/// A request received by a server. Dropping it without responding
+/// tells the caller that no response is coming.
+pub struct Request<Req, Resp> {
+    body: Req,
+    responder: Responder<Resp>,
+}
+
+/// Sends the response to a single request. Dropping it without responding
+/// tells the caller that no response is coming.
+pub struct Responder<Resp> {
+    id: u64,
+    slot: Option<Arc<Slot<Resp>>>,
+}
+
+impl<Req, Resp> Request<Req, Resp> {
+    fn new(envelope: Envelope<Req, Resp>) -> Request<Req, Resp> {
+        Request {
+            body: envelope.body,
+            responder: Responder {
+                id: envelope.id,
+                slot: Some(envelope.slot),
+            },
+        }
+    }
+
+    /// Returns the id of the request, which is unique among the requests
+    /// made by a client and its clones
+    pub fn id(&self) -> u64 {
+        self.responder.id
+    }
+
+    /// Returns what was sent
+    pub fn body(&self) -> &Req {
+        &self.body
+    }
+
+    /// Sends the response back to the caller. Returns the response
+    /// if the caller has stopped waiting for it
+    pub fn respond(self, resp: Resp) -> Result<(), Resp> {
+        self.responder.respond(resp)
+    }
+
+    /// Splits the request into what was sent and the responder,
+    /// so that the response can be sent once the body has been used up
+    pub fn into_parts(self) -> (Req, Responder<Resp>) {
+        (self.body, self.responder)
+    }
+}
+
+impl<Resp> Responder<Resp> {
+    /// Returns the id of the request this responds to
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Sends the response back to the caller. Returns the response
+    /// if the caller has stopped waiting for it
+    pub fn respond(mut self, resp: Resp) -> Result<(), Resp> {
+        let slot = self.slot.take().unwrap();
+        // The caller's end holds the only other reference
+        if Arc::strong_count(&slot) == 1 {
+            return Err(resp);
+        }
+        slot.close(Some(resp));
+        Ok(())
+    }
+}
+
+impl<Resp> Drop for Responder<Resp> {
+    fn drop(&mut self) {
+        if let Some(slot) = self.slot.take() {
+            slot.close(None);
+        }
+    }
+}
+
+/// The caller's end of a request which has been sent. Dropping it
+/// stops waiting, and the server is told so when it responds.
+pub struct Call<Resp> {
+    id: u64,
+    slot: Arc<Slot<Resp>>,
+}
+
+impl<Resp> Call<Resp> {
+    /// Returns the id the request was sent with
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Returns the response if it has arrived. Returns Disconnected if the
+    /// server dropped the request without responding, and Empty otherwise
+    pub fn try_wait(&self) -> Result<Resp, TryRecvError> {
+        self.slot.take_or_wait(|| Waiter::Nobody)
+    }
+
+    /// Blocks until the response arrives. Returns Err(RecvError)
+    /// if the server dropped the request without responding
+    pub fn wait(self) -> Result<Resp, RecvError> {
+        loop {
+            match self.slot.take_or_wait(|| Waiter::Thread(thread::current())) {
+                Ok(resp) => return Ok(resp),
+                Err(TryRecvError::Disconnected) => return Err(RecvError),
+                Err(TryRecvError::Empty) => thread::park(),
+            }
+        }
+    }
+}
+
+impl<Resp> Future for Call<Resp> {
+    type Item = Resp;
+    type Error = RecvError;
+
+    fn poll(&mut self) -> Poll<Resp, RecvError> {
+        match self.slot.take_or_wait(|| Waiter::Task(current())) {
+            Ok(resp) => Ok(Async::Ready(resp)),
+            Err(TryRecvError::Disconnected) => Err(RecvError),
+            Err(TryRecvError::Empty) => Ok(Async::NotReady),
+        }
+    }
+}
+
+/// The queue reports Full once every server has left,
+/// which is turned into Disconnected for the clients
+fn servers_gone<T>(
+    sent: Result<(), FrozenTrySendError<T>>,
+    servers: usize,
+) -> Result<(), FrozenTrySendError<T>> {
+    match sent {
+        Err(FrozenTrySendError::Full(val)) if servers == 0 => {
+            Err(FrozenTrySendError::Disconnected(val))
+        }
+        sent => sent,
+    }
+}
+
+/// This makes blocking calls to the servers of a channel. Cloning it
+/// adds another client, and the servers disconnect once they're all gone
+pub struct Client<Req, Resp> {
+    sender: InnerSend<MPMC<Envelope<Req, Resp>>, Envelope<Req, Resp>>,
+    ids: Ids,
+}
+
+/// This receives the requests made to a channel, which each go
+/// to one of the servers. Cloning it adds another server
+pub struct Server<Req, Resp> {
+    receiver: InnerRecv<MPMC<Envelope<Req, Resp>>, Envelope<Req, Resp>>,
+}
+
+impl<Req, Resp> Client<Req, Resp> {
+    /// Tries to send the request without blocking, returning the
+    /// call to wait on for the response
+    pub fn try_call(&self, req: Req) -> Result<Call<Resp>, FrozenTrySendError<Req>> {
+        let (envelope, call) = self.ids.wrap(req);
+        let sent = self.sender.try_send(envelope);
+        servers_gone(sent, self.sender.stream_count())
+            .map(|()| call)
+            .map_err(|e| e.map(|envelope| envelope.body))
+    }
+
+    /// Sends the request, waiting for room if the servers are behind,
+    /// and blocks until the response arrives. Returns Err(RecvError)
+    /// if there are no servers or the request was dropped without a response
+    pub fn call(&self, req: Req) -> Result<Resp, RecvError> {
+        let (mut envelope, call) = self.ids.wrap(req);
+        let mut backoff = Backoff::new();
+        loop {
+            let sent = self.sender.try_send(envelope);
+            match servers_gone(sent, self.sender.stream_count()) {
+                Ok(()) => return call.wait(),
+                Err(FrozenTrySendError::Full(rejected))
+                | Err(FrozenTrySendError::Frozen(rejected)) => envelope = rejected,
+                Err(FrozenTrySendError::Disconnected(_)) => return Err(RecvError),
+            }
+            backoff.snooze(None);
+        }
+    }
+
+    /// Removes this client from the channel
+    pub fn unsubscribe(self) {
+        self.sender.unsubscribe()
+    }
+}
+
+impl<Req, Resp> Clone for Client<Req, Resp> {
+    fn clone(&self) -> Client<Req, Resp> {
+        Client {
+            sender: self.sender.clone(),
+            ids: self.ids.clone(),
+        }
+    }
+}
+
+impl<Req, Resp> Server<Req, Resp> {
+    /// Tries to receive a request without blocking
+    pub fn try_recv(&self) -> Result<Request<Req, Resp>, TryRecvError> {
+        self.receiver.try_recv().map(Request::new)
+    }
+
+    /// Receives a request, blocking until there is one.
+    /// Returns Err(RecvError) once every client is gone
+    pub fn recv(&self) -> Result<Request<Req, Resp>, RecvError> {
+        self.receiver.recv().map(Request::new)
+    }
+
+    /// Removes this server from the channel
+    pub fn unsubscribe(self) -> bool {
+        self.receiver.unsubscribe()
+    }
+}
+
+impl<Req, Resp> Clone for Server<Req, Resp> {
+    fn clone(&self) -> Server<Req, Resp> {
+        Server {
+            receiver: self.receiver.clone(),
+        }
+    }
+}
+
+impl<Req, Resp> Iterator for Server<Req, Resp> {
+    type Item = Request<Req, Resp>;
+
+    #[inline(always)]
+    fn next(&mut self) -> Option<Request<Req, Resp>> {
+        self.recv().ok()
+    }
+}
+
+/// This makes calls to the servers of a channel which resolve as futures.
+/// Cloning it adds another client, and the servers disconnect once they're all gone
+pub struct FutClient<Req, Resp> {
+    sender: FutInnerSend<MPMC<Envelope<Req, Resp>>, Envelope<Req, Resp>>,
+    ids: Ids,
+}
+
+/// This receives the requests made to a channel as a futures stream, with
+/// each request going to one of the servers. Cloning it adds another server
+pub struct FutServer<Req, Resp> {
+    receiver: FutInnerRecv<MPMC<Envelope<Req, Resp>>, Envelope<Req, Resp>>,
+}
+
+/// A call made with ```FutClient::call```, which resolves to the response once
+/// the request has been sent and answered. It fails with RecvError if there are
+/// no servers or the request was dropped without a response
+pub struct FutCall<Req, Resp> {
+    sender: FutInnerSend<MPMC<Envelope<Req, Resp>>, Envelope<Req, Resp>>,
+    // Held until the request fits in the queue
+    pending: Option<Envelope<Req, Resp>>,
+    call: Call<Resp>,
+}
+
+impl<Req, Resp> FutClient<Req, Resp> {
+    /// Makes a call which sends the request once there's room and then waits
+    /// for the response. Nothing is sent until the call is polled
+    pub fn call(&self, req: Req) -> FutCall<Req, Resp> {
+        let (envelope, call) = self.ids.wrap(req);
+        FutCall {
+            sender: self.sender.clone(),
+            pending: Some(envelope),
+            call,
+        }
+    }
+
+    /// Identical to ```Client::try_call```
+    pub fn try_call(&self, req: Req) -> Result<Call<Resp>, FrozenTrySendError<Req>> {
+        let (envelope, call) = self.ids.wrap(req);
+        let sent = self.sender.try_send(envelope);
+        servers_gone(sent, self.sender.stream_count())
+            .map(|()| call)
+            .map_err(|e| e.map(|envelope| envelope.body))
+    }
+
+    /// Removes this client from the channel
+    pub fn unsubscribe(self) {
+        self.sender.unsubscribe()
+    }
+}
+
+impl<Req, Resp> Clone for FutClient<Req, Resp> {
+    fn clone(&self) -> FutClient<Req, Resp> {
+        FutClient {
+            sender: self.sender.clone(),
+            ids: self.ids.clone(),
+        }
+    }
+}
+
+impl<Req, Resp> FutCall<Req, Resp> {
+    /// Returns the id the request is sent with
+    pub fn id(&self) -> u64 {
+        self.call.id
+    }
+}
+
+impl<Req, Resp> Future for FutCall<Req, Resp> {
+    type Item = Resp;
+    type Error = RecvError;
+
+    fn poll(&mut self) -> Poll<Resp, RecvError> {
+        if let Some(envelope) = self.pending.take() {
+            match self.sender.start_send(envelope) {
+                Ok(AsyncSink::Ready) => {
+                    let _ = self.sender.poll_complete();
+                }
+                Ok(AsyncSink::NotReady(_)) if self.sender.stream_count() == 0 => {
+                    return Err(RecvError)
+                }
+                Ok(AsyncSink::NotReady(envelope)) => {
+                    self.pending = Some(envelope);
+                    return Ok(Async::NotReady);
+                }
+                Err(_) => return Err(RecvError),
+            }
+        }
+        self.call.poll()
+    }
+}
+
+impl<Req, Resp> FutServer<Req, Resp> {
+    /// Identical to ```Server::try_recv```
+    pub fn try_recv(&self) -> Result<Request<Req, Resp>, TryRecvError> {
+        self.receiver.try_recv().map(Request::new)
+    }
+
+    /// Removes this server from the channel
+    pub fn unsubscribe(self) -> bool {
+        self.receiver.unsubscribe()
+    }
+}
+
+impl<Req, Resp> Clone for FutServer<Req, Resp> {
+    fn clone(&self) -> FutServer<Req, Resp> {
+        FutServer {
+            receiver: self.receiver.clone(),
+        }
+    }
+}
+
+impl<Req, Resp> Stream for FutServer<Req, Resp> {
+    type Item = Request<Req, Resp>;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Option<Request<Req, Resp>>, ()> {
+        Ok(self
+            .receiver
+            .poll()?
+            .map(|envelope| envelope.map(Request::new)))
+    }
+}
+
+/// Creates a (```Client```, ```Server```) pair where up to capacity
+/// requests can be waiting for a server at once
+pub fn channel<Req, Resp>(capacity: usize) -> (Client<Req, Resp>, Server<Req, Resp>) {
+    let (sender, receiver) = MultiQueue::create_tx_rx(capacity);
+    (
+        Client {
+            sender,
+            ids: Ids::new(),
+        },
+        Server { receiver },
+    )
+}
+
+/// Creates a (```FutClient```, ```FutServer```) pair where up to capacity
+/// requests can be waiting for a server at once
+///
+/// # Examples
+///
+/// ```
+/// extern crate futures;
+/// use futures::{Future, Stream};
+/// use multiqueue2::rpc;
+/// use std::thread;
+///
+/// let (client, server) = rpc::fut_channel(4);
+/// let worker = thread::spawn(move || {
+///     server
+///         .for_each(|request| {
+///             let (body, responder) = request.into_parts();
+///             let _ = responder.respond(format!("hello {}", body));
+///             Ok(())
+///         })
+///         .wait()
+/// });
+///
+/// assert_eq!("hello world", client.call("world").wait().unwrap());
+/// drop(client);
+/// worker.join().unwrap().unwrap();
+/// ```
+pub fn fut_channel<Req, Resp>(capacity: usize) -> (FutClient<Req, Resp>, FutServer<Req, Resp>) {
+    let (sender, receiver) = futures_multiqueue(capacity);
+    (
+        FutClient {
+            sender,
+            ids: Ids::new(),
+        },
+        FutServer { receiver },
+    )
+}
+
+unsafe impl<Req: Send, Resp: Send> Send for Client<Req, Resp> {}
+unsafe impl<Req: Send, Resp: Send> Send for Server<Req, Resp> {}
+unsafe impl<Req: Send, Resp: Send> Send for FutClient<Req, Resp> {}
+unsafe impl<Req: Send, Resp: Send> Send for FutServer<Req, Resp> {}
+unsafe impl<Req: Send, Resp: Send> Send for FutCall<Req, Resp> {}
+
+#[cfg(test)]
+mod test {
+
+    use super::{channel, fut_channel};
+    use crate::error::FrozenTrySendError;
+
+    extern crate crossbeam;
+    use self::crossbeam::scope;
+
+    extern crate futures;
+    use self::futures::{Future, Stream};
+
+    use std::sync::mpsc::{RecvError, TryRecvError};
+
+    #[test]
+    fn test_dropped_requests() {
+        let (client, server) = channel::<usize, usize>(2);
+        let first = client.try_call(1).unwrap();
+        let second = client.try_call(2).unwrap();
+        assert_eq!(
+            Err(FrozenTrySendError::Full(3)),
+            client.try_call(3).map(|_| ())
+        );
+        assert_eq!(Err(TryRecvError::Empty), first.try_wait());
+        let request = server.try_recv().unwrap();
+        assert_eq!(first.id(), request.id());
+        drop(request);
+        assert_eq!(Err(TryRecvError::Disconnected), first.try_wait());
+        // Responses to callers which stopped waiting come back
+        drop(second);
+        assert_eq!(Err(5), server.try_recv().unwrap().respond(5));
+        drop(server);
+        assert_eq!(Err(RecvError), client.call(4));
+        assert_eq!(
+            Err(FrozenTrySendError::Disconnected(6)),
+            client.try_call(6).map(|_| ())
+        );
+    }
+
+    #[test]
+    fn test_rpc_threaded() {
+        let (client, server) = channel(4);
+        let num_loop = 1000;
+        scope(|scope| {
+            for _ in 0..2 {
+                let cur_server = server.clone();
+                scope.spawn(move |_| {
+                    for request in cur_server {
+                        let (body, responder) = request.into_parts();
+                        responder.respond(body * 2).unwrap();
+                    }
+                });
+            }
+            server.unsubscribe();
+            for t in 0..4 {
+                let cur_client = client.clone();
+                scope.spawn(move |_| {
+                    for i in 0..num_loop {
+                        let val = t * num_loop + i;
+                        // Each caller gets its own response, not somebody else's
+                        assert_eq!(Ok(val * 2), cur_client.call(val));
+                    }
+                });
+            }
+            client.unsubscribe();
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_fut_rpc() {
+        let (client, server) = fut_channel(1);
+        scope(|scope| {
+            scope.spawn(move |_| {
+                server
+                    .for_each(|request| {
+                        let body = *request.body();
+                        let _ = request.respond(body + 1);
+                        Ok(())
+                    })
+                    .wait()
+                    .unwrap();
+            });
+            let calls: Vec<_> = (0..100).map(|i| client.call(i)).collect();
+            let got = futures::future::join_all(calls).wait().unwrap();
+            assert_eq!((1..101).collect::<Vec<_>>(), got);
+            drop(client);
+        })
+        .unwrap();
+    }
+}
@@ -0,0 +1,257 @@
+//! The rings behind the queues which split values between several mpmc rings,
+//! such as the keyed, partitioned and priority queues, and the receiving side
+//! of the partitioned queue
+
+use crate::multiqueue::{InnerRecv, InnerSend, MultiQueue, MPMC};
+use crate::stats::QueueStats;
+use crate::wait::Doorbell;
+
+use std::cell::Cell;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::mpsc::{RecvError, TryRecvError};
+
+type Senders<T> = Vec<InnerSend<MPMC<T>, T>>;
+
+/// Creates n mpmc rings, each of which holds capacity values
+pub fn rings<T>(capacity: usize, n: usize) -> (Senders<T>, Vec<InnerRecv<MPMC<T>, T>>) {
+    let mut senders = Vec::with_capacity(n);
+    let mut receivers = Vec::with_capacity(n);
+    for _ in 0..n {
+        // Writers notify as usual, so a receiver on one ring just blocks on it
+        // and one on several has each of them ring its doorbell
+        let (send, recv) = MultiQueue::<MPMC<T>, T>::create_tx_rx(capacity);
+        senders.push(send);
+        receivers.push(recv);
+    }
+    (senders, receivers)
+}
+
+/// Returns the hash used to pick the shard for a key
+pub fn hash_key<K: Hash + ?Sized>(key: &K) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Some of the shards of a queue, received from in turns. Nobody else receives
+/// from them, so values on the same shard are received in the order they were sent
+#[derive(Debug)]
+pub struct Shards<T> {
+    // Kept in order of shard id
+    shards: Vec<(usize, InnerRecv<MPMC<T>, T>)>,
+    next: Cell<usize>,
+    doorbell: Doorbell,
+}
+
+impl<T> Shards<T> {
+    /// Creates n rings and Shards owning all of them, numbered in order
+    pub fn create(capacity: usize, n: usize) -> (Senders<T>, Shards<T>) {
+        let (senders, receivers) = rings(capacity, n);
+        (
+            senders,
+            Shards::new(receivers.into_iter().enumerate().collect()),
+        )
+    }
+
+    pub fn new(mut shards: Vec<(usize, InnerRecv<MPMC<T>, T>)>) -> Shards<T> {
+        shards.sort_by_key(|&(id, _)| id);
+        Shards {
+            shards,
+            next: Cell::new(0),
+            doorbell: Doorbell::new(),
+        }
+    }
+
+    /// Tries to receive a value from one of the shards without blocking,
+    /// taking turns between them so a busy shard can't starve the rest.
+    /// Returns Disconnected once the writers are gone and the shards are empty.
+    #[inline(always)]
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let num = self.shards.len();
+        let start = self.next.get();
+        let mut any_empty = false;
+        for i in 0..num {
+            let at = (start + i) % num;
+            match self.shards[at].1.try_recv() {
+                Ok(val) => {
+                    self.next.set((at + 1) % num);
+                    return Ok(val);
+                }
+                Err(TryRecvError::Empty) => any_empty = true,
+                Err(TryRecvError::Disconnected) => (),
+            }
+        }
+        if any_empty {
+            Err(TryRecvError::Empty)
+        } else {
+            Err(TryRecvError::Disconnected)
+        }
+    }
+
+    /// Receives a value from one of the shards, blocking on all of them if they're empty
+    pub fn recv(&self) -> Result<T, RecvError> {
+        self.doorbell.recv(
+            || self.try_recv(),
+            |bell| {
+                let mut rung = true;
+                for (_, recv) in &self.shards {
+                    rung &= recv.add_doorbell(bell);
+                }
+                rung
+            },
+            |bell| {
+                for (_, recv) in &self.shards {
+                    recv.remove_doorbell(bell);
+                }
+            },
+        )
+    }
+
+    pub fn ids(&self) -> Vec<usize> {
+        self.shards.iter().map(|&(id, _)| id).collect()
+    }
+
+    pub fn lags(&self) -> Vec<(usize, usize)> {
+        self.shards
+            .iter()
+            .map(|&(id, ref recv)| (id, recv.lag()))
+            .collect()
+    }
+
+    pub fn stats(&self) -> Vec<(usize, QueueStats)> {
+        self.shards
+            .iter()
+            .map(|&(id, ref recv)| (id, recv.stats()))
+            .collect()
+    }
+
+    /// Removes the shard with the passed id, if it's one of these
+    pub fn take(&mut self, id: usize) -> Option<InnerRecv<MPMC<T>, T>> {
+        let at = self.shards.iter().position(|&(shard, _)| shard == id)?;
+        self.next.set(0);
+        Some(self.shards.remove(at).1)
+    }
+
+    pub fn insert(&mut self, id: usize, recv: InnerRecv<MPMC<T>, T>) {
+        let at = self
+            .shards
+            .iter()
+            .position(|&(shard, _)| shard > id)
+            .unwrap_or(self.shards.len());
+        self.shards.insert(at, (id, recv));
+    }
+
+    pub fn into_inner(self) -> Vec<(usize, InnerRecv<MPMC<T>, T>)> {
+        self.shards
+    }
+
+    /// Removes these shards from the queue, disconnecting them
+    pub fn unsubscribe(self) {
+        for (_, recv) in self.shards {
+            recv.unsubscribe();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::Shards;
+
+    extern crate crossbeam;
+    use self::crossbeam::scope;
+
+    use std::sync::mpsc::TryRecvError;
+    use std::thread::{sleep, yield_now};
+    use std::time::Duration;
+
+    #[test]
+    fn test_take_turns() {
+        let (mut senders, shards) = Shards::create(4, 3);
+        for val in 0..3 {
+            senders[0].try_send(val).unwrap();
+        }
+        senders[2].try_send(10).unwrap();
+        // A busy shard doesn't get to go again until the others have had a turn
+        assert_eq!(0, shards.try_recv().unwrap());
+        assert_eq!(10, shards.try_recv().unwrap());
+        assert_eq!(1, shards.try_recv().unwrap());
+        assert_eq!(2, shards.try_recv().unwrap());
+        assert_eq!(Err(TryRecvError::Empty), shards.try_recv());
+        // Shards only disconnect once every one of them has
+        let first = senders.remove(0);
+        drop(senders);
+        assert_eq!(Err(TryRecvError::Empty), shards.try_recv());
+        drop(first);
+        assert_eq!(Err(TryRecvError::Disconnected), shards.try_recv());
+    }
+
+    #[test]
+    fn test_recv_wakeup() {
+        let (mut senders, shards) = Shards::create(4, 3);
+        let last = senders.pop().unwrap();
+        drop(senders);
+        scope(|scope| {
+            scope.spawn(move |_| {
+                // The receiver is blocked by now, and has to be woken by the send
+                sleep(Duration::from_millis(20));
+                last.try_send(1).unwrap();
+                sleep(Duration::from_millis(20));
+            });
+            assert_eq!(Ok(1), shards.recv());
+            assert!(shards.recv().is_err());
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_shards_threaded() {
+        let (senders, shards) = Shards::create(4, 8);
+        let num_loop = 10000;
+        let num_keys = 16;
+        let mut all = shards.into_inner();
+        let mut recvs = Vec::new();
+        for n in (1..4).rev() {
+            let rest = all.split_off(all.len() / n);
+            recvs.push(Shards::new(all));
+            all = rest;
+        }
+        scope(|scope| {
+            for key in 0..num_keys {
+                let cur_writer = senders[key % senders.len()].clone();
+                scope.spawn(move |_| {
+                    for i in 0..num_loop {
+                        while cur_writer.try_send((key, i)).is_err() {
+                            yield_now();
+                        }
+                    }
+                });
+            }
+            drop(senders);
+            let handles: Vec<_> = recvs
+                .into_iter()
+                .map(|recv| {
+                    scope.spawn(move |_| {
+                        let mut counts = vec![0; num_keys];
+                        while let Ok((key, i)) = recv.recv() {
+                            assert_eq!(counts[key], i);
+                            counts[key] += 1;
+                        }
+                        counts
+                    })
+                })
+                .collect();
+            let mut totals = vec![0; num_keys];
+            for handle in handles {
+                for (key, count) in handle.join().unwrap().into_iter().enumerate() {
+                    // Every key's values went to one receiver
+                    assert!(count == 0 || count == num_loop);
+                    totals[key] += count;
+                }
+            }
+            assert_eq!(vec![num_loop; num_keys], totals);
+        })
+        .unwrap();
+    }
+}
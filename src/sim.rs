@@ -0,0 +1,358 @@
+//! A single threaded harness which runs senders and receivers in an order picked
+//! from a seed, so that a test which fails on one interleaving fails on it every time.
+//!
+//! Each actor is a closure which takes one step, such as a single ```try_send``` or
+//! ```try_recv```, and says whether it got anywhere. The simulation keeps picking one
+//! of the actors which haven't finished at random and running a step of it, going
+//! through the same code as threads doing the same calls would. Blocking calls
+//! like ```recv``` would hang the only thread there is, so actors stick to the
+//! ```try_``` ones.
+//!
+//! Steps run whole, so this finds orderings of operations which go wrong, rather
+//! than races inside a single operation; the model checking in tests/loom.rs covers those.
+//!
+//! Only built with the test-util feature.
+//!
+//! # Examples
+//!
+//! ```
+//! use multiqueue2::mpmc_queue;
+//! use multiqueue2::sim::{consume, produce, Simulation};
+//! use std::cell::RefCell;
+//!
+//! for seed in 0..20 {
+//!     let (w, r) = mpmc_queue(4);
+//!     let r2 = r.clone();
+//!     let got = RefCell::new(Vec::new());
+//!     let mut sim = Simulation::new(seed);
+//!     sim.spawn("writer", produce(0..100, move |v| w.try_send(v)));
+//!     sim.spawn("reader", consume(move || r.try_recv(), |v| got.borrow_mut().push(v)));
+//!     sim.spawn("reader2", consume(move || r2.try_recv(), |v| got.borrow_mut().push(v)));
+//!     sim.run().unwrap();
+//!     drop(sim);
+//!     let mut got = got.into_inner();
+//!     got.sort();
+//!     assert_eq!((0..100).collect::<Vec<_>>(), got);
+//! }
+//! ```
+
+use crate::error::FrozenTrySendError;
+
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
+use std::sync::mpsc::TryRecvError;
+
+pub const DEFAULT_MAX_STEPS: u64 = 10_000_000;
+
+/// What happened when an actor took a step
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    /// The actor did something which other actors might see
+    Progress,
+    /// The actor couldn't do anything until another one does, such as
+    /// receiving from an empty queue or sending into a full one
+    Blocked,
+    /// The actor is finished, and is dropped along with the handles it owns
+    Done,
+}
+
+/// The error returned when a simulation can't run to the end
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SimError {
+    /// Every actor left blocked since anything last made progress,
+    /// so none of them ever could again
+    Deadlock {
+        seed: u64,
+        step: u64,
+        blocked: Vec<String>,
+    },
+    /// The actors took more steps than the simulation was allowed
+    StepLimit { seed: u64 },
+}
+
+/// Runs actors one step at a time on the calling thread, in an order picked from a seed
+pub struct Simulation<'a> {
+    seed: u64,
+    rng: u64,
+    max_steps: u64,
+    steps: u64,
+    actors: Vec<Actor<'a>>,
+}
+
+struct Actor<'a> {
+    name: Cow<'static, str>,
+    // Taken once the actor is done, to drop what it owns
+    step: Option<Box<dyn FnMut() -> Step + 'a>>,
+    // Whether the actor has been blocked since anything last made progress
+    blocked: bool,
+}
+
+impl<'a> Simulation<'a> {
+    /// Calls with_max_steps(seed, DEFAULT_MAX_STEPS)
+    pub fn new(seed: u64) -> Simulation<'a> {
+        Simulation::with_max_steps(seed, DEFAULT_MAX_STEPS)
+    }
+
+    /// Constructs a Simulation which orders steps by the passed seed,
+    /// and gives up once the actors have taken max_steps of them
+    pub fn with_max_steps(seed: u64, max_steps: u64) -> Simulation<'a> {
+        Simulation {
+            seed,
+            rng: seed,
+            max_steps,
+            steps: 0,
+            actors: Vec::new(),
+        }
+    }
+
+    /// Adds an actor, which takes a step each time the passed closure is called
+    pub fn spawn<N, F>(&mut self, name: N, step: F)
+    where
+        N: Into<Cow<'static, str>>,
+        F: FnMut() -> Step + 'a,
+    {
+        self.actors.push(Actor {
+            name: name.into(),
+            step: Some(Box::new(step)),
+            blocked: false,
+        });
+    }
+
+    /// Returns the seed the steps are ordered by
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Returns how many steps the actors have taken
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Takes a single step of an actor picked at random, returning
+    /// the actor's name and what happened, or None once every actor is done
+    pub fn step(&mut self) -> Option<(&str, Step)> {
+        let live = self.actors.iter().filter(|a| a.step.is_some()).count();
+        if live == 0 {
+            return None;
+        }
+        let mut pick = (self.next_rand() % live as u64) as usize;
+        let idx = self
+            .actors
+            .iter()
+            .position(|a| {
+                if a.step.is_none() {
+                    return false;
+                }
+                if pick == 0 {
+                    return true;
+                }
+                pick -= 1;
+                false
+            })
+            .unwrap();
+        self.steps += 1;
+        let result = (self.actors[idx].step.as_mut().unwrap())();
+        match result {
+            Step::Blocked => self.actors[idx].blocked = true,
+            Step::Progress | Step::Done => {
+                if result == Step::Done {
+                    self.actors[idx].step = None;
+                }
+                for actor in &mut self.actors {
+                    actor.blocked = false;
+                }
+            }
+        }
+        Some((&self.actors[idx].name, result))
+    }
+
+    /// Steps the actors until all of them are done, returning how many steps they took
+    pub fn run(&mut self) -> Result<u64, SimError> {
+        while self.step().is_some() {
+            let live: Vec<&Actor<'a>> = self.actors.iter().filter(|a| a.step.is_some()).collect();
+            if live.is_empty() {
+                break;
+            }
+            if live.iter().all(|a| a.blocked) {
+                return Err(SimError::Deadlock {
+                    seed: self.seed,
+                    step: self.steps,
+                    blocked: live.iter().map(|a| a.name.to_string()).collect(),
+                });
+            }
+            if self.steps >= self.max_steps {
+                return Err(SimError::StepLimit { seed: self.seed });
+            }
+        }
+        Ok(self.steps)
+    }
+
+    // splitmix64, which is plenty for picking actors and the same everywhere
+    fn next_rand(&mut self) -> u64 {
+        self.rng = self.rng.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+/// Returns an actor which sends each of the values with try_send, one per step.
+/// It's done once every value is sent, or once try_send says the queue is disconnected
+pub fn produce<T, I, F>(values: I, mut try_send: F) -> impl FnMut() -> Step
+where
+    I: IntoIterator<Item = T>,
+    F: FnMut(T) -> Result<(), FrozenTrySendError<T>>,
+{
+    let mut values = values.into_iter();
+    let mut pending = None;
+    move || {
+        let val = match pending.take().or_else(|| values.next()) {
+            Some(val) => val,
+            None => return Step::Done,
+        };
+        match try_send(val) {
+            Ok(()) => Step::Progress,
+            Err(FrozenTrySendError::Full(val)) | Err(FrozenTrySendError::Frozen(val)) => {
+                pending = Some(val);
+                Step::Blocked
+            }
+            Err(FrozenTrySendError::Disconnected(_)) => Step::Done,
+        }
+    }
+}
+
+/// Returns an actor which receives a value with try_recv each step and hands it to
+/// each. It's done once try_recv says the queue is disconnected
+pub fn consume<T, F, G>(mut try_recv: F, mut each: G) -> impl FnMut() -> Step
+where
+    F: FnMut() -> Result<T, TryRecvError>,
+    G: FnMut(T),
+{
+    move || match try_recv() {
+        Ok(val) => {
+            each(val);
+            Step::Progress
+        }
+        Err(TryRecvError::Empty) => Step::Blocked,
+        Err(TryRecvError::Disconnected) => Step::Done,
+    }
+}
+
+impl<'a> fmt::Debug for Simulation<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Simulation")
+            .field("seed", &self.seed)
+            .field("steps", &self.steps)
+            .finish()
+    }
+}
+
+impl fmt::Display for SimError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SimError::Deadlock {
+                seed,
+                step,
+                ref blocked,
+            } => write!(
+                f,
+                "simulation with seed {} deadlocked at step {} with {:?} blocked",
+                seed, step, blocked
+            ),
+            SimError::StepLimit { seed } => {
+                write!(f, "simulation with seed {} ran out of steps", seed)
+            }
+        }
+    }
+}
+
+impl Error for SimError {}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::broadcast::broadcast_queue;
+    use crate::mpmc::mpmc_queue;
+
+    use std::cell::RefCell;
+
+    fn order(seed: u64) -> Vec<String> {
+        let mut sim = Simulation::new(seed);
+        for name in &["a", "b", "c"] {
+            let mut left = 5;
+            sim.spawn(*name, move || {
+                left -= 1;
+                if left == 0 {
+                    Step::Done
+                } else {
+                    Step::Progress
+                }
+            });
+        }
+        let mut names = Vec::new();
+        while let Some((name, _)) = sim.step() {
+            names.push(name.to_string());
+        }
+        names
+    }
+
+    #[test]
+    fn test_seeded_order() {
+        assert_eq!(15, order(7).len());
+        assert_eq!(order(7), order(7));
+        assert!((0..10).any(|seed| order(seed) != order(7)));
+    }
+
+    #[test]
+    fn test_broadcast_seeds() {
+        for seed in 0..50 {
+            let (w, r) = broadcast_queue(2);
+            let r2 = r.add_stream();
+            let got = (RefCell::new(Vec::new()), RefCell::new(Vec::new()));
+            let w2 = w.clone();
+            let mut sim = Simulation::new(seed);
+            sim.spawn("w", produce(0..20, move |v| w.try_send(v)));
+            sim.spawn("w2", produce(20..40, move |v| w2.try_send(v)));
+            sim.spawn(
+                "r",
+                consume(move || r.try_recv(), |v| got.0.borrow_mut().push(v)),
+            );
+            sim.spawn(
+                "r2",
+                consume(move || r2.try_recv(), |v| got.1.borrow_mut().push(v)),
+            );
+            sim.run().unwrap();
+            drop(sim);
+            assert_eq!(got.0.borrow().len(), 40);
+            assert_eq!(got.0, got.1);
+        }
+    }
+
+    #[test]
+    fn test_deadlock() {
+        let (w, r) = mpmc_queue(2);
+        let mut sim = Simulation::new(3);
+        // Nothing reads, so the writer gets stuck once the queue is full
+        sim.spawn("w", produce(0..10, |v| w.try_send(v)));
+        match sim.run() {
+            Err(SimError::Deadlock { seed, blocked, .. }) => {
+                assert_eq!(3, seed);
+                assert_eq!(vec!["w".to_string()], blocked);
+            }
+            other => panic!("expected a deadlock, got {:?}", other),
+        }
+        drop(sim);
+        assert_eq!(0, r.try_recv().unwrap());
+    }
+
+    #[test]
+    fn test_step_limit() {
+        let mut sim = Simulation::with_max_steps(1, 100);
+        sim.spawn("spin", || Step::Progress);
+        assert_eq!(Err(SimError::StepLimit { seed: 1 }), sim.run());
+        assert_eq!(100, sim.steps());
+    }
+}
@@ -0,0 +1,223 @@
+//! Snapshots of a queue's state, along with the counters
+//! kept on every queue when the "stats" feature is enabled
+
+#[cfg(feature = "stats")]
+use std::sync::atomic::AtomicUsize;
+
+/// A snapshot of a queue's state, taken with ```stats```.
+/// Every stream's position is read before the head, so no stream is ever
+/// ahead of the head, but the queue may keep moving while the snapshot is taken.
+/// Positions count the values written to or read from the queue since it was created.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct QueueStats {
+    /// The position the next value will be written at
+    pub head: usize,
+    /// The number of values the queue can hold
+    pub capacity: usize,
+    /// The number of values the furthest behind stream has left to read,
+    /// which is at most capacity
+    pub occupancy: usize,
+    /// The number of writers subscribed to the queue
+    pub writers: usize,
+    /// Whether every writer or every stream is gone, so nothing more can pass through
+    pub closed: bool,
+    /// The state of each stream subscribed to the queue
+    pub streams: Vec<StreamStats>,
+    /// The number of values sent
+    #[cfg(feature = "stats")]
+    pub sends: usize,
+    /// The number of sends which failed because the queue was full
+    #[cfg(feature = "stats")]
+    pub full: usize,
+    /// The number of times writers called into the wait strategy to wake readers
+    #[cfg(feature = "stats")]
+    pub notifies: usize,
+    /// The highest occupancy seen by a writer right after sending,
+    /// since the queue was created or ```reset_high_water``` was last called
+    #[cfg(feature = "stats")]
+    pub high_water: usize,
+    /// The number of values dropped because one with the same id was sent recently
+    #[cfg(feature = "stats")]
+    pub duplicates: usize,
+}
+
+/// The state of a single stream in a ```QueueStats```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StreamStats {
+    /// The label the stream was created with, if any
+    pub name: Option<String>,
+    /// The position the stream will read from next
+    pub tail: usize,
+    /// The number of receivers consuming from the stream
+    pub consumers: usize,
+    /// The number of values received from the stream by all of its consumers
+    #[cfg(feature = "stats")]
+    pub received: usize,
+}
+
+/// How many bytes a queue takes up, taken with ```memory_footprint```.
+/// Only the slots values sit in are counted, not anything the values
+/// themselves point to, such as the contents of a ```String```.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoryFootprint {
+    /// The slots holding the values, one for each value the queue can hold
+    pub ring: usize,
+    /// The refcounts and markers kept alongside each slot, along with
+    /// the times kept on delay, expiring and timestamped queues
+    pub refcounts: usize,
+    /// The padding which keeps what different threads write on separate cache lines
+    pub padding: usize,
+    /// The rest of the queue itself, such as the head and the options it was created with
+    pub header: usize,
+    /// The positions and consumer counts of the streams
+    pub streams: usize,
+    /// What the memory manager takes up, including stream memory
+    /// which has been replaced but not yet freed
+    pub reclaim: usize,
+}
+
+impl MemoryFootprint {
+    /// Returns the total number of bytes
+    pub fn total(&self) -> usize {
+        self.ring + self.refcounts + self.padding + self.header + self.streams + self.reclaim
+    }
+}
+
+/// The queue-wide counters. Each is bumped by different threads,
+/// so they're kept on separate cache lines
+#[cfg(feature = "stats")]
+#[repr(C)]
+pub struct Counters {
+    pub sends: AtomicUsize,
+    d1: [u8; 64],
+    pub full: AtomicUsize,
+    d2: [u8; 64],
+    pub notifies: AtomicUsize,
+    d3: [u8; 64],
+    pub high_water: AtomicUsize,
+    d4: [u8; 64],
+    pub duplicates: AtomicUsize,
+    d5: [u8; 64],
+}
+
+#[cfg(feature = "stats")]
+impl Counters {
+    pub fn new() -> Counters {
+        Counters {
+            sends: AtomicUsize::new(0),
+            d1: [0; 64],
+            full: AtomicUsize::new(0),
+            d2: [0; 64],
+            notifies: AtomicUsize::new(0),
+            d3: [0; 64],
+            high_water: AtomicUsize::new(0),
+            d4: [0; 64],
+            duplicates: AtomicUsize::new(0),
+            d5: [0; 64],
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use crate::broadcast::broadcast_queue;
+    use crate::mpmc::mpmc_queue;
+
+    extern crate crossbeam;
+    use self::crossbeam::scope;
+
+    use std::thread::yield_now;
+
+    #[test]
+    fn test_snapshot() {
+        let (writer, reader) = broadcast_queue(4);
+        let stream = reader.add_stream_named("audit");
+        let _audit_worker = stream.clone();
+        for i in 0..3 {
+            writer.try_send(i).unwrap();
+        }
+        reader.try_recv().unwrap();
+        let stats = writer.stats();
+        assert_eq!(3, stats.head);
+        assert_eq!(4, stats.capacity);
+        assert_eq!(3, stats.occupancy);
+        assert_eq!(1, stats.writers);
+        assert!(!stats.closed);
+        let streams: Vec<_> = stats
+            .streams
+            .iter()
+            .map(|s| (s.name.as_deref(), s.tail, s.consumers))
+            .collect();
+        assert_eq!(vec![(None, 1, 1), (Some("audit"), 0, 2)], streams);
+        assert_eq!(stats, reader.stats());
+        drop(writer);
+        let stats = reader.stats();
+        assert_eq!(0, stats.writers);
+        assert!(stats.closed);
+    }
+
+    #[test]
+    #[cfg(feature = "stats")]
+    fn test_counters() {
+        let (writer, reader) = broadcast_queue(4);
+        let stream = reader.add_stream_named("audit");
+        for i in 0..6 {
+            let _ = writer.try_send(i);
+        }
+        for _ in 0..3 {
+            reader.try_recv().unwrap();
+        }
+        stream.try_recv_view(|_| ()).ok().unwrap();
+        let stats = reader.stats();
+        assert_eq!(4, stats.sends);
+        assert_eq!(2, stats.full);
+        // Nobody was waiting to be woken
+        assert_eq!(0, stats.notifies);
+        assert_eq!(4, stats.high_water);
+        let received: Vec<_> = stats
+            .streams
+            .iter()
+            .map(|s| (s.name.as_deref(), s.received))
+            .collect();
+        assert_eq!(vec![(None, 3), (Some("audit"), 1)], received);
+        assert_eq!(stats, writer.stats());
+    }
+
+    #[test]
+    fn test_stats_threaded() {
+        let (writer, reader) = mpmc_queue(4);
+        let num_loop = 10000;
+        scope(|scope| {
+            for _ in 0..2 {
+                let cur_writer = writer.clone();
+                scope.spawn(move |_| {
+                    for i in 0..num_loop {
+                        while cur_writer.try_send(i).is_err() {
+                            yield_now();
+                        }
+                    }
+                });
+            }
+            for _ in 0..2 {
+                let cur_reader = reader.clone();
+                scope.spawn(move |_| for _ in cur_reader {});
+            }
+            writer.unsubscribe();
+        })
+        .unwrap();
+        let stats = reader.stats();
+        assert_eq!(2 * num_loop, stats.head);
+        assert_eq!(stats.head, stats.streams[0].tail);
+        assert_eq!(0, stats.occupancy);
+        assert!(stats.closed);
+        #[cfg(feature = "stats")]
+        {
+            assert_eq!(2 * num_loop, stats.sends);
+            assert_eq!(2 * num_loop, stats.streams[0].received);
+            assert!(stats.high_water > 0 && stats.high_water <= 4);
+            reader.reset_high_water();
+            assert_eq!(0, reader.stats().high_water);
+        }
+    }
+}
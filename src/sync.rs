@@ -0,0 +1,70 @@
+//! The atomics and locks the core of the queue is built on. These come from loom
+//! when built with ```--cfg loom```, so that the tests in tests/loom.rs can check
+//! every interleaving of the sends, receives and unsubscribes in them.
+
+#[cfg(loom)]
+mod imp {
+    extern crate loom;
+    pub use self::loom::sync::atomic::{fence, AtomicBool, AtomicPtr, AtomicU64, AtomicUsize};
+    pub use self::loom::sync::Mutex;
+    pub use self::loom::thread::yield_now;
+}
+
+#[cfg(not(loom))]
+mod imp {
+    pub use std::sync::atomic::{fence, AtomicBool, AtomicPtr, AtomicU64, AtomicUsize};
+    pub use std::sync::Mutex;
+    pub use std::thread::yield_now;
+}
+
+pub use self::imp::*;
+
+use std::sync::atomic::Ordering;
+
+/// A u64 which is stored and loaded atomically, as two halves on targets without
+/// 64 bit atomics. The halves are stored and loaded separately there, so this is
+/// only for values whose stores and loads are already ordered by something else,
+/// like the times kept alongside a slot are by the slot's tag
+pub struct AtomicStamp {
+    #[cfg(target_has_atomic = "64")]
+    val: AtomicU64,
+    #[cfg(not(target_has_atomic = "64"))]
+    high: AtomicUsize,
+    #[cfg(not(target_has_atomic = "64"))]
+    low: AtomicUsize,
+}
+
+impl AtomicStamp {
+    pub fn new(val: u64) -> AtomicStamp {
+        #[cfg(target_has_atomic = "64")]
+        let stamp = AtomicStamp {
+            val: AtomicU64::new(val),
+        };
+        #[cfg(not(target_has_atomic = "64"))]
+        let stamp = AtomicStamp {
+            high: AtomicUsize::new((val >> 32) as usize),
+            low: AtomicUsize::new(val as u32 as usize),
+        };
+        stamp
+    }
+
+    #[inline(always)]
+    pub fn load(&self, ord: Ordering) -> u64 {
+        #[cfg(target_has_atomic = "64")]
+        let val = self.val.load(ord);
+        #[cfg(not(target_has_atomic = "64"))]
+        let val = (self.high.load(ord) as u64) << 32 | self.low.load(ord) as u64;
+        val
+    }
+
+    #[inline(always)]
+    pub fn store(&self, val: u64, ord: Ordering) {
+        #[cfg(target_has_atomic = "64")]
+        self.val.store(val, ord);
+        #[cfg(not(target_has_atomic = "64"))]
+        {
+            self.high.store((val >> 32) as usize, ord);
+            self.low.store(val as u32 as usize, ord);
+        }
+    }
+}
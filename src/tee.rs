@@ -0,0 +1,326 @@
+//! Support for sending every value into several queues at once
+
+use crate::error::FrozenTrySendError;
+use crate::multiqueue::{InnerSend, QueueRW, Reservation};
+
+trait Target<T> {
+    fn reserve(&self, val: &T) -> Result<Reservation, FrozenTrySendError<()>>;
+    fn commit(&self, reservation: Reservation, val: T);
+    fn release(&self, reservation: Reservation);
+    fn filters_sends(&self) -> bool;
+    fn writer_count(&self) -> usize;
+}
+
+impl<RW: QueueRW<T>, T> Target<T> for InnerSend<RW, T> {
+    fn reserve(&self, val: &T) -> Result<Reservation, FrozenTrySendError<()>> {
+        InnerSend::reserve(self, val)
+    }
+
+    #[inline(always)]
+    fn commit(&self, reservation: Reservation, val: T) {
+        InnerSend::commit(self, reservation, val)
+    }
+
+    fn release(&self, reservation: Reservation) {
+        InnerSend::release(self, reservation)
+    }
+
+    fn filters_sends(&self) -> bool {
+        InnerSend::filters_sends(self)
+    }
+
+    fn writer_count(&self) -> usize {
+        InnerSend::writer_count(self)
+    }
+}
+
+/// A sender which has been handed over to a ```Tee```.
+/// These are made from ```BroadcastSender```s and ```MPMCSender```s with ```into```
+pub struct TeeTarget<T> {
+    sender: Box<dyn Target<T>>,
+}
+
+impl<T> TeeTarget<T> {
+    pub(crate) fn new<RW: QueueRW<T> + 'static>(sender: InnerSend<RW, T>) -> TeeTarget<T>
+    where
+        T: 'static,
+    {
+        TeeTarget {
+            sender: Box::new(sender),
+        }
+    }
+}
+
+/// This sends every value into several queues, either going into all of them
+/// or into none. Room for the value is reserved in every queue before it's
+/// written into any of them, and if one of them is full, frozen or has no
+/// receivers the room taken in the others is given back, so no queue is
+/// ever left with a value the others are missing.
+///
+/// Receivers, snapshots and inspectors only ever make more room, and anything
+/// holding the reserved slot lets go of it as soon as it sees it's been received,
+/// so to be sure nobody takes the reserved room the tee only has to be the only
+/// sender on each of its queues. Those can't be conflated or deduplicated either,
+/// since they decide for themselves whether a value goes in. Values are sent to every
+/// queue in the same order, so each one sees exactly the same sequence.
+///
+/// # Examples
+///
+/// ```
+/// use multiqueue2::{broadcast_queue, mpmc_queue, FrozenTrySendError, Tee};
+///
+/// let (bsend, brecv) = broadcast_queue(4);
+/// let (msend, mrecv) = mpmc_queue(1);
+///
+/// let mut tee = Tee::new();
+/// tee.add(bsend);
+/// tee.add(msend);
+///
+/// tee.try_send(1).unwrap();
+/// // The mpmc queue is full, so the broadcast one doesn't get this either
+/// assert_eq!(Err(FrozenTrySendError::Full(2)), tee.try_send(2));
+/// assert_eq!(1, mrecv.try_recv().unwrap());
+/// tee.try_send(3).unwrap();
+/// assert_eq!(vec![1, 3], brecv.try_iter().collect::<Vec<_>>());
+/// assert_eq!(3, mrecv.try_recv().unwrap());
+/// ```
+pub struct Tee<T> {
+    targets: Vec<TeeTarget<T>>,
+}
+
+impl<T: Clone> Tee<T> {
+    /// Creates a ```Tee``` without any queues to send to
+    pub fn new() -> Tee<T> {
+        Tee {
+            targets: Vec::new(),
+        }
+    }
+
+    /// Adds a queue to send values to. Panics if anybody else can send to the queue,
+    /// or if it's conflated or deduplicated
+    pub fn add<S: Into<TeeTarget<T>>>(&mut self, target: S) {
+        let target = target.into();
+        assert!(
+            target.sender.writer_count() == 1,
+            "Multiqueue error - a tee has to be the only sender on its queues"
+        );
+        assert!(
+            !target.sender.filters_sends(),
+            "Multiqueue error - a tee can't send to conflated or deduplicated queues"
+        );
+        self.targets.push(target);
+    }
+
+    /// Returns the number of queues values are sent to
+    pub fn num_targets(&self) -> usize {
+        self.targets.len()
+    }
+
+    /// Tries to send the value to every queue. Returns Full if any of them are full,
+    /// Frozen if any of them are frozen and Disconnected if any of them have no
+    /// receivers, or if there are no queues, in which case none of the queues get the value.
+    pub fn try_send(&self, val: T) -> Result<(), FrozenTrySendError<T>> {
+        let mut reserved = Vec::with_capacity(self.targets.len());
+        for target in &self.targets {
+            match target.sender.reserve(&val) {
+                Ok(reservation) => reserved.push(reservation),
+                Err(e) => {
+                    for (target, reservation) in self.targets.iter().zip(reserved) {
+                        target.sender.release(reservation);
+                    }
+                    return Err(e.map(|()| val));
+                }
+            }
+        }
+        let mut reserved = self.targets.iter().zip(reserved);
+        let (last, last_reservation) = match reserved.next_back() {
+            Some(last) => last,
+            None => return Err(FrozenTrySendError::Disconnected(val)),
+        };
+        for (target, reservation) in reserved {
+            target.sender.commit(reservation, val.clone());
+        }
+        last.sender.commit(last_reservation, val);
+        Ok(())
+    }
+
+    /// Removes this sender from all of its queues
+    pub fn unsubscribe(self) {}
+}
+
+impl<T: Clone> Default for Tee<T> {
+    fn default() -> Tee<T> {
+        Tee::new()
+    }
+}
+
+unsafe impl<T: Send + Sync> Send for TeeTarget<T> {}
+unsafe impl<T: Send + Sync> Send for Tee<T> {}
+
+#[cfg(test)]
+mod test {
+
+    use super::Tee;
+    use crate::broadcast::broadcast_queue;
+    use crate::error::FrozenTrySendError;
+    use crate::mpmc::{mpmc_queue, mpmc_queue_conflated, mpmc_queue_weighted};
+
+    extern crate crossbeam;
+    use self::crossbeam::scope;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc::TryRecvError;
+    use std::thread::yield_now;
+
+    #[test]
+    fn test_all_or_nothing() {
+        let (send_a, recv_a) = mpmc_queue(2);
+        let (send_b, recv_b) = broadcast_queue(4);
+        let (send_c, recv_c) = mpmc_queue_weighted(8, 10, |v: &usize| *v);
+        let mut tee = Tee::new();
+        assert_eq!(Err(FrozenTrySendError::Disconnected(0)), tee.try_send(0));
+        tee.add(send_a);
+        tee.add(send_b);
+        tee.add(send_c);
+        assert_eq!(3, tee.num_targets());
+        tee.try_send(1).unwrap();
+        tee.try_send(2).unwrap();
+        // Only the first queue is out of room
+        assert_eq!(Err(FrozenTrySendError::Full(3)), tee.try_send(3));
+        assert_eq!(1, recv_a.try_recv().unwrap());
+        // And now only the last one
+        assert_eq!(Err(FrozenTrySendError::Full(8)), tee.try_send(8));
+        tee.try_send(4).unwrap();
+        assert_eq!(vec![2, 4], recv_a.try_iter().collect::<Vec<_>>());
+        assert_eq!(vec![1, 2, 4], recv_b.try_iter().collect::<Vec<_>>());
+        assert_eq!(vec![1, 2, 4], recv_c.try_iter().collect::<Vec<_>>());
+        drop(recv_b);
+        assert_eq!(Err(FrozenTrySendError::Disconnected(5)), tee.try_send(5));
+        assert_eq!(Err(TryRecvError::Empty), recv_a.try_recv());
+        drop(tee);
+        assert_eq!(Err(TryRecvError::Disconnected), recv_c.try_recv());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_shared_sender() {
+        let (send, _recv) = mpmc_queue::<usize>(4);
+        let mut tee = Tee::new();
+        tee.add(send.clone());
+    }
+
+    #[test]
+    fn test_release_on_failure() {
+        let (send_a, recv_a) = mpmc_queue_weighted(8, 10, |v: &usize| *v);
+        let (send_b, recv_b) = broadcast_queue(4);
+        let mut tee = Tee::new();
+        tee.add(send_a);
+        tee.add(send_b);
+        tee.try_send(4).unwrap();
+        recv_b.freeze();
+        // The weight taken in the first queue is given back
+        assert_eq!(Err(FrozenTrySendError::Frozen(6)), tee.try_send(6));
+        recv_b.unfreeze();
+        tee.try_send(6).unwrap();
+        assert_eq!(vec![4, 6], recv_a.try_iter().collect::<Vec<_>>());
+        assert_eq!(vec![4, 6], recv_b.try_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_conflated_target() {
+        let (send, _recv) = mpmc_queue_conflated(4, |v: &usize| *v);
+        let mut tee = Tee::new();
+        tee.add(send);
+    }
+
+    #[test]
+    fn test_tee_threaded() {
+        let num_loop = 10000;
+        let mut tee = Tee::new();
+        let mut receivers = Vec::new();
+        for capacity in &[2, 4, 8] {
+            let (send, recv) = mpmc_queue(*capacity);
+            tee.add(send);
+            receivers.push(recv);
+        }
+        scope(|scope| {
+            scope.spawn(move |_| {
+                for i in 0..num_loop {
+                    while tee.try_send(i).is_err() {
+                        yield_now();
+                    }
+                }
+            });
+            for recv in receivers {
+                scope.spawn(move |_| {
+                    // Every queue gets every value, in the order it was sent
+                    let got: Vec<_> = recv.into_iter().collect();
+                    assert_eq!((0..num_loop).collect::<Vec<_>>(), got);
+                });
+            }
+        })
+        .unwrap();
+    }
+
+    /// Takes a while to clone, so the tee spends a while
+    /// between reserving room in a queue and writing into it
+    #[derive(Debug)]
+    struct SlowClone(usize);
+
+    impl Clone for SlowClone {
+        fn clone(&self) -> SlowClone {
+            for _ in 0..20 {
+                yield_now();
+            }
+            SlowClone(self.0)
+        }
+    }
+
+    #[test]
+    fn test_snapshot_while_reserved() {
+        let num_loop = 2000;
+        let mut tee = Tee::new();
+        let mut receivers = Vec::new();
+        for _ in 0..2 {
+            let (send, recv) = broadcast_queue(2);
+            tee.add(send);
+            receivers.push(recv);
+        }
+        let finished = AtomicUsize::new(0);
+        scope(|scope| {
+            scope.spawn(move |_| {
+                for i in 0..num_loop {
+                    while tee.try_send(SlowClone(i)).is_err() {
+                        yield_now();
+                    }
+                }
+            });
+            for recv in receivers {
+                // Snapshots hold slots while looking at them,
+                // which must never take the room a tee reserved
+                let watcher = recv.clone();
+                let finished = &finished;
+                scope.spawn(move |_| {
+                    while finished.load(Ordering::Relaxed) != 2 {
+                        let _ = watcher.snapshot();
+                    }
+                });
+                scope.spawn(move |_| {
+                    let mut got = Vec::new();
+                    loop {
+                        let _ = recv.snapshot();
+                        match recv.try_recv() {
+                            Ok(val) => got.push(val.0),
+                            Err(TryRecvError::Empty) => yield_now(),
+                            Err(TryRecvError::Disconnected) => break,
+                        }
+                    }
+                    finished.fetch_add(1, Ordering::Relaxed);
+                    assert_eq!((0..num_loop).collect::<Vec<_>>(), got);
+                });
+            }
+        })
+        .unwrap();
+    }
+}
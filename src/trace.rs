@@ -0,0 +1,78 @@
+//! Events emitted through the tracing crate when the "tracing" feature is enabled.
+//! Every event carries the id of the queue it happened on, so events from many
+//! queues in the same program can be told apart
+
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+
+static NEXT_QUEUE_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// The tracing state of a single queue. Full and empty events are only
+/// emitted when the queue changes between states, rather than on every
+/// failed send or wait, so that a busy queue doesn't flood the subscriber
+pub struct QueueTrace {
+    id: usize,
+    full: AtomicBool,
+    empty: AtomicBool,
+}
+
+impl QueueTrace {
+    pub fn new(capacity: usize) -> QueueTrace {
+        let id = NEXT_QUEUE_ID.fetch_add(1, Relaxed);
+        tracing::debug!(queue = id, capacity, "multiqueue created");
+        QueueTrace {
+            id,
+            full: AtomicBool::new(false),
+            empty: AtomicBool::new(false),
+        }
+    }
+
+    #[inline(always)]
+    pub fn sent(&self) {
+        if self.full.load(Relaxed) && self.full.swap(false, Relaxed) {
+            tracing::trace!(queue = self.id, "multiqueue no longer full");
+        }
+        if self.empty.load(Relaxed) && self.empty.swap(false, Relaxed) {
+            tracing::trace!(queue = self.id, "multiqueue no longer empty");
+        }
+    }
+
+    pub fn full(&self) {
+        if !self.full.load(Relaxed) && !self.full.swap(true, Relaxed) {
+            tracing::debug!(queue = self.id, "multiqueue full");
+        }
+    }
+
+    pub fn empty(&self) {
+        if !self.empty.load(Relaxed) && !self.empty.swap(true, Relaxed) {
+            tracing::trace!(queue = self.id, "multiqueue empty");
+        }
+    }
+
+    pub fn stream_added(&self, name: Option<&str>, streams: usize) {
+        tracing::debug!(
+            queue = self.id,
+            stream = name,
+            streams,
+            "multiqueue stream added"
+        );
+    }
+
+    pub fn stream_removed(&self, name: Option<&str>) {
+        tracing::debug!(queue = self.id, stream = name, "multiqueue stream removed");
+    }
+
+    pub fn writers_gone(&self) {
+        tracing::debug!(
+            queue = self.id,
+            "multiqueue disconnected, every writer is gone"
+        );
+    }
+
+    pub fn readers_gone(&self) {
+        tracing::debug!(
+            queue = self.id,
+            "multiqueue disconnected, every stream is gone"
+        );
+    }
+}
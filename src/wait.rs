@@ -11,27 +11,54 @@
 //! let _ = broadcast_queue_with::<usize, BusyWait>(10, BusyWait::new());
 //! let _ = broadcast_queue_with::<usize, YieldingWait>(10, YieldingWait::new());
 //! let _ = broadcast_queue_with::<usize, BlockingWait>(10, BlockingWait::new());
+//! let _ = broadcast_queue_with::<usize, SingleThreadWait>(10, SingleThreadWait::new());
 //! ```
-use std::sync::atomic::AtomicUsize;
-use std::sync::atomic::Ordering::Relaxed;
-use std::thread::yield_now;
+//!
+//! Queues made without a wait strategy use ```DefaultWait```, which is
+//! ```BlockingWait``` everywhere but wasm32. There it's ```AtomicsWait``` when
+//! the crate is built with the atomics target feature, so that web workers sharing
+//! memory can block on ```Atomics.wait```, and ```SingleThreadWait``` otherwise.
+//! On Windows and macOS it's ```AddressWait```, which sleeps readers on the
+//! native address waits rather than a mutex and condition variable.
+use std::sync::mpsc::{RecvError, TryRecvError};
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::Duration;
 
 use crate::countedindex::{past, rm_tag};
+use crate::ordering::{RELAXED, SEQ_CST};
+use crate::sync::{fence, yield_now, AtomicUsize};
+extern crate crossbeam;
 extern crate parking_lot;
+use self::crossbeam::channel::{self, Receiver, Sender, TrySendError};
+
+#[cfg(all(target_arch = "wasm32", target_feature = "atomics"))]
+use std::arch::wasm32;
+#[cfg(any(
+    all(target_arch = "wasm32", target_feature = "atomics"),
+    windows,
+    target_os = "macos"
+))]
+use std::sync::atomic::{AtomicI32, Ordering::SeqCst};
 
 pub const DEFAULT_YIELD_SPINS: usize = 50;
 pub const DEFAULT_TRY_SPINS: usize = 50;
 pub const DEFAULT_CHECK_DELAY: u64 = 20;
+pub const DEFAULT_SELECT_MAX_SLEEP_US: u64 = 1000;
+
+/// The queue tag a reader is waiting on, the corresponding AtomicUsize,
+/// and the number of writers, in the same order as ```Wait::wait``` takes them
+pub type SelectTarget<'a> = (usize, &'a AtomicUsize, &'a AtomicUsize);
 
 #[inline(always)]
 pub fn load_tagless(val: &AtomicUsize) -> usize {
-    rm_tag(val.load(Relaxed))
+    rm_tag(val.load(RELAXED))
 }
 
 #[inline(always)]
 pub fn check(seq: usize, at: &AtomicUsize, wc: &AtomicUsize) -> bool {
     let cur_count = load_tagless(at);
-    wc.load(Relaxed) == 0 || seq == cur_count || past(seq, cur_count).1
+    wc.load(RELAXED) == 0 || seq == cur_count || past(seq, cur_count).1
 
     // if wc.load(Relaxed) == 0 || seq == cur_count || past(seq, cur_count).1 {
     //     true
@@ -42,6 +69,140 @@ pub fn check(seq: usize, at: &AtomicUsize, wc: &AtomicUsize) -> bool {
     // }
 }
 
+/// Waits until any of the passed targets are available. The queues may all have
+/// different waiting strategies and writers only notify their own, so this spins,
+/// then yields, and then sleeps for increasingly long (up to
+/// DEFAULT_SELECT_MAX_SLEEP_US microseconds) between checks instead of blocking
+#[cold]
+pub fn select_wait(targets: &[SelectTarget<'_>]) {
+    let ready = || targets.iter().any(|&(seq, at, wc)| check(seq, at, wc));
+    for _ in 0..DEFAULT_TRY_SPINS {
+        if ready() {
+            return;
+        }
+    }
+    for _ in 0..DEFAULT_YIELD_SPINS {
+        yield_now();
+        if ready() {
+            return;
+        }
+    }
+    let mut sleep_us = 1;
+    loop {
+        sleep(Duration::from_micros(sleep_us));
+        if ready() {
+            return;
+        }
+        if sleep_us < DEFAULT_SELECT_MAX_SLEEP_US {
+            sleep_us *= 2;
+        }
+    }
+}
+
+/// A doorbell which receivers taking from several queues have each of them ring,
+/// so that they can block on all of the queues at once. It's only added to the
+/// queues while the receiver is about to block, so writers don't pay for it otherwise
+#[derive(Debug)]
+pub(crate) struct Doorbell {
+    ringer: Sender<()>,
+    bell: Receiver<()>,
+}
+
+impl Doorbell {
+    pub fn new() -> Doorbell {
+        // One ring is as good as any number of them
+        let (ringer, bell) = channel::bounded(1);
+        Doorbell { ringer, bell }
+    }
+
+    /// Returns what rings the doorbell, for whatever else should wake the receiver
+    pub fn ringer(&self) -> &Sender<()> {
+        &self.ringer
+    }
+
+    /// Calls poll until it finds something other than empty queues, blocking on the
+    /// doorbell in between. add_to puts the doorbell in every queue being polled,
+    /// returning whether all of them will ring it, and remove_from takes it out again.
+    /// Queues whose writers never notify can't ring it, so while any of those are
+    /// polled this sleeps for increasingly long between checks, like select_wait
+    pub fn recv<T, P, A, R>(
+        &self,
+        mut poll: P,
+        mut add_to: A,
+        mut remove_from: R,
+    ) -> Result<T, RecvError>
+    where
+        P: FnMut() -> Result<T, TryRecvError>,
+        A: FnMut(&Sender<()>) -> bool,
+        R: FnMut(&Sender<()>),
+    {
+        let mut sleep_us = 1;
+        loop {
+            match poll() {
+                Ok(val) => return Ok(val),
+                Err(TryRecvError::Disconnected) => return Err(RecvError),
+                Err(TryRecvError::Empty) => (),
+            }
+            // Nothing sent before the doorbell went in rings it,
+            // so the queues are checked again before blocking
+            let rung = add_to(&self.ringer);
+            let rval = poll();
+            if let Err(TryRecvError::Empty) = rval {
+                if rung {
+                    let _ = self.bell.recv();
+                } else {
+                    let _ = self.bell.recv_timeout(Duration::from_micros(sleep_us));
+                    if sleep_us < DEFAULT_SELECT_MAX_SLEEP_US {
+                        sleep_us *= 2;
+                    }
+                }
+            }
+            remove_from(&self.ringer);
+            match rval {
+                Ok(val) => return Ok(val),
+                Err(TryRecvError::Disconnected) => return Err(RecvError),
+                Err(TryRecvError::Empty) => (),
+            }
+        }
+    }
+}
+
+/// Spins, then yields, and then sleeps for longer and longer, for the
+/// loops which poll a queue because nothing notifies them
+pub(crate) struct Backoff {
+    step: usize,
+    sleep_us: u64,
+}
+
+impl Backoff {
+    pub fn new() -> Backoff {
+        Backoff {
+            step: 0,
+            sleep_us: 1,
+        }
+    }
+
+    /// Waits a little, but never for longer than limit if there is one
+    pub fn snooze(&mut self, limit: Option<Duration>) {
+        self.step += 1;
+        if self.step <= DEFAULT_TRY_SPINS {
+            return;
+        }
+        if self.step <= DEFAULT_TRY_SPINS + DEFAULT_YIELD_SPINS {
+            yield_now();
+            return;
+        }
+        let mut nap = Duration::from_micros(self.sleep_us);
+        if let Some(limit) = limit {
+            nap = nap.min(limit);
+        }
+        sleep(nap);
+        if self.sleep_us < DEFAULT_SELECT_MAX_SLEEP_US {
+            self.sleep_us *= 2;
+        }
+    }
+}
+
 /// This is the trait that something implements to allow receivers
 /// to block waiting for more data.
 pub trait Wait {
@@ -53,9 +214,32 @@ pub trait Wait {
     /// Called by writers to awaken waiting readers
     fn notify(&self);
 
+    /// Called by writers when a single value has gone into a queue whose readers
+    /// all take from the same stream, so only one of them can get it. Waking
+    /// more than one is always fine, which is what this does unless overridden.
+    /// Writers only call this when needs_every_notify returns false
+    fn notify_one(&self) {
+        self.notify()
+    }
+
     /// Returns whether writers need to call notify
     /// Optimized the various BusyWait variants
     fn needs_notify(&self) -> bool;
+
+    /// Returns whether writers have to notify after every send. When notify only
+    /// wakes readers blocked in wait this can return false, and writers then only
+    /// notify while some reader has found the queue empty and not received since.
+    /// Such a reader always goes on to call wait, which has to check the queue
+    /// again once it's set up to be woken, since the notify may already have
+    /// happened. Anything else woken by notify, like a doorbell, needs every one
+    fn needs_every_notify(&self) -> bool {
+        true
+    }
+
+    /// Returns the name of the strategy, which shows up in the Debug output of queues
+    fn name(&self) -> &'static str {
+        "custom"
+    }
 }
 
 /// Thus spins in a loop on the queue waiting for a value to be ready
@@ -72,12 +256,114 @@ pub struct YieldingWait {
 /// This tries spinning on the queue for a short while, then yielding, and then blocks
 #[derive(Default)]
 pub struct BlockingWait {
-    spins_first: usize,
-    spins_yield: usize,
+    // Atomics so that they can be tuned while the queue is in use
+    spins_first: AtomicUsize,
+    spins_yield: AtomicUsize,
     lock: parking_lot::Mutex<bool>,
     condvar: parking_lot::Condvar,
+    // Lets writers skip the lock while no reader is blocked
+    sleeping: AtomicUsize,
+}
+
+/// This panics instead of waiting for a writer, for targets with only one thread
+/// such as wasm32 without atomics. Nothing could send while a reader waits there,
+/// so blocking would hang forever. Only use ```try_recv``` on empty queues with it
+#[derive(Copy, Clone, Default)]
+pub struct SingleThreadWait {}
+
+/// This spins on the queue for a short while, then yields, and then sleeps on
+/// ```Atomics.wait``` until a writer calls ```Atomics.notify```. It's only available
+/// on wasm32 with the atomics target feature, and like any blocking in a browser
+/// it only works in web workers; the main thread has to stick to ```try_recv```
+#[cfg(all(target_arch = "wasm32", target_feature = "atomics"))]
+pub struct AtomicsWait {
+    spins_first: usize,
+    spins_yield: usize,
+    // Bumped whenever a writer wakes readers, and what they sleep on
+    wakeups: AtomicI32,
+    sleeping: AtomicUsize,
+}
+
+/// This spins on the queue for a short while, then yields, and then sleeps on the
+/// address of a wakeup counter until a writer bumps it, with ```WaitOnAddress``` on
+/// Windows and ```__ulock_wait``` on macOS. Writers never take a lock to wake
+/// readers, and skip the syscall entirely unless some reader is asleep
+#[cfg(any(windows, target_os = "macos"))]
+pub struct AddressWait {
+    spins_first: usize,
+    spins_yield: usize,
+    // Bumped whenever a writer wakes readers, and what they sleep on
+    wakeups: AtomicI32,
+    sleeping: AtomicUsize,
 }
 
+/// This blocks like ```BlockingWait```, and also rings doorbells: crossbeam channels
+/// which get a message whenever the queue might have something new to receive.
+/// A doorbell can go in a ```crossbeam_channel::Select``` next to other channels, so
+/// code that already selects over those can take from a queue as well.
+///
+/// When a doorbell fires, receive from the queue with ```try_recv``` until it's empty
+/// (or disconnected) before selecting again. Anything sent after that rings again.
+/// Each selecting thread should have its own doorbell, since a ring only wakes one.
+///
+/// # Examples
+///
+/// ```
+/// extern crate crossbeam;
+/// use crossbeam::channel::{unbounded, Select};
+/// use multiqueue2::broadcast_queue_with;
+/// use multiqueue2::wait::SelectableWait;
+/// use std::sync::Arc;
+///
+/// let wait = Arc::new(SelectableWait::new());
+/// let (send, recv) = broadcast_queue_with(10, wait.clone());
+/// let bell = wait.doorbell();
+/// let (old_send, old_recv) = unbounded();
+///
+/// send.try_send(1).unwrap();
+/// old_send.send(2).unwrap();
+///
+/// let mut got = Vec::new();
+/// while got.len() < 2 {
+///     let mut sel = Select::new();
+///     let bell_op = sel.recv(&bell);
+///     let old_op = sel.recv(&old_recv);
+///     let oper = sel.select();
+///     if oper.index() == bell_op {
+///         oper.recv(&bell).unwrap();
+///         while let Ok(v) = recv.try_recv() {
+///             got.push(v);
+///         }
+///     } else if oper.index() == old_op {
+///         got.push(oper.recv(&old_recv).unwrap());
+///     }
+/// }
+/// got.sort();
+/// assert_eq!(vec![1, 2], got);
+/// ```
+pub struct SelectableWait {
+    blocking: BlockingWait,
+    bells: parking_lot::Mutex<Vec<Sender<()>>>,
+    // Lets writers skip the lock while nobody has a doorbell
+    num_bells: AtomicUsize,
+}
+
+/// The wait strategy used by queues which aren't given one
+#[cfg(not(any(target_arch = "wasm32", windows, target_os = "macos")))]
+pub type DefaultWait = BlockingWait;
+
+/// The wait strategy used by queues which aren't given one
+#[cfg(any(windows, target_os = "macos"))]
+pub type DefaultWait = AddressWait;
+
+/// The wait strategy used by queues which aren't given one
+#[cfg(all(target_arch = "wasm32", target_feature = "atomics"))]
+pub type DefaultWait = AtomicsWait;
+
+/// The wait strategy used by queues which aren't given one
+#[cfg(all(target_arch = "wasm32", not(target_feature = "atomics")))]
+pub type DefaultWait = SingleThreadWait;
+
 unsafe impl Sync for BusyWait {}
 unsafe impl Sync for YieldingWait {}
 unsafe impl Sync for BlockingWait {}
@@ -91,6 +377,12 @@ impl BusyWait {
     }
 }
 
+impl SingleThreadWait {
+    pub fn new() -> SingleThreadWait {
+        SingleThreadWait {}
+    }
+}
+
 impl YieldingWait {
     /// Calls with_spins(DEFAULT_TRY_SPINS, DEFAULT_YIELD_SPINS)
     pub fn new() -> YieldingWait {
@@ -117,12 +409,208 @@ impl BlockingWait {
     /// and then yields for spins_yield spins, then blocks on a condition variable.
     pub fn with_spins(spins_first: usize, spins_yield: usize) -> BlockingWait {
         BlockingWait {
-            spins_first,
-            spins_yield,
+            spins_first: AtomicUsize::new(spins_first),
+            spins_yield: AtomicUsize::new(spins_yield),
             lock: parking_lot::Mutex::new(false),
             condvar: parking_lot::Condvar::new(),
+            sleeping: AtomicUsize::new(0),
+        }
+    }
+
+    /// Changes how many spins and then yields readers go through before blocking.
+    /// Readers already waiting keep going with what they started with
+    pub fn set_spins(&self, spins_first: usize, spins_yield: usize) {
+        self.spins_first.store(spins_first, RELAXED);
+        self.spins_yield.store(spins_yield, RELAXED);
+    }
+}
+
+impl SelectableWait {
+    /// Blocks readers the same way as ```BlockingWait::new```
+    pub fn new() -> SelectableWait {
+        SelectableWait::with_spins(DEFAULT_TRY_SPINS, DEFAULT_YIELD_SPINS)
+    }
+
+    /// Blocks readers the same way as ```BlockingWait::with_spins```
+    pub fn with_spins(spins_first: usize, spins_yield: usize) -> SelectableWait {
+        SelectableWait {
+            blocking: BlockingWait::with_spins(spins_first, spins_yield),
+            bells: parking_lot::Mutex::new(Vec::new()),
+            num_bells: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns a new doorbell for the queue. It holds at most one ring, so it never
+    /// fills up however far behind the selecting thread is, and it's forgotten
+    /// once dropped. A doorbell made while the queue holds values won't have rung
+    /// for them, so drain the queue after making one
+    pub fn doorbell(&self) -> Receiver<()> {
+        let (send, recv) = channel::bounded(1);
+        let mut bells = self.bells.lock();
+        bells.push(send);
+        self.num_bells.store(bells.len(), RELAXED);
+        // Pairs with the fence in notify, see there
+        fence(SEQ_CST);
+        recv
+    }
+
+    fn ring(&self) {
+        let mut bells = self.bells.lock();
+        bells.retain(|bell| !matches!(bell.try_send(()), Err(TrySendError::Disconnected(_))));
+        self.num_bells.store(bells.len(), RELAXED);
+    }
+}
+
+#[cfg(all(target_arch = "wasm32", target_feature = "atomics"))]
+impl AtomicsWait {
+    /// Calls with_spins(DEFAULT_TRY_SPINS, DEFAULT_YIELD_SPINS)
+    pub fn new() -> AtomicsWait {
+        AtomicsWait::with_spins(DEFAULT_TRY_SPINS, DEFAULT_YIELD_SPINS)
+    }
+
+    /// Constructs an AtomicsWait that busywaits for spins_first spins
+    /// and then yields for spins_yield spins, then sleeps on Atomics.wait.
+    pub fn with_spins(spins_first: usize, spins_yield: usize) -> AtomicsWait {
+        AtomicsWait {
+            spins_first,
+            spins_yield,
+            wakeups: AtomicI32::new(0),
+            sleeping: AtomicUsize::new(0),
+        }
+    }
+
+    fn wakeups_ptr(&self) -> *mut i32 {
+        &self.wakeups as *const AtomicI32 as *mut i32
+    }
+}
+
+#[cfg(any(windows, target_os = "macos"))]
+impl AddressWait {
+    /// Calls with_spins(DEFAULT_TRY_SPINS, DEFAULT_YIELD_SPINS)
+    pub fn new() -> AddressWait {
+        AddressWait::with_spins(DEFAULT_TRY_SPINS, DEFAULT_YIELD_SPINS)
+    }
+
+    /// Constructs an AddressWait that busywaits for spins_first spins
+    /// and then yields for spins_yield spins, then sleeps on the wakeup counter.
+    pub fn with_spins(spins_first: usize, spins_yield: usize) -> AddressWait {
+        AddressWait {
+            spins_first,
+            spins_yield,
+            wakeups: AtomicI32::new(0),
+            sleeping: AtomicUsize::new(0),
+        }
+    }
+}
+
+#[cfg(any(windows, target_os = "macos"))]
+impl Default for AddressWait {
+    fn default() -> AddressWait {
+        AddressWait::new()
+    }
+}
+
+/// Sleeps until the value at addr isn't seen anymore or something wakes it,
+/// possibly spuriously
+#[cfg(windows)]
+fn address_sleep(addr: &AtomicI32, seen: i32) {
+    use std::ffi::c_void;
+    #[link(name = "synchronization")]
+    extern "system" {
+        fn WaitOnAddress(
+            address: *const c_void,
+            compare: *const c_void,
+            size: usize,
+            millis: u32,
+        ) -> i32;
+    }
+    const INFINITE: u32 = u32::MAX;
+    unsafe {
+        WaitOnAddress(
+            addr as *const AtomicI32 as *const c_void,
+            &seen as *const i32 as *const c_void,
+            std::mem::size_of::<i32>(),
+            INFINITE,
+        );
+    }
+}
+
+/// Wakes everything sleeping on addr
+#[cfg(windows)]
+fn address_wake_all(addr: &AtomicI32) {
+    use std::ffi::c_void;
+    #[link(name = "synchronization")]
+    extern "system" {
+        fn WakeByAddressAll(address: *const c_void);
+    }
+    unsafe {
+        WakeByAddressAll(addr as *const AtomicI32 as *const c_void);
+    }
+}
+
+// These are private in libSystem, but they're what libc++ and Rust's own
+// std park on, so they aren't going anywhere
+#[cfg(target_os = "macos")]
+const UL_COMPARE_AND_WAIT: u32 = 1;
+#[cfg(target_os = "macos")]
+const ULF_WAKE_ALL: u32 = 0x100;
+#[cfg(target_os = "macos")]
+const ULF_NO_ERRNO: u32 = 0x0100_0000;
+
+#[cfg(target_os = "macos")]
+extern "C" {
+    fn __ulock_wait(operation: u32, addr: *mut std::ffi::c_void, value: u64, timeout: u32) -> i32;
+    fn __ulock_wake(operation: u32, addr: *mut std::ffi::c_void, wake_value: u64) -> i32;
+}
+
+/// Sleeps until the value at addr isn't seen anymore or something wakes it,
+/// possibly spuriously
+#[cfg(target_os = "macos")]
+fn address_sleep(addr: &AtomicI32, seen: i32) {
+    unsafe {
+        __ulock_wait(
+            UL_COMPARE_AND_WAIT | ULF_NO_ERRNO,
+            addr as *const AtomicI32 as *mut std::ffi::c_void,
+            seen as u32 as u64,
+            0,
+        );
+    }
+}
+
+/// Wakes everything sleeping on addr
+#[cfg(target_os = "macos")]
+fn address_wake_all(addr: &AtomicI32) {
+    unsafe {
+        __ulock_wake(
+            UL_COMPARE_AND_WAIT | ULF_WAKE_ALL | ULF_NO_ERRNO,
+            addr as *const AtomicI32 as *mut std::ffi::c_void,
+            0,
+        );
+    }
+}
+
+impl Wait for SingleThreadWait {
+    #[cold]
+    fn wait(&self, _seq: usize, _w_pos: &AtomicUsize, wc: &AtomicUsize) {
+        // Readers only wait after finding the queue empty, and with one thread
+        // nothing can have been written since. Slots which have never been
+        // written to pass check, so it can't be relied on to tell
+        if wc.load(RELAXED) != 0 {
+            panic!("Multiqueue error - waited on an empty queue with no other thread to fill it");
         }
     }
+
+    fn notify(&self) {
+        // Nothing ever waits
+    }
+
+    fn needs_notify(&self) -> bool {
+        false
+    }
+
+    fn name(&self) -> &'static str {
+        "SingleThreadWait"
+    }
 }
 
 impl Wait for BusyWait {
@@ -142,6 +630,10 @@ impl Wait for BusyWait {
     fn needs_notify(&self) -> bool {
         false
     }
+
+    fn name(&self) -> &'static str {
+        "BusyWait"
+    }
 }
 
 impl Wait for YieldingWait {
@@ -169,17 +661,21 @@ impl Wait for YieldingWait {
     fn needs_notify(&self) -> bool {
         false
     }
+
+    fn name(&self) -> &'static str {
+        "YieldingWait"
+    }
 }
 
 impl Wait for BlockingWait {
     #[cold]
     fn wait(&self, seq: usize, w_pos: &AtomicUsize, wc: &AtomicUsize) {
-        for _ in 0..self.spins_first {
+        for _ in 0..self.spins_first.load(RELAXED) {
             if check(seq, w_pos, wc) {
                 return;
             }
         }
-        for _ in 0..self.spins_yield {
+        for _ in 0..self.spins_yield.load(RELAXED) {
             yield_now();
             if check(seq, w_pos, wc) {
                 return;
@@ -189,10 +685,16 @@ impl Wait for BlockingWait {
         loop {
             {
                 let mut lock = self.lock.lock();
+                self.sleeping.fetch_add(1, SEQ_CST);
+                // Pairs with the fence in notify, so either the writer sees
+                // this reader sleeping or this reader sees what was written
+                fence(SEQ_CST);
                 if check(seq, w_pos, wc) {
+                    self.sleeping.fetch_sub(1, RELAXED);
                     return;
                 }
                 self.condvar.wait(&mut lock);
+                self.sleeping.fetch_sub(1, RELAXED);
             }
             if check(seq, w_pos, wc) {
                 return;
@@ -201,22 +703,246 @@ impl Wait for BlockingWait {
     }
 
     fn notify(&self) {
-        // I don't try and do any flag tricks here to avoid the notify
-        // since they would require a store-load fence or an rmw operation.
-        // on top of potentially doing the mutex and condition variable.
-        // The fast path here is pretty fast anyways
-        let _lock = self.lock.lock();
-        self.condvar.notify_all();
+        // The fence costs about what taking an uncontended lock does,
+        // and saves the lock entirely while every reader is busy
+        fence(SEQ_CST);
+        if self.sleeping.load(RELAXED) != 0 {
+            let _lock = self.lock.lock();
+            self.condvar.notify_all();
+        }
+    }
+
+    fn notify_one(&self) {
+        // A reader which has been woken is off the condition variable, so
+        // another call wakes somebody else rather than waking it again
+        fence(SEQ_CST);
+        if self.sleeping.load(RELAXED) != 0 {
+            let _lock = self.lock.lock();
+            self.condvar.notify_one();
+        }
+    }
+
+    fn needs_notify(&self) -> bool {
+        true
+    }
+
+    fn needs_every_notify(&self) -> bool {
+        false
+    }
+
+    fn name(&self) -> &'static str {
+        "BlockingWait"
+    }
+}
+
+impl Default for SelectableWait {
+    fn default() -> SelectableWait {
+        SelectableWait::new()
+    }
+}
+
+impl Wait for SelectableWait {
+    #[cold]
+    fn wait(&self, seq: usize, w_pos: &AtomicUsize, wc: &AtomicUsize) {
+        self.blocking.wait(seq, w_pos, wc)
+    }
+
+    fn notify(&self) {
+        self.blocking.notify();
+        // Either this sees a new doorbell and rings it, or its owner sees what
+        // was written when draining after making it. Existing doorbells are
+        // fine either way, since taking a ring out of one is a SeqCst rmw
+        fence(SEQ_CST);
+        if self.num_bells.load(RELAXED) != 0 {
+            self.ring();
+        }
+    }
+
+    fn notify_one(&self) {
+        // Every doorbell still rings, since there's no telling which
+        // selecting thread is free to take the value
+        self.blocking.notify_one();
+        fence(SEQ_CST);
+        if self.num_bells.load(RELAXED) != 0 {
+            self.ring();
+        }
     }
 
     fn needs_notify(&self) -> bool {
         true
     }
+
+    fn name(&self) -> &'static str {
+        "SelectableWait"
+    }
+}
+
+#[cfg(all(target_arch = "wasm32", target_feature = "atomics"))]
+impl Wait for AtomicsWait {
+    #[cold]
+    fn wait(&self, seq: usize, w_pos: &AtomicUsize, wc: &AtomicUsize) {
+        for _ in 0..self.spins_first {
+            if check(seq, w_pos, wc) {
+                return;
+            }
+        }
+        for _ in 0..self.spins_yield {
+            yield_now();
+            if check(seq, w_pos, wc) {
+                return;
+            }
+        }
+
+        loop {
+            // If a writer bumps wakeups after this load, the wait returns straight away
+            let seen = self.wakeups.load(SeqCst);
+            self.sleeping.fetch_add(1, SeqCst);
+            // Pairs with the fence in notify, so either the writer sees
+            // this reader sleeping or this reader sees what was written
+            fence(SeqCst);
+            if check(seq, w_pos, wc) {
+                self.sleeping.fetch_sub(1, SeqCst);
+                return;
+            }
+            unsafe {
+                wasm32::memory_atomic_wait32(self.wakeups_ptr(), seen, -1);
+            }
+            self.sleeping.fetch_sub(1, SeqCst);
+            if check(seq, w_pos, wc) {
+                return;
+            }
+        }
+    }
+
+    fn notify(&self) {
+        // Unlike BlockingWait this never takes a lock, and leaves wakeups alone
+        // unless some reader is actually asleep
+        fence(SeqCst);
+        if self.sleeping.load(SeqCst) != 0 {
+            self.wakeups.fetch_add(1, SeqCst);
+            unsafe {
+                wasm32::memory_atomic_notify(self.wakeups_ptr(), u32::MAX);
+            }
+        }
+    }
+
+    fn needs_notify(&self) -> bool {
+        true
+    }
+
+    fn needs_every_notify(&self) -> bool {
+        false
+    }
+
+    fn name(&self) -> &'static str {
+        "AtomicsWait"
+    }
+}
+
+#[cfg(all(target_arch = "wasm32", target_feature = "atomics"))]
+impl Clone for AtomicsWait {
+    fn clone(&self) -> AtomicsWait {
+        AtomicsWait::with_spins(self.spins_first, self.spins_yield)
+    }
+}
+
+#[cfg(any(windows, target_os = "macos"))]
+impl Wait for AddressWait {
+    #[cold]
+    fn wait(&self, seq: usize, w_pos: &AtomicUsize, wc: &AtomicUsize) {
+        for _ in 0..self.spins_first {
+            if check(seq, w_pos, wc) {
+                return;
+            }
+        }
+        for _ in 0..self.spins_yield {
+            yield_now();
+            if check(seq, w_pos, wc) {
+                return;
+            }
+        }
+
+        loop {
+            // If a writer bumps wakeups after this load, the sleep returns straight away
+            let seen = self.wakeups.load(SeqCst);
+            self.sleeping.fetch_add(1, SeqCst);
+            // Pairs with the fence in notify, so either the writer sees
+            // this reader sleeping or this reader sees what was written
+            fence(SeqCst);
+            if check(seq, w_pos, wc) {
+                self.sleeping.fetch_sub(1, SeqCst);
+                return;
+            }
+            address_sleep(&self.wakeups, seen);
+            self.sleeping.fetch_sub(1, SeqCst);
+            if check(seq, w_pos, wc) {
+                return;
+            }
+        }
+    }
+
+    fn notify(&self) {
+        fence(SeqCst);
+        if self.sleeping.load(SeqCst) != 0 {
+            self.wakeups.fetch_add(1, SeqCst);
+            address_wake_all(&self.wakeups);
+        }
+    }
+
+    fn needs_notify(&self) -> bool {
+        true
+    }
+
+    fn needs_every_notify(&self) -> bool {
+        false
+    }
+
+    fn name(&self) -> &'static str {
+        "AddressWait"
+    }
+}
+
+#[cfg(any(windows, target_os = "macos"))]
+impl Clone for AddressWait {
+    fn clone(&self) -> AddressWait {
+        AddressWait::with_spins(self.spins_first, self.spins_yield)
+    }
 }
 
 impl Clone for BlockingWait {
     fn clone(&self) -> BlockingWait {
-        BlockingWait::with_spins(self.spins_first, self.spins_yield)
+        BlockingWait::with_spins(
+            self.spins_first.load(RELAXED),
+            self.spins_yield.load(RELAXED),
+        )
+    }
+}
+
+/// Lets a wait strategy be shared with the queue, so that something
+/// like ```BlockingWait::set_spins``` can still be called on it later
+impl<W: Wait> Wait for Arc<W> {
+    fn wait(&self, seq: usize, w_pos: &AtomicUsize, wc: &AtomicUsize) {
+        (**self).wait(seq, w_pos, wc)
+    }
+
+    fn notify(&self) {
+        (**self).notify()
+    }
+
+    fn notify_one(&self) {
+        (**self).notify_one()
+    }
+
+    fn needs_notify(&self) -> bool {
+        (**self).needs_notify()
+    }
+
+    fn needs_every_notify(&self) -> bool {
+        (**self).needs_every_notify()
+    }
+
+    fn name(&self) -> &'static str {
+        (**self).name()
     }
 }
 
@@ -256,7 +982,7 @@ mod test {
                             }
                             yield_now();
                         }
-                        assert!(false, "Writer could not write");
+                        panic!("Writer could not write");
                     }
                 });
             }
@@ -264,10 +990,7 @@ mod test {
             for _ in 0..receivers {
                 let this_reader = reader.add_stream().into_single().unwrap();
                 scope.spawn(move |_| {
-                    let mut myv = Vec::new();
-                    for _ in 0..senders {
-                        myv.push(0);
-                    }
+                    let mut myv = vec![0; senders];
                     for _ in 0..num_loop * senders {
                         if let Ok(val) = this_reader.recv() {
                             assert_eq!(myv[val.0], val.1);
@@ -313,4 +1036,227 @@ mod test {
         test_waiter(BlockingWait::with_spins(0, 0));
     }
 
+    #[cfg(any(windows, target_os = "macos"))]
+    #[test]
+    fn test_addresswait() {
+        test_waiter(AddressWait::new());
+    }
+
+    #[cfg(any(windows, target_os = "macos"))]
+    #[test]
+    fn test_addresswait_nospin() {
+        test_waiter(AddressWait::with_spins(0, 0));
+    }
+
+    #[test]
+    fn test_singlethreadwait() {
+        let (writer, reader) = broadcast_queue_with(4, SingleThreadWait::new());
+        writer.try_send(1).unwrap();
+        assert_eq!(Ok(1), reader.recv());
+        writer.unsubscribe();
+        assert!(reader.recv().is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "no other thread to fill it")]
+    fn test_singlethreadwait_empty() {
+        let (_writer, reader) = broadcast_queue_with::<usize, _>(4, SingleThreadWait::new());
+        let _ = reader.recv();
+    }
+
+    #[test]
+    fn test_selectablewait() {
+        test_waiter(Arc::new(SelectableWait::new()));
+    }
+
+    #[test]
+    fn test_doorbell_select() {
+        use self::crossbeam::channel::{unbounded, Select};
+        use std::sync::mpsc::TryRecvError;
+
+        let wait = Arc::new(SelectableWait::with_spins(0, 0));
+        let (writer, reader) = broadcast_queue_with(4, wait.clone());
+        let bell = wait.doorbell();
+        let (chan_send, chan_recv) = unbounded();
+        let num_loop = 10000;
+        scope(|scope| {
+            scope.spawn(move |_| {
+                for i in 0..num_loop {
+                    while writer.try_send(i).is_err() {
+                        yield_now();
+                    }
+                }
+            });
+            scope.spawn(move |_| {
+                for i in 0..num_loop {
+                    chan_send.send(i).unwrap();
+                }
+            });
+            let (mut from_queue, mut from_chan) = (0, 0);
+            let (mut queue_done, mut chan_done) = (false, false);
+            while !(queue_done && chan_done) {
+                let mut sel = Select::new();
+                let bell_op = if queue_done {
+                    None
+                } else {
+                    Some(sel.recv(&bell))
+                };
+                let chan_op = if chan_done {
+                    None
+                } else {
+                    Some(sel.recv(&chan_recv))
+                };
+                let oper = sel.select();
+                if Some(oper.index()) == bell_op {
+                    oper.recv(&bell).unwrap();
+                    loop {
+                        match reader.try_recv() {
+                            Ok(v) => {
+                                assert_eq!(from_queue, v);
+                                from_queue += 1;
+                            }
+                            Err(TryRecvError::Empty) => break,
+                            Err(TryRecvError::Disconnected) => {
+                                queue_done = true;
+                                break;
+                            }
+                        }
+                    }
+                } else if Some(oper.index()) == chan_op {
+                    match oper.recv(&chan_recv) {
+                        Ok(v) => {
+                            assert_eq!(from_chan, v);
+                            from_chan += 1;
+                        }
+                        Err(_) => chan_done = true,
+                    }
+                }
+            }
+            assert_eq!((num_loop, num_loop), (from_queue, from_chan));
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_doorbell_dropped() {
+        let wait = Arc::new(SelectableWait::new());
+        let (writer, _reader) = broadcast_queue_with(4, wait.clone());
+        let kept = wait.doorbell();
+        drop(wait.doorbell());
+        writer.try_send(1).unwrap();
+        writer.try_send(2).unwrap();
+        assert_eq!(1, wait.num_bells.load(RELAXED));
+        // Rings don't pile up
+        assert_eq!(1, kept.len());
+    }
+
+    #[test]
+    fn test_blockingwait_notify_one() {
+        use crate::mpmc::mpmc_queue_with;
+
+        let waiter = Arc::new(BlockingWait::with_spins(0, 0));
+        let (writer, reader) = mpmc_queue_with(4, waiter.clone());
+        let num_loop = 10000;
+        let total = AtomicUsize::new(0);
+        scope(|scope| {
+            for _ in 0..4 {
+                let cur_reader = reader.clone();
+                let cur_total = &total;
+                scope.spawn(move |_| {
+                    for _ in cur_reader {
+                        cur_total.fetch_add(1, Ordering::Relaxed);
+                    }
+                });
+            }
+            reader.unsubscribe();
+            scope.spawn(move |_| {
+                for i in 0..num_loop {
+                    while writer.try_send(i).is_err() {
+                        yield_now();
+                    }
+                }
+            });
+        })
+        .unwrap();
+        // Each value only woke one reader, but every reader still got woken for the disconnect
+        assert_eq!(num_loop, total.load(Ordering::Relaxed));
+        assert_eq!(0, waiter.sleeping.load(RELAXED));
+    }
+
+    /// Blocks like BlockingWait while counting what the queue calls
+    struct CountingWait {
+        blocking: BlockingWait,
+        every: bool,
+        waits: AtomicUsize,
+        notifies: AtomicUsize,
+    }
+
+    impl Wait for CountingWait {
+        fn wait(&self, seq: usize, w_pos: &super::AtomicUsize, wc: &super::AtomicUsize) {
+            self.waits.fetch_add(1, Ordering::SeqCst);
+            self.blocking.wait(seq, w_pos, wc)
+        }
+
+        fn notify(&self) {
+            self.notifies.fetch_add(1, Ordering::SeqCst);
+            self.blocking.notify()
+        }
+
+        fn needs_notify(&self) -> bool {
+            true
+        }
+
+        fn needs_every_notify(&self) -> bool {
+            self.every
+        }
+    }
+
+    fn notifies_for_blocked_reader(every: bool) -> (usize, usize) {
+        let waiter = Arc::new(CountingWait {
+            blocking: BlockingWait::with_spins(0, 0),
+            every,
+            waits: AtomicUsize::new(0),
+            notifies: AtomicUsize::new(0),
+        });
+        let (writer, reader) = broadcast_queue_with(4, waiter.clone());
+        // Slots which have never been written pass check, so
+        // a reader only blocks once every one has been
+        for i in 0..4 {
+            writer.try_send(i).unwrap();
+            assert_eq!(i, reader.recv().unwrap());
+        }
+        let idle = waiter.notifies.load(Ordering::SeqCst);
+        scope(|scope| {
+            scope.spawn(move |_| assert_eq!(4, reader.recv().unwrap()));
+            while waiter.waits.load(Ordering::SeqCst) == 0 {
+                yield_now();
+            }
+            writer.try_send(4).unwrap();
+        })
+        .unwrap();
+        (idle, waiter.notifies.load(Ordering::SeqCst) - idle)
+    }
+
+    #[test]
+    fn test_notify_only_waiting() {
+        // Nobody was waiting for the first values, but somebody was for the last
+        assert_eq!((0, 1), notifies_for_blocked_reader(false));
+        assert_eq!((4, 1), notifies_for_blocked_reader(true));
+    }
+
+    #[test]
+    fn test_blockingwait_set_spins() {
+        let waiter = Arc::new(BlockingWait::new());
+        let tuner = waiter.clone();
+        scope(|scope| {
+            scope.spawn(move |_| {
+                for i in 0..100 {
+                    tuner.set_spins(i % 3, i % 2);
+                    yield_now();
+                }
+            });
+            mpsc_broadcast(2, 2, waiter);
+        })
+        .unwrap();
+    }
 }
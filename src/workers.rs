@@ -0,0 +1,176 @@
+//! Support for handing the values of an mpmc queue to a pool of threads
+
+use crate::mpmc::MPMCReceiver;
+
+use std::panic;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+/// A set of threads which each hold a receiver on the same mpmc queue and pass
+/// every value they receive to the same function. The workers stop once
+/// every sender is gone and the queue has been drained, or once they panic.
+/// A worker which panics drops its receiver, so the queue disconnects
+/// as usual if every worker has stopped.
+///
+/// Dropping the pool leaves the workers running, the same as dropping
+/// a ```JoinHandle```. These are made with ```MPMCReceiver::spawn_workers```.
+///
+/// # Examples
+///
+/// ```
+/// use multiqueue2::mpmc_queue;
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// use std::sync::Arc;
+///
+/// let (w, r) = mpmc_queue(16);
+/// let total = Arc::new(AtomicUsize::new(0));
+/// let sum = total.clone();
+/// let pool = r.spawn_workers(4, move |val| {
+///     sum.fetch_add(val, Ordering::Relaxed);
+/// });
+/// for i in 0..100 {
+///     while w.try_send(i).is_err() {}
+/// }
+/// // The workers stop once the senders are gone and everything was received
+/// drop(w);
+/// pool.join();
+/// assert_eq!(4950, total.load(Ordering::Relaxed));
+/// ```
+pub struct WorkerPool {
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl WorkerPool {
+    pub(crate) fn new<T, F>(receiver: MPMCReceiver<T>, workers: usize, f: F) -> WorkerPool
+    where
+        T: Send + 'static,
+        F: Fn(T) + Send + Sync + 'static,
+    {
+        assert!(workers > 0, "Multiqueue error - zero workers received");
+        let f = Arc::new(f);
+        // The last worker takes the passed receiver, so no receiver is left
+        // behind to keep the queue from disconnecting once the workers are gone
+        let mut receivers: Vec<_> = (1..workers).map(|_| receiver.clone()).collect();
+        receivers.push(receiver);
+        let handles = receivers
+            .into_iter()
+            .enumerate()
+            .map(|(i, receiver)| {
+                let f = f.clone();
+                thread::Builder::new()
+                    .name(format!("multiqueue-worker-{}", i))
+                    .spawn(move || {
+                        while let Ok(val) = receiver.recv() {
+                            f(val);
+                        }
+                    })
+                    .expect("Multiqueue error - couldn't spawn a worker thread")
+            })
+            .collect();
+        WorkerPool { handles }
+    }
+
+    /// Returns the number of workers in the pool
+    pub fn workers(&self) -> usize {
+        self.handles.len()
+    }
+
+    /// Returns whether every worker has stopped
+    pub fn is_finished(&self) -> bool {
+        self.handles.iter().all(JoinHandle::is_finished)
+    }
+
+    /// Waits for every worker to stop. If any of them panicked, one of their
+    /// panics is resumed on this thread once the rest have stopped as well
+    pub fn join(self) {
+        if let Err(payload) = self.try_join() {
+            panic::resume_unwind(payload);
+        }
+    }
+
+    /// Identical to ```join```, except the panic is returned instead of resumed
+    pub fn try_join(self) -> thread::Result<()> {
+        let mut rval = Ok(());
+        for handle in self.handles {
+            let joined = handle.join();
+            if rval.is_ok() {
+                rval = joined;
+            }
+        }
+        rval
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use crate::error::FrozenTrySendError;
+    use crate::mpmc::mpmc_queue;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use std::sync::{Arc, Mutex};
+    use std::thread::yield_now;
+
+    fn send_all(writer: &crate::MPMCSender<usize>, num: usize) {
+        for i in 0..num {
+            let mut val = writer.try_send(i);
+            while let Err(FrozenTrySendError::Full(v)) = val {
+                yield_now();
+                val = writer.try_send(v);
+            }
+        }
+    }
+
+    #[test]
+    fn test_workers() {
+        let (writer, reader) = mpmc_queue(8);
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = seen.clone();
+        let pool = reader.spawn_workers(3, move |val| sink.lock().unwrap().push(val));
+        assert_eq!(3, pool.workers());
+        send_all(&writer, 10000);
+        drop(writer);
+        pool.join();
+        let mut seen = seen.lock().unwrap().clone();
+        seen.sort_unstable();
+        assert_eq!((0..10000).collect::<Vec<_>>(), seen);
+    }
+
+    #[test]
+    fn test_workers_disconnect() {
+        let (writer, reader) = mpmc_queue::<usize>(8);
+        let pool = reader.spawn_workers(2, |_| panic!("worker failed"));
+        writer.try_send(0).unwrap();
+        writer.try_send(1).unwrap();
+        assert!(pool.try_join().is_err());
+        // Nothing is left holding the queue open once every worker has stopped
+        assert!(writer.try_send(2).is_err());
+    }
+
+    #[test]
+    fn test_workers_panic() {
+        let (writer, reader) = mpmc_queue(8);
+        let handled = Arc::new(AtomicUsize::new(0));
+        let count = handled.clone();
+        let pool = reader.spawn_workers(4, move |val| {
+            if val == 5 {
+                panic!("worker failed");
+            }
+            count.fetch_add(1, Ordering::Relaxed);
+        });
+        send_all(&writer, 100);
+        drop(writer);
+        // The other workers go on receiving after one panics
+        let payload = pool.try_join().unwrap_err();
+        assert_eq!(Some(&"worker failed"), payload.downcast_ref::<&str>());
+        assert_eq!(99, handled.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_workers_zero() {
+        let (_writer, reader) = mpmc_queue::<usize>(8);
+        reader.spawn_workers(0, |_| ());
+    }
+}
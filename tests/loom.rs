@@ -0,0 +1,137 @@
+// Model checks the core of the queue with loom. These only build with --cfg loom,
+// and take a while, so run them in release mode:
+// RUSTFLAGS="--cfg loom" cargo test --test loom --release
+#![cfg(loom)]
+
+extern crate loom;
+extern crate multiqueue2 as multiqueue;
+
+use loom::model::Builder;
+use loom::thread;
+
+use multiqueue::wait::YieldingWait;
+use multiqueue::{broadcast_queue_with, mpmc_queue_with, FrozenTrySendError};
+
+use std::sync::mpsc::TryRecvError;
+
+fn model<F: Fn() + Sync + Send + 'static>(f: F) {
+    let mut builder = Builder::new();
+    if builder.preemption_bound.is_none() {
+        builder.preemption_bound = Some(2);
+    }
+    // Writers spinning on a full queue take a lot of steps
+    builder.max_branches = 100_000;
+    builder.check(f);
+}
+
+fn send_all<F: Fn(usize) -> Result<(), FrozenTrySendError<usize>>>(vals: &[usize], send: F) {
+    for &val in vals {
+        loop {
+            match send(val) {
+                Ok(()) => break,
+                Err(FrozenTrySendError::Full(_)) => thread::yield_now(),
+                Err(FrozenTrySendError::Disconnected(_)) => panic!("Writer was disconnected"),
+                Err(FrozenTrySendError::Frozen(_)) => panic!("Queue was frozen"),
+            }
+        }
+    }
+}
+
+fn recv_one<F: Fn() -> Result<usize, TryRecvError>>(recv: F) -> Option<usize> {
+    loop {
+        match recv() {
+            Ok(val) => return Some(val),
+            Err(TryRecvError::Empty) => thread::yield_now(),
+            Err(TryRecvError::Disconnected) => return None,
+        }
+    }
+}
+
+fn recv_all<F: Fn() -> Result<usize, TryRecvError>>(recv: F) -> Vec<usize> {
+    let mut got = Vec::new();
+    while let Some(val) = recv_one(&recv) {
+        got.push(val);
+    }
+    got
+}
+
+// A writer sending and then leaving races with the reader finding the queue empty
+// and checking whether the writers are gone, which is what the "gnarly if block"
+// in try_recv is for. The value must never be lost to a Disconnected.
+#[test]
+fn broadcast_send_then_disconnect() {
+    model(|| {
+        let (send, recv) = broadcast_queue_with(2, YieldingWait::new());
+        let writer = thread::spawn(move || {
+            send_all(&[1], |v| send.try_send(v));
+        });
+        assert_eq!(vec![1], recv_all(|| recv.try_recv()));
+        writer.join().unwrap();
+    });
+}
+
+#[test]
+fn mpmc_send_then_disconnect() {
+    model(|| {
+        let (send, recv) = mpmc_queue_with(2, YieldingWait::new());
+        let writer = thread::spawn(move || {
+            send_all(&[1], |v| send.try_send(v));
+        });
+        assert_eq!(vec![1], recv_all(|| recv.try_recv()));
+        writer.join().unwrap();
+    });
+}
+
+// Consumers on an mpmc stream race to commit the same slot, and each value
+// must go to exactly one of them
+#[test]
+fn mpmc_competing_consumers() {
+    model(|| {
+        let (send, recv) = mpmc_queue_with(2, YieldingWait::new());
+        let other = recv.clone();
+        let reader = thread::spawn(move || recv_all(|| other.try_recv()));
+        send_all(&[1, 2], |v| send.try_send(v));
+        drop(send);
+        let mut got = recv_all(|| recv.try_recv());
+        got.extend(reader.join().unwrap());
+        got.sort();
+        assert_eq!(vec![1, 2], got);
+    });
+}
+
+// The writer wraps around the queue, so it has to wait for both streams
+// before reusing a slot, and neither stream may see an overwritten value
+#[test]
+fn broadcast_wraparound() {
+    model(|| {
+        let (send, recv) = broadcast_queue_with(1, YieldingWait::new());
+        let stream = recv.add_stream();
+        let writer = thread::spawn(move || {
+            send_all(&[1, 2], |v| send.try_send(v));
+        });
+        for val in 1..3 {
+            assert_eq!(Some(val), recv_one(|| recv.try_recv()));
+            assert_eq!(Some(val), recv_one(|| stream.try_recv()));
+        }
+        assert_eq!(None, recv_one(|| recv.try_recv()));
+        assert_eq!(None, recv_one(|| stream.try_recv()));
+        writer.join().unwrap();
+    });
+}
+
+// A stream leaving while the writer waits on it has to let the writer through,
+// and must not take any values from the streams which are left
+#[test]
+fn broadcast_unsubscribe_while_sending() {
+    model(|| {
+        let (send, recv) = broadcast_queue_with(1, YieldingWait::new());
+        let stream = recv.add_stream();
+        let writer = thread::spawn(move || {
+            send_all(&[1, 2], |v| send.try_send(v));
+        });
+        let _ = stream.try_recv();
+        stream.unsubscribe();
+        assert_eq!(vec![1, 2], recv_all(|| recv.try_recv()));
+        writer.join().unwrap();
+    });
+}
@@ -6,6 +6,7 @@ use futures::future::lazy;
 use futures::{Async, Future, Sink, Stream};
 
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::SendError;
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
@@ -72,6 +73,25 @@ fn send_recv_threads_no_capacity() {
     t.join().unwrap();
 }
 
+#[test]
+fn send_all_threads() {
+    const AMT: usize = 10_000;
+    let (tx, rx) = multiqueue::mpmc_fut_queue::<usize>(4);
+
+    let t = thread::spawn(move || {
+        let got: Vec<_> = rx.wait().map(|v| v.unwrap()).collect();
+        assert_eq!((0..AMT).collect::<Vec<_>>(), got);
+    });
+
+    let (tx, _) = tx
+        .send_all(futures::stream::iter_ok::<_, SendError<usize>>(0..AMT))
+        .wait()
+        .unwrap();
+    drop(tx);
+
+    t.join().unwrap();
+}
+
 #[test]
 fn recv_close_gets_none() {
     let (tx, rx) = multiqueue::mpmc_fut_queue::<i32>(10);
@@ -5,6 +5,8 @@ extern crate multiqueue2 as multiqueue;
 use futures::future::lazy;
 use futures::{Async, Future, Sink, Stream};
 
+use std::marker::PhantomPinned;
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
@@ -72,6 +74,100 @@ fn send_recv_threads_no_capacity() {
     t.join().unwrap();
 }
 
+#[test]
+fn clone_uni_recv() {
+    let (tx, rx) = multiqueue::broadcast_fut_queue::<u32>(4);
+    let rx = rx.into_single(|x: &u32| x + 1).ok().unwrap();
+    let handles: Vec<_> = (0..3)
+        .map(|_| {
+            let rx = rx.clone();
+            thread::spawn(move || rx.wait().map(Result::unwrap).collect::<Vec<_>>())
+        })
+        .collect();
+    drop(rx);
+
+    let t = thread::spawn(move || {
+        let mut tx = tx;
+        for i in 0..100 {
+            tx = tx.send(i).wait().unwrap();
+        }
+    });
+    for handle in handles {
+        assert_eq!((1..101).collect::<Vec<_>>(), handle.join().unwrap());
+    }
+    t.join().unwrap();
+}
+
+#[test]
+fn stateful_uni_recv() {
+    // Not Clone, so the stream has to keep using the same closure
+    struct Total(u32);
+
+    let (tx, rx) = multiqueue::broadcast_fut_queue::<u32>(4);
+    let mut total = Total(0);
+    let rx = rx
+        .into_single(move |x: &u32| {
+            total.0 += x;
+            total.0
+        })
+        .ok()
+        .unwrap();
+
+    let t = thread::spawn(move || {
+        let mut tx = tx;
+        for i in 1..5 {
+            tx = tx.send(i).wait().unwrap();
+        }
+    });
+    assert_eq!(
+        vec![1, 3, 6, 10],
+        rx.wait().map(Result::unwrap).collect::<Vec<_>>()
+    );
+    t.join().unwrap();
+}
+
+#[test]
+fn panicking_uni_recv() {
+    let (tx, rx) = multiqueue::broadcast_fut_queue::<u32>(1);
+    let mut rx = rx
+        .into_single(|x: &u32| {
+            if *x == 1 {
+                panic!("op failed");
+            }
+            x + 1
+        })
+        .ok()
+        .unwrap();
+
+    let t = thread::spawn(move || {
+        let mut tx = tx;
+        for i in 1..4 {
+            tx = tx.send(i).wait().unwrap();
+        }
+    });
+    // Lets the sender fill the queue and park waiting for room
+    thread::sleep(Duration::from_millis(100));
+    assert!(panic::catch_unwind(AssertUnwindSafe(|| (&mut rx).wait().next())).is_err());
+    // The value the op panicked on is gone, and the sender was woken for the rest
+    assert_eq!(
+        vec![3, 4],
+        rx.wait().map(Result::unwrap).collect::<Vec<_>>()
+    );
+    t.join().unwrap();
+}
+
+#[test]
+fn send_not_unpin() {
+    #[derive(Clone, Debug, PartialEq)]
+    struct Pinned(u32, PhantomPinned);
+
+    let (tx, rx) = multiqueue::broadcast_fut_queue::<Pinned>(4);
+    let mut rx = rx.wait();
+
+    tx.send(Pinned(1, PhantomPinned)).wait().unwrap();
+    assert_eq!(rx.next().unwrap(), Ok(Pinned(1, PhantomPinned)));
+}
+
 #[test]
 fn recv_close_gets_none() {
     let (tx, rx) = multiqueue::broadcast_fut_queue::<i32>(10);
@@ -215,3 +311,69 @@ fn stress_receiver_multi_task_bounded_hard() {
 
     assert_eq!(AMT, n.load(Ordering::Relaxed));
 }
+
+#[test]
+fn set_spins_while_blocked() {
+    const AMT: u32 = 2000;
+    let (tx, rx) = multiqueue::broadcast_fut_queue::<i32>(0);
+    let tuner = rx.clone();
+    let mut rx = rx.wait();
+
+    let t = thread::spawn(move || {
+        for _ in 0..AMT {
+            assert_eq!(rx.next().unwrap(), Ok(1));
+        }
+    });
+
+    let mut send_tx = tx.clone();
+    let s = thread::spawn(move || {
+        for _ in 0..AMT {
+            send_tx = send_tx.send(1).wait().unwrap();
+        }
+    });
+
+    for i in 0..100 {
+        tx.set_spins(1000 + i * 10, 5 + i % 3);
+        tuner.set_spins(2000 - i * 10, 10);
+        thread::sleep(Duration::from_micros(10));
+    }
+    drop(tx);
+    tuner.unsubscribe();
+
+    s.join().unwrap();
+    t.join().unwrap();
+}
+
+#[cfg(feature = "stats")]
+#[test]
+fn dropped_parked_receiver_stops_waiting() {
+    fn park(rx: &mut multiqueue::BroadcastFutReceiver<i32>) {
+        lazy(|| {
+            assert_eq!(Ok(Async::NotReady), rx.poll());
+            Ok::<(), ()>(())
+        })
+        .wait()
+        .unwrap();
+    }
+
+    let (tx, rx) = multiqueue::broadcast_fut_queue_with::<i32>(1, 0, 0);
+    let mut parked = rx.add_stream();
+    tx.try_send(0).unwrap();
+    assert_eq!(Ok(0), rx.try_recv());
+    assert_eq!(Ok(0), parked.try_recv());
+
+    // A parked receiver has to be woken
+    park(&mut parked);
+    let before = tx.stats().notifies;
+    tx.try_send(1).unwrap();
+    assert_eq!(before + 1, tx.stats().notifies);
+    assert_eq!(Ok(1), rx.try_recv());
+    assert_eq!(Ok(1), parked.try_recv());
+
+    // Once it's dropped nobody's left waiting, so writers stop notifying
+    park(&mut parked);
+    drop(parked);
+    let before = tx.stats().notifies;
+    tx.try_send(2).unwrap();
+    assert_eq!(before, tx.stats().notifies);
+}